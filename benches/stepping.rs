@@ -0,0 +1,98 @@
+// Benchmarks of the ptrace plumbing that stepping is built on: how fast a tracee is single-stepped,
+// how fast a breakpoint is hit and resumed over, and how fast memory is read in bulk. The fixture
+// is `yes`, which loops calling write() forever, with its output thrown away.
+#![allow(clippy::needless_return)]
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use pbreak::{
+    disasm::BREAKPOINT_INSTRUCTION,
+    launch::LaunchOptions,
+    tracee::{Tracee, TraceeStatus},
+};
+
+// How much memory each iteration of the bulk read reads.
+const BULK_READ_LEN: usize = 64 * 1024;
+
+// Launches the fixture and runs it to its entry point, by which time its libraries are loaded.
+unsafe fn launch_fixture() -> Tracee {
+    let mut options = LaunchOptions::new("yes", &[]);
+    options.stdout = Some("/dev/null".to_string());
+    options.stop_at_entry = true;
+    return Tracee::launch(&options);
+}
+
+fn single_step(c: &mut Criterion) {
+    unsafe {
+        let mut tracee = launch_fixture();
+        c.bench_function("single_step", |b| {
+            b.iter(|| {
+                tracee.step(None);
+                tracee.wait_on_signal();
+                assert_eq!(tracee.status(), TraceeStatus::Stopped);
+            });
+        });
+        tracee.kill();
+    }
+}
+
+// Each iteration hits a breakpoint at write(), then steps over it with the original instruction
+// put back, as `continue` does.
+fn breakpoint_hit_and_resume(c: &mut Criterion) {
+    unsafe {
+        let mut tracee = launch_fixture();
+        let write = tracee
+            .read_library_functions()
+            .into_iter()
+            .find(|function| function.name == "write")
+            .expect("fixture should link write()");
+        let mut original = tracee
+            .patch_memory(write.address, BREAKPOINT_INSTRUCTION)
+            .unwrap();
+        c.bench_function("breakpoint_hit_and_resume", |b| {
+            b.iter(|| {
+                tracee.resume();
+                tracee.wait_on_signal();
+                assert_eq!(tracee.read_general_purpose_registers().pc, write.address);
+                tracee.unpatch_memory(write.address, &original).unwrap();
+                tracee.step(None);
+                tracee.wait_on_signal();
+                original = tracee
+                    .patch_memory(write.address, BREAKPOINT_INSTRUCTION)
+                    .unwrap();
+            });
+        });
+        tracee.kill();
+    }
+}
+
+// Reads the code of the fixture's largest mapping, e.g. of libc, from its start.
+fn bulk_memory_read(c: &mut Criterion) {
+    unsafe {
+        let mut tracee = launch_fixture();
+        let mapping = tracee
+            .read_maps()
+            .into_iter()
+            .filter(|mapping| mapping.permissions.starts_with("r-x"))
+            .max_by_key(|mapping| mapping.end - mapping.start)
+            .expect("fixture should map code");
+        let len = BULK_READ_LEN.min((mapping.end - mapping.start) as usize);
+
+        let mut group = c.benchmark_group("bulk_memory_read");
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_function("read_memory", |b| {
+            b.iter(|| {
+                return tracee.read_memory(mapping.start, len).unwrap();
+            });
+        });
+        group.finish();
+        tracee.kill();
+    }
+}
+
+criterion_group!(
+    benches,
+    single_step,
+    breakpoint_hit_and_resume,
+    bulk_memory_read
+);
+criterion_main!(benches);
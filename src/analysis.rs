@@ -0,0 +1,162 @@
+use crate::{procfs, tracee::Tracee};
+
+const FUTEX_WAIT: u64 = 0;
+const FUTEX_LOCK_PI: u64 = 6;
+const FUTEX_WAIT_BITSET: u64 = 9;
+const FUTEX_WAIT_REQUEUE_PI: u64 = 11;
+const FUTEX_LOCK_PI2: u64 = 13;
+const FUTEX_CMD_MASK: u64 = !(128 | 256);
+
+// Priority-inheritance futexes hold the owner's TID in their low bits.
+const FUTEX_TID_MASK: u32 = 0x3fffffff;
+
+// Encoding of `svc #0`, the AArch64 system call instruction.
+const SVC_INSTRUCTION: u32 = 0xd4000001;
+
+// Offset of `__owner` in glibc's `pthread_mutex_t`, which starts with the futex word `__lock`.
+const PTHREAD_MUTEX_OWNER_OFFSET: u64 = 8;
+
+pub struct FutexWait {
+    pub tid: libc::pid_t,
+    pub address: u64,
+    pub op: u64,
+    pub value: u32,
+    // The thread that holds the lock, if it could be decoded.
+    pub owner_tid: Option<libc::pid_t>,
+}
+
+// Inspects the futex that a thread is blocked on. Returns None if the thread is not waiting on a
+// futex. Owners of plain futexes are only known if the futex is a glibc pthread mutex, so they are
+// only reported if they are threads of the tracee.
+pub unsafe fn inspect_futex_wait(tracee: &Tracee, tid: libc::pid_t) -> Option<FutexWait> {
+    let (number, args) = read_blocking_syscall(tracee, tid)?;
+    if number != libc::SYS_futex {
+        return None;
+    }
+
+    let [address, op, ..] = args;
+    let value = read_u32(tracee, address)?;
+    let owner_tid = match op & FUTEX_CMD_MASK {
+        FUTEX_LOCK_PI | FUTEX_LOCK_PI2 => Some((value & FUTEX_TID_MASK) as libc::pid_t),
+        FUTEX_WAIT | FUTEX_WAIT_BITSET | FUTEX_WAIT_REQUEUE_PI => {
+            read_u32(tracee, address + PTHREAD_MUTEX_OWNER_OFFSET).map(|owner| owner as libc::pid_t)
+        }
+        _ => None,
+    };
+    let owner_tid = owner_tid.filter(|owner_tid| {
+        tracee
+            .threads()
+            .iter()
+            .any(|thread| thread.tid == *owner_tid && thread.tid != tid)
+    });
+
+    return Some(FutexWait {
+        tid,
+        address,
+        op,
+        value,
+        owner_tid,
+    });
+}
+
+pub fn futex_op_name(op: u64) -> &'static str {
+    return match op & FUTEX_CMD_MASK {
+        FUTEX_WAIT => "FUTEX_WAIT",
+        FUTEX_LOCK_PI => "FUTEX_LOCK_PI",
+        FUTEX_WAIT_BITSET => "FUTEX_WAIT_BITSET",
+        FUTEX_WAIT_REQUEUE_PI => "FUTEX_WAIT_REQUEUE_PI",
+        FUTEX_LOCK_PI2 => "FUTEX_LOCK_PI2",
+        _ => "FUTEX_?",
+    };
+}
+
+// Finds the system call that a stopped thread was blocked in.
+unsafe fn read_blocking_syscall(tracee: &Tracee, tid: libc::pid_t) -> Option<(i64, [u64; 6])> {
+    if let Some(syscall) = procfs::read_syscall(tid) {
+        return Some(syscall);
+    }
+
+    // Stopping a thread interrupts its blocking system call, which the kernel prepares to restart
+    // by rewinding the PC to the `svc` instruction and restoring the original arguments. By then,
+    // the kernel no longer reports the system call in procfs, but the registers still describe it.
+    let regs = tracee.read_thread_general_purpose_registers(tid);
+    if read_u32(tracee, regs.pc)? != SVC_INSTRUCTION {
+        return None;
+    }
+
+    let mut args = [0; 6];
+    args.copy_from_slice(&regs.regs[..6]);
+    return Some((regs.regs[8] as i64, args));
+}
+
+// Builds the lock wait-for graph of the tracee's threads, and returns every cycle in it. Each cycle
+// lists the threads involved, starting from the lowest TID, where each thread waits on a lock
+// held by the next one.
+pub unsafe fn find_deadlocks(tracee: &Tracee) -> Vec<Vec<libc::pid_t>> {
+    let edges = tracee
+        .threads()
+        .iter()
+        .filter_map(|thread| inspect_futex_wait(tracee, thread.tid))
+        .filter_map(|wait| Some((wait.tid, wait.owner_tid?)))
+        .collect::<Vec<(libc::pid_t, libc::pid_t)>>();
+    return find_wait_cycles(&edges);
+}
+
+fn find_wait_cycles(edges: &[(libc::pid_t, libc::pid_t)]) -> Vec<Vec<libc::pid_t>> {
+    let waits_on = |tid: libc::pid_t| {
+        edges
+            .iter()
+            .find(|(waiter, _)| *waiter == tid)
+            .map(|(_, owner)| *owner)
+    };
+
+    let mut cycles: Vec<Vec<libc::pid_t>> = vec![];
+    for (start, _) in edges {
+        let mut path = vec![*start];
+        while let Some(owner) = waits_on(*path.last().unwrap()) {
+            if let Some(index) = path.iter().position(|tid| *tid == owner) {
+                let mut cycle = path[index..].to_vec();
+                let min_index = (0..cycle.len()).min_by_key(|i| cycle[*i]).unwrap();
+                cycle.rotate_left(min_index);
+                if !cycles.contains(&cycle) {
+                    cycles.push(cycle);
+                }
+                break;
+            }
+            path.push(owner);
+        }
+    }
+
+    return cycles;
+}
+
+unsafe fn read_u32(tracee: &Tracee, address: u64) -> Option<u32> {
+    let bytes = tracee.read_memory(address, 4).ok()?;
+    return Some(u32::from_ne_bytes(bytes.try_into().unwrap()));
+}
+
+#[cfg(test)]
+mod test {
+    use super::{find_wait_cycles, futex_op_name};
+
+    #[test]
+    fn futex_op_name_ignores_private_and_clock_flags() {
+        assert_eq!(futex_op_name(0), "FUTEX_WAIT");
+        assert_eq!(futex_op_name(128), "FUTEX_WAIT");
+        assert_eq!(futex_op_name(128 | 256 | 9), "FUTEX_WAIT_BITSET");
+        assert_eq!(futex_op_name(128 | 6), "FUTEX_LOCK_PI");
+        assert_eq!(futex_op_name(1), "FUTEX_?");
+    }
+
+    #[test]
+    fn find_wait_cycles_finds_each_cycle_once() {
+        let edges = [(3, 1), (1, 2), (2, 3), (4, 1), (5, 6), (6, 5)];
+        assert_eq!(find_wait_cycles(&edges), vec![vec![1, 2, 3], vec![5, 6]]);
+    }
+
+    #[test]
+    fn find_wait_cycles_ignores_chains() {
+        let edges = [(1, 2), (2, 3), (4, 3)];
+        assert!(find_wait_cycles(&edges).is_empty());
+    }
+}
@@ -0,0 +1,366 @@
+// Assembles aarch64 instructions for patching code in place, e.g. turning a check into a `nop` or
+// a call into a `mov x0, #0`. Only the instructions that such hot-fixes are made of are known;
+// anything else can be written as its encoding with `.inst`.
+//
+// Instructions are separated by semicolons or newlines, and branch targets are absolute addresses,
+// e.g. "mov x0, #1; b 0x4005d0".
+
+// Every instruction is one 32-bit word.
+pub const INSTRUCTION_LEN: u64 = 4;
+
+// The instructions that can be assembled, as they are listed for those who ask for others.
+pub const MNEMONICS: [&str; 15] = [
+    "nop", "ret", "brk", "svc", "b", "bl", "b.<cond>", "cbz", "cbnz", "mov", "movz", "movn",
+    "movk", ".inst", ".word",
+];
+
+// The register number that reads as zero, e.g. in `xzr`.
+const ZERO_REGISTER: u32 = 31;
+
+const CONDITIONS: [(&str, u32); 16] = [
+    ("eq", 0x0),
+    ("ne", 0x1),
+    ("cs", 0x2),
+    ("hs", 0x2),
+    ("cc", 0x3),
+    ("lo", 0x3),
+    ("mi", 0x4),
+    ("pl", 0x5),
+    ("vs", 0x6),
+    ("vc", 0x7),
+    ("hi", 0x8),
+    ("ls", 0x9),
+    ("ge", 0xa),
+    ("lt", 0xb),
+    ("gt", 0xc),
+    ("le", 0xd),
+];
+
+#[derive(PartialEq, Debug, thiserror::Error)]
+pub enum AssemblyError {
+    #[error("no instructions to assemble")]
+    Empty,
+    #[error(
+        "unsupported instruction: \"{0}\" (supported are {mnemonics}; write others as .inst \
+         <encoding>)",
+        mnemonics = MNEMONICS.join(", ")
+    )]
+    UnknownInstruction(String),
+    #[error("invalid operands: \"{0}\"")]
+    InvalidOperands(String),
+    #[error("out of range: \"{0}\"")]
+    OutOfRange(String),
+}
+
+// A general-purpose register operand, e.g. `x0` or `wzr`.
+#[derive(PartialEq, Clone, Copy, Debug)]
+struct Register {
+    number: u32,
+    is_64_bit: bool,
+}
+
+// Assembles instructions that will live at `address`, into their encodings in memory order.
+pub fn assemble(text: &str, address: u64) -> Result<Vec<u8>, AssemblyError> {
+    let instructions = text
+        .split([';', '\n'])
+        .map(str::trim)
+        .filter(|instruction| !instruction.is_empty())
+        .collect::<Vec<&str>>();
+    if instructions.is_empty() {
+        return Err(AssemblyError::Empty);
+    }
+
+    let mut code = vec![];
+    for (i, instruction) in instructions.iter().enumerate() {
+        let word = assemble_instruction(instruction, address + i as u64 * INSTRUCTION_LEN)?;
+        code.extend(word.to_le_bytes());
+    }
+    return Ok(code);
+}
+
+fn assemble_instruction(instruction: &str, address: u64) -> Result<u32, AssemblyError> {
+    let (mnemonic, operands_str) = instruction
+        .split_once(char::is_whitespace)
+        .unwrap_or((instruction, ""));
+    let mnemonic = mnemonic.to_ascii_lowercase();
+    let operands = match operands_str.trim().is_empty() {
+        true => vec![],
+        false => operands_str
+            .split(',')
+            .map(str::trim)
+            .collect::<Vec<&str>>(),
+    };
+    let invalid = || AssemblyError::InvalidOperands(instruction.to_string());
+    let out_of_range = || AssemblyError::OutOfRange(instruction.to_string());
+
+    if let Some(condition) = mnemonic.strip_prefix("b.") {
+        let Some((_, code)) = CONDITIONS.iter().find(|(name, _)| *name == condition) else {
+            return Err(AssemblyError::UnknownInstruction(instruction.to_string()));
+        };
+        let [target] = operands.as_slice() else {
+            return Err(invalid());
+        };
+        let offset = branch_offset(target, address, 19).ok_or_else(out_of_range)?;
+        return Ok(0x54000000 | offset << 5 | code);
+    }
+
+    return match (mnemonic.as_str(), operands.as_slice()) {
+        ("nop", []) => Ok(0xd503201f),
+        ("ret", []) => Ok(0xd65f0000 | 30 << 5),
+        ("ret", [register]) => match parse_register(register) {
+            Some(register) if register.is_64_bit => Ok(0xd65f0000 | register.number << 5),
+            _ => Err(invalid()),
+        },
+        ("brk", [immediate]) => {
+            let immediate = parse_immediate(immediate).ok_or_else(invalid)?;
+            let immediate = u16::try_from(immediate).map_err(|_| out_of_range())?;
+            Ok(0xd4200000 | (immediate as u32) << 5)
+        }
+        ("svc", [immediate]) => {
+            let immediate = parse_immediate(immediate).ok_or_else(invalid)?;
+            let immediate = u16::try_from(immediate).map_err(|_| out_of_range())?;
+            Ok(0xd4000001 | (immediate as u32) << 5)
+        }
+        ("b" | "bl", [target]) => {
+            let offset = branch_offset(target, address, 26).ok_or_else(out_of_range)?;
+            let opcode = match mnemonic.as_str() {
+                "b" => 0x14000000,
+                _ => 0x94000000,
+            };
+            Ok(opcode | offset)
+        }
+        ("cbz" | "cbnz", [register, target]) => {
+            let register = parse_register(register).ok_or_else(invalid)?;
+            let offset = branch_offset(target, address, 19).ok_or_else(out_of_range)?;
+            let opcode = match mnemonic.as_str() {
+                "cbz" => 0x34000000,
+                _ => 0x35000000,
+            };
+            Ok(sf(register) | opcode | offset << 5 | register.number)
+        }
+        ("mov", [destination, source]) => {
+            let destination = parse_register(destination).ok_or_else(invalid)?;
+            match parse_register(source) {
+                // `orr rd, zr, rm`
+                Some(source) if source.is_64_bit == destination.is_64_bit => {
+                    Ok(sf(destination) | 0x2a0003e0 | source.number << 16 | destination.number)
+                }
+                Some(_) => Err(invalid()),
+                None => {
+                    let immediate = parse_immediate(source).ok_or_else(invalid)?;
+                    encode_mov_immediate(destination, immediate).ok_or_else(out_of_range)
+                }
+            }
+        }
+        ("movz" | "movn" | "movk", [destination, immediate, shift @ ..]) if shift.len() <= 1 => {
+            let destination = parse_register(destination).ok_or_else(invalid)?;
+            let immediate = parse_immediate(immediate).ok_or_else(invalid)?;
+            let immediate = u16::try_from(immediate).map_err(|_| out_of_range())?;
+            let shift = match shift.first() {
+                None => 0,
+                Some(shift) => {
+                    let amount = shift
+                        .strip_prefix("lsl")
+                        .and_then(|amount| parse_immediate(amount.trim()))
+                        .ok_or_else(invalid)?;
+                    let max_amount = match destination.is_64_bit {
+                        true => 48,
+                        false => 16,
+                    };
+                    if amount % 16 != 0 || amount > max_amount {
+                        return Err(out_of_range());
+                    }
+                    amount as u32
+                }
+            };
+            let opcode = match mnemonic.as_str() {
+                "movz" => 0x52800000,
+                "movn" => 0x12800000,
+                _ => 0x72800000,
+            };
+            Ok(sf(destination)
+                | opcode
+                | (shift / 16) << 21
+                | (immediate as u32) << 5
+                | destination.number)
+        }
+        (".inst" | ".word", [encoding]) => {
+            let encoding = parse_immediate(encoding).ok_or_else(invalid)?;
+            u32::try_from(encoding).map_err(|_| out_of_range())
+        }
+        (
+            "nop" | "ret" | "brk" | "svc" | "b" | "bl" | "cbz" | "cbnz" | "mov" | "movz" | "movn"
+            | "movk" | ".inst" | ".word",
+            _,
+        ) => Err(invalid()),
+        _ => Err(AssemblyError::UnknownInstruction(instruction.to_string())),
+    };
+}
+
+// Moves an immediate with a single `movz` or `movn`, which covers any value whose bits, or whose
+// inverted bits, all lie within one 16-bit halfword. Returns None for any other value.
+fn encode_mov_immediate(destination: Register, immediate: i64) -> Option<u32> {
+    let (value, halfwords) = match destination.is_64_bit {
+        true => (immediate as u64, 4),
+        false => {
+            // 32-bit registers take values that fit in 32 bits, whether signed or not.
+            if immediate < i32::MIN as i64 || immediate > u32::MAX as i64 {
+                return None;
+            }
+            (immediate as u64 & 0xffffffff, 2)
+        }
+    };
+    let mask = match destination.is_64_bit {
+        true => u64::MAX,
+        false => 0xffffffff,
+    };
+
+    for (opcode, bits) in [(0x52800000, value), (0x12800000, !value & mask)] {
+        for halfword in 0..halfwords {
+            let shift = halfword * 16;
+            if bits & !(0xffff << shift) == 0 {
+                let immediate = (bits >> shift) as u32;
+                return Some(
+                    sf(destination) | opcode | halfword << 21 | immediate << 5 | destination.number,
+                );
+            }
+        }
+    }
+    return None;
+}
+
+// The size flag of instructions that operate on either 32-bit or 64-bit registers.
+fn sf(register: Register) -> u32 {
+    return match register.is_64_bit {
+        true => 1 << 31,
+        false => 0,
+    };
+}
+
+// The offset from `address` to an absolute branch target, in instructions, as a field of `bits`
+// bits. Returns None if the target is unaligned or out of reach.
+fn branch_offset(target: &str, address: u64, bits: u32) -> Option<u32> {
+    let target = parse_immediate(target)? as u64;
+    let offset = target.wrapping_sub(address) as i64;
+    if offset % INSTRUCTION_LEN as i64 != 0 {
+        return None;
+    }
+    let offset = offset / INSTRUCTION_LEN as i64;
+    let limit = 1i64 << (bits - 1);
+    if offset < -limit || offset >= limit {
+        return None;
+    }
+    return Some(offset as u32 & ((1 << bits) - 1));
+}
+
+fn parse_register(operand: &str) -> Option<Register> {
+    let operand = operand.to_ascii_lowercase();
+    let register = match operand.as_str() {
+        "xzr" => (ZERO_REGISTER, true),
+        "wzr" => (ZERO_REGISTER, false),
+        "lr" => (30, true),
+        "fp" => (29, true),
+        _ => {
+            let is_64_bit = match operand.chars().next()? {
+                'x' => true,
+                'w' => false,
+                _ => return None,
+            };
+            let number = operand[1..].parse::<u32>().ok()?;
+            if number >= ZERO_REGISTER || operand[1..].starts_with('+') {
+                return None;
+            }
+            (number, is_64_bit)
+        }
+    };
+    return Some(Register {
+        number: register.0,
+        is_64_bit: register.1,
+    });
+}
+
+// Parses an immediate or an address, in decimal or hex, with or without a leading '#'.
+fn parse_immediate(operand: &str) -> Option<i64> {
+    let operand = operand.strip_prefix('#').unwrap_or(operand);
+    let (is_negative, magnitude) = match operand.strip_prefix('-') {
+        Some(magnitude) => (true, magnitude),
+        None => (false, operand),
+    };
+    let magnitude = match magnitude.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => magnitude.parse::<u64>().ok()?,
+    };
+    return match is_negative {
+        true => Some((magnitude as i64).checked_neg()?),
+        false => Some(magnitude as i64),
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::{assemble, AssemblyError};
+
+    fn words(code: &[u8]) -> Vec<u32> {
+        return code
+            .chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+    }
+
+    #[test]
+    fn assemble_encodes_hot_fix_instructions() {
+        let code = assemble(
+            "nop; ret; ret x1; brk #0; svc #0; mov x0, #0; mov w0, #-1; mov x1, x2; movk x3, #0xbeef, lsl #16",
+            0x1000,
+        )
+        .unwrap();
+        assert_eq!(
+            words(&code),
+            vec![
+                0xd503201f, 0xd65f03c0, 0xd65f0020, 0xd4200000, 0xd4000001, 0xd2800000, 0x12800000,
+                0xaa0203e1, 0xf2b7dde3,
+            ]
+        );
+    }
+
+    #[test]
+    fn assemble_encodes_branches_relative_to_their_address() {
+        let code = assemble("b 0x1000\nbl 0x2000\nb.ne 0x1004\ncbz w2, 0xff8", 0x1008).unwrap();
+        assert_eq!(
+            words(&code),
+            vec![0x17fffffe, 0x940003fd, 0x54ffffa1, 0x34ffff22]
+        );
+    }
+
+    #[test]
+    fn assemble_rejects_what_it_cannot_encode() {
+        assert_eq!(assemble(" ; ", 0x1000), Err(AssemblyError::Empty));
+        assert_eq!(
+            assemble("add x0, x0, #1", 0x1000),
+            Err(AssemblyError::UnknownInstruction(
+                "add x0, x0, #1".to_string()
+            ))
+        );
+        assert!(
+            AssemblyError::UnknownInstruction("add x0, x0, #1".to_string())
+                .to_string()
+                .contains("nop, ret, brk")
+        );
+        assert_eq!(
+            assemble("mov x0, w1", 0x1000),
+            Err(AssemblyError::InvalidOperands("mov x0, w1".to_string()))
+        );
+        assert_eq!(
+            assemble("mov x0, #0x10001", 0x1000),
+            Err(AssemblyError::OutOfRange("mov x0, #0x10001".to_string()))
+        );
+        assert_eq!(
+            assemble("b 0x1002", 0x1000),
+            Err(AssemblyError::OutOfRange("b 0x1002".to_string()))
+        );
+        assert_eq!(
+            assemble(".inst 0xd503201f", 0x1000).map(|code| words(&code)),
+            Ok(vec![0xd503201f])
+        );
+    }
+}
@@ -0,0 +1,273 @@
+// The auxiliary vector, which the kernel hands to a program at exec to tell it, and its dynamic
+// linker, where it was loaded and what the system is like, e.g. AT_ENTRY and AT_BASE.
+use crate::procfs;
+
+const AT_PHDR: u64 = 3;
+const AT_BASE: u64 = 7;
+const AT_ENTRY: u64 = 9;
+const AT_SECURE: u64 = 23;
+const AT_RANDOM: u64 = 25;
+
+// How the value of an entry is shown.
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum EntryFormat {
+    Decimal,
+    Hex,
+    // The address of a NUL-terminated string in the program's memory.
+    String,
+    Boolean,
+}
+
+// The entries that are known, by type, with their names and descriptions as in linux/auxvec.h.
+const ENTRY_TYPES: [(u64, &str, &str, EntryFormat); 30] = [
+    (0, "AT_NULL", "End of vector", EntryFormat::Hex),
+    (1, "AT_IGNORE", "Entry should be ignored", EntryFormat::Hex),
+    (
+        2,
+        "AT_EXECFD",
+        "File descriptor of program",
+        EntryFormat::Decimal,
+    ),
+    (
+        3,
+        "AT_PHDR",
+        "Program headers for program",
+        EntryFormat::Hex,
+    ),
+    (
+        4,
+        "AT_PHENT",
+        "Size of program header entry",
+        EntryFormat::Decimal,
+    ),
+    (
+        5,
+        "AT_PHNUM",
+        "Number of program headers",
+        EntryFormat::Decimal,
+    ),
+    (6, "AT_PAGESZ", "System page size", EntryFormat::Decimal),
+    (
+        7,
+        "AT_BASE",
+        "Base address of interpreter",
+        EntryFormat::Hex,
+    ),
+    (8, "AT_FLAGS", "Flags", EntryFormat::Hex),
+    (9, "AT_ENTRY", "Entry point of program", EntryFormat::Hex),
+    (10, "AT_NOTELF", "Program is not ELF", EntryFormat::Boolean),
+    (11, "AT_UID", "Real user ID", EntryFormat::Decimal),
+    (12, "AT_EUID", "Effective user ID", EntryFormat::Decimal),
+    (13, "AT_GID", "Real group ID", EntryFormat::Decimal),
+    (14, "AT_EGID", "Effective group ID", EntryFormat::Decimal),
+    (
+        15,
+        "AT_PLATFORM",
+        "String identifying platform",
+        EntryFormat::String,
+    ),
+    (
+        16,
+        "AT_HWCAP",
+        "Machine-dependent CPU capability hints",
+        EntryFormat::Hex,
+    ),
+    (
+        17,
+        "AT_CLKTCK",
+        "Frequency of times()",
+        EntryFormat::Decimal,
+    ),
+    (
+        23,
+        "AT_SECURE",
+        "Boolean, was exec setuid-like?",
+        EntryFormat::Boolean,
+    ),
+    (
+        24,
+        "AT_BASE_PLATFORM",
+        "String identifying base platform",
+        EntryFormat::String,
+    ),
+    (
+        25,
+        "AT_RANDOM",
+        "Address of 16 random bytes",
+        EntryFormat::Hex,
+    ),
+    (26, "AT_HWCAP2", "Extension of AT_HWCAP", EntryFormat::Hex),
+    (
+        27,
+        "AT_RSEQ_FEATURE_SIZE",
+        "rseq supported feature size",
+        EntryFormat::Decimal,
+    ),
+    (
+        28,
+        "AT_RSEQ_ALIGN",
+        "rseq allocation alignment",
+        EntryFormat::Decimal,
+    ),
+    (29, "AT_HWCAP3", "Extension of AT_HWCAP", EntryFormat::Hex),
+    (30, "AT_HWCAP4", "Extension of AT_HWCAP", EntryFormat::Hex),
+    (
+        31,
+        "AT_EXECFN",
+        "File name of executable",
+        EntryFormat::String,
+    ),
+    (
+        32,
+        "AT_SYSINFO",
+        "Special system info/entry points",
+        EntryFormat::Hex,
+    ),
+    (
+        33,
+        "AT_SYSINFO_EHDR",
+        "System-supplied DSO's ELF header",
+        EntryFormat::Hex,
+    ),
+    (
+        51,
+        "AT_MINSIGSTKSZ",
+        "Minimal stack size for signal delivery",
+        EntryFormat::Decimal,
+    ),
+];
+
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct AuxiliaryVector {
+    // The entries as pairs of type and value, in order, without the AT_NULL that ends them.
+    entries: Vec<(u64, u64)>,
+}
+
+impl AuxiliaryVector {
+    pub fn parse(auxv: &[u8]) -> AuxiliaryVector {
+        return AuxiliaryVector {
+            entries: procfs::parse_auxv(auxv),
+        };
+    }
+
+    // Reads the auxiliary vector of a process from /proc/<pid>/auxv.
+    pub fn read(pid: libc::pid_t) -> Option<AuxiliaryVector> {
+        return Some(AuxiliaryVector {
+            entries: procfs::read_auxv(pid)?,
+        });
+    }
+
+    pub fn entries(&self) -> &[(u64, u64)] {
+        return &self.entries;
+    }
+
+    pub fn get(&self, kind: u64) -> Option<u64> {
+        return self
+            .entries
+            .iter()
+            .find_map(|(entry_kind, value)| (*entry_kind == kind).then_some(*value));
+    }
+
+    // The entry point of the program, which is where the dynamic linker jumps once it is done.
+    pub fn entry(&self) -> Option<u64> {
+        return self.get(AT_ENTRY);
+    }
+
+    // Where the program headers of the program are mapped.
+    pub fn program_headers(&self) -> Option<u64> {
+        return self.get(AT_PHDR);
+    }
+
+    // Where the dynamic linker was loaded. Statically linked programs have none.
+    pub fn interpreter_base(&self) -> Option<u64> {
+        return self.get(AT_BASE).filter(|base| *base != 0);
+    }
+
+    // The address of the 16 random bytes that e.g. the stack protector's canary is made of.
+    pub fn random_bytes(&self) -> Option<u64> {
+        return self.get(AT_RANDOM);
+    }
+
+    // Whether the program runs with more privileges than the user who ran it, e.g. setuid, in which
+    // case the dynamic linker ignores LD_PRELOAD and the like.
+    pub fn is_secure(&self) -> bool {
+        return self.get(AT_SECURE).is_some_and(|secure| secure != 0);
+    }
+}
+
+// Formats an entry like `info auxv` shows it, e.g.
+// `9    AT_ENTRY             Entry point of program                   0x4005d0`. Strings are read
+// from the program's memory with `read_string`.
+pub fn format_entry(kind: u64, value: u64, read_string: impl Fn(u64) -> Option<String>) -> String {
+    let Some((_, name, description, format)) = ENTRY_TYPES
+        .iter()
+        .find(|(entry_kind, ..)| *entry_kind == kind)
+    else {
+        return format!("{:<4} {:<20} {:<40} {:#x}", kind, "???", "", value);
+    };
+    let value = match format {
+        EntryFormat::Decimal => value.to_string(),
+        EntryFormat::Hex => format!("{:#x}", value),
+        EntryFormat::String => match read_string(value) {
+            None => format!("{:#x}", value),
+            Some(string) => format!("{:#x} {:?}", value, string),
+        },
+        EntryFormat::Boolean => match value {
+            0 => "no".to_string(),
+            _ => "yes".to_string(),
+        },
+    };
+    return format!("{:<4} {:<20} {:<40} {}", kind, name, description, value);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_entry, AuxiliaryVector};
+
+    fn auxv_bytes(entries: &[(u64, u64)]) -> Vec<u8> {
+        return entries
+            .iter()
+            .flat_map(|(kind, value)| [kind.to_ne_bytes(), value.to_ne_bytes()])
+            .flatten()
+            .collect();
+    }
+
+    #[test]
+    fn auxiliary_vector_finds_entries_by_type() {
+        let auxv = AuxiliaryVector::parse(&auxv_bytes(&[
+            (3, 0x400040),
+            (7, 0),
+            (9, 0x4005d0),
+            (23, 1),
+            (0, 0),
+        ]));
+        assert_eq!(auxv.entries().len(), 4);
+        assert_eq!(auxv.program_headers(), Some(0x400040));
+        assert_eq!(auxv.entry(), Some(0x4005d0));
+        assert_eq!(auxv.interpreter_base(), None);
+        assert_eq!(auxv.random_bytes(), None);
+        assert!(auxv.is_secure());
+    }
+
+    #[test]
+    fn format_entry_decodes_values_by_type() {
+        let read_string = |address| (address == 0x7000).then(|| "/bin/ls".to_string());
+        assert_eq!(
+            format_entry(9, 0x4005d0, read_string),
+            format!(
+                "9    {:<20} {:<40} 0x4005d0",
+                "AT_ENTRY", "Entry point of program"
+            )
+        );
+        assert_eq!(
+            format_entry(31, 0x7000, read_string),
+            format!(
+                "31   {:<20} {:<40} 0x7000 \"/bin/ls\"",
+                "AT_EXECFN", "File name of executable"
+            )
+        );
+        assert!(format_entry(23, 0, read_string).ends_with(" no"));
+        assert!(format_entry(6, 4096, read_string).ends_with(" 4096"));
+        assert!(format_entry(99, 0x10, read_string).starts_with("99   ???"));
+    }
+}
@@ -0,0 +1,332 @@
+// Software breakpoints, which replace the instruction at an address with a breakpoint instruction,
+// so that a thread that reaches it stops with SIGTRAP before running it.
+use std::mem;
+
+use crate::{
+    disasm::BREAKPOINT_INSTRUCTION,
+    tracee::{Tracee, TraceeError},
+};
+
+// Where instructions may start.
+#[cfg(target_arch = "aarch64")]
+const INSTRUCTION_ALIGNMENT: u64 = 4;
+#[cfg(target_arch = "x86_64")]
+const INSTRUCTION_ALIGNMENT: u64 = 1;
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Breakpoint {
+    id: usize,
+    address: u64,
+    // What the breakpoint was set at, e.g. "main" or "main.c:12", unless it was set at an address.
+    // It is looked up again in a new program after exec.
+    location: Option<String>,
+    // The bytes of the instruction that the breakpoint instruction replaced, while it is enabled.
+    // Disabled breakpoints have them put back, but are kept for when they are enabled again.
+    original: Option<Vec<u8>>,
+}
+
+impl Breakpoint {
+    // A breakpoint at `address`, which is disabled until it is enabled.
+    pub fn new(id: usize, address: u64) -> Breakpoint {
+        return Breakpoint {
+            id,
+            address,
+            location: None,
+            original: None,
+        };
+    }
+
+    pub fn id(&self) -> usize {
+        return self.id;
+    }
+
+    pub fn address(&self) -> u64 {
+        return self.address;
+    }
+
+    pub fn location(&self) -> Option<&str> {
+        return self.location.as_deref();
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        return self.original.is_some();
+    }
+
+    pub fn original(&self) -> Option<&[u8]> {
+        return self.original.as_deref();
+    }
+
+    // Patches the breakpoint instruction into the tracee, unless it is there already.
+    pub unsafe fn enable(&mut self, tracee: &Tracee) -> Result<(), TraceeError> {
+        if self.original.is_some() {
+            return Ok(());
+        }
+        if !self.address.is_multiple_of(INSTRUCTION_ALIGNMENT) {
+            return Err(TraceeError::SetBreakpoint(
+                "the address is not that of an instruction",
+            ));
+        }
+        self.original = Some(tracee.patch_memory(self.address, BREAKPOINT_INSTRUCTION)?);
+        return Ok(());
+    }
+
+    // Puts back the instruction that the breakpoint instruction replaced, if it is enabled.
+    pub unsafe fn disable(&mut self, tracee: &Tracee) -> Result<(), TraceeError> {
+        let Some(original) = &self.original else {
+            return Ok(());
+        };
+        tracee.unpatch_memory(self.address, original)?;
+        self.original = None;
+        return Ok(());
+    }
+}
+
+// A breakpoint at a location that the program has no code for, e.g. once it has exec'd a new
+// program that does not have the function, or not until a library that has it is loaded.
+#[derive(PartialEq, Clone, Debug)]
+pub struct PendingBreakpoint {
+    pub id: usize,
+    pub location: String,
+}
+
+// The breakpoints of a tracee, in the order that they were set. Their IDs are numbered from 1 and
+// never reused, so that they stay the same however many are deleted.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct BreakpointSet {
+    breakpoints: Vec<Breakpoint>,
+    pending: Vec<PendingBreakpoint>,
+    last_id: usize,
+}
+
+impl BreakpointSet {
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        return &self.breakpoints;
+    }
+
+    pub fn pending(&self) -> &[PendingBreakpoint] {
+        return &self.pending;
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Breakpoint> {
+        return self
+            .breakpoints
+            .iter()
+            .find(|breakpoint| breakpoint.id == id);
+    }
+
+    // The breakpoint at `address`, whether it is enabled or not.
+    pub fn find(&self, address: u64) -> Option<&Breakpoint> {
+        return self
+            .breakpoints
+            .iter()
+            .find(|breakpoint| breakpoint.address == address);
+    }
+
+    // Sets an enabled breakpoint at `address`, which `location` resolved to if it was given. Returns
+    // its ID.
+    pub unsafe fn insert(
+        &mut self,
+        tracee: &Tracee,
+        address: u64,
+        location: Option<&str>,
+    ) -> Result<usize, TraceeError> {
+        let id = self.last_id + 1;
+        self.place(tracee, id, address, location)?;
+        self.last_id = id;
+        return Ok(id);
+    }
+
+    // Sets a pending breakpoint at the address that its location now resolves to, with the same ID.
+    pub unsafe fn resolve_pending(
+        &mut self,
+        tracee: &Tracee,
+        id: usize,
+        address: u64,
+    ) -> Result<(), TraceeError> {
+        let Some(i) = self.pending.iter().position(|pending| pending.id == id) else {
+            return Err(TraceeError::UnknownBreakpoint(id));
+        };
+        let location = self.pending[i].location.clone();
+        self.place(tracee, id, address, Some(&location))?;
+        self.pending.remove(i);
+        return Ok(());
+    }
+
+    unsafe fn place(
+        &mut self,
+        tracee: &Tracee,
+        id: usize,
+        address: u64,
+        location: Option<&str>,
+    ) -> Result<(), TraceeError> {
+        if self.find(address).is_some() {
+            return Err(TraceeError::SetBreakpoint(
+                "there already is one at that address",
+            ));
+        }
+        let mut breakpoint = Breakpoint::new(id, address);
+        breakpoint.location = location.map(str::to_string);
+        breakpoint.enable(tracee)?;
+        self.breakpoints.push(breakpoint);
+        return Ok(());
+    }
+
+    pub unsafe fn enable(&mut self, tracee: &Tracee, id: usize) -> Result<(), TraceeError> {
+        return self.get_mut(id)?.enable(tracee);
+    }
+
+    pub unsafe fn disable(&mut self, tracee: &Tracee, id: usize) -> Result<(), TraceeError> {
+        return self.get_mut(id)?.disable(tracee);
+    }
+
+    // Disables a breakpoint and forgets it.
+    pub unsafe fn delete(&mut self, tracee: &Tracee, id: usize) -> Result<(), TraceeError> {
+        if let Some(i) = self.pending.iter().position(|pending| pending.id == id) {
+            self.pending.remove(i);
+            return Ok(());
+        }
+        self.get_mut(id)?.disable(tracee)?;
+        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+        return Ok(());
+    }
+
+    // Makes the breakpoints that were set at locations pending, without touching the tracee, e.g.
+    // once it has exec'd a new program that they are looked up in again. Those that were set at
+    // addresses mean nothing there, and are forgotten. Returns them.
+    pub fn make_pending(&mut self) -> Vec<Breakpoint> {
+        let (located, forgotten) = mem::take(&mut self.breakpoints)
+            .into_iter()
+            .partition::<Vec<Breakpoint>, _>(|breakpoint| breakpoint.location.is_some());
+        self.pending.extend(located.into_iter().map(|breakpoint| {
+            return PendingBreakpoint {
+                id: breakpoint.id,
+                location: breakpoint.location.unwrap(),
+            };
+        }));
+        self.pending.sort_by_key(|pending| pending.id);
+        return forgotten;
+    }
+
+    fn get_mut(&mut self, id: usize) -> Result<&mut Breakpoint, TraceeError> {
+        return self
+            .breakpoints
+            .iter_mut()
+            .find(|breakpoint| breakpoint.id == id)
+            .ok_or(TraceeError::UnknownBreakpoint(id));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Breakpoint, BreakpointSet, PendingBreakpoint};
+    use crate::{
+        disasm::BREAKPOINT_INSTRUCTION,
+        tracee::{Tracee, TraceeError},
+    };
+
+    #[test]
+    fn breakpoint_is_patched_in_and_restored() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            let pc = tracee.read_general_purpose_registers().pc;
+            let code = tracee
+                .read_memory(pc, BREAKPOINT_INSTRUCTION.len())
+                .unwrap();
+            let mut breakpoint = Breakpoint::new(1, pc);
+            breakpoint.enable(&tracee).unwrap();
+            assert_eq!(breakpoint.original(), Some(code.as_slice()));
+            assert_eq!(
+                tracee
+                    .read_memory(pc, BREAKPOINT_INSTRUCTION.len())
+                    .unwrap(),
+                BREAKPOINT_INSTRUCTION
+            );
+            breakpoint.disable(&tracee).unwrap();
+            assert!(!breakpoint.is_enabled());
+            assert_eq!(
+                tracee
+                    .read_memory(pc, BREAKPOINT_INSTRUCTION.len())
+                    .unwrap(),
+                code
+            );
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn breakpoint_set_keeps_ids_of_disabled_and_deleted_breakpoints() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            let pc = tracee.read_general_purpose_registers().pc;
+            let code = tracee
+                .read_memory(pc, BREAKPOINT_INSTRUCTION.len())
+                .unwrap();
+            let mut breakpoints = BreakpointSet::default();
+            assert_eq!(breakpoints.insert(&tracee, pc, None).unwrap(), 1);
+            assert!(matches!(
+                breakpoints.insert(&tracee, pc, None),
+                Err(TraceeError::SetBreakpoint(_))
+            ));
+            assert_eq!(breakpoints.insert(&tracee, pc + 4, None).unwrap(), 2);
+
+            breakpoints.disable(&tracee, 1).unwrap();
+            assert!(!breakpoints.get(1).unwrap().is_enabled());
+            assert_eq!(
+                tracee
+                    .read_memory(pc, BREAKPOINT_INSTRUCTION.len())
+                    .unwrap(),
+                code
+            );
+            breakpoints.enable(&tracee, 1).unwrap();
+            assert!(breakpoints.get(1).unwrap().is_enabled());
+
+            breakpoints.delete(&tracee, 1).unwrap();
+            assert!(matches!(
+                breakpoints.delete(&tracee, 1),
+                Err(TraceeError::UnknownBreakpoint(1))
+            ));
+            assert_eq!(breakpoints.insert(&tracee, pc, None).unwrap(), 3);
+            assert_eq!(breakpoints.find(pc + 4).unwrap().id(), 2);
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn breakpoint_set_keeps_breakpoints_at_locations_pending() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            let pc = tracee.read_general_purpose_registers().pc;
+            let mut breakpoints = BreakpointSet::default();
+            assert_eq!(breakpoints.insert(&tracee, pc, None).unwrap(), 1);
+            assert_eq!(
+                breakpoints.insert(&tracee, pc + 4, Some("main")).unwrap(),
+                2
+            );
+
+            let forgotten = breakpoints.make_pending();
+            assert_eq!(
+                forgotten.iter().map(Breakpoint::id).collect::<Vec<usize>>(),
+                vec![1]
+            );
+            assert!(breakpoints.breakpoints().is_empty());
+            assert_eq!(
+                breakpoints.pending(),
+                &[PendingBreakpoint {
+                    id: 2,
+                    location: "main".to_string()
+                }]
+            );
+
+            breakpoints.resolve_pending(&tracee, 2, pc + 8).unwrap();
+            assert!(breakpoints.pending().is_empty());
+            let breakpoint = breakpoints.find(pc + 8).unwrap();
+            assert_eq!((breakpoint.id(), breakpoint.location()), (2, Some("main")));
+            assert!(breakpoint.is_enabled());
+            assert!(matches!(
+                breakpoints.resolve_pending(&tracee, 2, pc + 8),
+                Err(TraceeError::UnknownBreakpoint(2))
+            ));
+            tracee.kill();
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// Call traces, which `ltrace` and `ftrace` set up without stopping the tracee: a breakpoint at the
+// entry of each traced function notes the call and its arguments, and a breakpoint at the return
+// address of the call reports it along with what it returned. pbreak handles both and lets the
+// thread carry on.
+use std::time::{Duration, Instant};
+
+// How many of the arguments of a call are shown, from x0 on. Without prototypes, the number that
+// a function takes is unknown.
+pub const SHOWN_ARGUMENT_COUNT: usize = 4;
+
+// Which command traces the calls of a function.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum CallTraceKind {
+    // `ltrace`, at the PLT stubs through which the program calls into libraries.
+    Library,
+    // `ftrace`, at the entries of the functions that it is given.
+    Function,
+}
+
+// A function whose calls are traced, by a breakpoint at its entry.
+#[derive(PartialEq, Clone, Debug)]
+pub struct CallTrace {
+    pub name: String,
+    pub address: u64,
+    pub kind: CallTraceKind,
+    // The bytes that the breakpoint replaced.
+    pub original: Vec<u8>,
+}
+
+// A traced call that a thread has yet to return from.
+#[derive(PartialEq, Clone, Debug)]
+pub struct TracedCall {
+    pub name: String,
+    pub kind: CallTraceKind,
+    pub arguments: Vec<u64>,
+    // Where the call returns to, and the sp that it returns with, which tells the returns of
+    // recursive calls apart.
+    pub return_address: u64,
+    pub sp: u64,
+    pub entered_at: Instant,
+}
+
+// Formats a library call once it has returned, as ltrace does, e.g.
+// `puts(0x4005d0, 0x1, 0x0, 0x0) = 0x6`.
+pub fn format_library_call(name: &str, arguments: &[u64], return_value: u64) -> String {
+    let arguments = arguments
+        .iter()
+        .map(|argument| format!("{:#x}", argument))
+        .collect::<Vec<String>>();
+    return format!("{}({}) = {:#x}", name, arguments.join(", "), return_value);
+}
+
+// Formats the entry of a function that `ftrace` traces, indented by how many traced functions the
+// thread is in already, e.g. `  -> fib`.
+pub fn format_function_entry(name: &str, depth: usize) -> String {
+    return format!("{}-> {}", "  ".repeat(depth), name);
+}
+
+// Formats the return of a function that `ftrace` traces, indented like its entry, along with how
+// long the call took, e.g. `  <- fib = 0x1 (0.000012s)`.
+pub fn format_function_return(
+    name: &str,
+    depth: usize,
+    return_value: u64,
+    elapsed: Duration,
+) -> String {
+    return format!(
+        "{}<- {} = {:#x} ({:.6}s)",
+        "  ".repeat(depth),
+        name,
+        return_value,
+        elapsed.as_secs_f64()
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{format_function_entry, format_function_return, format_library_call};
+
+    #[test]
+    fn format_library_call_shows_arguments_and_return_value() {
+        assert_eq!(
+            format_library_call("puts", &[0x4005d0, 0x1, 0x0, 0x0], 0x6),
+            "puts(0x4005d0, 0x1, 0x0, 0x0) = 0x6"
+        );
+        assert_eq!(format_library_call("abort", &[], 0), "abort() = 0x0");
+    }
+
+    #[test]
+    fn format_function_entry_and_return_are_indented_by_depth() {
+        assert_eq!(format_function_entry("main", 0), "-> main");
+        assert_eq!(format_function_entry("fib", 2), "    -> fib");
+        assert_eq!(
+            format_function_return("fib", 2, 0x1, Duration::from_micros(12)),
+            "    <- fib = 0x1 (0.000012s)"
+        );
+    }
+}
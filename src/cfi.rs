@@ -0,0 +1,528 @@
+// Call frame information (.eh_frame), which tells how to recover the registers of a function's
+// caller at any instruction of the function: where the canonical frame address (CFA), i.e. the
+// stack pointer at the call, is, and where the function saved the caller's registers relative to
+// it. Unlike the frame record chain, it also covers functions that keep no frame record.
+use crate::{
+    dwarf::{DwarfError, Reader},
+    elf::ElfFile,
+};
+
+// Opcodes that keep an operand in their low 6 bits (see section 6.4.2 of DWARF 5).
+const DW_CFA_ADVANCE_LOC: u8 = 0x40;
+const DW_CFA_OFFSET: u8 = 0x80;
+const DW_CFA_RESTORE: u8 = 0xc0;
+
+// Opcodes that are the whole byte.
+const DW_CFA_NOP: u8 = 0x00;
+const DW_CFA_SET_LOC: u8 = 0x01;
+const DW_CFA_ADVANCE_LOC1: u8 = 0x02;
+const DW_CFA_ADVANCE_LOC2: u8 = 0x03;
+const DW_CFA_ADVANCE_LOC4: u8 = 0x04;
+const DW_CFA_OFFSET_EXTENDED: u8 = 0x05;
+const DW_CFA_RESTORE_EXTENDED: u8 = 0x06;
+const DW_CFA_UNDEFINED: u8 = 0x07;
+const DW_CFA_SAME_VALUE: u8 = 0x08;
+const DW_CFA_REGISTER: u8 = 0x09;
+const DW_CFA_REMEMBER_STATE: u8 = 0x0a;
+const DW_CFA_RESTORE_STATE: u8 = 0x0b;
+const DW_CFA_DEF_CFA: u8 = 0x0c;
+const DW_CFA_DEF_CFA_REGISTER: u8 = 0x0d;
+const DW_CFA_DEF_CFA_OFFSET: u8 = 0x0e;
+const DW_CFA_DEF_CFA_EXPRESSION: u8 = 0x0f;
+const DW_CFA_EXPRESSION: u8 = 0x10;
+const DW_CFA_OFFSET_EXTENDED_SF: u8 = 0x11;
+const DW_CFA_DEF_CFA_SF: u8 = 0x12;
+const DW_CFA_DEF_CFA_OFFSET_SF: u8 = 0x13;
+const DW_CFA_VAL_OFFSET: u8 = 0x14;
+const DW_CFA_VAL_OFFSET_SF: u8 = 0x15;
+const DW_CFA_VAL_EXPRESSION: u8 = 0x16;
+// Toggles whether the return address is signed, on AArch64. Addresses are unsigned by masking.
+const DW_CFA_AARCH64_NEGATE_RA_STATE: u8 = 0x2d;
+const DW_CFA_GNU_ARGS_SIZE: u8 = 0x2e;
+const DW_CFA_GNU_NEGATIVE_OFFSET_EXTENDED: u8 = 0x2f;
+
+// How pointers in .eh_frame are encoded: their format in the low bits, and what they are relative
+// to in the high bits (see the Linux Standard Base, section 10.5).
+const DW_EH_PE_OMIT: u8 = 0xff;
+const DW_EH_PE_ABSPTR: u8 = 0x00;
+const DW_EH_PE_ULEB128: u8 = 0x01;
+const DW_EH_PE_UDATA2: u8 = 0x02;
+const DW_EH_PE_UDATA4: u8 = 0x03;
+const DW_EH_PE_UDATA8: u8 = 0x04;
+const DW_EH_PE_SLEB128: u8 = 0x09;
+const DW_EH_PE_SDATA2: u8 = 0x0a;
+const DW_EH_PE_SDATA4: u8 = 0x0b;
+const DW_EH_PE_SDATA8: u8 = 0x0c;
+const DW_EH_PE_PCREL: u8 = 0x10;
+
+// The DWARF numbers of the AArch64 registers: x0-x30, sp, then v0-v31 from 64 on, of which the
+// call frame information only describes the low 64 bits.
+pub const DWARF_SP: usize = 31;
+pub const DWARF_V0: usize = 64;
+pub const DWARF_REGISTER_COUNT: usize = 96;
+
+// Where a register of the caller is, at some instruction of the function.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum RegisterRule {
+    // The caller's value is lost.
+    Undefined,
+    // The function has not changed the register.
+    SameValue,
+    // Saved in memory at the CFA plus the offset.
+    Offset(i64),
+    // Is the CFA plus the offset.
+    ValOffset(i64),
+    // Held in another register.
+    Register(u16),
+}
+
+// How to recover the caller's registers at an instruction.
+#[derive(PartialEq, Clone, Debug)]
+pub struct UnwindRow {
+    // The CFA is the value of the register plus the offset.
+    pub cfa_register: u16,
+    pub cfa_offset: i64,
+    // The register that holds the return address, by the rules below.
+    pub return_address_register: u16,
+    // The rules of the registers that the function has changed. Others keep their value.
+    pub registers: Vec<(u16, RegisterRule)>,
+}
+
+// A common information entry, which FDEs share.
+struct Cie<'a> {
+    code_alignment: u64,
+    data_alignment: i64,
+    return_address_register: u16,
+    pointer_encoding: u8,
+    has_augmentation_data: bool,
+    initial_instructions: &'a [u8],
+}
+
+impl UnwindRow {
+    pub fn rule(&self, register: u16) -> RegisterRule {
+        return self
+            .registers
+            .iter()
+            .find(|(other, _)| *other == register)
+            .map_or(RegisterRule::SameValue, |(_, rule)| *rule);
+    }
+
+    fn set_rule(&mut self, register: u16, rule: RegisterRule) {
+        self.registers.retain(|(other, _)| *other != register);
+        self.registers.push((register, rule));
+    }
+
+    // Puts back the rule that the CIE gave a register, if any.
+    fn restore_rule(&mut self, register: u16, initial: Option<&UnwindRow>) {
+        self.registers.retain(|(other, _)| *other != register);
+        if let Some(rule) = initial.and_then(|initial| {
+            return initial
+                .registers
+                .iter()
+                .find(|(other, _)| *other == register);
+        }) {
+            self.registers.push(*rule);
+        }
+    }
+}
+
+// Finds how to unwind the instruction at the address, relative to the load bias, from the
+// .eh_frame section of a file. Returns None if no FDE covers the address.
+pub fn find_unwind_row(elf: &ElfFile, address: u64) -> Result<Option<UnwindRow>, DwarfError> {
+    let Some(eh_frame) = elf.section_data(".eh_frame")? else {
+        return Ok(None);
+    };
+    let section_address = elf.section_address(".eh_frame")?.unwrap_or(0);
+    return find_row_in_section(eh_frame, section_address, address);
+}
+
+fn find_row_in_section(
+    eh_frame: &[u8],
+    section_address: u64,
+    address: u64,
+) -> Result<Option<UnwindRow>, DwarfError> {
+    let mut reader = Reader::new(eh_frame);
+    while !reader.is_empty() {
+        let (len, _) = reader.initial_length()?;
+        // A zero length terminates the section.
+        if len == 0 {
+            return Ok(None);
+        }
+        let entry_start = reader.position;
+        let end = entry_start.saturating_add(len as usize);
+        if end > eh_frame.len() {
+            return Err(DwarfError::Malformed("entry out of bounds"));
+        }
+
+        // CIEs have an ID of zero. FDEs have the distance back to their CIE instead.
+        let cie_pointer = reader.u32()? as usize;
+        if cie_pointer != 0 {
+            let cie_offset = entry_start
+                .checked_sub(cie_pointer)
+                .ok_or(DwarfError::Malformed("CIE pointer out of bounds"))?;
+            let cie = parse_cie(eh_frame, cie_offset)?;
+            let field_address = section_address + reader.position as u64;
+            let pc_begin = apply_encoding(
+                read_encoded(&mut reader, cie.pointer_encoding)?,
+                cie.pointer_encoding,
+                field_address,
+            )?;
+            let pc_range = read_encoded(&mut reader, cie.pointer_encoding & 0x0f)?;
+            if cie.has_augmentation_data {
+                let len = reader.uleb128()? as usize;
+                reader.bytes(len)?;
+            }
+            if (pc_begin..pc_begin.wrapping_add(pc_range)).contains(&address) {
+                let instructions = &eh_frame[reader.position..end];
+                let mut initial = UnwindRow {
+                    cfa_register: DWARF_SP as u16,
+                    cfa_offset: 0,
+                    return_address_register: cie.return_address_register,
+                    registers: vec![],
+                };
+                run_instructions(
+                    cie.initial_instructions,
+                    &cie,
+                    None,
+                    section_address,
+                    pc_begin,
+                    address,
+                    &mut initial,
+                )?;
+                let mut row = initial.clone();
+                run_instructions(
+                    instructions,
+                    &cie,
+                    Some(&initial),
+                    section_address,
+                    pc_begin,
+                    address,
+                    &mut row,
+                )?;
+                return Ok(Some(row));
+            }
+        }
+        reader.position = end;
+    }
+    return Ok(None);
+}
+
+fn parse_cie(eh_frame: &[u8], offset: usize) -> Result<Cie<'_>, DwarfError> {
+    let mut reader = Reader::new(eh_frame);
+    reader.position = offset;
+    let (len, _) = reader.initial_length()?;
+    let end = reader.position.saturating_add(len as usize);
+    if end > eh_frame.len() {
+        return Err(DwarfError::Malformed("CIE out of bounds"));
+    }
+    if reader.u32()? != 0 {
+        return Err(DwarfError::Malformed("CIE pointer does not point to a CIE"));
+    }
+    let version = reader.u8()?;
+    let augmentation = reader.c_string()?;
+    let code_alignment = reader.uleb128()?;
+    let data_alignment = reader.sleb128()?;
+    let return_address_register = match version {
+        1 => reader.u8()? as u16,
+        _ => reader.uleb128()? as u16,
+    };
+
+    let mut pointer_encoding = DW_EH_PE_ABSPTR;
+    let has_augmentation_data = augmentation.starts_with('z');
+    if has_augmentation_data {
+        let len = reader.uleb128()? as usize;
+        let data_end = reader.position + len;
+        for c in augmentation.chars().skip(1) {
+            match c {
+                'L' => {
+                    reader.u8()?;
+                }
+                'P' => {
+                    let encoding = reader.u8()?;
+                    read_encoded(&mut reader, encoding)?;
+                }
+                'R' => pointer_encoding = reader.u8()?,
+                // The rest carry no data, e.g. 'S' for signal frames and 'B' for BTI.
+                _ => {}
+            }
+        }
+        reader.position = data_end;
+    } else if !augmentation.is_empty() {
+        return Err(DwarfError::Unsupported("CIE augmentation"));
+    }
+
+    return Ok(Cie {
+        code_alignment,
+        data_alignment,
+        return_address_register,
+        pointer_encoding,
+        has_augmentation_data,
+        initial_instructions: eh_frame
+            .get(reader.position..end)
+            .ok_or(DwarfError::Malformed("CIE out of bounds"))?,
+    });
+}
+
+// Reads a pointer in the format of the encoding, without applying what it is relative to.
+fn read_encoded(reader: &mut Reader, encoding: u8) -> Result<u64, DwarfError> {
+    if encoding == DW_EH_PE_OMIT {
+        return Ok(0);
+    }
+    return match encoding & 0x0f {
+        DW_EH_PE_ABSPTR | DW_EH_PE_UDATA8 | DW_EH_PE_SDATA8 => reader.u64(),
+        DW_EH_PE_ULEB128 => reader.uleb128(),
+        DW_EH_PE_UDATA2 => Ok(reader.u16()? as u64),
+        DW_EH_PE_UDATA4 => Ok(reader.u32()? as u64),
+        DW_EH_PE_SLEB128 => Ok(reader.sleb128()? as u64),
+        DW_EH_PE_SDATA2 => Ok(reader.u16()? as i16 as u64),
+        DW_EH_PE_SDATA4 => Ok(reader.u32()? as i32 as u64),
+        _ => Err(DwarfError::Unsupported("pointer encoding")),
+    };
+}
+
+fn apply_encoding(value: u64, encoding: u8, field_address: u64) -> Result<u64, DwarfError> {
+    return match encoding & 0x70 {
+        DW_EH_PE_ABSPTR => Ok(value),
+        DW_EH_PE_PCREL => Ok(field_address.wrapping_add(value)),
+        _ => Err(DwarfError::Unsupported("pointer encoding")),
+    };
+}
+
+// Runs the instructions of a CIE or FDE, which start at the given location, until the row is that
+// of the address.
+fn run_instructions(
+    instructions: &[u8],
+    cie: &Cie,
+    initial: Option<&UnwindRow>,
+    section_address: u64,
+    mut location: u64,
+    address: u64,
+    row: &mut UnwindRow,
+) -> Result<(), DwarfError> {
+    let mut reader = Reader::new(instructions);
+    let mut remembered = vec![];
+    while !reader.is_empty() {
+        let opcode = reader.u8()?;
+        let operand = opcode & 0x3f;
+        let mut advance = None;
+        match opcode & 0xc0 {
+            DW_CFA_ADVANCE_LOC => advance = Some(operand as u64),
+            DW_CFA_OFFSET => {
+                let offset = reader.uleb128()? as i64 * cie.data_alignment;
+                row.set_rule(operand as u16, RegisterRule::Offset(offset));
+            }
+            DW_CFA_RESTORE => row.restore_rule(operand as u16, initial),
+            _ => match opcode {
+                DW_CFA_NOP | DW_CFA_AARCH64_NEGATE_RA_STATE => {}
+                DW_CFA_SET_LOC => {
+                    let field_address = section_address + reader.position as u64;
+                    let value = read_encoded(&mut reader, cie.pointer_encoding)?;
+                    location = apply_encoding(value, cie.pointer_encoding, field_address)?;
+                    if location > address {
+                        return Ok(());
+                    }
+                }
+                DW_CFA_ADVANCE_LOC1 => advance = Some(reader.u8()? as u64),
+                DW_CFA_ADVANCE_LOC2 => advance = Some(reader.u16()? as u64),
+                DW_CFA_ADVANCE_LOC4 => advance = Some(reader.u32()? as u64),
+                DW_CFA_OFFSET_EXTENDED => {
+                    let register = reader.uleb128()? as u16;
+                    let offset = reader.uleb128()? as i64 * cie.data_alignment;
+                    row.set_rule(register, RegisterRule::Offset(offset));
+                }
+                DW_CFA_OFFSET_EXTENDED_SF => {
+                    let register = reader.uleb128()? as u16;
+                    let offset = reader.sleb128()? * cie.data_alignment;
+                    row.set_rule(register, RegisterRule::Offset(offset));
+                }
+                DW_CFA_GNU_NEGATIVE_OFFSET_EXTENDED => {
+                    let register = reader.uleb128()? as u16;
+                    let offset = -(reader.uleb128()? as i64) * cie.data_alignment;
+                    row.set_rule(register, RegisterRule::Offset(offset));
+                }
+                DW_CFA_VAL_OFFSET => {
+                    let register = reader.uleb128()? as u16;
+                    let offset = reader.uleb128()? as i64 * cie.data_alignment;
+                    row.set_rule(register, RegisterRule::ValOffset(offset));
+                }
+                DW_CFA_VAL_OFFSET_SF => {
+                    let register = reader.uleb128()? as u16;
+                    let offset = reader.sleb128()? * cie.data_alignment;
+                    row.set_rule(register, RegisterRule::ValOffset(offset));
+                }
+                DW_CFA_RESTORE_EXTENDED => {
+                    let register = reader.uleb128()? as u16;
+                    row.restore_rule(register, initial);
+                }
+                DW_CFA_UNDEFINED => {
+                    let register = reader.uleb128()? as u16;
+                    row.set_rule(register, RegisterRule::Undefined);
+                }
+                DW_CFA_SAME_VALUE => {
+                    let register = reader.uleb128()? as u16;
+                    row.set_rule(register, RegisterRule::SameValue);
+                }
+                DW_CFA_REGISTER => {
+                    let register = reader.uleb128()? as u16;
+                    let other = reader.uleb128()? as u16;
+                    row.set_rule(register, RegisterRule::Register(other));
+                }
+                DW_CFA_REMEMBER_STATE => remembered.push(row.registers.clone()),
+                DW_CFA_RESTORE_STATE => {
+                    row.registers = remembered.pop().ok_or(DwarfError::Malformed(
+                        "state restored without being remembered",
+                    ))?;
+                }
+                DW_CFA_DEF_CFA => {
+                    row.cfa_register = reader.uleb128()? as u16;
+                    row.cfa_offset = reader.uleb128()? as i64;
+                }
+                DW_CFA_DEF_CFA_SF => {
+                    row.cfa_register = reader.uleb128()? as u16;
+                    row.cfa_offset = reader.sleb128()? * cie.data_alignment;
+                }
+                DW_CFA_DEF_CFA_REGISTER => row.cfa_register = reader.uleb128()? as u16,
+                DW_CFA_DEF_CFA_OFFSET => row.cfa_offset = reader.uleb128()? as i64,
+                DW_CFA_DEF_CFA_OFFSET_SF => {
+                    row.cfa_offset = reader.sleb128()? * cie.data_alignment;
+                }
+                DW_CFA_GNU_ARGS_SIZE => {
+                    reader.uleb128()?;
+                }
+                DW_CFA_DEF_CFA_EXPRESSION | DW_CFA_EXPRESSION | DW_CFA_VAL_EXPRESSION => {
+                    return Err(DwarfError::Unsupported("DWARF expressions in CFI"));
+                }
+                _ => return Err(DwarfError::Unsupported("CFA instruction")),
+            },
+        }
+
+        if let Some(delta) = advance {
+            location = location.wrapping_add(delta * cie.code_alignment);
+            if location > address {
+                return Ok(());
+            }
+        }
+    }
+    return Ok(());
+}
+
+// Computes the caller's registers, by DWARF number, from those at the instruction that the row
+// is of. Registers that the row does not describe keep their value. Returns None if a register
+// that was saved in memory cannot be read.
+pub fn unwind_registers(
+    row: &UnwindRow,
+    regs: &[u64; DWARF_REGISTER_COUNT],
+    read_word: impl Fn(u64) -> Option<u64>,
+) -> Option<[u64; DWARF_REGISTER_COUNT]> {
+    let cfa = regs
+        .get(row.cfa_register as usize)?
+        .wrapping_add_signed(row.cfa_offset);
+    let mut caller = *regs;
+    for (register, rule) in &row.registers {
+        let Some(value) = caller.get_mut(*register as usize) else {
+            continue;
+        };
+        *value = match rule {
+            RegisterRule::Undefined | RegisterRule::SameValue => continue,
+            RegisterRule::Offset(offset) => read_word(cfa.wrapping_add_signed(*offset))?,
+            RegisterRule::ValOffset(offset) => cfa.wrapping_add_signed(*offset),
+            RegisterRule::Register(other) => *regs.get(*other as usize)?,
+        };
+    }
+    caller[DWARF_SP] = cfa;
+    return Some(caller);
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{
+        find_row_in_section, find_unwind_row, unwind_registers, RegisterRule, UnwindRow,
+        DWARF_REGISTER_COUNT, DWARF_SP,
+    };
+    use crate::{elf::ElfFile, procfs::read_exe_path};
+
+    // An .eh_frame at 0x1000 whose one FDE covers 0x2000-0x2010, as a function that pushes a frame
+    // record and then sets up x29 as its frame pointer.
+    fn eh_frame() -> Vec<u8> {
+        let mut cie = vec![0, 0, 0, 0, 1];
+        cie.extend_from_slice(b"zR\0");
+        // Code alignment 4, data alignment -8, return address in x30, pcrel|sdata4 pointers.
+        cie.extend_from_slice(&[4, 0x78, 30, 1, 0x1b]);
+        // DW_CFA_def_cfa sp, 0.
+        cie.extend_from_slice(&[0x0c, 31, 0]);
+        cie.resize(cie.len().next_multiple_of(4), 0);
+
+        let fde_start = 4 + cie.len();
+        let mut fde = ((fde_start + 4) as u32).to_le_bytes().to_vec();
+        let pc_begin_address = 0x1000 + fde_start as i64 + 8;
+        fde.extend_from_slice(&((0x2000 - pc_begin_address) as i32).to_le_bytes());
+        fde.extend_from_slice(&0x10u32.to_le_bytes());
+        fde.push(0);
+        // At 0x2004: DW_CFA_def_cfa_offset 16, DW_CFA_offset x29 -16, DW_CFA_offset x30 -8.
+        fde.extend_from_slice(&[0x41, 0x0e, 16, 0x80 | 29, 2, 0x80 | 30, 1]);
+        // At 0x200c: DW_CFA_def_cfa_register x29.
+        fde.extend_from_slice(&[0x42, 0x0d, 29]);
+        fde.resize(fde.len().next_multiple_of(4), 0);
+
+        let mut section = (cie.len() as u32).to_le_bytes().to_vec();
+        section.extend_from_slice(&cie);
+        section.extend_from_slice(&(fde.len() as u32).to_le_bytes());
+        section.extend_from_slice(&fde);
+        section.extend_from_slice(&[0, 0, 0, 0]);
+        return section;
+    }
+
+    fn find_row(address: u64) -> Option<UnwindRow> {
+        return find_row_in_section(&eh_frame(), 0x1000, address).unwrap();
+    }
+
+    #[test]
+    fn find_row_in_section_runs_instructions_up_to_address() {
+        let entry = find_row(0x2000).unwrap();
+        assert_eq!((entry.cfa_register, entry.cfa_offset), (31, 0));
+        assert_eq!(entry.rule(30), RegisterRule::SameValue);
+
+        let body = find_row(0x2008).unwrap();
+        assert_eq!((body.cfa_register, body.cfa_offset), (31, 16));
+        assert_eq!(body.rule(29), RegisterRule::Offset(-16));
+        assert_eq!(body.rule(30), RegisterRule::Offset(-8));
+        assert_eq!(body.return_address_register, 30);
+
+        let framed = find_row(0x200c).unwrap();
+        assert_eq!((framed.cfa_register, framed.cfa_offset), (29, 16));
+        assert!(find_row(0x1fff).is_none());
+        assert!(find_row(0x2010).is_none());
+    }
+
+    #[test]
+    fn unwind_registers_reads_saved_registers() {
+        let row = find_row(0x200c).unwrap();
+        let mut regs = [0u64; DWARF_REGISTER_COUNT];
+        regs[29] = 0x7ff0;
+        regs[DWARF_SP] = 0x7fe0;
+        regs[19] = 42;
+        let memory = HashMap::from([(0x7ff0, 0x8000), (0x7ff8, 0x400123)]);
+        let caller =
+            unwind_registers(&row, &regs, |address| memory.get(&address).copied()).unwrap();
+        assert_eq!(caller[DWARF_SP], 0x8000);
+        assert_eq!(caller[29], 0x8000);
+        assert_eq!(caller[30], 0x400123);
+        assert_eq!(caller[19], 42);
+        assert!(unwind_registers(&row, &regs, |_| None).is_none());
+    }
+
+    #[test]
+    fn find_unwind_row_covers_functions_of_current_executable() {
+        let path = read_exe_path(std::process::id() as libc::pid_t);
+        let elf = ElfFile::read(&path).unwrap();
+        let function = elf
+            .function_symbols()
+            .unwrap()
+            .into_iter()
+            .find(|function| function.name.contains("find_unwind_row_covers_functions"))
+            .unwrap();
+        assert!(find_unwind_row(&elf, function.address).unwrap().is_some());
+    }
+}
@@ -0,0 +1,431 @@
+// Leaves tracees in a usable state if pbreak panics or is killed: any code that was patched into
+// them is restored, and they are detached from rather than killed along with pbreak.
+//
+// Cleanup may run in a signal handler, so everything here is limited to atomics and raw system
+// calls.
+use std::{
+    ptr::null_mut,
+    sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicUsize, Ordering},
+};
+
+const MAX_TRACEES: usize = 64;
+const MAX_PATCHES: usize = 1024;
+
+// Signals that end pbreak, and so trigger cleanup.
+const FATAL_SIGNALS: [libc::c_int; 7] = [
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGABRT,
+    libc::SIGBUS,
+    libc::SIGSEGV,
+];
+
+// The tracees, by PID (0 for a free slot), and whether they were attached with PTRACE_SEIZE.
+static TRACEE_PIDS: [AtomicI32; MAX_TRACEES] = [const { AtomicI32::new(0) }; MAX_TRACEES];
+static TRACEE_SEIZED: [AtomicBool; MAX_TRACEES] = [const { AtomicBool::new(false) }; MAX_TRACEES];
+
+// Words of tracee memory that were overwritten, e.g. by breakpoints, along with their original
+// contents, how many patches overwrite them, and when the first of those was made.
+static PATCH_PIDS: [AtomicI32; MAX_PATCHES] = [const { AtomicI32::new(0) }; MAX_PATCHES];
+static PATCH_ADDRESSES: [AtomicU64; MAX_PATCHES] = [const { AtomicU64::new(0) }; MAX_PATCHES];
+static PATCH_ORIGINALS: [AtomicU64; MAX_PATCHES] = [const { AtomicU64::new(0) }; MAX_PATCHES];
+static PATCH_COUNTS: [AtomicUsize; MAX_PATCHES] = [const { AtomicUsize::new(0) }; MAX_PATCHES];
+static PATCH_SEQUENCES: [AtomicU64; MAX_PATCHES] = [const { AtomicU64::new(0) }; MAX_PATCHES];
+static NEXT_PATCH_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+static HAS_RUN: AtomicBool = AtomicBool::new(false);
+
+pub fn register_tracee(pid: libc::pid_t, seized: bool) {
+    for (i, slot) in TRACEE_PIDS.iter().enumerate() {
+        if slot
+            .compare_exchange(0, pid, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            TRACEE_SEIZED[i].store(seized, Ordering::SeqCst);
+            return;
+        }
+    }
+}
+
+// Forgets a tracee, along with its patches, e.g. once it has been detached from or has exited.
+pub fn unregister_tracee(pid: libc::pid_t) {
+    for slot in &TRACEE_PIDS {
+        let _ = slot.compare_exchange(pid, 0, Ordering::SeqCst, Ordering::SeqCst);
+    }
+    for slot in &PATCH_PIDS {
+        let _ = slot.compare_exchange(pid, 0, Ordering::SeqCst, Ordering::SeqCst);
+    }
+}
+
+fn find_patch(pid: libc::pid_t, address: u64) -> Option<usize> {
+    return (0..MAX_PATCHES).find(|i| {
+        return PATCH_PIDS[*i].load(Ordering::SeqCst) == pid
+            && PATCH_ADDRESSES[*i].load(Ordering::SeqCst) == address;
+    });
+}
+
+// Remembers the original contents of a word of a tracee's memory, before it is overwritten. A word
+// that is patched again, e.g. by a second breakpoint in it, keeps the contents from before the
+// first patch, and is only forgotten once each patch is unregistered. Returns false if there is no
+// room left to remember it, in which case it must not be patched.
+pub fn register_patch(pid: libc::pid_t, address: u64, original: u64) -> bool {
+    return register_patches(pid, address, original, 1);
+}
+
+fn register_patches(pid: libc::pid_t, address: u64, original: u64, count: usize) -> bool {
+    if let Some(i) = find_patch(pid, address) {
+        PATCH_COUNTS[i].fetch_add(count, Ordering::SeqCst);
+        return true;
+    }
+    for (i, slot) in PATCH_PIDS.iter().enumerate() {
+        // The address is stored first, so that a cleanup never restores half a patch.
+        if slot.load(Ordering::SeqCst) == 0 {
+            PATCH_ADDRESSES[i].store(address, Ordering::SeqCst);
+            PATCH_ORIGINALS[i].store(original, Ordering::SeqCst);
+            PATCH_COUNTS[i].store(count, Ordering::SeqCst);
+            PATCH_SEQUENCES[i].store(
+                NEXT_PATCH_SEQUENCE.fetch_add(1, Ordering::SeqCst),
+                Ordering::SeqCst,
+            );
+            if slot
+                .compare_exchange(0, pid, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                return true;
+            }
+        }
+    }
+    return false;
+}
+
+// Forgets a patch, once the bytes that it overwrote have been written back. The word is forgotten
+// along with the last patch of it.
+pub fn unregister_patch(pid: libc::pid_t, address: u64) {
+    if let Some(i) = find_patch(pid, address) {
+        if PATCH_COUNTS[i].fetch_sub(1, Ordering::SeqCst) == 1 {
+            PATCH_PIDS[i].store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+// Forgets every patch of a tracee, e.g. once it has exec'd and its memory is gone.
+pub fn forget_patches(pid: libc::pid_t) {
+    for slot in &PATCH_PIDS {
+        let _ = slot.compare_exchange(pid, 0, Ordering::SeqCst, Ordering::SeqCst);
+    }
+}
+
+// Registers the patches of a tracee for another one that has a copy of its memory, e.g. a forked
+// child. Returns false if there is no room left for some of them.
+pub fn copy_patches(pid: libc::pid_t, to_pid: libc::pid_t) -> bool {
+    let mut has_room = true;
+    for i in patch_slots(pid) {
+        has_room &= register_patches(
+            to_pid,
+            PATCH_ADDRESSES[i].load(Ordering::SeqCst),
+            PATCH_ORIGINALS[i].load(Ordering::SeqCst),
+            PATCH_COUNTS[i].load(Ordering::SeqCst),
+        );
+    }
+    return has_room;
+}
+
+// The slots of the patched words of a tracee, in the order they were first patched.
+fn patch_slots(pid: libc::pid_t) -> Vec<usize> {
+    let mut slots = (0..MAX_PATCHES)
+        .filter(|i| PATCH_PIDS[*i].load(Ordering::SeqCst) == pid)
+        .collect::<Vec<usize>>();
+    slots.sort_by_key(|i| PATCH_SEQUENCES[*i].load(Ordering::SeqCst));
+    return slots;
+}
+
+// The patched words of a tracee, as pairs of address and original word, in the order they were
+// first patched.
+pub fn patches(pid: libc::pid_t) -> Vec<(u64, u64)> {
+    return patch_slots(pid)
+        .into_iter()
+        .map(|i| {
+            return (
+                PATCH_ADDRESSES[i].load(Ordering::SeqCst),
+                PATCH_ORIGINALS[i].load(Ordering::SeqCst),
+            );
+        })
+        .collect();
+}
+
+// Whether the tracees have already been cleaned up, in which case they must be left alone.
+pub fn has_run() -> bool {
+    return HAS_RUN.load(Ordering::SeqCst);
+}
+
+// Cleans up the tracees when pbreak panics or receives a fatal signal.
+pub unsafe fn install() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        run();
+        // Other threads must not carry on with tracees that are gone.
+        libc::_exit(101);
+    }));
+
+    let mut action = std::mem::zeroed::<libc::sigaction>();
+    action.sa_sigaction = handle_fatal_signal as *const () as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESETHAND;
+    libc::sigemptyset(&mut action.sa_mask);
+    for signal in FATAL_SIGNALS {
+        libc::sigaction(signal, &action, null_mut());
+    }
+}
+
+// Blocks the fatal signals on the calling thread, so that they are handled by the thread that
+// traces the tracees. Only that thread may detach from them.
+pub unsafe fn block_fatal_signals() {
+    let mut set = std::mem::zeroed::<libc::sigset_t>();
+    libc::sigemptyset(&mut set);
+    for signal in FATAL_SIGNALS {
+        libc::sigaddset(&mut set, signal);
+    }
+    libc::pthread_sigmask(libc::SIG_BLOCK, &set, null_mut());
+}
+
+extern "C" fn handle_fatal_signal(signal: libc::c_int) {
+    unsafe {
+        run();
+        // SA_RESETHAND restored the default action, which raising the signal again now takes.
+        libc::raise(signal);
+    }
+}
+
+// Restores the patches of every tracee and detaches from it. Runs at most once.
+pub unsafe fn run() {
+    if HAS_RUN.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    for (i, slot) in TRACEE_PIDS.iter().enumerate() {
+        let pid = slot.load(Ordering::SeqCst);
+        if pid != 0 {
+            release_tracee(pid, TRACEE_SEIZED[i].load(Ordering::SeqCst));
+        }
+    }
+}
+
+unsafe fn release_tracee(pid: libc::pid_t, seized: bool) {
+    // Every thread has to be stopped to detach from it, and the patches are written back through
+    // the first one that is.
+    let mut has_restored_patches = false;
+    for_each_task(pid, |tid| {
+        if !stop_task(pid, tid, seized) {
+            return;
+        }
+        if !has_restored_patches {
+            restore_patches(pid, tid);
+            has_restored_patches = true;
+        }
+        libc::ptrace(
+            libc::PTRACE_DETACH,
+            tid,
+            null_mut::<libc::c_void>(),
+            null_mut::<libc::c_void>(),
+        );
+    });
+
+    // Threads that were stopped with SIGSTOP may still have it pending.
+    libc::kill(pid, libc::SIGCONT);
+}
+
+// Ensures that a task is in a ptrace-stop. Returns false if it cannot be stopped, e.g. because it
+// has exited.
+unsafe fn stop_task(pid: libc::pid_t, tid: libc::pid_t, seized: bool) -> bool {
+    // A task that is already stopped can be inspected right away, and may have had its stop reaped
+    // already, so it must not be waited on.
+    let mut message: libc::c_ulong = 0;
+    if libc::ptrace(
+        libc::PTRACE_GETEVENTMSG,
+        tid,
+        null_mut::<libc::c_void>(),
+        &mut message as *mut libc::c_ulong,
+    ) >= 0
+    {
+        return true;
+    }
+
+    match seized {
+        true => {
+            libc::ptrace(
+                libc::PTRACE_INTERRUPT,
+                tid,
+                null_mut::<libc::c_void>(),
+                null_mut::<libc::c_void>(),
+            );
+        }
+        false => {
+            libc::syscall(libc::SYS_tgkill, pid, tid, libc::SIGSTOP);
+        }
+    }
+
+    let mut info = std::mem::zeroed::<libc::siginfo_t>();
+    let wait_options = libc::WSTOPPED | libc::WEXITED | libc::__WALL;
+    if libc::waitid(libc::P_PID, tid as libc::id_t, &mut info, wait_options) < 0 {
+        return false;
+    }
+    return info.si_code == libc::CLD_TRAPPED || info.si_code == libc::CLD_STOPPED;
+}
+
+unsafe fn restore_patches(pid: libc::pid_t, tid: libc::pid_t) {
+    for (i, slot) in PATCH_PIDS.iter().enumerate() {
+        if slot.load(Ordering::SeqCst) != pid {
+            continue;
+        }
+        libc::ptrace(
+            libc::PTRACE_POKEDATA,
+            tid,
+            PATCH_ADDRESSES[i].load(Ordering::SeqCst) as *mut libc::c_void,
+            PATCH_ORIGINALS[i].load(Ordering::SeqCst) as *mut libc::c_void,
+        );
+    }
+}
+
+// Calls `f` with the ID of every thread of a process, as listed in /proc/<pid>/task. Reads the
+// directory with raw system calls and fixed buffers, since memory must not be allocated here.
+unsafe fn for_each_task(pid: libc::pid_t, mut f: impl FnMut(libc::pid_t)) {
+    let mut path = [0u8; 32];
+    let mut len = 0;
+    for byte in b"/proc/" {
+        path[len] = *byte;
+        len += 1;
+    }
+    len += format_decimal(pid as u64, &mut path[len..]);
+    for byte in b"/task" {
+        path[len] = *byte;
+        len += 1;
+    }
+
+    let fd = libc::open(
+        path.as_ptr() as *const libc::c_char,
+        libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+    );
+    if fd < 0 {
+        return;
+    }
+
+    let mut buffer = [0u8; 4096];
+    loop {
+        let n_bytes = libc::syscall(libc::SYS_getdents64, fd, buffer.as_mut_ptr(), buffer.len());
+        if n_bytes <= 0 {
+            break;
+        }
+
+        // Each linux_dirent64 is an inode (8 bytes), offset (8), record length (2), type (1), and
+        // then the NUL-terminated name.
+        let mut offset = 0;
+        while offset < n_bytes as usize {
+            let record_len = u16::from_ne_bytes([buffer[offset + 16], buffer[offset + 17]]);
+            if let Some(tid) = parse_decimal(&buffer[offset + 19..offset + record_len as usize]) {
+                f(tid as libc::pid_t);
+            }
+            offset += record_len as usize;
+        }
+    }
+    libc::close(fd);
+}
+
+// Writes a number in decimal, returning the number of digits.
+fn format_decimal(mut value: u64, buffer: &mut [u8]) -> usize {
+    let mut digits = [0u8; 20];
+    let mut n_digits = 0;
+    loop {
+        digits[n_digits] = b'0' + (value % 10) as u8;
+        n_digits += 1;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+
+    for i in 0..n_digits {
+        buffer[i] = digits[n_digits - 1 - i];
+    }
+    return n_digits;
+}
+
+// Parses a NUL-terminated decimal number, returning None for anything else, e.g. "." or "..".
+fn parse_decimal(bytes: &[u8]) -> Option<u64> {
+    let mut value: u64 = 0;
+    let mut n_digits = 0;
+    for byte in bytes {
+        match byte {
+            0 => break,
+            b'0'..=b'9' => value = value.checked_mul(10)?.checked_add((byte - b'0') as u64)?,
+            _ => return None,
+        }
+        n_digits += 1;
+    }
+    return match n_digits {
+        0 => None,
+        _ => Some(value),
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        copy_patches, for_each_task, forget_patches, format_decimal, parse_decimal, patches,
+        register_patch, unregister_patch,
+    };
+
+    #[test]
+    fn format_decimal_writes_digits() {
+        let mut buffer = [0u8; 20];
+        let len = format_decimal(40213, &mut buffer);
+        assert_eq!(&buffer[..len], b"40213");
+        assert_eq!(format_decimal(0, &mut buffer), 1);
+        assert_eq!(buffer[0], b'0');
+    }
+
+    #[test]
+    fn parse_decimal_rejects_other_names() {
+        assert_eq!(parse_decimal(b"1234\0\0\0"), Some(1234));
+        assert_eq!(parse_decimal(b".\0"), None);
+        assert_eq!(parse_decimal(b"\0"), None);
+    }
+
+    #[test]
+    fn register_patch_keeps_one_entry_per_word() {
+        // No such processes exist, so no other test registers patches for them.
+        let (pid, child_pid) = (-10, -11);
+        assert!(register_patch(pid, 0x1000, 1));
+        assert!(register_patch(pid, 0x1008, 2));
+        // A second breakpoint in the word sees the first one in it, which is not to be restored.
+        assert!(register_patch(pid, 0x1000, 3));
+        assert_eq!(patches(pid), vec![(0x1000, 1), (0x1008, 2)]);
+        unregister_patch(pid, 0x1000);
+        assert_eq!(patches(pid), vec![(0x1000, 1), (0x1008, 2)]);
+        unregister_patch(pid, 0x1000);
+        assert_eq!(patches(pid), vec![(0x1008, 2)]);
+
+        // Words stay in the order they were first patched, even in slots that were freed before.
+        assert!(register_patch(pid, 0x2000, 4));
+        assert_eq!(patches(pid), vec![(0x1008, 2), (0x2000, 4)]);
+
+        // Copies keep how many patches there are of each word.
+        assert!(register_patch(pid, 0x2000, 5));
+        assert!(copy_patches(pid, child_pid));
+        unregister_patch(child_pid, 0x2000);
+        assert_eq!(patches(child_pid), vec![(0x1008, 2), (0x2000, 4)]);
+        forget_patches(pid);
+        forget_patches(child_pid);
+        assert!(patches(pid).is_empty());
+    }
+
+    #[test]
+    fn for_each_task_lists_threads_of_current_process() {
+        let mut tids = vec![];
+        unsafe {
+            for_each_task(libc::getpid(), |tid| tids.push(tid));
+        }
+        assert!(tids.contains(&unsafe { libc::gettid() }));
+    }
+}
@@ -1,10 +1,50 @@
-use crate::{session::run_session, tracee::Tracee};
-use std::num::ParseIntError;
+use crate::{
+    container,
+    coredump::CoreTarget,
+    dap, gdbserver,
+    launch::LaunchOptions,
+    procfs,
+    session::{run_audit_session, run_core_session, run_session, run_tui_session, run_web_session},
+    tracee::Tracee,
+};
+use std::{num::ParseIntError, path::Path, thread::sleep, time::Duration};
 
 pub enum Command {
     Missing,
-    Attach { pid: libc::pid_t },
-    Fork { program: String, args: Vec<String> },
+    Attach {
+        pid: libc::pid_t,
+    },
+    WaitFor {
+        name: String,
+    },
+    Container {
+        container: String,
+        // The name of the process to attach to, rather than the container's init process.
+        process: Option<String>,
+    },
+    Fork {
+        launch: LaunchOptions,
+    },
+    Server {
+        address: String,
+        launch: LaunchOptions,
+    },
+    Tui {
+        launch: LaunchOptions,
+    },
+    Web {
+        address: String,
+        launch: LaunchOptions,
+    },
+    Audit {
+        path: String,
+        launch: LaunchOptions,
+    },
+    Dap,
+    Core {
+        core: String,
+        executable: String,
+    },
 }
 
 impl Command {
@@ -23,12 +63,62 @@ impl Command {
                 Ok(pid) => pid,
             };
 
-            return Command::Attach { pid: pid };
+            return Command::Attach { pid };
+        }
+
+        if args.len() == 3 && args[1] == "--waitfor" {
+            return Command::WaitFor {
+                name: args[2].to_string(),
+            };
+        }
+
+        if (args.len() == 3 || args.len() == 4) && args[1] == "--container" {
+            return Command::Container {
+                container: args[2].to_string(),
+                process: args.get(3).cloned(),
+            };
+        }
+
+        if args.len() == 2 && args[1] == "--dap" {
+            return Command::Dap;
+        }
+
+        if args.len() == 4 && args[1] == "--core" {
+            return Command::Core {
+                core: args[2].to_string(),
+                executable: args[3].to_string(),
+            };
+        }
+
+        if args.len() >= 3 && args[1] == "--tui" {
+            return Command::Tui {
+                launch: parse_launch_options(&args[2..]),
+            };
+        }
+
+        if args.len() >= 4 && args[1] == "--web" {
+            return Command::Web {
+                address: args[2].to_string(),
+                launch: parse_launch_options(&args[3..]),
+            };
+        }
+
+        if args.len() >= 4 && args[1] == "--audit" {
+            return Command::Audit {
+                path: args[2].to_string(),
+                launch: parse_launch_options(&args[3..]),
+            };
+        }
+
+        if args.len() >= 4 && args[1] == "--server" {
+            return Command::Server {
+                address: args[2].to_string(),
+                launch: parse_launch_options(&args[3..]),
+            };
         }
 
         return Command::Fork {
-            program: args[1].to_string(),
-            args: args.iter().skip(2).map(|s| s.clone()).collect(),
+            launch: parse_launch_options(&args[1..]),
         };
     }
 
@@ -37,9 +127,25 @@ impl Command {
         return match self {
             Command::Missing => self.run_missing(),
             Command::Attach { pid } => self.run_attach(*pid),
-            Command::Fork { program, args } => {
-                self.run_fork(program, args);
+            Command::WaitFor { name } => self.run_waitfor(name),
+            Command::Container { container, process } => {
+                self.run_container(container, process.as_deref())
             }
+            Command::Fork { launch } => {
+                self.run_fork(launch);
+            }
+            Command::Server { address, launch } => self.run_server(address, launch),
+            Command::Tui { launch } => {
+                self.run_tui(launch);
+            }
+            Command::Web { address, launch } => {
+                self.run_web(address, launch);
+            }
+            Command::Audit { path, launch } => {
+                self.run_audit(path, launch);
+            }
+            Command::Dap => self.run_dap(),
+            Command::Core { core, executable } => self.run_core(core, executable),
         };
     }
 
@@ -49,14 +155,114 @@ impl Command {
     }
 
     unsafe fn run_attach(&self, pid: libc::pid_t) -> ! {
-        let mut tracee = Tracee::from_pid(pid);
-        run_session(&mut tracee);
+        let tracee = Tracee::from_pid(pid);
+        run_session(tracee);
+        unreachable!("session should not terminate without exiting");
+    }
+
+    // Polls for a new process with a matching name, and attaches to it as soon as it appears.
+    unsafe fn run_waitfor(&self, name: &str) -> ! {
+        let existing_pids = procfs::find_pids_by_name(name);
+        println!("Waiting for a process named \"{}\"...", name);
+
+        loop {
+            let new_pid = procfs::find_pids_by_name(name)
+                .into_iter()
+                .find(|pid| !existing_pids.contains(pid));
+            if let Some(pid) = new_pid {
+                self.run_attach(pid);
+            }
+
+            sleep(Duration::from_millis(1));
+        }
+    }
+
+    // Attaches to the init process of a container, or a process of it with the given name. Its
+    // files are read from inside the container.
+    unsafe fn run_container(&self, container: &str, process: Option<&str>) -> i32 {
+        let pid_result = match process {
+            None => container::find_init_pid(container),
+            Some(name) => container::find_process(container, name),
+        };
+        return match pid_result {
+            Err(err) => {
+                println!("{}", err);
+                -1
+            }
+            Ok(pid) => {
+                println!(
+                    "Attaching to process ({}) of container \"{}\"",
+                    pid, container
+                );
+                self.run_attach(pid);
+            }
+        };
+    }
+
+    unsafe fn run_fork(&self, launch: &LaunchOptions) -> ! {
+        let tracee = Tracee::launch(launch);
+        run_session(tracee);
+        unreachable!("session should not terminate without exiting");
+    }
+
+    // Debugs the forked program in a full-screen UI.
+    unsafe fn run_tui(&self, launch: &LaunchOptions) -> ! {
+        let tracee = Tracee::launch(launch);
+        run_tui_session(tracee);
         unreachable!("session should not terminate without exiting");
     }
 
-    unsafe fn run_fork(&self, program: &str, args: &[String]) -> ! {
-        let mut tracee = Tracee::from_cmd(program, args);
-        run_session(&mut tracee);
+    // Debugs the forked program as usual, while serving the web frontend at the address.
+    unsafe fn run_web(&self, address: &str, launch: &LaunchOptions) -> ! {
+        let tracee = Tracee::launch(launch);
+        run_web_session(tracee, address);
         unreachable!("session should not terminate without exiting");
     }
+
+    // Debugs the forked program as usual, while recording an audit trail of it to the path.
+    unsafe fn run_audit(&self, path: &str, launch: &LaunchOptions) -> ! {
+        let tracee = Tracee::launch(launch);
+        run_audit_session(tracee, path);
+        unreachable!("session should not terminate without exiting");
+    }
+
+    // Serves the forked program to a remote debugger, e.g. `gdb -ex "target remote :1234"`.
+    unsafe fn run_server(&self, address: &str, launch: &LaunchOptions) -> i32 {
+        let tracee = Tracee::launch(launch);
+        if let Err(err) = gdbserver::serve(tracee, address) {
+            println!("failed to serve on {}: {}", address, err);
+            return -1;
+        }
+        return 0;
+    }
+
+    // Speaks the Debug Adapter Protocol on stdin and stdout, for editors like VS Code.
+    unsafe fn run_dap(&self) -> i32 {
+        if let Err(err) = dap::serve() {
+            eprintln!("failed to serve the debug adapter protocol: {}", err);
+            return -1;
+        }
+        return 0;
+    }
+
+    // Inspects a core file of the executable, without running anything.
+    unsafe fn run_core(&self, core: &str, executable: &str) -> i32 {
+        let target = match CoreTarget::open(Path::new(core), Path::new(executable)) {
+            Err(err) => {
+                println!("failed to load core file {}: {}", core, err);
+                return -1;
+            }
+            Ok(target) => target,
+        };
+        run_core_session(target);
+        return 0;
+    }
+}
+
+// Parses the command line of the program to launch, along with the options before it.
+fn parse_launch_options(args: &[String]) -> LaunchOptions {
+    return match LaunchOptions::parse(args) {
+        Err(err) => panic!("{}", err),
+        Ok(launch) => launch,
+    };
 }
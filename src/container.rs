@@ -0,0 +1,130 @@
+// Finds the processes of containers, which are attached to like any other process. Names are
+// resolved by asking the container runtimes that know them, while IDs are also matched against the
+// cgroups of processes, which works with runtimes that have no CLI at hand, e.g. containerd.
+use std::{fs, process::Command};
+
+use crate::procfs;
+
+// The CLIs that can resolve a container name to the PID of its init process.
+const RUNTIMES: &[&str] = &["docker", "podman", "nerdctl"];
+
+// Container IDs are 64 hex digits, which short IDs are prefixes of.
+const CONTAINER_ID_LEN: usize = 64;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ContainerError {
+    #[error("no running container named \"{0}\"")]
+    NotFound(String),
+    #[error("no process named \"{name}\" in container \"{container}\"")]
+    NoProcess { container: String, name: String },
+}
+
+// Resolves a container by name or ID to the PID of its init process, as the debugger sees it.
+pub fn find_init_pid(container: &str) -> Result<libc::pid_t, ContainerError> {
+    for runtime in RUNTIMES {
+        if let Some(pid) = inspect_pid(runtime, container) {
+            return Ok(pid);
+        }
+    }
+
+    let is_id = container.len() <= CONTAINER_ID_LEN
+        && !container.is_empty()
+        && container.bytes().all(|byte| byte.is_ascii_hexdigit());
+    if !is_id {
+        return Err(ContainerError::NotFound(container.to_string()));
+    }
+    let members = procfs::read_pids()
+        .into_iter()
+        .filter(|pid| {
+            return read_container_id(*pid).is_some_and(|id| id.starts_with(container));
+        })
+        .collect::<Vec<libc::pid_t>>();
+    // The init process is the one whose parent is outside the container, e.g. its shim.
+    return members
+        .iter()
+        .find(|pid| procfs::read_ppid(**pid).is_none_or(|ppid| !members.contains(&ppid)))
+        .copied()
+        .ok_or_else(|| ContainerError::NotFound(container.to_string()));
+}
+
+// Finds a process of a container by name, preferring the oldest if there are several.
+pub fn find_process(container: &str, name: &str) -> Result<libc::pid_t, ContainerError> {
+    let init_pid = find_init_pid(container)?;
+    let pid_namespace = read_pid_namespace(init_pid);
+    let mut pids = procfs::find_pids_by_name(name)
+        .into_iter()
+        .filter(|pid| pid_namespace.is_some() && read_pid_namespace(*pid) == pid_namespace)
+        .collect::<Vec<libc::pid_t>>();
+    pids.sort();
+    return pids.first().copied().ok_or(ContainerError::NoProcess {
+        container: container.to_string(),
+        name: name.to_string(),
+    });
+}
+
+// Asks a container runtime for the PID of a container's init process. Returns None if the runtime
+// is not installed or does not know the container, or the container is not running.
+fn inspect_pid(runtime: &str, container: &str) -> Option<libc::pid_t> {
+    let output = Command::new(runtime)
+        .args(["inspect", "--format", "{{.State.Pid}}", container])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // Stopped containers have a PID of 0.
+    let pid = String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse::<libc::pid_t>()
+        .ok()?;
+    return (pid > 0).then_some(pid);
+}
+
+fn read_pid_namespace(pid: libc::pid_t) -> Option<String> {
+    let namespace = fs::read_link(format!("/proc/{}/ns/pid", pid)).ok()?;
+    return Some(namespace.display().to_string());
+}
+
+fn read_container_id(pid: libc::pid_t) -> Option<String> {
+    let cgroup = fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+    return parse_container_id(&cgroup).map(str::to_string);
+}
+
+// Finds the container ID in the cgroups of a process, which runtimes name their cgroups after, e.g.
+// "0::/system.slice/docker-<id>.scope" or "0::/kubepods/.../cri-containerd-<id>.scope".
+fn parse_container_id(cgroup: &str) -> Option<&str> {
+    return cgroup.lines().find_map(|line| {
+        let path = line.splitn(3, ':').nth(2)?;
+        return path.rsplit('/').find_map(|component| {
+            return component.split(['-', '.']).find(|part| {
+                return part.len() == CONTAINER_ID_LEN
+                    && part.bytes().all(|byte| byte.is_ascii_hexdigit());
+            });
+        });
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::{find_init_pid, parse_container_id};
+
+    #[test]
+    fn parse_container_id_reads_runtime_cgroup_names() {
+        let id = "4f3c".repeat(16);
+        let docker = format!("0::/system.slice/docker-{}.scope\n", id);
+        assert_eq!(parse_container_id(&docker), Some(id.as_str()));
+        let containerd = format!(
+            "12:pids:/kubepods/besteffort/pod1234/cri-containerd-{}.scope\n0::/\n",
+            id
+        );
+        assert_eq!(parse_container_id(&containerd), Some(id.as_str()));
+        let cgroupfs = format!("0::/docker/{}\n", id);
+        assert_eq!(parse_container_id(&cgroupfs), Some(id.as_str()));
+        assert_eq!(parse_container_id("0::/user.slice/session-2.scope\n"), None);
+    }
+
+    #[test]
+    fn find_init_pid_fails_for_unknown_container() {
+        assert!(find_init_pid("pbreak-no-such-container").is_err());
+    }
+}
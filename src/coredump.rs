@@ -0,0 +1,880 @@
+use std::{
+    fs::{self, File},
+    io, mem,
+    os::unix::fs::{FileExt, MetadataExt},
+    path::Path,
+};
+
+use crate::{
+    elf::{self, load_bias, ElfError, ElfFile, FunctionSymbol},
+    procfs::{self, MemoryMapping},
+    tracee::Tracee,
+};
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_CORE: u16 = 4;
+const EM_AARCH64: u16 = 183;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const NT_PRSTATUS: u32 = 1;
+const NT_FPREGSET: u32 = 2;
+const NT_PRPSINFO: u32 = 3;
+const NT_AUXV: u32 = 6;
+const NT_FILE: u32 = 0x4649_4c45;
+const CORE_NOTE_NAME: &[u8] = b"CORE\0";
+
+// Sizes of the 64-bit structures, as laid out in the file.
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const SECTION_HEADER_SIZE: u16 = 64;
+// `struct elf_prstatus` on AArch64, whose registers start at `pr_reg`.
+const PRSTATUS_SIZE: usize = 392;
+const PRSTATUS_REGS_OFFSET: usize = 112;
+const PRSTATUS_FPVALID_OFFSET: usize = 384;
+// `struct elf_prpsinfo`, whose command name and arguments are truncated to fit.
+const PRPSINFO_SIZE: usize = 136;
+const PRPSINFO_FNAME_LEN: usize = 16;
+const PRPSINFO_PSARGS_LEN: usize = 80;
+
+// x0 to x30, sp, pc, and pstate, as `elf_gregset_t` orders them.
+pub const CORE_REGISTER_COUNT: usize = 34;
+
+// The page size assumed for core files that do not list their mapped files.
+const DEFAULT_PAGE_SIZE: u64 = 0x1000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CoreError {
+    #[error("failed to read {path}: {source}")]
+    Read { path: String, source: io::Error },
+    #[error("malformed core file: {0}")]
+    Malformed(&'static str),
+    #[error("unsupported core file: {0}")]
+    Unsupported(&'static str),
+    #[error(transparent)]
+    Elf(#[from] ElfError),
+    #[error("{path} is not mapped in the core file")]
+    NotMapped { path: String },
+    #[error("cannot access memory at address {0:#x}")]
+    Unmapped(u64),
+    #[error("the core file is read-only")]
+    ReadOnly,
+}
+
+// A thread of a core file.
+#[derive(PartialEq, Clone, Debug)]
+pub struct CoreThread {
+    pub tid: libc::pid_t,
+    // The signal that the thread stopped with, or 0.
+    pub signal: libc::c_int,
+    pub regs: [u64; CORE_REGISTER_COUNT],
+    // The raw `user_fpsimd_struct`.
+    pub fp_regs: Vec<u8>,
+}
+
+// The contents of a readable mapping. Those that could not be read are left empty, and read as
+// zeroes from the core file.
+#[derive(PartialEq, Clone, Debug)]
+pub struct CoreSegment {
+    pub start: u64,
+    pub end: u64,
+    // The access permissions, e.g. "r-xp".
+    pub permissions: String,
+    pub data: Vec<u8>,
+}
+
+// The state of a process, as a core file records it.
+#[derive(PartialEq, Clone, Debug)]
+pub struct CoreSnapshot {
+    pub pid: libc::pid_t,
+    pub ppid: libc::pid_t,
+    pub uid: u32,
+    pub gid: u32,
+    pub name: String,
+    pub args: Vec<String>,
+    pub page_size: u64,
+    // The thread that stopped the process comes first.
+    pub threads: Vec<CoreThread>,
+    pub segments: Vec<CoreSegment>,
+    // Every mapping, including unreadable ones, whose files are listed by NT_FILE.
+    pub mappings: Vec<MemoryMapping>,
+    pub auxv: Vec<u8>,
+}
+
+impl CoreSnapshot {
+    // Captures the state of a stopped tracee.
+    pub unsafe fn capture(tracee: &Tracee) -> io::Result<CoreSnapshot> {
+        let pid = tracee.pid();
+        let mappings = procfs::read_maps(pid).ok_or(io::ErrorKind::NotFound)?;
+        let metadata = fs::metadata(format!("/proc/{}", pid))?;
+
+        let selected_tid = tracee.selected_tid();
+        let mut tids = tracee
+            .threads()
+            .iter()
+            .map(|thread| thread.tid)
+            .collect::<Vec<libc::pid_t>>();
+        tids.sort_by_key(|tid| *tid != selected_tid);
+        let threads = tids
+            .into_iter()
+            .map(|tid| {
+                let signal = match tid == selected_tid {
+                    true => tracee.read_siginfo().map_or(0, |info| info.si_signo),
+                    false => 0,
+                };
+                let regs = tracee.read_thread_general_purpose_registers(tid);
+                let fp_regs = tracee.read_thread_floating_point_registers(tid);
+                return CoreThread {
+                    tid,
+                    signal,
+                    regs: to_core_registers(&regs),
+                    fp_regs: struct_bytes(&fp_regs),
+                };
+            })
+            .collect();
+
+        // Memory is read through /proc, which is much faster than ptrace for whole mappings.
+        let memory = File::open(format!("/proc/{}/mem", pid))?;
+        let segments = mappings
+            .iter()
+            .filter(|mapping| mapping.permissions.starts_with('r'))
+            .map(|mapping| {
+                let mut data = vec![0; (mapping.end - mapping.start) as usize];
+                if memory.read_exact_at(&mut data, mapping.start).is_err() {
+                    data.clear();
+                }
+                return CoreSegment {
+                    start: mapping.start,
+                    end: mapping.end,
+                    permissions: mapping.permissions.clone(),
+                    data,
+                };
+            })
+            .collect();
+
+        return Ok(CoreSnapshot {
+            pid,
+            ppid: procfs::read_ppid(pid).unwrap_or(0),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            name: procfs::read_comm(pid).unwrap_or_default(),
+            args: procfs::read_cmdline(pid).unwrap_or_default(),
+            page_size: libc::sysconf(libc::_SC_PAGESIZE) as u64,
+            threads,
+            segments,
+            mappings,
+            auxv: fs::read(format!("/proc/{}/auxv", pid)).unwrap_or_default(),
+        });
+    }
+
+    // Encodes the snapshot as an ELF core file, which gdb, lldb, and pbreak can load.
+    pub fn encode(&self) -> Vec<u8> {
+        let notes = self.encode_notes();
+        let header_count = 1 + self.segments.len();
+        let notes_offset = ELF_HEADER_SIZE + header_count * PROGRAM_HEADER_SIZE;
+        // Segments are page-aligned in the file, as they are in memory.
+        let segments_offset = align(notes_offset + notes.len(), self.page_size as usize);
+
+        let mut core = Vec::with_capacity(segments_offset);
+        core.extend_from_slice(ELF_MAGIC);
+        core.extend_from_slice(&[ELFCLASS64, ELFDATA2LSB, EV_CURRENT]);
+        core.resize(16, 0);
+        push_u16(&mut core, ET_CORE);
+        push_u16(&mut core, EM_AARCH64);
+        push_u32(&mut core, EV_CURRENT as u32);
+        push_u64(&mut core, 0);
+        push_u64(&mut core, ELF_HEADER_SIZE as u64);
+        push_u64(&mut core, 0);
+        push_u32(&mut core, 0);
+        push_u16(&mut core, ELF_HEADER_SIZE as u16);
+        push_u16(&mut core, PROGRAM_HEADER_SIZE as u16);
+        push_u16(&mut core, header_count as u16);
+        push_u16(&mut core, SECTION_HEADER_SIZE);
+        push_u16(&mut core, 0);
+        push_u16(&mut core, 0);
+
+        push_program_header(&mut core, PT_NOTE, 0, notes_offset, 0, notes.len(), 0, 4);
+        let mut offset = segments_offset;
+        for segment in &self.segments {
+            let memory_size = (segment.end - segment.start) as usize;
+            let flags = segment_flags(&segment.permissions);
+            push_program_header(
+                &mut core,
+                PT_LOAD,
+                flags,
+                offset,
+                segment.start,
+                segment.data.len(),
+                memory_size,
+                self.page_size,
+            );
+            offset += segment.data.len();
+        }
+
+        core.extend_from_slice(&notes);
+        core.resize(segments_offset, 0);
+        for segment in &self.segments {
+            core.extend_from_slice(&segment.data);
+        }
+        return core;
+    }
+
+    // Parses a core file, as written by `encode` or by the kernel. Only what `encode` writes is
+    // read back; other notes are skipped.
+    pub fn parse(data: &[u8]) -> Result<CoreSnapshot, CoreError> {
+        if data.len() < ELF_HEADER_SIZE || &data[..4] != ELF_MAGIC {
+            return Err(CoreError::Malformed("missing ELF header"));
+        }
+        if data[4] != ELFCLASS64 || data[5] != ELFDATA2LSB {
+            return Err(CoreError::Unsupported(
+                "only little-endian ELF64 is supported",
+            ));
+        }
+        if u16_at(data, 16)? != ET_CORE {
+            return Err(CoreError::Unsupported("not a core file"));
+        }
+        if u16_at(data, 18)? != EM_AARCH64 {
+            return Err(CoreError::Unsupported("only AArch64 is supported"));
+        }
+
+        let mut snapshot = CoreSnapshot {
+            pid: 0,
+            ppid: 0,
+            uid: 0,
+            gid: 0,
+            name: String::new(),
+            args: vec![],
+            page_size: DEFAULT_PAGE_SIZE,
+            threads: vec![],
+            segments: vec![],
+            mappings: vec![],
+            auxv: vec![],
+        };
+        let headers_offset = u64_at(data, 0x20)? as usize;
+        let header_size = u16_at(data, 0x36)? as usize;
+        let header_count = u16_at(data, 0x38)? as usize;
+        let mut files = vec![];
+        for i in 0..header_count {
+            let header = headers_offset + i * header_size;
+            let offset = u64_at(data, header + 8)? as usize;
+            let file_size = u64_at(data, header + 32)? as usize;
+            let contents = data
+                .get(offset..offset + file_size)
+                .ok_or(CoreError::Malformed("segment out of bounds"))?;
+            match u32_at(data, header)? {
+                PT_NOTE => files = snapshot.parse_notes(contents)?,
+                PT_LOAD => {
+                    let start = u64_at(data, header + 16)?;
+                    snapshot.segments.push(CoreSegment {
+                        start,
+                        end: start + u64_at(data, header + 40)?,
+                        permissions: segment_permissions(u32_at(data, header + 4)?),
+                        data: contents.to_vec(),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        // Mapped files take the permissions of the segments that they were dumped to.
+        snapshot.mappings = files
+            .into_iter()
+            .map(|(start, end, offset, path)| {
+                let permissions = snapshot
+                    .segments
+                    .iter()
+                    .find(|segment| segment.start <= start && start < segment.end)
+                    .map_or("---p".to_string(), |segment| segment.permissions.clone());
+                return MemoryMapping {
+                    start,
+                    end,
+                    permissions,
+                    offset,
+                    path,
+                };
+            })
+            .collect();
+        return Ok(snapshot);
+    }
+
+    // Reads the notes into the snapshot, returning the mapped files of NT_FILE as their start,
+    // end, offset, and path.
+    fn parse_notes(&mut self, notes: &[u8]) -> Result<Vec<(u64, u64, u64, String)>, CoreError> {
+        let mut files = vec![];
+        let mut offset = 0;
+        while offset + 12 <= notes.len() {
+            let name_size = u32_at(notes, offset)? as usize;
+            let description_size = u32_at(notes, offset + 4)? as usize;
+            let kind = u32_at(notes, offset + 8)?;
+            let name_offset = offset + 12;
+            let description_offset = align(name_offset + name_size, 4);
+            offset = align(description_offset + description_size, 4);
+            let name = notes.get(name_offset..name_offset + name_size);
+            let description = notes
+                .get(description_offset..description_offset + description_size)
+                .ok_or(CoreError::Malformed("note out of bounds"))?;
+            // Other owners, e.g. "LINUX", reuse the same kinds for other things.
+            if name != Some(CORE_NOTE_NAME) {
+                continue;
+            }
+
+            match kind {
+                NT_PRPSINFO => {
+                    self.uid = u32_at(description, 16)?;
+                    self.gid = u32_at(description, 20)?;
+                    self.pid = u32_at(description, 24)? as libc::pid_t;
+                    self.ppid = u32_at(description, 28)? as libc::pid_t;
+                    self.name = string_at(description, 40, PRPSINFO_FNAME_LEN)?;
+                    self.args = string_at(description, 56, PRPSINFO_PSARGS_LEN)?
+                        .split_whitespace()
+                        .map(str::to_string)
+                        .collect();
+                }
+                NT_PRSTATUS => {
+                    let mut regs = [0; CORE_REGISTER_COUNT];
+                    for (i, reg) in regs.iter_mut().enumerate() {
+                        *reg = u64_at(description, PRSTATUS_REGS_OFFSET + i * 8)?;
+                    }
+                    self.threads.push(CoreThread {
+                        tid: u32_at(description, 32)? as libc::pid_t,
+                        signal: u16_at(description, 12)? as i16 as libc::c_int,
+                        regs,
+                        fp_regs: vec![],
+                    });
+                }
+                // The floating-point registers follow the status of their thread.
+                NT_FPREGSET => {
+                    if let Some(thread) = self.threads.last_mut() {
+                        thread.fp_regs = description.to_vec();
+                    }
+                }
+                NT_AUXV => self.auxv = description.to_vec(),
+                NT_FILE => files = self.parse_files(description)?,
+                _ => {}
+            }
+        }
+        return Ok(files);
+    }
+
+    fn parse_files(&mut self, data: &[u8]) -> Result<Vec<(u64, u64, u64, String)>, CoreError> {
+        let count = u64_at(data, 0)? as usize;
+        self.page_size = u64_at(data, 8)?;
+        let mut paths = data
+            .get(16 + count * 24..)
+            .ok_or(CoreError::Malformed("truncated NT_FILE note"))?
+            .split(|byte| *byte == 0);
+        let mut files = vec![];
+        for i in 0..count {
+            let entry = 16 + i * 24;
+            let path = paths
+                .next()
+                .ok_or(CoreError::Malformed("truncated NT_FILE note"))?;
+            files.push((
+                u64_at(data, entry)?,
+                u64_at(data, entry + 8)?,
+                u64_at(data, entry + 16)? * self.page_size,
+                String::from_utf8_lossy(path).to_string(),
+            ));
+        }
+        return Ok(files);
+    }
+
+    // Reads memory out of the segments. Whatever a segment holds past its data reads as zeroes.
+    pub fn read_memory(&self, address: u64, len: usize) -> Result<Vec<u8>, CoreError> {
+        let mut bytes = Vec::with_capacity(len);
+        let end = address + len as u64;
+        while (bytes.len() as u64) < len as u64 {
+            let next = address + bytes.len() as u64;
+            let segment = self
+                .segments
+                .iter()
+                .find(|segment| segment.start <= next && next < segment.end)
+                .ok_or(CoreError::Unmapped(next))?;
+            let segment_end = segment.end.min(end);
+            for address in next..segment_end {
+                let index = (address - segment.start) as usize;
+                bytes.push(segment.data.get(index).copied().unwrap_or(0));
+            }
+        }
+        return Ok(bytes);
+    }
+
+    fn encode_notes(&self) -> Vec<u8> {
+        let mut notes = vec![];
+        push_note(&mut notes, NT_PRPSINFO, &self.encode_prpsinfo());
+        for thread in &self.threads {
+            push_note(&mut notes, NT_PRSTATUS, &self.encode_prstatus(thread));
+            push_note(&mut notes, NT_FPREGSET, &thread.fp_regs);
+        }
+        if !self.auxv.is_empty() {
+            push_note(&mut notes, NT_AUXV, &self.auxv);
+        }
+        push_note(&mut notes, NT_FILE, &self.encode_files());
+        return notes;
+    }
+
+    fn encode_prpsinfo(&self) -> Vec<u8> {
+        let mut prpsinfo = vec![0; PRPSINFO_SIZE];
+        // The state, which is always stopped while being dumped.
+        prpsinfo[0] = 3;
+        prpsinfo[1] = b'T';
+        prpsinfo[16..20].copy_from_slice(&self.uid.to_le_bytes());
+        prpsinfo[20..24].copy_from_slice(&self.gid.to_le_bytes());
+        prpsinfo[24..28].copy_from_slice(&self.pid.to_le_bytes());
+        prpsinfo[28..32].copy_from_slice(&self.ppid.to_le_bytes());
+        prpsinfo[32..36].copy_from_slice(&process_group(self.pid).to_le_bytes());
+        prpsinfo[36..40].copy_from_slice(&session_id(self.pid).to_le_bytes());
+        // Both strings are NUL-terminated within their fields.
+        let name = self.name.as_bytes();
+        let name_len = name.len().min(PRPSINFO_FNAME_LEN - 1);
+        prpsinfo[40..40 + name_len].copy_from_slice(&name[..name_len]);
+        let args = self.args.join(" ");
+        let args_len = args.len().min(PRPSINFO_PSARGS_LEN - 1);
+        prpsinfo[56..56 + args_len].copy_from_slice(&args.as_bytes()[..args_len]);
+        return prpsinfo;
+    }
+
+    fn encode_prstatus(&self, thread: &CoreThread) -> Vec<u8> {
+        let mut prstatus = vec![0; PRSTATUS_SIZE];
+        prstatus[0..4].copy_from_slice(&thread.signal.to_le_bytes());
+        prstatus[12..14].copy_from_slice(&(thread.signal as i16).to_le_bytes());
+        prstatus[32..36].copy_from_slice(&thread.tid.to_le_bytes());
+        prstatus[36..40].copy_from_slice(&self.ppid.to_le_bytes());
+        prstatus[40..44].copy_from_slice(&process_group(self.pid).to_le_bytes());
+        prstatus[44..48].copy_from_slice(&session_id(self.pid).to_le_bytes());
+        for (i, reg) in thread.regs.iter().enumerate() {
+            let offset = PRSTATUS_REGS_OFFSET + i * 8;
+            prstatus[offset..offset + 8].copy_from_slice(&reg.to_le_bytes());
+        }
+        let fp_valid = !thread.fp_regs.is_empty() as u32;
+        prstatus[PRSTATUS_FPVALID_OFFSET..PRSTATUS_FPVALID_OFFSET + 4]
+            .copy_from_slice(&fp_valid.to_le_bytes());
+        return prstatus;
+    }
+
+    // Lists the mapped files: their count and the page size, a start, end, and offset in pages
+    // for each, and then their NUL-terminated paths.
+    fn encode_files(&self) -> Vec<u8> {
+        let files = self
+            .mappings
+            .iter()
+            .filter(|mapping| mapping.path.starts_with('/'))
+            .collect::<Vec<&MemoryMapping>>();
+        let mut data = vec![];
+        push_u64(&mut data, files.len() as u64);
+        push_u64(&mut data, self.page_size);
+        for file in &files {
+            push_u64(&mut data, file.start);
+            push_u64(&mut data, file.end);
+            push_u64(&mut data, file.offset / self.page_size);
+        }
+        for file in &files {
+            data.extend_from_slice(file.path.as_bytes());
+            data.push(0);
+        }
+        return data;
+    }
+}
+
+// Writes a core file of a stopped tracee. Returns how many segments of memory it holds.
+pub unsafe fn write_core(tracee: &Tracee, path: &Path) -> io::Result<usize> {
+    let snapshot = CoreSnapshot::capture(tracee)?;
+    fs::write(path, snapshot.encode())?;
+    return Ok(snapshot.segments.len());
+}
+
+// A core file, loaded as a target whose memory and registers cannot change. Its functions come
+// from the executable that it was dumped from.
+pub struct CoreTarget {
+    snapshot: CoreSnapshot,
+    selected_tid: libc::pid_t,
+    functions: Vec<FunctionSymbol>,
+}
+
+impl CoreTarget {
+    pub fn open(core_path: &Path, executable: &Path) -> Result<CoreTarget, CoreError> {
+        let data = fs::read(core_path).map_err(|source| CoreError::Read {
+            path: core_path.display().to_string(),
+            source,
+        })?;
+        let snapshot = CoreSnapshot::parse(&data)?;
+        let Some(selected_tid) = snapshot.threads.first().map(|thread| thread.tid) else {
+            return Err(CoreError::Malformed("no threads"));
+        };
+
+        let elf = ElfFile::read(executable)?;
+        let path = mapped_path(&snapshot.mappings, executable).ok_or(CoreError::NotMapped {
+            path: executable.display().to_string(),
+        })?;
+        let Some(bias) = load_bias(&elf, &path, &snapshot.mappings)? else {
+            return Err(CoreError::NotMapped { path });
+        };
+        let mut functions = elf
+            .function_symbols()?
+            .into_iter()
+            .map(|function| FunctionSymbol {
+                address: function.address.wrapping_add(bias),
+                ..function
+            })
+            .collect::<Vec<FunctionSymbol>>();
+        functions.sort_by_key(|function| function.address);
+
+        return Ok(CoreTarget {
+            snapshot,
+            selected_tid,
+            functions,
+        });
+    }
+
+    pub fn snapshot(&self) -> &CoreSnapshot {
+        return &self.snapshot;
+    }
+
+    pub fn selected_tid(&self) -> libc::pid_t {
+        return self.selected_tid;
+    }
+
+    pub fn select_thread(&mut self, tid: libc::pid_t) -> bool {
+        if !self.snapshot.threads.iter().any(|thread| thread.tid == tid) {
+            return false;
+        }
+        self.selected_tid = tid;
+        return true;
+    }
+
+    // Describes an address by the function of the executable that contains it, e.g. "main+0x14".
+    pub fn describe_address(&self, address: u64) -> Option<String> {
+        return elf::describe_address(&self.functions, address);
+    }
+
+    pub fn read_thread_registers(&self, tid: libc::pid_t) -> Option<libc::user_regs_struct> {
+        let thread = self
+            .snapshot
+            .threads
+            .iter()
+            .find(|thread| thread.tid == tid)?;
+        return Some(from_core_registers(&thread.regs));
+    }
+}
+
+// Finds the path that the core file knows the executable by, which may differ from the one it is
+// given by, e.g. when it is relative or was copied off another machine.
+fn mapped_path(mappings: &[MemoryMapping], executable: &Path) -> Option<String> {
+    if let Ok(canonical) = fs::canonicalize(executable) {
+        let canonical = canonical.display().to_string();
+        if mappings.iter().any(|mapping| mapping.path == canonical) {
+            return Some(canonical);
+        }
+    }
+    let name = executable.file_name()?;
+    return mappings
+        .iter()
+        .find(|mapping| Path::new(&mapping.path).file_name() == Some(name))
+        .map(|mapping| mapping.path.clone());
+}
+
+fn to_core_registers(regs: &libc::user_regs_struct) -> [u64; CORE_REGISTER_COUNT] {
+    let mut core_regs = [0; CORE_REGISTER_COUNT];
+    core_regs[..31].copy_from_slice(&regs.regs);
+    core_regs[31] = regs.sp;
+    core_regs[32] = regs.pc;
+    core_regs[33] = regs.pstate;
+    return core_regs;
+}
+
+fn from_core_registers(core_regs: &[u64; CORE_REGISTER_COUNT]) -> libc::user_regs_struct {
+    let mut regs = unsafe { mem::zeroed::<libc::user_regs_struct>() };
+    regs.regs.copy_from_slice(&core_regs[..31]);
+    regs.sp = core_regs[31];
+    regs.pc = core_regs[32];
+    regs.pstate = core_regs[33];
+    return regs;
+}
+
+// The bytes of a plain-old-data struct, as the kernel lays it out.
+unsafe fn struct_bytes<T>(value: &T) -> Vec<u8> {
+    let ptr = value as *const T as *const u8;
+    return std::slice::from_raw_parts(ptr, mem::size_of::<T>()).to_vec();
+}
+
+fn process_group(pid: libc::pid_t) -> libc::pid_t {
+    return unsafe { libc::getpgid(pid) }.max(0);
+}
+
+fn session_id(pid: libc::pid_t) -> libc::pid_t {
+    return unsafe { libc::getsid(pid) }.max(0);
+}
+
+fn segment_flags(permissions: &str) -> u32 {
+    let mut flags = 0;
+    for (i, flag) in [PF_R, PF_W, PF_X].into_iter().enumerate() {
+        if permissions.as_bytes().get(i).is_some_and(|c| *c != b'-') {
+            flags |= flag;
+        }
+    }
+    return flags;
+}
+
+fn segment_permissions(flags: u32) -> String {
+    let mut permissions = String::new();
+    for (flag, c) in [(PF_R, 'r'), (PF_W, 'w'), (PF_X, 'x')] {
+        permissions.push(match flags & flag != 0 {
+            true => c,
+            false => '-',
+        });
+    }
+    permissions.push('p');
+    return permissions;
+}
+
+fn align(offset: usize, alignment: usize) -> usize {
+    return offset.div_ceil(alignment) * alignment;
+}
+
+#[allow(clippy::too_many_arguments)]
+fn push_program_header(
+    data: &mut Vec<u8>,
+    kind: u32,
+    flags: u32,
+    offset: usize,
+    address: u64,
+    file_size: usize,
+    memory_size: usize,
+    alignment: u64,
+) {
+    push_u32(data, kind);
+    push_u32(data, flags);
+    push_u64(data, offset as u64);
+    push_u64(data, address);
+    push_u64(data, 0);
+    push_u64(data, file_size as u64);
+    push_u64(data, memory_size as u64);
+    push_u64(data, alignment);
+}
+
+// Appends a note named "CORE", whose name and description are padded to 4 bytes.
+fn push_note(data: &mut Vec<u8>, kind: u32, description: &[u8]) {
+    push_u32(data, CORE_NOTE_NAME.len() as u32);
+    push_u32(data, description.len() as u32);
+    push_u32(data, kind);
+    data.extend_from_slice(CORE_NOTE_NAME);
+    data.resize(align(data.len(), 4), 0);
+    data.extend_from_slice(description);
+    data.resize(align(data.len(), 4), 0);
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, CoreError> {
+    let bytes = data.get(offset..offset + 2);
+    let bytes = bytes.ok_or(CoreError::Malformed("truncated file"))?;
+    return Ok(u16::from_le_bytes(bytes.try_into().unwrap()));
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, CoreError> {
+    let bytes = data.get(offset..offset + 4);
+    let bytes = bytes.ok_or(CoreError::Malformed("truncated file"))?;
+    return Ok(u32::from_le_bytes(bytes.try_into().unwrap()));
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Result<u64, CoreError> {
+    let bytes = data.get(offset..offset + 8);
+    let bytes = bytes.ok_or(CoreError::Malformed("truncated file"))?;
+    return Ok(u64::from_le_bytes(bytes.try_into().unwrap()));
+}
+
+// Reads a NUL-terminated string out of a field of `len` bytes.
+fn string_at(data: &[u8], offset: usize, len: usize) -> Result<String, CoreError> {
+    let bytes = data
+        .get(offset..offset + len)
+        .ok_or(CoreError::Malformed("truncated file"))?;
+    let len = bytes.iter().position(|byte| *byte == 0).unwrap_or(len);
+    return Ok(String::from_utf8_lossy(&bytes[..len]).to_string());
+}
+
+fn push_u16(data: &mut Vec<u8>, value: u16) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u64(data: &mut Vec<u8>, value: u64) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+// The snapshot that the tests of the formats that snapshots are written in start from.
+#[cfg(test)]
+pub(crate) mod test {
+    use super::{
+        segment_flags, segment_permissions, CoreError, CoreSegment, CoreSnapshot, CoreThread,
+        CORE_REGISTER_COUNT,
+    };
+    use crate::procfs::MemoryMapping;
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        return u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap());
+    }
+
+    pub(crate) fn u32_at(data: &[u8], offset: usize) -> u32 {
+        return u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+    }
+
+    pub(crate) fn u64_at(data: &[u8], offset: usize) -> u64 {
+        return u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+    }
+
+    // A process that crashed in an ELF file mapped at 0x400000, with its stack pointer in the
+    // stack below 0x800000.
+    pub(crate) fn sample_snapshot() -> CoreSnapshot {
+        let mut regs = [0; CORE_REGISTER_COUNT];
+        regs[0] = 0x11;
+        regs[31] = 0x7ff000;
+        regs[32] = 0x400010;
+        let mut code = b"\x7fELF".to_vec();
+        code.resize(0x1000, 0xaa);
+        return CoreSnapshot {
+            pid: 10,
+            ppid: 1,
+            uid: 1000,
+            gid: 1000,
+            name: "prog".to_string(),
+            args: vec!["prog".to_string(), "-v".to_string()],
+            page_size: 0x1000,
+            threads: vec![CoreThread {
+                tid: 10,
+                signal: libc::SIGSEGV,
+                regs,
+                fp_regs: vec![0; 528],
+            }],
+            segments: vec![
+                CoreSegment {
+                    start: 0x400000,
+                    end: 0x401000,
+                    permissions: "r-xp".to_string(),
+                    data: code,
+                },
+                CoreSegment {
+                    start: 0x7fe000,
+                    end: 0x800000,
+                    permissions: "rw-p".to_string(),
+                    data: vec![0xbb; 0x2000],
+                },
+            ],
+            mappings: vec![
+                MemoryMapping {
+                    start: 0x400000,
+                    end: 0x401000,
+                    permissions: "r-xp".to_string(),
+                    offset: 0,
+                    path: "/bin/prog".to_string(),
+                },
+                MemoryMapping {
+                    start: 0x7fe000,
+                    end: 0x800000,
+                    permissions: "rw-p".to_string(),
+                    offset: 0,
+                    path: "[stack]".to_string(),
+                },
+            ],
+            auxv: vec![],
+        };
+    }
+
+    #[test]
+    fn encode_writes_elf_core_with_notes_and_segments() {
+        let core = sample_snapshot().encode();
+        assert_eq!(&core[..4], b"\x7fELF");
+        assert_eq!(u16_at(&core, 16), 4);
+        assert_eq!(u16_at(&core, 18), 183);
+        assert_eq!(u16_at(&core, 0x38), 3);
+
+        // The notes come first: PRPSINFO, then PRSTATUS and FPREGSET of each thread, then NT_FILE.
+        let note_header = 64;
+        assert_eq!(u32_at(&core, note_header), 4);
+        let notes_offset = u64_at(&core, note_header + 8) as usize;
+        let notes_size = u64_at(&core, note_header + 32) as usize;
+        let mut kinds = vec![];
+        let mut offset = notes_offset;
+        while offset < notes_offset + notes_size {
+            let name_size = u32_at(&core, offset) as usize;
+            let description_size = u32_at(&core, offset + 4) as usize;
+            kinds.push(u32_at(&core, offset + 8));
+            assert_eq!(&core[offset + 12..offset + 16], b"CORE");
+            if kinds.last() == Some(&1) {
+                let prstatus = offset + 12 + name_size.div_ceil(4) * 4;
+                assert_eq!(u32_at(&core, prstatus + 32), 10);
+                assert_eq!(u64_at(&core, prstatus + 112), 0x11);
+                assert_eq!(u64_at(&core, prstatus + 112 + 32 * 8), 0x400010);
+            }
+            offset += 12 + name_size.div_ceil(4) * 4 + description_size.div_ceil(4) * 4;
+        }
+        assert_eq!(kinds, vec![3, 1, 2, 0x4649_4c45]);
+
+        let load_header = 64 + 56;
+        assert_eq!(u32_at(&core, load_header), 1);
+        assert_eq!(u32_at(&core, load_header + 4), 5);
+        let load_offset = u64_at(&core, load_header + 8) as usize;
+        assert_eq!(load_offset % 0x1000, 0);
+        assert_eq!(u64_at(&core, load_header + 16), 0x400000);
+        assert_eq!(u64_at(&core, load_header + 32), 0x1000);
+        assert_eq!(&core[load_offset..load_offset + 5], b"\x7fELF\xaa");
+
+        let stack_header = load_header + 56;
+        assert_eq!(u32_at(&core, stack_header + 4), 6);
+        let stack_offset = u64_at(&core, stack_header + 8) as usize;
+        assert_eq!(stack_offset, load_offset + 0x1000);
+        assert_eq!(u64_at(&core, stack_header + 16), 0x7fe000);
+        assert_eq!(core[stack_offset], 0xbb);
+        assert_eq!(core.len(), stack_offset + 0x2000);
+    }
+
+    #[test]
+    fn parse_reads_back_what_encode_writes() {
+        let mut snapshot = sample_snapshot();
+        let core = snapshot.encode();
+        // Only the mapped files are dumped, not e.g. the stack.
+        snapshot
+            .mappings
+            .retain(|mapping| mapping.path.starts_with('/'));
+        assert_eq!(CoreSnapshot::parse(&core).unwrap(), snapshot);
+
+        assert!(matches!(
+            CoreSnapshot::parse(b"not a core file"),
+            Err(CoreError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn read_memory_reads_segments_and_zeroes_past_their_data() {
+        let mut snapshot = sample_snapshot();
+        snapshot.segments.push(CoreSegment {
+            start: 0x401000,
+            end: 0x402000,
+            permissions: "rw-p".to_string(),
+            data: vec![0xbb; 2],
+        });
+        assert_eq!(
+            snapshot.read_memory(0x400ffe, 6).unwrap(),
+            vec![0xaa, 0xaa, 0xbb, 0xbb, 0, 0]
+        );
+        assert!(matches!(
+            snapshot.read_memory(0x401ffe, 4),
+            Err(CoreError::Unmapped(0x402000))
+        ));
+    }
+
+    #[test]
+    fn segment_flags_follow_permissions() {
+        assert_eq!(segment_flags("r-xp"), 5);
+        assert_eq!(segment_flags("rw-p"), 6);
+        assert_eq!(segment_flags("---p"), 0);
+        assert_eq!(segment_permissions(5), "r-xp");
+        assert_eq!(segment_permissions(6), "rw-p");
+    }
+}
@@ -0,0 +1,912 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, stdin, BufRead, ErrorKind, Write},
+    os::fd::FromRawFd,
+    path::Path,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    breakpoint::Breakpoint,
+    cleanup,
+    dwarf::LineRow,
+    elf::{describe_address, FunctionSymbol},
+    json::Json,
+    launch::LaunchOptions,
+    procfs,
+    reaper::WaitStatus,
+    signal::{signal_name, Trap},
+    tracee::{Tracee, TraceeStatus},
+    unwind::unwind,
+};
+
+// How often a running tracee is checked on while waiting for the next request.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Stepping by source line gives up after this many instructions, e.g. in a loop that never
+// reaches another line.
+const MAX_LINE_STEP_INSTRUCTIONS: usize = 100_000;
+
+// The size of an aarch64 `bl`, which a call returns past.
+const CALL_INSTRUCTION_LEN: u64 = 4;
+
+// A frame of the last stack trace that the client asked for, which scopes and variables refer
+// to by its position.
+struct FrameReference {
+    tid: libc::pid_t,
+    index: usize,
+    pc: u64,
+    fp: u64,
+}
+
+// Serves a single tracee to an editor like VS Code over the Debug Adapter Protocol (see
+// https://microsoft.github.io/debug-adapter-protocol/).
+pub struct DapServer {
+    // The original stdout, which carries the protocol. Everything printed goes to stderr instead.
+    output: File,
+    next_seq: i64,
+    tracee: Option<Tracee>,
+    // Whether the tracee was launched rather than attached to, in which case it is killed rather
+    // than detached from when the client disconnects.
+    launched: bool,
+    stop_on_entry: bool,
+    functions: Vec<FunctionSymbol>,
+    line_rows: Vec<LineRow>,
+    // The addresses of the breakpoints that the client set, by source path.
+    source_breakpoints: HashMap<String, Vec<u64>>,
+    frames: Vec<FrameReference>,
+    // Whether the running tracee is being paused by the client.
+    pausing: bool,
+}
+
+// Speaks the Debug Adapter Protocol over stdin and stdout until the client disconnects.
+pub unsafe fn serve() -> io::Result<()> {
+    cleanup::install();
+    // The tracee and pbreak itself print freely, which would corrupt the protocol on stdout.
+    let output_fd = libc::dup(libc::STDOUT_FILENO);
+    if output_fd < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Messages are read on a separate thread, so that the tracee can be waited on while it runs.
+    let (message_sender, messages) = mpsc::channel();
+    thread::spawn(move || {
+        cleanup::block_fatal_signals();
+        let mut reader = stdin().lock();
+        loop {
+            let message = read_message(&mut reader);
+            let is_done = !matches!(message, Ok(Some(_)));
+            if message_sender.send(message).is_err() || is_done {
+                return;
+            }
+        }
+    });
+
+    let mut server = DapServer {
+        output: File::from_raw_fd(output_fd),
+        next_seq: 1,
+        tracee: None,
+        launched: false,
+        stop_on_entry: false,
+        functions: vec![],
+        line_rows: vec![],
+        source_breakpoints: HashMap::new(),
+        frames: vec![],
+        pausing: false,
+    };
+    loop {
+        let is_running = server
+            .tracee
+            .as_ref()
+            .is_some_and(|tracee| tracee.status() == TraceeStatus::Running);
+        let message = match is_running {
+            false => messages.recv().unwrap_or(Ok(None)),
+            true => match messages.recv_timeout(POLL_INTERVAL) {
+                Err(RecvTimeoutError::Disconnected) => Ok(None),
+                Err(RecvTimeoutError::Timeout) => {
+                    if server
+                        .tracee
+                        .as_mut()
+                        .is_some_and(|tracee| tracee.poll_signal())
+                    {
+                        server.report_stop("pause")?;
+                    }
+                    continue;
+                }
+                Ok(message) => message,
+            },
+        };
+
+        let request = match message? {
+            None => break,
+            Some(message) => match Json::parse(&message) {
+                Err(err) => {
+                    println!("ignoring malformed message: {}", err);
+                    continue;
+                }
+                Ok(request) => request,
+            },
+        };
+        if !server.handle_request(&request)? {
+            break;
+        }
+    }
+
+    server.end_session(None);
+    return Ok(());
+}
+
+// Reads a message, which is framed by a Content-Length header. Returns None at the end of input.
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(len_str) = line.strip_prefix("Content-Length:") {
+            content_length = len_str.trim().parse::<usize>().ok();
+        }
+    }
+
+    let Some(len) = content_length else {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "missing Content-Length header",
+        ));
+    };
+    let mut content = vec![0; len];
+    reader.read_exact(&mut content)?;
+    return Ok(Some(String::from_utf8_lossy(&content).to_string()));
+}
+
+fn frame_message(message: &Json) -> String {
+    let content = message.to_string();
+    return format!("Content-Length: {}\r\n\r\n{}", content.len(), content);
+}
+
+// Finds where a breakpoint on a source line goes: the first instruction of the first line at or
+// after it that has any code. Returns the line along with the address.
+fn resolve_source_line(line_rows: &[LineRow], path: &str, line: u64) -> Option<(u64, u64)> {
+    return line_rows
+        .iter()
+        .filter(|row| row.line >= line && is_same_source(&row.path, path))
+        .map(|row| (row.line, row.address))
+        .min();
+}
+
+// Whether a path of the line table names the given source file. Relative paths in the line table
+// are relative to wherever the program was built.
+fn is_same_source(row_path: &str, path: &str) -> bool {
+    return row_path == path
+        || (!row_path.starts_with('/') && path.ends_with(&format!("/{}", row_path)));
+}
+
+impl DapServer {
+    fn send(&mut self, mut message: Vec<(&str, Json)>) -> io::Result<()> {
+        message.insert(0, ("seq", Json::from(self.next_seq)));
+        self.next_seq += 1;
+        let message = frame_message(&Json::object(message));
+        self.output.write_all(message.as_bytes())?;
+        return self.output.flush();
+    }
+
+    fn send_event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        return self.send(vec![
+            ("type", Json::from("event")),
+            ("event", Json::from(event)),
+            ("body", body),
+        ]);
+    }
+
+    fn respond(&mut self, request: &Json, result: Result<Json, String>) -> io::Result<()> {
+        let mut response = vec![
+            ("type", Json::from("response")),
+            (
+                "request_seq",
+                request.get("seq").cloned().unwrap_or(Json::Null),
+            ),
+            (
+                "command",
+                request.get("command").cloned().unwrap_or(Json::Null),
+            ),
+        ];
+        match result {
+            Ok(body) => {
+                response.push(("success", Json::from(true)));
+                response.push(("body", body));
+            }
+            Err(message) => {
+                response.push(("success", Json::from(false)));
+                response.push(("message", Json::from(message)));
+            }
+        }
+        return self.send(response);
+    }
+
+    // Handles a request. Returns false once the client has disconnected.
+    unsafe fn handle_request(&mut self, request: &Json) -> io::Result<bool> {
+        let command = request.get("command").and_then(Json::as_str).unwrap_or("");
+        let arguments = request.get("arguments").cloned().unwrap_or(Json::Null);
+
+        let is_stopped = self
+            .tracee
+            .as_ref()
+            .is_some_and(|tracee| tracee.status() == TraceeStatus::Stopped);
+        match command {
+            "initialize" => {
+                let capabilities = Json::object(vec![
+                    ("supportsConfigurationDoneRequest", Json::from(true)),
+                    ("supportsSteppingGranularity", Json::from(true)),
+                    ("supportTerminateDebuggee", Json::from(true)),
+                ]);
+                self.respond(request, Ok(capabilities))?;
+            }
+            "launch" | "attach" => {
+                let result = self.start(command, &arguments);
+                let is_started = result.is_ok();
+                self.respond(request, result.map(|_| Json::Null))?;
+                // Breakpoints can only be placed once there is a tracee to place them in.
+                if is_started {
+                    self.send_event("initialized", Json::Null)?;
+                }
+            }
+            "disconnect" => {
+                let terminate = arguments.get("terminateDebuggee").and_then(Json::as_bool);
+                self.end_session(terminate);
+                self.respond(request, Ok(Json::Null))?;
+                return Ok(false);
+            }
+            _ if self.tracee.is_none() => {
+                self.respond(request, Err("no program is being debugged".to_string()))?;
+            }
+            "setBreakpoints" => {
+                let result = self.set_breakpoints(&arguments);
+                self.respond(request, result)?;
+            }
+            "setExceptionBreakpoints" => {
+                let body = Json::object(vec![("breakpoints", Json::from(vec![]))]);
+                self.respond(request, Ok(body))?;
+            }
+            "configurationDone" => {
+                self.respond(request, Ok(Json::Null))?;
+                match self.stop_on_entry {
+                    true => self.send_stopped_event("entry", None)?,
+                    false => self.resume()?,
+                }
+            }
+            "threads" => {
+                let threads = self.threads();
+                self.respond(request, Ok(threads))?;
+            }
+            "stackTrace" if is_stopped => {
+                let result = self.stack_trace(&arguments);
+                self.respond(request, result)?;
+            }
+            "scopes" if is_stopped => {
+                let result = self.scopes(&arguments);
+                self.respond(request, result)?;
+            }
+            "variables" if is_stopped => {
+                let result = self.variables(&arguments);
+                self.respond(request, result)?;
+            }
+            "continue" if is_stopped => {
+                self.select_thread(&arguments);
+                let body = Json::object(vec![("allThreadsContinued", Json::from(true))]);
+                self.respond(request, Ok(body))?;
+                self.resume()?;
+            }
+            "next" | "stepIn" | "stepOut" if is_stopped => {
+                self.select_thread(&arguments);
+                self.respond(request, Ok(Json::Null))?;
+                let by_instruction =
+                    arguments.get("granularity").and_then(Json::as_str) == Some("instruction");
+                match command {
+                    "stepOut" => self.step_out()?,
+                    _ => self.step(command == "next", by_instruction)?,
+                }
+            }
+            "pause" => {
+                self.respond(request, Ok(Json::Null))?;
+                if let Some(tracee) = self.tracee.as_mut() {
+                    if tracee.status() == TraceeStatus::Running {
+                        self.pausing = true;
+                        tracee.interrupt(None);
+                        self.report_stop("pause")?;
+                    }
+                }
+            }
+            "stackTrace" | "scopes" | "variables" | "continue" | "next" | "stepIn" | "stepOut" => {
+                self.respond(request, Err("the program is not stopped".to_string()))?;
+            }
+            _ => {
+                let message = format!("unsupported request: \"{}\"", command);
+                self.respond(request, Err(message))?;
+            }
+        }
+        return Ok(true);
+    }
+
+    // Launches or attaches to the program given by the arguments of the request.
+    unsafe fn start(&mut self, command: &str, arguments: &Json) -> Result<(), String> {
+        if self.tracee.is_some() {
+            return Err("a program is already being debugged".to_string());
+        }
+
+        let tracee = match command {
+            "launch" => {
+                let program = arguments
+                    .get("program")
+                    .and_then(Json::as_str)
+                    .ok_or("missing \"program\" to launch")?;
+                let args = arguments
+                    .get("args")
+                    .and_then(Json::as_array)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|arg| arg.as_str().map(str::to_string))
+                    .collect::<Vec<String>>();
+                let mut launch = LaunchOptions::new(program, &args);
+                launch.cwd = arguments
+                    .get("cwd")
+                    .and_then(Json::as_str)
+                    .map(str::to_string);
+                Tracee::launch(&launch)
+            }
+            _ => {
+                let pid = arguments
+                    .get("processId")
+                    .and_then(Json::as_i64)
+                    .ok_or("missing \"processId\" to attach to")?;
+                Tracee::from_pid(pid as libc::pid_t)
+            }
+        };
+
+        // Symbols and line tables are only needed for stacks, breakpoints and stepping, so a
+        // program without them can still be debugged.
+        self.functions = tracee.read_functions().unwrap_or_default();
+        self.line_rows = tracee.read_line_rows().unwrap_or_default();
+        self.launched = command == "launch";
+        self.stop_on_entry = arguments
+            .get("stopOnEntry")
+            .and_then(Json::as_bool)
+            .unwrap_or(false);
+        self.tracee = Some(tracee);
+        return Ok(());
+    }
+
+    // Ends the session, killing a launched tracee or detaching from an attached one, unless the
+    // client says otherwise.
+    unsafe fn end_session(&mut self, terminate: Option<bool>) {
+        let Some(mut tracee) = self.tracee.take() else {
+            return;
+        };
+        if tracee.status() == TraceeStatus::Exited || tracee.status() == TraceeStatus::Terminated {
+            return;
+        }
+
+        if tracee.status() == TraceeStatus::Running {
+            tracee.interrupt(None);
+        }
+        match terminate.unwrap_or(self.launched) {
+            true => tracee.kill(),
+            // Breakpoints are restored while detaching, along with every other patch.
+            false => tracee.detach(),
+        }
+    }
+
+    // Selects the thread that a request names, if any.
+    unsafe fn select_thread(&mut self, arguments: &Json) {
+        let Some(tid) = arguments.get("threadId").and_then(Json::as_i64) else {
+            return;
+        };
+        if let Some(tracee) = self.tracee.as_mut() {
+            tracee.select_thread(tid as libc::pid_t);
+        }
+    }
+
+    unsafe fn set_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let path = arguments
+            .get("source")
+            .and_then(|source| source.get("path"))
+            .and_then(Json::as_str)
+            .ok_or("missing source path")?
+            .to_string();
+        let lines = arguments
+            .get("breakpoints")
+            .and_then(Json::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|breakpoint| breakpoint.get("line").and_then(Json::as_u64))
+            .collect::<Vec<u64>>();
+
+        // The breakpoints of the source are replaced as a whole.
+        let old_breakpoints = self.source_breakpoints.remove(&path).unwrap_or_default();
+        for address in old_breakpoints {
+            self.remove_breakpoint(address);
+        }
+
+        let mut addresses = vec![];
+        let mut results = vec![];
+        for line in lines {
+            let location = resolve_source_line(&self.line_rows, &path, line)
+                .filter(|(_, address)| self.insert_breakpoint(*address));
+            if let Some((_, address)) = location {
+                addresses.push(address);
+            }
+            results.push(match location {
+                None => Json::object(vec![
+                    ("verified", Json::from(false)),
+                    ("line", Json::from(line)),
+                    ("message", Json::from("no code at or after this line")),
+                ]),
+                Some((actual_line, _)) => Json::object(vec![
+                    ("verified", Json::from(true)),
+                    ("line", Json::from(actual_line)),
+                ]),
+            });
+        }
+        self.source_breakpoints.insert(path, addresses);
+        return Ok(Json::object(vec![("breakpoints", Json::from(results))]));
+    }
+
+    // Sets a breakpoint of the tracee's. Returns false if the memory could not be written.
+    unsafe fn insert_breakpoint(&mut self, address: u64) -> bool {
+        let Some(tracee) = self.tracee.as_mut() else {
+            return false;
+        };
+        if tracee.breakpoints().find(address).is_some() {
+            return true;
+        }
+        return match tracee.insert_breakpoint(address) {
+            Err(err) => {
+                println!("{}", err);
+                false
+            }
+            Ok(_) => true,
+        };
+    }
+
+    // Removes a breakpoint instruction, unless another source line still resolves to it.
+    unsafe fn remove_breakpoint(&mut self, address: u64) {
+        let is_shared = self
+            .source_breakpoints
+            .values()
+            .flatten()
+            .any(|a| *a == address);
+        if is_shared {
+            return;
+        }
+        let Some(tracee) = self.tracee.as_mut() else {
+            return;
+        };
+        let Some(id) = tracee.breakpoints().find(address).map(Breakpoint::id) else {
+            return;
+        };
+        if let Err(err) = tracee.delete_breakpoint(id) {
+            println!("{}", err);
+        }
+    }
+
+    unsafe fn threads(&self) -> Json {
+        let Some(tracee) = self.tracee.as_ref() else {
+            return Json::object(vec![("threads", Json::from(vec![]))]);
+        };
+        let threads = tracee
+            .threads()
+            .iter()
+            .map(|thread| {
+                let name = procfs::read_comm(thread.tid).unwrap_or_default();
+                return Json::object(vec![
+                    ("id", Json::from(thread.tid)),
+                    ("name", Json::from(format!("{} ({})", name, thread.tid))),
+                ]);
+            })
+            .collect::<Vec<Json>>();
+        return Json::object(vec![("threads", Json::from(threads))]);
+    }
+
+    unsafe fn stack_trace(&mut self, arguments: &Json) -> Result<Json, String> {
+        let tracee = self.tracee.as_mut().ok_or("no program is being debugged")?;
+        let tid = arguments
+            .get("threadId")
+            .and_then(Json::as_i64)
+            .map_or(tracee.selected_tid(), |tid| tid as libc::pid_t);
+        let start = arguments
+            .get("startFrame")
+            .and_then(Json::as_u64)
+            .unwrap_or(0) as usize;
+        let levels = match arguments.get("levels").and_then(Json::as_u64) {
+            None | Some(0) => usize::MAX,
+            Some(levels) => levels as usize,
+        };
+
+        let selected_tid = tracee.selected_tid();
+        if !tracee.select_thread(tid) {
+            return Err(format!("unknown thread: {}", tid));
+        }
+        let frames = unwind(tracee);
+        tracee.select_thread(selected_tid);
+
+        let mut stack_frames = vec![];
+        for (index, frame) in frames.iter().enumerate().skip(start).take(levels) {
+            self.frames.push(FrameReference {
+                tid,
+                index,
+                pc: frame.pc,
+                fp: frame.fp,
+            });
+            // A return address is past the call, which may already be on the next line.
+            let call_pc = match index {
+                0 => frame.pc,
+                _ => frame.pc.saturating_sub(1),
+            };
+            let name = describe_address(&self.functions, frame.pc)
+                .unwrap_or_else(|| format!("{:#x}", frame.pc));
+            let mut stack_frame = vec![
+                ("id", Json::from(self.frames.len())),
+                ("name", Json::from(name)),
+                ("line", Json::from(0)),
+                ("column", Json::from(0)),
+                (
+                    "instructionPointerReference",
+                    Json::from(format!("{:#x}", frame.pc)),
+                ),
+            ];
+            if let Some(row) = self.line_at(call_pc) {
+                let source_name = Path::new(&row.path)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                stack_frame[2] = ("line", Json::from(row.line));
+                stack_frame.push((
+                    "source",
+                    Json::object(vec![
+                        ("name", Json::from(source_name)),
+                        ("path", Json::from(row.path.as_str())),
+                    ]),
+                ));
+            }
+            stack_frames.push(Json::object(stack_frame));
+        }
+        return Ok(Json::object(vec![
+            ("stackFrames", Json::from(stack_frames)),
+            ("totalFrames", Json::from(frames.len())),
+        ]));
+    }
+
+    // Every frame has a single scope of registers. Without debug info for variables, they are
+    // what there is to show.
+    fn scopes(&self, arguments: &Json) -> Result<Json, String> {
+        let frame_id = arguments
+            .get("frameId")
+            .and_then(Json::as_u64)
+            .filter(|frame_id| (1..=self.frames.len() as u64).contains(frame_id))
+            .ok_or("unknown frame")?;
+        let scope = Json::object(vec![
+            ("name", Json::from("Registers")),
+            ("presentationHint", Json::from("registers")),
+            ("variablesReference", Json::from(frame_id)),
+            ("expensive", Json::from(false)),
+        ]);
+        return Ok(Json::object(vec![("scopes", Json::from(vec![scope]))]));
+    }
+
+    // Lists the registers of a frame. Only the innermost frame has all of them; the others only
+    // have what the frame records keep.
+    unsafe fn variables(&self, arguments: &Json) -> Result<Json, String> {
+        let frame = arguments
+            .get("variablesReference")
+            .and_then(Json::as_u64)
+            .and_then(|reference| self.frames.get((reference as usize).checked_sub(1)?))
+            .ok_or("unknown variables reference")?;
+        let tracee = self.tracee.as_ref().ok_or("no program is being debugged")?;
+
+        let mut registers = vec![];
+        match frame.index {
+            0 => {
+                let regs = tracee.read_thread_general_purpose_registers(frame.tid);
+                for (i, value) in regs.regs.iter().enumerate() {
+                    registers.push((format!("x{}", i), *value));
+                }
+                registers.push(("sp".to_string(), regs.sp));
+                registers.push(("pc".to_string(), regs.pc));
+                registers.push(("cpsr".to_string(), regs.pstate));
+            }
+            _ => {
+                registers.push(("pc".to_string(), frame.pc));
+                registers.push(("fp".to_string(), frame.fp));
+            }
+        }
+        let variables = registers
+            .into_iter()
+            .map(|(name, value)| {
+                return Json::object(vec![
+                    ("name", Json::from(name)),
+                    ("value", Json::from(format!("{:#018x}", value))),
+                    ("variablesReference", Json::from(0)),
+                ]);
+            })
+            .collect::<Vec<Json>>();
+        return Ok(Json::object(vec![("variables", Json::from(variables))]));
+    }
+
+    // The line that an instruction of the main executable was compiled from, if known.
+    fn line_at(&self, pc: u64) -> Option<&LineRow> {
+        // Past the end of a line table, the last row would seem to cover everything.
+        let function = self.functions.iter().find(|function| {
+            return function.size > 0
+                && (function.address..function.address + function.size).contains(&pc);
+        })?;
+        let end = self.line_rows.partition_point(|row| row.address <= pc);
+        return self.line_rows[..end]
+            .last()
+            .filter(|row| row.address >= function.address);
+    }
+
+    // Resumes the tracee in the background. The tracee steps over a breakpoint that it stopped at.
+    unsafe fn resume(&mut self) -> io::Result<()> {
+        self.frames.clear();
+        if let Some(tracee) = self.tracee.as_mut() {
+            tracee.resume();
+        }
+        return Ok(());
+    }
+
+    // Single-steps the selected thread, over the breakpoint that it stopped at if any. Returns false
+    // if it stopped for anything other than the step, or exited.
+    unsafe fn step_instruction(&mut self) -> bool {
+        let Some(tracee) = self.tracee.as_mut() else {
+            return false;
+        };
+        self.frames.clear();
+        let is_alive = tracee.step_instruction().is_some();
+        return is_alive && tracee.trap() == Some(Trap::SingleStep);
+    }
+
+    // Steps to the start of another source line, or by a single instruction. Calls are stepped
+    // over by `next`, as well as by `stepIn` when there is no line info for the called code.
+    unsafe fn step(&mut self, over_calls: bool, by_instruction: bool) -> io::Result<()> {
+        let start_pc = self.read_registers().pc;
+        let start_line = self
+            .line_at(start_pc)
+            .map(|row| (row.path.clone(), row.line));
+
+        for _ in 0..MAX_LINE_STEP_INSTRUCTIONS {
+            let before = self.read_registers();
+            if !self.step_instruction() {
+                return self.report_stop("step");
+            }
+
+            let pc = self.read_registers().pc;
+            let return_address = before.pc + CALL_INSTRUCTION_LEN;
+            let is_call = pc != return_address && self.read_registers().regs[30] == return_address;
+            if is_call
+                && (over_calls || self.line_at(pc).is_none())
+                && !self.run_to(return_address, before.sp)?
+            {
+                return Ok(());
+            }
+            if by_instruction {
+                break;
+            }
+
+            let pc = self.read_registers().pc;
+            let Some(row) = self.line_at(pc) else {
+                // Returned into code without line info, which there is no stepping through.
+                break;
+            };
+            let is_new_line = Some((row.path.clone(), row.line)) != start_line;
+            if is_new_line && row.address == pc {
+                break;
+            }
+        }
+        return self.send_stopped_event("step", None);
+    }
+
+    // Runs until the selected frame returns to its caller.
+    unsafe fn step_out(&mut self) -> io::Result<()> {
+        let sp = self.read_registers().sp;
+        let tracee = self.tracee.as_ref().unwrap();
+        let Some(caller) = unwind(tracee).get(1).map(|frame| frame.pc) else {
+            return self.send_stopped_event("step", None);
+        };
+        if self.run_to(caller, sp)? {
+            self.send_stopped_event("step", None)?;
+        }
+        return Ok(());
+    }
+
+    // Runs until a return to `address`, where the stack pointer is back to at least `sp`; deeper
+    // recursive calls that return there do not count. Returns false if the tracee stopped for
+    // another reason on the way, which has then been reported.
+    unsafe fn run_to(&mut self, address: u64, sp: u64) -> io::Result<bool> {
+        let is_temporary = self
+            .tracee
+            .as_ref()
+            .is_some_and(|tracee| tracee.breakpoints().find(address).is_none());
+        if is_temporary && !self.insert_breakpoint(address) {
+            self.report_stop("step")?;
+            return Ok(false);
+        }
+
+        let mut is_reached = false;
+        loop {
+            if !self.step_instruction() {
+                break;
+            }
+            // E.g. stepping out from a `ret`.
+            let regs = self.read_registers();
+            if regs.pc == address && regs.sp >= sp {
+                is_reached = true;
+                break;
+            }
+            let tracee = self.tracee.as_mut().unwrap();
+            tracee.resume();
+            tracee.wait_on_signal();
+            if tracee.status() != TraceeStatus::Stopped {
+                break;
+            }
+
+            let regs = tracee.read_general_purpose_registers();
+            let is_at_breakpoint = matches!(
+                tracee.trap(),
+                Some(Trap::Breakpoint { address: trap_address }) if trap_address == address
+            );
+            if !is_at_breakpoint || regs.pc != address {
+                break;
+            }
+            if regs.sp >= sp {
+                is_reached = true;
+                break;
+            }
+        }
+
+        let is_alive = self
+            .tracee
+            .as_ref()
+            .is_some_and(|tracee| tracee.status() == TraceeStatus::Stopped);
+        if is_temporary && is_alive {
+            let tracee = self.tracee.as_mut().unwrap();
+            if let Some(id) = tracee.breakpoints().find(address).map(Breakpoint::id) {
+                if let Err(err) = tracee.delete_breakpoint(id) {
+                    println!("{}", err);
+                }
+            }
+        }
+        if !is_reached {
+            self.report_stop("step")?;
+        }
+        return Ok(is_reached);
+    }
+
+    unsafe fn read_registers(&self) -> libc::user_regs_struct {
+        return self
+            .tracee
+            .as_ref()
+            .unwrap()
+            .read_general_purpose_registers();
+    }
+
+    // Tells the client why the tracee stopped, or that it is gone.
+    unsafe fn report_stop(&mut self, default_reason: &str) -> io::Result<()> {
+        let Some(tracee) = self.tracee.as_ref() else {
+            return Ok(());
+        };
+        self.frames.clear();
+
+        match tracee.exit_status() {
+            Some(WaitStatus::Exited(code)) => {
+                self.send_event("exited", Json::object(vec![("exitCode", Json::from(code))]))?;
+                return self.send_event("terminated", Json::Null);
+            }
+            Some(WaitStatus::Signaled(signal)) => {
+                // Like a shell reports it.
+                let body = Json::object(vec![("exitCode", Json::from(128 + signal))]);
+                self.send_event("exited", body)?;
+                return self.send_event("terminated", Json::Null);
+            }
+            _ => {}
+        }
+
+        let (reason, description) = match tracee.trap() {
+            Some(Trap::Breakpoint { address }) if tracee.breakpoints().find(address).is_some() => {
+                ("breakpoint", None)
+            }
+            _ => match tracee.read_siginfo() {
+                Some(info) if info.si_signo != libc::SIGTRAP => {
+                    ("exception", Some(signal_name(info.si_signo)))
+                }
+                _ if self.pausing => ("pause", None),
+                _ => (default_reason, None),
+            },
+        };
+        self.pausing = false;
+        return self.send_stopped_event(reason, description);
+    }
+
+    unsafe fn send_stopped_event(
+        &mut self,
+        reason: &str,
+        description: Option<String>,
+    ) -> io::Result<()> {
+        let tid = self
+            .tracee
+            .as_ref()
+            .map_or(0, |tracee| tracee.selected_tid());
+        let mut body = vec![
+            ("reason", Json::from(reason)),
+            ("threadId", Json::from(tid)),
+            ("allThreadsStopped", Json::from(true)),
+        ];
+        if let Some(description) = description {
+            body.push(("text", Json::from(description)));
+        }
+        return self.send_event("stopped", Json::object(body));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::{frame_message, is_same_source, read_message, resolve_source_line};
+    use crate::{dwarf::LineRow, json::Json};
+
+    #[test]
+    fn read_message_reads_framed_content() {
+        let message = Json::object(vec![("seq", Json::from(1))]);
+        let framed = format!("{}{}", frame_message(&message), frame_message(&message));
+        let mut reader = Cursor::new(framed.into_bytes());
+        assert_eq!(read_message(&mut reader).unwrap().unwrap(), "{\"seq\":1}");
+        assert_eq!(read_message(&mut reader).unwrap().unwrap(), "{\"seq\":1}");
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+
+        let mut reader = Cursor::new(b"Content-Type: x\r\n\r\n{}".to_vec());
+        assert!(read_message(&mut reader).is_err());
+    }
+
+    #[test]
+    fn resolve_source_line_moves_to_next_line_with_code() {
+        let row = |address, line| LineRow {
+            address,
+            path: "src/main.c".to_string(),
+            line,
+        };
+        let rows = vec![
+            row(0x1000, 3),
+            row(0x1008, 5),
+            row(0x1010, 4),
+            row(0x1018, 5),
+        ];
+        assert_eq!(
+            resolve_source_line(&rows, "/home/me/project/src/main.c", 5),
+            Some((5, 0x1008))
+        );
+        assert_eq!(
+            resolve_source_line(&rows, "/home/me/project/src/main.c", 2),
+            Some((3, 0x1000))
+        );
+        assert_eq!(
+            resolve_source_line(&rows, "/home/me/project/src/main.c", 6),
+            None
+        );
+        assert_eq!(resolve_source_line(&rows, "/other/main.c", 5), None);
+    }
+
+    #[test]
+    fn is_same_source_matches_relative_paths_by_suffix() {
+        assert!(is_same_source("/src/a.c", "/src/a.c"));
+        assert!(is_same_source("a.c", "/src/a.c"));
+        assert!(!is_same_source("a.c", "/src/ba.c"));
+        assert!(!is_same_source("/other/a.c", "/src/a.c"));
+    }
+}
@@ -0,0 +1,275 @@
+use capstone::{arch, prelude::BuildsCapstone, Capstone, InsnGroupType};
+
+// The longest instruction of the architecture, which bounds how much code is read to decode a
+// number of instructions.
+#[cfg(target_arch = "aarch64")]
+pub const MAX_INSTRUCTION_LEN: usize = 4;
+#[cfg(target_arch = "x86_64")]
+pub const MAX_INSTRUCTION_LEN: usize = 15;
+
+// The instruction that software breakpoints are made of: `brk #0` on aarch64, `int3` on x86_64.
+#[cfg(target_arch = "aarch64")]
+pub const BREAKPOINT_INSTRUCTION: &[u8] = &[0x00, 0x00, 0x20, 0xd4];
+#[cfg(target_arch = "x86_64")]
+pub const BREAKPOINT_INSTRUCTION: &[u8] = &[0xcc];
+
+// The instruction that makes a system call: `svc #0` on aarch64, `syscall` on x86_64.
+#[cfg(target_arch = "aarch64")]
+pub const SYSCALL_INSTRUCTION: &[u8] = &[0x01, 0x00, 0x00, 0xd4];
+#[cfg(target_arch = "x86_64")]
+pub const SYSCALL_INSTRUCTION: &[u8] = &[0x0f, 0x05];
+
+// The address of the instruction `count` instructions before the one at `address`. Instructions
+// have a fixed length, so they can be found without decoding any.
+#[cfg(target_arch = "aarch64")]
+pub fn preceding_instruction_address(address: u64, count: usize) -> u64 {
+    return address.saturating_sub((count * MAX_INSTRUCTION_LEN) as u64);
+}
+
+// Instructions have variable lengths and cannot be decoded backwards reliably, so the instructions
+// before `address` are not known.
+#[cfg(target_arch = "x86_64")]
+pub fn preceding_instruction_address(address: u64, _count: usize) -> u64 {
+    return address;
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Instruction {
+    pub address: u64,
+    pub bytes: Vec<u8>,
+    // The assembly, e.g. "sub sp, sp, #0x10".
+    pub text: String,
+    // Where the instruction jumps or calls to, if it is a direct branch.
+    pub branch_target: Option<u64>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DisassemblyError {
+    #[error("failed to disassemble: {0}")]
+    Capstone(String),
+}
+
+// Decodes up to `count` instructions of `code`, which lives at `address`. Decoding stops early at
+// bytes that are not a valid instruction.
+pub fn disassemble(
+    code: &[u8],
+    address: u64,
+    count: usize,
+) -> Result<Vec<Instruction>, DisassemblyError> {
+    let capstone = disassembler().map_err(|err| DisassemblyError::Capstone(err.to_string()))?;
+    let instructions = capstone
+        .disasm_count(code, address, count)
+        .map_err(|err| DisassemblyError::Capstone(err.to_string()))?;
+    return Ok(instructions
+        .iter()
+        .map(|instruction| {
+            let mnemonic = instruction.mnemonic().unwrap_or("");
+            let operands = instruction.op_str().unwrap_or("");
+            let text = match operands {
+                "" => mnemonic.to_string(),
+                operands => format!("{} {}", mnemonic, operands),
+            };
+            let is_branch = capstone.insn_detail(&instruction).is_ok_and(|detail| {
+                detail.groups().any(|group| {
+                    group.0 == InsnGroupType::CS_GRP_JUMP as u8
+                        || group.0 == InsnGroupType::CS_GRP_CALL as u8
+                })
+            });
+            return Instruction {
+                address: instruction.address(),
+                bytes: instruction.bytes().to_vec(),
+                text,
+                branch_target: match is_branch {
+                    true => parse_branch_target(operands),
+                    false => None,
+                },
+            };
+        })
+        .collect());
+}
+
+// Parses the target of a direct branch out of its operands, where it comes last, e.g. "#0x4005d0"
+// of "cbz x0, #0x4005d0". Indirect branches have a register there instead.
+fn parse_branch_target(operands: &str) -> Option<u64> {
+    let target = operands.rsplit(", ").next()?;
+    let hex = target.trim_start_matches('#').strip_prefix("0x")?;
+    return u64::from_str_radix(hex, 16).ok();
+}
+
+#[cfg(target_arch = "aarch64")]
+fn disassembler() -> capstone::CsResult<Capstone> {
+    return Capstone::new()
+        .arm64()
+        .mode(arch::arm64::ArchMode::Arm)
+        .detail(true)
+        .build();
+}
+
+#[cfg(target_arch = "x86_64")]
+fn disassembler() -> capstone::CsResult<Capstone> {
+    return Capstone::new()
+        .x86()
+        .mode(arch::x86::ArchMode::Mode64)
+        .detail(true)
+        .build();
+}
+
+// Formats an instruction as a line of a listing, marking the current instruction with an arrow
+// and breakpoints with an asterisk. Branch targets are described by `describe_target`, e.g. by
+// the function they are in.
+pub fn format_instruction(
+    instruction: &Instruction,
+    is_pc: bool,
+    is_breakpoint: bool,
+    describe_target: impl Fn(u64) -> Option<String>,
+) -> String {
+    let bytes = instruction
+        .bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let target = match instruction.branch_target.and_then(describe_target) {
+        None => String::new(),
+        Some(description) => format!(" <{}>", description),
+    };
+    return format!(
+        "{} {} {:#018x}  {:<width$}  {}{}",
+        if is_pc { "=>" } else { "  " },
+        if is_breakpoint { "*" } else { " " },
+        instruction.address,
+        bytes,
+        instruction.text,
+        target,
+        width = MAX_INSTRUCTION_LEN * 3 - 1,
+    );
+}
+
+// Writes the original contents of patched words (e.g. breakpoints) back into `code`, which was
+// read from `address`, so that the code is shown as the program has it rather than with traps in
+// it. Returns the addresses of the bytes that were patched.
+//
+// Patches are given in the order they were made. A word that was patched twice has the first
+// patch in the "original" of the second, so they are undone from last to first.
+pub fn restore_patched_bytes(code: &mut [u8], address: u64, patches: &[(u64, u64)]) -> Vec<u64> {
+    let mut patched_addresses = vec![];
+    for (patch_address, original) in patches.iter().rev() {
+        for (i, original_byte) in original.to_ne_bytes().iter().enumerate() {
+            let byte_address = patch_address.wrapping_add(i as u64);
+            let Some(offset) = byte_address.checked_sub(address) else {
+                continue;
+            };
+            let Some(byte) = code.get_mut(offset as usize) else {
+                continue;
+            };
+            if *byte != *original_byte {
+                *byte = *original_byte;
+                patched_addresses.push(byte_address);
+            }
+        }
+    }
+    return patched_addresses;
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        disassemble, format_instruction, parse_branch_target, restore_patched_bytes, Instruction,
+    };
+
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn disassemble_decodes_instructions() {
+        let code = [0xff, 0xff, 0xff, 0x97, 0xc0, 0x03, 0x5f, 0xd6];
+        assert_eq!(
+            disassemble(&code, 0x1000, 10).unwrap(),
+            vec![
+                Instruction {
+                    address: 0x1000,
+                    bytes: vec![0xff, 0xff, 0xff, 0x97],
+                    text: "bl #0xffc".to_string(),
+                    branch_target: Some(0xffc),
+                },
+                Instruction {
+                    address: 0x1004,
+                    bytes: vec![0xc0, 0x03, 0x5f, 0xd6],
+                    text: "ret".to_string(),
+                    branch_target: None,
+                },
+            ]
+        );
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn disassemble_decodes_instructions() {
+        let code = [0xe8, 0xfb, 0xff, 0xff, 0xff, 0xc3];
+        assert_eq!(
+            disassemble(&code, 0x1000, 10).unwrap(),
+            vec![
+                Instruction {
+                    address: 0x1000,
+                    bytes: vec![0xe8, 0xfb, 0xff, 0xff, 0xff],
+                    text: "call 0x1000".to_string(),
+                    branch_target: Some(0x1000),
+                },
+                Instruction {
+                    address: 0x1005,
+                    bytes: vec![0xc3],
+                    text: "ret".to_string(),
+                    branch_target: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn format_instruction_marks_pc_and_breakpoints() {
+        let instruction = Instruction {
+            address: 0x1000,
+            bytes: vec![0xc3],
+            text: "ret".to_string(),
+            branch_target: None,
+        };
+        let line = format_instruction(&instruction, true, true, |_| None);
+        assert!(line.starts_with("=> * 0x0000000000001000  c3 "));
+        assert!(line.ends_with("  ret"));
+        assert!(format_instruction(&instruction, false, false, |_| None).starts_with("     0x"));
+    }
+
+    #[test]
+    fn format_instruction_describes_branch_targets() {
+        let instruction = Instruction {
+            address: 0x1000,
+            bytes: vec![0x94, 0x00, 0x00, 0x10],
+            text: "bl #0x1040".to_string(),
+            branch_target: Some(0x1040),
+        };
+        let line = format_instruction(&instruction, false, false, |address| {
+            return Some(format!("helper@{:#x}", address));
+        });
+        assert!(line.ends_with("bl #0x1040 <helper@0x1040>"));
+    }
+
+    #[test]
+    fn parse_branch_target_ignores_indirect_branches() {
+        assert_eq!(parse_branch_target("x0, #0x4005d0"), Some(0x4005d0));
+        assert_eq!(parse_branch_target("0x401000"), Some(0x401000));
+        assert_eq!(parse_branch_target("x16"), None);
+        assert_eq!(parse_branch_target("qword ptr [rip + 0x2fe2]"), None);
+    }
+
+    #[test]
+    fn restore_patched_bytes_undoes_patches_in_reverse() {
+        let original = u64::from_ne_bytes([1, 2, 3, 4, 5, 6, 7, 8]);
+        let first_patch = u64::from_ne_bytes([0xcc, 2, 3, 4, 5, 6, 7, 8]);
+        let mut code = [0xcc, 2, 3, 0xcc];
+        let patched_addresses = restore_patched_bytes(
+            &mut code,
+            0x1000,
+            &[(0x1000, original), (0x1000, first_patch)],
+        );
+        assert_eq!(code, [1, 2, 3, 4]);
+        assert_eq!(patched_addresses, vec![0x1003, 0x1000]);
+    }
+}
@@ -0,0 +1,548 @@
+use crate::elf::{ElfError, ElfFile};
+
+// Standard opcodes of line number programs (see section 6.2.5.2 of DWARF 5).
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_CONST_ADD_PC: u8 = 8;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+
+// Extended opcodes, which follow a zero byte.
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+const DW_LNE_DEFINE_FILE: u8 = 3;
+
+// What the fields of directory and file entries hold, from DWARF 5 on.
+const DW_LNCT_PATH: u64 = 1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 2;
+
+// Forms that the fields of directory and file entries are encoded in.
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum DwarfError {
+    #[error(transparent)]
+    Elf(#[from] ElfError),
+    #[error("malformed DWARF: {0}")]
+    Malformed(&'static str),
+    #[error("unsupported DWARF: {0}")]
+    Unsupported(&'static str),
+}
+
+// A row of a line table: the first instruction of a source line, or of part of one.
+#[derive(PartialEq, Clone, Debug)]
+pub struct LineRow {
+    // The address of the instruction, relative to the load bias.
+    pub address: u64,
+    pub path: String,
+    pub line: u64,
+}
+
+// Reads the line tables of every compilation unit of a file, which map instructions to the source
+// lines they were compiled from. Files without debug info have none.
+pub fn read_line_rows(elf: &ElfFile) -> Result<Vec<LineRow>, DwarfError> {
+    let Some(debug_line) = elf.section_data(".debug_line")? else {
+        return Ok(vec![]);
+    };
+    let strings = Strings {
+        debug_str: elf.section_data(".debug_str")?.unwrap_or_default(),
+        debug_line_str: elf.section_data(".debug_line_str")?.unwrap_or_default(),
+    };
+
+    let mut rows = vec![];
+    let mut reader = Reader::new(debug_line);
+    while !reader.is_empty() {
+        rows.extend(parse_line_program(&mut reader, &strings)?);
+    }
+    rows.sort_by_key(|row| row.address);
+    return Ok(rows);
+}
+
+// The string sections that entries of line program headers may refer to.
+struct Strings<'a> {
+    debug_str: &'a [u8],
+    debug_line_str: &'a [u8],
+}
+
+// The header fields that drive the line number state machine.
+struct LineProgramHeader {
+    version: u16,
+    minimum_instruction_length: u8,
+    line_base: i8,
+    line_range: u8,
+    opcode_base: u8,
+    standard_opcode_lengths: Vec<u8>,
+    paths: Vec<String>,
+}
+
+// Parses one line number program, leaving the reader at the start of the next.
+fn parse_line_program(reader: &mut Reader, strings: &Strings) -> Result<Vec<LineRow>, DwarfError> {
+    let (unit_length, is_dwarf64) = reader.initial_length()?;
+    let mut unit = Reader::new(reader.bytes(unit_length as usize)?);
+    let version = unit.u16()?;
+    if !(2..=5).contains(&version) {
+        return Err(DwarfError::Unsupported("line table version"));
+    }
+    if version >= 5 {
+        // The address and segment selector sizes.
+        unit.bytes(2)?;
+    }
+    let header_length = unit.offset(is_dwarf64)?;
+    let program_start = unit.position + header_length as usize;
+
+    let mut header = LineProgramHeader {
+        version,
+        minimum_instruction_length: unit.u8()?,
+        line_base: 0,
+        line_range: 0,
+        opcode_base: 0,
+        standard_opcode_lengths: vec![],
+        paths: vec![],
+    };
+    if version >= 4 {
+        // The maximum number of operations per instruction, which is only for VLIW machines.
+        unit.u8()?;
+    }
+    // Whether rows are statements by default.
+    unit.u8()?;
+    header.line_base = unit.u8()? as i8;
+    header.line_range = unit.u8()?;
+    header.opcode_base = unit.u8()?;
+    if header.line_range == 0 || header.opcode_base == 0 {
+        return Err(DwarfError::Malformed("line table header"));
+    }
+    header.standard_opcode_lengths = unit.bytes(header.opcode_base as usize - 1)?.to_vec();
+    header.paths = match version {
+        5 => parse_v5_paths(&mut unit, strings, is_dwarf64)?,
+        _ => parse_paths(&mut unit)?,
+    };
+
+    unit.position = program_start;
+    return run_line_program(&mut unit, &header);
+}
+
+// Parses the include directories and file names of a line program header before DWARF 5, where
+// file 1 is the first file.
+fn parse_paths(unit: &mut Reader) -> Result<Vec<String>, DwarfError> {
+    let mut directories = vec![String::new()];
+    loop {
+        let directory = unit.c_string()?;
+        if directory.is_empty() {
+            break;
+        }
+        directories.push(directory);
+    }
+
+    let mut paths = vec![String::new()];
+    loop {
+        let name = unit.c_string()?;
+        if name.is_empty() {
+            break;
+        }
+        let directory_index = unit.uleb128()? as usize;
+        // The modification time and length of the file.
+        unit.uleb128()?;
+        unit.uleb128()?;
+        paths.push(join_path(directories.get(directory_index), name));
+    }
+    return Ok(paths);
+}
+
+// Parses the directory and file name entries of a DWARF 5 line program header, whose fields are
+// described by formats, and where file 0 is the primary source file.
+fn parse_v5_paths(
+    unit: &mut Reader,
+    strings: &Strings,
+    is_dwarf64: bool,
+) -> Result<Vec<String>, DwarfError> {
+    let mut directories = vec![];
+    for entry in parse_v5_entries(unit, strings, is_dwarf64)? {
+        directories.push(entry.path);
+    }
+
+    let mut paths = vec![];
+    for entry in parse_v5_entries(unit, strings, is_dwarf64)? {
+        paths.push(join_path(
+            directories.get(entry.directory_index),
+            entry.path,
+        ));
+    }
+    return Ok(paths);
+}
+
+struct EntryFields {
+    path: String,
+    directory_index: usize,
+}
+
+fn parse_v5_entries(
+    unit: &mut Reader,
+    strings: &Strings,
+    is_dwarf64: bool,
+) -> Result<Vec<EntryFields>, DwarfError> {
+    let format_count = unit.u8()?;
+    let mut formats = vec![];
+    for _ in 0..format_count {
+        formats.push((unit.uleb128()?, unit.uleb128()?));
+    }
+
+    let count = unit.uleb128()?;
+    let mut entries = vec![];
+    for _ in 0..count {
+        let mut entry = EntryFields {
+            path: String::new(),
+            directory_index: 0,
+        };
+        for (content_type, form) in &formats {
+            let value = read_form(unit, *form, strings, is_dwarf64)?;
+            match (*content_type, value) {
+                (DW_LNCT_PATH, FormValue::String(path)) => entry.path = path,
+                (DW_LNCT_DIRECTORY_INDEX, FormValue::Number(index)) => {
+                    entry.directory_index = index as usize;
+                }
+                _ => {}
+            }
+        }
+        entries.push(entry);
+    }
+    return Ok(entries);
+}
+
+enum FormValue {
+    String(String),
+    Number(u64),
+    Other,
+}
+
+fn read_form(
+    unit: &mut Reader,
+    form: u64,
+    strings: &Strings,
+    is_dwarf64: bool,
+) -> Result<FormValue, DwarfError> {
+    return Ok(match form {
+        DW_FORM_STRING => FormValue::String(unit.c_string()?),
+        DW_FORM_LINE_STRP => {
+            let offset = unit.offset(is_dwarf64)? as usize;
+            FormValue::String(string_at(strings.debug_line_str, offset)?)
+        }
+        DW_FORM_STRP => {
+            let offset = unit.offset(is_dwarf64)? as usize;
+            FormValue::String(string_at(strings.debug_str, offset)?)
+        }
+        DW_FORM_UDATA => FormValue::Number(unit.uleb128()?),
+        DW_FORM_DATA1 => FormValue::Number(unit.u8()? as u64),
+        DW_FORM_DATA2 => FormValue::Number(unit.u16()? as u64),
+        DW_FORM_DATA4 => FormValue::Number(unit.u32()? as u64),
+        DW_FORM_DATA8 => FormValue::Number(unit.u64()?),
+        DW_FORM_DATA16 => {
+            unit.bytes(16)?;
+            FormValue::Other
+        }
+        DW_FORM_BLOCK => {
+            let len = unit.uleb128()? as usize;
+            unit.bytes(len)?;
+            FormValue::Other
+        }
+        _ => return Err(DwarfError::Unsupported("form in line table header")),
+    });
+}
+
+// Runs the line number state machine over the opcodes of a program, collecting its rows.
+fn run_line_program(
+    unit: &mut Reader,
+    header: &LineProgramHeader,
+) -> Result<Vec<LineRow>, DwarfError> {
+    let initial_file: u64 = match header.version {
+        5 => 0,
+        _ => 1,
+    };
+    let mut paths = header.paths.clone();
+    let mut rows = vec![];
+    let mut address: u64 = 0;
+    let mut file = initial_file;
+    let mut line: u64 = 1;
+    let mut push_row = |address: u64, file: u64, line: u64| {
+        rows.push((address, file, line));
+    };
+
+    while !unit.is_empty() {
+        let opcode = unit.u8()?;
+        if opcode >= header.opcode_base {
+            let adjusted = opcode - header.opcode_base;
+            address = address.wrapping_add(
+                header.minimum_instruction_length as u64 * (adjusted / header.line_range) as u64,
+            );
+            line = line.wrapping_add_signed(
+                header.line_base as i64 + (adjusted % header.line_range) as i64,
+            );
+            push_row(address, file, line);
+            continue;
+        }
+
+        match opcode {
+            0 => {
+                let len = unit.uleb128()? as usize;
+                let mut instruction = Reader::new(unit.bytes(len)?);
+                match instruction.u8()? {
+                    DW_LNE_END_SEQUENCE => {
+                        address = 0;
+                        file = initial_file;
+                        line = 1;
+                    }
+                    DW_LNE_SET_ADDRESS => {
+                        address = match len - 1 {
+                            4 => instruction.u32()? as u64,
+                            8 => instruction.u64()?,
+                            _ => return Err(DwarfError::Unsupported("address size")),
+                        };
+                    }
+                    DW_LNE_DEFINE_FILE => {
+                        let name = instruction.c_string()?;
+                        paths.push(name);
+                    }
+                    _ => {}
+                }
+            }
+            DW_LNS_COPY => push_row(address, file, line),
+            DW_LNS_ADVANCE_PC => {
+                let advance = unit.uleb128()?;
+                address = address.wrapping_add(header.minimum_instruction_length as u64 * advance);
+            }
+            DW_LNS_ADVANCE_LINE => line = line.wrapping_add_signed(unit.sleb128()?),
+            DW_LNS_SET_FILE => file = unit.uleb128()?,
+            DW_LNS_CONST_ADD_PC => {
+                let adjusted = 255 - header.opcode_base;
+                address = address.wrapping_add(
+                    header.minimum_instruction_length as u64
+                        * (adjusted / header.line_range) as u64,
+                );
+            }
+            DW_LNS_FIXED_ADVANCE_PC => address = address.wrapping_add(unit.u16()? as u64),
+            _ => {
+                // Every other standard opcode, known or not, is skipped over by its operands.
+                for _ in 0..header.standard_opcode_lengths[opcode as usize - 1] {
+                    unit.uleb128()?;
+                }
+            }
+        }
+    }
+
+    return Ok(rows
+        .into_iter()
+        .filter_map(|(address, file, line)| {
+            let path = paths.get(file as usize)?;
+            return Some(LineRow {
+                address,
+                path: path.clone(),
+                line,
+            });
+        })
+        .collect());
+}
+
+fn join_path(directory: Option<&String>, name: String) -> String {
+    return match directory {
+        Some(directory) if !directory.is_empty() && !name.starts_with('/') => {
+            format!("{}/{}", directory, name)
+        }
+        _ => name,
+    };
+}
+
+fn string_at(section: &[u8], offset: usize) -> Result<String, DwarfError> {
+    let mut reader = Reader::new(section);
+    reader.position = offset;
+    return reader.c_string();
+}
+
+// Reads the little-endian encodings of DWARF out of a section.
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    pub(crate) position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Reader<'a> {
+        return Reader { data, position: 0 };
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        return self.position >= self.data.len();
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], DwarfError> {
+        let bytes = self
+            .data
+            .get(self.position..self.position.saturating_add(len))
+            .ok_or(DwarfError::Malformed("truncated section"))?;
+        self.position += len;
+        return Ok(bytes);
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, DwarfError> {
+        return Ok(self.bytes(1)?[0]);
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, DwarfError> {
+        return Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()));
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, DwarfError> {
+        return Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()));
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, DwarfError> {
+        return Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()));
+    }
+
+    // Reads the length of a unit, and whether it is in the 64-bit DWARF format.
+    pub(crate) fn initial_length(&mut self) -> Result<(u64, bool), DwarfError> {
+        return match self.u32()? {
+            0xffff_ffff => Ok((self.u64()?, true)),
+            len => Ok((len as u64, false)),
+        };
+    }
+
+    // Reads an offset into a section, whose size depends on the DWARF format.
+    pub(crate) fn offset(&mut self, is_dwarf64: bool) -> Result<u64, DwarfError> {
+        return match is_dwarf64 {
+            true => self.u64(),
+            false => Ok(self.u32()? as u64),
+        };
+    }
+
+    pub(crate) fn uleb128(&mut self) -> Result<u64, DwarfError> {
+        let mut value: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    pub(crate) fn sleb128(&mut self) -> Result<i64, DwarfError> {
+        let mut value: i64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    pub(crate) fn c_string(&mut self) -> Result<String, DwarfError> {
+        let rest = self
+            .data
+            .get(self.position..)
+            .ok_or(DwarfError::Malformed("string out of bounds"))?;
+        let len = rest
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or(DwarfError::Malformed("unterminated string"))?;
+        self.position += len + 1;
+        return Ok(String::from_utf8_lossy(&rest[..len]).to_string());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_line_program, read_line_rows, LineRow, Reader, Strings};
+    use crate::{elf::ElfFile, procfs::read_exe_path};
+
+    #[test]
+    fn reader_decodes_leb128() {
+        let mut reader = Reader::new(&[0xe5, 0x8e, 0x26, 0x7f, 0x80, 0x7f]);
+        assert_eq!(reader.uleb128().unwrap(), 624485);
+        assert_eq!(reader.sleb128().unwrap(), -1);
+        assert_eq!(reader.sleb128().unwrap(), -128);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn parse_line_program_runs_version_4_program() {
+        let mut header = vec![];
+        header.extend_from_slice(&[4, 1, 1, 0xfb, 14, 13]);
+        header.extend_from_slice(&[0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1]);
+        header.extend_from_slice(b"/src\0\0");
+        header.extend_from_slice(b"main.c\0\x01\0\0\0");
+
+        let mut program = vec![];
+        // Set the address to 0x1000, then emit rows for lines 1, 3, and 4.
+        program.extend_from_slice(&[0, 9, 2, 0x00, 0x10, 0, 0, 0, 0, 0, 0]);
+        program.push(1);
+        program.extend_from_slice(&[3, 2]);
+        program.extend_from_slice(&[2, 1]);
+        program.push(1);
+        // A special opcode advancing the address by 4 and the line by 1.
+        program.push(13 + 6 + 14);
+        program.extend_from_slice(&[0, 1, 1]);
+
+        let mut unit = vec![];
+        unit.extend_from_slice(&4u16.to_le_bytes());
+        unit.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        unit.extend_from_slice(&header);
+        unit.extend_from_slice(&program);
+        let mut section = (unit.len() as u32).to_le_bytes().to_vec();
+        section.extend_from_slice(&unit);
+
+        let strings = Strings {
+            debug_str: &[],
+            debug_line_str: &[],
+        };
+        let mut reader = Reader::new(&section);
+        let rows = parse_line_program(&mut reader, &strings).unwrap();
+        assert!(reader.is_empty());
+        assert_eq!(
+            rows,
+            vec![
+                LineRow {
+                    address: 0x1000,
+                    path: "/src/main.c".to_string(),
+                    line: 1,
+                },
+                LineRow {
+                    address: 0x1004,
+                    path: "/src/main.c".to_string(),
+                    line: 3,
+                },
+                LineRow {
+                    address: 0x1008,
+                    path: "/src/main.c".to_string(),
+                    line: 4,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn read_line_rows_of_current_executable_include_this_file() {
+        let path = read_exe_path(std::process::id() as libc::pid_t);
+        let rows = read_line_rows(&ElfFile::read(&path).unwrap()).unwrap();
+        assert!(rows.iter().any(|row| row.path.ends_with("src/dwarf.rs")));
+    }
+}
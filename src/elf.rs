@@ -0,0 +1,520 @@
+use std::{fs, path::Path};
+
+use crate::procfs::MemoryMapping;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const SHT_SYMTAB: u32 = 2;
+const SHT_DYNSYM: u32 = 11;
+const STT_FUNC: u8 = 2;
+const SHN_UNDEF: u16 = 0;
+const SHF_COMPRESSED: u64 = 0x800;
+const NT_GNU_BUILD_ID: u32 = 3;
+
+// Sizes of the 64-bit structures, as laid out in the file.
+const SECTION_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const SYMBOL_SIZE: usize = 24;
+const RELA_SIZE: usize = 24;
+
+// Every PLT stub is 16 bytes on both AArch64 and x86-64.
+const PLT_ENTRY_SIZE: u64 = 16;
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum ElfError {
+    #[error("failed to read {path}: {message}")]
+    Read { path: String, message: String },
+    #[error("malformed ELF file: {0}")]
+    Malformed(&'static str),
+    #[error("unsupported ELF file: {0}")]
+    Unsupported(&'static str),
+}
+
+// A stub in the procedure linkage table, through which calls to a dynamically linked function are
+// made.
+#[derive(PartialEq, Clone, Debug)]
+pub struct PltEntry {
+    pub name: String,
+    // The address of the stub, relative to the load bias.
+    pub address: u64,
+    // The address of the GOT slot that the stub jumps through, relative to the load bias. Once
+    // the function is resolved, the slot holds its address.
+    pub got_address: u64,
+}
+
+// Where an ELF file asks to be loaded, which is all that its load bias depends on.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct LoadLayout {
+    pub is_fixed_address: bool,
+    pub min_load_address: Option<u64>,
+    // The entry point, relative to the load bias.
+    pub entry: u64,
+    // Where the dynamic section is, relative to the load bias, unless the file is linked
+    // statically.
+    pub dynamic_address: Option<u64>,
+}
+
+// A function defined in an ELF file.
+#[derive(PartialEq, Clone, Debug)]
+pub struct FunctionSymbol {
+    pub name: String,
+    // The entry point of the function, relative to the load bias.
+    pub address: u64,
+    pub size: u64,
+}
+
+struct Section {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    address: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+}
+
+// A little-endian ELF64 file, such as an executable or shared library.
+pub struct ElfFile {
+    data: Vec<u8>,
+}
+
+impl ElfFile {
+    pub fn read(path: &Path) -> Result<ElfFile, ElfError> {
+        return match fs::read(path) {
+            Err(err) => Err(ElfError::Read {
+                path: path.display().to_string(),
+                message: err.to_string(),
+            }),
+            Ok(data) => ElfFile::parse(data),
+        };
+    }
+
+    pub fn parse(data: Vec<u8>) -> Result<ElfFile, ElfError> {
+        if data.len() < 64 || &data[..4] != ELF_MAGIC {
+            return Err(ElfError::Malformed("missing ELF header"));
+        }
+        if data[4] != ELFCLASS64 || data[5] != ELFDATA2LSB {
+            return Err(ElfError::Unsupported(
+                "only little-endian ELF64 is supported",
+            ));
+        }
+        return Ok(ElfFile { data });
+    }
+
+    fn u16_at(&self, offset: usize) -> Result<u16, ElfError> {
+        let bytes = self.data.get(offset..offset + 2);
+        let bytes = bytes.ok_or(ElfError::Malformed("truncated file"))?;
+        return Ok(u16::from_le_bytes(bytes.try_into().unwrap()));
+    }
+
+    fn u32_at(&self, offset: usize) -> Result<u32, ElfError> {
+        let bytes = self.data.get(offset..offset + 4);
+        let bytes = bytes.ok_or(ElfError::Malformed("truncated file"))?;
+        return Ok(u32::from_le_bytes(bytes.try_into().unwrap()));
+    }
+
+    fn u64_at(&self, offset: usize) -> Result<u64, ElfError> {
+        let bytes = self.data.get(offset..offset + 8);
+        let bytes = bytes.ok_or(ElfError::Malformed("truncated file"))?;
+        return Ok(u64::from_le_bytes(bytes.try_into().unwrap()));
+    }
+
+    // Reads a NUL-terminated string out of a string table section.
+    fn string_at(&self, table: &Section, offset: u32) -> Result<String, ElfError> {
+        let start = (table.offset + offset as u64) as usize;
+        let end = (table.offset + table.size) as usize;
+        let bytes = self
+            .data
+            .get(start..end)
+            .ok_or(ElfError::Malformed("string out of bounds"))?;
+        let len = bytes
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(bytes.len());
+        return Ok(String::from_utf8_lossy(&bytes[..len]).to_string());
+    }
+
+    fn machine(&self) -> Result<u16, ElfError> {
+        return self.u16_at(18);
+    }
+
+    // Whether the file is loaded at a fixed address, rather than wherever the loader puts it.
+    pub fn is_fixed_address(&self) -> Result<bool, ElfError> {
+        return Ok(self.u16_at(16)? == ET_EXEC);
+    }
+
+    // The address that the program starts at, before it is relocated.
+    pub fn entry(&self) -> Result<u64, ElfError> {
+        return self.u64_at(0x18);
+    }
+
+    fn sections(&self) -> Result<Vec<Section>, ElfError> {
+        let offset = self.u64_at(0x28)? as usize;
+        let count = self.u16_at(0x3c)? as usize;
+
+        let mut sections = Vec::with_capacity(count);
+        for i in 0..count {
+            let header = offset + i * SECTION_HEADER_SIZE;
+            sections.push(Section {
+                name: self.u32_at(header)?,
+                kind: self.u32_at(header + 4)?,
+                flags: self.u64_at(header + 8)?,
+                address: self.u64_at(header + 16)?,
+                offset: self.u64_at(header + 24)?,
+                size: self.u64_at(header + 32)?,
+                link: self.u32_at(header + 40)?,
+            });
+        }
+        return Ok(sections);
+    }
+
+    fn find_section<'a>(
+        &self,
+        sections: &'a [Section],
+        name: &str,
+    ) -> Result<Option<&'a Section>, ElfError> {
+        let names_index = self.u16_at(0x3e)? as usize;
+        let names = sections
+            .get(names_index)
+            .ok_or(ElfError::Malformed("missing section name table"))?;
+        for section in sections {
+            if self.string_at(names, section.name)? == name {
+                return Ok(Some(section));
+            }
+        }
+        return Ok(None);
+    }
+
+    // The contents of the section with the given name, if the file has one.
+    pub fn section_data(&self, name: &str) -> Result<Option<&[u8]>, ElfError> {
+        let sections = self.sections()?;
+        let Some(section) = self.find_section(&sections, name)? else {
+            return Ok(None);
+        };
+        if section.flags & SHF_COMPRESSED != 0 {
+            return Err(ElfError::Unsupported("compressed sections"));
+        }
+
+        let start = section.offset as usize;
+        let data = self
+            .data
+            .get(start..start + section.size as usize)
+            .ok_or(ElfError::Malformed("section out of bounds"))?;
+        return Ok(Some(data));
+    }
+
+    // The address of the section with the given name, relative to the load bias, if the file has
+    // one.
+    pub fn section_address(&self, name: &str) -> Result<Option<u64>, ElfError> {
+        let sections = self.sections()?;
+        return Ok(self
+            .find_section(&sections, name)?
+            .map(|section| section.address));
+    }
+
+    // The build ID that the linker gave the file, which identifies it to debuginfod servers.
+    pub fn build_id(&self) -> Result<Option<Vec<u8>>, ElfError> {
+        let Some(note) = self.section_data(".note.gnu.build-id")? else {
+            return Ok(None);
+        };
+        // The note is its name and descriptor sizes, its type, then the name "GNU" and the ID,
+        // each padded to 4 bytes.
+        let word = |offset: usize| {
+            let bytes = note.get(offset..offset + 4);
+            let bytes = bytes.ok_or(ElfError::Malformed("truncated build ID note"))?;
+            return Ok(u32::from_le_bytes(bytes.try_into().unwrap()));
+        };
+        let name_size = word(0)? as usize;
+        let id_size = word(4)? as usize;
+        if word(8)? != NT_GNU_BUILD_ID {
+            return Ok(None);
+        }
+        let id_offset = 12 + name_size.next_multiple_of(4);
+        let id = note
+            .get(id_offset..id_offset + id_size)
+            .ok_or(ElfError::Malformed("truncated build ID note"))?;
+        return Ok(Some(id.to_vec()));
+    }
+
+    pub fn load_layout(&self) -> Result<LoadLayout, ElfError> {
+        return Ok(LoadLayout {
+            is_fixed_address: self.is_fixed_address()?,
+            min_load_address: self.min_load_address()?,
+            entry: self.entry()?,
+            dynamic_address: self.dynamic_address()?,
+        });
+    }
+
+    // The lowest virtual address that the file asks to be loaded at.
+    pub fn min_load_address(&self) -> Result<Option<u64>, ElfError> {
+        let offset = self.u64_at(0x20)? as usize;
+        let count = self.u16_at(0x38)? as usize;
+
+        let mut min_address = None;
+        for i in 0..count {
+            let header = offset + i * PROGRAM_HEADER_SIZE;
+            if self.u32_at(header)? != PT_LOAD {
+                continue;
+            }
+
+            let address = self.u64_at(header + 16)?;
+            let alignment = self.u64_at(header + 48)?.max(1);
+            let address = address - address % alignment;
+            min_address = Some(min_address.map_or(address, |min: u64| min.min(address)));
+        }
+        return Ok(min_address);
+    }
+
+    // The virtual address of the dynamic section, as the PT_DYNAMIC segment gives it.
+    pub fn dynamic_address(&self) -> Result<Option<u64>, ElfError> {
+        let offset = self.u64_at(0x20)? as usize;
+        let count = self.u16_at(0x38)? as usize;
+        for i in 0..count {
+            let header = offset + i * PROGRAM_HEADER_SIZE;
+            if self.u32_at(header)? == PT_DYNAMIC {
+                return Ok(Some(self.u64_at(header + 16)?));
+            }
+        }
+        return Ok(None);
+    }
+
+    // Lists the PLT stubs of the file, along with the names of the functions they call.
+    pub fn plt_entries(&self) -> Result<Vec<PltEntry>, ElfError> {
+        let sections = self.sections()?;
+        let Some(relocations) = self.find_section(&sections, ".rela.plt")? else {
+            // Statically linked, or linked without lazy binding stubs (e.g. -fno-plt).
+            return Ok(vec![]);
+        };
+
+        // With indirect branch tracking, the stubs that are called live in .plt.sec instead, and
+        // have no header in front of them.
+        let (stubs, header_size) = match self.find_section(&sections, ".plt.sec")? {
+            Some(stubs) => (stubs, 0),
+            None => {
+                let stubs = self.find_section(&sections, ".plt")?;
+                let stubs = stubs.ok_or(ElfError::Malformed("missing .plt section"))?;
+                let header_size = match self.machine()? {
+                    EM_AARCH64 => 32,
+                    EM_X86_64 => 16,
+                    _ => return Err(ElfError::Unsupported("unknown PLT layout")),
+                };
+                (stubs, header_size)
+            }
+        };
+
+        let symbols = sections
+            .get(relocations.link as usize)
+            .ok_or(ElfError::Malformed("missing dynamic symbol table"))?;
+        let names = sections
+            .get(symbols.link as usize)
+            .ok_or(ElfError::Malformed("missing dynamic string table"))?;
+
+        let mut entries = vec![];
+        for i in 0..(relocations.size as usize / RELA_SIZE) {
+            let relocation = relocations.offset as usize + i * RELA_SIZE;
+            let got_address = self.u64_at(relocation)?;
+            let symbol_index = (self.u64_at(relocation + 8)? >> 32) as usize;
+
+            // IFUNC relocations refer to no symbol, but still take up a stub.
+            let name = match symbol_index {
+                0 => continue,
+                _ => {
+                    let symbol = symbols.offset as usize + symbol_index * SYMBOL_SIZE;
+                    self.string_at(names, self.u32_at(symbol)?)?
+                }
+            };
+            entries.push(PltEntry {
+                name,
+                address: stubs.address + header_size + i as u64 * PLT_ENTRY_SIZE,
+                got_address,
+            });
+        }
+        return Ok(entries);
+    }
+
+    // Lists the functions defined in the file, sorted by address. Uses the full symbol table if the
+    // file was not stripped, and the dynamic symbol table otherwise.
+    pub fn function_symbols(&self) -> Result<Vec<FunctionSymbol>, ElfError> {
+        let sections = self.sections()?;
+        let symbols = match sections.iter().find(|section| section.kind == SHT_SYMTAB) {
+            Some(symbols) => symbols,
+            None => match sections.iter().find(|section| section.kind == SHT_DYNSYM) {
+                Some(symbols) => symbols,
+                None => return Ok(vec![]),
+            },
+        };
+        let names = sections
+            .get(symbols.link as usize)
+            .ok_or(ElfError::Malformed("missing symbol string table"))?;
+
+        let mut functions = vec![];
+        for i in 0..(symbols.size as usize / SYMBOL_SIZE) {
+            let symbol = symbols.offset as usize + i * SYMBOL_SIZE;
+            let info = *self
+                .data
+                .get(symbol + 4)
+                .ok_or(ElfError::Malformed("truncated file"))?;
+            if info & 0xf != STT_FUNC || self.u16_at(symbol + 6)? == SHN_UNDEF {
+                continue;
+            }
+
+            functions.push(FunctionSymbol {
+                name: self.string_at(names, self.u32_at(symbol)?)?,
+                address: self.u64_at(symbol + 8)?,
+                size: self.u64_at(symbol + 16)?,
+            });
+        }
+        functions.sort_by(|a, b| a.address.cmp(&b.address).then(a.name.cmp(&b.name)));
+        functions.dedup();
+        return Ok(functions);
+    }
+}
+
+// Describes an address by the function that contains it, e.g. "main+0x14". The functions must be
+// sorted by address.
+pub fn describe_address(functions: &[FunctionSymbol], address: u64) -> Option<String> {
+    let function = functions
+        .iter()
+        .rev()
+        .find(|function| function.address <= address)?;
+    let offset = address - function.address;
+    return match offset {
+        0 => Some(function.name.clone()),
+        _ if offset < function.size => Some(format!("{}+{:#x}", function.name, offset)),
+        _ => None,
+    };
+}
+
+// Computes the difference between the addresses that a file is loaded at in a process and the
+// addresses it asks for, given the process's memory mappings. Returns None if the file is not
+// mapped.
+pub fn load_bias(
+    elf: &ElfFile,
+    path: &str,
+    mappings: &[MemoryMapping],
+) -> Result<Option<u64>, ElfError> {
+    return Ok(elf.load_layout()?.load_bias(path, mappings));
+}
+
+impl LoadLayout {
+    // Computes the load bias of the file at `path` from the process's memory mappings, as
+    // `load_bias` does.
+    pub fn load_bias(&self, path: &str, mappings: &[MemoryMapping]) -> Option<u64> {
+        if self.is_fixed_address {
+            return Some(0);
+        }
+
+        let min_load_address = self.min_load_address?;
+        let first_mapping = mappings
+            .iter()
+            .find(|mapping| mapping.path == path && mapping.offset == 0)?;
+        return Some(first_mapping.start.wrapping_sub(min_load_address));
+    }
+
+    // Computes the load bias of a program from where the kernel says its entry point is, i.e.
+    // AT_ENTRY, which does not depend on the mappings being named after the file.
+    pub fn load_bias_from_entry(&self, entry: u64) -> u64 {
+        return entry.wrapping_sub(self.entry);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{describe_address, load_bias, ElfFile, FunctionSymbol};
+    use crate::procfs::{read_exe_path, read_maps};
+
+    #[test]
+    fn elf_file_parse_rejects_other_files() {
+        assert!(ElfFile::parse(b"#!/bin/sh\n".to_vec()).is_err());
+        assert!(ElfFile::parse(vec![0; 64]).is_err());
+    }
+
+    #[test]
+    fn plt_entries_of_c_program_name_libc_functions() {
+        let elf = ElfFile::read(Path::new("/bin/ls")).unwrap();
+        let entries = elf.plt_entries().unwrap();
+        assert!(entries.iter().all(|entry| !entry.name.is_empty()));
+        assert!(entries
+            .windows(2)
+            .all(|pair| pair[0].address < pair[1].address));
+    }
+
+    #[test]
+    fn function_symbols_of_current_executable_include_its_functions() {
+        let path = read_exe_path(std::process::id() as libc::pid_t);
+        let functions = ElfFile::read(&path).unwrap().function_symbols().unwrap();
+        assert!(functions.iter().any(|function| function
+            .name
+            .contains("function_symbols_of_current_executable")));
+        assert!(functions
+            .windows(2)
+            .all(|pair| pair[0].address <= pair[1].address));
+    }
+
+    #[test]
+    fn build_id_of_current_executable_matches_its_note() {
+        let path = read_exe_path(std::process::id() as libc::pid_t);
+        let elf = ElfFile::read(&path).unwrap();
+        let note = elf.section_data(".note.gnu.build-id").unwrap();
+        match (elf.build_id().unwrap(), note) {
+            (None, None) => {}
+            (Some(id), Some(note)) => {
+                assert!(!id.is_empty());
+                assert!(note.ends_with(&id));
+            }
+            (id, note) => panic!("build ID {:?} for note {:?}", id, note),
+        }
+    }
+
+    #[test]
+    fn describe_address_names_containing_function() {
+        let functions = vec![
+            FunctionSymbol {
+                name: "main".to_string(),
+                address: 0x1000,
+                size: 0x20,
+            },
+            FunctionSymbol {
+                name: "helper".to_string(),
+                address: 0x1040,
+                size: 0x10,
+            },
+        ];
+        assert_eq!(
+            describe_address(&functions, 0x1000),
+            Some("main".to_string())
+        );
+        assert_eq!(
+            describe_address(&functions, 0x1014),
+            Some("main+0x14".to_string())
+        );
+        assert_eq!(describe_address(&functions, 0x1030), None);
+        assert_eq!(describe_address(&functions, 0x800), None);
+    }
+
+    #[test]
+    fn load_bias_of_current_executable_matches_its_mapping() {
+        let pid = std::process::id() as libc::pid_t;
+        let path = read_exe_path(pid);
+        let elf = ElfFile::read(&path).unwrap();
+        let mappings = read_maps(pid).unwrap();
+
+        let bias = load_bias(&elf, path.to_str().unwrap(), &mappings).unwrap();
+        let function_address =
+            load_bias_of_current_executable_matches_its_mapping as *const () as u64;
+        let mapping = mappings
+            .iter()
+            .find(|mapping| (mapping.start..mapping.end).contains(&function_address))
+            .unwrap();
+        assert_eq!(mapping.path, path.to_str().unwrap());
+        assert!(bias.unwrap() <= mapping.start);
+    }
+}
@@ -0,0 +1,89 @@
+// A ptrace event stop, decoded together with its event message.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum PtraceEvent {
+    // The thread created a new thread.
+    Clone { new_tid: libc::pid_t },
+    Fork { child_pid: libc::pid_t },
+    Vfork { child_pid: libc::pid_t },
+    // The vforked child has exec'ed or exited, so the parent owns its address space again.
+    VforkDone { child_pid: libc::pid_t },
+    // The thread exec'ed. The exec'ing thread takes over the TID of the thread group leader.
+    Exec { former_tid: libc::pid_t },
+    // The thread is about to exit with the given wait status.
+    Exit { status: libc::c_int },
+    // A seccomp filter returned SECCOMP_RET_TRACE with the given data.
+    Seccomp { data: u32 },
+    // A seized thread stopped because of PTRACE_INTERRUPT or a group-stop.
+    Stop,
+}
+
+impl PtraceEvent {
+    // Decodes the PTRACE_EVENT_* of a ptrace-stop, which is reported in the bits above the stop
+    // signal (status >> 16 for waitpid, si_status >> 8 for waitid), along with the message that
+    // PTRACE_GETEVENTMSG read for it. Returns None for stops without an event.
+    pub fn decode(event: libc::c_int, message: libc::c_ulong) -> Option<PtraceEvent> {
+        return match event {
+            libc::PTRACE_EVENT_CLONE => Some(PtraceEvent::Clone {
+                new_tid: message as libc::pid_t,
+            }),
+            libc::PTRACE_EVENT_FORK => Some(PtraceEvent::Fork {
+                child_pid: message as libc::pid_t,
+            }),
+            libc::PTRACE_EVENT_VFORK => Some(PtraceEvent::Vfork {
+                child_pid: message as libc::pid_t,
+            }),
+            libc::PTRACE_EVENT_VFORK_DONE => Some(PtraceEvent::VforkDone {
+                child_pid: message as libc::pid_t,
+            }),
+            libc::PTRACE_EVENT_EXEC => Some(PtraceEvent::Exec {
+                former_tid: message as libc::pid_t,
+            }),
+            libc::PTRACE_EVENT_EXIT => Some(PtraceEvent::Exit {
+                status: message as libc::c_int,
+            }),
+            libc::PTRACE_EVENT_SECCOMP => Some(PtraceEvent::Seccomp {
+                data: message as u32 & libc::SECCOMP_RET_DATA,
+            }),
+            libc::PTRACE_EVENT_STOP => Some(PtraceEvent::Stop),
+            _ => None,
+        };
+    }
+
+    // Whether PTRACE_GETEVENTMSG has anything to say about the event.
+    pub fn has_message(event: libc::c_int) -> bool {
+        return event != 0 && event != libc::PTRACE_EVENT_STOP;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PtraceEvent;
+
+    #[test]
+    fn decode_includes_event_message() {
+        assert_eq!(
+            PtraceEvent::decode(libc::PTRACE_EVENT_CLONE, 1234),
+            Some(PtraceEvent::Clone { new_tid: 1234 })
+        );
+        assert_eq!(
+            PtraceEvent::decode(libc::PTRACE_EVENT_EXIT, 3 << 8),
+            Some(PtraceEvent::Exit { status: 3 << 8 })
+        );
+        assert_eq!(
+            PtraceEvent::decode(libc::PTRACE_EVENT_SECCOMP, 0x2a),
+            Some(PtraceEvent::Seccomp { data: 0x2a })
+        );
+        assert_eq!(
+            PtraceEvent::decode(libc::PTRACE_EVENT_STOP, 0),
+            Some(PtraceEvent::Stop)
+        );
+    }
+
+    #[test]
+    fn decode_ignores_stops_without_events() {
+        assert_eq!(PtraceEvent::decode(0, 0), None);
+        assert!(!PtraceEvent::has_message(0));
+        assert!(!PtraceEvent::has_message(libc::PTRACE_EVENT_STOP));
+        assert!(PtraceEvent::has_message(libc::PTRACE_EVENT_FORK));
+    }
+}
@@ -0,0 +1,838 @@
+// Expressions, e.g. `*(u32 *)($sp + 8) == 0x2a`, which `print`, `display` and assignments to
+// convenience variables evaluate. They are made of integer and floating-point literals, registers
+// (`$x0`), convenience variables (`$count`), the addresses of functions (`main`), the operators of
+// C, dereferences, and casts to scalar and pointer types.
+use std::fmt;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExpressionError {
+    #[error("missing expression")]
+    Empty,
+    #[error("unexpected \"{0}\"")]
+    Unexpected(String),
+    #[error("unexpected end of expression")]
+    UnexpectedEnd,
+    #[error("invalid number: \"{0}\"")]
+    InvalidNumber(String),
+    #[error("convenience variable ${0} is not set")]
+    Unset(String),
+    #[error("no symbol \"{0}\"")]
+    UnknownSymbol(String),
+    #[error("cannot read memory at {0:#x}")]
+    ReadMemory(u64),
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("invalid operand to \"{0}\"")]
+    InvalidOperand(&'static str),
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    I64,
+    U64,
+    F32,
+    F64,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Value {
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    // An address, along with the type of what is there.
+    Pointer { address: u64, target: ScalarType },
+}
+
+// What expressions read from the program and the session.
+pub trait ExpressionContext {
+    fn register(&self, name: &str) -> Option<u64>;
+    fn variable(&self, name: &str) -> Option<Value>;
+    fn symbol(&self, name: &str) -> Option<u64>;
+    fn read_memory(&self, address: u64, len: usize) -> Option<Vec<u8>>;
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum UnaryOp {
+    Plus,
+    Negate,
+    Not,
+    BitNot,
+    Deref,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+// The binary operators, with their precedence. Higher binds tighter.
+const BINARY_OPS: &[(&str, BinaryOp, u8)] = &[
+    ("*", BinaryOp::Mul, 10),
+    ("/", BinaryOp::Div, 10),
+    ("%", BinaryOp::Rem, 10),
+    ("+", BinaryOp::Add, 9),
+    ("-", BinaryOp::Sub, 9),
+    ("<<", BinaryOp::Shl, 8),
+    (">>", BinaryOp::Shr, 8),
+    ("<", BinaryOp::Lt, 7),
+    ("<=", BinaryOp::Le, 7),
+    (">", BinaryOp::Gt, 7),
+    (">=", BinaryOp::Ge, 7),
+    ("==", BinaryOp::Eq, 6),
+    ("!=", BinaryOp::Ne, 6),
+    ("&", BinaryOp::BitAnd, 5),
+    ("^", BinaryOp::BitXor, 4),
+    ("|", BinaryOp::BitOr, 3),
+    ("&&", BinaryOp::And, 2),
+    ("||", BinaryOp::Or, 1),
+];
+
+// Operators of two characters, which are matched before those of one.
+const TWO_CHAR_OPERATORS: &[&str] = &["<<", ">>", "<=", ">=", "==", "!=", "&&", "||"];
+const ONE_CHAR_OPERATORS: &str = "+-*/%<>&|^!~()";
+
+// The names of scalar types that casts accept, in both Rust and C spelling.
+const TYPE_NAMES: &[(&str, ScalarType)] = &[
+    ("i8", ScalarType::I8),
+    ("int8_t", ScalarType::I8),
+    ("char", ScalarType::I8),
+    ("signed char", ScalarType::I8),
+    ("u8", ScalarType::U8),
+    ("uint8_t", ScalarType::U8),
+    ("unsigned char", ScalarType::U8),
+    ("i16", ScalarType::I16),
+    ("int16_t", ScalarType::I16),
+    ("short", ScalarType::I16),
+    ("u16", ScalarType::U16),
+    ("uint16_t", ScalarType::U16),
+    ("unsigned short", ScalarType::U16),
+    ("i32", ScalarType::I32),
+    ("int32_t", ScalarType::I32),
+    ("int", ScalarType::I32),
+    ("u32", ScalarType::U32),
+    ("uint32_t", ScalarType::U32),
+    ("unsigned", ScalarType::U32),
+    ("unsigned int", ScalarType::U32),
+    ("i64", ScalarType::I64),
+    ("int64_t", ScalarType::I64),
+    ("long", ScalarType::I64),
+    ("long long", ScalarType::I64),
+    ("u64", ScalarType::U64),
+    ("uint64_t", ScalarType::U64),
+    ("unsigned long", ScalarType::U64),
+    ("unsigned long long", ScalarType::U64),
+    ("size_t", ScalarType::U64),
+    ("uintptr_t", ScalarType::U64),
+    ("f32", ScalarType::F32),
+    ("float", ScalarType::F32),
+    ("f64", ScalarType::F64),
+    ("double", ScalarType::F64),
+];
+
+#[derive(PartialEq, Clone, Debug)]
+enum Expr {
+    Value(Value),
+    // A register, or else a convenience variable.
+    Dollar(String),
+    Symbol(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Cast {
+        ty: ScalarType,
+        is_pointer: bool,
+        expr: Box<Expr>,
+    },
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+}
+
+// Evaluates an expression.
+pub fn evaluate(
+    expression: &str,
+    context: &dyn ExpressionContext,
+) -> Result<Value, ExpressionError> {
+    let tokens = tokenize(expression)?;
+    if tokens.is_empty() {
+        return Err(ExpressionError::Empty);
+    }
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+    };
+    let expr = parser.parse_binary(0)?;
+    if let Some(token) = parser.peek() {
+        return Err(ExpressionError::Unexpected(token.to_string()));
+    }
+    return eval(&expr, context);
+}
+
+impl Value {
+    // Whether the value counts as true in a condition, i.e. is not zero.
+    pub fn is_true(&self) -> bool {
+        return match *self {
+            Value::Int(value) => value != 0,
+            Value::UInt(value) => value != 0,
+            Value::Float(value) => value != 0.0,
+            Value::Pointer { address, .. } => address != 0,
+        };
+    }
+
+    // The bits of an integer or address. Floats are truncated.
+    pub fn to_bits(self) -> u64 {
+        return match self {
+            Value::Int(value) => value as u64,
+            Value::UInt(value) => value,
+            Value::Float(value) => value as i64 as u64,
+            Value::Pointer { address, .. } => address,
+        };
+    }
+
+    fn to_float(self) -> f64 {
+        return match self {
+            Value::Int(value) => value as f64,
+            Value::UInt(value) => value as f64,
+            Value::Float(value) => value,
+            Value::Pointer { address, .. } => address as f64,
+        };
+    }
+
+    // Converts to a scalar type, truncating or extending integers as C does.
+    fn cast(self, ty: ScalarType) -> Value {
+        let bits = match self {
+            Value::Float(value) if ty.is_signed() => value as i64 as u64,
+            Value::Float(value) => value as u64,
+            value => value.to_bits(),
+        };
+        return match ty {
+            ScalarType::I8 => Value::Int(bits as i8 as i64),
+            ScalarType::U8 => Value::UInt(bits as u8 as u64),
+            ScalarType::I16 => Value::Int(bits as i16 as i64),
+            ScalarType::U16 => Value::UInt(bits as u16 as u64),
+            ScalarType::I32 => Value::Int(bits as i32 as i64),
+            ScalarType::U32 => Value::UInt(bits as u32 as u64),
+            ScalarType::I64 => Value::Int(bits as i64),
+            ScalarType::U64 => Value::UInt(bits),
+            ScalarType::F32 => Value::Float(self.to_float() as f32 as f64),
+            ScalarType::F64 => Value::Float(self.to_float()),
+        };
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match *self {
+            Value::Int(value) => write!(f, "{} ({:#x})", value, value),
+            Value::UInt(value) => write!(f, "{} ({:#x})", value, value),
+            Value::Float(value) => write!(f, "{}", value),
+            Value::Pointer { address, target } => {
+                write!(f, "({} *) {:#x}", target.name(), address)
+            }
+        };
+    }
+}
+
+impl ScalarType {
+    pub fn size(&self) -> usize {
+        return match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::I64 | ScalarType::U64 | ScalarType::F64 => 8,
+        };
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            ScalarType::I8 => "i8",
+            ScalarType::U8 => "u8",
+            ScalarType::I16 => "i16",
+            ScalarType::U16 => "u16",
+            ScalarType::I32 => "i32",
+            ScalarType::U32 => "u32",
+            ScalarType::I64 => "i64",
+            ScalarType::U64 => "u64",
+            ScalarType::F32 => "f32",
+            ScalarType::F64 => "f64",
+        };
+    }
+
+    fn is_signed(&self) -> bool {
+        return matches!(
+            self,
+            ScalarType::I8 | ScalarType::I16 | ScalarType::I32 | ScalarType::I64
+        );
+    }
+
+    // Decodes a value of the type from the bytes of memory.
+    fn decode(&self, bytes: &[u8]) -> Value {
+        let mut word = [0u8; 8];
+        word[..bytes.len()].copy_from_slice(bytes);
+        let value = Value::UInt(u64::from_le_bytes(word));
+        return match self {
+            ScalarType::F32 => Value::Float(f32::from_le_bytes(bytes.try_into().unwrap()) as f64),
+            ScalarType::F64 => Value::Float(f64::from_le_bytes(word)),
+            ty => value.cast(*ty),
+        };
+    }
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        return self.tokens.get(self.position).map(String::as_str);
+    }
+
+    fn next(&mut self) -> Result<String, ExpressionError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or(ExpressionError::UnexpectedEnd)?;
+        self.position += 1;
+        return Ok(token);
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), ExpressionError> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(ExpressionError::Unexpected(token));
+        }
+        return Ok(());
+    }
+
+    // Parses binary operations of at least the given precedence, by precedence climbing.
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expr, ExpressionError> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            let Some((_, op, precedence)) = self
+                .peek()
+                .and_then(|token| BINARY_OPS.iter().find(|(name, _, _)| *name == token))
+            else {
+                return Ok(lhs);
+            };
+            if *precedence < min_precedence {
+                return Ok(lhs);
+            }
+            self.position += 1;
+            let rhs = self.parse_binary(precedence + 1)?;
+            lhs = Expr::Binary(*op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ExpressionError> {
+        let token = self.next()?;
+        let op = match token.as_str() {
+            "+" => Some(UnaryOp::Plus),
+            "-" => Some(UnaryOp::Negate),
+            "!" => Some(UnaryOp::Not),
+            "~" => Some(UnaryOp::BitNot),
+            "*" => Some(UnaryOp::Deref),
+            _ => None,
+        };
+        if let Some(op) = op {
+            return Ok(Expr::Unary(op, Box::new(self.parse_unary()?)));
+        }
+
+        if token == "(" {
+            if let Some((ty, is_pointer)) = self.parse_cast_type() {
+                let expr = self.parse_unary()?;
+                return Ok(Expr::Cast {
+                    ty,
+                    is_pointer,
+                    expr: Box::new(expr),
+                });
+            }
+            let expr = self.parse_binary(0)?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        if let Some(name) = token.strip_prefix('$') {
+            return Ok(Expr::Dollar(name.to_string()));
+        }
+        if token.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+            return Ok(Expr::Value(parse_number(&token)?));
+        }
+        if token.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            return Ok(Expr::Symbol(token));
+        }
+        return Err(ExpressionError::Unexpected(token));
+    }
+
+    // Parses the type of a cast after its opening parenthesis, e.g. "u32 *)", if it is one. A
+    // pointer to pointers points to addresses.
+    fn parse_cast_type(&mut self) -> Option<(ScalarType, bool)> {
+        let words = self.tokens[self.position..]
+            .iter()
+            .take_while(|token| token.starts_with(|c: char| c.is_ascii_alphabetic()))
+            .map(String::as_str)
+            .collect::<Vec<&str>>();
+        let stars = self.tokens[self.position + words.len()..]
+            .iter()
+            .take_while(|token| *token == "*")
+            .count();
+        let name = words.join(" ");
+        let ty = match (name.as_str(), stars) {
+            ("void", 1) => ScalarType::U8,
+            (_, 2..) => ScalarType::U64,
+            (name, _) => {
+                TYPE_NAMES
+                    .iter()
+                    .find(|(type_name, _)| *type_name == name)?
+                    .1
+            }
+        };
+        if words.is_empty()
+            || self.tokens.get(self.position + words.len() + stars) != Some(&")".to_string())
+        {
+            return None;
+        }
+        self.position += words.len() + stars + 1;
+        return Some((ty, stars > 0));
+    }
+}
+
+// Splits an expression into numbers, names, and operators.
+fn tokenize(expression: &str) -> Result<Vec<String>, ExpressionError> {
+    let chars = expression.chars().collect::<Vec<char>>();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        if c.is_ascii_digit() || c == '.' {
+            // Exponents of decimal floats may have a sign, e.g. "1e-3".
+            let is_hex = chars[i..].starts_with(&['0', 'x']);
+            i += 1;
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric()
+                    || chars[i] == '.'
+                    || (!is_hex
+                        && matches!(chars[i], '+' | '-')
+                        && matches!(chars[i - 1], 'e' | 'E')))
+            {
+                i += 1;
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '$' {
+            // Names may be qualified, e.g. "ns::function".
+            i += 1;
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric()
+                    || chars[i] == '_'
+                    || (chars[i] == ':' && chars.get(i + 1) == Some(&':'))
+                    || (chars[i] == ':' && chars[i - 1] == ':'))
+            {
+                i += 1;
+            }
+        } else if chars.get(i + 1).is_some_and(|next| {
+            return TWO_CHAR_OPERATORS.contains(&format!("{}{}", c, next).as_str());
+        }) {
+            i += 2;
+        } else if ONE_CHAR_OPERATORS.contains(c) {
+            i += 1;
+        } else {
+            return Err(ExpressionError::Unexpected(c.to_string()));
+        }
+        tokens.push(chars[start..i].iter().collect());
+    }
+    return Ok(tokens);
+}
+
+// Splits a call, e.g. "strlen((char *)$x0, 1 + 2)", into what is called and its arguments, which
+// are expressions in their own right. Returns None if the text is not a call.
+pub fn parse_call(text: &str) -> Option<(&str, Vec<&str>)> {
+    let text = text.trim();
+    let (callee, args) = text.strip_suffix(')')?.split_once('(')?;
+    let callee = callee.trim();
+    if callee.is_empty() {
+        return None;
+    }
+
+    // Commas inside parentheses belong to the arguments.
+    let mut parsed_args = vec![];
+    let mut depth = 0i32;
+    let mut start = 0;
+    for (i, c) in args.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parsed_args.push(args[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+        if depth < 0 {
+            return None;
+        }
+    }
+    if depth != 0 {
+        return None;
+    }
+    let last = args[start..].trim();
+    if !last.is_empty() || !parsed_args.is_empty() {
+        parsed_args.push(last);
+    }
+    return Some((callee, parsed_args));
+}
+
+// Parses a literal: a decimal or hexadecimal integer, or a decimal float.
+fn parse_number(token: &str) -> Result<Value, ExpressionError> {
+    let invalid = || ExpressionError::InvalidNumber(token.to_string());
+    if let Some(hex) = token.strip_prefix("0x") {
+        return u64::from_str_radix(hex, 16)
+            .map(Value::UInt)
+            .map_err(|_| invalid());
+    }
+    if token.contains(['.', 'e', 'E']) {
+        return token
+            .parse::<f64>()
+            .map(Value::Float)
+            .map_err(|_| invalid());
+    }
+    return match token.parse::<i64>() {
+        Ok(value) => Ok(Value::Int(value)),
+        Err(_) => token.parse::<u64>().map(Value::UInt).map_err(|_| invalid()),
+    };
+}
+
+fn eval(expr: &Expr, context: &dyn ExpressionContext) -> Result<Value, ExpressionError> {
+    return match expr {
+        Expr::Value(value) => Ok(*value),
+        Expr::Dollar(name) => match context.register(name) {
+            Some(value) => Ok(Value::UInt(value)),
+            None => context
+                .variable(name)
+                .ok_or_else(|| ExpressionError::Unset(name.to_string())),
+        },
+        Expr::Symbol(name) => context
+            .symbol(name)
+            .map(Value::UInt)
+            .ok_or_else(|| ExpressionError::UnknownSymbol(name.to_string())),
+        Expr::Unary(op, expr) => eval_unary(*op, eval(expr, context)?, context),
+        // The right-hand side of logical operators is only evaluated if it decides the result.
+        Expr::Binary(BinaryOp::And, lhs, rhs) => {
+            let is_true = eval(lhs, context)?.is_true() && eval(rhs, context)?.is_true();
+            Ok(Value::Int(is_true as i64))
+        }
+        Expr::Binary(BinaryOp::Or, lhs, rhs) => {
+            let is_true = eval(lhs, context)?.is_true() || eval(rhs, context)?.is_true();
+            Ok(Value::Int(is_true as i64))
+        }
+        Expr::Binary(op, lhs, rhs) => eval_binary(*op, eval(lhs, context)?, eval(rhs, context)?),
+        Expr::Cast {
+            ty,
+            is_pointer,
+            expr,
+        } => {
+            let value = eval(expr, context)?;
+            match (is_pointer, value) {
+                (true, Value::Float(_)) => Err(ExpressionError::InvalidOperand("cast")),
+                (true, value) => Ok(Value::Pointer {
+                    address: value.to_bits(),
+                    target: *ty,
+                }),
+                (false, value) => Ok(value.cast(*ty)),
+            }
+        }
+    };
+}
+
+fn eval_unary(
+    op: UnaryOp,
+    value: Value,
+    context: &dyn ExpressionContext,
+) -> Result<Value, ExpressionError> {
+    return match (op, value) {
+        (UnaryOp::Plus, value) => Ok(value),
+        (UnaryOp::Not, value) => Ok(Value::Int(!value.is_true() as i64)),
+        (UnaryOp::Negate, Value::Int(value)) => Ok(Value::Int(value.wrapping_neg())),
+        (UnaryOp::Negate, Value::UInt(value)) => Ok(Value::UInt(value.wrapping_neg())),
+        (UnaryOp::Negate, Value::Float(value)) => Ok(Value::Float(-value)),
+        (UnaryOp::BitNot, Value::Int(value)) => Ok(Value::Int(!value)),
+        (UnaryOp::BitNot, Value::UInt(value)) => Ok(Value::UInt(!value)),
+        (UnaryOp::Negate, _) => Err(ExpressionError::InvalidOperand("-")),
+        (UnaryOp::BitNot, _) => Err(ExpressionError::InvalidOperand("~")),
+        // Integers are taken to be the addresses of 64-bit words, e.g. `*$sp`.
+        (UnaryOp::Deref, Value::Float(_)) => Err(ExpressionError::InvalidOperand("*")),
+        (UnaryOp::Deref, value) => {
+            let (address, ty) = match value {
+                Value::Pointer { address, target } => (address, target),
+                value => (value.to_bits(), ScalarType::U64),
+            };
+            let bytes = context
+                .read_memory(address, ty.size())
+                .filter(|bytes| bytes.len() == ty.size())
+                .ok_or(ExpressionError::ReadMemory(address))?;
+            Ok(ty.decode(&bytes))
+        }
+    };
+}
+
+fn eval_binary(op: BinaryOp, lhs: Value, rhs: Value) -> Result<Value, ExpressionError> {
+    // Pointers move by whole elements, as in C.
+    match (op, lhs, rhs) {
+        (
+            BinaryOp::Add | BinaryOp::Sub,
+            Value::Pointer { address, target },
+            Value::Int(_) | Value::UInt(_),
+        ) => {
+            let offset = rhs.to_bits().wrapping_mul(target.size() as u64);
+            let address = match op {
+                BinaryOp::Add => address.wrapping_add(offset),
+                _ => address.wrapping_sub(offset),
+            };
+            return Ok(Value::Pointer { address, target });
+        }
+        (BinaryOp::Add, Value::Int(_) | Value::UInt(_), Value::Pointer { .. }) => {
+            return eval_binary(op, rhs, lhs);
+        }
+        (
+            BinaryOp::Sub,
+            Value::Pointer { address, target },
+            Value::Pointer { address: other, .. },
+        ) => {
+            return Ok(Value::Int(
+                address.wrapping_sub(other) as i64 / target.size() as i64,
+            ));
+        }
+        _ => {}
+    }
+
+    let compare = |ordering: Option<std::cmp::Ordering>| {
+        let is_true = match op {
+            BinaryOp::Lt => ordering.is_some_and(|ordering| ordering.is_lt()),
+            BinaryOp::Le => ordering.is_some_and(|ordering| ordering.is_le()),
+            BinaryOp::Gt => ordering.is_some_and(|ordering| ordering.is_gt()),
+            BinaryOp::Ge => ordering.is_some_and(|ordering| ordering.is_ge()),
+            BinaryOp::Eq => ordering.is_some_and(|ordering| ordering.is_eq()),
+            _ => ordering.is_none_or(|ordering| ordering.is_ne()),
+        };
+        return Value::Int(is_true as i64);
+    };
+    let is_comparison = matches!(
+        op,
+        BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge | BinaryOp::Eq | BinaryOp::Ne
+    );
+
+    // Floats take over the other operand, then unsigned integers, as in C.
+    if matches!(lhs, Value::Float(_)) || matches!(rhs, Value::Float(_)) {
+        let (lhs, rhs) = (lhs.to_float(), rhs.to_float());
+        return match op {
+            _ if is_comparison => Ok(compare(lhs.partial_cmp(&rhs))),
+            BinaryOp::Mul => Ok(Value::Float(lhs * rhs)),
+            BinaryOp::Div => Ok(Value::Float(lhs / rhs)),
+            BinaryOp::Add => Ok(Value::Float(lhs + rhs)),
+            BinaryOp::Sub => Ok(Value::Float(lhs - rhs)),
+            _ => Err(ExpressionError::InvalidOperand(binary_op_name(op))),
+        };
+    }
+    if matches!(lhs, Value::Int(_)) && matches!(rhs, Value::Int(_)) {
+        let (lhs, rhs) = (lhs.to_bits() as i64, rhs.to_bits() as i64);
+        return match op {
+            _ if is_comparison => Ok(compare(Some(lhs.cmp(&rhs)))),
+            BinaryOp::Div | BinaryOp::Rem if rhs == 0 => Err(ExpressionError::DivisionByZero),
+            BinaryOp::Mul => Ok(Value::Int(lhs.wrapping_mul(rhs))),
+            BinaryOp::Div => Ok(Value::Int(lhs.wrapping_div(rhs))),
+            BinaryOp::Rem => Ok(Value::Int(lhs.wrapping_rem(rhs))),
+            BinaryOp::Add => Ok(Value::Int(lhs.wrapping_add(rhs))),
+            BinaryOp::Sub => Ok(Value::Int(lhs.wrapping_sub(rhs))),
+            BinaryOp::Shl => Ok(Value::Int(lhs.wrapping_shl(rhs as u32))),
+            BinaryOp::Shr => Ok(Value::Int(lhs.wrapping_shr(rhs as u32))),
+            BinaryOp::BitAnd => Ok(Value::Int(lhs & rhs)),
+            BinaryOp::BitXor => Ok(Value::Int(lhs ^ rhs)),
+            _ => Ok(Value::Int(lhs | rhs)),
+        };
+    }
+    let (lhs, rhs) = (lhs.to_bits(), rhs.to_bits());
+    return match op {
+        _ if is_comparison => Ok(compare(Some(lhs.cmp(&rhs)))),
+        BinaryOp::Div | BinaryOp::Rem if rhs == 0 => Err(ExpressionError::DivisionByZero),
+        BinaryOp::Mul => Ok(Value::UInt(lhs.wrapping_mul(rhs))),
+        BinaryOp::Div => Ok(Value::UInt(lhs / rhs)),
+        BinaryOp::Rem => Ok(Value::UInt(lhs % rhs)),
+        BinaryOp::Add => Ok(Value::UInt(lhs.wrapping_add(rhs))),
+        BinaryOp::Sub => Ok(Value::UInt(lhs.wrapping_sub(rhs))),
+        BinaryOp::Shl => Ok(Value::UInt(lhs.wrapping_shl(rhs as u32))),
+        BinaryOp::Shr => Ok(Value::UInt(lhs.wrapping_shr(rhs as u32))),
+        BinaryOp::BitAnd => Ok(Value::UInt(lhs & rhs)),
+        BinaryOp::BitXor => Ok(Value::UInt(lhs ^ rhs)),
+        _ => Ok(Value::UInt(lhs | rhs)),
+    };
+}
+
+fn binary_op_name(op: BinaryOp) -> &'static str {
+    return BINARY_OPS
+        .iter()
+        .find(|(_, other, _)| *other == op)
+        .map_or("?", |(name, _, _)| name);
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{evaluate, parse_call, ExpressionContext, ExpressionError, ScalarType, Value};
+
+    struct TestContext {
+        registers: HashMap<&'static str, u64>,
+        variables: HashMap<&'static str, Value>,
+        // Memory from address 0x1000 on.
+        memory: Vec<u8>,
+    }
+
+    impl ExpressionContext for TestContext {
+        fn register(&self, name: &str) -> Option<u64> {
+            return self.registers.get(name).copied();
+        }
+
+        fn variable(&self, name: &str) -> Option<Value> {
+            return self.variables.get(name).copied();
+        }
+
+        fn symbol(&self, name: &str) -> Option<u64> {
+            return (name == "main").then_some(0x400000);
+        }
+
+        fn read_memory(&self, address: u64, len: usize) -> Option<Vec<u8>> {
+            let start = address.checked_sub(0x1000)? as usize;
+            return self.memory.get(start..start + len).map(<[u8]>::to_vec);
+        }
+    }
+
+    fn context() -> TestContext {
+        let mut memory = vec![];
+        memory.extend(0x1122334455667788u64.to_le_bytes());
+        memory.extend(1.5f64.to_le_bytes());
+        memory.extend((-2i32).to_le_bytes());
+        return TestContext {
+            registers: HashMap::from([("x0", 0x1000), ("sp", 0x1008)]),
+            variables: HashMap::from([("count", Value::Int(41))]),
+            memory,
+        };
+    }
+
+    fn eval(expression: &str) -> Result<Value, ExpressionError> {
+        return evaluate(expression, &context());
+    }
+
+    #[test]
+    fn evaluate_follows_c_precedence() {
+        assert_eq!(eval("1 + 2 * 3").unwrap(), Value::Int(7));
+        assert_eq!(eval("(1 + 2) * 3").unwrap(), Value::Int(9));
+        assert_eq!(eval("1 << 4 | 1").unwrap(), Value::Int(17));
+        assert_eq!(eval("-7 / 2").unwrap(), Value::Int(-3));
+        assert_eq!(eval("1 < 2 && 2 <= 2 || 0").unwrap(), Value::Int(1));
+        assert_eq!(eval("!3 == 0").unwrap(), Value::Int(1));
+        assert_eq!(eval("~0x0f & 0xff").unwrap(), Value::UInt(0xf0));
+    }
+
+    #[test]
+    fn evaluate_reads_registers_variables_and_symbols() {
+        assert_eq!(eval("$count + 1").unwrap(), Value::Int(42));
+        assert_eq!(eval("$sp - $x0").unwrap(), Value::UInt(8));
+        assert_eq!(eval("main + 4").unwrap(), Value::UInt(0x400004));
+        assert!(matches!(eval("$hits"), Err(ExpressionError::Unset(_))));
+        assert!(matches!(
+            eval("missing"),
+            Err(ExpressionError::UnknownSymbol(_))
+        ));
+    }
+
+    #[test]
+    fn evaluate_mixes_floats_and_integers() {
+        assert_eq!(eval("1.5 * 2").unwrap(), Value::Float(3.0));
+        assert_eq!(eval("1e-1 < 1").unwrap(), Value::Int(1));
+        assert_eq!(eval("(int)2.9").unwrap(), Value::Int(2));
+        assert_eq!(eval("(double)3 / 2").unwrap(), Value::Float(1.5));
+    }
+
+    #[test]
+    fn evaluate_casts_and_dereferences() {
+        assert_eq!(eval("*$x0").unwrap(), Value::UInt(0x1122334455667788));
+        assert_eq!(eval("*(u8 *)$x0").unwrap(), Value::UInt(0x88));
+        assert_eq!(
+            eval("*(unsigned short *)($x0 + 2)").unwrap(),
+            Value::UInt(0x5566)
+        );
+        assert_eq!(eval("*(double *)$sp").unwrap(), Value::Float(1.5));
+        assert_eq!(eval("*((i32 *)$x0 + 4)").unwrap(), Value::Int(-2));
+        assert_eq!(eval("(u8)0x1ff").unwrap(), Value::UInt(0xff));
+        assert_eq!(eval("(i8)0xff").unwrap(), Value::Int(-1));
+        assert_eq!(
+            eval("(void *)$x0").unwrap(),
+            Value::Pointer {
+                address: 0x1000,
+                target: ScalarType::U8
+            }
+        );
+        assert!(matches!(
+            eval("*0x10"),
+            Err(ExpressionError::ReadMemory(0x10))
+        ));
+    }
+
+    #[test]
+    fn parse_call_splits_callee_and_arguments() {
+        assert_eq!(parse_call("getpid()"), Some(("getpid", vec![])));
+        assert_eq!(
+            parse_call("strlen((char *)$x0)"),
+            Some(("strlen", vec!["(char *)$x0"]))
+        );
+        assert_eq!(
+            parse_call(" add (1, (2 + 3) * 4 ,$count) "),
+            Some(("add", vec!["1", "(2 + 3) * 4", "$count"]))
+        );
+        assert_eq!(parse_call("f(1, )"), Some(("f", vec!["1", ""])));
+        assert_eq!(parse_call("getpid"), None);
+        assert_eq!(parse_call("(1)"), None);
+        assert_eq!(parse_call("f(1))"), None);
+    }
+
+    #[test]
+    fn evaluate_rejects_malformed_expressions() {
+        assert!(matches!(eval(""), Err(ExpressionError::Empty)));
+        assert!(matches!(eval("1 +"), Err(ExpressionError::UnexpectedEnd)));
+        assert!(matches!(eval("1 2"), Err(ExpressionError::Unexpected(_))));
+        assert!(matches!(eval("(1"), Err(ExpressionError::UnexpectedEnd)));
+        assert!(matches!(
+            eval("1 / 0"),
+            Err(ExpressionError::DivisionByZero)
+        ));
+        assert!(matches!(eval("0x"), Err(ExpressionError::InvalidNumber(_))));
+        assert!(matches!(eval("1 @ 2"), Err(ExpressionError::Unexpected(_))));
+        assert!(matches!(
+            eval("1.5 << 1"),
+            Err(ExpressionError::InvalidOperand("<<"))
+        ));
+    }
+}
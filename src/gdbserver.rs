@@ -0,0 +1,517 @@
+use std::{
+    fs,
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+
+use crate::{
+    breakpoint::Breakpoint,
+    cleanup,
+    reaper::WaitStatus,
+    rsp::{
+        decode_registers, encode_packet, encode_registers, from_gdb_signal, from_hex,
+        to_gdb_signal, to_hex, Incoming, PacketDecoder, CPSR_REGISTER, REGISTER_COUNT,
+    },
+    signal::Trap,
+    tracee::{Tracee, TraceeStatus},
+};
+
+// The largest packet that the client may send, as announced in qSupported.
+const PACKET_SIZE: usize = 0x4000;
+
+// How often a running tracee is checked on while waiting for the client to interrupt it.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Parses "<address>,<length>" as used by memory and breakpoint packets, both in hexadecimal.
+fn parse_address_length(s: &str) -> Option<(u64, usize)> {
+    let (address_str, length_str) = s.split_once(',')?;
+    let address = u64::from_str_radix(address_str, 16).ok()?;
+    let length = usize::from_str_radix(length_str, 16).ok()?;
+    return Some((address, length));
+}
+
+// Serves a slice of an object that the client reads in chunks, e.g. the target description.
+fn read_object(object: &[u8], offset_length: &str) -> Vec<u8> {
+    let Some((offset, length)) = parse_address_length(offset_length) else {
+        return b"E01".to_vec();
+    };
+    let offset = (offset as usize).min(object.len());
+    let end = offset.saturating_add(length).min(object.len());
+    let marker = match end == object.len() {
+        true => b'l',
+        false => b'm',
+    };
+    let mut reply = vec![marker];
+    reply.extend_from_slice(&object[offset..end]);
+    return reply;
+}
+
+fn target_description() -> String {
+    let mut registers = vec![];
+    for i in 0..31 {
+        registers.push(format!(r#"<reg name="x{}" bitsize="64" type="int"/>"#, i));
+    }
+    registers.push(r#"<reg name="sp" bitsize="64" type="data_ptr"/>"#.to_string());
+    registers.push(r#"<reg name="pc" bitsize="64" type="code_ptr"/>"#.to_string());
+    registers.push(r#"<reg name="cpsr" bitsize="32" type="int"/>"#.to_string());
+    return format!(
+        concat!(
+            r#"<?xml version="1.0"?><!DOCTYPE target SYSTEM "gdb-target.dtd">"#,
+            r#"<target version="1.0"><architecture>aarch64</architecture>"#,
+            r#"<feature name="org.gnu.gdb.aarch64.core">{}</feature></target>"#,
+        ),
+        registers.join("")
+    );
+}
+
+// What handling a packet amounts to.
+enum Action {
+    Reply(Vec<u8>),
+    // The tracee was resumed, and the stop reply is due once it stops.
+    Resume,
+    Detach,
+    Kill,
+}
+
+// Serves a tracee to a single client, such as GDB or LLDB, over the GDB Remote Serial Protocol.
+pub struct GdbServer {
+    tracee: Tracee,
+    stream: TcpStream,
+    decoder: PacketDecoder,
+    // Whether packets are acknowledged, which the client may turn off with QStartNoAckMode.
+    acknowledges: bool,
+    // Whether the last stop was an interrupt by the client, which it expects to see as SIGINT.
+    interrupted: bool,
+}
+
+// Listens on the given address, e.g. ":1234" for port 1234 of every interface, and serves the
+// tracee to the first client that connects. Returns once the client detaches or kills it.
+pub unsafe fn serve(mut tracee: Tracee, address: &str) -> io::Result<()> {
+    cleanup::install();
+    let address = match address.starts_with(':') {
+        true => format!("0.0.0.0{}", address),
+        false => address.to_string(),
+    };
+    let listener = TcpListener::bind(&address)?;
+    println!("Listening on {} for process ({})", address, tracee.pid());
+    let (stream, client_address) = listener.accept()?;
+    println!("Remote debugging from {}", client_address);
+
+    // The client decides which signals are delivered, with every request to resume.
+    tracee.set_pass_pending_signals(false);
+    let mut server = GdbServer {
+        tracee,
+        stream,
+        decoder: PacketDecoder::default(),
+        acknowledges: true,
+        interrupted: false,
+    };
+    if server.run()? {
+        server.tracee.detach();
+    }
+    return Ok(());
+}
+
+impl GdbServer {
+    // Handles packets until the client is done. Returns whether the tracee is to be detached.
+    unsafe fn run(&mut self) -> io::Result<bool> {
+        loop {
+            let Some(packet) = self.receive()? else {
+                println!("Remote client disconnected");
+                return Ok(false);
+            };
+
+            match self.handle_packet(&packet) {
+                Action::Reply(reply) => self.send(&reply)?,
+                Action::Resume => {
+                    self.wait_for_stop()?;
+                    let reply = self.stop_reply();
+                    self.send(&reply)?;
+                }
+                Action::Detach => {
+                    self.remove_breakpoints();
+                    self.send(b"OK")?;
+                    return Ok(true);
+                }
+                Action::Kill => {
+                    if self.tracee.status() != TraceeStatus::Exited
+                        && self.tracee.status() != TraceeStatus::Terminated
+                    {
+                        self.tracee.kill();
+                    }
+                    // A kill is not answered, except through vKill.
+                    if packet.starts_with(b"vKill") {
+                        self.send(b"OK")?;
+                    }
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    // Blocks until a packet arrives, acknowledging it. Returns None once the client disconnects.
+    fn receive(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            match self.decoder.next_incoming() {
+                Some(Incoming::Packet(packet)) => {
+                    if self.acknowledges {
+                        self.stream.write_all(b"+")?;
+                    }
+                    return Ok(Some(packet));
+                }
+                Some(Incoming::Corrupted) => self.stream.write_all(b"-")?,
+                // There is nothing to interrupt while the tracee is stopped.
+                Some(Incoming::Interrupt) => {}
+                None => {
+                    let mut buffer = [0u8; 4096];
+                    let n_bytes = self.stream.read(&mut buffer)?;
+                    if n_bytes == 0 {
+                        return Ok(None);
+                    }
+                    self.decoder.feed(&buffer[..n_bytes]);
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        return self.stream.write_all(&encode_packet(data));
+    }
+
+    // Waits until the resumed tracee stops or exits, interrupting it if the client asks to.
+    unsafe fn wait_for_stop(&mut self) -> io::Result<()> {
+        self.interrupted = false;
+        self.stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        let result = loop {
+            if self.tracee.poll_signal() {
+                break Ok(());
+            }
+
+            let mut buffer = [0u8; 4096];
+            match self.stream.read(&mut buffer) {
+                Ok(0) => break Err(io::Error::from(ErrorKind::UnexpectedEof)),
+                Ok(n_bytes) => self.decoder.feed(&buffer[..n_bytes]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) if err.kind() == ErrorKind::TimedOut => {}
+                Err(err) => break Err(err),
+            }
+            // Packets other than an interrupt are not expected until the stop reply is sent.
+            if self.decoder.take_interrupts() {
+                self.tracee.interrupt(None);
+                self.interrupted = true;
+                break Ok(());
+            }
+        };
+        self.stream.set_read_timeout(None)?;
+        return result;
+    }
+
+    unsafe fn stop_reply(&self) -> Vec<u8> {
+        match self.tracee.exit_status() {
+            Some(WaitStatus::Exited(code)) => return format!("W{:02x}", code as u8).into_bytes(),
+            Some(WaitStatus::Signaled(signal)) => {
+                return format!("X{:02x}", to_gdb_signal(signal)).into_bytes();
+            }
+            _ => {}
+        }
+
+        let tid = self.tracee.selected_tid();
+        let (signal, reason) = match self.interrupted {
+            true => (libc::SIGINT, ""),
+            // Stops without a signal, e.g. at a system call, look like a trap.
+            false => match self.tracee.read_siginfo() {
+                None => (libc::SIGTRAP, ""),
+                Some(info) if info.si_signo == libc::SIGTRAP => match self.tracee.trap() {
+                    Some(Trap::Breakpoint { .. }) => (libc::SIGTRAP, "swbreak:;"),
+                    _ => (libc::SIGTRAP, ""),
+                },
+                Some(info) => (info.si_signo, ""),
+            },
+        };
+        return format!("T{:02x}thread:{:x};{}", to_gdb_signal(signal), tid, reason).into_bytes();
+    }
+
+    unsafe fn handle_packet(&mut self, packet: &[u8]) -> Action {
+        let packet = String::from_utf8_lossy(packet).to_string();
+        let reply = |reply: &str| Action::Reply(reply.as_bytes().to_vec());
+
+        if self.tracee.status() == TraceeStatus::Exited
+            || self.tracee.status() == TraceeStatus::Terminated
+        {
+            return match packet.as_str() {
+                "?" => Action::Reply(self.stop_reply()),
+                "k" => Action::Kill,
+                _ if packet.starts_with("vKill") => Action::Kill,
+                _ if packet.starts_with('D') => Action::Kill,
+                _ => reply("E01"),
+            };
+        }
+
+        return match packet.as_str() {
+            "?" => Action::Reply(self.stop_reply()),
+            "QStartNoAckMode" => {
+                // The acknowledgement of this very packet was still sent.
+                self.acknowledges = false;
+                reply("OK")
+            }
+            "qAttached" => reply("0"),
+            "qC" => reply(&format!("QC{:x}", self.tracee.selected_tid())),
+            "qfThreadInfo" => {
+                let tids = self
+                    .tracee
+                    .threads()
+                    .iter()
+                    .map(|thread| format!("{:x}", thread.tid))
+                    .collect::<Vec<String>>();
+                reply(&format!("m{}", tids.join(",")))
+            }
+            "qsThreadInfo" => reply("l"),
+            "vCont?" => reply("vCont;c;C;s;S"),
+            "g" => reply(&self.read_registers()),
+            "k" => Action::Kill,
+            _ if packet.starts_with("qSupported") => reply(&format!(
+                "PacketSize={:x};QStartNoAckMode+;qXfer:features:read+;qXfer:auxv:read+;\
+                 qXfer:exec-file:read+;swbreak+;vContSupported+",
+                PACKET_SIZE
+            )),
+            _ if packet.starts_with("qXfer:features:read:target.xml:") => {
+                let offset_length = &packet["qXfer:features:read:target.xml:".len()..];
+                Action::Reply(read_object(target_description().as_bytes(), offset_length))
+            }
+            _ if packet.starts_with("qXfer:auxv:read::") => {
+                match fs::read(format!("/proc/{}/auxv", self.tracee.pid())) {
+                    Err(_) => reply("E01"),
+                    Ok(auxv) => {
+                        Action::Reply(read_object(&auxv, &packet["qXfer:auxv:read::".len()..]))
+                    }
+                }
+            }
+            _ if packet.starts_with("qXfer:exec-file:read:") => {
+                let Some((_, offset_length)) =
+                    packet["qXfer:exec-file:read:".len()..].split_once(':')
+                else {
+                    return reply("E01");
+                };
+                let path = self.tracee.executable().display().to_string();
+                Action::Reply(read_object(path.as_bytes(), offset_length))
+            }
+            _ if packet.starts_with('H') => {
+                // Hg and Hc select a thread, where 0 and -1 mean any.
+                match i64::from_str_radix(packet.get(2..).unwrap_or(""), 16) {
+                    Ok(tid) if tid > 0 => match self.tracee.select_thread(tid as libc::pid_t) {
+                        true => reply("OK"),
+                        false => reply("E01"),
+                    },
+                    Ok(_) => reply("OK"),
+                    Err(_) => reply("E01"),
+                }
+            }
+            _ if packet.starts_with('T') => match i64::from_str_radix(&packet[1..], 16) {
+                Ok(tid)
+                    if self
+                        .tracee
+                        .threads()
+                        .iter()
+                        .any(|thread| thread.tid as i64 == tid) =>
+                {
+                    reply("OK")
+                }
+                _ => reply("E01"),
+            },
+            _ if packet.starts_with('G') => match self.write_registers(&packet[1..]) {
+                true => reply("OK"),
+                false => reply("E01"),
+            },
+            _ if packet.starts_with('p') => match usize::from_str_radix(&packet[1..], 16) {
+                Ok(n) if n < REGISTER_COUNT => {
+                    let registers = self.read_registers();
+                    let start = n * 16;
+                    let end = match n {
+                        CPSR_REGISTER => start + 8,
+                        _ => start + 16,
+                    };
+                    reply(&registers[start..end])
+                }
+                _ => reply("E01"),
+            },
+            _ if packet.starts_with('P') => {
+                let Some((n_str, value)) = packet[1..].split_once('=') else {
+                    return reply("E01");
+                };
+                match usize::from_str_radix(n_str, 16) {
+                    Ok(n) if n < REGISTER_COUNT => {
+                        let mut registers = self.read_registers();
+                        let start = n * 16;
+                        let end = (start + value.len()).min(registers.len());
+                        registers.replace_range(start..end, &value[..end - start]);
+                        match self.write_registers(&registers) {
+                            true => reply("OK"),
+                            false => reply("E01"),
+                        }
+                    }
+                    _ => reply("E01"),
+                }
+            }
+            _ if packet.starts_with('m') => match parse_address_length(&packet[1..]) {
+                None => reply("E01"),
+                Some((address, length)) => match self.tracee.read_memory(address, length) {
+                    Err(_) => reply("E01"),
+                    Ok(mut bytes) => {
+                        // The client sees the code as the program has it, without breakpoints.
+                        self.tracee.hide_breakpoints(&mut bytes, address);
+                        reply(&to_hex(&bytes))
+                    }
+                },
+            },
+            _ if packet.starts_with('M') => {
+                let Some((address_length, hex)) = packet[1..].split_once(':') else {
+                    return reply("E01");
+                };
+                match (parse_address_length(address_length), from_hex(hex)) {
+                    (Some((address, _)), Some(bytes)) => {
+                        match self.tracee.write_memory(address, &bytes) {
+                            Err(_) => reply("E01"),
+                            Ok(()) => reply("OK"),
+                        }
+                    }
+                    _ => reply("E01"),
+                }
+            }
+            _ if packet.starts_with("Z0,") => match parse_address_length(&packet[3..]) {
+                None => reply("E01"),
+                Some((address, _)) => match self.insert_breakpoint(address) {
+                    true => reply("OK"),
+                    false => reply("E01"),
+                },
+            },
+            _ if packet.starts_with("z0,") => match parse_address_length(&packet[3..]) {
+                None => reply("E01"),
+                Some((address, _)) => match self.remove_breakpoint(address) {
+                    true => reply("OK"),
+                    false => reply("E01"),
+                },
+            },
+            _ if packet.starts_with('c') => {
+                self.tracee.resume();
+                Action::Resume
+            }
+            _ if packet.starts_with('s') => {
+                self.tracee.step(None);
+                Action::Resume
+            }
+            _ if packet.starts_with('C') || packet.starts_with('S') => {
+                let signal_str = packet[1..].split(';').next().unwrap_or("");
+                let Some(signal) = u8::from_str_radix(signal_str, 16)
+                    .ok()
+                    .and_then(from_gdb_signal)
+                else {
+                    return reply("E01");
+                };
+                match packet.starts_with('C') {
+                    true => self.tracee.resume_with_signal(signal),
+                    false => self.tracee.step(Some(signal)),
+                }
+                Action::Resume
+            }
+            _ if packet.starts_with("vCont;") => self.handle_vcont(&packet["vCont;".len()..]),
+            _ if packet.starts_with('D') => Action::Detach,
+            _ if packet.starts_with("vKill") => Action::Kill,
+            // Anything else is not supported, which is said with an empty reply.
+            _ => reply(""),
+        };
+    }
+
+    // Resumes the tracee as asked by actions like "s:1f3;c", which apply to the given thread or,
+    // without one, to every other thread. A thread that is stepped is resumed alone, and the
+    // first action decides how.
+    unsafe fn handle_vcont(&mut self, actions: &str) -> Action {
+        // Only the first action is taken, since the rest only apply to other threads.
+        let Some(action) = actions.split(';').next() else {
+            return Action::Reply(b"E01".to_vec());
+        };
+        let (action, tid) = match action.split_once(':') {
+            None => (action, None),
+            Some((action, tid_str)) => (action, i64::from_str_radix(tid_str, 16).ok()),
+        };
+        if let Some(tid) = tid.filter(|tid| *tid > 0) {
+            self.tracee.select_thread(tid as libc::pid_t);
+        }
+
+        let signal = match action.get(1..) {
+            None | Some("") => None,
+            Some(signal_str) => {
+                match u8::from_str_radix(signal_str, 16)
+                    .ok()
+                    .and_then(from_gdb_signal)
+                {
+                    None => return Action::Reply(b"E01".to_vec()),
+                    Some(signal) => Some(signal),
+                }
+            }
+        };
+        match (action.chars().next(), signal) {
+            (Some('s' | 'S'), signal) => self.tracee.step(signal),
+            (Some('c' | 'C'), None) => self.tracee.resume(),
+            (Some('c' | 'C'), Some(signal)) => self.tracee.resume_with_signal(signal),
+            _ => return Action::Reply(b"E01".to_vec()),
+        }
+        return Action::Resume;
+    }
+
+    // Reads every register of the selected thread, as hexadecimal target-endian bytes.
+    unsafe fn read_registers(&self) -> String {
+        return encode_registers(&self.tracee.read_general_purpose_registers());
+    }
+
+    unsafe fn write_registers(&self, hex: &str) -> bool {
+        let mut regs = self.tracee.read_general_purpose_registers();
+        if !decode_registers(hex, &mut regs) {
+            return false;
+        }
+        self.tracee.write_general_purpose_registers(&mut regs);
+        return true;
+    }
+
+    unsafe fn insert_breakpoint(&mut self, address: u64) -> bool {
+        if self.tracee.breakpoints().find(address).is_some() {
+            return true;
+        }
+        return self.tracee.insert_breakpoint(address).is_ok();
+    }
+
+    unsafe fn remove_breakpoint(&mut self, address: u64) -> bool {
+        let Some(id) = self.tracee.breakpoints().find(address).map(Breakpoint::id) else {
+            return true;
+        };
+        return self.tracee.delete_breakpoint(id).is_ok();
+    }
+
+    // Removes every breakpoint, which were all set by the client.
+    unsafe fn remove_breakpoints(&mut self) {
+        let ids = self
+            .tracee
+            .breakpoints()
+            .breakpoints()
+            .iter()
+            .map(Breakpoint::id)
+            .collect::<Vec<usize>>();
+        for id in ids {
+            if let Err(err) = self.tracee.delete_breakpoint(id) {
+                println!("{}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::read_object;
+
+    #[test]
+    fn read_object_marks_last_chunk() {
+        assert_eq!(read_object(b"abcdef", "0,4"), b"mabcd".to_vec());
+        assert_eq!(read_object(b"abcdef", "4,4"), b"lef".to_vec());
+        assert_eq!(read_object(b"abcdef", "6,4"), b"l".to_vec());
+    }
+}
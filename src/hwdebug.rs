@@ -0,0 +1,359 @@
+// Hardware breakpoints and watchpoints, which live in the debug registers of each thread rather
+// than being patched into code. The hardware only has a few of each, e.g. 6 breakpoints and 4
+// watchpoints, so their slots are allocated for the tracee as a whole and mirrored into every
+// thread.
+use std::{ffi::CStr, mem};
+
+// The regsets of the debug registers (see linux/elf.h).
+const NT_ARM_HW_BREAK: libc::c_int = 0x402;
+const NT_ARM_HW_WATCH: libc::c_int = 0x403;
+
+// The most slots of either kind that the architecture allows.
+const MAX_SLOTS: usize = 16;
+
+// Watchpoints watch up to this many bytes, within one aligned word.
+const MAX_WATCH_LEN: u64 = 8;
+
+// The privilege field of the control register that has slots trigger in user space only.
+const PRIVILEGE_EL0: u32 = 2;
+
+// Breakpoints cover the 4 bytes of an instruction.
+const INSTRUCTION_LEN: u64 = 4;
+
+// One pair of debug registers, as in `struct user_hwdebug_state`.
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct DebugRegister {
+    address: u64,
+    ctrl: u32,
+    pad: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct HardwareDebugState {
+    // The number of slots is in the low byte.
+    info: u32,
+    pad: u32,
+    registers: [DebugRegister; MAX_SLOTS],
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum SlotKind {
+    Breakpoint,
+    Watchpoint,
+}
+
+impl SlotKind {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            SlotKind::Breakpoint => "breakpoint",
+            SlotKind::Watchpoint => "watchpoint",
+        };
+    }
+
+    // What can be done instead when every slot is in use.
+    fn suggestion(&self) -> &'static str {
+        return match self {
+            SlotKind::Breakpoint => "free one, or use a software breakpoint instead",
+            SlotKind::Watchpoint => "free one, or check the value by single-stepping instead",
+        };
+    }
+
+    fn regset(&self) -> libc::c_int {
+        return match self {
+            SlotKind::Breakpoint => NT_ARM_HW_BREAK,
+            SlotKind::Watchpoint => NT_ARM_HW_WATCH,
+        };
+    }
+}
+
+// Which accesses a watchpoint triggers on.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum WatchAccess {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchAccess {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            WatchAccess::Read => "read",
+            WatchAccess::Write => "write",
+            WatchAccess::ReadWrite => "access",
+        };
+    }
+}
+
+// What a slot is set to: the address to break at or watch, and the control register that says how,
+// e.g. how many bytes are watched and for which accesses.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct HardwareSlot {
+    pub address: u64,
+    pub ctrl: u32,
+}
+
+impl HardwareSlot {
+    // A breakpoint on the instruction at `address`. Returns None if it is not aligned as
+    // instructions are.
+    pub fn breakpoint(address: u64) -> Option<HardwareSlot> {
+        if !address.is_multiple_of(INSTRUCTION_LEN) {
+            return None;
+        }
+        return Some(HardwareSlot {
+            address,
+            ctrl: 0xf << 5 | PRIVILEGE_EL0 << 1 | 1,
+        });
+    }
+
+    // A watchpoint on `len` bytes at `address`, which must not cross an aligned 8-byte word.
+    // Returns None if they do.
+    pub fn watchpoint(address: u64, len: u64, access: WatchAccess) -> Option<HardwareSlot> {
+        let offset = address % MAX_WATCH_LEN;
+        if len == 0 || offset + len > MAX_WATCH_LEN {
+            return None;
+        }
+        // The slot holds the aligned word, and the control register which of its bytes are watched.
+        let byte_mask = ((1u32 << len) - 1) << offset;
+        let access = match access {
+            WatchAccess::Read => 1,
+            WatchAccess::Write => 2,
+            WatchAccess::ReadWrite => 3,
+        };
+        return Some(HardwareSlot {
+            address: address - offset,
+            ctrl: byte_mask << 5 | access << 3 | PRIVILEGE_EL0 << 1 | 1,
+        });
+    }
+
+    // Whether a watchpoint covers the address that an access faulted at.
+    pub fn watches(&self, address: u64) -> bool {
+        return (self.address..self.address + MAX_WATCH_LEN).contains(&address);
+    }
+}
+
+#[derive(PartialEq, Debug, thiserror::Error)]
+pub enum HardwareDebugError {
+    #[error("the hardware has no {} slots", kind.name())]
+    Unsupported { kind: SlotKind },
+    #[error("all {total} hardware {} slots are in use; {}", kind.name(), kind.suggestion())]
+    NoFreeSlot { kind: SlotKind, total: usize },
+    #[error("failed to read debug registers of thread ({tid}): {message}")]
+    ReadRegisters { tid: libc::pid_t, message: String },
+    #[error("failed to write debug registers of thread ({tid}): {message}")]
+    WriteRegisters { tid: libc::pid_t, message: String },
+}
+
+// The slots of the tracee, by index, which is also their index in every thread's debug registers.
+#[derive(PartialEq, Clone, Debug)]
+pub struct DebugSlots {
+    breakpoints: Vec<Option<HardwareSlot>>,
+    watchpoints: Vec<Option<HardwareSlot>>,
+}
+
+impl DebugSlots {
+    pub fn new(breakpoint_count: usize, watchpoint_count: usize) -> DebugSlots {
+        return DebugSlots {
+            breakpoints: vec![None; breakpoint_count.min(MAX_SLOTS)],
+            watchpoints: vec![None; watchpoint_count.min(MAX_SLOTS)],
+        };
+    }
+
+    pub fn slots(&self, kind: SlotKind) -> &[Option<HardwareSlot>] {
+        return match kind {
+            SlotKind::Breakpoint => &self.breakpoints,
+            SlotKind::Watchpoint => &self.watchpoints,
+        };
+    }
+
+    fn slots_mut(&mut self, kind: SlotKind) -> &mut Vec<Option<HardwareSlot>> {
+        return match kind {
+            SlotKind::Breakpoint => &mut self.breakpoints,
+            SlotKind::Watchpoint => &mut self.watchpoints,
+        };
+    }
+
+    pub fn free_count(&self, kind: SlotKind) -> usize {
+        return self
+            .slots(kind)
+            .iter()
+            .filter(|slot| slot.is_none())
+            .count();
+    }
+
+    // Whether any slot is in use, in which case new threads need the slots mirrored into them.
+    pub fn is_in_use(&self) -> bool {
+        return self
+            .breakpoints
+            .iter()
+            .chain(&self.watchpoints)
+            .any(Option::is_some);
+    }
+
+    // Takes the first free slot of a kind. Returns its index.
+    pub fn allocate(
+        &mut self,
+        kind: SlotKind,
+        slot: HardwareSlot,
+    ) -> Result<usize, HardwareDebugError> {
+        let slots = self.slots_mut(kind);
+        if slots.is_empty() {
+            return Err(HardwareDebugError::Unsupported { kind });
+        }
+        let total = slots.len();
+        let Some(index) = slots.iter().position(Option::is_none) else {
+            return Err(HardwareDebugError::NoFreeSlot { kind, total });
+        };
+        slots[index] = Some(slot);
+        return Ok(index);
+    }
+
+    // Frees a slot. Returns what it was set to, if it was in use.
+    pub fn free(&mut self, kind: SlotKind, index: usize) -> Option<HardwareSlot> {
+        return self.slots_mut(kind).get_mut(index)?.take();
+    }
+}
+
+// Reads how many slots of a kind the hardware has, through a stopped thread.
+pub unsafe fn read_slot_count(
+    tid: libc::pid_t,
+    kind: SlotKind,
+) -> Result<usize, HardwareDebugError> {
+    let mut state = HardwareDebugState::default();
+    let mut iov = libc::iovec {
+        iov_base: &mut state as *mut HardwareDebugState as *mut libc::c_void,
+        iov_len: mem::size_of::<HardwareDebugState>(),
+    };
+    if libc::ptrace(
+        libc::PTRACE_GETREGSET,
+        tid,
+        kind.regset(),
+        &mut iov as *mut libc::iovec as *mut libc::c_void,
+    ) < 0
+    {
+        let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+        return Err(HardwareDebugError::ReadRegisters {
+            tid,
+            message: errno_message.to_string_lossy().to_string(),
+        });
+    }
+    return Ok((state.info & 0xff) as usize);
+}
+
+// Writes the slots of a kind into the debug registers of a stopped thread. Free slots are disabled.
+pub unsafe fn write_slots(
+    tid: libc::pid_t,
+    kind: SlotKind,
+    slots: &[Option<HardwareSlot>],
+) -> Result<(), HardwareDebugError> {
+    let mut state = HardwareDebugState::default();
+    for (register, slot) in state.registers.iter_mut().zip(slots) {
+        if let Some(slot) = slot {
+            register.address = slot.address;
+            register.ctrl = slot.ctrl;
+        }
+    }
+    // Only as many registers as the hardware has may be written.
+    let mut iov = libc::iovec {
+        iov_base: &mut state as *mut HardwareDebugState as *mut libc::c_void,
+        iov_len: mem::offset_of!(HardwareDebugState, registers)
+            + slots.len() * mem::size_of::<DebugRegister>(),
+    };
+    if libc::ptrace(
+        libc::PTRACE_SETREGSET,
+        tid,
+        kind.regset(),
+        &mut iov as *mut libc::iovec as *mut libc::c_void,
+    ) < 0
+    {
+        let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+        return Err(HardwareDebugError::WriteRegisters {
+            tid,
+            message: errno_message.to_string_lossy().to_string(),
+        });
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DebugSlots, HardwareDebugError, HardwareSlot, SlotKind, WatchAccess};
+
+    #[test]
+    fn debug_slots_allocate_the_first_free_slot() {
+        let mut slots = DebugSlots::new(2, 1);
+        let slot = |address| HardwareSlot { address, ctrl: 1 };
+        assert_eq!(slots.allocate(SlotKind::Breakpoint, slot(0x1000)), Ok(0));
+        assert_eq!(slots.allocate(SlotKind::Breakpoint, slot(0x2000)), Ok(1));
+        assert_eq!(slots.free_count(SlotKind::Breakpoint), 0);
+        assert_eq!(slots.free_count(SlotKind::Watchpoint), 1);
+        assert_eq!(slots.free(SlotKind::Breakpoint, 0), Some(slot(0x1000)));
+        assert_eq!(slots.free(SlotKind::Breakpoint, 0), None);
+        assert_eq!(slots.allocate(SlotKind::Breakpoint, slot(0x3000)), Ok(0));
+        assert_eq!(
+            slots.slots(SlotKind::Breakpoint),
+            &[Some(slot(0x3000)), Some(slot(0x2000))]
+        );
+        assert!(slots.is_in_use());
+    }
+
+    #[test]
+    fn hardware_slot_breakpoint_covers_an_aligned_instruction() {
+        assert_eq!(
+            HardwareSlot::breakpoint(0x400804),
+            Some(HardwareSlot {
+                address: 0x400804,
+                ctrl: 0xf << 5 | 2 << 1 | 1
+            })
+        );
+        assert_eq!(HardwareSlot::breakpoint(0x400806), None);
+    }
+
+    #[test]
+    fn hardware_slot_watchpoint_watches_bytes_of_an_aligned_word() {
+        let slot = HardwareSlot::watchpoint(0x1008, 8, WatchAccess::Write).unwrap();
+        assert_eq!(
+            slot,
+            HardwareSlot {
+                address: 0x1008,
+                ctrl: 0xff << 5 | 2 << 3 | 2 << 1 | 1
+            }
+        );
+        let slot = HardwareSlot::watchpoint(0x100c, 4, WatchAccess::ReadWrite).unwrap();
+        assert_eq!(slot.address, 0x1008);
+        assert_eq!(slot.ctrl, 0xf0 << 5 | 3 << 3 | 2 << 1 | 1);
+        assert!(slot.watches(0x100c) && slot.watches(0x1008) && !slot.watches(0x1010));
+        assert_eq!(HardwareSlot::watchpoint(0x100c, 8, WatchAccess::Read), None);
+        assert_eq!(HardwareSlot::watchpoint(0x1008, 0, WatchAccess::Read), None);
+    }
+
+    #[test]
+    fn debug_slots_report_when_exhausted() {
+        let mut slots = DebugSlots::new(1, 0);
+        let slot = HardwareSlot {
+            address: 0x1000,
+            ctrl: 1,
+        };
+        assert!(!slots.is_in_use());
+        slots.allocate(SlotKind::Breakpoint, slot).unwrap();
+        let err = slots.allocate(SlotKind::Breakpoint, slot).unwrap_err();
+        assert_eq!(
+            err,
+            HardwareDebugError::NoFreeSlot {
+                kind: SlotKind::Breakpoint,
+                total: 1
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "all 1 hardware breakpoint slots are in use; free one, or use a software breakpoint instead"
+        );
+        assert_eq!(
+            slots.allocate(SlotKind::Watchpoint, slot),
+            Err(HardwareDebugError::Unsupported {
+                kind: SlotKind::Watchpoint
+            })
+        );
+    }
+}
@@ -21,6 +21,11 @@ impl Pipe {
 
     // Reads a string out of the pipe.
     pub unsafe fn receive(&self) -> String {
+        return String::from_utf8_lossy(&self.receive_raw()).to_string();
+    }
+
+    // Reads bytes out of the pipe.
+    pub unsafe fn receive_raw(&self) -> Vec<u8> {
         let mut buffer = vec![0; 128];
 
         let n_bytes = libc::read(
@@ -37,7 +42,8 @@ impl Pipe {
             );
         }
 
-        return String::from_utf8_lossy(&buffer[..n_bytes as usize]).to_string();
+        buffer.truncate(n_bytes as usize);
+        return buffer;
     }
 
     // Sends a string into a pipe.
@@ -51,6 +57,16 @@ impl Pipe {
         }
     }
 
+    // Sends bytes into the pipe with a single write(), which is all that a forked child may do
+    // before it execs. A failure is not reported, since there is no one left to report it to.
+    pub unsafe fn send_raw(&self, bytes: &[u8]) {
+        libc::write(
+            self.write_fd,
+            bytes.as_ptr() as *const libc::c_void,
+            bytes.len(),
+        );
+    }
+
     // Closes the receiving end of the pipe.
     pub unsafe fn close_receiver(&mut self) {
         if self.read_fd != -1 {
@@ -112,6 +128,15 @@ mod test {
         }
     }
 
+    #[test]
+    fn pipe_send_raw_and_receive_raw_succeeds() {
+        unsafe {
+            let pipe = Pipe::new();
+            pipe.send_raw(&[0xff, 0, 1]);
+            assert_eq!(pipe.receive_raw(), vec![0xff, 0, 1]);
+        }
+    }
+
     #[test]
     fn pipe_close_succeeds() {
         unsafe {
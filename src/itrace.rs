@@ -0,0 +1,90 @@
+// Instruction traces, which `itrace` records by single-stepping the tracee: a line for each
+// instruction that ran, with its address, and optionally its disassembly and the registers that it
+// changed, so that the traces of a good and a bad run can be compared with diff.
+
+// Where an instruction trace ends, unless the process does first.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TraceLimit {
+    // After this many instructions.
+    Count(usize),
+    // Once the pc reaches this address, before the instruction there runs.
+    Until(u64),
+}
+
+// The registers that instructions are checked for changes to, in the order they are shown. The pc
+// is left out, since every instruction changes it.
+fn register_values(regs: &libc::user_regs_struct) -> Vec<(String, u64)> {
+    let mut values = regs
+        .regs
+        .iter()
+        .enumerate()
+        .map(|(i, value)| (format!("x{}", i), *value))
+        .collect::<Vec<(String, u64)>>();
+    values.push(("sp".to_string(), regs.sp));
+    values.push(("pstate".to_string(), regs.pstate));
+    return values;
+}
+
+// The registers whose values differ after an instruction ran from before, with their new values.
+pub fn changed_registers(
+    before: &libc::user_regs_struct,
+    after: &libc::user_regs_struct,
+) -> Vec<(String, u64)> {
+    return register_values(before)
+        .into_iter()
+        .zip(register_values(after))
+        .filter(|((_, old), (_, new))| old != new)
+        .map(|(_, changed)| changed)
+        .collect();
+}
+
+// Formats the line of an instruction that ran, e.g.
+// `0x0000000000400530  sub sp, sp, #0x10  sp=0x7ffffff0`.
+pub fn format_step(pc: u64, text: Option<&str>, changed: &[(String, u64)]) -> String {
+    let mut line = format!("{:#018x}", pc);
+    if let Some(text) = text {
+        line.push_str(&format!("  {}", text));
+    }
+    if !changed.is_empty() {
+        let values = changed
+            .iter()
+            .map(|(name, value)| format!("{}={:#x}", name, value))
+            .collect::<Vec<String>>();
+        line.push_str(&format!("  {}", values.join(" ")));
+    }
+    return line;
+}
+
+#[cfg(test)]
+mod test {
+    use std::mem;
+
+    use super::{changed_registers, format_step};
+
+    #[test]
+    fn changed_registers_lists_only_registers_that_differ() {
+        let before: libc::user_regs_struct = unsafe { mem::zeroed() };
+        let mut after = before;
+        after.regs[0] = 1;
+        after.sp = 0x7ffffff0;
+        after.pc = 0x400534;
+        assert_eq!(
+            changed_registers(&before, &after),
+            vec![("x0".to_string(), 1), ("sp".to_string(), 0x7ffffff0)]
+        );
+        assert!(changed_registers(&before, &before).is_empty());
+    }
+
+    #[test]
+    fn format_step_shows_what_was_asked_for() {
+        assert_eq!(format_step(0x400530, None, &[]), "0x0000000000400530");
+        assert_eq!(
+            format_step(
+                0x400530,
+                Some("sub sp, sp, #0x10"),
+                &[("sp".to_string(), 0x7ffffff0), ("x0".to_string(), 0)]
+            ),
+            "0x0000000000400530  sub sp, sp, #0x10  sp=0x7ffffff0 x0=0x0"
+        );
+    }
+}
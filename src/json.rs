@@ -0,0 +1,419 @@
+use std::fmt;
+
+// The largest integer that a JSON number holds exactly, as an IEEE 754 double.
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
+// A JSON value. Objects keep their fields in order, which keeps output predictable.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid JSON at offset {offset}: {message}")]
+pub struct JsonError {
+    offset: usize,
+    message: &'static str,
+}
+
+impl Json {
+    pub fn parse(s: &str) -> Result<Json, JsonError> {
+        let mut parser = Parser {
+            chars: s.char_indices().peekable(),
+            len: s.len(),
+        };
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+        if parser.chars.peek().is_some() {
+            return Err(parser.error("trailing characters"));
+        }
+        return Ok(value);
+    }
+
+    // Builds an object out of its fields.
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        return Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        );
+    }
+
+    // Looks up a field of an object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        let Json::Object(fields) = self else {
+            return None;
+        };
+        return fields
+            .iter()
+            .find(|(field_key, _)| field_key == key)
+            .map(|(_, value)| value);
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        return match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        };
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        return match self {
+            Json::Bool(b) => Some(*b),
+            _ => None,
+        };
+    }
+
+    // The number as an integer, if it is one.
+    pub fn as_i64(&self) -> Option<i64> {
+        return match self {
+            Json::Number(n) if n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER => Some(*n as i64),
+            _ => None,
+        };
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        return self.as_i64().and_then(|n| u64::try_from(n).ok());
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        return match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        };
+    }
+}
+
+impl fmt::Display for Json {
+    // Writes the value compactly, on a single line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) if n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER => {
+                write!(f, "{}", *n as i64)
+            }
+            Json::Number(n) if n.is_finite() => write!(f, "{}", n),
+            // JSON has no infinities or NaN.
+            Json::Number(_) => write!(f, "null"),
+            Json::String(s) => write!(f, "{}", quote_json(s)),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", quote_json(key), value)?;
+                }
+                write!(f, "}}")
+            }
+        };
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Json {
+        return Json::Bool(b);
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Json {
+        return Json::String(s.to_string());
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Json {
+        return Json::String(s);
+    }
+}
+
+impl From<i64> for Json {
+    fn from(n: i64) -> Json {
+        return Json::Number(n as f64);
+    }
+}
+
+impl From<u64> for Json {
+    fn from(n: u64) -> Json {
+        return Json::Number(n as f64);
+    }
+}
+
+impl From<i32> for Json {
+    fn from(n: i32) -> Json {
+        return Json::Number(n as f64);
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Json {
+        return Json::Number(n as f64);
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(values: Vec<Json>) -> Json {
+        return Json::Array(values);
+    }
+}
+
+pub fn quote_json(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    return quoted;
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    len: usize,
+}
+
+impl Parser<'_> {
+    fn error(&mut self, message: &'static str) -> JsonError {
+        let offset = self.chars.peek().map_or(self.len, |(offset, _)| *offset);
+        return JsonError { offset, message };
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .chars
+            .next_if(|(_, c)| matches!(c, ' ' | '\t' | '\n' | '\r'))
+            .is_some()
+        {}
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), JsonError> {
+        return match self.chars.next_if(|(_, c)| *c == expected) {
+            None => Err(self.error("unexpected character")),
+            Some(_) => Ok(()),
+        };
+    }
+
+    fn expect_word(&mut self, word: &str, value: Json) -> Result<Json, JsonError> {
+        for c in word.chars() {
+            self.expect(c)?;
+        }
+        return Ok(value);
+    }
+
+    fn parse_value(&mut self) -> Result<Json, JsonError> {
+        self.skip_whitespace();
+        let Some(&(_, c)) = self.chars.peek() else {
+            return Err(self.error("unexpected end"));
+        };
+        return match c {
+            'n' => self.expect_word("null", Json::Null),
+            't' => self.expect_word("true", Json::Bool(true)),
+            'f' => self.expect_word("false", Json::Bool(false)),
+            '"' => self.parse_string().map(Json::String),
+            '[' => self.parse_array(),
+            '{' => self.parse_object(),
+            '-' | '0'..='9' => self.parse_number(),
+            _ => Err(self.error("unexpected character")),
+        };
+    }
+
+    fn parse_number(&mut self) -> Result<Json, JsonError> {
+        let mut number_str = String::new();
+        while let Some((_, c)) = self
+            .chars
+            .next_if(|(_, c)| matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+        {
+            number_str.push(c);
+        }
+        return match number_str.parse::<f64>() {
+            Err(_) => Err(self.error("invalid number")),
+            Ok(n) => Ok(Json::Number(n)),
+        };
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            let Some((_, c)) = self.chars.next() else {
+                return Err(self.error("unterminated string"));
+            };
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let Some((_, escape)) = self.chars.next() else {
+                        return Err(self.error("unterminated string"));
+                    };
+                    match escape {
+                        '"' | '\\' | '/' => s.push(escape),
+                        'b' => s.push('\u{8}'),
+                        'f' => s.push('\u{c}'),
+                        'n' => s.push('\n'),
+                        'r' => s.push('\r'),
+                        't' => s.push('\t'),
+                        'u' => s.push(self.parse_unicode_escape()?),
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    // Parses the digits of a \u escape, along with the second half of a surrogate pair.
+    fn parse_unicode_escape(&mut self) -> Result<char, JsonError> {
+        let high = self.parse_hex4()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.error("invalid escape"));
+        }
+
+        self.expect('\\')?;
+        self.expect('u')?;
+        let low = self.parse_hex4()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return Err(self.error("invalid surrogate pair"));
+        }
+        let c = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+        return char::from_u32(c).ok_or_else(|| self.error("invalid escape"));
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, JsonError> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let digit = self
+                .chars
+                .next()
+                .and_then(|(_, c)| c.to_digit(16))
+                .ok_or_else(|| self.error("invalid escape"))?;
+            value = value * 16 + digit;
+        }
+        return Ok(value);
+    }
+
+    fn parse_array(&mut self) -> Result<Json, JsonError> {
+        self.expect('[')?;
+        let mut values = vec![];
+        self.skip_whitespace();
+        if self.chars.next_if(|(_, c)| *c == ']').is_some() {
+            return Ok(Json::Array(values));
+        }
+
+        loop {
+            values.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some((_, ',')) => continue,
+                Some((_, ']')) => return Ok(Json::Array(values)),
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Json, JsonError> {
+        self.expect('{')?;
+        let mut fields = vec![];
+        self.skip_whitespace();
+        if self.chars.next_if(|(_, c)| *c == '}').is_some() {
+            return Ok(Json::Object(fields));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            fields.push((key, self.parse_value()?));
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some((_, ',')) => continue,
+                Some((_, '}')) => return Ok(Json::Object(fields)),
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{quote_json, Json};
+
+    #[test]
+    fn parse_reads_nested_values() {
+        let value = Json::parse(
+            r#" {"seq": 1, "arguments": {"lines": [3, 7], "path": "a\"b\u00e9\ud83d\ude00"},
+                "ok": true, "none": null, "ratio": -1.5e1} "#,
+        )
+        .unwrap();
+        assert_eq!(value.get("seq").and_then(Json::as_i64), Some(1));
+        let arguments = value.get("arguments").unwrap();
+        assert_eq!(
+            arguments.get("lines").and_then(Json::as_array),
+            Some(&[Json::Number(3.0), Json::Number(7.0)][..])
+        );
+        assert_eq!(
+            arguments.get("path").and_then(Json::as_str),
+            Some("a\"b\u{e9}\u{1f600}")
+        );
+        assert_eq!(value.get("ok").and_then(Json::as_bool), Some(true));
+        assert_eq!(value.get("none"), Some(&Json::Null));
+        assert_eq!(value.get("ratio"), Some(&Json::Number(-15.0)));
+        assert_eq!(value.get("missing"), None);
+    }
+
+    #[test]
+    fn parse_rejects_malformed_values() {
+        assert!(Json::parse("{\"a\": }").is_err());
+        assert!(Json::parse("[1, 2").is_err());
+        assert!(Json::parse("\"unterminated").is_err());
+        assert!(Json::parse("1 2").is_err());
+        assert!(Json::parse("nul").is_err());
+    }
+
+    #[test]
+    fn display_writes_compact_json() {
+        let value = Json::object(vec![
+            ("id", Json::from(3)),
+            ("name", Json::from("main\n")),
+            ("values", Json::from(vec![Json::from(true), Json::Null])),
+            ("half", Json::Number(0.5)),
+        ]);
+        assert_eq!(
+            value.to_string(),
+            r#"{"id":3,"name":"main\n","values":[true,null],"half":0.5}"#
+        );
+        assert_eq!(Json::parse(&value.to_string()).unwrap(), value);
+    }
+
+    #[test]
+    fn quote_json_escapes_special_characters() {
+        assert_eq!(
+            quote_json("write(1, \"a\\n\", 2)"),
+            "\"write(1, \\\"a\\\\n\\\", 2)\""
+        );
+        assert_eq!(quote_json("\x01"), "\"\\u0001\"");
+    }
+}
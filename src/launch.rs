@@ -0,0 +1,563 @@
+use std::{env, ffi::CString, path::Path};
+
+// The shell that runs the command lines of --shell.
+const SHELL_PATH: &str = "/bin/sh";
+
+// The resources that --rlimit limits, by the names that `ulimit` and prlimit(1) use.
+const RESOURCE_NAMES: &[(&str, libc::__rlimit_resource_t)] = &[
+    ("as", libc::RLIMIT_AS),
+    ("core", libc::RLIMIT_CORE),
+    ("cpu", libc::RLIMIT_CPU),
+    ("data", libc::RLIMIT_DATA),
+    ("fsize", libc::RLIMIT_FSIZE),
+    ("locks", libc::RLIMIT_LOCKS),
+    ("memlock", libc::RLIMIT_MEMLOCK),
+    ("msgqueue", libc::RLIMIT_MSGQUEUE),
+    ("nice", libc::RLIMIT_NICE),
+    ("nofile", libc::RLIMIT_NOFILE),
+    ("nproc", libc::RLIMIT_NPROC),
+    ("rss", libc::RLIMIT_RSS),
+    ("rtprio", libc::RLIMIT_RTPRIO),
+    ("rttime", libc::RLIMIT_RTTIME),
+    ("sigpending", libc::RLIMIT_SIGPENDING),
+    ("stack", libc::RLIMIT_STACK),
+];
+
+// How a program is started under the debugger, which `run` does again to restart it.
+#[derive(PartialEq, Clone, Debug)]
+pub struct LaunchOptions {
+    pub program: String,
+    pub args: Vec<String>,
+    // Changes to the debugger's own environment, by name: values to set, or None to unset.
+    pub env: Vec<(String, Option<String>)>,
+    // Whether the changes apply to an empty environment rather than the debugger's.
+    pub env_clear: bool,
+    // The directory that the program runs in, if not the debugger's.
+    pub cwd: Option<String>,
+    // The files that the program's stdin, stdout and stderr are redirected to, if any. Output
+    // files are truncated.
+    pub stdin: Option<String>,
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    // Whether the program runs on a pseudo-terminal of its own, rather than the debugger's.
+    pub pty: bool,
+    // Whether the program's addresses stay the same from run to run, by disabling ASLR for it.
+    pub no_aslr: bool,
+    // Whether the program and its arguments are a command line for the shell, which expands globs,
+    // variables and redirections, and then execs the program in its place.
+    pub shell: bool,
+    // The resource limits that the program starts with, where they differ from the debugger's.
+    pub rlimits: Vec<ResourceLimit>,
+    // Whether the program first stops at its entry point, after the dynamic linker has loaded its
+    // libraries, rather than at its first instruction after exec.
+    pub stop_at_entry: bool,
+}
+
+// A limit of a resource, e.g. RLIMIT_CORE, as setrlimit() sets it.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct ResourceLimit {
+    pub resource: libc::__rlimit_resource_t,
+    pub soft: libc::rlim_t,
+    pub hard: libc::rlim_t,
+}
+
+impl LaunchOptions {
+    pub fn new(program: &str, args: &[String]) -> LaunchOptions {
+        return LaunchOptions {
+            program: program.to_string(),
+            args: args.to_vec(),
+            env: vec![],
+            env_clear: false,
+            cwd: None,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            pty: false,
+            no_aslr: false,
+            shell: false,
+            rlimits: vec![],
+            stop_at_entry: false,
+        };
+    }
+
+    // Parses the command line of a program to launch, which starts with options like
+    // `--env NAME=value`. The program comes after them, or after `--`.
+    pub fn parse(args: &[String]) -> Result<LaunchOptions, String> {
+        let mut options = LaunchOptions::new("", &[]);
+        let mut i = 0;
+        loop {
+            let value = |name: &str| {
+                return args.get(i + 1).ok_or(format!("missing value for {}", name));
+            };
+            match args.get(i).map(String::as_str) {
+                Some("--env") => {
+                    let assignment = value("--env")?;
+                    let Some((name, value)) = parse_env_assignment(assignment) else {
+                        return Err(format!("invalid value for --env: \"{}\"", assignment));
+                    };
+                    options.set_env(name, Some(value));
+                    i += 2;
+                }
+                Some("--env-clear") => {
+                    options.env_clear = true;
+                    i += 1;
+                }
+                Some("--cwd") => {
+                    options.cwd = Some(value("--cwd")?.to_string());
+                    i += 2;
+                }
+                Some("--stdin") => {
+                    options.stdin = Some(value("--stdin")?.to_string());
+                    i += 2;
+                }
+                Some("--stdout") => {
+                    options.stdout = Some(value("--stdout")?.to_string());
+                    i += 2;
+                }
+                Some("--stderr") => {
+                    options.stderr = Some(value("--stderr")?.to_string());
+                    i += 2;
+                }
+                Some("--pty") => {
+                    options.pty = true;
+                    i += 1;
+                }
+                Some("--no-aslr") => {
+                    options.no_aslr = true;
+                    i += 1;
+                }
+                Some("--rlimit") => {
+                    for limit in parse_rlimits(value("--rlimit")?)? {
+                        options.set_rlimit(limit);
+                    }
+                    i += 2;
+                }
+                Some("--stop-at-entry") => {
+                    options.stop_at_entry = true;
+                    i += 1;
+                }
+                Some("--shell") => {
+                    options.shell = true;
+                    i += 1;
+                }
+                Some("--") => {
+                    i += 1;
+                    break;
+                }
+                _ => break,
+            }
+        }
+
+        let Some(program) = args.get(i) else {
+            return Err("missing program".to_string());
+        };
+        options.program = program.to_string();
+        options.args = args[i + 1..].to_vec();
+        return Ok(options);
+    }
+
+    // Sets or unsets a variable of the environment, replacing any earlier change to it.
+    pub fn set_env(&mut self, name: &str, value: Option<&str>) {
+        self.env.retain(|(changed_name, _)| changed_name != name);
+        self.env.push((name.to_string(), value.map(str::to_string)));
+    }
+
+    // The path of the program to exec. Relative paths are relative to the debugger's directory,
+    // even when the program runs in another, while bare names are looked up in PATH.
+    pub fn program_path(&self) -> String {
+        if self.cwd.is_none() || !self.program.contains('/') || self.program.starts_with('/') {
+            return self.program.clone();
+        }
+        return match env::current_dir() {
+            Err(_) => self.program.clone(),
+            Ok(dir) => dir.join(Path::new(&self.program)).display().to_string(),
+        };
+    }
+
+    // Sets the limit of a resource, replacing any earlier one.
+    pub fn set_rlimit(&mut self, limit: ResourceLimit) {
+        self.rlimits
+            .retain(|earlier_limit| earlier_limit.resource != limit.resource);
+        self.rlimits.push(limit);
+    }
+
+    // The program to exec and its arguments, which are those of the shell for --shell. The first
+    // is argv[0], which is the program as it was given rather than the path that it resolves to.
+    pub fn exec_args(&self) -> (String, Vec<String>) {
+        if !self.shell {
+            let mut args = vec![self.program.clone()];
+            args.extend(self.args.iter().cloned());
+            return (self.program_path(), args);
+        }
+        let command_line = [self.program.clone()]
+            .into_iter()
+            .chain(self.args.iter().cloned())
+            .collect::<Vec<String>>()
+            .join(" ");
+        return (
+            SHELL_PATH.to_string(),
+            vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                shell_command(&command_line),
+            ],
+        );
+    }
+
+    // The redirections of the standard streams, as the paths to open with the flags to open them
+    // with, and the descriptors to put them at.
+    pub fn redirections(&self) -> Vec<(CString, libc::c_int, libc::c_int)> {
+        let output_flags = libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC;
+        return [
+            (&self.stdin, libc::O_RDONLY, libc::STDIN_FILENO),
+            (&self.stdout, output_flags, libc::STDOUT_FILENO),
+            (&self.stderr, output_flags, libc::STDERR_FILENO),
+        ]
+        .into_iter()
+        .filter_map(|(path, flags, fd)| {
+            let path = CString::new(path.as_ref()?.as_str()).ok()?;
+            return Some((path, flags, fd));
+        })
+        .collect();
+    }
+
+    // The environment that the program starts with, as "NAME=value" strings for exec.
+    pub fn environment(&self) -> Vec<CString> {
+        let base = env::vars_os()
+            .filter(|_| !self.env_clear)
+            .map(|(name, value)| {
+                return (
+                    name.to_string_lossy().to_string(),
+                    value.to_string_lossy().to_string(),
+                );
+            })
+            .collect::<Vec<(String, String)>>();
+        return apply_env_changes(base, &self.env)
+            .into_iter()
+            .filter_map(|(name, value)| CString::new(format!("{}={}", name, value)).ok())
+            .collect();
+    }
+}
+
+// Applies changes to an environment, keeping the order of the variables that it already has.
+fn apply_env_changes(
+    mut vars: Vec<(String, String)>,
+    changes: &[(String, Option<String>)],
+) -> Vec<(String, String)> {
+    for (name, value) in changes {
+        match value {
+            None => vars.retain(|(var_name, _)| var_name != name),
+            Some(value) => match vars.iter_mut().find(|(var_name, _)| var_name == name) {
+                None => vars.push((name.clone(), value.clone())),
+                Some(var) => var.1 = value.clone(),
+            },
+        }
+    }
+    return vars;
+}
+
+// Makes the shell exec the program of a command line in its own place, so that the process that
+// the debugger launched becomes the program. `exec` goes after any leading assignments, as in
+// "DEBUG=1 exec ./app", which the shell exports to the program.
+fn shell_command(command_line: &str) -> String {
+    let mut word_start = None;
+    let mut quote = None;
+    let mut is_escaped = false;
+    for (i, c) in command_line.char_indices() {
+        if is_escaped {
+            is_escaped = false;
+            continue;
+        }
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => is_escaped = true,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, _) if c.is_whitespace() => {
+                // A word ends, which is an assignment if it got this far.
+                word_start = None;
+                continue;
+            }
+            (None, _) => {}
+        }
+        let start = *word_start.get_or_insert(i);
+        if start == i && !is_assignment(&command_line[i..]) {
+            return format!("{}exec {}", &command_line[..i], &command_line[i..]);
+        }
+    }
+    return command_line.to_string();
+}
+
+// Whether a word of a command line starts with "NAME=".
+fn is_assignment(word: &str) -> bool {
+    let Some((name, _)) = word.split_once('=') else {
+        return false;
+    };
+    return !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+}
+
+// Parses limits such as "core=unlimited,nofile=1024:4096", whose values are either both the soft
+// and hard limits, like `ulimit` sets them, or "<soft>:<hard>".
+pub fn parse_rlimits(spec: &str) -> Result<Vec<ResourceLimit>, String> {
+    let parse_value = |value: &str| match value {
+        "unlimited" | "infinity" => Some(libc::RLIM_INFINITY),
+        _ => value.parse::<libc::rlim_t>().ok(),
+    };
+    return spec
+        .split(',')
+        .map(|limit| {
+            let invalid = || format!("invalid resource limit: \"{}\"", limit);
+            let (name, value) = limit.split_once('=').ok_or_else(invalid)?;
+            let resource = RESOURCE_NAMES
+                .iter()
+                .find_map(|(resource_name, resource)| (*resource_name == name).then_some(*resource))
+                .ok_or_else(|| format!("unknown resource: \"{}\"", name))?;
+            let (soft, hard) = match value.split_once(':') {
+                None => (value, value),
+                Some((soft, hard)) => (soft, hard),
+            };
+            let (Some(soft), Some(hard)) = (parse_value(soft), parse_value(hard)) else {
+                return Err(invalid());
+            };
+            if soft > hard {
+                return Err(format!("soft limit exceeds hard limit: \"{}\"", limit));
+            }
+            return Ok(ResourceLimit {
+                resource,
+                soft,
+                hard,
+            });
+        })
+        .collect();
+}
+
+pub fn resource_name(resource: libc::__rlimit_resource_t) -> &'static str {
+    return RESOURCE_NAMES
+        .iter()
+        .find(|(_, named_resource)| *named_resource == resource)
+        .map_or("unknown", |(name, _)| name);
+}
+
+// Parses an assignment such as "NAME=value", whose value may be empty.
+pub fn parse_env_assignment(assignment: &str) -> Option<(&str, &str)> {
+    let (name, value) = assignment.split_once('=')?;
+    if name.is_empty() {
+        return None;
+    }
+    return Some((name, value));
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        apply_env_changes, parse_env_assignment, parse_rlimits, resource_name, shell_command,
+        LaunchOptions, ResourceLimit,
+    };
+
+    #[test]
+    fn apply_env_changes_sets_and_unsets_in_order() {
+        let vars = vec![
+            ("HOME".to_string(), "/root".to_string()),
+            ("LANG".to_string(), "C".to_string()),
+        ];
+        let mut options = LaunchOptions::new("/bin/true", &[]);
+        options.set_env("LANG", Some("en_US.UTF-8"));
+        options.set_env("DEBUG", Some("1"));
+        options.set_env("HOME", None);
+        options.set_env("DEBUG", Some("2"));
+        assert_eq!(
+            apply_env_changes(vars, &options.env),
+            vec![
+                ("LANG".to_string(), "en_US.UTF-8".to_string()),
+                ("DEBUG".to_string(), "2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn environment_includes_changes() {
+        let mut options = LaunchOptions::new("/bin/true", &[]);
+        options.set_env("PBREAK_TEST_VAR", Some("a=b"));
+        let environment = options.environment();
+        assert!(environment
+            .iter()
+            .any(|var| var.to_str().unwrap() == "PBREAK_TEST_VAR=a=b"));
+    }
+
+    #[test]
+    fn environment_of_env_clear_has_changes_only() {
+        let mut options = LaunchOptions::new("/bin/true", &[]);
+        options.env_clear = true;
+        options.set_env("A", Some("1"));
+        let environment = options.environment();
+        assert_eq!(environment.len(), 1);
+        assert_eq!(environment[0].to_str().unwrap(), "A=1");
+    }
+
+    #[test]
+    fn parse_reads_options_before_program() {
+        let args = |args: &[&str]| {
+            args.iter()
+                .map(|arg| arg.to_string())
+                .collect::<Vec<String>>()
+        };
+        let options = LaunchOptions::parse(&args(&[
+            "--env-clear",
+            "--env",
+            "A=1",
+            "--env",
+            "B=",
+            "--cwd",
+            "/tmp",
+            "--stdout",
+            "out.txt",
+            "--stdin",
+            "/dev/null",
+            "--pty",
+            "--no-aslr",
+            "--shell",
+            "--stop-at-entry",
+            "--rlimit",
+            "core=0,nofile=10",
+            "--rlimit",
+            "core=unlimited",
+            "prog",
+            "--env",
+            "x",
+        ]))
+        .unwrap();
+        assert_eq!(options.program, "prog");
+        assert_eq!(options.args, args(&["--env", "x"]));
+        assert!(options.env_clear && options.pty && options.no_aslr && options.shell);
+        assert!(options.stop_at_entry);
+        assert_eq!(
+            options
+                .rlimits
+                .iter()
+                .map(|limit| (limit.resource, limit.soft))
+                .collect::<Vec<_>>(),
+            vec![
+                (libc::RLIMIT_NOFILE, 10),
+                (libc::RLIMIT_CORE, libc::RLIM_INFINITY)
+            ]
+        );
+        assert_eq!(options.cwd.as_deref(), Some("/tmp"));
+        assert_eq!(
+            (options.stdin.as_deref(), options.stdout.as_deref()),
+            (Some("/dev/null"), Some("out.txt"))
+        );
+        let redirections = options.redirections();
+        assert_eq!(redirections.len(), 2);
+        assert_eq!(redirections[0].0.to_str().unwrap(), "/dev/null");
+        assert_eq!(redirections[1].2, libc::STDOUT_FILENO);
+        assert_eq!(
+            options.env,
+            vec![
+                ("A".to_string(), Some("1".to_string())),
+                ("B".to_string(), Some("".to_string())),
+            ]
+        );
+
+        let options = LaunchOptions::parse(&args(&["--", "--env"])).unwrap();
+        assert_eq!((options.program.as_str(), options.env.len()), ("--env", 0));
+        assert!(LaunchOptions::parse(&args(&["--env", "A"])).is_err());
+        assert!(LaunchOptions::parse(&args(&["--env"])).is_err());
+        assert!(LaunchOptions::parse(&args(&["--cwd"])).is_err());
+        assert!(LaunchOptions::parse(&args(&["--env-clear"])).is_err());
+    }
+
+    #[test]
+    fn program_path_is_relative_to_debugger_directory() {
+        let mut options = LaunchOptions::new("./app", &[]);
+        assert_eq!(options.program_path(), "./app");
+        options.cwd = Some("/tmp".to_string());
+        let dir = std::env::current_dir().unwrap();
+        assert_eq!(
+            options.program_path(),
+            dir.join("./app").display().to_string()
+        );
+        options.program = "ls".to_string();
+        assert_eq!(options.program_path(), "ls");
+    }
+
+    #[test]
+    fn exec_args_start_with_program() {
+        let args = |args: &[&str]| {
+            args.iter()
+                .map(|arg| arg.to_string())
+                .collect::<Vec<String>>()
+        };
+        let mut options = LaunchOptions::new("./app", &args(&["-v", "a b", ""]));
+        assert_eq!(
+            options.exec_args(),
+            ("./app".to_string(), args(&["./app", "-v", "a b", ""]))
+        );
+        options.cwd = Some("/tmp".to_string());
+        assert_eq!(options.exec_args().1[0], "./app");
+        assert_eq!(LaunchOptions::new("ls", &[]).exec_args().1, args(&["ls"]));
+    }
+
+    #[test]
+    fn exec_args_of_shell_run_command_line() {
+        let mut options = LaunchOptions::new("./app", &["*.txt".to_string(), ">out".to_string()]);
+        options.shell = true;
+        assert_eq!(
+            options.exec_args(),
+            (
+                "/bin/sh".to_string(),
+                vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    "exec ./app *.txt >out".to_string()
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn shell_command_execs_after_assignments() {
+        assert_eq!(shell_command("./app -v"), "exec ./app -v");
+        assert_eq!(
+            shell_command("  A=1 B='x y' C=\"\\\" z\" ./app $HOME"),
+            "  A=1 B='x y' C=\"\\\" z\" exec ./app $HOME"
+        );
+        assert_eq!(shell_command("A=1 'B=2' ./app"), "A=1 exec 'B=2' ./app");
+        assert_eq!(shell_command("1A=x ./app"), "exec 1A=x ./app");
+        assert_eq!(shell_command("A=1"), "A=1");
+    }
+
+    #[test]
+    fn parse_rlimits_reads_unlimited_and_soft_hard_pairs() {
+        assert_eq!(
+            parse_rlimits("core=unlimited,nofile=1024:4096").unwrap(),
+            vec![
+                ResourceLimit {
+                    resource: libc::RLIMIT_CORE,
+                    soft: libc::RLIM_INFINITY,
+                    hard: libc::RLIM_INFINITY,
+                },
+                ResourceLimit {
+                    resource: libc::RLIMIT_NOFILE,
+                    soft: 1024,
+                    hard: 4096,
+                },
+            ]
+        );
+        assert!(parse_rlimits("nofile=4096:1024").is_err());
+        assert!(parse_rlimits("files=10").is_err());
+        assert!(parse_rlimits("core").is_err());
+        assert!(parse_rlimits("stack=8M").is_err());
+        assert_eq!(resource_name(libc::RLIMIT_STACK), "stack");
+    }
+
+    #[test]
+    fn parse_env_assignment_splits_at_first_equals() {
+        assert_eq!(parse_env_assignment("A=b=c"), Some(("A", "b=c")));
+        assert_eq!(parse_env_assignment("A="), Some(("A", "")));
+        assert_eq!(parse_env_assignment("=b"), None);
+        assert_eq!(parse_env_assignment("A"), None);
+    }
+}
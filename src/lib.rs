@@ -1,4 +1,52 @@
+#![allow(clippy::needless_return, clippy::missing_safety_doc)]
+
+pub mod analysis;
+pub mod asm;
+pub mod auxv;
+pub mod breakpoint;
+pub mod calltrace;
+pub mod cfi;
+pub mod cleanup;
 pub mod cli;
+pub mod container;
+pub mod coredump;
+pub mod dap;
+pub mod disasm;
+pub mod dwarf;
+pub mod elf;
+pub mod event;
+pub mod expr;
+pub mod gdbserver;
+pub mod hwdebug;
 pub mod ipc;
+pub mod itrace;
+pub mod json;
+pub mod launch;
+pub mod minidump;
+pub mod net;
+pub mod perf;
+pub mod plugin;
+pub mod procfs;
+pub mod profile;
+pub mod pty;
+pub mod reaper;
+pub mod register;
+pub mod remote;
+pub mod replay;
+pub mod rpc;
+pub mod rsp;
+pub mod script;
 pub mod session;
+pub mod signal;
+pub mod solib;
+pub mod source;
+pub mod symbols;
+pub mod syscall;
+pub mod target;
+pub mod thread;
+pub mod trace_file;
 pub mod tracee;
+pub mod tui;
+pub mod unwind;
+pub mod variables;
+pub mod web;
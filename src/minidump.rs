@@ -0,0 +1,490 @@
+use std::{
+    collections::HashMap,
+    ffi::CStr,
+    fs, io, mem,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    coredump::{CoreSnapshot, CoreThread},
+    elf::ElfFile,
+    procfs,
+};
+
+const MINIDUMP_SIGNATURE: u32 = 0x504d_444d;
+const MINIDUMP_VERSION: u32 = 0xa793;
+
+const THREAD_LIST_STREAM: u32 = 3;
+const MODULE_LIST_STREAM: u32 = 4;
+const MEMORY_LIST_STREAM: u32 = 5;
+const EXCEPTION_STREAM: u32 = 6;
+const SYSTEM_INFO_STREAM: u32 = 7;
+// Breakpad's streams of Linux processes, which hold their files from /proc.
+const LINUX_CMD_LINE_STREAM: u32 = 0x4767_0006;
+const LINUX_AUXV_STREAM: u32 = 0x4767_0008;
+const LINUX_MAPS_STREAM: u32 = 0x4767_0009;
+
+const CPU_ARCHITECTURE_ARM64: u16 = 12;
+const OS_LINUX: u32 = 0x8201;
+// The control, integer, and floating-point registers of an ARM64 context.
+const CONTEXT_ARM64_FULL: u32 = 0x0040_0007;
+// "BpEL", which marks a CodeView record that holds the build ID of an ELF file.
+const CV_ELF_SIGNATURE: u32 = 0x4270_454c;
+const NT_GNU_BUILD_ID: u32 = 3;
+
+// Sizes of the structures, as laid out in the file.
+const HEADER_SIZE: usize = 32;
+const DIRECTORY_ENTRY_SIZE: usize = 12;
+const CONTEXT_ARM64_SIZE: usize = 912;
+const FIXED_FILE_INFO_SIZE: usize = 52;
+
+// The most of a thread's stack that is kept, from its stack pointer up.
+const MAX_STACK_SIZE: u64 = 0x8000;
+// How much code around the pc of the thread that crashed is kept, for symbolicators that
+// disassemble it.
+const CRASH_CODE_SIZE: u64 = 0x100;
+
+// A mapped ELF file, which symbolicators look up by its build ID.
+#[derive(PartialEq, Clone, Debug)]
+pub struct MinidumpModule {
+    pub path: String,
+    pub base: u64,
+    pub size: u64,
+}
+
+// A range of memory that has been written to the file.
+struct MemoryDescriptor {
+    start: u64,
+    size: u32,
+    rva: u32,
+}
+
+// Lays out a minidump: its header and stream directory come first, and are filled in as streams
+// are written after them.
+struct MinidumpWriter {
+    data: Vec<u8>,
+    stream_count: usize,
+}
+
+impl MinidumpWriter {
+    fn new(stream_count: usize, timestamp: u32) -> MinidumpWriter {
+        let mut data = vec![];
+        push_u32(&mut data, MINIDUMP_SIGNATURE);
+        push_u32(&mut data, MINIDUMP_VERSION);
+        push_u32(&mut data, stream_count as u32);
+        push_u32(&mut data, HEADER_SIZE as u32);
+        push_u32(&mut data, 0);
+        push_u32(&mut data, timestamp);
+        push_u64(&mut data, 0);
+        data.resize(HEADER_SIZE + stream_count * DIRECTORY_ENTRY_SIZE, 0);
+        return MinidumpWriter {
+            data,
+            stream_count: 0,
+        };
+    }
+
+    // Appends bytes at an 8-byte aligned offset, returning their size and offset.
+    fn append(&mut self, bytes: &[u8]) -> (u32, u32) {
+        self.data.resize(self.data.len().next_multiple_of(8), 0);
+        let rva = self.data.len() as u32;
+        self.data.extend_from_slice(bytes);
+        return (bytes.len() as u32, rva);
+    }
+
+    // Appends a string as UTF-16, after its size in bytes and before a NUL.
+    fn append_string(&mut self, string: &str) -> u32 {
+        let mut bytes = vec![];
+        let chars = string.encode_utf16().collect::<Vec<u16>>();
+        push_u32(&mut bytes, (chars.len() * 2) as u32);
+        for c in chars.into_iter().chain([0]) {
+            bytes.extend_from_slice(&c.to_le_bytes());
+        }
+        return self.append(&bytes).1;
+    }
+
+    fn append_memory(&mut self, start: u64, bytes: &[u8]) -> MemoryDescriptor {
+        let (size, rva) = self.append(bytes);
+        return MemoryDescriptor { start, size, rva };
+    }
+
+    fn append_stream(&mut self, kind: u32, bytes: &[u8]) {
+        let (size, rva) = self.append(bytes);
+        let entry = HEADER_SIZE + self.stream_count * DIRECTORY_ENTRY_SIZE;
+        self.data[entry..entry + 4].copy_from_slice(&kind.to_le_bytes());
+        self.data[entry + 4..entry + 8].copy_from_slice(&size.to_le_bytes());
+        self.data[entry + 8..entry + 12].copy_from_slice(&rva.to_le_bytes());
+        self.stream_count += 1;
+    }
+}
+
+// Encodes a snapshot as a minidump, which Breakpad, Crashpad, and the crash reporters built on
+// them can symbolicate, given the build IDs of the modules by their paths.
+pub fn encode_minidump(
+    snapshot: &CoreSnapshot,
+    modules: &[MinidumpModule],
+    build_ids: &HashMap<String, Vec<u8>>,
+    timestamp: u32,
+) -> Vec<u8> {
+    // The thread that stopped the process comes first, and crashed if it has a signal.
+    let crashed = snapshot.threads.first().filter(|thread| thread.signal != 0);
+    let stream_count = 7 + crashed.is_some() as usize;
+    let mut writer = MinidumpWriter::new(stream_count, timestamp);
+
+    let mut memory = vec![];
+    let mut threads = vec![];
+    push_u32(&mut threads, snapshot.threads.len() as u32);
+    let mut crashed_context = (0, 0);
+    for thread in &snapshot.threads {
+        let context = writer.append(&encode_context(thread));
+        if crashed.is_some_and(|crashed| crashed.tid == thread.tid) {
+            crashed_context = context;
+        }
+        let sp = thread.regs[31];
+        let stack = read_memory_within(snapshot, sp, sp.saturating_add(MAX_STACK_SIZE));
+        let stack = writer.append_memory(sp, &stack);
+        push_u32(&mut threads, thread.tid as u32);
+        // The suspend count, priority class, priority, and thread environment block.
+        push_u32(&mut threads, 0);
+        push_u32(&mut threads, 0);
+        push_u32(&mut threads, 0);
+        push_u64(&mut threads, 0);
+        push_memory_descriptor(&mut threads, &stack);
+        push_u32(&mut threads, context.0);
+        push_u32(&mut threads, context.1);
+        memory.push(stack);
+    }
+
+    if let Some(crashed) = crashed {
+        let pc = crashed.regs[32];
+        let segment_start = snapshot
+            .segments
+            .iter()
+            .find(|segment| segment.start <= pc && pc < segment.end)
+            .map_or(pc, |segment| segment.start);
+        let start = pc.saturating_sub(CRASH_CODE_SIZE / 2).max(segment_start);
+        let code = read_memory_within(snapshot, start, start + CRASH_CODE_SIZE);
+        if !code.is_empty() {
+            memory.push(writer.append_memory(start, &code));
+        }
+    }
+
+    let system_info = encode_system_info(&mut writer);
+    let module_list = encode_module_list(&mut writer, modules, build_ids);
+
+    writer.append_stream(SYSTEM_INFO_STREAM, &system_info);
+    writer.append_stream(THREAD_LIST_STREAM, &threads);
+    writer.append_stream(MODULE_LIST_STREAM, &module_list);
+    let mut memory_list = vec![];
+    push_u32(&mut memory_list, memory.len() as u32);
+    for descriptor in &memory {
+        push_memory_descriptor(&mut memory_list, descriptor);
+    }
+    writer.append_stream(MEMORY_LIST_STREAM, &memory_list);
+    if let Some(crashed) = crashed {
+        // Linux has no exception codes, so the signal stands in for one, as Breakpad does.
+        let mut exception = vec![];
+        push_u32(&mut exception, crashed.tid as u32);
+        push_u32(&mut exception, 0);
+        push_u32(&mut exception, crashed.signal as u32);
+        push_u32(&mut exception, 0);
+        push_u64(&mut exception, 0);
+        push_u64(&mut exception, crashed.regs[32]);
+        exception.resize(exception.len() + 8 + 15 * 8, 0);
+        push_u32(&mut exception, crashed_context.0);
+        push_u32(&mut exception, crashed_context.1);
+        writer.append_stream(EXCEPTION_STREAM, &exception);
+    }
+
+    let mut cmdline = vec![];
+    for arg in &snapshot.args {
+        cmdline.extend_from_slice(arg.as_bytes());
+        cmdline.push(0);
+    }
+    writer.append_stream(LINUX_CMD_LINE_STREAM, &cmdline);
+    writer.append_stream(LINUX_AUXV_STREAM, &snapshot.auxv);
+    let maps = snapshot
+        .mappings
+        .iter()
+        .map(|mapping| {
+            return format!(
+                "{:x}-{:x} {} {:08x} 00:00 0 {}\n",
+                mapping.start, mapping.end, mapping.permissions, mapping.offset, mapping.path
+            );
+        })
+        .collect::<String>();
+    writer.append_stream(LINUX_MAPS_STREAM, maps.as_bytes());
+    return writer.data;
+}
+
+// Finds the ELF files that the snapshot maps, by where their headers are mapped.
+pub fn find_modules(snapshot: &CoreSnapshot) -> Vec<MinidumpModule> {
+    let mut modules = vec![];
+    for mapping in &snapshot.mappings {
+        if !mapping.path.starts_with('/') || mapping.offset != 0 {
+            continue;
+        }
+        if modules
+            .iter()
+            .any(|module: &MinidumpModule| module.path == mapping.path)
+        {
+            continue;
+        }
+        if snapshot.read_memory(mapping.start, 4).ok().as_deref() != Some(b"\x7fELF") {
+            continue;
+        }
+        let end = snapshot
+            .mappings
+            .iter()
+            .filter(|other| other.path == mapping.path)
+            .map(|other| other.end)
+            .max()
+            .unwrap_or(mapping.end);
+        modules.push(MinidumpModule {
+            path: mapping.path.clone(),
+            base: mapping.start,
+            size: end - mapping.start,
+        });
+    }
+    return modules;
+}
+
+// Reads the GNU build ID of an ELF file, which symbol servers index debug files by.
+pub fn read_build_id(path: &Path) -> Option<Vec<u8>> {
+    let elf = ElfFile::read(path).ok()?;
+    let note = elf.section_data(".note.gnu.build-id").ok()??;
+    let name_size = u32::from_le_bytes(note.get(0..4)?.try_into().ok()?) as usize;
+    let description_size = u32::from_le_bytes(note.get(4..8)?.try_into().ok()?) as usize;
+    let kind = u32::from_le_bytes(note.get(8..12)?.try_into().ok()?);
+    if kind != NT_GNU_BUILD_ID {
+        return None;
+    }
+    let description_offset = (12 + name_size).next_multiple_of(4);
+    return Some(
+        note.get(description_offset..description_offset + description_size)?
+            .to_vec(),
+    );
+}
+
+// Writes a minidump of a snapshot. Returns how many modules it lists.
+pub fn write_minidump(snapshot: &CoreSnapshot, path: &Path) -> io::Result<usize> {
+    let modules = find_modules(snapshot);
+    // The modules of a process in a container are read from its root.
+    let root = procfs::read_root(snapshot.pid);
+    let build_ids = modules
+        .iter()
+        .filter_map(|module| {
+            let path = procfs::resolve_path(root.as_deref(), Path::new(&module.path));
+            let build_id = read_build_id(&path)?;
+            return Some((module.path.clone(), build_id));
+        })
+        .collect::<HashMap<String, Vec<u8>>>();
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as u32);
+    fs::write(
+        path,
+        encode_minidump(snapshot, &modules, &build_ids, timestamp),
+    )?;
+    return Ok(modules.len());
+}
+
+// Reads memory from `start` up to `end`, or up to the end of the segment that holds `start`,
+// whichever comes first. Returns nothing if `start` is not in a segment.
+fn read_memory_within(snapshot: &CoreSnapshot, start: u64, end: u64) -> Vec<u8> {
+    let Some(segment) = snapshot
+        .segments
+        .iter()
+        .find(|segment| segment.start <= start && start < segment.end)
+    else {
+        return vec![];
+    };
+    let len = (end.min(segment.end) - start) as usize;
+    return snapshot.read_memory(start, len).unwrap_or_default();
+}
+
+// Encodes the registers of a thread as the ARM64 `CONTEXT` of Windows, which minidumps use on
+// every OS.
+fn encode_context(thread: &CoreThread) -> Vec<u8> {
+    let mut context = vec![];
+    push_u32(&mut context, CONTEXT_ARM64_FULL);
+    push_u32(&mut context, thread.regs[33] as u32);
+    // x0 to x30, sp, and pc.
+    for reg in &thread.regs[..33] {
+        push_u64(&mut context, *reg);
+    }
+    // The vector registers, then fpsr and fpcr, in the order of `user_fpsimd_struct`.
+    match thread.fp_regs.len() >= 520 {
+        true => {
+            context.extend_from_slice(&thread.fp_regs[..512]);
+            context.extend_from_slice(&thread.fp_regs[516..520]);
+            context.extend_from_slice(&thread.fp_regs[512..516]);
+        }
+        false => context.resize(context.len() + 520, 0),
+    }
+    // The debug registers are left out.
+    context.resize(CONTEXT_ARM64_SIZE, 0);
+    return context;
+}
+
+// Describes the machine that pbreak runs on, which is the one that the process ran on.
+fn encode_system_info(writer: &mut MinidumpWriter) -> Vec<u8> {
+    let mut utsname = unsafe { mem::zeroed::<libc::utsname>() };
+    unsafe { libc::uname(&mut utsname) };
+    let field = |chars: &[libc::c_char]| unsafe {
+        return CStr::from_ptr(chars.as_ptr()).to_string_lossy().to_string();
+    };
+    let release = field(&utsname.release);
+    let description = format!(
+        "{} {} {} {}",
+        field(&utsname.sysname),
+        release,
+        field(&utsname.version),
+        field(&utsname.machine)
+    );
+    // The kernel's release, e.g. "6.1.0-13-arm64", as its major, minor, and patch versions.
+    let mut versions = release
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|part| part.parse::<u32>().unwrap_or(0));
+    let processor_count = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) }.clamp(0, 255);
+
+    let description_rva = writer.append_string(&description);
+    let mut system_info = vec![];
+    push_u16(&mut system_info, CPU_ARCHITECTURE_ARM64);
+    push_u16(&mut system_info, 0);
+    push_u16(&mut system_info, 0);
+    system_info.push(processor_count as u8);
+    system_info.push(0);
+    push_u32(&mut system_info, versions.next().unwrap_or(0));
+    push_u32(&mut system_info, versions.next().unwrap_or(0));
+    push_u32(&mut system_info, versions.next().unwrap_or(0));
+    push_u32(&mut system_info, OS_LINUX);
+    push_u32(&mut system_info, description_rva);
+    push_u16(&mut system_info, 0);
+    push_u16(&mut system_info, 0);
+    system_info.resize(system_info.len() + 24, 0);
+    return system_info;
+}
+
+fn encode_module_list(
+    writer: &mut MinidumpWriter,
+    modules: &[MinidumpModule],
+    build_ids: &HashMap<String, Vec<u8>>,
+) -> Vec<u8> {
+    let mut module_list = vec![];
+    push_u32(&mut module_list, modules.len() as u32);
+    for module in modules {
+        let name_rva = writer.append_string(&module.path);
+        let cv_record = match build_ids.get(&module.path) {
+            None => (0, 0),
+            Some(build_id) => {
+                let mut record = vec![];
+                push_u32(&mut record, CV_ELF_SIGNATURE);
+                record.extend_from_slice(build_id);
+                writer.append(&record)
+            }
+        };
+        push_u64(&mut module_list, module.base);
+        push_u32(&mut module_list, module.size as u32);
+        // The checksum and timestamp, which ELF files do not have.
+        push_u32(&mut module_list, 0);
+        push_u32(&mut module_list, 0);
+        push_u32(&mut module_list, name_rva);
+        module_list.resize(module_list.len() + FIXED_FILE_INFO_SIZE, 0);
+        push_u32(&mut module_list, cv_record.0);
+        push_u32(&mut module_list, cv_record.1);
+        // The misc record, and two reserved fields.
+        module_list.resize(module_list.len() + 8 + 16, 0);
+    }
+    return module_list;
+}
+
+fn push_memory_descriptor(data: &mut Vec<u8>, descriptor: &MemoryDescriptor) {
+    push_u64(data, descriptor.start);
+    push_u32(data, descriptor.size);
+    push_u32(data, descriptor.rva);
+}
+
+fn push_u16(data: &mut Vec<u8>, value: u16) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u64(data: &mut Vec<u8>, value: u64) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{encode_minidump, find_modules, MinidumpModule};
+    use crate::coredump::test::{sample_snapshot, u32_at, u64_at};
+
+    #[test]
+    fn find_modules_lists_mapped_elf_files() {
+        assert_eq!(
+            find_modules(&sample_snapshot()),
+            vec![MinidumpModule {
+                path: "/bin/prog".to_string(),
+                base: 0x400000,
+                size: 0x1000,
+            }]
+        );
+    }
+
+    #[test]
+    fn encode_minidump_writes_threads_modules_memory_and_exception() {
+        let snapshot = sample_snapshot();
+        let modules = find_modules(&snapshot);
+        let build_ids = HashMap::from([("/bin/prog".to_string(), vec![0x12, 0x34])]);
+        let dump = encode_minidump(&snapshot, &modules, &build_ids, 7);
+        assert_eq!(&dump[..4], b"MDMP");
+        assert_eq!(u32_at(&dump, 20), 7);
+        let stream_count = u32_at(&dump, 8) as usize;
+        let directory = u32_at(&dump, 12) as usize;
+        let streams = (0..stream_count)
+            .map(|i| {
+                let entry = directory + i * 12;
+                let kind = u32_at(&dump, entry);
+                return (kind, u32_at(&dump, entry + 8) as usize);
+            })
+            .collect::<HashMap<u32, usize>>();
+        assert_eq!(streams.len(), 8);
+
+        // The thread's stack runs from its sp to the end of its segment, and its context holds
+        // its pc.
+        let thread = streams[&3] + 4;
+        assert_eq!(u32_at(&dump, streams[&3]), 1);
+        assert_eq!(u32_at(&dump, thread), 10);
+        assert_eq!(u64_at(&dump, thread + 24), 0x7ff000);
+        assert_eq!(u32_at(&dump, thread + 32), 0x1000);
+        let stack = u32_at(&dump, thread + 36) as usize;
+        assert_eq!(dump[stack], 0xbb);
+        let context = u32_at(&dump, thread + 44) as usize;
+        assert_eq!(u64_at(&dump, context + 8 + 32 * 8), 0x400010);
+
+        // The stack and the code around the pc are listed as memory.
+        assert_eq!(u32_at(&dump, streams[&5]), 2);
+        assert_eq!(u64_at(&dump, streams[&5] + 4 + 16), 0x400000);
+
+        let module = streams[&4] + 4;
+        assert_eq!(u32_at(&dump, streams[&4]), 1);
+        assert_eq!(u64_at(&dump, module), 0x400000);
+        let name = u32_at(&dump, module + 20) as usize;
+        assert_eq!(u32_at(&dump, name), 18);
+        assert_eq!(&dump[name + 4..name + 8], &[b'/', 0, b'b', 0]);
+        let cv_record = u32_at(&dump, module + 24 + 52 + 4) as usize;
+        assert_eq!(&dump[cv_record..cv_record + 6], b"LEpB\x12\x34");
+
+        assert_eq!(u32_at(&dump, streams[&6]), 10);
+        assert_eq!(u32_at(&dump, streams[&6] + 8), libc::SIGSEGV as u32);
+        assert_eq!(
+            &dump[streams[&0x4767_0006]..streams[&0x4767_0006] + 8],
+            b"prog\0-v\0"
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    fs,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+// The socket tables of procfs, which are named after the protocols of their sockets.
+const INET_TABLES: &[&str] = &["tcp", "tcp6", "udp", "udp6"];
+
+// Set in the flags of listening Unix sockets.
+const UNIX_ACCEPTING: u64 = 0x10000;
+
+// A socket of the network namespace of a process, as procfs lists it.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Socket {
+    pub inode: u64,
+    // e.g. "tcp", "udp6" or "unix".
+    pub protocol: &'static str,
+    // The bound address, or path for Unix sockets, which may have none.
+    pub local: String,
+    // The address of the peer, if connected. Unix sockets do not show theirs.
+    pub remote: Option<String>,
+    // e.g. "ESTABLISHED" or "LISTEN".
+    pub state: &'static str,
+}
+
+// Reads the sockets of the network namespace that the process is in. The tables that cannot be
+// read, e.g. those of IPv6 where it is disabled, are skipped.
+pub fn read_sockets(pid: libc::pid_t) -> Vec<Socket> {
+    let mut sockets = vec![];
+    for protocol in INET_TABLES {
+        if let Ok(lines) = fs::read_to_string(format!("/proc/{}/net/{}", pid, protocol)) {
+            sockets.extend(parse_inet_table(&lines, protocol));
+        }
+    }
+    if let Ok(lines) = fs::read_to_string(format!("/proc/{}/net/unix", pid)) {
+        sockets.extend(parse_unix_table(&lines));
+    }
+    return sockets;
+}
+
+// Parses a table such as /proc/net/tcp, whose lines are formatted as "<slot>: <local address>
+// <remote address> <state> ... <inode> ...", after a header.
+fn parse_inet_table(table: &str, protocol: &'static str) -> Vec<Socket> {
+    return table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<&str>>();
+            let local = parse_inet_address(fields.get(1)?)?;
+            let remote = parse_inet_address(fields.get(2)?)?;
+            let state = u8::from_str_radix(fields.get(3)?, 16).ok()?;
+            let inode = fields.get(9)?.parse::<u64>().ok()?;
+            let is_connected = remote.port() != 0;
+            return Some(Socket {
+                inode,
+                protocol,
+                local: local.to_string(),
+                remote: is_connected.then(|| remote.to_string()),
+                state: match protocol.starts_with("udp") {
+                    // UDP sockets are "established" once connected, and "closed" otherwise.
+                    true if !is_connected => "UNCONNECTED",
+                    _ => tcp_state_name(state),
+                },
+            });
+        })
+        .collect();
+}
+
+// Parses an address such as "0100007F:1F90", whose IP address is hex of the words of its bytes, as
+// the kernel stores them, and whose port is plain hex.
+fn parse_inet_address(address: &str) -> Option<SocketAddr> {
+    let (ip, port) = address.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let mut bytes = vec![];
+    for i in (0..ip.len()).step_by(8) {
+        let word = u32::from_str_radix(ip.get(i..i + 8)?, 16).ok()?;
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+    return match bytes.len() {
+        4 => Some(SocketAddr::new(
+            Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?).into(),
+            port,
+        )),
+        16 => Some(SocketAddr::new(
+            Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?).into(),
+            port,
+        )),
+        _ => None,
+    };
+}
+
+fn tcp_state_name(state: u8) -> &'static str {
+    return match state {
+        1 => "ESTABLISHED",
+        2 => "SYN_SENT",
+        3 => "SYN_RECV",
+        4 => "FIN_WAIT1",
+        5 => "FIN_WAIT2",
+        6 => "TIME_WAIT",
+        7 => "CLOSE",
+        8 => "CLOSE_WAIT",
+        9 => "LAST_ACK",
+        10 => "LISTEN",
+        11 => "CLOSING",
+        12 => "NEW_SYN_RECV",
+        _ => "UNKNOWN",
+    };
+}
+
+// Parses /proc/net/unix, whose lines are formatted as "<slot>: <refcount> <protocol> <flags>
+// <type> <state> <inode> [path]", after a header. Abstract paths start with "@".
+fn parse_unix_table(table: &str) -> Vec<Socket> {
+    return table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<&str>>();
+            let flags = u64::from_str_radix(fields.get(3)?, 16).ok()?;
+            let state = u8::from_str_radix(fields.get(5)?, 16).ok()?;
+            let inode = fields.get(6)?.parse::<u64>().ok()?;
+            let state = match state {
+                _ if flags & UNIX_ACCEPTING != 0 => "LISTEN",
+                1 => "UNCONNECTED",
+                2 => "CONNECTING",
+                3 => "CONNECTED",
+                4 => "DISCONNECTING",
+                _ => "UNKNOWN",
+            };
+            return Some(Socket {
+                inode,
+                protocol: "unix",
+                local: fields.get(7).unwrap_or(&"").to_string(),
+                remote: None,
+                state,
+            });
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_inet_address, parse_inet_table, parse_unix_table, read_sockets, Socket};
+
+    #[test]
+    fn parse_inet_address_reads_ipv4_and_ipv6() {
+        let ipv4 = format!("{:08X}:1F90", u32::from_ne_bytes([127, 0, 0, 1]));
+        assert_eq!(
+            parse_inet_address(&ipv4).unwrap().to_string(),
+            "127.0.0.1:8080"
+        );
+        let loopback = u32::from_ne_bytes([0, 0, 0, 1]);
+        let ipv6 = format!("{:08X}{:08X}{:08X}{:08X}:0050", 0, 0, 0, loopback);
+        assert_eq!(parse_inet_address(&ipv6).unwrap().to_string(), "[::1]:80");
+        assert_eq!(parse_inet_address("0100007F"), None);
+    }
+
+    #[test]
+    fn parse_inet_table_reads_states_and_inodes() {
+        let local = u32::from_ne_bytes([10, 0, 0, 2]);
+        let remote = u32::from_ne_bytes([10, 0, 0, 3]);
+        let table = format!(
+            "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n\
+             0: {:08X}:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 111 1 0 100 0 0 10 0\n\
+             1: {:08X}:9C40 {:08X}:0050 01 00000000:00000000 00:00000000 00000000  1000        0 222 1 0 20 4 30 10 -1\n",
+            local, local, remote
+        );
+        assert_eq!(
+            parse_inet_table(&table, "tcp"),
+            vec![
+                Socket {
+                    inode: 111,
+                    protocol: "tcp",
+                    local: "10.0.0.2:8080".to_string(),
+                    remote: None,
+                    state: "LISTEN",
+                },
+                Socket {
+                    inode: 222,
+                    protocol: "tcp",
+                    local: "10.0.0.2:40000".to_string(),
+                    remote: Some("10.0.0.3:80".to_string()),
+                    state: "ESTABLISHED",
+                },
+            ]
+        );
+        assert_eq!(
+            parse_inet_table(&table.replace(" 0A ", " 07 "), "udp")[0].state,
+            "UNCONNECTED"
+        );
+    }
+
+    #[test]
+    fn parse_unix_table_reads_paths_and_listeners() {
+        let table = "Num       RefCount Protocol Flags    Type St Inode Path\n\
+                     0000000000000000: 00000002 00000000 00010000 0001 01 333 /run/app.sock\n\
+                     0000000000000000: 00000003 00000000 00000000 0001 03 444\n";
+        let sockets = parse_unix_table(table);
+        assert_eq!(sockets.len(), 2);
+        assert_eq!(
+            (
+                sockets[0].inode,
+                sockets[0].local.as_str(),
+                sockets[0].state
+            ),
+            (333, "/run/app.sock", "LISTEN")
+        );
+        assert_eq!(
+            (
+                sockets[1].inode,
+                sockets[1].local.as_str(),
+                sockets[1].state
+            ),
+            (444, "", "CONNECTED")
+        );
+    }
+
+    #[test]
+    fn read_sockets_includes_listening_socket_of_current_process() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let sockets = read_sockets(std::process::id() as libc::pid_t);
+        assert!(sockets
+            .iter()
+            .any(|socket| socket.local == address && socket.state == "LISTEN"));
+    }
+}
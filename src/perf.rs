@@ -0,0 +1,201 @@
+use std::{ffi::CStr, mem};
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+const PERF_COUNT_HW_BRANCH_MISSES: u64 = 5;
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+// Bits of the flags of perf_event_attr.
+const PERF_ATTR_INHERIT: u64 = 1 << 1;
+const PERF_ATTR_EXCLUDE_KERNEL: u64 = 1 << 5;
+const PERF_ATTR_EXCLUDE_HV: u64 = 1 << 6;
+
+// The first version of perf_event_attr (PERF_ATTR_SIZE_VER1), which has every field that is
+// needed for counting (see linux/perf_event.h).
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    kind: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum PerfCounterKind {
+    Instructions,
+    Cycles,
+    BranchMisses,
+}
+
+impl PerfCounterKind {
+    pub fn from_name(name: &str) -> Option<PerfCounterKind> {
+        return match name {
+            "instructions" => Some(PerfCounterKind::Instructions),
+            "cycles" => Some(PerfCounterKind::Cycles),
+            "branch-misses" => Some(PerfCounterKind::BranchMisses),
+            _ => None,
+        };
+    }
+
+    // Parses a comma-separated list of counter names, e.g. "instructions,cycles".
+    pub fn from_names(names: &str) -> Option<Vec<PerfCounterKind>> {
+        return names.split(',').map(PerfCounterKind::from_name).collect();
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            PerfCounterKind::Instructions => "instructions",
+            PerfCounterKind::Cycles => "cycles",
+            PerfCounterKind::BranchMisses => "branch-misses",
+        };
+    }
+
+    fn config(&self) -> u64 {
+        return match self {
+            PerfCounterKind::Instructions => PERF_COUNT_HW_INSTRUCTIONS,
+            PerfCounterKind::Cycles => PERF_COUNT_HW_CPU_CYCLES,
+            PerfCounterKind::BranchMisses => PERF_COUNT_HW_BRANCH_MISSES,
+        };
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PerfError {
+    #[error("failed to open {counter} counter of thread ({tid}): {message}")]
+    Open {
+        counter: &'static str,
+        tid: libc::pid_t,
+        message: String,
+    },
+}
+
+struct PerfCounter {
+    kind: PerfCounterKind,
+    // One file descriptor per thread that was alive when counting began. Threads that they clone
+    // later are counted along with them.
+    fds: Vec<libc::c_int>,
+    last_total: u64,
+}
+
+// Hardware performance counters of a tracee, counting what it does in user space.
+pub struct PerfCounters {
+    counters: Vec<PerfCounter>,
+}
+
+impl PerfCounters {
+    pub unsafe fn open(
+        tids: &[libc::pid_t],
+        kinds: &[PerfCounterKind],
+    ) -> Result<PerfCounters, PerfError> {
+        // Whatever was opened so far is closed on failure, when this is dropped.
+        let mut counters = PerfCounters { counters: vec![] };
+        for kind in kinds {
+            counters.counters.push(PerfCounter {
+                kind: *kind,
+                fds: vec![],
+                last_total: 0,
+            });
+            for tid in tids {
+                let fd = open_counter(*kind, *tid)?;
+                counters.counters.last_mut().unwrap().fds.push(fd);
+            }
+        }
+        return Ok(counters);
+    }
+
+    // Reads how much each counter has advanced since it was last read.
+    pub unsafe fn read_deltas(&mut self) -> Vec<(PerfCounterKind, u64)> {
+        let mut deltas = vec![];
+        for counter in self.counters.iter_mut() {
+            let total = counter.fds.iter().map(|fd| read_counter(*fd)).sum::<u64>();
+            deltas.push((counter.kind, total.wrapping_sub(counter.last_total)));
+            counter.last_total = total;
+        }
+        return deltas;
+    }
+}
+
+impl Drop for PerfCounters {
+    fn drop(&mut self) {
+        for counter in &self.counters {
+            for fd in &counter.fds {
+                unsafe {
+                    libc::close(*fd);
+                }
+            }
+        }
+    }
+}
+
+unsafe fn open_counter(kind: PerfCounterKind, tid: libc::pid_t) -> Result<libc::c_int, PerfError> {
+    let attr = PerfEventAttr {
+        kind: PERF_TYPE_HARDWARE,
+        size: mem::size_of::<PerfEventAttr>() as u32,
+        config: kind.config(),
+        // Counting only user space works without privileges in the default perf_event_paranoid.
+        flags: PERF_ATTR_INHERIT | PERF_ATTR_EXCLUDE_KERNEL | PERF_ATTR_EXCLUDE_HV,
+        ..PerfEventAttr::default()
+    };
+    let fd = libc::syscall(
+        libc::SYS_perf_event_open,
+        &attr as *const PerfEventAttr,
+        tid,
+        -1 as libc::c_int,
+        -1 as libc::c_int,
+        PERF_FLAG_FD_CLOEXEC,
+    );
+    if fd < 0 {
+        let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+        return Err(PerfError::Open {
+            counter: kind.name(),
+            tid,
+            message: errno_message.to_string_lossy().to_string(),
+        });
+    }
+    return Ok(fd as libc::c_int);
+}
+
+unsafe fn read_counter(fd: libc::c_int) -> u64 {
+    let mut value: u64 = 0;
+    if libc::read(
+        fd,
+        &mut value as *mut u64 as *mut libc::c_void,
+        mem::size_of::<u64>(),
+    ) < 0
+    {
+        let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+        panic!("failed to read perf counter: {:?}", errno_message);
+    }
+    return value;
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PerfCounterKind, PerfEventAttr};
+
+    #[test]
+    fn perf_event_attr_has_size_of_first_version() {
+        assert_eq!(std::mem::size_of::<PerfEventAttr>(), 72);
+    }
+
+    #[test]
+    fn perf_counter_kind_from_names_rejects_unknown_counters() {
+        assert_eq!(
+            PerfCounterKind::from_names("instructions,branch-misses"),
+            Some(vec![
+                PerfCounterKind::Instructions,
+                PerfCounterKind::BranchMisses
+            ])
+        );
+        assert_eq!(PerfCounterKind::from_names("instructions,cache"), None);
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    ffi::{CStr, CString},
+    fs, io,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
+
+use crate::{
+    script::{ScriptAction, ScriptEngine},
+    trace_file::TraceRecord,
+    tracee::Tracee,
+};
+
+// The function that a plugin library exports to construct its plugin, e.g.:
+//
+//     #[no_mangle]
+//     pub fn pbreak_plugin_create() -> Box<dyn pbreak::plugin::Plugin> { ... }
+//
+// Trait objects have no stable ABI, so the library must be built by the same compiler, against
+// the same version of pbreak.
+const CREATE_SYMBOL: &CStr = c"pbreak_plugin_create";
+
+type CreatePlugin = fn() -> Box<dyn Plugin>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PluginError {
+    #[error("failed to read plugin {path}: {source}")]
+    Read { path: String, source: io::Error },
+    #[error("failed to load plugin {path}: {message}")]
+    Load { path: String, message: String },
+    #[error("plugin {path} failed: {message}")]
+    Script { path: String, message: String },
+}
+
+// Extends a session with commands and handlers of what tracees record, e.g. their stops. Plugins
+// ask the session for the same actions as scripts do.
+pub trait Plugin {
+    fn name(&self) -> &str;
+    // The commands that the plugin adds, by their first word.
+    fn commands(&self) -> Vec<String>;
+
+    // Handles one of the plugin's commands, given its words.
+    unsafe fn handle_command(&mut self, tracee: &mut Tracee, words: &[&str]) -> Vec<ScriptAction>;
+
+    // Handles something that a tracee recorded, e.g. a stop.
+    unsafe fn handle_record(
+        &mut self,
+        _tracee: &mut Tracee,
+        _record: &TraceRecord,
+    ) -> Vec<ScriptAction> {
+        return vec![];
+    }
+}
+
+// A plugin written as a rhai script, with its own variables and functions.
+pub struct ScriptPlugin {
+    name: String,
+    scripts: ScriptEngine,
+}
+
+impl Plugin for ScriptPlugin {
+    fn name(&self) -> &str {
+        return &self.name;
+    }
+
+    fn commands(&self) -> Vec<String> {
+        return self.scripts.commands();
+    }
+
+    unsafe fn handle_command(&mut self, tracee: &mut Tracee, words: &[&str]) -> Vec<ScriptAction> {
+        let Some((result, actions)) = self.scripts.run_command(tracee, words) else {
+            return vec![];
+        };
+        match result {
+            Err(err) => println!("{}: {}", self.name, err),
+            Ok(None) => {}
+            Ok(Some(value)) => println!("{}", value),
+        }
+        return actions;
+    }
+
+    unsafe fn handle_record(
+        &mut self,
+        tracee: &mut Tracee,
+        record: &TraceRecord,
+    ) -> Vec<ScriptAction> {
+        return self.scripts.run_hooks(tracee, record);
+    }
+}
+
+// Loads a plugin, which is a rhai script if its name ends in ".rhai", and a shared library
+// otherwise. A script runs against the tracee as it loads, and may ask for actions right away.
+pub unsafe fn load_plugin(
+    path: &Path,
+    tracee: &mut Tracee,
+) -> Result<(Box<dyn Plugin>, Vec<ScriptAction>), PluginError> {
+    let path_str = path.display().to_string();
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "rhai")
+    {
+        let script = fs::read_to_string(path).map_err(|source| PluginError::Read {
+            path: path_str.clone(),
+            source,
+        })?;
+        let mut scripts = ScriptEngine::new();
+        let (result, actions) = scripts.run(tracee, &script);
+        if let Err(message) = result {
+            return Err(PluginError::Script {
+                path: path_str,
+                message,
+            });
+        }
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or(path_str);
+        return Ok((Box::new(ScriptPlugin { name, scripts }), actions));
+    }
+
+    let create = load_library_symbol(path, CREATE_SYMBOL).map_err(|message| {
+        return PluginError::Load {
+            path: path_str,
+            message,
+        };
+    })?;
+    let create = std::mem::transmute::<*mut libc::c_void, CreatePlugin>(create);
+    return Ok((create(), vec![]));
+}
+
+// Opens a shared library and looks up a symbol of it. The library stays loaded for good, since
+// whatever it hands out may refer to its code.
+unsafe fn load_library_symbol(path: &Path, symbol: &CStr) -> Result<*mut libc::c_void, String> {
+    let path_cstr = CString::new(path.as_os_str().as_bytes()).map_err(|err| err.to_string())?;
+    let library = libc::dlopen(path_cstr.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+    if library.is_null() {
+        return Err(dlerror_message());
+    }
+    let address = libc::dlsym(library, symbol.as_ptr());
+    if address.is_null() {
+        let message = dlerror_message();
+        libc::dlclose(library);
+        return Err(message);
+    }
+    return Ok(address);
+}
+
+unsafe fn dlerror_message() -> String {
+    let message = libc::dlerror();
+    if message.is_null() {
+        return "unknown dynamic linker error".to_string();
+    }
+    return CStr::from_ptr(message).to_string_lossy().to_string();
+}
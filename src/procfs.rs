@@ -0,0 +1,621 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+// Lists the IDs of every task (thread) in the process, in ascending order.
+pub fn read_task_ids(pid: libc::pid_t) -> Vec<libc::pid_t> {
+    let task_path = format!("/proc/{}/task", pid);
+    let entries = match fs::read_dir(&task_path) {
+        Err(err) => panic!("failed to read {}: {}", task_path, err),
+        Ok(entries) => entries,
+    };
+
+    let mut tids = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<libc::pid_t>().ok())
+        .collect::<Vec<libc::pid_t>>();
+    tids.sort();
+    return tids;
+}
+
+// Lists the IDs of every process on the system.
+pub fn read_pids() -> Vec<libc::pid_t> {
+    let entries = match fs::read_dir("/proc") {
+        Err(err) => panic!("failed to read /proc: {}", err),
+        Ok(entries) => entries,
+    };
+
+    return entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<libc::pid_t>().ok())
+        .collect::<Vec<libc::pid_t>>();
+}
+
+// Lists the IDs of every process whose name matches. Since the kernel truncates process names,
+// only the first 15 bytes of the name are compared.
+pub fn find_pids_by_name(name: &str) -> Vec<libc::pid_t> {
+    let name = &name.as_bytes()[..name.len().min(15)];
+    return read_pids()
+        .into_iter()
+        .filter(|pid| read_comm(*pid).is_some_and(|comm| comm.as_bytes() == name))
+        .collect::<Vec<libc::pid_t>>();
+}
+
+// Resolves the path of the program image that the process is executing.
+pub fn read_exe_path(pid: libc::pid_t) -> PathBuf {
+    let exe_path = format!("/proc/{}/exe", pid);
+    return match fs::read_link(&exe_path) {
+        Err(err) => panic!("failed to read {}: {}", exe_path, err),
+        Ok(path) => path,
+    };
+}
+
+// Resolves where the debugger reaches the root directory of the process, if not at its own root,
+// i.e. when the process is in another mount namespace, like that of a container, or chrooted. The
+// files that the process sees, e.g. its executable and libraries, are then under /proc/<pid>/root.
+// Returns None if the process no longer exists.
+pub fn read_root(pid: libc::pid_t) -> Option<PathBuf> {
+    let mount_namespace = fs::read_link(format!("/proc/{}/ns/mnt", pid)).ok()?;
+    let root = fs::read_link(format!("/proc/{}/root", pid)).ok()?;
+    let is_own_root = fs::read_link("/proc/self/ns/mnt").ok() == Some(mount_namespace)
+        && fs::read_link("/proc/self/root").ok() == Some(root);
+    return match is_own_root {
+        true => None,
+        false => Some(PathBuf::from(format!("/proc/{}/root", pid))),
+    };
+}
+
+// Resolves a path as a process with the given root sees it to one that the debugger can open.
+pub fn resolve_path(root: Option<&Path>, path: &Path) -> PathBuf {
+    return match (root, path.strip_prefix("/")) {
+        (Some(root), Ok(relative_path)) => root.join(relative_path),
+        _ => path.to_path_buf(),
+    };
+}
+
+// Reads the auxiliary vector that the kernel passed the process at exec, as pairs of AT_* types
+// and values. Returns None if the process no longer exists.
+pub fn read_auxv(pid: libc::pid_t) -> Option<Vec<(u64, u64)>> {
+    let auxv = fs::read(format!("/proc/{}/auxv", pid)).ok()?;
+    return Some(parse_auxv(&auxv));
+}
+
+// Parses an auxiliary vector, which ends with an AT_NULL entry.
+pub fn parse_auxv(auxv: &[u8]) -> Vec<(u64, u64)> {
+    return auxv
+        .chunks_exact(16)
+        .map(|entry| {
+            return (
+                u64::from_ne_bytes(entry[..8].try_into().unwrap()),
+                u64::from_ne_bytes(entry[8..].try_into().unwrap()),
+            );
+        })
+        .take_while(|(kind, _)| *kind != libc::AT_NULL)
+        .collect();
+}
+
+// Resolves the current working directory of the process. Returns None if the process no longer
+// exists.
+pub fn read_cwd(pid: libc::pid_t) -> Option<PathBuf> {
+    return fs::read_link(format!("/proc/{}/cwd", pid)).ok();
+}
+
+// Reads the name of a task, as set by prctl(PR_SET_NAME) or pthread_setname_np(). Returns None
+// if the task no longer exists.
+pub fn read_comm(tid: libc::pid_t) -> Option<String> {
+    let comm = fs::read_to_string(format!("/proc/{}/comm", tid)).ok()?;
+    return Some(comm.trim_end_matches('\n').to_string());
+}
+
+// Reads the command line arguments of the process. Returns None if the process no longer exists.
+pub fn read_cmdline(pid: libc::pid_t) -> Option<Vec<String>> {
+    let cmdline = fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let Some(cmdline) = cmdline.strip_suffix(&[0]) else {
+        // Kernel threads and zombies have an empty command line.
+        return Some(vec![]);
+    };
+
+    return Some(
+        cmdline
+            .split(|byte| *byte == 0)
+            .map(|arg| String::from_utf8_lossy(arg).to_string())
+            .collect(),
+    );
+}
+
+// Reads the environment that the process started with, as "NAME=value" strings. Changes that the
+// process made to its environment since are not seen. Returns None if the process no longer
+// exists.
+pub fn read_environ(pid: libc::pid_t) -> Option<Vec<String>> {
+    let environ = fs::read(format!("/proc/{}/environ", pid)).ok()?;
+    return Some(
+        environ
+            .split(|byte| *byte == 0)
+            .filter(|var| !var.is_empty())
+            .map(|var| String::from_utf8_lossy(var).to_string())
+            .collect(),
+    );
+}
+
+#[derive(PartialEq, Debug)]
+pub struct TaskStat {
+    // The scheduler state, e.g. 'R' (running), 'S' (sleeping), 'D' (disk sleep), 't' (traced).
+    pub state: char,
+    // Time spent in user mode, in clock ticks.
+    pub utime: u64,
+    // Time spent in kernel mode, in clock ticks.
+    pub stime: u64,
+    // The CPU that the task last ran on.
+    pub processor: u32,
+}
+
+// Reads scheduling statistics of a task. Returns None if the task no longer exists.
+pub fn read_stat(tid: libc::pid_t) -> Option<TaskStat> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", tid)).ok()?;
+    return parse_stat(&stat);
+}
+
+fn parse_stat(stat: &str) -> Option<TaskStat> {
+    // The command name may contain spaces and parentheses, so fields are counted from its end.
+    // The first field after it is field 3 in proc(5).
+    let fields = stat[stat.rfind(')')? + 1..]
+        .split_whitespace()
+        .collect::<Vec<&str>>();
+    let field = |number: usize| fields.get(number - 3).copied();
+
+    return Some(TaskStat {
+        state: field(3)?.chars().next()?,
+        utime: field(14)?.parse().ok()?,
+        stime: field(15)?.parse().ok()?,
+        processor: field(39)?.parse().ok()?,
+    });
+}
+
+// Reads the number and arguments of the system call that a task is blocked in. Returns None if
+// the task is not in a system call, or no longer exists.
+pub fn read_syscall(tid: libc::pid_t) -> Option<(i64, [u64; 6])> {
+    let syscall = fs::read_to_string(format!("/proc/{}/syscall", tid)).ok()?;
+    return parse_syscall(&syscall);
+}
+
+fn parse_syscall(syscall: &str) -> Option<(i64, [u64; 6])> {
+    // Formatted as "<nr> <arg0> ... <arg5> <sp> <pc>", "-1 <sp> <pc>", or "running".
+    let fields = syscall.split_whitespace().collect::<Vec<&str>>();
+    let number = fields.first()?.parse::<i64>().ok()?;
+    if number < 0 || fields.len() < 7 {
+        return None;
+    }
+
+    let mut args = [0; 6];
+    for (arg, field) in args.iter_mut().zip(&fields[1..7]) {
+        *arg = u64::from_str_radix(field.strip_prefix("0x")?, 16).ok()?;
+    }
+    return Some((number, args));
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct MemoryMapping {
+    pub start: u64,
+    pub end: u64,
+    // The access permissions, e.g. "r-xp".
+    pub permissions: String,
+    // The offset of the mapping into the mapped file.
+    pub offset: u64,
+    // The mapped file, or a pseudo-path like "[stack]". Empty for anonymous mappings.
+    pub path: String,
+}
+
+// Reads the memory mappings of a process, in ascending order of address. Returns None if the
+// process no longer exists.
+pub fn read_maps(pid: libc::pid_t) -> Option<Vec<MemoryMapping>> {
+    let maps = fs::read_to_string(format!("/proc/{}/maps", pid)).ok()?;
+    return Some(maps.lines().filter_map(parse_mapping).collect());
+}
+
+fn parse_mapping(line: &str) -> Option<MemoryMapping> {
+    // Formatted as "<start>-<end> <perms> <offset> <dev> <inode> <path>", where the path may
+    // contain spaces and is missing for anonymous mappings.
+    let mut fields = line.splitn(6, ' ');
+    let (start, end) = fields.next()?.split_once('-')?;
+    let permissions = fields.next()?;
+    let offset = fields.next()?;
+    let _device = fields.next()?;
+    let _inode = fields.next()?;
+    let path = fields.next().unwrap_or_default().trim_start();
+
+    return Some(MemoryMapping {
+        start: u64::from_str_radix(start, 16).ok()?,
+        end: u64::from_str_radix(end, 16).ok()?,
+        permissions: permissions.to_string(),
+        offset: u64::from_str_radix(offset, 16).ok()?,
+        path: path.to_string(),
+    });
+}
+
+// Reads the thread group ID (i.e. the PID of the owning process) of a task.
+pub fn read_tgid(tid: libc::pid_t) -> Option<libc::pid_t> {
+    return read_status_field(tid, "Tgid")?.parse::<libc::pid_t>().ok();
+}
+
+// Reads the PID of the parent process of a task.
+pub fn read_ppid(tid: libc::pid_t) -> Option<libc::pid_t> {
+    return read_status_field(tid, "PPid")?.parse::<libc::pid_t>().ok();
+}
+
+#[derive(PartialEq, Debug)]
+pub struct OpenFile {
+    pub fd: libc::c_int,
+    // What the descriptor refers to, e.g. a path, "pipe:[1234]" or "socket:[1234]".
+    pub target: String,
+    // The file offset and the flags it was opened with, from fdinfo.
+    pub position: Option<u64>,
+    pub flags: Option<u64>,
+}
+
+impl OpenFile {
+    // The inode of a socket, pipe or other object that has no path.
+    pub fn inode(&self, kind: &str) -> Option<u64> {
+        let inode = self.target.strip_prefix(kind)?.strip_prefix(":[")?;
+        return inode.strip_suffix(']')?.parse::<u64>().ok();
+    }
+}
+
+// Reads the open file descriptors of a process, in ascending order. Returns None if the process no
+// longer exists. Descriptors that are closed while being read are skipped.
+pub fn read_fds(pid: libc::pid_t) -> Option<Vec<OpenFile>> {
+    let entries = fs::read_dir(format!("/proc/{}/fd", pid)).ok()?;
+    let mut files = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let fd = entry.file_name().to_str()?.parse::<libc::c_int>().ok()?;
+            let target = fs::read_link(entry.path()).ok()?;
+            let fdinfo = fs::read_to_string(format!("/proc/{}/fdinfo/{}", pid, fd));
+            let (position, flags) = parse_fdinfo(&fdinfo.unwrap_or_default());
+            return Some(OpenFile {
+                fd,
+                target: target.display().to_string(),
+                position,
+                flags,
+            });
+        })
+        .collect::<Vec<OpenFile>>();
+    files.sort_by_key(|file| file.fd);
+    return Some(files);
+}
+
+// Reads the offset and flags of fdinfo, which has lines like "pos:\t0" and "flags:\t02000002",
+// where the flags are octal.
+fn parse_fdinfo(fdinfo: &str) -> (Option<u64>, Option<u64>) {
+    let field = |name: &str| {
+        return fdinfo.lines().find_map(|line| {
+            let value = line.strip_prefix(name)?.strip_prefix(':')?;
+            return Some(value.trim());
+        });
+    };
+    let position = field("pos").and_then(|value| value.parse::<u64>().ok());
+    let flags = field("flags").and_then(|value| u64::from_str_radix(value, 8).ok());
+    return (position, flags);
+}
+
+#[derive(PartialEq, Debug)]
+pub struct ProcessStatus {
+    // The scheduler state, e.g. "t (tracing stop)".
+    pub state: String,
+    // The real, effective, saved and filesystem user IDs.
+    pub uids: Vec<u32>,
+    // The real, effective, saved and filesystem group IDs.
+    pub gids: Vec<u32>,
+    // The resident set size, in kB. Kernel threads and zombies have none.
+    pub vm_rss: Option<u64>,
+    pub threads: usize,
+    // The PIDs of the process in its PID namespace and those that it is nested in, innermost last.
+    pub namespace_pids: Vec<libc::pid_t>,
+}
+
+// Reads the highlights of the status of a process. Returns None if the process no longer exists.
+pub fn read_status(pid: libc::pid_t) -> Option<ProcessStatus> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    return parse_status(&status);
+}
+
+fn parse_status(status: &str) -> Option<ProcessStatus> {
+    let field = |name: &str| {
+        return status.lines().find_map(|line| {
+            let value = line.strip_prefix(name)?.strip_prefix(':')?;
+            return Some(value.trim());
+        });
+    };
+    let ids = |value: &str| {
+        return value
+            .split_whitespace()
+            .filter_map(|id| id.parse::<u32>().ok())
+            .collect::<Vec<u32>>();
+    };
+
+    return Some(ProcessStatus {
+        state: field("State")?.to_string(),
+        uids: ids(field("Uid")?),
+        gids: ids(field("Gid")?),
+        // Formatted as "<size> kB".
+        vm_rss: field("VmRSS").and_then(|value| value.split_whitespace().next()?.parse().ok()),
+        threads: field("Threads")?.parse().ok()?,
+        // Kernels before 4.1 have no NSpid.
+        namespace_pids: field("NSpid").map_or(vec![], |value| {
+            return value
+                .split_whitespace()
+                .filter_map(|pid| pid.parse().ok())
+                .collect();
+        }),
+    });
+}
+
+fn read_status_field(tid: libc::pid_t, name: &str) -> Option<String> {
+    let status = fs::read_to_string(format!("/proc/{}/status", tid)).ok()?;
+    return status.lines().find_map(|line| {
+        let value = line.strip_prefix(name)?.strip_prefix(':')?;
+        return Some(value.trim().to_string());
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{
+        find_pids_by_name, parse_auxv, parse_fdinfo, parse_mapping, parse_stat, parse_status,
+        parse_syscall, read_auxv, read_cmdline, read_comm, read_cwd, read_environ, read_exe_path,
+        read_fds, read_maps, read_pids, read_ppid, read_root, read_stat, read_status,
+        read_task_ids, read_tgid, resolve_path, MemoryMapping, OpenFile, ProcessStatus, TaskStat,
+    };
+
+    #[test]
+    fn read_task_ids_includes_current_process() {
+        let pid = std::process::id() as libc::pid_t;
+        assert!(read_task_ids(pid).contains(&pid));
+    }
+
+    #[test]
+    fn read_tgid_of_current_process_is_its_pid() {
+        let pid = std::process::id() as libc::pid_t;
+        assert_eq!(read_tgid(pid), Some(pid));
+    }
+
+    #[test]
+    fn parse_auxv_stops_at_null_entry() {
+        let auxv = [
+            (libc::AT_PAGESZ, 4096),
+            (libc::AT_ENTRY, 0x400000),
+            (0, 0),
+            (7, 7),
+        ]
+        .iter()
+        .flat_map(|(kind, value)| [kind.to_ne_bytes(), u64::to_ne_bytes(*value)].concat())
+        .collect::<Vec<u8>>();
+        assert_eq!(
+            parse_auxv(&auxv),
+            vec![(libc::AT_PAGESZ, 4096), (libc::AT_ENTRY, 0x400000)]
+        );
+    }
+
+    #[test]
+    fn read_auxv_of_current_process_has_its_entry_point() {
+        let pid = std::process::id() as libc::pid_t;
+        let entry = unsafe { libc::getauxval(libc::AT_ENTRY) };
+        assert!(read_auxv(pid).unwrap().contains(&(libc::AT_ENTRY, entry)));
+    }
+
+    #[test]
+    fn read_root_of_current_process_is_none() {
+        let pid = std::process::id() as libc::pid_t;
+        assert_eq!(read_root(pid), None);
+        assert_eq!(read_root(-1), None);
+    }
+
+    #[test]
+    fn resolve_path_joins_absolute_paths_to_root() {
+        let root = Path::new("/proc/42/root");
+        assert_eq!(
+            resolve_path(Some(root), Path::new("/usr/lib/libc.so.6")),
+            Path::new("/proc/42/root/usr/lib/libc.so.6")
+        );
+        assert_eq!(
+            resolve_path(Some(root), Path::new("[vdso]")),
+            Path::new("[vdso]")
+        );
+        assert_eq!(
+            resolve_path(None, Path::new("/bin/ls")),
+            Path::new("/bin/ls")
+        );
+    }
+
+    #[test]
+    fn read_exe_path_of_current_process_matches_current_exe() {
+        let pid = std::process::id() as libc::pid_t;
+        assert_eq!(read_exe_path(pid), std::env::current_exe().unwrap());
+    }
+
+    #[test]
+    fn read_comm_of_named_thread_matches_its_name() {
+        let handle = std::thread::Builder::new()
+            .name("pbreak-test".to_string())
+            .spawn(|| read_comm(unsafe { libc::gettid() }))
+            .unwrap();
+        assert_eq!(handle.join().unwrap().unwrap(), "pbreak-test");
+    }
+
+    #[test]
+    fn read_comm_of_missing_task_is_none() {
+        assert_eq!(read_comm(-1), None);
+    }
+
+    #[test]
+    fn read_cmdline_of_current_process_matches_args() {
+        let pid = std::process::id() as libc::pid_t;
+        let args = std::env::args().collect::<Vec<String>>();
+        assert_eq!(read_cmdline(pid).unwrap(), args);
+    }
+
+    #[test]
+    fn read_environ_of_current_process_includes_path() {
+        let pid = std::process::id() as libc::pid_t;
+        let Ok(path) = std::env::var("PATH") else {
+            return;
+        };
+        assert!(read_environ(pid)
+            .unwrap()
+            .contains(&format!("PATH={}", path)));
+    }
+
+    #[test]
+    fn read_pids_includes_current_process() {
+        let pid = std::process::id() as libc::pid_t;
+        assert!(read_pids().contains(&pid));
+    }
+
+    #[test]
+    fn find_pids_by_name_finds_current_process() {
+        let pid = std::process::id() as libc::pid_t;
+        let name = read_comm(pid).unwrap();
+        assert!(find_pids_by_name(&name).contains(&pid));
+    }
+
+    #[test]
+    fn read_ppid_of_current_process_matches_getppid() {
+        let pid = std::process::id() as libc::pid_t;
+        assert_eq!(read_ppid(pid), Some(unsafe { libc::getppid() }));
+    }
+
+    #[test]
+    fn parse_stat_reads_fields_after_command_name() {
+        let stat = "42 (a) b (c)) S 1 42 42 0 -1 4194560 100 0 0 0 7 3 0 0 20 0 1 0 \
+                    100 1000 100 18446744073709551615 1 1 0 0 0 0 0 0 0 0 0 0 17 5 0 0 0 0 0";
+        assert_eq!(
+            parse_stat(stat),
+            Some(TaskStat {
+                state: 'S',
+                utime: 7,
+                stime: 3,
+                processor: 5,
+            })
+        );
+    }
+
+    #[test]
+    fn read_stat_of_current_thread_is_running() {
+        let tid = unsafe { libc::gettid() };
+        assert_eq!(read_stat(tid).unwrap().state, 'R');
+    }
+
+    #[test]
+    fn parse_syscall_reads_number_and_arguments() {
+        let syscall = "98 0xaaaa1000 0x80 0x2 0x0 0x0 0x0 0xffffe000 0xffff1234\n";
+        assert_eq!(
+            parse_syscall(syscall),
+            Some((98, [0xaaaa1000, 0x80, 0x2, 0, 0, 0]))
+        );
+    }
+
+    #[test]
+    fn parse_syscall_ignores_tasks_outside_syscalls() {
+        assert_eq!(parse_syscall("running\n"), None);
+        assert_eq!(parse_syscall("-1 0xffffe000 0xffff1234\n"), None);
+    }
+
+    #[test]
+    fn parse_mapping_reads_paths_with_spaces() {
+        let line = "aaaab0000000-aaaab0002000 r-xp 00001000 fe:00 1234       /tmp/my program";
+        assert_eq!(
+            parse_mapping(line),
+            Some(MemoryMapping {
+                start: 0xaaaab0000000,
+                end: 0xaaaab0002000,
+                permissions: "r-xp".to_string(),
+                offset: 0x1000,
+                path: "/tmp/my program".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_mapping("ffff0000-ffff1000 rw-p 00000000 00:00 0 ")
+                .unwrap()
+                .path,
+            ""
+        );
+    }
+
+    #[test]
+    fn read_maps_of_current_process_includes_its_executable() {
+        let pid = std::process::id() as libc::pid_t;
+        let exe_path = read_exe_path(pid);
+        let maps = read_maps(pid).unwrap();
+        assert!(maps
+            .iter()
+            .any(|mapping| mapping.path == exe_path.to_str().unwrap()));
+    }
+
+    #[test]
+    fn read_cwd_of_current_process_matches_current_dir() {
+        let pid = std::process::id() as libc::pid_t;
+        assert_eq!(read_cwd(pid), Some(std::env::current_dir().unwrap()));
+    }
+
+    #[test]
+    fn parse_status_reads_highlights() {
+        let status = "Name:\tcat\nState:\tt (tracing stop)\nTgid:\t42\n\
+                      Uid:\t1000\t1000\t1000\t1000\nGid:\t100\t100\t100\t100\n\
+                      NSpid:\t42\t7\nVmRSS:\t    1536 kB\nThreads:\t3\n";
+        assert_eq!(
+            parse_status(status),
+            Some(ProcessStatus {
+                state: "t (tracing stop)".to_string(),
+                uids: vec![1000, 1000, 1000, 1000],
+                gids: vec![100, 100, 100, 100],
+                vm_rss: Some(1536),
+                threads: 3,
+                namespace_pids: vec![42, 7],
+            })
+        );
+    }
+
+    #[test]
+    fn read_status_of_current_process_matches_its_ids() {
+        let pid = std::process::id() as libc::pid_t;
+        let status = read_status(pid).unwrap();
+        assert!(status.threads >= 1);
+        assert_eq!(status.uids[0], unsafe { libc::getuid() });
+        assert_eq!(status.gids[0], unsafe { libc::getgid() });
+    }
+
+    #[test]
+    fn parse_fdinfo_reads_position_and_octal_flags() {
+        let fdinfo = "pos:\t4096\nflags:\t02100002\nmnt_id:\t25\nino:\t1234\n";
+        assert_eq!(parse_fdinfo(fdinfo), (Some(4096), Some(0o2100002)));
+        assert_eq!(parse_fdinfo(""), (None, None));
+    }
+
+    #[test]
+    fn read_fds_of_current_process_includes_open_file() {
+        let pid = std::process::id() as libc::pid_t;
+        let file = std::fs::File::open("/proc/self/exe").unwrap();
+        let fd = std::os::fd::AsRawFd::as_raw_fd(&file);
+        let files = read_fds(pid).unwrap();
+        let open_file = files.iter().find(|open_file| open_file.fd == fd).unwrap();
+        assert_eq!(open_file.target, read_exe_path(pid).display().to_string());
+        assert_eq!(open_file.position, Some(0));
+        assert_eq!(open_file.flags.unwrap() & libc::O_ACCMODE as u64, 0);
+    }
+
+    #[test]
+    fn open_file_inode_reads_socket_and_pipe_targets() {
+        let open_file = |target: &str| OpenFile {
+            fd: 3,
+            target: target.to_string(),
+            position: None,
+            flags: None,
+        };
+        assert_eq!(open_file("socket:[1234]").inode("socket"), Some(1234));
+        assert_eq!(open_file("pipe:[99]").inode("socket"), None);
+        assert_eq!(open_file("pipe:[99]").inode("pipe"), Some(99));
+        assert_eq!(open_file("/tmp/socket:[1]").inode("socket"), None);
+    }
+}
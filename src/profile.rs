@@ -0,0 +1,198 @@
+use std::{collections::HashMap, path::Path, time::Duration};
+
+use crate::{elf::FunctionSymbol, procfs::MemoryMapping, unwind::Frame};
+
+// How often `profile` samples the tracee's stacks, i.e. 100 times a second.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+
+// Instructions are 4 bytes long, so the call that a return address returns from is 4 bytes back.
+const CALL_INSTRUCTION_LEN: u64 = 4;
+
+// Stack samples, counted by their folded stacks, e.g. "main;parse;read 12". That is the format
+// that flamegraph tools take as input.
+#[derive(Default)]
+pub struct Profile {
+    stacks: HashMap<String, usize>,
+    samples: usize,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn samples(&self) -> usize {
+        return self.samples;
+    }
+
+    // Adds a sample of a stack, given the names of its frames from the innermost outwards.
+    pub fn add_sample(&mut self, names: &[String]) {
+        let folded = names
+            .iter()
+            .rev()
+            // Semicolons separate frames in folded stacks.
+            .map(|name| name.replace(';', ":"))
+            .collect::<Vec<String>>()
+            .join(";");
+        *self.stacks.entry(folded).or_insert(0) += 1;
+        self.samples += 1;
+    }
+
+    // Formats the samples as folded stacks, one stack per line, most frequent first.
+    pub fn folded(&self) -> String {
+        let mut stacks = self.stacks.iter().collect::<Vec<(&String, &usize)>>();
+        stacks.sort_by(|(a_stack, a_count), (b_stack, b_count)| {
+            return b_count.cmp(a_count).then(a_stack.cmp(b_stack));
+        });
+        return stacks
+            .iter()
+            .map(|(stack, count)| format!("{} {}\n", stack, count))
+            .collect();
+    }
+}
+
+// Names the frames of a stack by their functions, if known, or else by the files they are in,
+// e.g. "[libc.so.6]".
+pub fn frame_names(
+    frames: &[Frame],
+    functions: &[FunctionSymbol],
+    mappings: &[MemoryMapping],
+) -> Vec<String> {
+    return frames
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            // Callers are named by their calls rather than the return addresses, which may be
+            // past the end of the function if the call is its last instruction. The frame that a
+            // signal interrupted is at its pc, though.
+            let is_caller = i > 0 && !frames[i - 1].is_signal_trampoline;
+            let pc = match is_caller {
+                true => frame.pc.saturating_sub(CALL_INSTRUCTION_LEN),
+                false => frame.pc,
+            };
+            return frame_name(functions, mappings, pc);
+        })
+        .collect();
+}
+
+fn frame_name(functions: &[FunctionSymbol], mappings: &[MemoryMapping], pc: u64) -> String {
+    let function = functions
+        .iter()
+        .rev()
+        .find(|function| function.address <= pc)
+        .filter(|function| pc - function.address < function.size);
+    if let Some(function) = function {
+        return function.name.clone();
+    }
+
+    let mapping = mappings
+        .iter()
+        .find(|mapping| mapping.start <= pc && pc < mapping.end);
+    return match mapping {
+        Some(mapping) if !mapping.path.is_empty() => {
+            let name = Path::new(&mapping.path)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or(mapping.path.clone());
+            match name.starts_with('[') {
+                true => name,
+                false => format!("[{}]", name),
+            }
+        }
+        _ => "[unknown]".to_string(),
+    };
+}
+
+// Parses a duration such as "10s", "500ms" or "2m". Plain numbers are seconds.
+pub fn parse_duration(duration_str: &str) -> Option<Duration> {
+    let (number, unit) = match duration_str.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        None => (duration_str, "s"),
+        Some(i) => duration_str.split_at(i),
+    };
+    let number = number.parse::<f64>().ok()?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        _ => return None,
+    };
+    return Duration::try_from_secs_f64(seconds).ok();
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{frame_names, parse_duration, Profile};
+    use crate::{elf::FunctionSymbol, procfs::MemoryMapping, unwind::Frame};
+
+    #[test]
+    fn folded_stacks_count_samples_outermost_first() {
+        let mut profile = Profile::new();
+        let names = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+        profile.add_sample(&names(&["read", "parse", "main"]));
+        profile.add_sample(&names(&["compute", "main"]));
+        profile.add_sample(&names(&["read", "parse", "main"]));
+        assert_eq!(profile.samples(), 3);
+        assert_eq!(profile.folded(), "main;parse;read 2\nmain;compute 1\n");
+    }
+
+    #[test]
+    fn frame_names_use_functions_then_files() {
+        let function = |name: &str, address, size| FunctionSymbol {
+            name: name.to_string(),
+            address,
+            size,
+        };
+        let functions = vec![
+            function("main", 0x1000, 0x40),
+            function("helper", 0x1040, 0x20),
+        ];
+        let mapping = |start, end, path: &str| MemoryMapping {
+            start,
+            end,
+            permissions: "r-xp".to_string(),
+            offset: 0,
+            path: path.to_string(),
+        };
+        let mappings = vec![
+            mapping(0x1000, 0x2000, "/bin/app"),
+            mapping(0x7000, 0x8000, "/usr/lib/libc.so.6"),
+            mapping(0x9000, 0xa000, "[vdso]"),
+        ];
+        let frame = |pc| Frame {
+            pc,
+            fp: 0,
+            is_signal_trampoline: false,
+        };
+        assert_eq!(
+            frame_names(
+                &[
+                    frame(0x1048),
+                    frame(0x1040),
+                    frame(0x7010),
+                    frame(0x9010),
+                    frame(0x5000)
+                ],
+                &functions,
+                &mappings,
+            ),
+            vec!["helper", "main", "[libc.so.6]", "[vdso]", "[unknown]"]
+        );
+    }
+
+    #[test]
+    fn parse_duration_takes_units() {
+        assert_eq!(parse_duration("10"), Some(Duration::from_secs(10)));
+        assert_eq!(parse_duration("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_duration("2h"), None);
+        assert_eq!(parse_duration("fast"), None);
+    }
+}
@@ -0,0 +1,204 @@
+// A pseudo-terminal for a launched program, so that the program's output does not interleave with
+// the prompt. Output is kept until `tty` shows it, and while attached with `tty`, it is shown as it
+// arrives and typed lines go to the program instead of the session.
+use std::{
+    ffi::{CStr, CString},
+    io::{self, stdout, Write},
+    mem,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crate::cleanup;
+
+// Older output is dropped beyond this, so that a chatty program does not grow the debugger.
+const MAX_BUFFERED_OUTPUT: usize = 1024 * 1024;
+
+pub struct Pty {
+    inner: Arc<PtyInner>,
+    // The path of the program's end, e.g. "/dev/pts/3".
+    slave_path: CString,
+}
+
+// What the reading thread shares with the `Pty`. The master is closed once both are done with it.
+struct PtyInner {
+    master: libc::c_int,
+    output: Mutex<PtyOutput>,
+}
+
+#[derive(Default)]
+struct PtyOutput {
+    // What the program wrote that has not been shown yet.
+    buffered: Vec<u8>,
+    is_shown_live: bool,
+}
+
+impl Pty {
+    // Opens a new pseudo-terminal, with the modes and size of the debugger's terminal, if any.
+    pub unsafe fn open() -> io::Result<Pty> {
+        let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC);
+        if master < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // The master is closed along with the inner part on errors.
+        let inner = Arc::new(PtyInner {
+            master,
+            output: Mutex::new(PtyOutput::default()),
+        });
+        if libc::grantpt(master) < 0 || libc::unlockpt(master) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut name = vec![0; 128];
+        let err = libc::ptsname_r(master, name.as_mut_ptr(), name.len());
+        if err != 0 {
+            return Err(io::Error::from_raw_os_error(err));
+        }
+        let slave_path = CStr::from_ptr(name.as_ptr()).to_owned();
+
+        // Modes set on the master apply to the program's end too.
+        let mut termios = mem::zeroed::<libc::termios>();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) == 0 {
+            libc::tcsetattr(master, libc::TCSANOW, &termios);
+        }
+        let pty = Pty { inner, slave_path };
+        pty.sync_size();
+        return Ok(pty);
+    }
+
+    pub fn slave_path(&self) -> &CStr {
+        return &self.slave_path;
+    }
+
+    // Starts reading the program's output on a thread of its own, which ends once the program and
+    // its children have closed their end. Until the program has opened its end, there is nothing
+    // to read, so this is called once it has.
+    pub fn start_reading(&self) {
+        let inner = self.inner.clone();
+        thread::spawn(move || {
+            unsafe { cleanup::block_fatal_signals() };
+            let mut buffer = vec![0u8; 4096];
+            loop {
+                let n_bytes = unsafe {
+                    libc::read(
+                        inner.master,
+                        buffer.as_mut_ptr() as *mut libc::c_void,
+                        buffer.len(),
+                    )
+                };
+                if n_bytes < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                // Reads fail with EIO once the other end is closed.
+                if n_bytes <= 0 {
+                    return;
+                }
+                inner
+                    .output
+                    .lock()
+                    .unwrap()
+                    .add(&buffer[..n_bytes as usize]);
+            }
+        });
+    }
+
+    // Starts or stops showing output as it arrives. Returns the output that was kept until then,
+    // which is shown first when starting.
+    pub fn show_live(&self, is_shown_live: bool) -> Vec<u8> {
+        let mut output = self.inner.output.lock().unwrap();
+        output.is_shown_live = is_shown_live;
+        return match is_shown_live {
+            true => mem::take(&mut output.buffered),
+            false => vec![],
+        };
+    }
+
+    // Writes to the program's input, as if typed at its terminal.
+    pub fn write_input(&self, bytes: &[u8]) -> io::Result<()> {
+        let mut written = 0;
+        while written < bytes.len() {
+            let n_bytes = unsafe {
+                libc::write(
+                    self.inner.master,
+                    bytes[written..].as_ptr() as *const libc::c_void,
+                    bytes.len() - written,
+                )
+            };
+            if n_bytes < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            written += n_bytes as usize;
+        }
+        return Ok(());
+    }
+
+    // Gives the program's terminal the size of the debugger's, which sends it SIGWINCH if that
+    // changed its size.
+    pub unsafe fn sync_size(&self) {
+        let mut size = mem::zeroed::<libc::winsize>();
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 {
+            libc::ioctl(self.inner.master, libc::TIOCSWINSZ, &size);
+        }
+    }
+}
+
+impl PtyOutput {
+    fn add(&mut self, bytes: &[u8]) {
+        if self.is_shown_live {
+            let mut stdout = stdout();
+            let _ = stdout.write_all(bytes);
+            let _ = stdout.flush();
+            return;
+        }
+        self.buffered.extend_from_slice(bytes);
+        if self.buffered.len() > MAX_BUFFERED_OUTPUT {
+            let excess = self.buffered.len() - MAX_BUFFERED_OUTPUT;
+            self.buffered.drain(..excess);
+        }
+    }
+}
+
+impl Drop for PtyInner {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.master) };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        thread::sleep,
+        time::{Duration, Instant},
+    };
+
+    use super::{Pty, PtyOutput, MAX_BUFFERED_OUTPUT};
+
+    #[test]
+    fn pty_keeps_output_until_shown() {
+        unsafe {
+            let pty = Pty::open().unwrap();
+            let slave = libc::open(pty.slave_path().as_ptr(), libc::O_RDWR | libc::O_NOCTTY);
+            assert!(slave >= 0);
+            pty.start_reading();
+            libc::write(slave, b"hello\n".as_ptr() as *const libc::c_void, 6);
+
+            let start = Instant::now();
+            let mut output = vec![];
+            while !output.ends_with(b"\n") && start.elapsed() < Duration::from_secs(5) {
+                sleep(Duration::from_millis(10));
+                output.extend(pty.show_live(true));
+                pty.show_live(false);
+            }
+            assert!(output.starts_with(b"hello"));
+            libc::close(slave);
+        }
+    }
+
+    #[test]
+    fn pty_output_keeps_latest_bytes() {
+        let mut output = PtyOutput::default();
+        output.add(&vec![b'a'; MAX_BUFFERED_OUTPUT]);
+        output.add(b"bc");
+        assert_eq!(output.buffered.len(), MAX_BUFFERED_OUTPUT);
+        assert!(output.buffered.ends_with(b"abc"));
+    }
+}
@@ -0,0 +1,190 @@
+use std::{cell::RefCell, ffi::CStr, mem};
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum WaitStatus {
+    // The task exited with the given exit code.
+    Exited(libc::c_int),
+    // The task was terminated by the given signal.
+    Signaled(libc::c_int),
+    // The task entered a ptrace-stop with the given signal and PTRACE_EVENT_* (0 if none).
+    Stopped(libc::c_int, libc::c_int),
+}
+
+// Tracees are tied to the thread that traces them, so all of this state is per tracer thread.
+thread_local! {
+    // Statuses that were reaped while waiting on other tasks, kept until their owner waits.
+    static DEFERRED_STATUSES: RefCell<Vec<(libc::pid_t, WaitStatus)>> = const { RefCell::new(vec![]) };
+
+    // Children that were detached from, which still need to be reaped once they exit.
+    static DETACHED_CHILDREN: RefCell<Vec<libc::pid_t>> = const { RefCell::new(vec![]) };
+}
+
+// Blocks until a task accepted by `is_owned` changes state, returning the task's ID and status.
+// Statuses of other tasks are set aside rather than lost, so that they can be claimed later.
+pub unsafe fn wait(is_owned: impl Fn(libc::pid_t) -> bool) -> (libc::pid_t, WaitStatus) {
+    return match reap(is_owned, 0) {
+        None => unreachable!("blocking waits should always reap a task"),
+        Some(reaped) => reaped,
+    };
+}
+
+// Like `wait`, but returns None instead of blocking if no accepted task has changed state.
+pub unsafe fn try_wait(
+    is_owned: impl Fn(libc::pid_t) -> bool,
+) -> Option<(libc::pid_t, WaitStatus)> {
+    return reap(is_owned, libc::WNOHANG);
+}
+
+unsafe fn reap(
+    is_owned: impl Fn(libc::pid_t) -> bool,
+    extra_options: libc::c_int,
+) -> Option<(libc::pid_t, WaitStatus)> {
+    let deferred = DEFERRED_STATUSES.with_borrow_mut(|statuses| {
+        let index = statuses.iter().position(|(tid, _)| is_owned(*tid))?;
+        return Some(statuses.remove(index));
+    });
+    if deferred.is_some() {
+        return deferred;
+    }
+
+    loop {
+        let (tid, status) = wait_any(extra_options)?;
+        if DETACHED_CHILDREN.with_borrow_mut(|children| claim(children, tid, status)) {
+            continue;
+        }
+
+        if is_owned(tid) {
+            return Some((tid, status));
+        }
+
+        DEFERRED_STATUSES.with_borrow_mut(|statuses| statuses.push((tid, status)));
+    }
+}
+
+// Blocks until the given task changes state.
+pub unsafe fn wait_on_task(tid: libc::pid_t) -> WaitStatus {
+    return wait(|owned_tid| owned_tid == tid).1;
+}
+
+// Sets a status aside for its owner to claim with its next wait, e.g. one that the owner came across
+// while waiting for something else.
+pub fn defer_status(tid: libc::pid_t, status: WaitStatus) {
+    DEFERRED_STATUSES.with_borrow_mut(|statuses| statuses.push((tid, status)));
+}
+
+// Discards any statuses that were set aside for the given tasks, e.g. after detaching from them.
+pub fn forget_tasks(tids: &[libc::pid_t]) {
+    DEFERRED_STATUSES.with_borrow_mut(|statuses| statuses.retain(|(tid, _)| !tids.contains(tid)));
+}
+
+// Arranges for a detached child process to be reaped once it exits, instead of lingering as a
+// zombie. It is a no-op for processes that are not children of the calling thread.
+pub unsafe fn reap_when_exited(pid: libc::pid_t) {
+    let mut info = mem::zeroed::<libc::siginfo_t>();
+    let wait_options = libc::WEXITED | libc::WNOHANG | libc::__WNOTHREAD;
+    if libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, wait_options) < 0 {
+        // ECHILD: not our child, so someone else reaps it.
+        return;
+    }
+
+    if info.si_pid() == 0 {
+        DETACHED_CHILDREN.with_borrow_mut(|children| children.push(pid));
+    }
+}
+
+// Consumes the status of a detached child, returning true if the status belonged to one.
+fn claim(children: &mut Vec<libc::pid_t>, tid: libc::pid_t, status: WaitStatus) -> bool {
+    let Some(index) = children.iter().position(|pid| *pid == tid) else {
+        return false;
+    };
+
+    if let WaitStatus::Exited(_) | WaitStatus::Signaled(_) = status {
+        children.remove(index);
+    }
+    return true;
+}
+
+// Returns None only with WNOHANG, when no task has changed state.
+unsafe fn wait_any(extra_options: libc::c_int) -> Option<(libc::pid_t, WaitStatus)> {
+    let mut info = mem::zeroed::<libc::siginfo_t>();
+    let wait_options =
+        libc::WEXITED | libc::WSTOPPED | libc::__WALL | libc::__WNOTHREAD | extra_options;
+    if libc::waitid(libc::P_ALL, 0, &mut info, wait_options) < 0 {
+        let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+        panic!("failed to wait on tasks: {:?}", errno_message);
+    }
+    if info.si_pid() == 0 {
+        return None;
+    }
+
+    let status = match info.si_code {
+        libc::CLD_EXITED => WaitStatus::Exited(info.si_status()),
+        libc::CLD_KILLED | libc::CLD_DUMPED => WaitStatus::Signaled(info.si_status()),
+        libc::CLD_TRAPPED | libc::CLD_STOPPED => {
+            WaitStatus::Stopped(info.si_status() & 0xff, info.si_status() >> 8)
+        }
+        si_code => unreachable!("unexpected si_code [{}] from waitid", si_code),
+    };
+    return Some((info.si_pid(), status));
+}
+
+#[cfg(test)]
+mod test {
+    use super::{claim, defer_status, try_wait, wait_on_task, WaitStatus};
+
+    #[test]
+    fn claim_ignores_unknown_tasks() {
+        let mut children = vec![10];
+        assert!(!claim(&mut children, 11, WaitStatus::Exited(0)));
+        assert_eq!(children, vec![10]);
+    }
+
+    #[test]
+    fn claim_forgets_children_once_they_exit() {
+        let mut children = vec![10, 11];
+        assert!(claim(
+            &mut children,
+            10,
+            WaitStatus::Stopped(libc::SIGSTOP, 0)
+        ));
+        assert_eq!(children, vec![10, 11]);
+        assert!(claim(
+            &mut children,
+            10,
+            WaitStatus::Signaled(libc::SIGKILL)
+        ));
+        assert_eq!(children, vec![11]);
+    }
+
+    #[test]
+    fn try_wait_does_not_block_on_running_tasks() {
+        unsafe {
+            let pid = libc::fork();
+            if pid == 0 {
+                libc::usleep(100_000);
+                libc::_exit(5);
+            }
+
+            assert_eq!(try_wait(|tid| tid == pid), None);
+            assert_eq!(wait_on_task(pid), WaitStatus::Exited(5));
+        }
+    }
+
+    #[test]
+    fn deferred_statuses_are_claimed_by_the_next_wait() {
+        unsafe {
+            // No such task exists, so the status can only come from being deferred.
+            defer_status(
+                -2,
+                WaitStatus::Stopped(libc::SIGTRAP, libc::PTRACE_EVENT_CLONE),
+            );
+            assert_eq!(
+                try_wait(|tid| tid == -2),
+                Some((
+                    -2,
+                    WaitStatus::Stopped(libc::SIGTRAP, libc::PTRACE_EVENT_CLONE)
+                ))
+            );
+        }
+    }
+}
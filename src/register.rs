@@ -0,0 +1,56 @@
+// Looks up a general purpose register by name (e.g. "x0", "fp", "lr", "sp", "pc").
+pub fn read_general_purpose_register(regs: &libc::user_regs_struct, name: &str) -> Option<u64> {
+    return match name {
+        "sp" => Some(regs.sp),
+        "pc" => Some(regs.pc),
+        "pstate" => Some(regs.pstate),
+        "fp" => Some(regs.regs[29]),
+        "lr" => Some(regs.regs[30]),
+        name => {
+            let index = name.strip_prefix('x')?.parse::<usize>().ok()?;
+            regs.regs.get(index).copied()
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use std::mem;
+
+    use super::read_general_purpose_register;
+
+    fn sample_regs() -> libc::user_regs_struct {
+        let mut regs: libc::user_regs_struct = unsafe { mem::zeroed() };
+        for (i, reg) in regs.regs.iter_mut().enumerate() {
+            *reg = i as u64;
+        }
+        regs.sp = 0x1000;
+        regs.pc = 0x2000;
+        regs.pstate = 0x3000;
+        return regs;
+    }
+
+    #[test]
+    fn read_general_purpose_register_reads_numbered_registers() {
+        let regs = sample_regs();
+        assert_eq!(read_general_purpose_register(&regs, "x0"), Some(0));
+        assert_eq!(read_general_purpose_register(&regs, "x30"), Some(30));
+    }
+
+    #[test]
+    fn read_general_purpose_register_reads_named_registers() {
+        let regs = sample_regs();
+        assert_eq!(read_general_purpose_register(&regs, "sp"), Some(0x1000));
+        assert_eq!(read_general_purpose_register(&regs, "pc"), Some(0x2000));
+        assert_eq!(read_general_purpose_register(&regs, "pstate"), Some(0x3000));
+        assert_eq!(read_general_purpose_register(&regs, "fp"), Some(29));
+        assert_eq!(read_general_purpose_register(&regs, "lr"), Some(30));
+    }
+
+    #[test]
+    fn read_general_purpose_register_rejects_unknown_registers() {
+        let regs = sample_regs();
+        assert_eq!(read_general_purpose_register(&regs, "x31"), None);
+        assert_eq!(read_general_purpose_register(&regs, "rip"), None);
+    }
+}
@@ -0,0 +1,449 @@
+use std::{
+    cell::RefCell,
+    io::{self, Read, Write},
+    net::TcpStream,
+};
+
+use crate::{
+    rsp::{
+        decode_registers, encode_packet, encode_registers, from_gdb_signal, from_hex,
+        to_gdb_signal, to_hex, Incoming, PacketDecoder,
+    },
+    signal::signal_name,
+    tracee::TraceeStatus,
+};
+
+// The most memory asked for by a single `m` packet, whose reply takes twice as many bytes. Stubs
+// announce larger packet sizes than this, e.g. gdbserver's 0x3fff.
+const MAX_MEMORY_CHUNK: usize = 0x400;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RemoteError {
+    #[error("failed to talk to the remote stub: {0}")]
+    Io(#[from] io::Error),
+    #[error("the remote stub closed the connection")]
+    Disconnected,
+    #[error("the remote stub replied with an error: {0}")]
+    Reply(String),
+    #[error("unexpected reply from the remote stub: \"{0}\"")]
+    Unexpected(String),
+}
+
+struct Connection {
+    stream: TcpStream,
+    decoder: PacketDecoder,
+    // Whether packets are acknowledged, until QStartNoAckMode turns it off.
+    acknowledges: bool,
+}
+
+impl Connection {
+    fn send(&mut self, packet: &str) -> Result<(), RemoteError> {
+        self.stream.write_all(&encode_packet(packet.as_bytes()))?;
+        return Ok(());
+    }
+
+    // Blocks until a packet arrives, acknowledging it.
+    fn receive(&mut self) -> Result<String, RemoteError> {
+        loop {
+            match self.decoder.next_incoming() {
+                Some(Incoming::Packet(packet)) => {
+                    if self.acknowledges {
+                        self.stream.write_all(b"+")?;
+                    }
+                    return Ok(String::from_utf8_lossy(&packet).to_string());
+                }
+                Some(Incoming::Corrupted) => self.stream.write_all(b"-")?,
+                // Stubs do not interrupt their clients.
+                Some(Incoming::Interrupt) => {}
+                None => {
+                    let mut buffer = [0u8; 4096];
+                    let n_bytes = self.stream.read(&mut buffer)?;
+                    if n_bytes == 0 {
+                        return Err(RemoteError::Disconnected);
+                    }
+                    self.decoder.feed(&buffer[..n_bytes]);
+                }
+            }
+        }
+    }
+
+    // Sends a packet and waits for its reply. Replies like "E01" are turned into errors.
+    fn request(&mut self, packet: &str) -> Result<String, RemoteError> {
+        self.send(packet)?;
+        let reply = self.receive()?;
+        if reply.len() == 3 && reply.starts_with('E') {
+            return Err(RemoteError::Reply(reply));
+        }
+        return Ok(reply);
+    }
+
+    // Sends a request that is answered with "OK".
+    fn request_ok(&mut self, packet: &str) -> Result<(), RemoteError> {
+        return match self.request(packet)?.as_str() {
+            "OK" => Ok(()),
+            reply => Err(RemoteError::Unexpected(reply.to_string())),
+        };
+    }
+}
+
+// A process that is debugged through a stub speaking the GDB Remote Serial Protocol, such as
+// gdbserver or `pbreak --server`.
+pub struct RemoteTarget {
+    connection: RefCell<Connection>,
+    // The stub does not say which process it debugs, so this is the first of its threads, which
+    // on Linux is the thread group leader.
+    pid: libc::pid_t,
+    status: TraceeStatus,
+    tids: Vec<libc::pid_t>,
+    selected_tid: libc::pid_t,
+}
+
+impl RemoteTarget {
+    // Connects to the stub at the given address, e.g. "localhost:1234", and reports where the
+    // process is stopped.
+    pub fn connect(address: &str) -> Result<RemoteTarget, RemoteError> {
+        let mut connection = Connection {
+            stream: TcpStream::connect(address)?,
+            decoder: PacketDecoder::default(),
+            acknowledges: true,
+        };
+        let features = connection.request("qSupported:swbreak+;vContSupported+")?;
+        if features
+            .split(';')
+            .any(|feature| feature == "QStartNoAckMode+")
+        {
+            connection.request_ok("QStartNoAckMode")?;
+            connection.acknowledges = false;
+        }
+
+        let mut target = RemoteTarget {
+            connection: RefCell::new(connection),
+            pid: 0,
+            status: TraceeStatus::Stopped,
+            tids: vec![],
+            selected_tid: 0,
+        };
+        target.update_threads()?;
+        target.pid = target.tids.first().copied().unwrap_or(0);
+        target.selected_tid = target.pid;
+        let stop_reply = target.connection.get_mut().request("?")?;
+        target.handle_stop_reply(&stop_reply)?;
+        return Ok(target);
+    }
+
+    pub fn pid(&self) -> libc::pid_t {
+        return self.pid;
+    }
+
+    pub fn status(&self) -> TraceeStatus {
+        return self.status;
+    }
+
+    pub fn thread_ids(&self) -> &[libc::pid_t] {
+        return &self.tids;
+    }
+
+    pub fn selected_tid(&self) -> libc::pid_t {
+        return self.selected_tid;
+    }
+
+    pub fn select_thread(&mut self, tid: libc::pid_t) -> bool {
+        if !self.tids.contains(&tid) {
+            return false;
+        }
+        self.selected_tid = tid;
+        return true;
+    }
+
+    // Resumes every thread, delivering the given signal to the selected one.
+    pub fn resume(&mut self, signal: Option<libc::c_int>) {
+        let packet = match signal {
+            None => "c".to_string(),
+            Some(signal) => format!("C{:02x}", to_gdb_signal(signal)),
+        };
+        self.restart(&packet);
+    }
+
+    // Resumes only the selected thread, for a single instruction.
+    pub fn step(&mut self, signal: Option<libc::c_int>) {
+        let packet = match signal {
+            None => "s".to_string(),
+            Some(signal) => format!("S{:02x}", to_gdb_signal(signal)),
+        };
+        self.restart(&packet);
+    }
+
+    // Sends a packet that resumes the process, which is not answered until it stops again.
+    fn restart(&mut self, packet: &str) {
+        if self.status == TraceeStatus::Exited || self.status == TraceeStatus::Terminated {
+            println!("process ({}) is no longer alive", self.pid);
+            return;
+        }
+
+        let connection = self.connection.get_mut();
+        let result = connection
+            .request_ok(&format!("Hc{:x}", self.selected_tid))
+            .and_then(|_| connection.send(packet));
+        match result {
+            Err(err) => println!("{}", err),
+            Ok(()) => self.status = TraceeStatus::Running,
+        }
+    }
+
+    // Blocks until the resumed process stops or exits, and reports it. Output that the stub
+    // forwards from the process in the meantime is printed as it arrives.
+    pub fn wait_on_signal(&mut self) {
+        if self.status != TraceeStatus::Running {
+            return;
+        }
+
+        loop {
+            let reply = match self.connection.get_mut().receive() {
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+                Ok(reply) => reply,
+            };
+            if let Some(hex) = reply.strip_prefix('O').filter(|hex| !hex.is_empty()) {
+                let output = from_hex(hex).unwrap_or_default();
+                print!("{}", String::from_utf8_lossy(&output));
+                continue;
+            }
+
+            if let Err(err) = self.handle_stop_reply(&reply) {
+                println!("{}", err);
+            }
+            return;
+        }
+    }
+
+    // Handles a stop reply, e.g. "T05thread:1f3;" for a thread that stopped with SIGTRAP, or
+    // "W00" for a process that exited with code 0.
+    fn handle_stop_reply(&mut self, reply: &str) -> Result<(), RemoteError> {
+        let unexpected = || RemoteError::Unexpected(reply.to_string());
+        let kind = reply.chars().next().ok_or_else(unexpected)?;
+        let number = reply
+            .get(1..3)
+            .and_then(|number_str| u8::from_str_radix(number_str, 16).ok())
+            .ok_or_else(unexpected)?;
+
+        match kind {
+            'W' => {
+                self.status = TraceeStatus::Exited;
+                self.tids.clear();
+                println!("Process ({}) exited with code [{}]", self.pid, number);
+            }
+            'X' => {
+                let signal = from_gdb_signal(number).unwrap_or(0);
+                self.status = TraceeStatus::Terminated;
+                self.tids.clear();
+                println!(
+                    "Process ({}) terminated with signal [{}: {}]",
+                    self.pid,
+                    signal,
+                    signal_name(signal),
+                );
+            }
+            'T' | 'S' => {
+                self.status = TraceeStatus::Stopped;
+                self.update_threads()?;
+                for pair in reply[3..].split(';') {
+                    if let Some(("thread", tid_str)) = pair.split_once(':') {
+                        if let Ok(tid) = libc::pid_t::from_str_radix(tid_str, 16) {
+                            self.selected_tid = tid;
+                        }
+                    }
+                }
+
+                let signal = from_gdb_signal(number).unwrap_or(0);
+                let thread_suffix = match self.selected_tid == self.pid {
+                    true => String::new(),
+                    false => format!(" in thread ({})", self.selected_tid),
+                };
+                println!(
+                    "Process ({}) stopped with signal [{}: {}]{}",
+                    self.pid,
+                    signal,
+                    signal_name(signal),
+                    thread_suffix,
+                );
+            }
+            _ => return Err(unexpected()),
+        }
+        return Ok(());
+    }
+
+    // Reads the thread list, which the stub hands out in parts.
+    fn update_threads(&mut self) -> Result<(), RemoteError> {
+        let connection = self.connection.get_mut();
+        let mut tids = vec![];
+        let mut reply = connection.request("qfThreadInfo")?;
+        while let Some(tids_str) = reply.strip_prefix('m') {
+            for tid_str in tids_str.split(',') {
+                let tid = libc::pid_t::from_str_radix(tid_str, 16)
+                    .map_err(|_| RemoteError::Unexpected(reply.clone()))?;
+                tids.push(tid);
+            }
+            reply = connection.request("qsThreadInfo")?;
+        }
+        self.tids = tids;
+        return Ok(());
+    }
+
+    // Kills the process, after which the stub may close the connection.
+    pub fn kill(&mut self) {
+        if let Err(err) = self
+            .connection
+            .get_mut()
+            .request_ok(&format!("vKill;{:x}", self.pid))
+        {
+            println!("{}", err);
+            return;
+        }
+        self.status = TraceeStatus::Terminated;
+        self.tids.clear();
+        println!(
+            "Process ({}) terminated with signal [{}: {}]",
+            self.pid,
+            libc::SIGKILL,
+            signal_name(libc::SIGKILL),
+        );
+    }
+
+    // Detaches from the process, which carries on without the stub.
+    pub fn detach(mut self) {
+        match self.connection.get_mut().request_ok("D") {
+            Err(err) => println!("{}", err),
+            Ok(()) => println!("Detached from process ({})", self.pid),
+        }
+    }
+
+    // Reads memory in chunks. Reading stops early at memory that is not mapped, which is only an
+    // error if nothing could be read at all.
+    pub fn read_memory(&self, address: u64, len: usize) -> Result<Vec<u8>, RemoteError> {
+        let mut connection = self.connection.borrow_mut();
+        let mut bytes = vec![];
+        while bytes.len() < len {
+            let chunk_address = address + bytes.len() as u64;
+            let chunk_len = (len - bytes.len()).min(MAX_MEMORY_CHUNK);
+            let chunk = match connection.request(&format!("m{:x},{:x}", chunk_address, chunk_len)) {
+                Err(err) if bytes.is_empty() => return Err(err),
+                Err(_) => break,
+                Ok(reply) => from_hex(&reply).ok_or(RemoteError::Unexpected(reply))?,
+            };
+            if chunk.is_empty() {
+                break;
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        return Ok(bytes);
+    }
+
+    pub fn write_memory(&self, address: u64, bytes: &[u8]) -> Result<(), RemoteError> {
+        return self.connection.borrow_mut().request_ok(&format!(
+            "M{:x},{:x}:{}",
+            address,
+            bytes.len(),
+            to_hex(bytes)
+        ));
+    }
+
+    pub fn read_thread_registers(
+        &self,
+        tid: libc::pid_t,
+    ) -> Result<libc::user_regs_struct, RemoteError> {
+        let hex = self.read_register_packet(tid)?;
+        let mut regs = unsafe { std::mem::zeroed::<libc::user_regs_struct>() };
+        if !decode_registers(&hex, &mut regs) {
+            return Err(RemoteError::Unexpected(hex));
+        }
+        return Ok(regs);
+    }
+
+    // Writes the registers of the selected thread. The stub's other registers, e.g. the
+    // floating-point ones, are written back as they are.
+    pub fn write_registers(&self, regs: &libc::user_regs_struct) -> Result<(), RemoteError> {
+        let mut hex = self.read_register_packet(self.selected_tid)?;
+        let core_hex = encode_registers(regs);
+        if hex.len() < core_hex.len() {
+            return Err(RemoteError::Unexpected(hex));
+        }
+        hex.replace_range(..core_hex.len(), &core_hex);
+        return self
+            .connection
+            .borrow_mut()
+            .request_ok(&format!("G{}", hex));
+    }
+
+    fn read_register_packet(&self, tid: libc::pid_t) -> Result<String, RemoteError> {
+        let mut connection = self.connection.borrow_mut();
+        connection.request_ok(&format!("Hg{:x}", tid))?;
+        let hex = connection.request("g")?;
+        // Registers that the stub cannot read are given as "xx".
+        return Ok(hex.replace('x', "0"));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use super::RemoteTarget;
+    use crate::{
+        rsp::{encode_packet, Incoming, PacketDecoder},
+        tracee::TraceeStatus,
+    };
+
+    // Serves canned replies to the packets that connecting and reading memory take.
+    fn spawn_stub() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut decoder = PacketDecoder::default();
+            let mut buffer = [0u8; 4096];
+            loop {
+                let Some(Incoming::Packet(packet)) = decoder.next_incoming() else {
+                    let n_bytes = stream.read(&mut buffer).unwrap();
+                    if n_bytes == 0 {
+                        return;
+                    }
+                    decoder.feed(&buffer[..n_bytes]);
+                    continue;
+                };
+
+                let reply: &[u8] = match packet.as_slice() {
+                    b"QStartNoAckMode" => b"OK",
+                    b"qfThreadInfo" => b"m2a,2b",
+                    b"qsThreadInfo" => b"l",
+                    b"?" => b"T05thread:2b;",
+                    b"m1000,4" => b"deadbeef",
+                    _ if packet.starts_with(b"qSupported") => b"PacketSize=4000;QStartNoAckMode+",
+                    _ => b"E01",
+                };
+                stream.write_all(b"+").unwrap();
+                stream.write_all(&encode_packet(reply)).unwrap();
+            }
+        });
+        return address;
+    }
+
+    #[test]
+    fn connect_reports_stop_and_reads_memory() {
+        let target = RemoteTarget::connect(&spawn_stub()).unwrap();
+        assert_eq!(target.pid(), 0x2a);
+        assert_eq!(target.selected_tid(), 0x2b);
+        assert_eq!(target.thread_ids(), &[0x2a, 0x2b]);
+        assert_eq!(target.status(), TraceeStatus::Stopped);
+        assert_eq!(
+            target.read_memory(0x1000, 4).unwrap(),
+            vec![0xde, 0xad, 0xbe, 0xef]
+        );
+        assert!(target.read_memory(0x2000, 4).is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use std::{collections::HashMap, mem};
+
+// Sizes of the structures that replayed system calls write, as laid out on 64-bit targets.
+const TIMESPEC_SIZE: usize = 16;
+const TIMEVAL_SIZE: usize = 16;
+const TIMEZONE_SIZE: usize = 8;
+const IOVEC_SIZE: usize = 16;
+const SOCKLEN_SIZE: usize = 4;
+
+// Signals that the kernel raises for what an instruction did, which happen again by themselves
+// when the same code runs, as long as they were not sent by someone.
+const SYNCHRONOUS_SIGNALS: &[libc::c_int] = &[
+    libc::SIGSEGV,
+    libc::SIGBUS,
+    libc::SIGILL,
+    libc::SIGFPE,
+    libc::SIGTRAP,
+    libc::SIGSYS,
+];
+
+// Something non-deterministic that happened to a recorded process.
+#[derive(PartialEq, Clone, Debug)]
+pub enum ReplayEvent {
+    // A system call returned a value, and wrote the given bytes into the process.
+    Syscall {
+        number: u64,
+        value: i64,
+        writes: Vec<(u64, Vec<u8>)>,
+    },
+    // An asynchronous signal arrived after the preceding system call returned.
+    Signal {
+        signal: libc::c_int,
+    },
+}
+
+// What was recorded from a process, from the checkpoint that recording started at.
+#[derive(PartialEq, Clone, Debug)]
+pub struct ReplayLog {
+    pub events: Vec<ReplayEvent>,
+    // Where the recording stopped, which replaying stops at once every event has been replayed.
+    pub end_pc: Option<u64>,
+}
+
+pub enum ReplayMode {
+    Recording {
+        events: Vec<ReplayEvent>,
+        // The replayed system calls that threads are in, with their numbers and arguments.
+        entries: HashMap<libc::pid_t, (u64, [u64; 6])>,
+    },
+    Replaying {
+        log: ReplayLog,
+        // The next event to replay.
+        position: usize,
+        // The thread whose system call is being replayed, once it was skipped at its entry.
+        skipped_tid: Option<libc::pid_t>,
+        // The bytes that the breakpoint at the end of the recording replaced, once inserted.
+        end_breakpoint: Option<Vec<u8>>,
+    },
+}
+
+// How a signal that a thread stopped with is dealt with while recording or replaying.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ReplayedSignal {
+    // The signal is handled as usual.
+    Delivered,
+    // The signal did not happen in the recording, so it is discarded.
+    Suppressed,
+    // The thread reached the end of the recording.
+    End,
+}
+
+// Whether a system call returns something that may differ from run to run, so that it is
+// recorded and then skipped while replaying, returning what it did in the recording instead.
+// System calls that change the state of the kernel, e.g. open(), are run again instead.
+pub fn is_replayed_syscall(name: &str) -> bool {
+    return matches!(
+        name,
+        "read"
+            | "pread64"
+            | "readv"
+            | "recvfrom"
+            | "getrandom"
+            | "getdents64"
+            | "getpid"
+            | "getppid"
+            | "gettid"
+            | "clock_gettime"
+            | "gettimeofday"
+            | "nanosleep"
+            | "clock_nanosleep"
+            | "uname"
+            | "sysinfo"
+    );
+}
+
+// Lists the memory that a replayed system call wrote, as addresses and lengths, given its
+// arguments and return value. Memory is read with the given function, e.g. for the iovecs of
+// readv().
+pub fn syscall_outputs(
+    name: &str,
+    args: &[u64; 6],
+    value: i64,
+    read_memory: impl Fn(u64, usize) -> Option<Vec<u8>>,
+) -> Vec<(u64, usize)> {
+    let succeeded = value >= 0;
+    let mut outputs = match name {
+        "read" | "pread64" | "getdents64" if succeeded => vec![(args[1], value as usize)],
+        "getrandom" if succeeded => vec![(args[0], value as usize)],
+        "recvfrom" if succeeded => {
+            let address_len = read_memory(args[5], SOCKLEN_SIZE).map_or(0, |bytes| {
+                u32::from_le_bytes(bytes.try_into().unwrap()) as usize
+            });
+            vec![
+                (args[1], value as usize),
+                (args[4], address_len),
+                (args[5], SOCKLEN_SIZE),
+            ]
+        }
+        "readv" if succeeded => {
+            // The bytes that were read fill the buffers in order.
+            let count = args[2] as usize;
+            let iovecs = read_memory(args[1], count * IOVEC_SIZE).unwrap_or_default();
+            let mut remaining = value as usize;
+            let mut outputs = vec![];
+            for iovec in iovecs.chunks_exact(IOVEC_SIZE) {
+                let base = u64::from_le_bytes(iovec[..8].try_into().unwrap());
+                let len = u64::from_le_bytes(iovec[8..].try_into().unwrap()) as usize;
+                outputs.push((base, len.min(remaining)));
+                remaining -= len.min(remaining);
+            }
+            outputs
+        }
+        "clock_gettime" if succeeded => vec![(args[1], TIMESPEC_SIZE)],
+        "gettimeofday" if succeeded => vec![(args[0], TIMEVAL_SIZE), (args[1], TIMEZONE_SIZE)],
+        // The remaining time is written when interrupted.
+        "nanosleep" => vec![(args[1], TIMESPEC_SIZE)],
+        "clock_nanosleep" => vec![(args[3], TIMESPEC_SIZE)],
+        "uname" if succeeded => vec![(args[0], mem::size_of::<libc::utsname>())],
+        "sysinfo" if succeeded => vec![(args[0], mem::size_of::<libc::sysinfo>())],
+        _ => vec![],
+    };
+    outputs.retain(|(address, len)| *address != 0 && *len != 0);
+    return outputs;
+}
+
+// Whether a signal arrived from outside of the thread, e.g. from kill() or a timer, rather than
+// being raised by the kernel for one of its instructions. Only those are recorded, since the rest
+// are raised again while replaying.
+pub fn is_asynchronous_signal(signal: libc::c_int, code: libc::c_int) -> bool {
+    // Positive codes are set by the kernel, and others by whoever sent the signal.
+    return !(SYNCHRONOUS_SIGNALS.contains(&signal) && code > 0);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_asynchronous_signal, is_replayed_syscall, syscall_outputs};
+    use crate::signal::{SI_KERNEL, SI_USER};
+
+    #[test]
+    fn is_replayed_syscall_selects_inputs_but_not_state_changes() {
+        assert!(is_replayed_syscall("read"));
+        assert!(is_replayed_syscall("getrandom"));
+        assert!(!is_replayed_syscall("openat"));
+        assert!(!is_replayed_syscall("mmap"));
+    }
+
+    #[test]
+    fn syscall_outputs_follow_arguments_and_return_value() {
+        let no_memory = |_, _| None;
+        assert_eq!(
+            syscall_outputs("read", &[3, 0x1000, 64, 0, 0, 0], 10, no_memory),
+            vec![(0x1000, 10)]
+        );
+        assert_eq!(
+            syscall_outputs("read", &[3, 0x1000, 64, 0, 0, 0], -11, no_memory),
+            vec![]
+        );
+        assert_eq!(
+            syscall_outputs("getrandom", &[0x2000, 16, 0, 0, 0, 0], 16, no_memory),
+            vec![(0x2000, 16)]
+        );
+        assert_eq!(
+            syscall_outputs("nanosleep", &[0x3000, 0, 0, 0, 0, 0], -4, no_memory),
+            vec![]
+        );
+
+        let iovecs = [0x1000u64, 4, 0x2000, 8]
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect::<Vec<u8>>();
+        assert_eq!(
+            syscall_outputs("readv", &[3, 0x500, 2, 0, 0, 0], 6, |address, len| {
+                assert_eq!((address, len), (0x500, 32));
+                return Some(iovecs.clone());
+            }),
+            vec![(0x1000, 4), (0x2000, 2)]
+        );
+
+        assert_eq!(
+            syscall_outputs(
+                "recvfrom",
+                &[3, 0x1000, 64, 0, 0x600, 0x700],
+                5,
+                |address, _| {
+                    assert_eq!(address, 0x700);
+                    return Some(16u32.to_le_bytes().to_vec());
+                }
+            ),
+            vec![(0x1000, 5), (0x600, 16), (0x700, 4)]
+        );
+    }
+
+    #[test]
+    fn is_asynchronous_signal_tells_sent_signals_from_faults() {
+        // SEGV_MAPERR
+        assert!(!is_asynchronous_signal(libc::SIGSEGV, 1));
+        assert!(is_asynchronous_signal(libc::SIGSEGV, SI_USER));
+        assert!(is_asynchronous_signal(libc::SIGALRM, SI_KERNEL));
+        assert!(is_asynchronous_signal(libc::SIGCHLD, libc::CLD_EXITED));
+    }
+}
@@ -0,0 +1,317 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::Sender,
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use crate::{
+    cleanup,
+    json::{Json, JsonError},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RpcError {
+    #[error("parse error: {0}")]
+    Parse(#[from] JsonError),
+    #[error("invalid request: {0}")]
+    InvalidRequest(&'static str),
+    #[error("unknown method: \"{0}\"")]
+    MethodNotFound(String),
+    #[error("invalid params: {0}")]
+    InvalidParams(String),
+    // The request was valid, but could not be carried out, e.g. because memory is unmapped.
+    #[error("{0}")]
+    Failed(String),
+}
+
+impl RpcError {
+    // The error code defined by JSON-RPC 2.0, or an implementation-defined server error.
+    fn code(&self) -> i64 {
+        return match self {
+            RpcError::Parse(_) => -32700,
+            RpcError::InvalidRequest(_) => -32600,
+            RpcError::MethodNotFound(_) => -32601,
+            RpcError::InvalidParams(_) => -32602,
+            RpcError::Failed(_) => -32000,
+        };
+    }
+}
+
+// Clients of every server are numbered together, so that they are told apart by their ids.
+static NEXT_CLIENT_ID: AtomicUsize = AtomicUsize::new(1);
+
+// A connection to the control socket, or to another server that takes requests, e.g. the web
+// server. Clones refer to the same connection.
+#[derive(Clone)]
+pub struct RpcClient {
+    id: usize,
+    stream: Arc<Mutex<dyn Write + Send>>,
+}
+
+impl RpcClient {
+    // A client whose responses and notifications are written to the stream.
+    pub fn new(stream: impl Write + Send + 'static) -> RpcClient {
+        return RpcClient {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::SeqCst),
+            stream: Arc::new(Mutex::new(stream)),
+        };
+    }
+
+    // Sends a message, which is a line of JSON. Returns false once the client is gone.
+    fn send(&self, message: &Json) -> bool {
+        let mut stream = self.stream.lock().unwrap();
+        return stream
+            .write_all(format!("{}\n", message).as_bytes())
+            .is_ok();
+    }
+}
+
+// A request from a client, which is handled by the session.
+pub struct RpcRequest {
+    pub client: RpcClient,
+    // Notifications have no id, and get no response.
+    id: Option<Json>,
+    pub method: String,
+    pub params: Json,
+}
+
+impl RpcRequest {
+    pub fn respond(&self, result: Result<Json, RpcError>) {
+        if let Some(id) = &self.id {
+            self.client.send(&format_response(id.clone(), result));
+        }
+    }
+}
+
+// Accepts JSON-RPC 2.0 clients on a Unix socket, and passes their requests on to the session.
+// Messages are lines of JSON in both directions.
+pub struct RpcServer {
+    path: PathBuf,
+    closed: Arc<AtomicBool>,
+    subscribers: Vec<RpcClient>,
+}
+
+impl RpcServer {
+    // Listens on a new socket at the path, sending every request that arrives to `requests`.
+    pub fn listen<T>(path: &Path, requests: Sender<T>) -> io::Result<RpcServer>
+    where
+        T: From<RpcRequest> + Send + 'static,
+    {
+        let listener = UnixListener::bind(path)?;
+        let closed = Arc::new(AtomicBool::new(false));
+        let is_closed = closed.clone();
+        thread::spawn(move || {
+            unsafe { cleanup::block_fatal_signals() };
+            for stream_result in listener.incoming() {
+                if is_closed.load(Ordering::SeqCst) {
+                    return;
+                }
+                let Ok(stream) = stream_result else {
+                    continue;
+                };
+                let Ok(reader) = stream.try_clone() else {
+                    continue;
+                };
+                let client = RpcClient::new(stream);
+                let requests = requests.clone();
+                thread::spawn(move || serve_client(client, reader, requests));
+            }
+        });
+
+        return Ok(RpcServer {
+            path: path.to_path_buf(),
+            closed,
+            subscribers: vec![],
+        });
+    }
+
+    // Sends every published notification to the client from now on.
+    pub fn subscribe(&mut self, client: &RpcClient) {
+        if !self
+            .subscribers
+            .iter()
+            .any(|subscriber| subscriber.id == client.id)
+        {
+            self.subscribers.push(client.clone());
+        }
+    }
+
+    pub fn unsubscribe(&mut self, client: &RpcClient) {
+        self.subscribers
+            .retain(|subscriber| subscriber.id != client.id);
+    }
+
+    // Sends a notification to the subscribers, forgetting those that are gone.
+    pub fn publish(&mut self, method: &str, params: Json) {
+        let notification = Json::object(vec![
+            ("jsonrpc", Json::from("2.0")),
+            ("method", Json::from(method)),
+            ("params", params),
+        ]);
+        self.subscribers
+            .retain(|subscriber| subscriber.send(&notification));
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        // The listening thread is blocked accepting, so it is woken up by a last connection.
+        self.closed.store(true, Ordering::SeqCst);
+        let _ = UnixStream::connect(&self.path);
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Reads the requests of a client until it disconnects. Malformed ones are answered right away.
+fn serve_client<T: From<RpcRequest>>(client: RpcClient, stream: UnixStream, requests: Sender<T>) {
+    unsafe { cleanup::block_fatal_signals() };
+    for line_result in BufReader::new(stream).lines() {
+        let Ok(line) = line_result else {
+            return;
+        };
+        if !handle_line(&client, &line, &requests) {
+            return;
+        }
+    }
+}
+
+// Passes a request of a client on to the session, or answers it right away if it is malformed.
+// Returns false once the session is gone.
+pub fn handle_line<T: From<RpcRequest>>(
+    client: &RpcClient,
+    line: &str,
+    requests: &Sender<T>,
+) -> bool {
+    if line.trim().is_empty() {
+        return true;
+    }
+
+    match parse_request(line) {
+        Err((id, err)) => {
+            client.send(&format_response(id, Err(err)));
+        }
+        Ok((id, method, params)) => {
+            let request = RpcRequest {
+                client: client.clone(),
+                id,
+                method,
+                params,
+            };
+            return requests.send(T::from(request)).is_ok();
+        }
+    }
+    return true;
+}
+
+// Reads an address out of the params, given as a number or as a string, e.g. "0x4005d0". Strings
+// hold any 64-bit address, which numbers of JSON do not.
+pub fn read_address(params: &Json, name: &str) -> Result<u64, RpcError> {
+    let value = params.get(name);
+    let address = match value.and_then(Json::as_str) {
+        None => value.and_then(Json::as_u64),
+        Some(address_str) => match address_str.strip_prefix("0x") {
+            None => address_str.parse::<u64>().ok(),
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        },
+    };
+    return address.ok_or_else(|| RpcError::InvalidParams(format!("invalid \"{}\"", name)));
+}
+
+// Parses a request into its id, method and params. On failure, returns the id to respond to,
+// which is null if it could not be told.
+fn parse_request(line: &str) -> Result<(Option<Json>, String, Json), (Json, RpcError)> {
+    let request = Json::parse(line).map_err(|err| (Json::Null, RpcError::from(err)))?;
+    let id = request.get("id").cloned();
+    let error_id = id.clone().unwrap_or(Json::Null);
+    if request.get("jsonrpc").and_then(Json::as_str) != Some("2.0") {
+        return Err((
+            error_id,
+            RpcError::InvalidRequest("expected \"jsonrpc\": \"2.0\""),
+        ));
+    }
+    let Some(method) = request.get("method").and_then(Json::as_str) else {
+        return Err((error_id, RpcError::InvalidRequest("missing \"method\"")));
+    };
+    let params = request.get("params").cloned().unwrap_or(Json::Null);
+    return Ok((id, method.to_string(), params));
+}
+
+fn format_response(id: Json, result: Result<Json, RpcError>) -> Json {
+    let outcome = match result {
+        Ok(result) => ("result", result),
+        Err(err) => (
+            "error",
+            Json::object(vec![
+                ("code", Json::from(err.code())),
+                ("message", Json::from(err.to_string())),
+            ]),
+        ),
+    };
+    return Json::object(vec![("jsonrpc", Json::from("2.0")), ("id", id), outcome]);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_response, parse_request, read_address, RpcError};
+    use crate::json::Json;
+
+    #[test]
+    fn parse_request_reads_id_method_and_params() {
+        let (id, method, params) = parse_request(
+            r#"{"jsonrpc":"2.0","id":7,"method":"readMemory","params":{"length":4}}"#,
+        )
+        .unwrap();
+        assert_eq!(id, Some(Json::from(7)));
+        assert_eq!(method, "readMemory");
+        assert_eq!(params.get("length"), Some(&Json::from(4)));
+
+        let (id, _, params) = parse_request(r#"{"jsonrpc":"2.0","method":"continue"}"#).unwrap();
+        assert_eq!(id, None);
+        assert_eq!(params, Json::Null);
+    }
+
+    #[test]
+    fn parse_request_rejects_invalid_requests() {
+        let (id, err) = parse_request("{").unwrap_err();
+        assert_eq!(id, Json::Null);
+        assert!(matches!(err, RpcError::Parse(_)));
+
+        let (id, err) = parse_request(r#"{"id":"a","method":"status"}"#).unwrap_err();
+        assert_eq!(id, Json::from("a"));
+        assert!(matches!(err, RpcError::InvalidRequest(_)));
+    }
+
+    #[test]
+    fn read_address_accepts_numbers_and_strings() {
+        let params =
+            Json::parse(r#"{"a":4096,"b":"0xffff800000001000","c":"12","d":"x"}"#).unwrap();
+        assert_eq!(read_address(&params, "a").unwrap(), 4096);
+        assert_eq!(read_address(&params, "b").unwrap(), 0xffff800000001000);
+        assert_eq!(read_address(&params, "c").unwrap(), 12);
+        assert!(read_address(&params, "d").is_err());
+        assert!(read_address(&params, "e").is_err());
+    }
+
+    #[test]
+    fn format_response_writes_result_or_error() {
+        assert_eq!(
+            format_response(Json::from(1), Ok(Json::Null)).to_string(),
+            r#"{"jsonrpc":"2.0","id":1,"result":null}"#
+        );
+        assert_eq!(
+            format_response(
+                Json::from(2),
+                Err(RpcError::MethodNotFound("jump".to_string()))
+            )
+            .to_string(),
+            r#"{"jsonrpc":"2.0","id":2,"error":{"code":-32601,"message":"unknown method: \"jump\""}}"#
+        );
+    }
+}
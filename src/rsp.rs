@@ -0,0 +1,286 @@
+// Framing, encodings and numbering shared by both ends of the GDB Remote Serial Protocol (see
+// "Remote Protocol" in the GDB manual).
+
+// The byte that the client sends outside of any packet to interrupt a running tracee (^C).
+pub const INTERRUPT: u8 = 0x03;
+
+// Registers in the order of the target description: x0 to x30, sp, pc, and cpsr.
+pub const REGISTER_COUNT: usize = 34;
+pub const CPSR_REGISTER: usize = 33;
+
+// Linux signals and the numbers that GDB knows them by, which only agree for the older ones (see
+// gdb/signals.def).
+pub const GDB_SIGNALS: [(libc::c_int, u8); 31] = [
+    (libc::SIGHUP, 1),
+    (libc::SIGINT, 2),
+    (libc::SIGQUIT, 3),
+    (libc::SIGILL, 4),
+    (libc::SIGTRAP, 5),
+    (libc::SIGABRT, 6),
+    (libc::SIGBUS, 10),
+    (libc::SIGFPE, 8),
+    (libc::SIGKILL, 9),
+    (libc::SIGUSR1, 30),
+    (libc::SIGSEGV, 11),
+    (libc::SIGUSR2, 31),
+    (libc::SIGPIPE, 13),
+    (libc::SIGALRM, 14),
+    (libc::SIGTERM, 15),
+    (libc::SIGSTKFLT, 143),
+    (libc::SIGCHLD, 20),
+    (libc::SIGCONT, 19),
+    (libc::SIGSTOP, 17),
+    (libc::SIGTSTP, 18),
+    (libc::SIGTTIN, 21),
+    (libc::SIGTTOU, 22),
+    (libc::SIGURG, 16),
+    (libc::SIGXCPU, 24),
+    (libc::SIGXFSZ, 25),
+    (libc::SIGVTALRM, 26),
+    (libc::SIGPROF, 27),
+    (libc::SIGWINCH, 28),
+    (libc::SIGIO, 23),
+    (libc::SIGPWR, 32),
+    (libc::SIGSYS, 12),
+];
+// GDB numbers real-time signals 33 to 63 from 45 on, but 32 and 64 separately.
+pub const GDB_SIGNAL_REALTIME_33: u8 = 45;
+pub const GDB_SIGNAL_REALTIME_32: u8 = 77;
+pub const GDB_SIGNAL_REALTIME_64: u8 = 78;
+
+// Something that the other end sent.
+#[derive(PartialEq, Debug)]
+pub enum Incoming {
+    Packet(Vec<u8>),
+    // A packet that arrived corrupted, and should be sent again.
+    Corrupted,
+    Interrupt,
+}
+
+// Splits the byte stream from the other end into packets, which are framed as `$data#checksum`.
+#[derive(Default)]
+pub struct PacketDecoder {
+    buffer: Vec<u8>,
+}
+
+impl PacketDecoder {
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    // Takes any interrupts out of the stream, leaving packets for later. Returns whether there
+    // were any.
+    pub fn take_interrupts(&mut self) -> bool {
+        let len = self.buffer.len();
+        self.buffer.retain(|byte| *byte != INTERRUPT);
+        return self.buffer.len() != len;
+    }
+
+    pub fn next_incoming(&mut self) -> Option<Incoming> {
+        // Acknowledgements are skipped, since packets are never sent again.
+        loop {
+            match self.buffer.first()? {
+                b'$' => break,
+                &INTERRUPT => {
+                    self.buffer.remove(0);
+                    return Some(Incoming::Interrupt);
+                }
+                _ => {
+                    self.buffer.remove(0);
+                }
+            }
+        }
+
+        let end = self.buffer.iter().position(|byte| *byte == b'#')?;
+        if self.buffer.len() < end + 3 {
+            return None;
+        }
+        let data = self.buffer[1..end].to_vec();
+        let checksum_str = String::from_utf8_lossy(&self.buffer[end + 1..end + 3]).to_string();
+        self.buffer.drain(..end + 3);
+
+        return match u8::from_str_radix(&checksum_str, 16) {
+            Ok(expected) if expected == checksum(&data) => {
+                Some(Incoming::Packet(decode_data(&data)))
+            }
+            _ => Some(Incoming::Corrupted),
+        };
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    return data.iter().fold(0, |sum: u8, byte| sum.wrapping_add(*byte));
+}
+
+// Frames a packet, escaping the bytes that would end it early.
+pub fn encode_packet(data: &[u8]) -> Vec<u8> {
+    let mut escaped = vec![];
+    for byte in data {
+        match byte {
+            b'$' | b'#' | b'}' | b'*' => escaped.extend_from_slice(&[b'}', byte ^ 0x20]),
+            _ => escaped.push(*byte),
+        }
+    }
+
+    let mut packet = vec![b'$'];
+    packet.extend_from_slice(&escaped);
+    packet.extend_from_slice(format!("#{:02x}", checksum(&escaped)).as_bytes());
+    return packet;
+}
+
+// Undoes escapes and run-length encoding, where "x*" followed by a count byte stands for x and
+// another (count - 29) copies of it. Stubs like gdbserver compress replies that way.
+fn decode_data(data: &[u8]) -> Vec<u8> {
+    let mut decoded = vec![];
+    let mut bytes = data.iter();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'}' => decoded.push(bytes.next().map_or(0, |byte| byte ^ 0x20)),
+            b'*' => {
+                let (Some(last), Some(count)) = (decoded.last().copied(), bytes.next()) else {
+                    continue;
+                };
+                let repeats = count.saturating_sub(29) as usize;
+                decoded.extend(std::iter::repeat_n(last, repeats));
+            }
+            _ => decoded.push(*byte),
+        }
+    }
+    return decoded;
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    return (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect();
+}
+
+// Encodes the registers as the `g` packet has them: target-endian bytes in hexadecimal, in the
+// order of the target description.
+pub fn encode_registers(regs: &libc::user_regs_struct) -> String {
+    let mut bytes = vec![];
+    for value in regs.regs.iter().chain([regs.sp, regs.pc].iter()) {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes.extend_from_slice(&(regs.pstate as u32).to_le_bytes());
+    return to_hex(&bytes);
+}
+
+// Decodes the registers of a `g` or `G` packet into `regs`. Registers beyond cpsr, like the
+// floating-point ones that stubs may include, are ignored. Returns false if any are missing.
+pub fn decode_registers(hex: &str, regs: &mut libc::user_regs_struct) -> bool {
+    let Some(bytes) = hex.get(..(33 * 8 + 4) * 2).and_then(from_hex) else {
+        return false;
+    };
+
+    let values = bytes[..33 * 8]
+        .chunks(8)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+        .collect::<Vec<u64>>();
+    regs.regs.copy_from_slice(&values[..31]);
+    regs.sp = values[31];
+    regs.pc = values[32];
+    // The upper half of pstate is not part of cpsr.
+    let cpsr = u32::from_le_bytes(bytes[33 * 8..].try_into().unwrap());
+    regs.pstate = (regs.pstate & !0xffff_ffff) | cpsr as u64;
+    return true;
+}
+
+pub fn to_gdb_signal(signal: libc::c_int) -> u8 {
+    if let Some((_, gdb_signal)) = GDB_SIGNALS.iter().find(|(linux, _)| *linux == signal) {
+        return *gdb_signal;
+    }
+    return match signal {
+        32 => GDB_SIGNAL_REALTIME_32,
+        33..=63 => GDB_SIGNAL_REALTIME_33 + (signal - 33) as u8,
+        64 => GDB_SIGNAL_REALTIME_64,
+        _ => 0,
+    };
+}
+
+pub fn from_gdb_signal(gdb_signal: u8) -> Option<libc::c_int> {
+    if let Some((signal, _)) = GDB_SIGNALS.iter().find(|(_, gdb)| *gdb == gdb_signal) {
+        return Some(*signal);
+    }
+    return match gdb_signal {
+        GDB_SIGNAL_REALTIME_32 => Some(32),
+        GDB_SIGNAL_REALTIME_33..=75 => {
+            Some((gdb_signal - GDB_SIGNAL_REALTIME_33) as libc::c_int + 33)
+        }
+        GDB_SIGNAL_REALTIME_64 => Some(64),
+        _ => None,
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode_packet, from_gdb_signal, from_hex, to_gdb_signal, Incoming, PacketDecoder};
+
+    #[test]
+    fn packet_decoder_splits_packets_and_interrupts() {
+        let mut decoder = PacketDecoder::default();
+        decoder.feed(b"+$qC#b4\x03$m0,4");
+        assert_eq!(
+            decoder.next_incoming(),
+            Some(Incoming::Packet(b"qC".to_vec()))
+        );
+        assert_eq!(decoder.next_incoming(), Some(Incoming::Interrupt));
+        assert_eq!(decoder.next_incoming(), None);
+
+        decoder.feed(b"#fd$g#00");
+        assert_eq!(
+            decoder.next_incoming(),
+            Some(Incoming::Packet(b"m0,4".to_vec()))
+        );
+        assert_eq!(decoder.next_incoming(), Some(Incoming::Corrupted));
+    }
+
+    #[test]
+    fn encode_packet_escapes_special_bytes() {
+        assert_eq!(encode_packet(b"OK"), b"$OK#9a".to_vec());
+        assert_eq!(encode_packet(b"a#b"), b"$a}\x03b#43".to_vec());
+
+        let mut decoder = PacketDecoder::default();
+        decoder.feed(&encode_packet(b"a#b}c"));
+        assert_eq!(
+            decoder.next_incoming(),
+            Some(Incoming::Packet(b"a#b}c".to_vec()))
+        );
+    }
+
+    #[test]
+    fn packet_decoder_expands_run_lengths() {
+        let mut decoder = PacketDecoder::default();
+        decoder.feed(b"$0* #7a");
+        assert_eq!(
+            decoder.next_incoming(),
+            Some(Incoming::Packet(b"0000".to_vec()))
+        );
+    }
+
+    #[test]
+    fn gdb_signals_round_trip() {
+        assert_eq!(to_gdb_signal(libc::SIGTRAP), 5);
+        assert_eq!(to_gdb_signal(libc::SIGUSR1), 30);
+        assert_eq!(to_gdb_signal(libc::SIGCHLD), 20);
+        assert_eq!(to_gdb_signal(34), 46);
+        for signal in 1..=64 {
+            assert_eq!(from_gdb_signal(to_gdb_signal(signal)), Some(signal));
+        }
+    }
+
+    #[test]
+    fn from_hex_rejects_odd_lengths() {
+        assert_eq!(from_hex("00ff7f"), Some(vec![0x00, 0xff, 0x7f]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+}
@@ -0,0 +1,402 @@
+use std::{cell::RefCell, rc::Rc};
+
+use rhai::{Array, Blob, Dynamic, Engine, EvalAltResult, FnPtr, Map, Scope, AST};
+
+use crate::{
+    json::Json, register::read_general_purpose_register, trace_file::TraceRecord, tracee::Tracee,
+};
+
+// The longest string that `read_string` reads, in case it is not terminated.
+const MAX_STRING_LEN: usize = 4096;
+
+// What a script asked of the session, which is carried out once the script returns.
+#[derive(PartialEq, Clone, Debug)]
+pub enum ScriptAction {
+    Break(u64),
+    DeleteBreak(u64),
+    // Resumes the tracee in the background, e.g. from a hook that only logs a stop.
+    Resume,
+    Command(String),
+}
+
+#[derive(Default)]
+struct Hooks {
+    stop: Vec<FnPtr>,
+    syscall: Vec<FnPtr>,
+    signal: Vec<FnPtr>,
+    exit: Vec<FnPtr>,
+    // Session commands added by the script, by their first word.
+    commands: Vec<(String, FnPtr)>,
+}
+
+// The tracee that a running script operates on, and what it asked for so far.
+struct ScriptContext {
+    // Only set while a script runs, and valid for as long.
+    tracee: *mut Tracee,
+    actions: Vec<ScriptAction>,
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+// What running a script came to: the value that it evaluated to, unless `()`, or why it failed,
+// along with what it asked of the session.
+pub type ScriptOutcome = (Result<Option<String>, String>, Vec<ScriptAction>);
+
+// Runs rhai scripts (see https://rhai.rs) against the tracee. Variables and functions persist
+// from one script to the next, and hooks run whenever a tracee records an event, e.g.:
+//
+//     let malloc = symbol("malloc");
+//     break_at(malloc);
+//     on_stop(|stop| {
+//         if stop.pc == malloc {
+//             let size = register("x0");
+//             if size > 1024 * 1024 { print(`malloc(${size})`); }
+//             resume();
+//         }
+//     });
+//
+// Scripts can also add session commands, which get the rest of the words as an array:
+//
+//     register_command("args", |args| print(args));
+pub struct ScriptEngine {
+    engine: Engine,
+    scope: Scope<'static>,
+    // The functions defined so far, which hooks may call.
+    functions: AST,
+    hooks: Rc<RefCell<Hooks>>,
+    context: Rc<RefCell<ScriptContext>>,
+}
+
+impl ScriptEngine {
+    pub fn new() -> ScriptEngine {
+        let mut engine = Engine::new();
+        let hooks = Rc::new(RefCell::new(Hooks::default()));
+        let context = Rc::new(RefCell::new(ScriptContext {
+            tracee: std::ptr::null_mut(),
+            actions: vec![],
+        }));
+        register_tracee_functions(&mut engine, &context);
+        register_hook_functions(&mut engine, &hooks);
+        return ScriptEngine {
+            engine,
+            scope: Scope::new(),
+            functions: AST::empty(),
+            hooks,
+            context,
+        };
+    }
+
+    // Whether any hooks are registered, which need tracees to queue their records.
+    pub fn has_hooks(&self) -> bool {
+        let hooks = self.hooks.borrow();
+        return !hooks.stop.is_empty()
+            || !hooks.syscall.is_empty()
+            || !hooks.signal.is_empty()
+            || !hooks.exit.is_empty();
+    }
+
+    // Runs a script against the tracee.
+    pub unsafe fn run(&mut self, tracee: &mut Tracee, script: &str) -> ScriptOutcome {
+        let ast = match self.engine.compile(script) {
+            Err(err) => return (Err(err.to_string()), vec![]),
+            Ok(ast) => self.functions.merge(&ast),
+        };
+        self.functions = ast.clone_functions_only();
+
+        self.context.borrow_mut().tracee = tracee;
+        let result = self
+            .engine
+            .eval_ast_with_scope::<Dynamic>(&mut self.scope, &ast);
+        let result = describe_result(result);
+        return (result, self.take_actions());
+    }
+
+    // Runs the hooks of a record, in the order that they were registered.
+    pub unsafe fn run_hooks(
+        &mut self,
+        tracee: &mut Tracee,
+        record: &TraceRecord,
+    ) -> Vec<ScriptAction> {
+        let hooks = {
+            let hooks = self.hooks.borrow();
+            match record {
+                TraceRecord::Stop { .. } => hooks.stop.clone(),
+                TraceRecord::Syscall { .. } => hooks.syscall.clone(),
+                TraceRecord::Signal { .. } => hooks.signal.clone(),
+                TraceRecord::Exit { .. } | TraceRecord::Terminated { .. } => hooks.exit.clone(),
+                // Scripts have no hooks for these.
+                TraceRecord::Fork { .. } | TraceRecord::Exec { .. } => vec![],
+            }
+        };
+        if hooks.is_empty() {
+            return vec![];
+        }
+
+        let mut event = json_to_dynamic(&record.to_json(tracee.pid())).cast::<Map>();
+        if let TraceRecord::Stop { .. } = record {
+            let pc = tracee.read_general_purpose_registers().pc;
+            event.insert("pc".into(), Dynamic::from(pc as i64));
+        }
+        let event = Dynamic::from(event);
+
+        self.context.borrow_mut().tracee = tracee;
+        for hook in hooks {
+            if let Err(err) = hook.call::<Dynamic>(&self.engine, &self.functions, (event.clone(),))
+            {
+                println!("script hook failed: {}", err);
+            }
+        }
+        return self.take_actions();
+    }
+
+    // The session commands that the scripts added.
+    pub fn commands(&self) -> Vec<String> {
+        return self
+            .hooks
+            .borrow()
+            .commands
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect();
+    }
+
+    // Runs a command that a script added, passing it the rest of the words. Returns None if no
+    // script added the command.
+    pub unsafe fn run_command(
+        &mut self,
+        tracee: &mut Tracee,
+        words: &[&str],
+    ) -> Option<ScriptOutcome> {
+        let (name, args) = words.split_first()?;
+        let command = self
+            .hooks
+            .borrow()
+            .commands
+            .iter()
+            .find(|(command_name, _)| command_name == name)
+            .map(|(_, command)| command.clone())?;
+
+        let args = args
+            .iter()
+            .map(|arg| Dynamic::from(arg.to_string()))
+            .collect::<Array>();
+        self.context.borrow_mut().tracee = tracee;
+        let result = command.call::<Dynamic>(&self.engine, &self.functions, (args,));
+        let result = describe_result(result);
+        return Some((result, self.take_actions()));
+    }
+
+    fn take_actions(&mut self) -> Vec<ScriptAction> {
+        let mut context = self.context.borrow_mut();
+        context.tracee = std::ptr::null_mut();
+        return std::mem::take(&mut context.actions);
+    }
+}
+
+impl Default for ScriptEngine {
+    fn default() -> ScriptEngine {
+        return ScriptEngine::new();
+    }
+}
+
+// Describes what a script evaluated to, unless it is `()`, or why it failed.
+fn describe_result(result: ScriptResult<Dynamic>) -> Result<Option<String>, String> {
+    return match result {
+        Err(err) => Err(err.to_string()),
+        Ok(value) if value.is_unit() => Ok(None),
+        Ok(value) => Ok(Some(value.to_string())),
+    };
+}
+
+// Runs `f` on the tracee of the running script.
+fn with_tracee<T>(
+    context: &Rc<RefCell<ScriptContext>>,
+    f: impl FnOnce(&mut Tracee) -> ScriptResult<T>,
+) -> ScriptResult<T> {
+    let tracee = context.borrow().tracee;
+    if tracee.is_null() {
+        return Err("no tracee to operate on".into());
+    }
+    // The pointer is only set while the tracee is borrowed for the script.
+    return f(unsafe { &mut *tracee });
+}
+
+fn register_tracee_functions(engine: &mut Engine, context: &Rc<RefCell<ScriptContext>>) {
+    let ctx = context.clone();
+    engine.register_fn("pid", move || -> ScriptResult<i64> {
+        return with_tracee(&ctx, |tracee| Ok(tracee.pid() as i64));
+    });
+    let ctx = context.clone();
+    engine.register_fn("tid", move || -> ScriptResult<i64> {
+        return with_tracee(&ctx, |tracee| Ok(tracee.selected_tid() as i64));
+    });
+
+    let ctx = context.clone();
+    engine.register_fn(
+        "read_memory",
+        move |address: i64, len: i64| -> ScriptResult<Blob> {
+            return with_tracee(&ctx, |tracee| unsafe {
+                return tracee
+                    .read_memory(address as u64, len.max(0) as usize)
+                    .map_err(|err| err.to_string().into());
+            });
+        },
+    );
+    let ctx = context.clone();
+    engine.register_fn("read_u64", move |address: i64| -> ScriptResult<i64> {
+        return with_tracee(&ctx, |tracee| unsafe {
+            let bytes = tracee
+                .read_memory(address as u64, 8)
+                .map_err(|err| err.to_string())?;
+            return Ok(i64::from_le_bytes(bytes.try_into().unwrap()));
+        });
+    });
+    let ctx = context.clone();
+    engine.register_fn("read_string", move |address: i64| -> ScriptResult<String> {
+        return with_tracee(&ctx, |tracee| unsafe {
+            let bytes = tracee
+                .read_c_string(address as u64, MAX_STRING_LEN)
+                .map_err(|err| err.to_string())?;
+            return Ok(String::from_utf8_lossy(&bytes).to_string());
+        });
+    });
+    let ctx = context.clone();
+    engine.register_fn(
+        "write_memory",
+        move |address: i64, bytes: Blob| -> ScriptResult<()> {
+            return with_tracee(&ctx, |tracee| unsafe {
+                return tracee
+                    .write_memory(address as u64, &bytes)
+                    .map_err(|err| err.to_string().into());
+            });
+        },
+    );
+
+    let ctx = context.clone();
+    engine.register_fn("register", move |name: &str| -> ScriptResult<i64> {
+        return with_tracee(&ctx, |tracee| unsafe {
+            let regs = tracee.read_general_purpose_registers();
+            return match read_general_purpose_register(&regs, name) {
+                None => Err(format!("unknown register: \"{}\"", name).into()),
+                Some(value) => Ok(value as i64),
+            };
+        });
+    });
+    let ctx = context.clone();
+    engine.register_fn("registers", move || -> ScriptResult<Map> {
+        return with_tracee(&ctx, |tracee| unsafe {
+            let regs = tracee.read_general_purpose_registers();
+            let mut map = Map::new();
+            for (i, value) in regs.regs.iter().enumerate() {
+                map.insert(format!("x{}", i).into(), Dynamic::from(*value as i64));
+            }
+            map.insert("sp".into(), Dynamic::from(regs.sp as i64));
+            map.insert("pc".into(), Dynamic::from(regs.pc as i64));
+            map.insert("pstate".into(), Dynamic::from(regs.pstate as i64));
+            return Ok(map);
+        });
+    });
+    let ctx = context.clone();
+    engine.register_fn("symbol", move |name: &str| -> ScriptResult<i64> {
+        return with_tracee(&ctx, |tracee| unsafe {
+            let functions = tracee.read_functions().map_err(|err| err.to_string())?;
+            return match functions.iter().find(|function| function.name == name) {
+                None => Err(format!("no function named \"{}\"", name).into()),
+                Some(function) => Ok(function.address as i64),
+            };
+        });
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("break_at", move |address: i64| {
+        ctx.borrow_mut()
+            .actions
+            .push(ScriptAction::Break(address as u64));
+    });
+    let ctx = context.clone();
+    engine.register_fn("delete_break", move |address: i64| {
+        ctx.borrow_mut()
+            .actions
+            .push(ScriptAction::DeleteBreak(address as u64));
+    });
+    let ctx = context.clone();
+    engine.register_fn("resume", move || {
+        ctx.borrow_mut().actions.push(ScriptAction::Resume);
+    });
+    let ctx = context.clone();
+    engine.register_fn("command", move |line: &str| {
+        ctx.borrow_mut()
+            .actions
+            .push(ScriptAction::Command(line.to_string()));
+    });
+}
+
+fn register_hook_functions(engine: &mut Engine, hooks: &Rc<RefCell<Hooks>>) {
+    let h = hooks.clone();
+    engine.register_fn("on_stop", move |hook: FnPtr| h.borrow_mut().stop.push(hook));
+    let h = hooks.clone();
+    engine.register_fn("on_syscall", move |hook: FnPtr| {
+        h.borrow_mut().syscall.push(hook)
+    });
+    let h = hooks.clone();
+    engine.register_fn("on_signal", move |hook: FnPtr| {
+        h.borrow_mut().signal.push(hook)
+    });
+    let h = hooks.clone();
+    engine.register_fn("on_exit", move |hook: FnPtr| h.borrow_mut().exit.push(hook));
+    let h = hooks.clone();
+    engine.register_fn("register_command", move |name: &str, command: FnPtr| {
+        let mut hooks = h.borrow_mut();
+        hooks
+            .commands
+            .retain(|(command_name, _)| command_name != name);
+        hooks.commands.push((name.to_string(), command));
+    });
+    let h = hooks.clone();
+    engine.register_fn("clear_hooks", move || {
+        let mut hooks = h.borrow_mut();
+        *hooks = Hooks {
+            commands: std::mem::take(&mut hooks.commands),
+            ..Hooks::default()
+        };
+    });
+}
+
+fn json_to_dynamic(json: &Json) -> Dynamic {
+    return match json {
+        Json::Null => Dynamic::UNIT,
+        Json::Bool(b) => Dynamic::from(*b),
+        Json::Number(n) if n.fract() == 0.0 => Dynamic::from(*n as i64),
+        Json::Number(n) => Dynamic::from(*n),
+        Json::String(s) => Dynamic::from(s.clone()),
+        Json::Array(values) => Dynamic::from(values.iter().map(json_to_dynamic).collect::<Array>()),
+        Json::Object(fields) => {
+            let mut map = Map::new();
+            for (key, value) in fields {
+                map.insert(key.as_str().into(), json_to_dynamic(value));
+            }
+            Dynamic::from(map)
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use rhai::Map;
+
+    use super::json_to_dynamic;
+    use crate::json::Json;
+
+    #[test]
+    fn json_to_dynamic_converts_objects_to_maps() {
+        let json =
+            Json::parse(r#"{"pid":10,"reason":"breakpoint","ok":true,"a":[1.5,null]}"#).unwrap();
+        let map = json_to_dynamic(&json).cast::<Map>();
+        assert_eq!(map["pid"].as_int(), Ok(10));
+        assert_eq!(map["reason"].clone().into_string().unwrap(), "breakpoint");
+        assert_eq!(map["ok"].as_bool(), Ok(true));
+        let array = map["a"].clone().into_array().unwrap();
+        assert_eq!(array[0].as_float(), Ok(1.5));
+        assert!(array[1].is_unit());
+    }
+}
@@ -1,58 +1,3255 @@
-use std::io::{stdin, stdout, BufRead, Write};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, stdin, stdout, BufRead, BufWriter, Write},
+    mem,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
 
-use crate::tracee::Tracee;
+use crate::{
+    analysis::{find_deadlocks, futex_op_name, inspect_futex_wait},
+    asm::{assemble, INSTRUCTION_LEN, MNEMONICS},
+    auxv::format_entry,
+    breakpoint::Breakpoint,
+    calltrace::CallTraceKind,
+    cleanup,
+    coredump::{write_core, CoreSnapshot, CoreTarget},
+    disasm::{disassemble, format_instruction, MAX_INSTRUCTION_LEN},
+    elf::{describe_address, load_bias, ElfFile},
+    expr::{self, parse_call, ExpressionContext, ExpressionError, Value},
+    hwdebug::SlotKind,
+    itrace::{changed_registers, format_step, TraceLimit},
+    json::Json,
+    launch::{parse_env_assignment, LaunchOptions},
+    minidump::write_minidump,
+    net::read_sockets,
+    perf::PerfCounterKind,
+    plugin::{load_plugin, Plugin},
+    procfs,
+    profile::{frame_names, parse_duration, Profile, SAMPLE_INTERVAL},
+    register::read_general_purpose_register,
+    remote::RemoteTarget,
+    replay::ReplayLog,
+    rpc::{read_address, RpcError, RpcRequest, RpcServer},
+    rsp::{from_hex, to_hex},
+    script::{ScriptAction, ScriptEngine},
+    signal::{format_siginfo, parse_signal, signal_name, SignalDispositions, Trap},
+    source::{parse_debuginfod_urls, SourceSettings},
+    syscall::{
+        decode::{format_open_flags, parse_errno, parse_return_value},
+        SyscallFilter, SyscallSummary,
+    },
+    target::Target,
+    tracee::{
+        CallArgument, FollowForkMode, SchedulerLocking, SyscallTraceMode, Tracee, TraceeStatus,
+    },
+    tui::{watch_resizes, Resize, Tui},
+    unwind::{unwind, unwind_thread},
+    variables::{parse_assignment, ConvenienceVariables},
+    web::WebServer,
+};
 
-pub unsafe fn run_session(tracee: &mut Tracee) {
-    let stdin = stdin();
+// How many instructions `disassemble` shows when not given a count.
+const DEFAULT_DISASSEMBLY_COUNT: usize = 10;
+
+// How long the strings that `info auxv` shows may be, e.g. AT_EXECFN.
+const MAX_AUXV_STRING_LEN: usize = 4096;
+
+// How many source lines `list` shows.
+const DEFAULT_LIST_COUNT: usize = 10;
+
+// How many instructions around the pc are shown whenever an inferior stops, until set otherwise.
+const DEFAULT_STOP_DISASSEMBLY_COUNT: usize = 5;
+
+// How often inferiors running in the background are checked on while waiting for a command.
+const BACKGROUND_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// What the session waits on: lines typed at the prompt, and requests of RPC clients.
+pub enum Input {
+    Line(io::Result<String>),
+    Rpc(RpcRequest),
+    Resize,
+}
+
+impl From<RpcRequest> for Input {
+    fn from(request: RpcRequest) -> Input {
+        return Input::Rpc(request);
+    }
+}
+
+impl From<Resize> for Input {
+    fn from(_: Resize) -> Input {
+        return Input::Resize;
+    }
+}
+
+struct Inferior {
+    id: usize,
+    // The inferior that forked this one, if any.
+    parent_id: Option<usize>,
+    // The command line of the process when it became an inferior.
+    cmdline: Vec<String>,
+    tracee: Tracee,
+    // Stopped copies of the process, which `restart` rolls it back to.
+    checkpoints: Vec<Checkpoint>,
+    next_checkpoint_id: usize,
+    // The recording that `replay` runs again, if any.
+    recording: Option<Recording>,
+    // How `run` starts the program again, unless the process was attached to.
+    launch_options: Option<LaunchOptions>,
+    // The breakpoint that `start` set at main, until the process stops at it.
+    temporary_breakpoint: Option<usize>,
+}
+
+struct Checkpoint {
+    id: usize,
+    // Where the process was stopped when the checkpoint was taken.
+    pc: u64,
+    tracee: Tracee,
+}
+
+struct Recording {
+    // A stopped copy of the process from where recording started, which replays are forked from.
+    checkpoint: Tracee,
+    // What was recorded, once recording stopped.
+    log: Option<ReplayLog>,
+}
+
+// An expression that `watch expr` checks for changes in value, for what hardware watchpoints cannot
+// watch, e.g. registers or values computed from several places in memory.
+struct ExpressionWatch {
+    id: usize,
+    expression: String,
+    // The value as of when it was last checked, or why it could not be evaluated then.
+    value: Result<Value, String>,
+}
+
+// How `continue until` and `watch expr` check their expressions.
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum ContinueUntilMode {
+    // The expression is checked whenever the program stops by itself, e.g. at breakpoints.
+    Resume,
+    // The expression is checked after every instruction of the selected thread.
+    Step,
+}
+
+impl ContinueUntilMode {
+    fn from_name(name: &str) -> Option<ContinueUntilMode> {
+        return match name {
+            "resume" => Some(ContinueUntilMode::Resume),
+            "step" => Some(ContinueUntilMode::Step),
+            _ => None,
+        };
+    }
+}
+
+pub struct Session {
+    inferiors: Vec<Inferior>,
+    selected_inferior_id: usize,
+    next_inferior_id: usize,
+    follow_fork_mode: FollowForkMode,
+    scheduler_locking: SchedulerLocking,
+    signal_dispositions: SignalDispositions,
+    pass_pending_signals: bool,
+    perf_counter_kinds: Vec<PerfCounterKind>,
+    trace_file_path: Option<PathBuf>,
+    // Whether inferiors record every system call to the trace file, for an audit trail.
+    auditing: bool,
+    // Where inferiors look for the source files that their line tables name.
+    source_settings: SourceSettings,
+    stop_disassembly_count: usize,
+    // The target of `target remote`, which commands go to instead of the selected inferior while
+    // connected.
+    remote: Option<RemoteTarget>,
+    // Where RPC clients send their requests, to be handled like typed commands.
+    inputs: Sender<Input>,
+    // The control socket, while listening. Inferiors queue their records for its subscribers.
+    rpc: Option<RpcServer>,
+    scripts: ScriptEngine,
+    plugins: Vec<Box<dyn Plugin>>,
+    // The full-screen UI, if the session runs in one.
+    tui: Option<Tui>,
+    // The web frontend, while serving. Its requests are handled like those of the control socket.
+    web: Option<WebServer>,
+    // The inferior whose terminal typed lines go to, while attached to it with `tty`.
+    tty_inferior_id: Option<usize>,
+    variables: ConvenienceVariables,
+    // The expressions that `display` shows at each stop, by id.
+    displays: Vec<(usize, String)>,
+    next_display_id: usize,
+    // The inferior and stop that the displays were last shown at.
+    last_display_stop: Option<(usize, u64)>,
+    continue_until_mode: ContinueUntilMode,
+    expression_watches: Vec<ExpressionWatch>,
+    next_expression_watch_id: usize,
+    watch_expr_mode: ContinueUntilMode,
+}
+
+pub unsafe fn run_session(tracee: Tracee) {
+    run_session_in(tracee, None, &[]);
+}
+
+// Runs the session in a full-screen UI, or as usual if the terminal cannot show one.
+pub unsafe fn run_tui_session(tracee: Tracee) {
+    match Tui::enter() {
+        Err(err) => {
+            println!("failed to start the TUI: {}", err);
+            run_session(tracee);
+        }
+        Ok(tui) => run_session_in(tracee, Some(tui), &[]),
+    }
+}
+
+// Runs the session while serving the web frontend at the address.
+pub unsafe fn run_web_session(tracee: Tracee, address: &str) {
+    run_session_in(tracee, None, &[format!("set web-server {}", address)]);
+}
+
+// Runs the session while recording an audit trail of the tracee to the path.
+pub unsafe fn run_audit_session(tracee: Tracee, path: &str) {
+    run_session_in(tracee, None, &[format!("set audit {}", path)]);
+}
+
+// Runs a session, after running the given commands as if they had been typed.
+unsafe fn run_session_in(tracee: Tracee, tui: Option<Tui>, commands: &[String]) {
+    cleanup::install();
+    let (input_sender, inputs) = mpsc::channel();
+    let mut session = Session::new(tracee, input_sender.clone());
+    if tui.is_some() {
+        if let Err(err) = watch_resizes(input_sender.clone()) {
+            println!("failed to watch for terminal resizes: {}", err);
+        }
+    }
+    session.tui = tui;
+    for command in commands {
+        session.handle_command(command);
+    }
+    session.draw_tui();
     let mut stdout = stdout();
 
-    write!(stdout, "pbreak> ").unwrap();
-    stdout.flush().unwrap();
+    // Lines are read on a separate thread, so that inferiors running in the background can be
+    // waited on while the prompt is shown. Tracees must be waited on by the thread that traces
+    // them, so that stays on this one.
+    thread::spawn(move || {
+        cleanup::block_fatal_signals();
+        for line_result in stdin().lock().lines() {
+            if input_sender.send(Input::Line(line_result)).is_err() {
+                return;
+            }
+        }
+        // The session ends with stdin, even while RPC clients are connected.
+        let _ = input_sender.send(Input::Line(Err(io::ErrorKind::UnexpectedEof.into())));
+    });
+
+    write!(stdout, "pbreak> ").unwrap();
+    stdout.flush().unwrap();
+
+    loop {
+        let input = match session.has_running_inferiors() {
+            false => match inputs.recv() {
+                Err(_) => return,
+                Ok(input) => input,
+            },
+            true => match inputs.recv_timeout(BACKGROUND_POLL_INTERVAL) {
+                Err(RecvTimeoutError::Disconnected) => return,
+                Err(RecvTimeoutError::Timeout) => {
+                    if session.poll_inferiors() {
+                        session.delete_hit_temporary_breakpoints();
+                        session.dispatch_records();
+                        session.show_displays_at_stop();
+                        session.draw_tui();
+                        write!(stdout, "{}", session.prompt()).unwrap();
+                        stdout.flush().unwrap();
+                    }
+                    continue;
+                }
+                Ok(input) => input,
+            },
+        };
+
+        match input {
+            Input::Line(Err(err)) if err.kind() == io::ErrorKind::UnexpectedEof => return,
+            Input::Line(Err(err)) => {
+                println!("failed to read line from stdin: {}", err);
+            }
+            Input::Line(Ok(line)) if session.tty_inferior_id.is_some() => {
+                session.handle_tty_line(&line)
+            }
+            Input::Line(Ok(line)) => session.handle_command(&line),
+            Input::Rpc(request) => {
+                session.handle_rpc(&request);
+                session.dispatch_records();
+                continue;
+            }
+            Input::Resize => {
+                // The prompt is cleared along with the screen.
+                if session.resize_tui() {
+                    write!(stdout, "{}", session.prompt()).unwrap();
+                    stdout.flush().unwrap();
+                }
+                continue;
+            }
+        }
+        session.delete_hit_temporary_breakpoints();
+        session.dispatch_records();
+        session.show_displays_at_stop();
+        session.draw_tui();
+
+        write!(stdout, "{}", session.prompt()).unwrap();
+        stdout.flush().unwrap();
+    }
+}
+
+impl Session {
+    // Constructs a `Session` whose first inferior is the given tracee.
+    pub fn new(tracee: Tracee, inputs: Sender<Input>) -> Session {
+        let mut session = Session {
+            inferiors: vec![],
+            selected_inferior_id: 1,
+            next_inferior_id: 1,
+            follow_fork_mode: FollowForkMode::Parent,
+            scheduler_locking: SchedulerLocking::Off,
+            signal_dispositions: SignalDispositions::new(),
+            pass_pending_signals: true,
+            perf_counter_kinds: vec![],
+            trace_file_path: None,
+            auditing: false,
+            source_settings: SourceSettings::from_env(),
+            stop_disassembly_count: DEFAULT_STOP_DISASSEMBLY_COUNT,
+            remote: None,
+            inputs,
+            rpc: None,
+            scripts: ScriptEngine::new(),
+            plugins: vec![],
+            tui: None,
+            web: None,
+            tty_inferior_id: None,
+            variables: ConvenienceVariables::default(),
+            displays: vec![],
+            next_display_id: 1,
+            last_display_stop: None,
+            continue_until_mode: ContinueUntilMode::Resume,
+            expression_watches: vec![],
+            next_expression_watch_id: 1,
+            watch_expr_mode: ContinueUntilMode::Resume,
+        };
+        session.add_inferior(tracee, None);
+        return session;
+    }
+
+    fn add_inferior(&mut self, mut tracee: Tracee, parent_id: Option<usize>) -> usize {
+        let id = self.next_inferior_id;
+        self.next_inferior_id += 1;
+
+        self.configure_tracee(&mut tracee);
+        let cmdline = procfs::read_cmdline(tracee.pid()).unwrap_or_default();
+        let launch_options = tracee.launch_options().cloned();
+        self.inferiors.push(Inferior {
+            id,
+            parent_id,
+            cmdline,
+            tracee,
+            checkpoints: vec![],
+            next_checkpoint_id: 1,
+            recording: None,
+            launch_options,
+            temporary_breakpoint: None,
+        });
+        return id;
+    }
+
+    // Applies the session's settings to a tracee that is new to it.
+    fn configure_tracee(&self, tracee: &mut Tracee) {
+        tracee.set_follow_fork_mode(self.follow_fork_mode);
+        tracee.set_scheduler_locking(self.scheduler_locking);
+        tracee.set_signal_dispositions(self.signal_dispositions.clone());
+        tracee.set_pass_pending_signals(self.pass_pending_signals);
+        tracee.set_stop_disassembly_count(self.stop_disassembly_count);
+        if !self.perf_counter_kinds.is_empty() {
+            if let Err(err) = unsafe { tracee.set_perf_counters(&self.perf_counter_kinds) } {
+                println!("{}", err);
+            }
+        }
+        if let Err(err) = tracee.set_trace_file(self.trace_file_path.as_deref()) {
+            println!("failed to open trace file: {}", err);
+        }
+        tracee.set_auditing(self.auditing);
+        tracee.set_source_settings(self.source_settings.clone());
+        tracee.set_record_queue(self.needs_records());
+    }
+
+    fn update_source_settings(&mut self) {
+        for inferior in self.inferiors.iter_mut() {
+            inferior
+                .tracee
+                .set_source_settings(self.source_settings.clone());
+        }
+    }
+
+    // Starts a trace file at the path, which every inferior then appends to. Returns false if the
+    // file cannot be created.
+    fn create_trace_file(&mut self, path_str: &str) -> bool {
+        let path = PathBuf::from(path_str);
+        if let Err(err) = File::create(&path) {
+            println!("failed to create trace file: {}", err);
+            return false;
+        }
+
+        self.trace_file_path = Some(path);
+        for inferior in self.inferiors.iter_mut() {
+            if let Err(err) = inferior
+                .tracee
+                .set_trace_file(self.trace_file_path.as_deref())
+            {
+                println!("failed to open trace file: {}", err);
+            }
+        }
+        return true;
+    }
+
+    // Redraws the panes of the TUI, if any, for the selected inferior.
+    unsafe fn draw_tui(&mut self) {
+        let i = self.selected_inferior_index();
+        if let Some(tui) = &mut self.tui {
+            tui.draw(&self.inferiors[i].tracee);
+        }
+    }
+
+    // Lays the TUI out again for the terminal's new size, and redraws it. Returns whether the screen
+    // was cleared for it.
+    unsafe fn resize_tui(&mut self) -> bool {
+        let Some(tui) = &mut self.tui else {
+            return false;
+        };
+        if !tui.resize() {
+            return false;
+        }
+        self.draw_tui();
+        return true;
+    }
+
+    // The prompt for commands, which is left out while typed lines go to an inferior's terminal.
+    fn prompt(&self) -> &'static str {
+        return match self.tty_inferior_id {
+            None => "pbreak> ",
+            Some(_) => "",
+        };
+    }
+
+    fn has_running_inferiors(&self) -> bool {
+        return self
+            .inferiors
+            .iter()
+            .any(|inferior| inferior.tracee.status() == TraceeStatus::Running);
+    }
+
+    // Reports the inferiors running in the background that have stopped or exited since the last
+    // check. Returns true if any have.
+    unsafe fn poll_inferiors(&mut self) -> bool {
+        let mut has_changed = false;
+        for i in 0..self.inferiors.len() {
+            let inferior = &mut self.inferiors[i];
+            if inferior.tracee.status() != TraceeStatus::Running || !inferior.tracee.poll_signal() {
+                continue;
+            }
+
+            has_changed = true;
+            // Typed lines are commands again once the program stops.
+            if self.tty_inferior_id == Some(inferior.id)
+                && inferior.tracee.status() != TraceeStatus::Running
+            {
+                self.detach_tty();
+            }
+            let inferior = &mut self.inferiors[i];
+            let parent_id = inferior.id;
+            for tracee in inferior.tracee.take_forked_tracees() {
+                let pid = tracee.pid();
+                let id = self.add_inferior(tracee, Some(parent_id));
+                println!("[New inferior {} (pid {}) from fork]", id, pid);
+            }
+        }
+        return has_changed;
+    }
+
+    // Prints the inferiors forked by the given parent (or the root inferiors), depth first.
+    fn print_process_tree(&self, parent_id: Option<usize>, depth: usize) {
+        let indent = "    ".repeat(depth);
+        for inferior in &self.inferiors {
+            if inferior.parent_id != parent_id {
+                continue;
+            }
+
+            let marker = match inferior.id == self.selected_inferior_id {
+                true => "*",
+                false => " ",
+            };
+            println!(
+                "{}{} {} pid {} {:?} {}",
+                indent,
+                marker,
+                inferior.id,
+                inferior.tracee.pid(),
+                inferior.tracee.status(),
+                inferior.cmdline.join(" "),
+            );
+            for thread in inferior.tracee.threads() {
+                println!(
+                    "{}      thread {} \"{}\" {:?}",
+                    indent,
+                    thread.tid,
+                    procfs::read_comm(thread.tid).unwrap_or_default(),
+                    thread.status,
+                );
+            }
+
+            self.print_process_tree(Some(inferior.id), depth + 1);
+        }
+    }
+
+    // Rolls the selected inferior back to a checkpoint. The checkpoint is forked once more rather
+    // than run itself, so that it can be restarted again, and the current process is killed.
+    unsafe fn restart_checkpoint(&mut self, id: usize) {
+        let i = self.selected_inferior_index();
+        let Some(checkpoint) = self.inferiors[i]
+            .checkpoints
+            .iter_mut()
+            .find(|checkpoint| checkpoint.id == id)
+        else {
+            println!("unknown checkpoint id: {}", id);
+            return;
+        };
+        let pc = checkpoint.pc;
+        let tracee = match checkpoint.tracee.checkpoint() {
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+            Ok(tracee) => tracee,
+        };
+
+        println!(
+            "Switching to process ({}) at checkpoint {}, pc {:#x}",
+            tracee.pid(),
+            id,
+            pc
+        );
+        self.replace_tracee(i, tracee);
+    }
+
+    // Replays the selected inferior's recording in a fresh copy of the process from where
+    // recording started, which replaces the current process.
+    unsafe fn replay_recording(&mut self) {
+        let i = self.selected_inferior_index();
+        let Some(Recording {
+            checkpoint,
+            log: Some(log),
+        }) = &mut self.inferiors[i].recording
+        else {
+            println!("There is no recording to replay.");
+            return;
+        };
+        let log = log.clone();
+        let mut tracee = match checkpoint.checkpoint() {
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+            Ok(tracee) => tracee,
+        };
+
+        println!(
+            "Replaying {} events in process ({})",
+            log.events.len(),
+            tracee.pid()
+        );
+        tracee.start_replaying(log);
+        self.replace_tracee(i, tracee);
+    }
+
+    // The launch options of the selected inferior, which `run` uses next. Inferiors that were
+    // attached to have none.
+    fn selected_launch_options(&mut self) -> Option<&mut LaunchOptions> {
+        let i = self.selected_inferior_index();
+        let inferior = &mut self.inferiors[i];
+        if inferior.launch_options.is_none() {
+            println!(
+                "inferior {} was not launched by pbreak, so it cannot be run again",
+                inferior.id
+            );
+        }
+        return inferior.launch_options.as_mut();
+    }
+
+    // Starts the selected inferior's program again, killing the current process, and continues
+    // it.
+    unsafe fn run_again(&mut self) {
+        let Some(launch_options) = self.selected_launch_options().cloned() else {
+            return;
+        };
+        let i = self.selected_inferior_index();
+        let tracee = Tracee::launch(&launch_options);
+        println!(
+            "Starting program: {} (pid {})",
+            tracee.executable().display(),
+            tracee.pid()
+        );
+        self.replace_tracee(i, tracee);
+        self.handle_command("continue");
+    }
+
+    // Starts the selected inferior's program again like `run`, but with a temporary breakpoint at
+    // main, so that it stops at the top of main. The breakpoint stays until the process stops at
+    // it, even if it stops somewhere else first.
+    unsafe fn start_at_main(&mut self) {
+        let Some(launch_options) = self.selected_launch_options().cloned() else {
+            return;
+        };
+        let i = self.selected_inferior_index();
+        let tracee = Tracee::launch(&launch_options);
+        println!(
+            "Starting program: {} (pid {})",
+            tracee.executable().display(),
+            tracee.pid()
+        );
+        self.replace_tracee(i, tracee);
+
+        // The executable is mapped from the first instruction on, so main is found at its runtime
+        // address already.
+        let tracee = &mut self.inferiors[i].tracee;
+        let insert_result = tracee.find_function("main").and_then(|main| {
+            return tracee
+                .insert_breakpoint(main.address)
+                .map(|id| (main.address, id))
+                .map_err(|err| err.to_string());
+        });
+        let (address, id) = match insert_result {
+            Err(err) => {
+                println!("failed to set a temporary breakpoint at main: {}", err);
+                return;
+            }
+            Ok(breakpoint) => breakpoint,
+        };
+        println!("Temporary breakpoint {} at {:#x} (main)", id, address);
+        self.inferiors[i].temporary_breakpoint = Some(id);
+        self.handle_command("continue");
+        if self.delete_hit_temporary_breakpoint(i) {
+            println!("Stopped at main");
+        }
+    }
+
+    // Deletes the inferior's temporary breakpoint if the process is stopped at it. Returns whether
+    // it was.
+    unsafe fn delete_hit_temporary_breakpoint(&mut self, i: usize) -> bool {
+        let inferior = &mut self.inferiors[i];
+        let Some(id) = inferior.temporary_breakpoint else {
+            return false;
+        };
+        let tracee = &mut inferior.tracee;
+        if tracee.status() != TraceeStatus::Stopped {
+            return false;
+        }
+        // One that was deleted by hand is forgotten, and one that is pending is kept.
+        let Some(address) = tracee.breakpoints().get(id).map(Breakpoint::address) else {
+            let is_pending = tracee
+                .breakpoints()
+                .pending()
+                .iter()
+                .any(|pending| pending.id == id);
+            if !is_pending {
+                inferior.temporary_breakpoint = None;
+            }
+            return false;
+        };
+        let is_hit = tracee.trap() == Some(Trap::Breakpoint { address })
+            && tracee.read_general_purpose_registers().pc == address;
+        if !is_hit {
+            return false;
+        }
+        if let Err(err) = tracee.delete_breakpoint(id) {
+            println!("{}", err);
+        }
+        inferior.temporary_breakpoint = None;
+        return true;
+    }
+
+    // Deletes the temporary breakpoints that the inferiors have stopped at.
+    unsafe fn delete_hit_temporary_breakpoints(&mut self) {
+        for i in 0..self.inferiors.len() {
+            self.delete_hit_temporary_breakpoint(i);
+        }
+    }
+
+    // Makes a copy of an inferior's process the inferior's tracee, killing the current process.
+    unsafe fn replace_tracee(&mut self, i: usize, mut tracee: Tracee) {
+        self.configure_tracee(&mut tracee);
+        self.inferiors[i].temporary_breakpoint = None;
+        let mut former_tracee = mem::replace(&mut self.inferiors[i].tracee, tracee);
+        if former_tracee.exit_status().is_none() {
+            former_tracee.kill();
+        }
+    }
+
+    // Shows what the selected inferior wrote to its terminal, and then shows its output as it
+    // arrives and passes typed lines to it, until "~." is typed.
+    unsafe fn attach_tty(&mut self) {
+        let i = self.selected_inferior_index();
+        let inferior = &self.inferiors[i];
+        let Some(pty) = inferior.tracee.pty() else {
+            println!(
+                "inferior {} has no terminal of its own; launch it with --pty",
+                inferior.id
+            );
+            return;
+        };
+        // The debugger's terminal may have been resized since the program last saw it.
+        pty.sync_size();
+        println!(
+            "[Attached to terminal {:?} of process ({}); type ~. to detach]",
+            pty.slave_path(),
+            inferior.tracee.pid()
+        );
+        if inferior.tracee.status() != TraceeStatus::Running {
+            println!("[Process is stopped; its input is read once it continues]");
+        }
+        let mut stdout = stdout();
+        stdout.write_all(&pty.show_live(true)).unwrap();
+        stdout.flush().unwrap();
+        self.tty_inferior_id = Some(inferior.id);
+    }
+
+    // Keeps the output of the attached terminal, if any, until it is attached to again.
+    fn detach_tty(&mut self) {
+        let Some(id) = self.tty_inferior_id.take() else {
+            return;
+        };
+        if let Some(inferior) = self.inferiors.iter().find(|inferior| inferior.id == id) {
+            if let Some(pty) = inferior.tracee.pty() {
+                pty.show_live(false);
+            }
+        }
+        println!("[Detached from terminal]");
+    }
+
+    // Passes a typed line to the attached terminal, unless it is "~.", which detaches.
+    fn handle_tty_line(&mut self, line: &str) {
+        let pty = self.inferiors.iter().find_map(|inferior| {
+            return match Some(inferior.id) == self.tty_inferior_id {
+                true => inferior.tracee.pty(),
+                false => None,
+            };
+        });
+        let Some(pty) = pty.filter(|_| line != "~.") else {
+            self.detach_tty();
+            return;
+        };
+        if let Err(err) = pty.write_input(format!("{}\n", line).as_bytes()) {
+            println!("failed to write to terminal: {}", err);
+            self.detach_tty();
+        }
+    }
+
+    // Assigns the value of an expression to a convenience variable, e.g. "$count = $count + 1".
+    unsafe fn assign_variable(&mut self, assignment: &str) {
+        let Some((name, expression)) = parse_assignment(assignment) else {
+            println!("invalid assignment: \"{}\"", assignment);
+            return;
+        };
+        if read_general_purpose_register(&mem::zeroed(), name).is_some() {
+            println!("cannot assign to register ${}", name);
+            return;
+        }
+        match self.evaluate(expression) {
+            Err(err) => println!("{}", err),
+            Ok(value) => self.variables.set(name, value),
+        }
+    }
+
+    // Evaluates an expression, with the registers and memory of the selected thread while it is
+    // stopped. Symbols are looked up in the selected inferior, unless connected to a remote target.
+    unsafe fn evaluate(&self, expression: &str) -> Result<Value, ExpressionError> {
+        let tracee = &self.inferiors[self.selected_inferior_index()].tracee;
+        let (target, tracee): (&dyn Target, Option<&Tracee>) = match self.remote.as_ref() {
+            Some(remote) => (remote, None),
+            None => (tracee, Some(tracee)),
+        };
+        let context = EvaluationContext {
+            target,
+            tracee,
+            regs: match target.status() {
+                TraceeStatus::Stopped => Some(target.read_general_purpose_registers()),
+                _ => None,
+            },
+            variables: &self.variables,
+        };
+        return expr::evaluate(expression, &context);
+    }
+
+    unsafe fn print_display(&self, id: usize, expression: &str) {
+        match self.evaluate(expression) {
+            Err(err) => println!("{}: {} = <{}>", id, expression, err),
+            Ok(value) => println!("{}: {} = {}", id, expression, value),
+        }
+    }
+
+    // Shows the displays once at each stop of the selected inferior, after the expression watches
+    // whose values changed.
+    unsafe fn show_displays_at_stop(&mut self) {
+        let tracee = &self.inferiors[self.selected_inferior_index()].tracee;
+        if self.remote.is_some() || tracee.status() != TraceeStatus::Stopped {
+            return;
+        }
+        let stop = (self.selected_inferior_id, tracee.stop_count());
+        if self.last_display_stop == Some(stop) {
+            return;
+        }
+        self.last_display_stop = Some(stop);
+        self.check_expression_watches();
+        for (id, expression) in &self.displays {
+            self.print_display(*id, expression);
+        }
+    }
+
+    // Evaluates the expression watches again, and reports those whose values changed since they
+    // were last checked. Returns whether any did.
+    unsafe fn check_expression_watches(&mut self) -> bool {
+        let mut changed = false;
+        for i in 0..self.expression_watches.len() {
+            let value = self
+                .evaluate(&self.expression_watches[i].expression)
+                .map_err(|err| err.to_string());
+            let watch = &mut self.expression_watches[i];
+            if value == watch.value {
+                continue;
+            }
+            println!("Expression watch {}: {}", watch.id, watch.expression);
+            println!("Old value = {}", format_watched_value(&watch.value));
+            println!("New value = {}", format_watched_value(&value));
+            watch.value = value;
+            changed = true;
+        }
+        return changed;
+    }
+
+    // Whether the selected inferior is stopped where the session can step it itself. Says why not
+    // otherwise.
+    fn can_step_locally(&self, command: &str) -> bool {
+        let tracee = &self.inferiors[self.selected_inferior_index()].tracee;
+        match tracee.status() {
+            TraceeStatus::Running => {
+                println!(
+                    "process ({}) is running in the background; use `interrupt` to stop it",
+                    tracee.pid()
+                );
+                return false;
+            }
+            TraceeStatus::Exited | TraceeStatus::Terminated => {
+                println!("process ({}) is no longer alive", tracee.pid());
+                return false;
+            }
+            TraceeStatus::Stopped => {}
+        }
+        if self.remote.is_some() {
+            println!("{} is not supported on remote targets", command);
+            return false;
+        }
+        return true;
+    }
+
+    // Resumes or single-steps the selected inferior, as set by continue-until-mode, until the
+    // expression is true where it stops. Any stop other than a single-step is the program's own,
+    // e.g. at a breakpoint or with a signal, which ends it too and is reported as usual, along with
+    // whether the expression holds there.
+    unsafe fn continue_until(&mut self, expression: &str) {
+        let i = self.selected_inferior_index();
+        if !self.can_step_locally("continue until") {
+            return;
+        }
+        // A malformed expression is caught before the program is set off.
+        if let Err(err) = self.evaluate(expression) {
+            println!("{}", err);
+            return;
+        }
+
+        let (is_single_step, result) = loop {
+            let tracee = &mut self.inferiors[i].tracee;
+            // Stops are reported by the tracee, except for single-steps.
+            match self.continue_until_mode {
+                ContinueUntilMode::Resume => {
+                    tracee.resume();
+                    tracee.wait_on_signal();
+                }
+                ContinueUntilMode::Step => {
+                    tracee.step_instruction();
+                }
+            }
+            if tracee.status() != TraceeStatus::Stopped {
+                // How the process ended has been reported.
+                return;
+            }
+            let is_single_step = tracee.trap() == Some(Trap::SingleStep);
+            match (is_single_step, self.evaluate(expression)) {
+                (true, Ok(value)) if !value.is_true() => {}
+                (is_single_step, result) => break (is_single_step, result),
+            }
+        };
+        let tracee = &self.inferiors[i].tracee;
+        match (is_single_step, result) {
+            (_, Err(err)) => println!("{}", err),
+            (true, Ok(value)) => {
+                let pc = tracee.read_general_purpose_registers().pc;
+                println!(
+                    "Process ({}) stopped at {:#x}, where {} is {}",
+                    tracee.pid(),
+                    pc,
+                    expression,
+                    value
+                );
+                tracee.print_stop_disassembly();
+            }
+            (false, Ok(value)) => println!("{} is {} here", expression, value),
+        }
+    }
+
+    // Single-steps the selected inferior until the value of an expression watch changes, for
+    // watch-expr-mode step. It also stops at enabled breakpoints, as it would have if resumed, and
+    // wherever the program stops by itself, e.g. with a signal or at a hardware breakpoint.
+    unsafe fn continue_watching(&mut self) {
+        let i = self.selected_inferior_index();
+        if !self.can_step_locally("continue") {
+            return;
+        }
+
+        loop {
+            // Stops are reported by the tracee, except for single-steps.
+            let Some(pc) = self.inferiors[i].tracee.step_instruction() else {
+                // How the process ended has been reported.
+                return;
+            };
+            let tracee = &self.inferiors[i].tracee;
+            if tracee.trap() != Some(Trap::SingleStep) {
+                self.check_expression_watches();
+                self.inferiors[i].tracee.print_stop_disassembly();
+                return;
+            }
+            if self.check_expression_watches() {
+                break;
+            }
+            let tracee = &self.inferiors[i].tracee;
+            if tracee
+                .breakpoints()
+                .find(pc)
+                .is_some_and(|breakpoint| breakpoint.is_enabled())
+            {
+                break;
+            }
+        }
+        let tracee = &self.inferiors[i].tracee;
+        let pc = tracee.read_general_purpose_registers().pc;
+        println!("Process ({}) stopped at {:#x}", tracee.pid(), pc);
+        tracee.print_stop_disassembly();
+    }
+
+    // Calls a function of the selected inferior, e.g. "strlen((char *)$x0)", and prints what it
+    // returns. What is called, and each argument, are expressions.
+    unsafe fn call_function(&mut self, call: &str) {
+        let Some((callee, expressions)) = parse_call(call) else {
+            println!("invalid call: \"{}\"", call);
+            return;
+        };
+        if self.remote.is_some() {
+            println!("call is not supported on remote targets");
+            return;
+        }
+        let address = match self.evaluate(callee) {
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+            Ok(value) => value.to_bits(),
+        };
+        let mut args = vec![];
+        for expression in expressions {
+            match self.evaluate(expression) {
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+                Ok(Value::Float(value)) => args.push(CallArgument::Float(value)),
+                Ok(value) => args.push(CallArgument::Integer(value.to_bits())),
+            }
+        }
+        match self.selected_tracee().call_function(address, &args) {
+            Err(err) => println!("{}", err),
+            Ok(value) => println!("{} returned {}", callee, Value::UInt(value)),
+        }
+    }
+
+    // Returns from the function that the selected thread is in without running the rest of it,
+    // with the value of the expression, if any, as what it returns.
+    unsafe fn return_from_function(&mut self, expression: &str) {
+        if self.remote.is_some() {
+            println!("return is not supported on remote targets");
+            return;
+        }
+        // The value is evaluated in the frame that returns.
+        let value = match expression.is_empty() {
+            true => None,
+            false => match self.evaluate(expression) {
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+                Ok(value) => Some(value),
+            },
+        };
+        let tracee = self.selected_tracee();
+        let pc = match tracee.pop_frame() {
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+            Ok(pc) => pc,
+        };
+        match value {
+            None => {}
+            Some(Value::Float(value)) => {
+                let mut fp_regs = tracee.read_floating_point_registers();
+                fp_regs.vregs[0] = value.to_bits() as u128;
+                tracee.write_floating_point_registers(&mut fp_regs);
+            }
+            Some(value) => {
+                let mut regs = tracee.read_general_purpose_registers();
+                regs.regs[0] = value.to_bits();
+                tracee.write_general_purpose_registers(&mut regs);
+            }
+        }
+
+        print_moved_pc(tracee, "Returned", pc);
+    }
+
+    fn selected_inferior_index(&self) -> usize {
+        let selected_inferior_id = self.selected_inferior_id;
+        return match self
+            .inferiors
+            .iter()
+            .position(|inferior| inferior.id == selected_inferior_id)
+        {
+            None => unreachable!("selected inferior ({}) should exist", selected_inferior_id),
+            Some(i) => i,
+        };
+    }
+
+    fn selected_tracee(&mut self) -> &mut Tracee {
+        let selected_inferior_id = self.selected_inferior_id;
+        return match self
+            .inferiors
+            .iter_mut()
+            .find(|inferior| inferior.id == selected_inferior_id)
+        {
+            None => unreachable!("selected inferior ({}) should exist", selected_inferior_id),
+            Some(inferior) => &mut inferior.tracee,
+        };
+    }
+
+    pub unsafe fn handle_command(&mut self, line: &str) {
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+        match words.as_slice() {
+            ["inferior", "list"] => {
+                for inferior in &self.inferiors {
+                    let marker = match inferior.id == self.selected_inferior_id {
+                        true => "*",
+                        false => " ",
+                    };
+                    println!(
+                        "{} {} pid {} {:?} {}",
+                        marker,
+                        inferior.id,
+                        inferior.tracee.pid(),
+                        inferior.tracee.status(),
+                        inferior.tracee.executable().display(),
+                    );
+                }
+            }
+            ["info", "processes"] => {
+                self.print_process_tree(None, 0);
+            }
+            ["checkpoint"] => {
+                let i = self.selected_inferior_index();
+                let inferior = &mut self.inferiors[i];
+                let pc = inferior.tracee.read_general_purpose_registers().pc;
+                match inferior.tracee.checkpoint() {
+                    Err(err) => println!("{}", err),
+                    Ok(tracee) => {
+                        let id = inferior.next_checkpoint_id;
+                        inferior.next_checkpoint_id += 1;
+                        println!("Checkpoint {} at {:#x} (pid {})", id, pc, tracee.pid());
+                        inferior.checkpoints.push(Checkpoint { id, pc, tracee });
+                    }
+                }
+            }
+            ["info", "checkpoints"] => {
+                let i = self.selected_inferior_index();
+                for checkpoint in &self.inferiors[i].checkpoints {
+                    println!(
+                        "  {} pid {} at {:#x}",
+                        checkpoint.id,
+                        checkpoint.tracee.pid(),
+                        checkpoint.pc
+                    );
+                }
+            }
+            ["record"] | ["record", "start"] => {
+                let i = self.selected_inferior_index();
+                let inferior = &mut self.inferiors[i];
+                let pc = inferior.tracee.read_general_purpose_registers().pc;
+                match inferior.tracee.checkpoint() {
+                    Err(err) => println!("{}", err),
+                    Ok(checkpoint) => {
+                        inferior.tracee.start_recording();
+                        inferior.recording = Some(Recording {
+                            checkpoint,
+                            log: None,
+                        });
+                        println!("Recording from {:#x}", pc);
+                    }
+                }
+            }
+            ["record", "stop"] => {
+                let i = self.selected_inferior_index();
+                let inferior = &mut self.inferiors[i];
+                match (inferior.tracee.stop_recording(), &mut inferior.recording) {
+                    (Some(log), Some(recording)) => {
+                        println!("Recorded {} events", log.events.len());
+                        recording.log = Some(log);
+                    }
+                    _ => println!("The process is not being recorded."),
+                }
+            }
+            ["replay"] => {
+                self.replay_recording();
+            }
+            ["replay", "stop"] => {
+                self.selected_tracee().stop_replay();
+            }
+            ["info", "record"] => {
+                let i = self.selected_inferior_index();
+                let inferior = &self.inferiors[i];
+                match (inferior.tracee.replay_status(), &inferior.recording) {
+                    (Some(status), _) => {
+                        println!("Process ({}) is {}", inferior.tracee.pid(), status)
+                    }
+                    (None, Some(Recording { log: Some(log), .. })) => {
+                        println!(
+                            "Recorded {} events, which `replay` replays",
+                            log.events.len()
+                        )
+                    }
+                    (None, _) => println!("No recording."),
+                }
+            }
+            ["restart", id_str] => {
+                let Ok(id) = id_str.parse::<usize>() else {
+                    println!("invalid checkpoint id: \"{}\"", id_str);
+                    return;
+                };
+                self.restart_checkpoint(id);
+            }
+            ["delete", "checkpoint", id_str] => {
+                let i = self.selected_inferior_index();
+                let checkpoints = &mut self.inferiors[i].checkpoints;
+                match id_str.parse::<usize>().ok().and_then(|id| {
+                    checkpoints
+                        .iter()
+                        .position(|checkpoint| checkpoint.id == id)
+                }) {
+                    None => println!("unknown checkpoint id: \"{}\"", id_str),
+                    // Dropping the tracee kills the copy.
+                    Some(index) => drop(checkpoints.remove(index)),
+                }
+            }
+            ["run"] => {
+                self.run_again();
+            }
+            ["start"] => {
+                self.start_at_main();
+            }
+            ["tty"] => {
+                self.attach_tty();
+            }
+            ["set", "env", assignment] => {
+                let Some((name, value)) = parse_env_assignment(assignment) else {
+                    println!("invalid assignment: \"{}\"; use NAME=value", assignment);
+                    return;
+                };
+                if let Some(launch_options) = self.selected_launch_options() {
+                    launch_options.set_env(name, Some(value));
+                }
+            }
+            ["unset", "env", name] => {
+                if let Some(launch_options) = self.selected_launch_options() {
+                    launch_options.set_env(name, None);
+                }
+            }
+            ["inferior", id_str] => match id_str.parse::<usize>() {
+                Err(_) => println!("invalid inferior id: \"{}\"", id_str),
+                Ok(id) => {
+                    if self.inferiors.iter().any(|inferior| inferior.id == id) {
+                        self.selected_inferior_id = id;
+                    } else {
+                        println!("unknown inferior id: {}", id);
+                    }
+                }
+            },
+            ["attach", pid_str] => match pid_str.parse::<libc::pid_t>() {
+                Err(_) => println!("invalid pid: \"{}\"", pid_str),
+                Ok(pid) => {
+                    let id = self.add_inferior(Tracee::from_pid(pid), None);
+                    self.selected_inferior_id = id;
+                    println!("[Inferior {} (pid {}) attached]", id, pid);
+                }
+            },
+            ["set", "follow-fork-mode", mode_name] => match FollowForkMode::from_name(mode_name) {
+                None => println!("invalid value for follow-fork-mode: \"{}\"", mode_name),
+                Some(mode) => {
+                    self.follow_fork_mode = mode;
+                    for inferior in self.inferiors.iter_mut() {
+                        inferior.tracee.set_follow_fork_mode(mode);
+                    }
+                }
+            },
+            ["set", "scheduler-locking", mode_name] => {
+                match SchedulerLocking::from_name(mode_name) {
+                    None => println!("invalid value for scheduler-locking: \"{}\"", mode_name),
+                    Some(mode) => {
+                        self.scheduler_locking = mode;
+                        for inferior in self.inferiors.iter_mut() {
+                            inferior.tracee.set_scheduler_locking(mode);
+                        }
+                    }
+                }
+            }
+            ["set", "pass-pending-signals", value] => {
+                let enabled = match *value {
+                    "on" => true,
+                    "off" => false,
+                    _ => {
+                        println!("invalid value for pass-pending-signals: \"{}\"", value);
+                        return;
+                    }
+                };
+
+                self.pass_pending_signals = enabled;
+                for inferior in self.inferiors.iter_mut() {
+                    inferior.tracee.set_pass_pending_signals(enabled);
+                }
+            }
+            ["set", "perf-counters", names] => {
+                let kinds = match *names {
+                    "off" => vec![],
+                    names => match PerfCounterKind::from_names(names) {
+                        None => {
+                            println!("invalid value for perf-counters: \"{}\"", names);
+                            return;
+                        }
+                        Some(kinds) => kinds,
+                    },
+                };
+
+                self.perf_counter_kinds = kinds;
+                for inferior in self.inferiors.iter_mut() {
+                    if let Err(err) = inferior.tracee.set_perf_counters(&self.perf_counter_kinds) {
+                        println!("{}", err);
+                    }
+                }
+            }
+            ["set", "disassemble-at-stop", value] => {
+                let count = match *value {
+                    "off" => 0,
+                    value => match value.parse::<usize>() {
+                        Err(_) => {
+                            println!("invalid value for disassemble-at-stop: \"{}\"", value);
+                            return;
+                        }
+                        Ok(count) => count,
+                    },
+                };
+
+                self.stop_disassembly_count = count;
+                for inferior in self.inferiors.iter_mut() {
+                    inferior.tracee.set_stop_disassembly_count(count);
+                }
+            }
+            ["set", "trace-file", "off"] => {
+                self.trace_file_path = None;
+                for inferior in self.inferiors.iter_mut() {
+                    inferior.tracee.set_trace_file(None).unwrap();
+                }
+            }
+            ["set", "trace-file", path_str] => {
+                self.create_trace_file(path_str);
+            }
+            ["set", "substitute-path", "off"] => {
+                self.source_settings.substitutions.clear();
+                self.update_source_settings();
+            }
+            ["set", "substitute-path", from, to] => {
+                self.source_settings
+                    .substitutions
+                    .push((from.to_string(), to.to_string()));
+                self.update_source_settings();
+            }
+            ["set", "debuginfod", "off"] => {
+                self.source_settings.debuginfod_urls.clear();
+                self.update_source_settings();
+            }
+            ["set", "debuginfod", urls @ ..] if !urls.is_empty() => {
+                let urls = parse_debuginfod_urls(&urls.join(" "));
+                if let Some(url) = urls.iter().find(|url| !url.starts_with("http://")) {
+                    println!(
+                        "unsupported debuginfod URL: \"{}\"; only http:// is supported",
+                        url
+                    );
+                    return;
+                }
+                self.source_settings.debuginfod_urls = urls;
+                self.update_source_settings();
+            }
+            ["info", "sources"] => {
+                for (from, to) in &self.source_settings.substitutions {
+                    println!("substitute-path {} -> {}", from, to);
+                }
+                for url in &self.source_settings.debuginfod_urls {
+                    println!("debuginfod {}", url);
+                }
+            }
+            ["set", "audit", "off"] => {
+                self.auditing = false;
+                for inferior in self.inferiors.iter_mut() {
+                    inferior.tracee.set_auditing(false);
+                }
+            }
+            ["set", "audit", path_str] => {
+                // Every system call, signal, fork and exec goes to the trace file from now on.
+                if !self.create_trace_file(path_str) {
+                    return;
+                }
+                self.auditing = true;
+                for inferior in self.inferiors.iter_mut() {
+                    inferior.tracee.set_auditing(true);
+                }
+            }
+            ["set", "rpc-socket", "off"] => {
+                self.rpc = None;
+                self.update_record_queues();
+            }
+            ["set", "rpc-socket", path_str] => {
+                // The old socket is closed first, in case the new one replaces it.
+                self.rpc = None;
+                match RpcServer::listen(Path::new(path_str), self.inputs.clone()) {
+                    Err(err) => println!("failed to listen on {}: {}", path_str, err),
+                    Ok(rpc) => {
+                        println!("Listening for JSON-RPC clients on {}", path_str);
+                        self.rpc = Some(rpc);
+                        self.update_record_queues();
+                    }
+                }
+            }
+            ["set", "web-server", "off"] => {
+                self.web = None;
+            }
+            ["set", "web-server", address] => {
+                // The old server is closed first, in case the new one listens at the same port.
+                self.web = None;
+                match WebServer::listen(address, self.inputs.clone()) {
+                    Err(err) => println!("failed to listen on {}: {}", address, err),
+                    Ok(web) => {
+                        println!("Serving the web frontend at {}", web.url());
+                        self.web = Some(web);
+                    }
+                }
+            }
+            ["set", variable, ..] if variable.starts_with('$') => {
+                self.assign_variable(line.trim_start()["set".len()..].trim());
+            }
+            [variable, ..] if variable.starts_with('$') => {
+                self.assign_variable(line);
+            }
+            ["print" | "p", ..] => {
+                let expression = line.trim_start()[words[0].len()..].trim();
+                match self.evaluate(expression) {
+                    Err(err) => println!("{}", err),
+                    Ok(value) => println!("{}", value),
+                }
+            }
+            ["call", ..] => {
+                self.call_function(line.trim_start()["call".len()..].trim());
+            }
+            ["return", ..] => {
+                self.return_from_function(line.trim_start()["return".len()..].trim());
+            }
+            ["continue"]
+                if self.watch_expr_mode == ContinueUntilMode::Step
+                    && !self.expression_watches.is_empty() =>
+            {
+                self.continue_watching();
+            }
+            ["continue", "until", ..] => {
+                let expression = line.trim_start()["continue".len()..].trim_start();
+                self.continue_until(expression["until".len()..].trim());
+            }
+            // The watches could not be checked while the program runs in the background, nor
+            // does stepping deliver signals.
+            ["continue", _]
+                if self.watch_expr_mode == ContinueUntilMode::Step
+                    && !self.expression_watches.is_empty() =>
+            {
+                println!(
+                    "{} is not supported while expression watches are checked by single-stepping",
+                    line.trim()
+                );
+            }
+            ["set", "continue-until-mode", mode_name] => {
+                match ContinueUntilMode::from_name(mode_name) {
+                    None => println!("invalid value for continue-until-mode: \"{}\"", mode_name),
+                    Some(mode) => self.continue_until_mode = mode,
+                }
+            }
+            ["set", "watch-expr-mode", mode_name] => {
+                match ContinueUntilMode::from_name(mode_name) {
+                    None => println!("invalid value for watch-expr-mode: \"{}\"", mode_name),
+                    Some(mode) => self.watch_expr_mode = mode,
+                }
+            }
+            ["watch", "expr"] => {
+                if self.expression_watches.is_empty() {
+                    println!("No expression watches.");
+                }
+                for watch in &self.expression_watches {
+                    println!(
+                        "{}: {} = {}",
+                        watch.id,
+                        watch.expression,
+                        format_watched_value(&watch.value)
+                    );
+                }
+            }
+            ["watch", "expr", "delete", id_str] => match id_str.parse::<usize>() {
+                Err(_) => println!("invalid expression watch number: \"{}\"", id_str),
+                Ok(id) => match self
+                    .expression_watches
+                    .iter()
+                    .position(|watch| watch.id == id)
+                {
+                    None => println!("no expression watch number {}", id),
+                    Some(i) => {
+                        self.expression_watches.remove(i);
+                    }
+                },
+            },
+            ["watch", "expr", ..] => {
+                if self.remote.is_some() {
+                    println!("watch expr is not supported on remote targets");
+                    return;
+                }
+                let expression =
+                    line.trim_start()["watch".len()..].trim_start()["expr".len()..].trim();
+                // Values that cannot be read yet are watched for when they can, but expressions
+                // that never could be are not.
+                let value = match self.evaluate(expression) {
+                    Err(
+                        err @ (ExpressionError::Empty
+                        | ExpressionError::Unexpected(_)
+                        | ExpressionError::UnexpectedEnd
+                        | ExpressionError::InvalidNumber(_)),
+                    ) => {
+                        println!("{}", err);
+                        return;
+                    }
+                    result => result.map_err(|err| err.to_string()),
+                };
+                let id = self.next_expression_watch_id;
+                self.next_expression_watch_id += 1;
+                println!(
+                    "Expression watch {}: {} = {}",
+                    id,
+                    expression,
+                    format_watched_value(&value)
+                );
+                self.expression_watches.push(ExpressionWatch {
+                    id,
+                    expression: expression.to_string(),
+                    value,
+                });
+            }
+            ["display"] => {
+                for (id, expression) in &self.displays {
+                    self.print_display(*id, expression);
+                }
+            }
+            ["display", ..] => {
+                let expression = line.trim_start()["display".len()..].trim();
+                let id = self.next_display_id;
+                self.next_display_id += 1;
+                self.displays.push((id, expression.to_string()));
+                if self.selected_tracee().status() == TraceeStatus::Stopped {
+                    self.print_display(id, expression);
+                }
+            }
+            ["undisplay", id_str] => match id_str.parse::<usize>() {
+                Err(_) => println!("invalid display number: \"{}\"", id_str),
+                Ok(id) => match self.displays.iter().position(|(other, _)| *other == id) {
+                    None => println!("no display number {}", id),
+                    Some(i) => {
+                        self.displays.remove(i);
+                    }
+                },
+            },
+            ["info", "display"] => {
+                if self.displays.is_empty() {
+                    println!("No display expressions.");
+                }
+                for (id, expression) in &self.displays {
+                    println!("{}: {}", id, expression);
+                }
+            }
+            ["show", "convenience"] => {
+                if self.variables.iter().next().is_none() {
+                    println!("No convenience variables.");
+                }
+                for (name, value) in self.variables.iter() {
+                    println!("${} = {}", name, value);
+                }
+            }
+            ["source", path_str] => match fs::read_to_string(path_str) {
+                Err(err) => println!("failed to read script {}: {}", path_str, err),
+                Ok(script) => self.run_script(&script),
+            },
+            ["script", ..] => {
+                let script = line.trim_start()["script".len()..].trim();
+                if script.is_empty() {
+                    println!("missing script to run");
+                    return;
+                }
+                self.run_script(script);
+            }
+            ["plugin", "load", path_str] => {
+                let i = self.selected_inferior_index();
+                match load_plugin(Path::new(path_str), &mut self.inferiors[i].tracee) {
+                    Err(err) => println!("{}", err),
+                    Ok((plugin, actions)) => {
+                        println!(
+                            "Loaded plugin \"{}\" with commands: {}",
+                            plugin.name(),
+                            plugin.commands().join(", ")
+                        );
+                        self.plugins.push(plugin);
+                        self.apply_script_actions(i, actions);
+                        self.update_record_queues();
+                    }
+                }
+            }
+            ["plugin", "list"] => {
+                if self.plugins.is_empty() {
+                    println!("No plugins loaded.");
+                }
+                for plugin in &self.plugins {
+                    println!("{}: {}", plugin.name(), plugin.commands().join(", "));
+                }
+            }
+            ["handle", signal_str, keywords @ ..] => {
+                let Some(signal) = parse_signal(signal_str) else {
+                    println!("unknown signal: \"{}\"", signal_str);
+                    return;
+                };
+
+                let mut disposition = self.signal_dispositions.get(signal);
+                for keyword in keywords {
+                    if !disposition.apply_keyword(keyword) {
+                        println!("unknown signal disposition: \"{}\"", keyword);
+                        return;
+                    }
+                }
+
+                self.signal_dispositions.set(signal, disposition);
+                for inferior in self.inferiors.iter_mut() {
+                    inferior
+                        .tracee
+                        .set_signal_dispositions(self.signal_dispositions.clone());
+                }
+                println!(
+                    "{} stop {} pass {} print {}",
+                    signal_name(signal),
+                    disposition.stop,
+                    disposition.pass,
+                    disposition.print,
+                );
+            }
+            ["target", "remote", address] => {
+                if self.remote.is_some() {
+                    println!("already connected to a remote target; use `disconnect` first");
+                    return;
+                }
+
+                println!("Remote debugging using {}", address);
+                match RemoteTarget::connect(address) {
+                    Err(err) => println!("{}", err),
+                    Ok(remote) => self.remote = Some(remote),
+                }
+            }
+            ["disconnect"] => match self.remote.take() {
+                None => println!("not connected to a remote target"),
+                Some(_) => println!("Ending remote debugging."),
+            },
+            ["detach"] => match self.remote.take() {
+                None => println!("not connected to a remote target"),
+                Some(remote) => remote.detach(),
+            },
+            _ => {
+                if let Some(remote) = self.remote.as_mut() {
+                    handle_target_command(remote, line);
+                    return;
+                }
+                if self.handle_added_command(&words) {
+                    return;
+                }
+
+                handle_command(self.selected_tracee(), line);
+
+                let parent_id = self.selected_inferior_id;
+                for tracee in self.selected_tracee().take_forked_tracees() {
+                    let pid = tracee.pid();
+                    let id = self.add_inferior(tracee, Some(parent_id));
+                    println!("[New inferior {} (pid {}) from fork]", id, pid);
+                }
+            }
+        }
+    }
+
+    // Inferiors queue their records while anything consumes them.
+    fn needs_records(&self) -> bool {
+        return self.rpc.is_some() || self.scripts.has_hooks() || !self.plugins.is_empty();
+    }
+
+    fn update_record_queues(&mut self) {
+        let enabled = self.needs_records();
+        for inferior in self.inferiors.iter_mut() {
+            inferior.tracee.set_record_queue(enabled);
+        }
+    }
+
+    // Sends what the inferiors recorded since the last time to the subscribed RPC clients, and
+    // runs the script hooks and plugins on the records. Hooks may stop inferiors again, which records more.
+    unsafe fn dispatch_records(&mut self) {
+        loop {
+            let mut has_records = false;
+            for i in 0..self.inferiors.len() {
+                let records = self.inferiors[i].tracee.take_records();
+                has_records |= !records.is_empty();
+                for record in records {
+                    let tracee = &mut self.inferiors[i].tracee;
+                    if let Some(rpc) = self.rpc.as_mut() {
+                        rpc.publish("record", record.to_json(tracee.pid()));
+                    }
+                    let mut actions = self.scripts.run_hooks(tracee, &record);
+                    for plugin in self.plugins.iter_mut() {
+                        actions.extend(plugin.handle_record(tracee, &record));
+                    }
+                    self.apply_script_actions(i, actions);
+                }
+            }
+            if !has_records {
+                return;
+            }
+        }
+    }
+
+    // Runs a script against the selected inferior.
+    unsafe fn run_script(&mut self, script: &str) {
+        let i = self.selected_inferior_index();
+        let (result, actions) = self.scripts.run(&mut self.inferiors[i].tracee, script);
+        match result {
+            Err(err) => println!("script failed: {}", err),
+            Ok(None) => {}
+            Ok(Some(value)) => println!("{}", value),
+        }
+        self.apply_script_actions(i, actions);
+        self.update_record_queues();
+    }
+
+    // Handles a command that a script or plugin added. Returns false if none did.
+    unsafe fn handle_added_command(&mut self, words: &[&str]) -> bool {
+        let Some(name) = words.first() else {
+            return false;
+        };
+        let i = self.selected_inferior_index();
+        let tracee = &mut self.inferiors[i].tracee;
+        let actions = match self.scripts.run_command(tracee, words) {
+            Some((result, actions)) => {
+                match result {
+                    Err(err) => println!("script failed: {}", err),
+                    Ok(None) => {}
+                    Ok(Some(value)) => println!("{}", value),
+                }
+                actions
+            }
+            None => {
+                let Some(plugin) = self
+                    .plugins
+                    .iter_mut()
+                    .find(|plugin| plugin.commands().iter().any(|command| command == name))
+                else {
+                    return false;
+                };
+                plugin.handle_command(tracee, words)
+            }
+        };
+        self.apply_script_actions(i, actions);
+        return true;
+    }
+
+    unsafe fn apply_script_actions(&mut self, i: usize, actions: Vec<ScriptAction>) {
+        for action in actions {
+            let tracee = &mut self.inferiors[i].tracee;
+            let result = match action {
+                ScriptAction::Break(address) => set_breakpoint_at(tracee, address),
+                ScriptAction::DeleteBreak(address) => delete_breakpoint_at(tracee, address),
+                ScriptAction::Resume if tracee.status() == TraceeStatus::Stopped => {
+                    tracee.resume();
+                    Ok(())
+                }
+                ScriptAction::Resume => Ok(()),
+                ScriptAction::Command(line) => {
+                    let selected_inferior_id = self.selected_inferior_id;
+                    self.selected_inferior_id = self.inferiors[i].id;
+                    self.handle_command(&line);
+                    if self
+                        .inferiors
+                        .iter()
+                        .any(|inferior| inferior.id == selected_inferior_id)
+                    {
+                        self.selected_inferior_id = selected_inferior_id;
+                    }
+                    Ok(())
+                }
+            };
+            if let Err(err) = result {
+                println!("{}", err);
+            }
+        }
+    }
 
-    for line_result in stdin.lock().lines() {
-        match line_result {
-            Err(err) => {
-                println!("failed to read line from stdin: {}", err);
+    // Handles a request of an RPC client, which mirrors the commands of the prompt.
+    pub unsafe fn handle_rpc(&mut self, request: &RpcRequest) {
+        let result = self.handle_rpc_method(request);
+        request.respond(result);
+    }
+
+    unsafe fn handle_rpc_method(&mut self, request: &RpcRequest) -> Result<Json, RpcError> {
+        let params = &request.params;
+        match request.method.as_str() {
+            "subscribe" => {
+                if let Some(rpc) = self.rpc.as_mut() {
+                    rpc.subscribe(&request.client);
+                }
+                return Ok(Json::Null);
             }
-            Ok(line) => handle_command(tracee, &line),
+            "unsubscribe" => {
+                if let Some(rpc) = self.rpc.as_mut() {
+                    rpc.unsubscribe(&request.client);
+                }
+                return Ok(Json::Null);
+            }
+            // Output goes to the terminal, like that of a typed command.
+            "command" => {
+                let Some(line) = params.get("line").and_then(Json::as_str) else {
+                    return Err(RpcError::InvalidParams("missing \"line\"".to_string()));
+                };
+                println!("{}", line);
+                self.handle_command(line);
+                return Ok(Json::Null);
+            }
+            "status" => {
+                let inferiors = self
+                    .inferiors
+                    .iter()
+                    .map(|inferior| {
+                        let tids = inferior
+                            .tracee
+                            .threads()
+                            .iter()
+                            .map(|thread| Json::from(thread.tid))
+                            .collect::<Vec<Json>>();
+                        return Json::object(vec![
+                            ("id", Json::from(inferior.id)),
+                            ("pid", Json::from(inferior.tracee.pid())),
+                            (
+                                "status",
+                                Json::from(
+                                    format!("{:?}", inferior.tracee.status()).to_lowercase(),
+                                ),
+                            ),
+                            ("selectedTid", Json::from(inferior.tracee.selected_tid())),
+                            ("threads", Json::from(tids)),
+                        ]);
+                    })
+                    .collect::<Vec<Json>>();
+                return Ok(Json::object(vec![
+                    ("selectedInferior", Json::from(self.selected_inferior_id)),
+                    ("inferiors", Json::from(inferiors)),
+                ]));
+            }
+            _ => {}
         }
 
-        write!(stdout, "pbreak> ").unwrap();
-        stdout.flush().unwrap();
+        if self.remote.is_some() {
+            return Err(RpcError::Failed(
+                "only \"command\" is supported while connected to a remote target".to_string(),
+            ));
+        }
+        let i = self.selected_inferior_index();
+        let tracee = &mut self.inferiors[i].tracee;
+        match request.method.as_str() {
+            "interrupt" => {
+                if tracee.status() == TraceeStatus::Running {
+                    tracee.interrupt(None);
+                }
+                return Ok(Json::Null);
+            }
+            "breakpoints" => {
+                let mut addresses = tracee
+                    .breakpoints()
+                    .breakpoints()
+                    .iter()
+                    .map(|breakpoint| breakpoint.address())
+                    .collect::<Vec<u64>>();
+                addresses.sort();
+                return Ok(Json::from(
+                    addresses
+                        .iter()
+                        .map(|address| Json::from(format!("{:#x}", address)))
+                        .collect::<Vec<Json>>(),
+                ));
+            }
+            "readMemory" | "writeMemory" | "readRegisters" | "backtrace" | "setBreakpoint"
+            | "removeBreakpoint" | "continue" => {}
+            method => return Err(RpcError::MethodNotFound(method.to_string())),
+        }
+        if tracee.status() != TraceeStatus::Stopped {
+            return Err(RpcError::Failed(format!(
+                "process ({}) is not stopped",
+                tracee.pid()
+            )));
+        }
+
+        match request.method.as_str() {
+            "readMemory" => {
+                let address = read_address(params, "address")?;
+                let Some(len) = params.get("length").and_then(Json::as_u64) else {
+                    return Err(RpcError::InvalidParams("missing \"length\"".to_string()));
+                };
+                return match tracee.read_memory(address, len as usize) {
+                    Err(err) => Err(RpcError::Failed(err.to_string())),
+                    Ok(bytes) => Ok(Json::object(vec![("data", Json::from(to_hex(&bytes)))])),
+                };
+            }
+            "writeMemory" => {
+                let address = read_address(params, "address")?;
+                let Some(bytes) = params.get("data").and_then(Json::as_str).and_then(from_hex)
+                else {
+                    return Err(RpcError::InvalidParams("missing hex \"data\"".to_string()));
+                };
+                return match tracee.write_memory(address, &bytes) {
+                    Err(err) => Err(RpcError::Failed(err.to_string())),
+                    Ok(()) => Ok(Json::Null),
+                };
+            }
+            "readRegisters" => {
+                let tid = read_tid(tracee, params)?;
+                let regs = tracee.read_thread_general_purpose_registers(tid);
+                let mut names = (0..regs.regs.len())
+                    .map(|i| format!("x{}", i))
+                    .collect::<Vec<String>>();
+                names.extend(["sp", "pc", "pstate"].map(str::to_string));
+                let fields = names
+                    .iter()
+                    .map(|name| {
+                        let value = read_general_purpose_register(&regs, name).unwrap_or(0);
+                        return (name.as_str(), Json::from(format!("{:#x}", value)));
+                    })
+                    .collect::<Vec<(&str, Json)>>();
+                return Ok(Json::object(fields));
+            }
+            "backtrace" => {
+                let tid = read_tid(tracee, params)?;
+                let functions = tracee.read_functions().unwrap_or_default();
+                let frames = unwind_thread(tracee, tid)
+                    .iter()
+                    .map(|frame| {
+                        let function = match frame.is_signal_trampoline {
+                            true => Some("<signal handler called>".to_string()),
+                            false => describe_address(&functions, frame.pc),
+                        };
+                        return Json::object(vec![
+                            ("pc", Json::from(format!("{:#x}", frame.pc))),
+                            ("function", function.map_or(Json::Null, Json::from)),
+                        ]);
+                    })
+                    .collect::<Vec<Json>>();
+                return Ok(Json::from(frames));
+            }
+            "setBreakpoint" => {
+                let address = read_address(params, "address")?;
+                return match set_breakpoint_at(tracee, address) {
+                    Err(err) => Err(RpcError::Failed(err)),
+                    Ok(()) => Ok(Json::Null),
+                };
+            }
+            "removeBreakpoint" => {
+                let address = read_address(params, "address")?;
+                return match delete_breakpoint_at(tracee, address) {
+                    Err(err) => Err(RpcError::Failed(err)),
+                    Ok(()) => Ok(Json::Null),
+                };
+            }
+            // Continues in the background; clients learn of the next stop from their records.
+            _ => {
+                tracee.resume();
+                return Ok(Json::Null);
+            }
+        }
+    }
+}
+
+// Reads the thread that a request is about out of its params, which is the selected one unless
+// given as "tid".
+fn read_tid(tracee: &Tracee, params: &Json) -> Result<libc::pid_t, RpcError> {
+    let tid = match params.get("tid").and_then(Json::as_i64) {
+        None => tracee.selected_tid(),
+        Some(tid) => tid as libc::pid_t,
+    };
+    if !tracee.threads().iter().any(|thread| thread.tid == tid) {
+        return Err(RpcError::Failed(format!("unknown thread id: {}", tid)));
     }
+    return Ok(tid);
+}
+
+// Places a breakpoint of RPC clients and scripts, which are the same as those set with `break`. One
+// that is there already is enabled.
+unsafe fn set_breakpoint_at(tracee: &mut Tracee, address: u64) -> Result<(), String> {
+    let result = match tracee.breakpoints().find(address).map(Breakpoint::id) {
+        Some(id) => tracee.enable_breakpoint(id),
+        None => tracee.insert_breakpoint(address).map(|_| ()),
+    };
+    return result.map_err(|err| err.to_string());
 }
 
+unsafe fn delete_breakpoint_at(tracee: &mut Tracee, address: u64) -> Result<(), String> {
+    let Some(id) = tracee.breakpoints().find(address).map(Breakpoint::id) else {
+        return Err(format!("no breakpoint at {:#x}", address));
+    };
+    return tracee.delete_breakpoint(id).map_err(|err| err.to_string());
+}
+
+// Handles a command that operates on a single inferior.
 pub unsafe fn handle_command(tracee: &mut Tracee, line: &str) {
-    match line {
-        "continue" => {
+    let words = line.split_whitespace().collect::<Vec<&str>>();
+    match words.as_slice() {
+        ["interrupt", ..] if tracee.status() != TraceeStatus::Running => {
+            println!("process ({}) is not running", tracee.pid());
+        }
+        ["interrupt"] => {
+            tracee.interrupt(None);
+        }
+        ["interrupt", tid_str] => match tid_str.parse::<libc::pid_t>() {
+            Err(_) => println!("invalid thread id: \"{}\"", tid_str),
+            Ok(tid) => {
+                if !tracee.interrupt(Some(tid)) {
+                    println!("unknown thread id: {}", tid);
+                }
+            }
+        },
+        // Everything here comes from procfs, so the process may be running.
+        ["info", "proc"] => print_process_info(tracee.pid()),
+        ["info", "fds"] => print_open_files(tracee.pid()),
+        ["info", "network"] => print_sockets(tracee.pid()),
+        ["info", "auxv"] => match tracee.read_auxv() {
+            None => println!("process ({}) no longer exists", tracee.pid()),
+            Some(auxv) => {
+                let read_string = |address| {
+                    let bytes = tracee.read_c_string(address, MAX_AUXV_STRING_LEN).ok()?;
+                    return Some(String::from_utf8_lossy(&bytes).to_string());
+                };
+                for (kind, value) in auxv.entries() {
+                    println!("{}", format_entry(*kind, *value, read_string));
+                }
+            }
+        },
+        ["info", "environ", names @ ..] => match procfs::read_environ(tracee.pid()) {
+            None => println!("process ({}) no longer exists", tracee.pid()),
+            Some(vars) => {
+                for var in vars {
+                    let name = var.split('=').next().unwrap_or_default();
+                    if names.is_empty() || names.contains(&name) {
+                        println!("{}", var);
+                    }
+                }
+            }
+        },
+        [_, ..] if tracee.status() == TraceeStatus::Running => {
+            println!(
+                "process ({}) is running in the background; use `interrupt` to stop it",
+                tracee.pid()
+            );
+        }
+        ["continue", "&"] => {
             tracee.resume();
-            tracee.wait_on_signal();
+            println!("Continuing in the background.");
         }
-        "readgp" => {
-            let regs = tracee.read_general_purpose_registers();
-            dbg!(regs.regs);
-            dbg!(regs.sp);
-            dbg!(regs.pc);
-            dbg!(regs.pstate);
+        ["signal" | "kill" | "profile" | "jump" | "patch" | "catch" | "break" | "breakpoint"
+        | "watch" | "hbreak" | "sharedlibrary" | "itrace" | "ltrace" | "ftrace" | "stepi"
+        | "si", ..]
+            if tracee.status() == TraceeStatus::Exited
+                || tracee.status() == TraceeStatus::Terminated =>
+        {
+            println!("process ({}) is no longer alive", tracee.pid());
         }
-        "writegp" => {
-            let mut regs = tracee.read_general_purpose_registers();
-            regs.sp = 99999999;
-            tracee.write_general_purpose_registers(&mut regs);
+        ["profile", duration_str, path_str @ ..] if path_str.len() <= 1 => {
+            let Some(duration) = parse_duration(duration_str) else {
+                println!("invalid duration: \"{}\"", duration_str);
+                return;
+            };
+            let profile = profile_tracee(tracee, duration);
+            match path_str.first() {
+                None => print!("{}", profile.folded()),
+                Some(path) => match fs::write(path, profile.folded()) {
+                    Err(err) => println!("failed to write profile {}: {}", path, err),
+                    Ok(()) => println!("Saved {} samples to {}", profile.samples(), path),
+                },
+            }
+        }
+        ["stepi" | "si"] => {
+            // How the process ended has been reported otherwise.
+            if let Some(pc) = tracee.step_instruction() {
+                let functions = tracee.read_functions().unwrap_or_default();
+                match describe_address(&functions, pc) {
+                    None => println!("{:#x}", pc),
+                    Some(function) => println!("{:#x} in {}", pc, function),
+                }
+                tracee.print_stop_disassembly();
+            }
+        }
+        ["itrace", args @ ..] => {
+            let mut disassemble_steps = false;
+            let mut show_registers = false;
+            let mut positional = vec![];
+            for arg in args {
+                match *arg {
+                    "--disassemble" => disassemble_steps = true,
+                    "--registers" => show_registers = true,
+                    _ if arg.starts_with("--") => {
+                        println!("unknown option: \"{}\"", arg);
+                        return;
+                    }
+                    _ => positional.push(*arg),
+                }
+            }
+            let (limit, path) = match positional.as_slice() {
+                ["until", location, path] => match tracee.resolve_location(location) {
+                    Err(err) => {
+                        println!("{}", err);
+                        return;
+                    }
+                    Ok(address) => (TraceLimit::Until(address), *path),
+                },
+                [count_str, path] => match count_str.parse::<usize>() {
+                    Err(_) => {
+                        println!("invalid count: \"{}\"", count_str);
+                        return;
+                    }
+                    Ok(count) => (TraceLimit::Count(count), *path),
+                },
+                _ => {
+                    println!("expected a count or \"until <location>\", and a file to write to");
+                    return;
+                }
+            };
+            match trace_instructions(tracee, limit, path, disassemble_steps, show_registers) {
+                Err(err) => println!("failed to write instruction trace {}: {}", path, err),
+                Ok(count) => println!("Traced {} instructions to {}", count, path),
+            }
+            // How the process ended has been reported otherwise.
+            if tracee.status() == TraceeStatus::Stopped {
+                let pc = tracee.read_general_purpose_registers().pc;
+                println!("Process ({}) stopped at {:#x}", tracee.pid(), pc);
+                tracee.print_stop_disassembly();
+            }
+        }
+        ["signal", signal_str] => match parse_signal(signal_str) {
+            None => println!("unknown signal: \"{}\"", signal_str),
+            Some(signal) => {
+                tracee.signal_thread(signal);
+                println!(
+                    "Sent {} to thread ({}); it is delivered once the thread is resumed",
+                    signal_name(signal),
+                    tracee.selected_tid(),
+                );
+            }
+        },
+        ["kill", signal_str] => match parse_signal(signal_str) {
+            None => println!("unknown signal: \"{}\"", signal_str),
+            Some(signal) => {
+                tracee.signal_process(signal);
+                println!(
+                    "Sent {} to process ({}); it is delivered once the process is resumed",
+                    signal_name(signal),
+                    tracee.pid(),
+                );
+            }
+        },
+        ["jump", location] => match tracee.resolve_location(location) {
+            Err(err) => println!("{}", err),
+            Ok(address) => {
+                let mut regs = tracee.read_general_purpose_registers();
+                regs.pc = address;
+                tracee.write_general_purpose_registers(&mut regs);
+                print_moved_pc(tracee, "Jumped", address);
+            }
+        },
+        // The instructions are the rest of the line, and may be quoted, e.g. `patch asm 0x4005d0
+        // "mov w0, #0; ret"`.
+        ["patch", "asm", location, _, ..] => match tracee.resolve_location(location) {
+            Err(err) => println!("{}", err),
+            Ok(address) => {
+                let rest =
+                    line.trim_start()["patch".len()..].trim_start()["asm".len()..].trim_start();
+                let instructions = rest[location.len()..].trim();
+                let instructions = instructions
+                    .strip_prefix('"')
+                    .and_then(|instructions| instructions.strip_suffix('"'))
+                    .unwrap_or(instructions);
+                match assemble(instructions, address) {
+                    Err(err) => println!("{}", err),
+                    Ok(code) => match tracee.patch_code(address, &code) {
+                        Err(err) => println!("{}", err),
+                        Ok(()) => {
+                            println!("Patched {} bytes at {:#x}", code.len(), address);
+                            tracee
+                                .print_disassembly(address, code.len() / INSTRUCTION_LEN as usize);
+                        }
+                    },
+                }
+            }
+        },
+        ["patch", "asm", ..] => {
+            println!("usage: patch asm <location> <instructions>");
+            println!("Instructions are separated by semicolons, e.g. \"mov w0, #0; ret\".");
+            println!(
+                "Supported are {}; others can be written as .inst <encoding>.",
+                MNEMONICS.join(", ")
+            );
+        }
+        ["patch", "restore", location] => match tracee.resolve_location(location) {
+            Err(err) => println!("{}", err),
+            Ok(address) => match tracee.restore_code_patch(address) {
+                Err(err) => println!("{}", err),
+                Ok(()) => println!("Restored the code at {:#x}", address),
+            },
+        },
+        ["catch", "load", pattern] => match tracee.catch_load(pattern) {
+            Err(err) => println!("{}", err),
+            Ok(()) => println!("Catching loads of libraries matching \"{}\"", pattern),
+        },
+        ["break", "on-resolve", symbol] => match tracee.break_on_resolve(symbol) {
+            Err(err) => println!("{}", err),
+            Ok(Some(target)) => println!("{} is already resolved to {:#x}", symbol, target),
+            Ok(None) => println!("Breaking once the dynamic linker resolves {}", symbol),
+        },
+        ["break", location] => match tracee.insert_breakpoint_at(location) {
+            Err(err) => println!("{}", err),
+            Ok((id, address)) => {
+                let functions = tracee.read_functions().unwrap_or_default();
+                match describe_address(&functions, address) {
+                    None => println!("Breakpoint {} at {:#x}", id, address),
+                    Some(function) => {
+                        println!("Breakpoint {} at {:#x} in {}", id, address, function)
+                    }
+                }
+            }
+        },
+        ["breakpoint", "list"] => {
+            let breakpoints = tracee.breakpoints().breakpoints();
+            let pending = tracee.breakpoints().pending();
+            if breakpoints.is_empty() && pending.is_empty() {
+                println!("No breakpoints.");
+                return;
+            }
+            let functions = tracee.read_functions().unwrap_or_default();
+            println!("{:<4} {:<4} {:<18} What", "Num", "Enb", "Address");
+            for breakpoint in breakpoints {
+                let enabled = match breakpoint.is_enabled() {
+                    true => "y",
+                    false => "n",
+                };
+                println!(
+                    "{:<4} {:<4} {:#018x} {}",
+                    breakpoint.id(),
+                    enabled,
+                    breakpoint.address(),
+                    describe_address(&functions, breakpoint.address()).unwrap_or_default()
+                );
+            }
+            for pending in pending {
+                println!(
+                    "{:<4} {:<4} {:<18} {}",
+                    pending.id, "y", "<pending>", pending.location
+                );
+            }
+        }
+        ["breakpoint", action @ ("enable" | "disable" | "delete"), id_str] => {
+            let Ok(id) = id_str.parse::<usize>() else {
+                println!("invalid breakpoint number: \"{}\"", id_str);
+                return;
+            };
+            let result = match *action {
+                "enable" => tracee.enable_breakpoint(id),
+                "disable" => tracee.disable_breakpoint(id),
+                _ => tracee.delete_breakpoint(id),
+            };
+            if let Err(err) = result {
+                println!("{}", err);
+            }
+        }
+        ["watch", "delete", id_str] => match id_str.parse::<usize>() {
+            Err(_) => println!("invalid watchpoint number: \"{}\"", id_str),
+            Ok(id) => {
+                if let Err(err) = tracee.delete_watchpoint(id) {
+                    println!("{}", err);
+                }
+            }
+        },
+        // e.g. `watch 0x4010 4 if $value == 0`, which watches 4 bytes, 8 unless given, and stops
+        // only once a write makes them 0.
+        ["watch", _, ..] => {
+            let rest = line.trim_start()["watch".len()..].trim();
+            let (target, condition) = match rest.split_once(" if ") {
+                None => (rest, None),
+                Some((target, condition)) => (target.trim(), Some(condition.trim())),
+            };
+            let (location, len_str) = match target.split_once(char::is_whitespace) {
+                None => (target, "8"),
+                Some((location, len_str)) => (location, len_str.trim()),
+            };
+            let Ok(len) = len_str.parse::<u64>() else {
+                println!("invalid watchpoint length: \"{}\"", len_str);
+                return;
+            };
+            match tracee.resolve_location(location) {
+                Err(err) => println!("{}", err),
+                Ok(address) => match tracee.insert_watchpoint(address, len, condition) {
+                    Err(err) => println!("{}", err),
+                    Ok(id) => match condition {
+                        None => println!("Watchpoint {} at {:#x} ({} bytes)", id, address, len),
+                        Some(condition) => println!(
+                            "Watchpoint {} at {:#x} ({} bytes) if {}",
+                            id, address, len, condition
+                        ),
+                    },
+                },
+            }
+        }
+        ["info", "watchpoints"] => {
+            if tracee.watchpoints().is_empty() {
+                println!("No watchpoints.");
+            }
+            for watchpoint in tracee.watchpoints() {
+                let condition = match &watchpoint.condition {
+                    None => String::new(),
+                    Some(condition) => format!(" if {}", condition),
+                };
+                println!(
+                    "{}: {:#x} ({} bytes) = {}{}",
+                    watchpoint.id,
+                    watchpoint.address,
+                    watchpoint.len,
+                    Value::UInt(watchpoint.value),
+                    condition
+                );
+            }
+        }
+        ["sharedlibrary", "reload"] => match tracee.reload_shared_libraries() {
+            Err(err) => println!("{}", err),
+            Ok(reload) => {
+                for path in &reload.added {
+                    println!("Indexed {}", path);
+                }
+                for path in &reload.removed {
+                    println!("Dropped {}", path);
+                }
+                println!(
+                    "{} object(s) indexed, {} dropped; later loads are indexed as they happen.",
+                    reload.added.len(),
+                    reload.removed.len()
+                );
+            }
+        },
+        ["info", "watch-resources"] => {
+            if print_debug_slots(tracee, &[SlotKind::Breakpoint, SlotKind::Watchpoint]) {
+                println!(
+                    "Slots in use are set in each of {} threads.",
+                    tracee.threads().len()
+                );
+            }
+        }
+        ["hbreak"] => {
+            print_debug_slots(tracee, &[SlotKind::Breakpoint]);
+        }
+        ["hbreak", "delete", index_str] => match index_str.parse::<usize>() {
+            Err(_) => println!("invalid slot: \"{}\"", index_str),
+            Ok(index) => {
+                if let Err(err) = tracee.remove_hw_breakpoint(index) {
+                    println!("{}", err);
+                }
+            }
+        },
+        ["hbreak", location] => match tracee.resolve_location(location) {
+            Err(err) => println!("{}", err),
+            Ok(address) => match tracee.set_hw_breakpoint(address) {
+                Err(err) => println!("{}", err),
+                Ok(index) => {
+                    let functions = tracee.read_functions().unwrap_or_default();
+                    match describe_address(&functions, address) {
+                        None => println!("Hardware breakpoint {} at {:#x}", index, address),
+                        Some(function) => println!(
+                            "Hardware breakpoint {} at {:#x} in {}",
+                            index, address, function
+                        ),
+                    }
+                }
+            },
+        },
+        ["info", "sharedlibrary"] => match tracee.read_shared_libraries() {
+            Err(err) => println!("{}", err),
+            Ok(libraries) if libraries.is_empty() => {
+                println!("No shared libraries loaded at this time.");
+            }
+            Ok(libraries) => {
+                println!(
+                    "{:<18}  {:<18}  {:<4}  {:<5}  Shared Object Library",
+                    "From", "To", "Syms", "Debug"
+                );
+                let yes_no = |found: bool| match found {
+                    true => "Yes",
+                    false => "No",
+                };
+                for library in libraries {
+                    println!(
+                        "{:#018x}  {:#018x}  {:<4}  {:<5}  {}",
+                        library.start,
+                        library.end,
+                        yes_no(library.has_symbols),
+                        yes_no(library.has_debug_info),
+                        library.path
+                    );
+                }
+            }
+        },
+        ["info", "patches"] => {
+            if tracee.code_patches().is_empty() {
+                println!("No code patches.");
+            }
+            for patch in tracee.code_patches() {
+                let instructions = disassemble(&patch.bytes, patch.address, patch.bytes.len())
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|instruction| instruction.text.clone())
+                    .collect::<Vec<String>>();
+                println!(
+                    "{:#x}  {} -> {}  {}",
+                    patch.address,
+                    to_hex(&patch.original),
+                    to_hex(&patch.bytes),
+                    instructions.join("; "),
+                );
+            }
+        }
+        ["stepsyscall"] => {
+            tracee.resume_until_syscall();
+            tracee.wait_on_signal();
+        }
+        ["trace", "syscalls", options @ ..] => {
+            let mut mode = SyscallTraceMode::Ptrace;
+            let mut filter = SyscallFilter::default();
+            let mut summary = None;
+            let mut fault = None;
+            for option in options {
+                match *option {
+                    "--seccomp" => mode = SyscallTraceMode::Seccomp,
+                    "--summary" => summary = Some(SyscallSummary::default()),
+                    _ if option.starts_with("--fail=") => {
+                        let errno_str = &option["--fail=".len()..];
+                        match parse_errno(errno_str) {
+                            None => {
+                                println!("unknown errno: \"{}\"", errno_str);
+                                return;
+                            }
+                            Some(errno) => fault = Some(errno),
+                        }
+                    }
+                    _ if option.starts_with("--") => {
+                        println!("unknown option: \"{}\"", option);
+                        return;
+                    }
+                    filter_spec => match SyscallFilter::from_spec(filter_spec) {
+                        Err(err) => {
+                            println!("invalid syscall filter: {}", err);
+                            return;
+                        }
+                        Ok(spec_filter) => filter = spec_filter,
+                    },
+                }
+            }
+
+            tracee.set_syscall_trace_mode(mode);
+            tracee.set_syscall_trace_filter(Some(filter));
+            tracee.set_syscall_summary(summary);
+            tracee.set_syscall_fault(fault);
+            match mode {
+                SyscallTraceMode::Ptrace => tracee.resume_until_syscall(),
+                SyscallTraceMode::Seccomp => tracee.resume(),
+            }
+            tracee.wait_on_signal();
+            tracee.set_syscall_trace_filter(None);
+            tracee.set_syscall_fault(None);
+
+            if let Some(summary) = tracee.take_syscall_summary() {
+                for line in summary.format_table() {
+                    println!("{}", line);
+                }
+            }
         }
-        "readfp" => {
+        ["syscall", "return", value_str] => match parse_return_value(value_str) {
+            None => println!("invalid return value: \"{}\"", value_str),
+            Some(value) => {
+                if !tracee.set_syscall_return_value(value) {
+                    println!("not stopped at the exit of a syscall");
+                }
+            }
+        },
+        ["syscall", "fail", errno_str] => match parse_errno(errno_str) {
+            None => println!("unknown errno: \"{}\"", errno_str),
+            Some(errno) => {
+                if !tracee.fail_syscall(errno) {
+                    println!("not stopped at the entry of a syscall");
+                }
+            }
+        },
+        ["readfp"] => {
             let regs = tracee.read_floating_point_registers();
             dbg!(regs.vregs);
             dbg!(regs.fpsr);
             dbg!(regs.fpcr);
         }
-        "writefp" => {
+        ["writefp"] => {
             let mut regs = tracee.read_floating_point_registers();
             regs.fpcr = 99999999;
             tracee.write_floating_point_registers(&mut regs);
         }
-        line => {
+        ["info", "signal"] => match tracee.read_siginfo() {
+            None => println!(
+                "thread ({}) did not stop for a signal",
+                tracee.selected_tid()
+            ),
+            Some(info) => {
+                for line in format_siginfo(&info) {
+                    println!("{}", line);
+                }
+            }
+        },
+        ["info", "signals-received"] => {
+            if tracee.signal_history().is_empty() {
+                println!("No signals received.");
+            }
+
+            for received in tracee.signal_history() {
+                let disposition = match (received.stopped, received.passed) {
+                    (true, true) => "stop, pass",
+                    (true, false) => "stop, nopass",
+                    (false, true) => "nostop, pass",
+                    (false, false) => "nostop, nopass",
+                };
+                println!(
+                    "+{:>10.3}s thread {} {} ({})",
+                    received.elapsed.as_secs_f64(),
+                    received.tid,
+                    signal_name(received.signal),
+                    disposition,
+                );
+            }
+        }
+        ["info", "functions", filter @ ..] if filter.len() <= 1 => {
+            print_functions(tracee, filter.first().copied().unwrap_or(""));
+        }
+        ["info", "plt"] => {
+            let path = tracee.executable().display().to_string();
+            print_plt(tracee, &[path]);
+        }
+        ["info", "plt", library] => {
+            let paths = find_mapped_objects(tracee, library);
+            if paths.is_empty() {
+                println!("no mapped object matches \"{}\"", library);
+            }
+            print_plt(tracee, &paths);
+        }
+        // A function is disassembled as a whole, unless its extent is unknown.
+        ["disassemble", name] if !name.starts_with("0x") => match tracee.find_function(name) {
+            Err(err) => println!("{}", err),
+            Ok(function) if function.size == 0 => {
+                tracee.print_disassembly(function.address, DEFAULT_DISASSEMBLY_COUNT);
+            }
+            Ok(function) => tracee.print_function_disassembly(&function),
+        },
+        ["disassemble", location, count_str @ ..] if count_str.len() <= 1 => {
+            let count = match count_str
+                .first()
+                .map(|count_str| count_str.parse::<usize>())
+            {
+                None => DEFAULT_DISASSEMBLY_COUNT,
+                Some(Ok(count)) => count,
+                Some(Err(_)) => {
+                    println!("invalid count: \"{}\"", count_str[0]);
+                    return;
+                }
+            };
+            match tracee.resolve_location(location) {
+                Err(err) => println!("{}", err),
+                Ok(address) => tracee.print_disassembly(address, count),
+            }
+        }
+        ["list"] => {
+            let pc = tracee.read_general_purpose_registers().pc;
+            tracee.print_source_listing(pc, DEFAULT_LIST_COUNT);
+        }
+        ["list", location] => match tracee.resolve_location(location) {
+            Err(err) => println!("{}", err),
+            Ok(address) => tracee.print_source_listing(address, DEFAULT_LIST_COUNT),
+        },
+        ["gcore", path_str @ ..] if path_str.len() <= 1 => {
+            let path = match path_str.first() {
+                None => format!("core.{}", tracee.pid()),
+                Some(path_str) => path_str.to_string(),
+            };
+            match write_core(tracee, Path::new(&path)) {
+                Err(err) => println!("failed to write core file {}: {}", path, err),
+                Ok(count) => println!("Saved corefile {} ({} memory segments)", path, count),
+            }
+        }
+        ["minidump", path_str @ ..] if path_str.len() <= 1 => {
+            let path = match path_str.first() {
+                None => format!("minidump.{}.dmp", tracee.pid()),
+                Some(path_str) => path_str.to_string(),
+            };
+            let snapshot = match CoreSnapshot::capture(tracee) {
+                Err(err) => {
+                    println!("failed to capture the process: {}", err);
+                    return;
+                }
+                Ok(snapshot) => snapshot,
+            };
+            save_minidump(&snapshot, &path);
+        }
+        ["ltrace", "off"] => match tracee.stop_tracing_calls(CallTraceKind::Library) {
+            Err(err) => println!("{}", err),
+            Ok(count) => println!("Stopped tracing calls through {} PLT stubs", count),
+        },
+        // Calls that the executable makes through its PLT stubs, or that the matching libraries
+        // do, are printed as they return while the tracee runs.
+        ["ltrace"] => {
+            let path = tracee.executable().display().to_string();
+            trace_library_calls(tracee, &[path]);
+        }
+        ["ltrace", library] => {
+            let paths = find_mapped_objects(tracee, library);
+            if paths.is_empty() {
+                println!("no mapped object matches \"{}\"", library);
+            }
+            trace_library_calls(tracee, &paths);
+        }
+        ["ftrace", "off"] => match tracee.stop_tracing_calls(CallTraceKind::Function) {
+            Err(err) => println!("{}", err),
+            Ok(count) => println!("Stopped tracing calls of {} functions", count),
+        },
+        // The entries and returns of the functions are printed as the tracee runs, with how long
+        // each call took.
+        ["ftrace", names @ ..] if !names.is_empty() => {
+            for name in names {
+                match tracee.trace_function_calls(name) {
+                    Err(err) => println!("{}", err),
+                    Ok(address) => println!("Tracing calls of {} at {:#x}", name, address),
+                }
+            }
+        }
+        ["backtrace" | "bt", "all"] => {
+            let selected_tid = tracee.selected_tid();
+            let tids = tracee
+                .threads()
+                .iter()
+                .map(|thread| thread.tid)
+                .collect::<Vec<libc::pid_t>>();
+            for tid in tids {
+                tracee.select_thread(tid);
+                let name = procfs::read_comm(tid).unwrap_or_default();
+                println!("Thread ({} \"{}\"):", tid, name);
+                print_backtrace(tracee, |_| None);
+                println!();
+            }
+            tracee.select_thread(selected_tid);
+        }
+        ["analyze", "futex"] => {
+            for thread in tracee.threads() {
+                let Some(wait) = inspect_futex_wait(tracee, thread.tid) else {
+                    continue;
+                };
+
+                let owner = match wait.owner_tid {
+                    None => "unknown owner".to_string(),
+                    Some(owner_tid) => format!(
+                        "held by thread ({} \"{}\")",
+                        owner_tid,
+                        procfs::read_comm(owner_tid).unwrap_or_default()
+                    ),
+                };
+                println!(
+                    "Thread ({} \"{}\") waits on futex {:#x} ({}, value {}), {}",
+                    wait.tid,
+                    procfs::read_comm(wait.tid).unwrap_or_default(),
+                    wait.address,
+                    futex_op_name(wait.op),
+                    wait.value,
+                    owner,
+                );
+            }
+        }
+        ["analyze", "deadlock"] => {
+            let deadlocks = find_deadlocks(tracee);
+            if deadlocks.is_empty() {
+                println!("No lock cycles found.");
+            }
+
+            for (i, cycle) in deadlocks.iter().enumerate() {
+                println!("Deadlock #{}:", i + 1);
+                for (j, tid) in cycle.iter().enumerate() {
+                    let owner_tid = cycle[(j + 1) % cycle.len()];
+                    println!(
+                        "  Thread ({} \"{}\") waits on a lock held by thread ({})",
+                        tid,
+                        procfs::read_comm(*tid).unwrap_or_default(),
+                        owner_tid,
+                    );
+                    for (k, frame) in unwind_thread(tracee, *tid).iter().enumerate() {
+                        println!("    #{:<3} {:#018x}", k, frame.pc);
+                    }
+                }
+            }
+        }
+        ["thread", "list"] => {
+            for thread in tracee.threads() {
+                let marker = match thread.tid == tracee.selected_tid() {
+                    true => "*",
+                    false => " ",
+                };
+                let name = procfs::read_comm(thread.tid).unwrap_or_default();
+                println!("{} {} \"{}\"", marker, thread.tid, name);
+            }
+        }
+        ["thread", "list", "--verbose"] => {
+            let ticks_per_second = libc::sysconf(libc::_SC_CLK_TCK) as f64;
+            for thread in tracee.threads() {
+                let marker = match thread.tid == tracee.selected_tid() {
+                    true => "*",
+                    false => " ",
+                };
+                let name = procfs::read_comm(thread.tid).unwrap_or_default();
+                match procfs::read_stat(thread.tid) {
+                    None => println!("{} {} \"{}\" <exited>", marker, thread.tid, name),
+                    Some(stat) => println!(
+                        "{} {} \"{}\" state {} cpu {} utime {:.2}s stime {:.2}s",
+                        marker,
+                        thread.tid,
+                        name,
+                        stat.state,
+                        stat.processor,
+                        stat.utime as f64 / ticks_per_second,
+                        stat.stime as f64 / ticks_per_second,
+                    ),
+                }
+            }
+        }
+        ["thread", "apply", "all", command @ ..] if !command.is_empty() => {
+            let selected_tid = tracee.selected_tid();
+            let tids = tracee
+                .threads()
+                .iter()
+                .map(|thread| thread.tid)
+                .collect::<Vec<libc::pid_t>>();
+            for tid in tids {
+                tracee.select_thread(tid);
+                let name = procfs::read_comm(tid).unwrap_or_default();
+                println!("Thread ({} \"{}\"):", tid, name);
+                handle_command(tracee, &command.join(" "));
+            }
+            tracee.select_thread(selected_tid);
+        }
+        _ => handle_target_command(tracee, line),
+    }
+}
+
+// Inspects a core file until stdin ends. Commands go to the core file as they would to any target,
+// except that backtraces name the functions of the executable.
+pub unsafe fn run_core_session(mut target: CoreTarget) {
+    let snapshot = target.snapshot();
+    println!("Core was generated by `{}`.", snapshot.args.join(" "));
+    if let Some(signal) = snapshot.threads.first().map(|thread| thread.signal) {
+        if signal != 0 {
+            println!("Program terminated with signal {}.", signal_name(signal));
+        }
+    }
+    print_backtrace(&target, |address| target.describe_address(address));
+
+    let mut stdout = stdout();
+    write!(stdout, "pbreak> ").unwrap();
+    stdout.flush().unwrap();
+    for line_result in stdin().lock().lines() {
+        let line = match line_result {
+            Err(err) => {
+                println!("failed to read line from stdin: {}", err);
+                return;
+            }
+            Ok(line) => line,
+        };
+        match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+            ["backtrace" | "bt"] => {
+                print_backtrace(&target, |address| target.describe_address(address));
+            }
+            ["minidump", path_str] => save_minidump(target.snapshot(), path_str),
+            _ => handle_target_command(&mut target, &line),
+        }
+        write!(stdout, "pbreak> ").unwrap();
+        stdout.flush().unwrap();
+    }
+}
+
+fn save_minidump(snapshot: &CoreSnapshot, path: &str) {
+    match write_minidump(snapshot, Path::new(path)) {
+        Err(err) => println!("failed to write minidump {}: {}", path, err),
+        Ok(count) => println!("Saved minidump {} ({} modules)", path, count),
+    }
+}
+
+// Handles a command that works the same on any target, including remote ones.
+pub unsafe fn handle_target_command(target: &mut dyn Target, line: &str) {
+    let words = line.split_whitespace().collect::<Vec<&str>>();
+    match words.as_slice() {
+        ["continue"] => {
+            target.resume();
+            target.wait_on_signal();
+        }
+        ["continue", signal_str] => match parse_signal(signal_str) {
+            None => println!("unknown signal: \"{}\"", signal_str),
+            Some(signal) => {
+                target.resume_with_signal(signal);
+                target.wait_on_signal();
+            }
+        },
+        ["kill"] => {
+            target.kill();
+        }
+        ["readgp"] => {
+            let regs = target.read_general_purpose_registers();
+            dbg!(regs.regs);
+            dbg!(regs.sp);
+            dbg!(regs.pc);
+            dbg!(regs.pstate);
+        }
+        ["writegp"] => {
+            let mut regs = target.read_general_purpose_registers();
+            regs.sp = 99999999;
+            target.write_general_purpose_registers(&mut regs);
+        }
+        ["register", "read", name] => {
+            let regs = target.read_general_purpose_registers();
+            match read_general_purpose_register(&regs, name) {
+                None => println!("unknown register: \"{}\"", name),
+                Some(value) => println!("{} = {:#018x}", name, value),
+            }
+        }
+        ["backtrace" | "bt"] => {
+            print_backtrace(target, |_| None);
+        }
+        ["thread", "list"] => {
+            for tid in target.thread_ids() {
+                let marker = match tid == target.selected_tid() {
+                    true => "*",
+                    false => " ",
+                };
+                println!("{} {}", marker, tid);
+            }
+        }
+        ["disassemble", address_str, count_str @ ..] if count_str.len() <= 1 => {
+            let Some(address) = address_str
+                .strip_prefix("0x")
+                .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+            else {
+                println!("invalid address: \"{}\"", address_str);
+                return;
+            };
+            let count = match count_str
+                .first()
+                .map(|count_str| count_str.parse::<usize>())
+            {
+                None => DEFAULT_DISASSEMBLY_COUNT,
+                Some(Ok(count)) => count,
+                Some(Err(_)) => {
+                    println!("invalid count: \"{}\"", count_str[0]);
+                    return;
+                }
+            };
+            print_disassembly(target, address, count);
+        }
+        ["thread", tid_str] => match tid_str.parse::<libc::pid_t>() {
+            Err(_) => println!("invalid thread id: \"{}\"", tid_str),
+            Ok(tid) => {
+                if !target.select_thread(tid) {
+                    println!("unknown thread id: {}", tid);
+                }
+            }
+        },
+        _ => {
             println!("unexpected command: \"{}\"", line);
         }
     }
 }
+
+// Prints the frames of the selected thread, along with the functions that `describe` finds for
+// their pcs.
+unsafe fn print_backtrace(target: &dyn Target, describe: impl Fn(u64) -> Option<String>) {
+    for (i, frame) in unwind(target).iter().enumerate() {
+        match (frame.is_signal_trampoline, describe(frame.pc)) {
+            (true, _) => println!("#{:<3} {:#018x} <signal handler called>", i, frame.pc),
+            (false, None) => println!("#{:<3} {:#018x}", i, frame.pc),
+            (false, Some(name)) => println!("#{:<3} {:#018x} in {}", i, frame.pc, name),
+        }
+    }
+}
+
+// Single-steps the selected thread until `limit`, writing a line for each instruction that it runs
+// to the file at `path`. It also stops at enabled breakpoints, as it would have if resumed. Returns
+// how many instructions ran.
+unsafe fn trace_instructions(
+    tracee: &mut Tracee,
+    limit: TraceLimit,
+    path: &str,
+    disassemble_steps: bool,
+    show_registers: bool,
+) -> io::Result<usize> {
+    let mut file = BufWriter::new(File::create(path)?);
+    // Loops run the same instructions over and over, which are only disassembled once.
+    let mut texts = HashMap::new();
+    let mut count = 0;
+    let mut result = Ok(());
+    let reports_stops = tracee.reports_stops();
+    tracee.set_reports_stops(false);
+    while tracee.status() == TraceeStatus::Stopped {
+        let regs = tracee.read_general_purpose_registers();
+        match limit {
+            TraceLimit::Count(limit) if count == limit => break,
+            TraceLimit::Until(address) if regs.pc == address => break,
+            _ => {}
+        }
+        if count > 0
+            && tracee
+                .breakpoints()
+                .find(regs.pc)
+                .is_some_and(|breakpoint| breakpoint.is_enabled())
+        {
+            break;
+        }
+        let text = match disassemble_steps {
+            true => Some(
+                texts
+                    .entry(regs.pc)
+                    .or_insert_with(|| read_instruction_text(tracee, regs.pc))
+                    .clone(),
+            ),
+            false => None,
+        };
+
+        let pc = tracee.step_instruction();
+        count += 1;
+        let changed = match (show_registers, pc) {
+            (true, Some(_)) => changed_registers(&regs, &tracee.read_general_purpose_registers()),
+            _ => vec![],
+        };
+        if let Err(err) = writeln!(file, "{}", format_step(regs.pc, text.as_deref(), &changed)) {
+            result = Err(err);
+            break;
+        }
+    }
+    tracee.set_reports_stops(reports_stops);
+    result?;
+    file.flush()?;
+    return Ok(count);
+}
+
+// The assembly of the instruction at `address`, as the program has it.
+unsafe fn read_instruction_text(tracee: &Tracee, address: u64) -> String {
+    let Ok(mut code) = tracee.read_memory(address, MAX_INSTRUCTION_LEN) else {
+        return "<unreadable>".to_string();
+    };
+    tracee.hide_breakpoints(&mut code, address);
+    return match disassemble(&code, address, 1) {
+        Ok(instructions) if !instructions.is_empty() => instructions[0].text.clone(),
+        _ => "(bad)".to_string(),
+    };
+}
+
+// Samples the stacks of the tracee's threads while it runs for the given duration, or until it
+// stops or exits. The tracee is interrupted once the duration is up.
+unsafe fn profile_tracee(tracee: &mut Tracee, duration: Duration) -> Profile {
+    let functions = tracee.read_functions().unwrap_or_default();
+    let mut profile = Profile::new();
+    let deadline = Instant::now() + duration;
+    tracee.resume();
+    while Instant::now() < deadline {
+        thread::sleep(SAMPLE_INTERVAL);
+        if tracee.poll_signal() {
+            return profile;
+        }
+        // Libraries may have been loaded since the last sample.
+        let mappings = tracee.read_maps();
+        for (tid, frames) in tracee.sample_stacks() {
+            // Stacks are rooted at the names of their threads.
+            let mut names = frame_names(&frames, &functions, &mappings);
+            names.push(procfs::read_comm(tid).unwrap_or_default());
+            profile.add_sample(&names);
+        }
+    }
+    tracee.interrupt(None);
+    return profile;
+}
+
+// Prints instructions read straight from the target's memory, without symbols.
+unsafe fn print_disassembly(target: &dyn Target, address: u64, count: usize) {
+    let code = match target.read_memory(address, count * MAX_INSTRUCTION_LEN) {
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+        Ok(code) => code,
+    };
+    let pc = target.read_general_purpose_registers().pc;
+    match disassemble(&code, address, count) {
+        Err(err) => println!("{}", err),
+        Ok(instructions) if instructions.is_empty() => {
+            println!("no valid instruction at {:#x}", address);
+        }
+        Ok(instructions) => {
+            for instruction in instructions {
+                let line =
+                    format_instruction(&instruction, instruction.address == pc, false, |_| {
+                        return None;
+                    });
+                println!("{}", line);
+            }
+        }
+    }
+}
+
+// Prints the functions of the main executable whose names contain the given string, at their
+// runtime addresses.
+// Prints what procfs knows about a process, like its command line and memory usage.
+fn print_process_info(pid: libc::pid_t) {
+    println!("process {}", pid);
+    let Some(status) = procfs::read_status(pid) else {
+        println!("process ({}) no longer exists", pid);
+        return;
+    };
+    if let Some(args) = procfs::read_cmdline(pid) {
+        let args = args
+            .iter()
+            .map(|arg| format!("'{}'", arg))
+            .collect::<Vec<String>>();
+        println!("cmdline = {}", args.join(" "));
+    }
+    if let Some(cwd) = procfs::read_cwd(pid) {
+        println!("cwd = '{}'", cwd.display());
+    }
+    if let Ok(exe) = fs::read_link(format!("/proc/{}/exe", pid)) {
+        println!("exe = '{}'", exe.display());
+    }
+    if let Some(root) = procfs::read_root(pid) {
+        println!(
+            "root = '{}' (another mount namespace or chroot)",
+            root.display()
+        );
+    }
+    if let [_, .., namespace_pid] = status.namespace_pids[..] {
+        println!("pid in namespace: {}", namespace_pid);
+    }
+    println!("state: {}", status.state);
+    let format_ids = |ids: &[u32]| {
+        let names = ["real", "effective", "saved", "fs"];
+        return names
+            .iter()
+            .zip(ids)
+            .map(|(name, id)| format!("{} {}", name, id))
+            .collect::<Vec<String>>()
+            .join(", ");
+    };
+    println!("uid: {}", format_ids(&status.uids));
+    println!("gid: {}", format_ids(&status.gids));
+    if let Some(vm_rss) = status.vm_rss {
+        println!("rss: {} kB", vm_rss);
+    }
+    println!("threads: {}", status.threads);
+}
+
+// Prints the open file descriptors of a process, describing sockets by their addresses and pipes by
+// their ends.
+fn print_open_files(pid: libc::pid_t) {
+    let Some(files) = procfs::read_fds(pid) else {
+        println!("process ({}) no longer exists", pid);
+        return;
+    };
+    let sockets = read_sockets(pid);
+    println!("{:>4} {:>10}  {:<30} target", "fd", "pos", "flags");
+    for file in files {
+        let mut target = file.target.clone();
+        if let Some(inode) = file.inode("socket") {
+            if let Some(socket) = sockets.iter().find(|socket| socket.inode == inode) {
+                target = format!("{} {} {}", target, socket.protocol, socket.local);
+                if let Some(remote) = &socket.remote {
+                    target = format!("{} -> {}", target, remote);
+                }
+                target = format!("{} ({})", target, socket.state);
+            }
+        }
+        if file.inode("pipe").is_some() {
+            let end = match file.flags.map(|flags| flags & libc::O_ACCMODE as u64) {
+                Some(0) => " (read end)",
+                Some(1) => " (write end)",
+                _ => "",
+            };
+            target += end;
+        }
+        let position = file
+            .position
+            .map(|position| position.to_string())
+            .unwrap_or_default();
+        let flags = file.flags.map(format_open_flags).unwrap_or_default();
+        println!("{:>4} {:>10}  {:<30} {}", file.fd, position, flags, target);
+    }
+}
+
+// Prints the sockets that a process has open, with their addresses and states.
+fn print_sockets(pid: libc::pid_t) {
+    let Some(files) = procfs::read_fds(pid) else {
+        println!("process ({}) no longer exists", pid);
+        return;
+    };
+    let sockets = read_sockets(pid);
+    println!(
+        "{:>4}  {:<6} {:<24} {:<24} state",
+        "fd", "proto", "local", "remote"
+    );
+    for file in files {
+        let Some(inode) = file.inode("socket") else {
+            continue;
+        };
+        // Sockets of other families, e.g. netlink, are not in the tables.
+        let Some(socket) = sockets.iter().find(|socket| socket.inode == inode) else {
+            println!(
+                "{:>4}  {:<6} {:<24} {:<24} ?",
+                file.fd, "?", file.target, ""
+            );
+            continue;
+        };
+        println!(
+            "{:>4}  {:<6} {:<24} {:<24} {}",
+            file.fd,
+            socket.protocol,
+            // Unix sockets may have no address.
+            match socket.local.is_empty() {
+                true => "*",
+                false => &socket.local,
+            },
+            socket.remote.as_deref().unwrap_or("*"),
+            socket.state
+        );
+    }
+}
+
+unsafe fn print_functions(tracee: &Tracee, filter: &str) {
+    let functions = match tracee.read_functions() {
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+        Ok(functions) => functions,
+    };
+
+    for function in functions {
+        if function.name.contains(filter) {
+            println!(
+                "{:#018x} {} ({} bytes)",
+                function.address, function.name, function.size,
+            );
+        }
+    }
+}
+
+fn format_watched_value(value: &Result<Value, String>) -> String {
+    return match value {
+        Err(err) => format!("<{}>", err),
+        Ok(value) => value.to_string(),
+    };
+}
+
+// Lists the hardware slots of the given kinds, with what the slots in use are set to. Returns false
+// if the hardware could not be asked how many there are.
+unsafe fn print_debug_slots(tracee: &mut Tracee, kinds: &[SlotKind]) -> bool {
+    let functions = tracee.read_functions().unwrap_or_default();
+    let slots = match tracee.debug_slots() {
+        Err(err) => {
+            println!("{}", err);
+            return false;
+        }
+        Ok(slots) => slots,
+    };
+    for kind in kinds {
+        println!(
+            "Hardware {}s: {} of {} free",
+            kind.name(),
+            slots.free_count(*kind),
+            slots.slots(*kind).len()
+        );
+        for (index, slot) in slots.slots(*kind).iter().enumerate() {
+            let Some(slot) = slot else {
+                continue;
+            };
+            match describe_address(&functions, slot.address) {
+                None => println!("  {}: {:#x}", index, slot.address),
+                Some(function) => println!("  {}: {:#x} in {}", index, slot.address, function),
+            }
+        }
+    }
+    return true;
+}
+
+// Reports where the pc of the selected thread was moved to, without the program having run.
+unsafe fn print_moved_pc(tracee: &Tracee, verb: &str, pc: u64) {
+    let functions = tracee.read_functions().unwrap_or_default();
+    match describe_address(&functions, pc) {
+        None => println!("{} to {:#x}", verb, pc),
+        Some(function) => println!("{} to {:#x} in {}", verb, pc, function),
+    }
+    tracee.print_stop_disassembly();
+}
+
+// What expressions read: the registers of the selected thread as of its stop, if stopped, and the
+// memory of the target, along with the convenience variables and the functions of the inferior.
+struct EvaluationContext<'a> {
+    target: &'a dyn Target,
+    tracee: Option<&'a Tracee>,
+    regs: Option<libc::user_regs_struct>,
+    variables: &'a ConvenienceVariables,
+}
+
+impl ExpressionContext for EvaluationContext<'_> {
+    fn register(&self, name: &str) -> Option<u64> {
+        return read_general_purpose_register(self.regs.as_ref()?, name);
+    }
+
+    fn variable(&self, name: &str) -> Option<Value> {
+        return self.variables.get(name);
+    }
+
+    fn symbol(&self, name: &str) -> Option<u64> {
+        let tracee = self.tracee?;
+        return unsafe { tracee.find_function(name) }
+            .ok()
+            .map(|function| function.address);
+    }
+
+    fn read_memory(&self, address: u64, len: usize) -> Option<Vec<u8>> {
+        // Memory cannot be read while the target runs.
+        self.regs?;
+        return unsafe { self.target.read_memory(address, len) }.ok();
+    }
+}
+
+// Traces the calls that the given mapped objects make through their PLT stubs.
+unsafe fn trace_library_calls(tracee: &mut Tracee, paths: &[String]) {
+    for path in paths {
+        match tracee.trace_library_calls(path) {
+            Err(err) => println!("{}", err),
+            Ok(count) => println!("Tracing calls through {} PLT stubs of {}", count, path),
+        }
+    }
+}
+
+// The paths of the mapped objects that contain `name`, e.g. the libc of "libc".
+unsafe fn find_mapped_objects(tracee: &Tracee, name: &str) -> Vec<String> {
+    let mut paths = tracee
+        .read_maps()
+        .into_iter()
+        .map(|mapping| mapping.path)
+        .filter(|path| path.starts_with('/') && path.contains(name))
+        .collect::<Vec<String>>();
+    paths.dedup();
+    return paths;
+}
+
+// Prints the PLT stubs of the given mapped objects at their runtime addresses, along with the
+// current contents of their GOT slots: the lazy binding trampoline until the call is resolved,
+// and the address of the called function afterwards.
+unsafe fn print_plt(tracee: &Tracee, paths: &[String]) {
+    let mappings = tracee.read_maps();
+    for path in paths {
+        let elf = match ElfFile::read(&tracee.resolve_path(Path::new(path))) {
+            Err(err) => {
+                println!("{}", err);
+                continue;
+            }
+            Ok(elf) => elf,
+        };
+        let (bias, entries) = match (load_bias(&elf, path, &mappings), elf.plt_entries()) {
+            (Err(err), _) | (_, Err(err)) => {
+                println!("{}: {}", path, err);
+                continue;
+            }
+            (Ok(None), _) => {
+                println!("{}: not mapped", path);
+                continue;
+            }
+            (Ok(Some(bias)), Ok(entries)) => (bias, entries),
+        };
+
+        println!("{}:", path);
+        for entry in entries {
+            let got_address = entry.got_address.wrapping_add(bias);
+            let target = match tracee.read_memory(got_address, 8) {
+                Err(_) => "<unreadable>".to_string(),
+                Ok(bytes) => format!("{:#018x}", u64::from_le_bytes(bytes.try_into().unwrap())),
+            };
+            println!(
+                "  {:#018x} {}@plt -> {}",
+                entry.address.wrapping_add(bias),
+                entry.name,
+                target,
+            );
+        }
+    }
+}
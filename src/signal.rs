@@ -0,0 +1,416 @@
+use std::time::Duration;
+
+// Names of the standard signals, indexed by signal number.
+const SIGNAL_NAMES: [&str; 32] = [
+    "",
+    "SIGHUP",
+    "SIGINT",
+    "SIGQUIT",
+    "SIGILL",
+    "SIGTRAP",
+    "SIGABRT",
+    "SIGBUS",
+    "SIGFPE",
+    "SIGKILL",
+    "SIGUSR1",
+    "SIGSEGV",
+    "SIGUSR2",
+    "SIGPIPE",
+    "SIGALRM",
+    "SIGTERM",
+    "SIGSTKFLT",
+    "SIGCHLD",
+    "SIGCONT",
+    "SIGSTOP",
+    "SIGTSTP",
+    "SIGTTIN",
+    "SIGTTOU",
+    "SIGURG",
+    "SIGXCPU",
+    "SIGXFSZ",
+    "SIGVTALRM",
+    "SIGPROF",
+    "SIGWINCH",
+    "SIGIO",
+    "SIGPWR",
+    "SIGSYS",
+];
+
+// Formats a signal number as its name, e.g. "SIGSEGV" or "SIGRTMIN+3".
+pub fn signal_name(signal: libc::c_int) -> String {
+    if signal > 0 && (signal as usize) < SIGNAL_NAMES.len() {
+        return SIGNAL_NAMES[signal as usize].to_string();
+    }
+
+    let sigrtmin = libc::SIGRTMIN();
+    if signal == sigrtmin {
+        return "SIGRTMIN".to_string();
+    }
+    if signal > sigrtmin && signal <= libc::SIGRTMAX() {
+        return format!("SIGRTMIN+{}", signal - sigrtmin);
+    }
+    return format!("signal {}", signal);
+}
+
+// Parses a signal given by name (with or without the "SIG" prefix) or by number.
+pub fn parse_signal(name: &str) -> Option<libc::c_int> {
+    if let Ok(signal) = name.parse::<libc::c_int>() {
+        return match signal > 0 && signal <= libc::SIGRTMAX() {
+            true => Some(signal),
+            false => None,
+        };
+    }
+
+    let name = name.to_ascii_uppercase();
+    let name = match name.starts_with("SIG") {
+        true => name,
+        false => format!("SIG{}", name),
+    };
+    if let Some(index) = SIGNAL_NAMES[1..].iter().position(|known| *known == name) {
+        return Some(index as libc::c_int + 1);
+    }
+
+    let sigrtmin = libc::SIGRTMIN();
+    if name == "SIGRTMIN" {
+        return Some(sigrtmin);
+    }
+    let offset = name
+        .strip_prefix("SIGRTMIN+")?
+        .parse::<libc::c_int>()
+        .ok()?;
+    return match sigrtmin + offset <= libc::SIGRTMAX() {
+        true => Some(sigrtmin + offset),
+        false => None,
+    };
+}
+
+// Values of si_code that any signal may have (see asm-generic/siginfo.h).
+pub(crate) const SI_USER: libc::c_int = 0;
+pub(crate) const SI_KERNEL: libc::c_int = 0x80;
+const SI_QUEUE: libc::c_int = -1;
+const SI_TIMER: libc::c_int = -2;
+const SI_MESGQ: libc::c_int = -3;
+const SI_ASYNCIO: libc::c_int = -4;
+const SI_SIGIO: libc::c_int = -5;
+pub(crate) const SI_TKILL: libc::c_int = -6;
+
+// Names of the signal-specific values of si_code, indexed by si_code - 1.
+const SEGV_CODE_NAMES: [&str; 9] = [
+    "SEGV_MAPERR",
+    "SEGV_ACCERR",
+    "SEGV_BNDERR",
+    "SEGV_PKUERR",
+    "SEGV_ACCADI",
+    "SEGV_ADIDERR",
+    "SEGV_ADIPERR",
+    "SEGV_MTEAERR",
+    "SEGV_MTESERR",
+];
+const BUS_CODE_NAMES: [&str; 5] = [
+    "BUS_ADRALN",
+    "BUS_ADRERR",
+    "BUS_OBJERR",
+    "BUS_MCEERR_AR",
+    "BUS_MCEERR_AO",
+];
+const ILL_CODE_NAMES: [&str; 8] = [
+    "ILL_ILLOPC",
+    "ILL_ILLOPN",
+    "ILL_ILLADR",
+    "ILL_ILLTRP",
+    "ILL_PRVOPC",
+    "ILL_PRVREG",
+    "ILL_COPROC",
+    "ILL_BADSTK",
+];
+const FPE_CODE_NAMES: [&str; 8] = [
+    "FPE_INTDIV",
+    "FPE_INTOVF",
+    "FPE_FLTDIV",
+    "FPE_FLTOVF",
+    "FPE_FLTUND",
+    "FPE_FLTRES",
+    "FPE_FLTINV",
+    "FPE_FLTSUB",
+];
+const TRAP_CODE_NAMES: [&str; 4] = ["TRAP_BRKPT", "TRAP_TRACE", "TRAP_BRANCH", "TRAP_HWBKPT"];
+const CLD_CODE_NAMES: [&str; 6] = [
+    "CLD_EXITED",
+    "CLD_KILLED",
+    "CLD_DUMPED",
+    "CLD_TRAPPED",
+    "CLD_STOPPED",
+    "CLD_CONTINUED",
+];
+
+// Names the value of si_code, which tells why a signal was sent.
+pub fn signal_code_name(signal: libc::c_int, code: libc::c_int) -> Option<&'static str> {
+    match code {
+        SI_USER => return Some("SI_USER"),
+        SI_KERNEL => return Some("SI_KERNEL"),
+        SI_QUEUE => return Some("SI_QUEUE"),
+        SI_TIMER => return Some("SI_TIMER"),
+        SI_MESGQ => return Some("SI_MESGQ"),
+        SI_ASYNCIO => return Some("SI_ASYNCIO"),
+        SI_SIGIO => return Some("SI_SIGIO"),
+        SI_TKILL => return Some("SI_TKILL"),
+        _ => {}
+    }
+
+    let names: &[&str] = match signal {
+        libc::SIGSEGV => &SEGV_CODE_NAMES,
+        libc::SIGBUS => &BUS_CODE_NAMES,
+        libc::SIGILL => &ILL_CODE_NAMES,
+        libc::SIGFPE => &FPE_CODE_NAMES,
+        libc::SIGTRAP => &TRAP_CODE_NAMES,
+        libc::SIGCHLD => &CLD_CODE_NAMES,
+        _ => &[],
+    };
+    return names.get((code as usize).wrapping_sub(1)).copied();
+}
+
+// Describes a siginfo, one field per line. Which fields are meaningful depends on the signal and
+// on how it was sent.
+pub unsafe fn format_siginfo(info: &libc::siginfo_t) -> Vec<String> {
+    let mut lines = vec![
+        format!("signal: {} ({})", signal_name(info.si_signo), info.si_signo),
+        format!(
+            "code: {} ({})",
+            signal_code_name(info.si_signo, info.si_code).unwrap_or("?"),
+            info.si_code
+        ),
+    ];
+    if info.si_errno != 0 {
+        lines.push(format!("errno: {}", info.si_errno));
+    }
+
+    match (info.si_signo, info.si_code) {
+        (_, SI_USER | SI_QUEUE | SI_TKILL) => {
+            lines.push(format!("sender pid: {}", info.si_pid()));
+            lines.push(format!("sender uid: {}", info.si_uid()));
+        }
+        (libc::SIGCHLD, _) => {
+            lines.push(format!("child pid: {}", info.si_pid()));
+            lines.push(format!("child uid: {}", info.si_uid()));
+            lines.push(format!("child status: {}", info.si_status()));
+        }
+        (libc::SIGSEGV | libc::SIGBUS | libc::SIGILL | libc::SIGFPE | libc::SIGTRAP, _) => {
+            lines.push(format!("address: {:#x}", info.si_addr() as u64));
+        }
+        _ => {}
+    }
+    return lines;
+}
+
+// Values of si_code for SIGTRAP.
+const TRAP_BRKPT: libc::c_int = 1;
+const TRAP_TRACE: libc::c_int = 2;
+const TRAP_BRANCH: libc::c_int = 3;
+const TRAP_HWBKPT: libc::c_int = 4;
+
+// What caused a SIGTRAP.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Trap {
+    // A software breakpoint instruction at the given address. Unlike x86's INT3, AArch64's BRK
+    // leaves the PC at the instruction itself, so the address needs no adjustment.
+    Breakpoint { address: u64 },
+    // A single-step finished.
+    SingleStep,
+    // A branch was taken while branch tracing.
+    Branch,
+    // A hardware breakpoint at the given address, or a watchpoint on the given data address.
+    HardwareBreakpoint { address: u64 },
+    // The signal was sent by a process, e.g. with raise(SIGTRAP).
+    Sent,
+    // Something else, such as an exec without PTRACE_O_TRACEEXEC, with the given si_code.
+    Other(libc::c_int),
+}
+
+// Classifies a SIGTRAP by its si_code, so that breakpoints can be told apart from other traps.
+pub unsafe fn classify_trap(info: &libc::siginfo_t) -> Trap {
+    return match info.si_code {
+        TRAP_BRKPT => Trap::Breakpoint {
+            address: info.si_addr() as u64,
+        },
+        TRAP_TRACE => Trap::SingleStep,
+        TRAP_BRANCH => Trap::Branch,
+        TRAP_HWBKPT => Trap::HardwareBreakpoint {
+            address: info.si_addr() as u64,
+        },
+        SI_USER | SI_QUEUE | SI_TKILL => Trap::Sent,
+        code => Trap::Other(code),
+    };
+}
+
+// How the debugger reacts when the tracee receives a signal.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct SignalDisposition {
+    // Whether the tracee stops so that it can be inspected.
+    pub stop: bool,
+    // Whether the signal is delivered to the tracee once it is resumed.
+    pub pass: bool,
+    // Whether the signal is reported.
+    pub print: bool,
+}
+
+impl SignalDisposition {
+    // Applies a keyword of the `handle` command, e.g. "nostop". Returns false if it is unknown.
+    pub fn apply_keyword(&mut self, keyword: &str) -> bool {
+        match keyword {
+            "stop" => {
+                // Stopping without saying why would be confusing.
+                self.stop = true;
+                self.print = true;
+            }
+            "nostop" => self.stop = false,
+            "print" => self.print = true,
+            "noprint" => {
+                self.print = false;
+                self.stop = false;
+            }
+            "pass" => self.pass = true,
+            "nopass" => self.pass = false,
+            _ => return false,
+        }
+        return true;
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct SignalDispositions {
+    // Indexed by signal number, up to SIGRTMAX.
+    dispositions: Vec<SignalDisposition>,
+}
+
+impl SignalDispositions {
+    pub fn new() -> SignalDispositions {
+        let mut dispositions = vec![
+            SignalDisposition {
+                stop: true,
+                pass: true,
+                print: true,
+            };
+            libc::SIGRTMAX() as usize + 1
+        ];
+
+        // Signals that programs routinely use for themselves.
+        for signal in [
+            libc::SIGALRM,
+            libc::SIGURG,
+            libc::SIGCHLD,
+            libc::SIGWINCH,
+            libc::SIGIO,
+            libc::SIGVTALRM,
+            libc::SIGPROF,
+        ] {
+            dispositions[signal as usize].stop = false;
+            dispositions[signal as usize].print = false;
+        }
+
+        // Signals that are usually caused by the debugger itself.
+        dispositions[libc::SIGINT as usize].pass = false;
+        dispositions[libc::SIGTRAP as usize].pass = false;
+
+        return SignalDispositions { dispositions };
+    }
+
+    pub fn get(&self, signal: libc::c_int) -> SignalDisposition {
+        return match self.dispositions.get(signal as usize) {
+            None => SignalDisposition {
+                stop: true,
+                pass: true,
+                print: true,
+            },
+            Some(disposition) => *disposition,
+        };
+    }
+
+    pub fn set(&mut self, signal: libc::c_int, disposition: SignalDisposition) {
+        if let Some(entry) = self.dispositions.get_mut(signal as usize) {
+            *entry = disposition;
+        }
+    }
+}
+
+impl Default for SignalDispositions {
+    fn default() -> SignalDispositions {
+        return SignalDispositions::new();
+    }
+}
+
+// A signal that the tracee received, along with how it was handled.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct ReceivedSignal {
+    // The time since the tracee was attached to.
+    pub elapsed: Duration,
+    pub tid: libc::pid_t,
+    pub signal: libc::c_int,
+    pub stopped: bool,
+    pub passed: bool,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        classify_trap, parse_signal, signal_code_name, signal_name, SignalDispositions, Trap,
+    };
+
+    #[test]
+    fn signal_name_formats_standard_and_realtime_signals() {
+        assert_eq!(signal_name(libc::SIGSEGV), "SIGSEGV");
+        assert_eq!(signal_name(libc::SIGRTMIN() + 3), "SIGRTMIN+3");
+        assert_eq!(signal_name(0), "signal 0");
+    }
+
+    #[test]
+    fn parse_signal_accepts_names_and_numbers() {
+        assert_eq!(parse_signal("SIGUSR1"), Some(libc::SIGUSR1));
+        assert_eq!(parse_signal("usr1"), Some(libc::SIGUSR1));
+        assert_eq!(parse_signal("9"), Some(libc::SIGKILL));
+        assert_eq!(parse_signal("SIGRTMIN+2"), Some(libc::SIGRTMIN() + 2));
+        assert_eq!(parse_signal("SIGFOO"), None);
+        assert_eq!(parse_signal("0"), None);
+    }
+
+    #[test]
+    fn signal_dispositions_default_to_stopping_except_for_routine_signals() {
+        let dispositions = SignalDispositions::new();
+        assert!(dispositions.get(libc::SIGSEGV).stop);
+        assert!(dispositions.get(libc::SIGSEGV).pass);
+        assert!(!dispositions.get(libc::SIGCHLD).stop);
+        assert!(!dispositions.get(libc::SIGTRAP).pass);
+    }
+
+    #[test]
+    fn signal_disposition_keywords_keep_stop_and_print_consistent() {
+        let mut disposition = SignalDispositions::new().get(libc::SIGUSR1);
+        assert!(disposition.apply_keyword("noprint"));
+        assert!(!disposition.stop);
+        assert!(disposition.apply_keyword("stop"));
+        assert!(disposition.print);
+        assert!(!disposition.apply_keyword("sometimes"));
+    }
+
+    #[test]
+    fn signal_code_name_depends_on_signal() {
+        assert_eq!(signal_code_name(libc::SIGSEGV, 1), Some("SEGV_MAPERR"));
+        assert_eq!(signal_code_name(libc::SIGBUS, 1), Some("BUS_ADRALN"));
+        assert_eq!(signal_code_name(libc::SIGSEGV, -6), Some("SI_TKILL"));
+        assert_eq!(signal_code_name(libc::SIGUSR1, 1), None);
+        assert_eq!(signal_code_name(libc::SIGSEGV, 42), None);
+    }
+
+    #[test]
+    fn classify_trap_uses_si_code() {
+        unsafe {
+            let mut info = std::mem::zeroed::<libc::siginfo_t>();
+            info.si_signo = libc::SIGTRAP;
+            info.si_code = 2;
+            assert_eq!(classify_trap(&info), Trap::SingleStep);
+            info.si_code = -6;
+            assert_eq!(classify_trap(&info), Trap::Sent);
+            info.si_code = 1;
+            assert!(matches!(classify_trap(&info), Trap::Breakpoint { .. }));
+        }
+    }
+}
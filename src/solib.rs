@@ -0,0 +1,203 @@
+// The dynamic linker's rendezvous structure, `struct r_debug`, through which debuggers learn which
+// shared objects a program has loaded. The dynamic linker points DT_DEBUG in the executable's
+// dynamic section at it once it starts, and keeps its list of loaded objects, the link map, there.
+//
+// Memory is read through `read_memory`, which returns None where it cannot be read, so that the
+// structures can be read from any target.
+
+const DT_NULL: u64 = 0;
+const DT_DEBUG: u64 = 21;
+
+// Bounds on how far the structures are followed, in case they are corrupt.
+const MAX_DYNAMIC_ENTRIES: usize = 4096;
+const MAX_LINK_MAP_LEN: usize = 4096;
+
+// The sizes of `struct r_debug` and of the leading part of `struct link_map` that is public.
+const RENDEZVOUS_SIZE: usize = 40;
+const LINK_MAP_ENTRY_SIZE: usize = 40;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum RendezvousState {
+    // The link map is consistent.
+    Consistent,
+    // An object is being added to the link map.
+    Adding,
+    // An object is being removed from the link map.
+    Deleting,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Rendezvous {
+    pub version: i32,
+    // The first entry of the link map, which is the executable's.
+    pub map: u64,
+    // The function that the dynamic linker calls before and after it changes the link map, which
+    // debuggers break at to be told.
+    pub brk: u64,
+    pub state: RendezvousState,
+    // Where the dynamic linker was loaded.
+    pub ldbase: u64,
+}
+
+// An object in the link map.
+#[derive(PartialEq, Clone, Debug)]
+pub struct LinkMapEntry {
+    // The load bias of the object.
+    pub base: u64,
+    // The path that the dynamic linker opened the object at, which is empty for the executable.
+    pub name: String,
+    // The address of the object's dynamic section.
+    pub dynamic: u64,
+}
+
+// Finds the address of the rendezvous structure through DT_DEBUG, given where the executable's
+// dynamic section is. Returns None until the dynamic linker has filled it in, and for executables
+// that are linked statically.
+pub fn find_rendezvous(
+    read_memory: impl Fn(u64, usize) -> Option<Vec<u8>>,
+    dynamic: u64,
+) -> Option<u64> {
+    for i in 0..MAX_DYNAMIC_ENTRIES {
+        let entry = read_memory(dynamic + i as u64 * 16, 16)?;
+        let tag = word_at(&entry, 0);
+        let value = word_at(&entry, 8);
+        match tag {
+            DT_NULL => return None,
+            DT_DEBUG => return (value != 0).then_some(value),
+            _ => continue,
+        }
+    }
+    return None;
+}
+
+pub fn read_rendezvous(
+    read_memory: impl Fn(u64, usize) -> Option<Vec<u8>>,
+    address: u64,
+) -> Option<Rendezvous> {
+    let bytes = read_memory(address, RENDEZVOUS_SIZE)?;
+    let state = match word_at(&bytes, 24) as u32 {
+        0 => RendezvousState::Consistent,
+        1 => RendezvousState::Adding,
+        2 => RendezvousState::Deleting,
+        _ => return None,
+    };
+    return Some(Rendezvous {
+        version: word_at(&bytes, 0) as u32 as i32,
+        map: word_at(&bytes, 8),
+        brk: word_at(&bytes, 16),
+        state,
+        ldbase: word_at(&bytes, 32),
+    });
+}
+
+// Reads the link map from its first entry, in load order. Names are read with `read_string`.
+pub fn read_link_map(
+    read_memory: impl Fn(u64, usize) -> Option<Vec<u8>>,
+    read_string: impl Fn(u64) -> Option<String>,
+    first: u64,
+) -> Vec<LinkMapEntry> {
+    let mut entries = vec![];
+    let mut address = first;
+    while address != 0 && entries.len() < MAX_LINK_MAP_LEN {
+        let Some(bytes) = read_memory(address, LINK_MAP_ENTRY_SIZE) else {
+            break;
+        };
+        let name_address = word_at(&bytes, 8);
+        let name = match name_address {
+            0 => String::new(),
+            _ => read_string(name_address).unwrap_or_default(),
+        };
+        entries.push(LinkMapEntry {
+            base: word_at(&bytes, 0),
+            name,
+            dynamic: word_at(&bytes, 16),
+        });
+        address = word_at(&bytes, 24);
+    }
+    return entries;
+}
+
+fn word_at(bytes: &[u8], offset: usize) -> u64 {
+    return u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{find_rendezvous, read_link_map, read_rendezvous, Rendezvous, RendezvousState};
+
+    // Memory made of words at the given addresses.
+    fn memory(words: &[(u64, u64)]) -> impl Fn(u64, usize) -> Option<Vec<u8>> {
+        let words = words.iter().copied().collect::<HashMap<u64, u64>>();
+        return move |address, len| {
+            return (0..len as u64)
+                .step_by(8)
+                .map(|offset| {
+                    words
+                        .get(&(address + offset))
+                        .map(|word| word.to_le_bytes())
+                })
+                .collect::<Option<Vec<[u8; 8]>>>()
+                .map(|words| words.concat());
+        };
+    }
+
+    #[test]
+    fn find_rendezvous_reads_dt_debug() {
+        let dynamic = [(0x1000, 1), (0x1008, 0x10), (0x1010, 21), (0x1018, 0x2000)];
+        assert_eq!(find_rendezvous(memory(&dynamic), 0x1000), Some(0x2000));
+        let unset = [(0x1000, 21), (0x1008, 0), (0x1010, 0), (0x1018, 0)];
+        assert_eq!(find_rendezvous(memory(&unset), 0x1000), None);
+        let missing = [(0x1000, 1), (0x1008, 0x10), (0x1010, 0), (0x1018, 0)];
+        assert_eq!(find_rendezvous(memory(&missing), 0x1000), None);
+    }
+
+    #[test]
+    fn read_link_map_follows_entries_from_the_rendezvous() {
+        let memory = memory(&[
+            // r_debug
+            (0x2000, 1),
+            (0x2008, 0x3000),
+            (0x2010, 0x7f0010),
+            (0x2018, 0),
+            (0x2020, 0x7f0000),
+            // The executable's entry, which has no name.
+            (0x3000, 0x400000),
+            (0x3008, 0),
+            (0x3010, 0x410000),
+            (0x3018, 0x3100),
+            (0x3020, 0),
+            // A library's entry.
+            (0x3100, 0x7e0000),
+            (0x3108, 0x5000),
+            (0x3110, 0x7e8000),
+            (0x3118, 0),
+            (0x3120, 0x3000),
+        ]);
+        let rendezvous = read_rendezvous(&memory, 0x2000).unwrap();
+        assert_eq!(
+            rendezvous,
+            Rendezvous {
+                version: 1,
+                map: 0x3000,
+                brk: 0x7f0010,
+                state: RendezvousState::Consistent,
+                ldbase: 0x7f0000,
+            }
+        );
+
+        let read_string = |address| (address == 0x5000).then(|| "/lib/libc.so.6".to_string());
+        let entries = read_link_map(&memory, read_string, rendezvous.map);
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (entry.base, entry.name.as_str(), entry.dynamic))
+                .collect::<Vec<(u64, &str, u64)>>(),
+            vec![
+                (0x400000, "", 0x410000),
+                (0x7e0000, "/lib/libc.so.6", 0x7e8000)
+            ]
+        );
+    }
+}
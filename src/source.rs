@@ -0,0 +1,395 @@
+// Finds the source files that debug info names, which may not be where they were compiled. Paths
+// are first rewritten by the substitutions of the session, e.g. for a project that was built in a
+// container, and missing files are then fetched from debuginfod servers by the build ID of the
+// executable. Fetched files are kept in a cache directory, so each is only downloaded once.
+//
+// Servers are spoken to over plain HTTP here. Those of https:// URLs are left to debuginfod-find of
+// elfutils, which is installed wherever such servers are set up, rather than building TLS in.
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    env, fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+// How long a server may take to accept a connection, and then to send each part of a response.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Sources bigger than this are not downloaded.
+const MAX_SOURCE_LEN: u64 = 64 * 1024 * 1024;
+
+#[derive(PartialEq, Clone, Default, Debug)]
+pub struct SourceSettings {
+    // Prefixes of source paths and what to replace them with, tried in order.
+    pub substitutions: Vec<(String, String)>,
+    // The base URLs of the debuginfod servers to fetch missing sources from.
+    pub debuginfod_urls: Vec<String>,
+}
+
+impl SourceSettings {
+    // The settings that the environment asks for, i.e. the servers of DEBUGINFOD_URLS.
+    pub fn from_env() -> SourceSettings {
+        let urls = env::var("DEBUGINFOD_URLS").unwrap_or_default();
+        return SourceSettings {
+            substitutions: vec![],
+            debuginfod_urls: parse_debuginfod_urls(&urls),
+        };
+    }
+}
+
+pub struct SourceFinder {
+    settings: SourceSettings,
+    // The files that could not be fetched, by build ID and path, which are not asked for again.
+    missing: RefCell<HashSet<(String, String)>>,
+}
+
+impl SourceFinder {
+    pub fn new(settings: SourceSettings) -> SourceFinder {
+        return SourceFinder {
+            settings,
+            missing: RefCell::new(HashSet::new()),
+        };
+    }
+
+    pub fn settings(&self) -> &SourceSettings {
+        return &self.settings;
+    }
+
+    // Reads the lines of a source file that debug info names, looking for it locally and then on
+    // the debuginfod servers. Those need the build ID of the file that the debug info is in, which
+    // is only read if the source is not found locally.
+    pub fn read_lines(
+        &self,
+        path: &str,
+        build_id: impl FnOnce() -> Option<Vec<u8>>,
+    ) -> Option<Vec<String>> {
+        let read_lines = |path: &str| {
+            return fs::read_to_string(path)
+                .ok()
+                .map(|source| source.lines().map(str::to_string).collect());
+        };
+        if let Some(path) = substitute_path(&self.settings.substitutions, path) {
+            if let Some(lines) = read_lines(&path) {
+                return Some(lines);
+            }
+        }
+        if let Some(lines) = read_lines(path) {
+            return Some(lines);
+        }
+
+        // Servers only have the sources of absolute paths.
+        if self.settings.debuginfod_urls.is_empty() || !path.starts_with('/') {
+            return None;
+        }
+        let build_id = format_build_id(&build_id()?);
+        let key = (build_id, path.to_string());
+        if self.missing.borrow().contains(&key) {
+            return None;
+        }
+        let cache_path = cache_path(&key.0, path)?;
+        if let Some(lines) = read_lines(&cache_path.display().to_string()) {
+            return Some(lines);
+        }
+
+        for url in &self.settings.debuginfod_urls {
+            let Ok(source) = fetch_source(url, &key.0, path) else {
+                continue;
+            };
+            // The cache is only an optimization, so failing to write it is fine.
+            if let Some(directory) = cache_path.parent() {
+                let _ = fs::create_dir_all(directory).and_then(|_| fs::write(&cache_path, &source));
+            }
+            let source = String::from_utf8_lossy(&source);
+            return Some(source.lines().map(str::to_string).collect());
+        }
+        self.missing.borrow_mut().insert(key);
+        return None;
+    }
+}
+
+// Splits the value of DEBUGINFOD_URLS, which separates URLs with spaces.
+pub fn parse_debuginfod_urls(urls: &str) -> Vec<String> {
+    return urls
+        .split_whitespace()
+        .map(|url| url.trim_end_matches('/').to_string())
+        .collect();
+}
+
+// Rewrites a path by the first substitution whose prefix it starts with. Prefixes only match
+// whole components, so "/build" does not rewrite "/builds/main.c".
+pub fn substitute_path(substitutions: &[(String, String)], path: &str) -> Option<String> {
+    return substitutions.iter().find_map(|(from, to)| {
+        let from = from.trim_end_matches('/');
+        let rest = path.strip_prefix(from)?;
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+        return Some(format!("{}{}", to.trim_end_matches('/'), rest));
+    });
+}
+
+pub fn format_build_id(build_id: &[u8]) -> String {
+    return build_id
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+}
+
+// Where a fetched source file is kept, e.g. ~/.cache/pbreak/sources/<build ID>/usr/src/main.c.
+fn cache_path(build_id: &str, path: &str) -> Option<PathBuf> {
+    let cache_home = match env::var_os("XDG_CACHE_HOME") {
+        Some(cache_home) if !cache_home.is_empty() => PathBuf::from(cache_home),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    // Components like ".." would escape the cache.
+    let components = path
+        .split('/')
+        .filter(|component| !component.is_empty() && *component != "." && *component != "..");
+    let mut cache_path = cache_home.join("pbreak").join("sources").join(build_id);
+    for component in components {
+        cache_path.push(component);
+    }
+    return Some(cache_path);
+}
+
+// Fetches a source file of the executable with the build ID from a debuginfod server.
+fn fetch_source(url: &str, build_id: &str, path: &str) -> io::Result<Vec<u8>> {
+    let unsupported = |message: &str| io::Error::new(io::ErrorKind::Unsupported, message);
+    if url.starts_with("https://") {
+        return fetch_source_with_client(url, build_id, path);
+    }
+    let Some(url) = url.strip_prefix("http://") else {
+        return Err(unsupported(
+            "only http:// and https:// servers are supported",
+        ));
+    };
+    let (host, base_path) = match url.find('/') {
+        None => (url, ""),
+        Some(i) => url.split_at(i),
+    };
+    let address = match host.contains(':') {
+        true => host.to_string(),
+        false => format!("{}:80", host),
+    };
+    let request_path = format!(
+        "{}/buildid/{}/source{}",
+        base_path,
+        build_id,
+        escape_url_path(path)
+    );
+    return http_get(&address, host, &request_path);
+}
+
+// Fetches a source file with debuginfod-find, which downloads it into a cache of its own and prints
+// where. It is only asked to try the given server, and within the usual timeout unless the
+// environment sets one.
+fn fetch_source_with_client(url: &str, build_id: &str, path: &str) -> io::Result<Vec<u8>> {
+    let mut command = Command::new("debuginfod-find");
+    command
+        .args(["source", build_id, path])
+        .env("DEBUGINFOD_URLS", url)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null());
+    if env::var_os("DEBUGINFOD_TIMEOUT").is_none() {
+        command.env("DEBUGINFOD_TIMEOUT", READ_TIMEOUT.as_secs().to_string());
+    }
+    let output = command.output().map_err(|err| {
+        return io::Error::new(
+            err.kind(),
+            format!("https:// servers need debuginfod-find: {}", err),
+        );
+    })?;
+    if !output.status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "debuginfod-find did not find the source",
+        ));
+    }
+    let fetched_path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if fs::metadata(&fetched_path)?.len() > MAX_SOURCE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "source file too large",
+        ));
+    }
+    return fs::read(fetched_path);
+}
+
+// Percent-encodes a path for a URL, keeping its slashes.
+pub fn escape_url_path(path: &str) -> String {
+    let mut escaped = String::new();
+    for byte in path.bytes() {
+        match byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            true => escaped.push(byte as char),
+            false => escaped += &format!("%{:02X}", byte),
+        }
+    }
+    return escaped;
+}
+
+// Sends a GET request of HTTP/1.0, which the server answers without chunking and then closes the
+// connection, and returns the body of a successful response.
+fn http_get(address: &str, host: &str, path: &str) -> io::Result<Vec<u8>> {
+    let socket_address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or(io::Error::from(io::ErrorKind::AddrNotAvailable))?;
+    let mut stream = TcpStream::connect_timeout(&socket_address, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    stream.write_all(format!("GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, host).as_bytes())?;
+    return read_http_response(&mut BufReader::new(stream));
+}
+
+// Reads the body of a response, failing unless its status is 200.
+pub fn read_http_response(reader: &mut impl BufRead) -> io::Result<Vec<u8>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line.split_whitespace().nth(1);
+    if status != Some("200") {
+        return Err(invalid(format!(
+            "unexpected response: {}",
+            status_line.trim_end()
+        )));
+    }
+
+    let mut content_len = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Err(invalid("unexpected end of headers".to_string()));
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_len = value.trim().parse::<u64>().ok();
+            }
+        }
+    }
+
+    let mut body = vec![];
+    match content_len {
+        Some(len) if len > MAX_SOURCE_LEN => {
+            return Err(invalid("source file too large".to_string()));
+        }
+        Some(len) => {
+            body.resize(len as usize, 0);
+            reader.read_exact(&mut body)?;
+        }
+        None => {
+            reader.take(MAX_SOURCE_LEN).read_to_end(&mut body)?;
+        }
+    }
+    return Ok(body);
+}
+
+// Lists source lines around a line, which is marked with an arrow.
+pub fn format_listing(lines: &[String], line: u64, count: usize) -> Vec<String> {
+    let first_line = (line as usize)
+        .saturating_sub(count.saturating_sub(1) / 2)
+        .max(1);
+    return lines
+        .iter()
+        .enumerate()
+        .skip(first_line - 1)
+        .take(count)
+        .map(|(i, text)| {
+            let marker = match i + 1 == line as usize {
+                true => "=>",
+                false => "  ",
+            };
+            return format!("{} {:>5}  {}", marker, i + 1, text);
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Cursor};
+
+    use super::{
+        escape_url_path, fetch_source, format_listing, parse_debuginfod_urls, read_http_response,
+        substitute_path,
+    };
+
+    #[test]
+    fn substitute_path_replaces_whole_components() {
+        let substitutions = vec![
+            ("/build/".to_string(), "/home/me/src".to_string()),
+            ("/usr/src/debug".to_string(), "/tmp/debug".to_string()),
+        ];
+        assert_eq!(
+            substitute_path(&substitutions, "/build/app/main.c"),
+            Some("/home/me/src/app/main.c".to_string())
+        );
+        assert_eq!(
+            substitute_path(&substitutions, "/usr/src/debug/glibc/malloc.c"),
+            Some("/tmp/debug/glibc/malloc.c".to_string())
+        );
+        assert_eq!(substitute_path(&substitutions, "/builds/main.c"), None);
+        assert_eq!(substitute_path(&substitutions, "main.c"), None);
+    }
+
+    #[test]
+    fn parse_debuginfod_urls_splits_on_spaces() {
+        assert_eq!(
+            parse_debuginfod_urls(" http://a:8002/  http://b/debuginfod "),
+            vec!["http://a:8002", "http://b/debuginfod"]
+        );
+        assert!(parse_debuginfod_urls("").is_empty());
+    }
+
+    #[test]
+    fn fetch_source_rejects_unknown_schemes() {
+        let err = fetch_source("ftp://example.com", "abcd", "/src/main.c").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn escape_url_path_keeps_slashes() {
+        assert_eq!(
+            escape_url_path("/usr/src/my file+1.c"),
+            "/usr/src/my%20file%2B1.c"
+        );
+    }
+
+    #[test]
+    fn read_http_response_reads_successful_bodies_only() {
+        let response = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nint xtra";
+        assert_eq!(
+            read_http_response(&mut Cursor::new(response)).unwrap(),
+            b"int x"
+        );
+        let response = "HTTP/1.0 200 OK\r\nServer: test\r\n\r\nint x;\n";
+        assert_eq!(
+            read_http_response(&mut Cursor::new(response)).unwrap(),
+            b"int x;\n"
+        );
+        let response = "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n";
+        assert!(read_http_response(&mut Cursor::new(response)).is_err());
+    }
+
+    #[test]
+    fn format_listing_centers_on_line() {
+        let lines = (1..=10)
+            .map(|i| format!("line {}", i))
+            .collect::<Vec<String>>();
+        assert_eq!(
+            format_listing(&lines, 5, 3),
+            vec!["       4  line 4", "=>     5  line 5", "       6  line 6"]
+        );
+        assert_eq!(
+            format_listing(&lines, 1, 3),
+            vec!["=>     1  line 1", "       2  line 2", "       3  line 3"]
+        );
+    }
+}
@@ -0,0 +1,227 @@
+// Indexes the functions and line tables of the files that a process has mapped: its executable and
+// its shared libraries. Parsing them takes most of the time to reach the prompt for a large
+// program, so files are indexed on worker threads in parallel, and the functions and line table of
+// each file are read in parallel too. Addresses are kept as the files give them, so an index stays
+// valid wherever the files are mapped.
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use crate::{
+    dwarf::{read_line_rows, DwarfError, LineRow},
+    elf::{ElfError, ElfFile, FunctionSymbol, LoadLayout},
+    procfs::MemoryMapping,
+};
+
+#[derive(Default)]
+pub struct SymbolIndex {
+    objects: Vec<ObjectIndex>,
+}
+
+// What is indexed of a file, or why it could not be.
+pub struct ObjectIndex {
+    // The path of the file as the process maps it.
+    pub path: String,
+    pub layout: Result<LoadLayout, ElfError>,
+    pub functions: Result<Vec<FunctionSymbol>, ElfError>,
+    pub line_rows: Result<Vec<LineRow>, DwarfError>,
+}
+
+impl SymbolIndex {
+    // Indexes the files that are not indexed yet. Each is given by its path as the process maps
+    // it, along with the path that the debugger reads it from. Returns the paths of the files that
+    // were indexed now.
+    pub fn add(&mut self, files: Vec<(String, PathBuf)>) -> Vec<String> {
+        let files = files
+            .into_iter()
+            .filter(|(path, _)| self.object(path).is_none())
+            .collect::<Vec<(String, PathBuf)>>();
+        self.objects.extend(index_files(&files));
+        return files.into_iter().map(|(path, _)| path).collect();
+    }
+
+    // Drops the files that are no longer mapped, e.g. libraries unloaded with dlclose(), so that
+    // they are indexed afresh if they are mapped again. Returns their paths.
+    pub fn remove_unmapped(&mut self, mapped_paths: &[String]) -> Vec<String> {
+        let removed = self
+            .objects
+            .iter()
+            .filter(|object| !mapped_paths.contains(&object.path))
+            .map(|object| object.path.clone())
+            .collect::<Vec<String>>();
+        self.objects
+            .retain(|object| mapped_paths.contains(&object.path));
+        return removed;
+    }
+
+    pub fn object(&self, path: &str) -> Option<&ObjectIndex> {
+        return self.objects.iter().find(|object| object.path == path);
+    }
+
+    pub fn objects(&self) -> &[ObjectIndex] {
+        return &self.objects;
+    }
+}
+
+impl ObjectIndex {
+    fn read(path: &str, read_path: &Path) -> ObjectIndex {
+        let elf = match ElfFile::read(read_path) {
+            Err(err) => {
+                return ObjectIndex {
+                    path: path.to_string(),
+                    layout: Err(err.clone()),
+                    functions: Err(err.clone()),
+                    line_rows: Err(DwarfError::Elf(err)),
+                };
+            }
+            Ok(elf) => elf,
+        };
+        return thread::scope(|scope| {
+            let line_rows = scope.spawn(|| read_line_rows(&elf));
+            let functions = elf.function_symbols();
+            return ObjectIndex {
+                path: path.to_string(),
+                layout: elf.load_layout(),
+                functions,
+                line_rows: line_rows.join().unwrap(),
+            };
+        });
+    }
+
+    // Computes the load bias of the file in a process with the given memory mappings. Returns None
+    // if the file is not mapped.
+    pub fn load_bias(&self, mappings: &[MemoryMapping]) -> Result<Option<u64>, ElfError> {
+        return Ok(self.layout.clone()?.load_bias(&self.path, mappings));
+    }
+
+    // Computes the load bias of the file from the entry point of the program that it is, i.e.
+    // AT_ENTRY.
+    pub fn load_bias_from_entry(&self, entry: u64) -> Result<u64, ElfError> {
+        return Ok(self.layout.clone()?.load_bias_from_entry(entry));
+    }
+}
+
+// Indexes files on as many worker threads as there are CPUs, each of which takes the next file
+// until none are left. Returns the indexes in the order of the files.
+fn index_files(files: &[(String, PathBuf)]) -> Vec<ObjectIndex> {
+    let n_workers = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(files.len());
+    let next_file = AtomicUsize::new(0);
+    let indexed = Mutex::new(Vec::with_capacity(files.len()));
+    thread::scope(|scope| {
+        for _ in 0..n_workers {
+            scope.spawn(|| loop {
+                let i = next_file.fetch_add(1, Ordering::Relaxed);
+                let Some((path, read_path)) = files.get(i) else {
+                    return;
+                };
+                let object = ObjectIndex::read(path, read_path);
+                indexed.lock().unwrap().push((i, object));
+            });
+        }
+    });
+
+    let mut indexed = indexed.into_inner().unwrap();
+    indexed.sort_by_key(|(i, _)| *i);
+    return indexed.into_iter().map(|(_, object)| object).collect();
+}
+
+// Lists the files that a process has mapped code from, e.g. its executable and shared libraries,
+// in ascending order of address. Data files like locale archives are left out, as are files that
+// were deleted since.
+pub fn mapped_code_files(mappings: &[MemoryMapping]) -> Vec<String> {
+    let mut paths: Vec<String> = vec![];
+    for mapping in mappings {
+        let is_code_file = mapping.permissions.contains('x')
+            && mapping.path.starts_with('/')
+            && !mapping.path.ends_with(" (deleted)");
+        if is_code_file && !paths.contains(&mapping.path) {
+            paths.push(mapping.path.clone());
+        }
+    }
+    return paths;
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::{mapped_code_files, SymbolIndex};
+    use crate::procfs::{read_exe_path, read_maps, MemoryMapping};
+
+    fn mapping(permissions: &str, path: &str) -> MemoryMapping {
+        return MemoryMapping {
+            start: 0,
+            end: 0,
+            permissions: permissions.to_string(),
+            offset: 0,
+            path: path.to_string(),
+        };
+    }
+
+    #[test]
+    fn mapped_code_files_lists_executables_and_libraries() {
+        let mappings = [
+            mapping("r--p", "/usr/bin/cat"),
+            mapping("r-xp", "/usr/bin/cat"),
+            mapping("rw-p", ""),
+            mapping("r--p", "/usr/lib/locale/locale-archive"),
+            mapping("r-xp", "/usr/lib/libc.so.6"),
+            mapping("r-xp", "/usr/bin/cat"),
+            mapping("r-xp", "/memfd:jit (deleted)"),
+            mapping("r-xp", "[vdso]"),
+        ];
+        assert_eq!(
+            mapped_code_files(&mappings),
+            vec!["/usr/bin/cat", "/usr/lib/libc.so.6"]
+        );
+    }
+
+    #[test]
+    fn symbol_index_indexes_mapped_files_once() {
+        let pid = std::process::id() as libc::pid_t;
+        let mappings = read_maps(pid).unwrap();
+        let files = mapped_code_files(&mappings)
+            .into_iter()
+            .map(|path| (path.clone(), PathBuf::from(path)))
+            .collect::<Vec<(String, PathBuf)>>();
+        let mut index = SymbolIndex::default();
+        assert_eq!(index.add(files.clone()).len(), files.len());
+        assert!(index.add(files.clone()).is_empty());
+        assert_eq!(index.objects().len(), files.len());
+
+        let executable = read_exe_path(pid).display().to_string();
+        let object = index.object(&executable).unwrap();
+        assert!(object.load_bias(&mappings).unwrap().is_some());
+        let functions = object.functions.as_ref().unwrap();
+        assert!(functions.iter().any(|function| function
+            .name
+            .contains("symbol_index_indexes_mapped_files_once")));
+        assert!(index.object("/no/such/file").is_none());
+    }
+
+    #[test]
+    fn symbol_index_drops_unmapped_files() {
+        let pid = std::process::id() as libc::pid_t;
+        let mappings = read_maps(pid).unwrap();
+        let files = mapped_code_files(&mappings)
+            .into_iter()
+            .map(|path| (path.clone(), PathBuf::from(path)))
+            .collect::<Vec<(String, PathBuf)>>();
+        let mut index = SymbolIndex::default();
+        index.add(files.clone());
+
+        let executable = read_exe_path(pid).display().to_string();
+        let removed = index.remove_unmapped(std::slice::from_ref(&executable));
+        assert_eq!(removed.len(), files.len() - 1);
+        assert!(!removed.contains(&executable));
+        assert_eq!(index.objects().len(), 1);
+        assert_eq!(index.add(files.clone()), removed);
+    }
+}
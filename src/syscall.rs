@@ -0,0 +1,301 @@
+use std::{collections::HashMap, time::Duration};
+
+mod aarch64;
+mod arm;
+pub mod decode;
+
+// Architectures of system calls, as reported by PTRACE_GET_SYSCALL_INFO (see linux/audit.h).
+pub const AUDIT_ARCH_AARCH64: u32 = 0xc00000b7;
+pub const AUDIT_ARCH_ARM: u32 = 0x40000028;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum SyscallStop {
+    // The thread is about to execute a system call.
+    Entry {
+        arch: u32,
+        number: u64,
+        args: [u64; 6],
+    },
+    // The thread has finished a system call. Errors are reported as negated errno values.
+    Exit {
+        arch: u32,
+        value: i64,
+        is_error: bool,
+    },
+    // A seccomp filter returned SECCOMP_RET_TRACE for the system call that the thread is about to
+    // execute, along with the given SECCOMP_RET_DATA.
+    Seccomp {
+        arch: u32,
+        number: u64,
+        args: [u64; 6],
+        data: u32,
+    },
+}
+
+fn syscall_names(arch: u32) -> &'static [(u64, &'static str)] {
+    return match arch {
+        AUDIT_ARCH_AARCH64 => aarch64::SYSCALL_NAMES,
+        // 32-bit tasks running on a 64-bit kernel use the compat system call numbers.
+        AUDIT_ARCH_ARM => arm::SYSCALL_NAMES,
+        _ => &[],
+    };
+}
+
+// Looks up the name of a system call of the given architecture.
+pub fn syscall_name(arch: u32, number: u64) -> Option<&'static str> {
+    let names = syscall_names(arch);
+    let index = names
+        .binary_search_by_key(&number, |(number, _)| *number)
+        .ok()?;
+    return Some(names[index].1);
+}
+
+// Looks up the number of a system call of the given architecture.
+pub fn syscall_number(arch: u32, name: &str) -> Option<u64> {
+    return syscall_names(arch)
+        .iter()
+        .find(|(_, syscall_name)| *syscall_name == name)
+        .map(|(number, _)| *number);
+}
+
+// Formats a system call for display, falling back to its number if its name is unknown.
+pub fn describe_syscall(arch: u32, number: u64) -> String {
+    return match syscall_name(arch, number) {
+        None => format!("syscall_{}", number),
+        Some(name) => name.to_string(),
+    };
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SyscallFilterError {
+    #[error("unknown syscall: \"{0}\"")]
+    UnknownSyscall(String),
+}
+
+// Selects the system calls to trace by name, e.g. "open*,read,write" or "!futex". Names may use
+// `*` as a wildcard, and names prefixed with `!` are excluded. Without any included names, every
+// system call that is not excluded is selected.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct SyscallFilter {
+    included: Vec<String>,
+    excluded: Vec<String>,
+}
+
+impl SyscallFilter {
+    pub fn from_spec(spec: &str) -> Result<SyscallFilter, SyscallFilterError> {
+        let mut filter = SyscallFilter::default();
+        for pattern in spec.split(',').filter(|pattern| !pattern.is_empty()) {
+            let (patterns, pattern) = match pattern.strip_prefix('!') {
+                None => (&mut filter.included, pattern),
+                Some(pattern) => (&mut filter.excluded, pattern),
+            };
+
+            // Catch typos early, rather than silently tracing nothing.
+            let is_known = [AUDIT_ARCH_AARCH64, AUDIT_ARCH_ARM].iter().any(|arch| {
+                syscall_names(*arch)
+                    .iter()
+                    .any(|(_, name)| matches_pattern(pattern, name))
+            });
+            if !is_known {
+                return Err(SyscallFilterError::UnknownSyscall(pattern.to_string()));
+            }
+            patterns.push(pattern.to_string());
+        }
+        return Ok(filter);
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let is_included = self.included.is_empty()
+            || self
+                .included
+                .iter()
+                .any(|pattern| matches_pattern(pattern, name));
+        return is_included
+            && !self
+                .excluded
+                .iter()
+                .any(|pattern| matches_pattern(pattern, name));
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct SyscallStats {
+    pub calls: u64,
+    pub errors: u64,
+    // The wall-clock time spent between the entries and exits of the calls.
+    pub time: Duration,
+}
+
+// Counts traced system calls by name, like strace -c.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct SyscallSummary {
+    stats: HashMap<String, SyscallStats>,
+}
+
+impl SyscallSummary {
+    pub fn record(&mut self, name: &str, time: Duration, is_error: bool) {
+        let stats = self.stats.entry(name.to_string()).or_default();
+        stats.calls += 1;
+        stats.time += time;
+        if is_error {
+            stats.errors += 1;
+        }
+    }
+
+    // Formats the summary as a table, from the system call that took the most time to the least.
+    pub fn format_table(&self) -> Vec<String> {
+        let mut stats = self.stats.iter().collect::<Vec<(&String, &SyscallStats)>>();
+        stats.sort_by(|(a_name, a), (b_name, b)| b.time.cmp(&a.time).then(a_name.cmp(b_name)));
+
+        let total_time = self
+            .stats
+            .values()
+            .map(|stats| stats.time)
+            .sum::<Duration>();
+        let separator = "------ ----------- ----------- --------- --------- ----------------";
+        let mut lines = vec![
+            "% time     seconds  usecs/call     calls    errors syscall".to_string(),
+            separator.to_string(),
+        ];
+        for (name, stats) in stats {
+            let percentage = match total_time.is_zero() {
+                true => 0.0,
+                false => 100.0 * stats.time.as_secs_f64() / total_time.as_secs_f64(),
+            };
+            lines.push(format!(
+                "{:>6.2} {:>11.6} {:>11} {:>9} {:>9} {}",
+                percentage,
+                stats.time.as_secs_f64(),
+                stats.time.as_micros() / stats.calls as u128,
+                stats.calls,
+                format_errors(stats.errors),
+                name,
+            ));
+        }
+
+        let total_calls = self.stats.values().map(|stats| stats.calls).sum::<u64>();
+        let total_errors = self.stats.values().map(|stats| stats.errors).sum::<u64>();
+        lines.push(separator.to_string());
+        lines.push(format!(
+            "{:>6.2} {:>11.6} {:>11} {:>9} {:>9} total",
+            100.0,
+            total_time.as_secs_f64(),
+            "",
+            total_calls,
+            format_errors(total_errors),
+        ));
+        return lines;
+    }
+}
+
+// Leaves the errors column blank when there are none, like strace does.
+fn format_errors(errors: u64) -> String {
+    return match errors {
+        0 => String::new(),
+        errors => errors.to_string(),
+    };
+}
+
+// Matches a name against a pattern where `*` stands for any sequence of characters.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let Some((prefix, rest)) = pattern.split_once('*') else {
+        return pattern == name;
+    };
+    let Some(name) = name.strip_prefix(prefix) else {
+        return false;
+    };
+
+    // Try every position that the wildcard could stretch to.
+    return (0..=name.len())
+        .filter(|i| name.is_char_boundary(*i))
+        .any(|i| matches_pattern(rest, &name[i..]));
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        describe_syscall, matches_pattern, syscall_name, syscall_names, syscall_number,
+        SyscallFilter, SyscallSummary, AUDIT_ARCH_AARCH64, AUDIT_ARCH_ARM,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn syscall_names_are_sorted_by_number() {
+        for arch in [AUDIT_ARCH_AARCH64, AUDIT_ARCH_ARM] {
+            let names = syscall_names(arch);
+            assert!(names.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        }
+    }
+
+    #[test]
+    fn syscall_name_uses_the_table_of_the_architecture() {
+        assert_eq!(syscall_name(AUDIT_ARCH_AARCH64, 56), Some("openat"));
+        assert_eq!(syscall_name(AUDIT_ARCH_ARM, 322), Some("openat"));
+        assert_eq!(syscall_name(AUDIT_ARCH_AARCH64, 1000), None);
+        assert_eq!(syscall_name(0, 56), None);
+    }
+
+    #[test]
+    fn syscall_number_is_inverse_of_syscall_name() {
+        assert_eq!(syscall_number(AUDIT_ARCH_AARCH64, "futex"), Some(98));
+        assert_eq!(syscall_number(AUDIT_ARCH_ARM, "futex"), Some(240));
+        assert_eq!(syscall_number(AUDIT_ARCH_AARCH64, "open"), None);
+    }
+
+    #[test]
+    fn describe_syscall_falls_back_to_number() {
+        assert_eq!(describe_syscall(AUDIT_ARCH_AARCH64, 63), "read");
+        assert_eq!(describe_syscall(AUDIT_ARCH_AARCH64, 1000), "syscall_1000");
+    }
+
+    #[test]
+    fn matches_pattern_supports_wildcards() {
+        assert!(matches_pattern("openat", "openat"));
+        assert!(!matches_pattern("open", "openat"));
+        assert!(matches_pattern("open*", "openat"));
+        assert!(matches_pattern("*stat*", "newfstatat"));
+        assert!(!matches_pattern("*stat", "statx"));
+    }
+
+    #[test]
+    fn syscall_filter_applies_included_and_excluded_names() {
+        let filter = SyscallFilter::from_spec("open*,read,!openat2").unwrap();
+        assert!(filter.matches("openat"));
+        assert!(filter.matches("read"));
+        assert!(!filter.matches("openat2"));
+        assert!(!filter.matches("write"));
+
+        let filter = SyscallFilter::from_spec("!futex").unwrap();
+        assert!(filter.matches("write"));
+        assert!(!filter.matches("futex"));
+    }
+
+    #[test]
+    fn syscall_filter_rejects_unknown_names() {
+        assert!(SyscallFilter::from_spec("opn").is_err());
+        assert!(SyscallFilter::from_spec("opn*").is_err());
+    }
+
+    #[test]
+    fn syscall_summary_sorts_by_time_and_totals() {
+        let mut summary = SyscallSummary::default();
+        summary.record("read", Duration::from_micros(10), false);
+        summary.record("openat", Duration::from_micros(30), true);
+        summary.record("openat", Duration::from_micros(10), false);
+
+        let table = summary.format_table();
+        assert_eq!(table.len(), 6);
+        assert_eq!(
+            table[2],
+            " 80.00    0.000040          20         2         1 openat"
+        );
+        assert_eq!(
+            table[3],
+            " 20.00    0.000010          10         1           read"
+        );
+        assert_eq!(
+            table[5],
+            "100.00    0.000050                     3         1 total"
+        );
+    }
+}
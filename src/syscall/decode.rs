@@ -0,0 +1,441 @@
+use std::ffi::CStr;
+
+use crate::{analysis::futex_op_name, signal::signal_name};
+
+// Strings and buffers are cut off after this many bytes, like strace does by default.
+const MAX_STRING_LEN: usize = 32;
+
+// Paths are printed in full, up to the kernel's own limit.
+const MAX_PATH_LEN: usize = libc::PATH_MAX as usize;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Arg {
+    // A signed decimal integer.
+    Int,
+    // An unsigned decimal integer, such as a size.
+    Uint,
+    // A pointer or opaque value.
+    Hex,
+    // A file descriptor.
+    Fd,
+    // A file descriptor that may be AT_FDCWD.
+    DirFd,
+    // A NUL-terminated path.
+    Path,
+    // A buffer whose length is given by the argument at the given index.
+    Buffer(usize),
+    OpenFlags,
+    // A file mode, in octal.
+    Mode,
+    Prot,
+    MapFlags,
+    Signal,
+    FutexOp,
+    Whence,
+}
+
+use Arg::*;
+
+// Describes the arguments of commonly traced system calls. The same names are used across
+// architectures, so compat tasks are decoded from the same table.
+fn syscall_args(name: &str) -> Option<&'static [Arg]> {
+    return match name {
+        "read" => Some(&[Fd, Hex, Uint]),
+        "write" => Some(&[Fd, Buffer(2), Uint]),
+        "pread64" => Some(&[Fd, Hex, Uint, Int]),
+        "pwrite64" => Some(&[Fd, Buffer(2), Uint, Int]),
+        "readv" | "writev" => Some(&[Fd, Hex, Uint]),
+        "open" => Some(&[Path, OpenFlags, Mode]),
+        "openat" => Some(&[DirFd, Path, OpenFlags, Mode]),
+        "creat" => Some(&[Path, Mode]),
+        "close" => Some(&[Fd]),
+        "dup" => Some(&[Fd]),
+        "dup2" => Some(&[Fd, Fd]),
+        "dup3" => Some(&[Fd, Fd, OpenFlags]),
+        "pipe2" => Some(&[Hex, OpenFlags]),
+        "lseek" => Some(&[Fd, Int, Whence]),
+        "fstat" | "fstat64" => Some(&[Fd, Hex]),
+        "stat" | "lstat" | "stat64" | "lstat64" => Some(&[Path, Hex]),
+        "newfstatat" | "fstatat64" => Some(&[DirFd, Path, Hex, Hex]),
+        "statx" => Some(&[DirFd, Path, Hex, Hex, Hex]),
+        "access" => Some(&[Path, Int]),
+        "faccessat" | "faccessat2" => Some(&[DirFd, Path, Int, Hex]),
+        "readlink" => Some(&[Path, Hex, Uint]),
+        "readlinkat" => Some(&[DirFd, Path, Hex, Uint]),
+        "unlink" | "rmdir" | "chdir" | "chroot" => Some(&[Path]),
+        "unlinkat" => Some(&[DirFd, Path, Hex]),
+        "mkdir" => Some(&[Path, Mode]),
+        "mkdirat" => Some(&[DirFd, Path, Mode]),
+        "renameat" | "renameat2" => Some(&[DirFd, Path, DirFd, Path, Hex]),
+        "fchdir" => Some(&[Fd]),
+        "getcwd" => Some(&[Hex, Uint]),
+        "getdents64" => Some(&[Fd, Hex, Uint]),
+        "ioctl" => Some(&[Fd, Hex, Hex]),
+        "fcntl" | "fcntl64" => Some(&[Fd, Int, Hex]),
+        "execve" => Some(&[Path, Hex, Hex]),
+        "execveat" => Some(&[DirFd, Path, Hex, Hex, Hex]),
+        "exit" | "exit_group" => Some(&[Int]),
+        "mmap" | "mmap2" => Some(&[Hex, Uint, Prot, MapFlags, Fd, Hex]),
+        "munmap" => Some(&[Hex, Uint]),
+        "mprotect" => Some(&[Hex, Uint, Prot]),
+        "brk" => Some(&[Hex]),
+        "futex" => Some(&[Hex, FutexOp, Uint, Hex, Hex, Uint]),
+        "kill" | "tkill" => Some(&[Int, Signal]),
+        "tgkill" => Some(&[Int, Int, Signal]),
+        "rt_sigaction" => Some(&[Signal, Hex, Hex, Uint]),
+        "rt_sigprocmask" => Some(&[Int, Hex, Hex, Uint]),
+        "wait4" => Some(&[Int, Hex, Hex, Hex]),
+        "nanosleep" => Some(&[Hex, Hex]),
+        "clock_nanosleep" => Some(&[Int, Int, Hex, Hex]),
+        "getrandom" => Some(&[Hex, Uint, Hex]),
+        "set_tid_address" => Some(&[Hex]),
+        "socket" => Some(&[Int, Int, Int]),
+        "connect" | "bind" => Some(&[Fd, Hex, Uint]),
+        "sendto" => Some(&[Fd, Buffer(2), Uint, Hex, Hex, Uint]),
+        "recvfrom" => Some(&[Fd, Hex, Uint, Hex, Hex, Hex]),
+        "getpid" | "getppid" | "gettid" | "getuid" | "geteuid" | "getgid" | "getegid"
+        | "sched_yield" => Some(&[]),
+        _ => None,
+    };
+}
+
+// Formats the entry of a system call like strace does, e.g. `openat(AT_FDCWD, "/etc/hosts",
+// O_RDONLY|O_CLOEXEC)`. Strings and paths are read out of the tracee with the given functions;
+// arguments of system calls without a known signature are printed as raw values.
+pub fn format_call(
+    name: &str,
+    args: &[u64; 6],
+    read_c_string: impl Fn(u64, usize) -> Option<Vec<u8>>,
+    read_memory: impl Fn(u64, usize) -> Option<Vec<u8>>,
+) -> String {
+    let Some(kinds) = syscall_args(name) else {
+        let args = args.iter().map(|arg| format!("{:#x}", arg));
+        return format!("{}({})", name, args.collect::<Vec<String>>().join(", "));
+    };
+
+    let args = kinds.iter().enumerate().map(|(i, kind)| {
+        let arg = args[i];
+        return match kind {
+            Int => (arg as i64).to_string(),
+            Uint => arg.to_string(),
+            Hex => format!("{:#x}", arg),
+            Fd => (arg as libc::c_int).to_string(),
+            DirFd => match arg as libc::c_int {
+                libc::AT_FDCWD => "AT_FDCWD".to_string(),
+                fd => fd.to_string(),
+            },
+            Path => match read_c_string(arg, MAX_PATH_LEN + 1) {
+                None => format!("{:#x}", arg),
+                Some(path) => quote_bytes(&path, MAX_PATH_LEN),
+            },
+            Buffer(len_index) => {
+                let len = (args[*len_index] as usize).min(MAX_STRING_LEN + 1);
+                match read_memory(arg, len) {
+                    None => format!("{:#x}", arg),
+                    Some(buffer) => quote_bytes(&buffer, MAX_STRING_LEN),
+                }
+            }
+            OpenFlags => format_open_flags(arg),
+            Mode => match arg {
+                0 => "0".to_string(),
+                _ => format!("0{:o}", arg),
+            },
+            Prot => match arg {
+                0 => "PROT_NONE".to_string(),
+                _ => format_flags(arg, PROT_FLAGS),
+            },
+            MapFlags => format_flags(arg, MAP_FLAGS),
+            Signal => signal_name(arg as libc::c_int),
+            FutexOp => match futex_op_name(arg) {
+                "FUTEX_?" => format!("{:#x}", arg),
+                op_name => op_name.to_string(),
+            },
+            Whence => match arg as libc::c_int {
+                libc::SEEK_SET => "SEEK_SET".to_string(),
+                libc::SEEK_CUR => "SEEK_CUR".to_string(),
+                libc::SEEK_END => "SEEK_END".to_string(),
+                whence => whence.to_string(),
+            },
+        };
+    });
+    return format!("{}({})", name, args.collect::<Vec<String>>().join(", "));
+}
+
+// Formats the return value of a system call like strace does, e.g. `3` or
+// `-1 ENOENT (No such file or directory)`.
+pub fn format_return(name: &str, value: i64, is_error: bool) -> String {
+    if is_error {
+        let errno = -value as libc::c_int;
+        let message = unsafe { CStr::from_ptr(libc::strerror(errno)) };
+        return match errno_name(errno) {
+            None => format!("-1 errno {} ({})", errno, message.to_string_lossy()),
+            Some(errno_name) => format!("-1 {} ({})", errno_name, message.to_string_lossy()),
+        };
+    }
+
+    return match name {
+        "mmap" | "mmap2" | "brk" => format!("{:#x}", value),
+        _ => value.to_string(),
+    };
+}
+
+// Formats bytes as a quoted C string, escaping anything that is not printable.
+fn quote_bytes(bytes: &[u8], max_len: usize) -> String {
+    let mut quoted = String::from("\"");
+    for byte in &bytes[..bytes.len().min(max_len)] {
+        match byte {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\r' => quoted.push_str("\\r"),
+            0x20..=0x7e => quoted.push(*byte as char),
+            _ => quoted.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    quoted.push('"');
+
+    if bytes.len() > max_len {
+        quoted.push_str("...");
+    }
+    return quoted;
+}
+
+const OPEN_FLAGS: &[(u64, &str)] = &[
+    (libc::O_CREAT as u64, "O_CREAT"),
+    (libc::O_EXCL as u64, "O_EXCL"),
+    (libc::O_NOCTTY as u64, "O_NOCTTY"),
+    (libc::O_TRUNC as u64, "O_TRUNC"),
+    (libc::O_APPEND as u64, "O_APPEND"),
+    (libc::O_NONBLOCK as u64, "O_NONBLOCK"),
+    // O_SYNC and O_TMPFILE include the bits of O_DSYNC and O_DIRECTORY, so they go first.
+    (libc::O_SYNC as u64, "O_SYNC"),
+    (libc::O_DSYNC as u64, "O_DSYNC"),
+    (libc::O_TMPFILE as u64, "O_TMPFILE"),
+    (libc::O_DIRECTORY as u64, "O_DIRECTORY"),
+    (libc::O_NOFOLLOW as u64, "O_NOFOLLOW"),
+    (libc::O_DIRECT as u64, "O_DIRECT"),
+    (libc::O_LARGEFILE as u64, "O_LARGEFILE"),
+    (libc::O_NOATIME as u64, "O_NOATIME"),
+    (libc::O_CLOEXEC as u64, "O_CLOEXEC"),
+    (libc::O_PATH as u64, "O_PATH"),
+    (libc::O_ASYNC as u64, "O_ASYNC"),
+];
+
+const PROT_FLAGS: &[(u64, &str)] = &[
+    (libc::PROT_READ as u64, "PROT_READ"),
+    (libc::PROT_WRITE as u64, "PROT_WRITE"),
+    (libc::PROT_EXEC as u64, "PROT_EXEC"),
+];
+
+const MAP_FLAGS: &[(u64, &str)] = &[
+    (libc::MAP_SHARED_VALIDATE as u64, "MAP_SHARED_VALIDATE"),
+    (libc::MAP_SHARED as u64, "MAP_SHARED"),
+    (libc::MAP_PRIVATE as u64, "MAP_PRIVATE"),
+    (libc::MAP_FIXED_NOREPLACE as u64, "MAP_FIXED_NOREPLACE"),
+    (libc::MAP_FIXED as u64, "MAP_FIXED"),
+    (libc::MAP_ANONYMOUS as u64, "MAP_ANONYMOUS"),
+    (libc::MAP_GROWSDOWN as u64, "MAP_GROWSDOWN"),
+    (libc::MAP_DENYWRITE as u64, "MAP_DENYWRITE"),
+    (libc::MAP_NORESERVE as u64, "MAP_NORESERVE"),
+    (libc::MAP_POPULATE as u64, "MAP_POPULATE"),
+    (libc::MAP_STACK as u64, "MAP_STACK"),
+];
+
+pub fn format_open_flags(flags: u64) -> String {
+    let access_mode = match flags & libc::O_ACCMODE as u64 {
+        0 => "O_RDONLY",
+        1 => "O_WRONLY",
+        2 => "O_RDWR",
+        _ => "O_ACCMODE",
+    };
+    return match flags & !(libc::O_ACCMODE as u64) {
+        0 => access_mode.to_string(),
+        flags => format!("{}|{}", access_mode, format_flags(flags, OPEN_FLAGS)),
+    };
+}
+
+// Formats a bitmask as `A|B|0x..`, matching the named flags in order.
+fn format_flags(flags: u64, names: &[(u64, &str)]) -> String {
+    let mut names_found = vec![];
+    let mut remaining = flags;
+    for (flag, name) in names {
+        if *flag != 0 && remaining & flag == *flag {
+            names_found.push(name.to_string());
+            remaining &= !flag;
+        }
+    }
+
+    if remaining != 0 || names_found.is_empty() {
+        names_found.push(format!("{:#x}", remaining));
+    }
+    return names_found.join("|");
+}
+
+const ERRNO_NAMES: &[(libc::c_int, &str)] = &[
+    (libc::EPERM, "EPERM"),
+    (libc::ENOENT, "ENOENT"),
+    (libc::ESRCH, "ESRCH"),
+    (libc::EINTR, "EINTR"),
+    (libc::EIO, "EIO"),
+    (libc::ENXIO, "ENXIO"),
+    (libc::E2BIG, "E2BIG"),
+    (libc::ENOEXEC, "ENOEXEC"),
+    (libc::EBADF, "EBADF"),
+    (libc::ECHILD, "ECHILD"),
+    (libc::EAGAIN, "EAGAIN"),
+    (libc::ENOMEM, "ENOMEM"),
+    (libc::EACCES, "EACCES"),
+    (libc::EFAULT, "EFAULT"),
+    (libc::EBUSY, "EBUSY"),
+    (libc::EEXIST, "EEXIST"),
+    (libc::EXDEV, "EXDEV"),
+    (libc::ENODEV, "ENODEV"),
+    (libc::ENOTDIR, "ENOTDIR"),
+    (libc::EISDIR, "EISDIR"),
+    (libc::EINVAL, "EINVAL"),
+    (libc::ENFILE, "ENFILE"),
+    (libc::EMFILE, "EMFILE"),
+    (libc::ENOTTY, "ENOTTY"),
+    (libc::EFBIG, "EFBIG"),
+    (libc::ENOSPC, "ENOSPC"),
+    (libc::ESPIPE, "ESPIPE"),
+    (libc::EROFS, "EROFS"),
+    (libc::EPIPE, "EPIPE"),
+    (libc::ERANGE, "ERANGE"),
+    (libc::EDEADLK, "EDEADLK"),
+    (libc::ENAMETOOLONG, "ENAMETOOLONG"),
+    (libc::ENOSYS, "ENOSYS"),
+    (libc::ENOTEMPTY, "ENOTEMPTY"),
+    (libc::ELOOP, "ELOOP"),
+    (libc::ENODATA, "ENODATA"),
+    (libc::ENOTSOCK, "ENOTSOCK"),
+    (libc::EOPNOTSUPP, "EOPNOTSUPP"),
+    (libc::EADDRINUSE, "EADDRINUSE"),
+    (libc::ECONNRESET, "ECONNRESET"),
+    (libc::ETIMEDOUT, "ETIMEDOUT"),
+    (libc::ECONNREFUSED, "ECONNREFUSED"),
+    (libc::EINPROGRESS, "EINPROGRESS"),
+    // Kernel-internal errors that tracers can observe before the system call is restarted.
+    (512, "ERESTARTSYS"),
+    (513, "ERESTARTNOINTR"),
+    (514, "ERESTARTNOHAND"),
+    (516, "ERESTART_RESTARTBLOCK"),
+];
+
+fn errno_name(errno: libc::c_int) -> Option<&'static str> {
+    return ERRNO_NAMES
+        .iter()
+        .find(|(number, _)| *number == errno)
+        .map(|(_, name)| *name);
+}
+
+// Parses a system call return value, given either as a number or as a negated errno name like
+// `-EPERM`.
+pub fn parse_return_value(value: &str) -> Option<i64> {
+    if let Some(name) = value.strip_prefix('-').filter(|name| name.starts_with('E')) {
+        return Some(-(parse_errno(name)? as i64));
+    }
+
+    return match value.strip_prefix("0x") {
+        None => value.parse::<i64>().ok(),
+        Some(hex) => u64::from_str_radix(hex, 16).ok().map(|value| value as i64),
+    };
+}
+
+// Parses an errno, given either as a name like `ENOSPC` or as a positive number.
+pub fn parse_errno(value: &str) -> Option<libc::c_int> {
+    if value.starts_with('E') {
+        let (errno, _) = ERRNO_NAMES.iter().find(|(_, known)| *known == value)?;
+        return Some(*errno);
+    }
+    return value.parse::<libc::c_int>().ok().filter(|errno| *errno > 0);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        format_call, format_flags, format_open_flags, format_return, parse_errno,
+        parse_return_value, quote_bytes,
+    };
+
+    #[test]
+    fn format_call_decodes_paths_and_flags() {
+        let args = [
+            libc::AT_FDCWD as u64,
+            0x1000,
+            (libc::O_RDONLY | libc::O_CLOEXEC) as u64,
+            0,
+            0,
+            0,
+        ];
+        let call = format_call(
+            "openat",
+            &args,
+            |address, _| (address == 0x1000).then(|| b"/etc/hosts".to_vec()),
+            |_, _| None,
+        );
+        assert_eq!(
+            call,
+            "openat(AT_FDCWD, \"/etc/hosts\", O_RDONLY|O_CLOEXEC, 0)"
+        );
+    }
+
+    #[test]
+    fn format_call_truncates_buffers() {
+        let args = [1, 0x1000, 40, 0, 0, 0];
+        let call = format_call("write", &args, |_, _| None, |_, len| Some(vec![b'a'; len]));
+        assert_eq!(call, format!("write(1, \"{}\"..., 40)", "a".repeat(32)));
+    }
+
+    #[test]
+    fn format_call_prints_unknown_syscalls_raw() {
+        let args = [1, 2, 3, 4, 5, 6];
+        let call = format_call("syscall_999", &args, |_, _| None, |_, _| None);
+        assert_eq!(call, "syscall_999(0x1, 0x2, 0x3, 0x4, 0x5, 0x6)");
+    }
+
+    #[test]
+    fn format_return_names_errors() {
+        assert_eq!(format_return("openat", 3, false), "3");
+        assert_eq!(
+            format_return("openat", -(libc::ENOENT as i64), true),
+            "-1 ENOENT (No such file or directory)"
+        );
+        assert_eq!(format_return("mmap", 0xffff0000, false), "0xffff0000");
+    }
+
+    #[test]
+    fn format_flags_keeps_unknown_bits() {
+        let names = &[(0x1, "A"), (0x2, "B")];
+        assert_eq!(format_flags(0x3, names), "A|B");
+        assert_eq!(format_flags(0x9, names), "A|0x8");
+        assert_eq!(format_flags(0, names), "0x0");
+        assert_eq!(
+            format_open_flags((libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC) as u64),
+            "O_WRONLY|O_CREAT|O_TRUNC"
+        );
+    }
+
+    #[test]
+    fn quote_bytes_escapes_unprintable_bytes() {
+        assert_eq!(quote_bytes(b"a\"b\n\x01", 32), "\"a\\\"b\\n\\x01\"");
+    }
+
+    #[test]
+    fn parse_return_value_accepts_numbers_and_errno_names() {
+        assert_eq!(parse_return_value("0"), Some(0));
+        assert_eq!(parse_return_value("-1"), Some(-1));
+        assert_eq!(parse_return_value("0x10"), Some(16));
+        assert_eq!(parse_return_value("-EPERM"), Some(-(libc::EPERM as i64)));
+        assert_eq!(parse_return_value("-EFOO"), None);
+    }
+
+    #[test]
+    fn parse_errno_accepts_names_and_positive_numbers() {
+        assert_eq!(parse_errno("ENOSPC"), Some(libc::ENOSPC));
+        assert_eq!(parse_errno("4"), Some(libc::EINTR));
+        assert_eq!(parse_errno("-4"), None);
+        assert_eq!(parse_errno("EFOO"), None);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::{
+    coredump::{CoreError, CoreTarget},
+    remote::{RemoteError, RemoteTarget},
+    tracee::{Tracee, TraceeError, TraceeStatus},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum TargetError {
+    #[error(transparent)]
+    Tracee(#[from] TraceeError),
+    #[error(transparent)]
+    Remote(#[from] RemoteError),
+    #[error(transparent)]
+    Core(#[from] CoreError),
+}
+
+// What commands need of a debugged process, whether it is traced here or by a remote stub.
+// Resuming does not wait; `wait_on_signal` blocks until the process stops or exits, and reports
+// it.
+pub trait Target {
+    fn pid(&self) -> libc::pid_t;
+    fn status(&self) -> TraceeStatus;
+    fn selected_tid(&self) -> libc::pid_t;
+    fn thread_ids(&self) -> Vec<libc::pid_t>;
+    // Returns false if the thread does not belong to the process.
+    fn select_thread(&mut self, tid: libc::pid_t) -> bool;
+
+    unsafe fn resume(&mut self);
+    unsafe fn resume_with_signal(&mut self, signal: libc::c_int);
+    unsafe fn step(&mut self, signal: Option<libc::c_int>);
+    unsafe fn wait_on_signal(&mut self);
+    unsafe fn kill(&mut self);
+
+    unsafe fn read_memory(&self, address: u64, len: usize) -> Result<Vec<u8>, TargetError>;
+    unsafe fn write_memory(&self, address: u64, bytes: &[u8]) -> Result<(), TargetError>;
+    unsafe fn read_thread_general_purpose_registers(
+        &self,
+        tid: libc::pid_t,
+    ) -> libc::user_regs_struct;
+    unsafe fn write_general_purpose_registers(&self, regs: &mut libc::user_regs_struct);
+
+    unsafe fn read_general_purpose_registers(&self) -> libc::user_regs_struct {
+        return self.read_thread_general_purpose_registers(self.selected_tid());
+    }
+}
+
+impl Target for Tracee {
+    fn pid(&self) -> libc::pid_t {
+        return Tracee::pid(self);
+    }
+
+    fn status(&self) -> TraceeStatus {
+        return Tracee::status(self);
+    }
+
+    fn selected_tid(&self) -> libc::pid_t {
+        return Tracee::selected_tid(self);
+    }
+
+    fn thread_ids(&self) -> Vec<libc::pid_t> {
+        return self.threads().iter().map(|thread| thread.tid).collect();
+    }
+
+    fn select_thread(&mut self, tid: libc::pid_t) -> bool {
+        return Tracee::select_thread(self, tid);
+    }
+
+    unsafe fn resume(&mut self) {
+        Tracee::resume(self);
+    }
+
+    unsafe fn resume_with_signal(&mut self, signal: libc::c_int) {
+        Tracee::resume_with_signal(self, signal);
+    }
+
+    unsafe fn step(&mut self, signal: Option<libc::c_int>) {
+        Tracee::step(self, signal);
+    }
+
+    unsafe fn wait_on_signal(&mut self) {
+        Tracee::wait_on_signal(self);
+    }
+
+    unsafe fn kill(&mut self) {
+        Tracee::kill(self);
+    }
+
+    unsafe fn read_memory(&self, address: u64, len: usize) -> Result<Vec<u8>, TargetError> {
+        return Ok(Tracee::read_memory(self, address, len)?);
+    }
+
+    unsafe fn write_memory(&self, address: u64, bytes: &[u8]) -> Result<(), TargetError> {
+        return Ok(Tracee::write_memory(self, address, bytes)?);
+    }
+
+    unsafe fn read_thread_general_purpose_registers(
+        &self,
+        tid: libc::pid_t,
+    ) -> libc::user_regs_struct {
+        return Tracee::read_thread_general_purpose_registers(self, tid);
+    }
+
+    unsafe fn write_general_purpose_registers(&self, regs: &mut libc::user_regs_struct) {
+        Tracee::write_general_purpose_registers(self, regs);
+    }
+
+    unsafe fn read_general_purpose_registers(&self) -> libc::user_regs_struct {
+        return Tracee::read_general_purpose_registers(self);
+    }
+}
+
+impl Target for RemoteTarget {
+    fn pid(&self) -> libc::pid_t {
+        return RemoteTarget::pid(self);
+    }
+
+    fn status(&self) -> TraceeStatus {
+        return RemoteTarget::status(self);
+    }
+
+    fn selected_tid(&self) -> libc::pid_t {
+        return RemoteTarget::selected_tid(self);
+    }
+
+    fn thread_ids(&self) -> Vec<libc::pid_t> {
+        return RemoteTarget::thread_ids(self).to_vec();
+    }
+
+    fn select_thread(&mut self, tid: libc::pid_t) -> bool {
+        return RemoteTarget::select_thread(self, tid);
+    }
+
+    unsafe fn resume(&mut self) {
+        RemoteTarget::resume(self, None);
+    }
+
+    unsafe fn resume_with_signal(&mut self, signal: libc::c_int) {
+        RemoteTarget::resume(self, Some(signal));
+    }
+
+    unsafe fn step(&mut self, signal: Option<libc::c_int>) {
+        RemoteTarget::step(self, signal);
+    }
+
+    unsafe fn wait_on_signal(&mut self) {
+        RemoteTarget::wait_on_signal(self);
+    }
+
+    unsafe fn kill(&mut self) {
+        RemoteTarget::kill(self);
+    }
+
+    unsafe fn read_memory(&self, address: u64, len: usize) -> Result<Vec<u8>, TargetError> {
+        return Ok(RemoteTarget::read_memory(self, address, len)?);
+    }
+
+    unsafe fn write_memory(&self, address: u64, bytes: &[u8]) -> Result<(), TargetError> {
+        return Ok(RemoteTarget::write_memory(self, address, bytes)?);
+    }
+
+    unsafe fn read_thread_general_purpose_registers(
+        &self,
+        tid: libc::pid_t,
+    ) -> libc::user_regs_struct {
+        return match RemoteTarget::read_thread_registers(self, tid) {
+            Err(err) => panic!("failed to read registers: {}", err),
+            Ok(regs) => regs,
+        };
+    }
+
+    unsafe fn write_general_purpose_registers(&self, regs: &mut libc::user_regs_struct) {
+        if let Err(err) = RemoteTarget::write_registers(self, regs) {
+            panic!("failed to write registers: {}", err);
+        }
+    }
+}
+
+// A core file is stopped for good: it can be inspected, but not run or changed.
+impl Target for CoreTarget {
+    fn pid(&self) -> libc::pid_t {
+        return self.snapshot().pid;
+    }
+
+    fn status(&self) -> TraceeStatus {
+        return TraceeStatus::Stopped;
+    }
+
+    fn selected_tid(&self) -> libc::pid_t {
+        return CoreTarget::selected_tid(self);
+    }
+
+    fn thread_ids(&self) -> Vec<libc::pid_t> {
+        return self
+            .snapshot()
+            .threads
+            .iter()
+            .map(|thread| thread.tid)
+            .collect();
+    }
+
+    fn select_thread(&mut self, tid: libc::pid_t) -> bool {
+        return CoreTarget::select_thread(self, tid);
+    }
+
+    unsafe fn resume(&mut self) {
+        println!("The program is not being run.");
+    }
+
+    unsafe fn resume_with_signal(&mut self, _signal: libc::c_int) {
+        println!("The program is not being run.");
+    }
+
+    unsafe fn step(&mut self, _signal: Option<libc::c_int>) {
+        println!("The program is not being run.");
+    }
+
+    unsafe fn wait_on_signal(&mut self) {}
+
+    unsafe fn kill(&mut self) {
+        println!("The program is not being run.");
+    }
+
+    unsafe fn read_memory(&self, address: u64, len: usize) -> Result<Vec<u8>, TargetError> {
+        return Ok(self.snapshot().read_memory(address, len)?);
+    }
+
+    unsafe fn write_memory(&self, _address: u64, _bytes: &[u8]) -> Result<(), TargetError> {
+        return Err(CoreError::ReadOnly.into());
+    }
+
+    unsafe fn read_thread_general_purpose_registers(
+        &self,
+        tid: libc::pid_t,
+    ) -> libc::user_regs_struct {
+        return match self.read_thread_registers(tid) {
+            None => panic!(
+                "failed to read registers: no thread {} in the core file",
+                tid
+            ),
+            Some(regs) => regs,
+        };
+    }
+
+    unsafe fn write_general_purpose_registers(&self, _regs: &mut libc::user_regs_struct) {
+        println!("{}", CoreError::ReadOnly);
+    }
+}
@@ -0,0 +1,50 @@
+use std::time::Instant;
+
+use crate::calltrace::TracedCall;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ThreadStatus {
+    Running,
+    // A SIGSTOP is in flight (from attaching, cloning, or stopping), but has not been waited on.
+    Stopping,
+    Stopped,
+}
+
+pub struct Thread {
+    pub tid: libc::pid_t,
+    pub status: ThreadStatus,
+    // The system call that the thread last stopped at the entry of, until it stops at its exit.
+    pub syscall: Option<u64>,
+    // The decoded entry of the system call that is being traced, printed once it returns.
+    pub traced_call: Option<String>,
+    // When the system call that is being traced was entered.
+    pub syscall_entered_at: Option<Instant>,
+    // The name and decoded entry of the system call that is being audited, recorded once it
+    // returns.
+    pub audited_call: Option<(String, String)>,
+    // The signal that the thread stopped with, which is delivered once the thread is resumed.
+    pub pending_signal: Option<libc::c_int>,
+    // Whether the thread is inside a system call that may change the memory mappings.
+    pub changing_maps: bool,
+    // Whether the thread was last restarted to single-step.
+    pub single_stepping: bool,
+    // The traced function calls that the thread has yet to return from, innermost last.
+    pub traced_function_calls: Vec<TracedCall>,
+}
+
+impl Thread {
+    pub fn new(tid: libc::pid_t, status: ThreadStatus) -> Thread {
+        return Thread {
+            tid,
+            status,
+            syscall: None,
+            traced_call: None,
+            syscall_entered_at: None,
+            audited_call: None,
+            pending_signal: None,
+            changing_maps: false,
+            single_stepping: false,
+            traced_function_calls: vec![],
+        };
+    }
+}
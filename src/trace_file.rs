@@ -0,0 +1,288 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    json::{quote_json, Json},
+    signal::signal_name,
+};
+
+// Something that happened to a tracee, as written to a trace file.
+#[derive(PartialEq, Clone, Debug)]
+pub enum TraceRecord {
+    // A thread stopped, and the tracee with it, e.g. "breakpoint" or "syscall-entry".
+    Stop {
+        tid: libc::pid_t,
+        reason: &'static str,
+        signal: Option<libc::c_int>,
+    },
+    // A traced system call, which has no result if it was seen by seccomp only.
+    Syscall {
+        tid: libc::pid_t,
+        call: String,
+        result: Option<String>,
+    },
+    // A signal was about to be delivered to a thread.
+    Signal {
+        tid: libc::pid_t,
+        signal: libc::c_int,
+        stopped: bool,
+        passed: bool,
+    },
+    // A thread created a thread or process, where the kind is "clone", "fork" or "vfork".
+    Fork {
+        tid: libc::pid_t,
+        child: libc::pid_t,
+        kind: &'static str,
+    },
+    // A thread executed a program, which replaced the tracee's threads.
+    Exec {
+        tid: libc::pid_t,
+        path: String,
+        args: Vec<String>,
+    },
+    Exit {
+        code: libc::c_int,
+    },
+    Terminated {
+        signal: libc::c_int,
+    },
+}
+
+impl TraceRecord {
+    // Converts the record to the same object that a trace file has, minus the time.
+    pub fn to_json(&self, pid: libc::pid_t) -> Json {
+        let mut fields = vec![("pid", Json::from(pid))];
+        match self {
+            TraceRecord::Stop {
+                tid,
+                reason,
+                signal,
+            } => {
+                fields.push(("tid", Json::from(*tid)));
+                fields.push(("type", Json::from("stop")));
+                fields.push(("reason", Json::from(*reason)));
+                if let Some(signal) = signal {
+                    fields.push(("signal", Json::from(signal_name(*signal))));
+                }
+            }
+            TraceRecord::Syscall { tid, call, result } => {
+                fields.push(("tid", Json::from(*tid)));
+                fields.push(("type", Json::from("syscall")));
+                fields.push(("call", Json::from(call.as_str())));
+                if let Some(result) = result {
+                    fields.push(("result", Json::from(result.as_str())));
+                }
+            }
+            TraceRecord::Signal {
+                tid,
+                signal,
+                stopped,
+                passed,
+            } => {
+                fields.push(("tid", Json::from(*tid)));
+                fields.push(("type", Json::from("signal")));
+                fields.push(("signal", Json::from(signal_name(*signal))));
+                fields.push(("stopped", Json::from(*stopped)));
+                fields.push(("passed", Json::from(*passed)));
+            }
+            TraceRecord::Fork { tid, child, kind } => {
+                fields.push(("tid", Json::from(*tid)));
+                fields.push(("type", Json::from(*kind)));
+                fields.push(("child", Json::from(*child)));
+            }
+            TraceRecord::Exec { tid, path, args } => {
+                fields.push(("tid", Json::from(*tid)));
+                fields.push(("type", Json::from("exec")));
+                fields.push(("path", Json::from(path.as_str())));
+                let args = args
+                    .iter()
+                    .map(|arg| Json::from(arg.as_str()))
+                    .collect::<Vec<Json>>();
+                fields.push(("args", Json::from(args)));
+            }
+            TraceRecord::Exit { code } => {
+                fields.push(("type", Json::from("exit")));
+                fields.push(("code", Json::from(*code)));
+            }
+            TraceRecord::Terminated { signal } => {
+                fields.push(("type", Json::from("terminated")));
+                fields.push(("signal", Json::from(signal_name(*signal))));
+            }
+        }
+        return Json::object(fields);
+    }
+}
+
+// Appends the records of a tracee to a file, one JSON object per line.
+pub struct TraceFile {
+    file: File,
+}
+
+impl TraceFile {
+    pub fn open(path: &Path) -> io::Result<TraceFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        return Ok(TraceFile { file });
+    }
+
+    pub fn write(&mut self, pid: libc::pid_t, record: &TraceRecord) -> io::Result<()> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        // Each record is written at once, so that tracees sharing the file do not interleave.
+        return self
+            .file
+            .write_all(format!("{}\n", format_record(time, pid, record)).as_bytes());
+    }
+}
+
+fn format_record(time: Duration, pid: libc::pid_t, record: &TraceRecord) -> String {
+    let mut fields = vec![
+        format!("\"time\":{:.6}", time.as_secs_f64()),
+        format!("\"pid\":{}", pid),
+    ];
+    match record {
+        TraceRecord::Stop {
+            tid,
+            reason,
+            signal,
+        } => {
+            fields.push(format!("\"tid\":{}", tid));
+            fields.push("\"type\":\"stop\"".to_string());
+            fields.push(format!("\"reason\":{}", quote_json(reason)));
+            if let Some(signal) = signal {
+                fields.push(format!("\"signal\":{}", quote_json(&signal_name(*signal))));
+            }
+        }
+        TraceRecord::Syscall { tid, call, result } => {
+            fields.push(format!("\"tid\":{}", tid));
+            fields.push("\"type\":\"syscall\"".to_string());
+            fields.push(format!("\"call\":{}", quote_json(call)));
+            if let Some(result) = result {
+                fields.push(format!("\"result\":{}", quote_json(result)));
+            }
+        }
+        TraceRecord::Signal {
+            tid,
+            signal,
+            stopped,
+            passed,
+        } => {
+            fields.push(format!("\"tid\":{}", tid));
+            fields.push("\"type\":\"signal\"".to_string());
+            fields.push(format!("\"signal\":{}", quote_json(&signal_name(*signal))));
+            fields.push(format!("\"stopped\":{}", stopped));
+            fields.push(format!("\"passed\":{}", passed));
+        }
+        TraceRecord::Fork { tid, child, kind } => {
+            fields.push(format!("\"tid\":{}", tid));
+            fields.push(format!("\"type\":{}", quote_json(kind)));
+            fields.push(format!("\"child\":{}", child));
+        }
+        TraceRecord::Exec { tid, path, args } => {
+            fields.push(format!("\"tid\":{}", tid));
+            fields.push("\"type\":\"exec\"".to_string());
+            fields.push(format!("\"path\":{}", quote_json(path)));
+            let args = args
+                .iter()
+                .map(|arg| quote_json(arg))
+                .collect::<Vec<String>>();
+            fields.push(format!("\"args\":[{}]", args.join(",")));
+        }
+        TraceRecord::Exit { code } => {
+            fields.push("\"type\":\"exit\"".to_string());
+            fields.push(format!("\"code\":{}", code));
+        }
+        TraceRecord::Terminated { signal } => {
+            fields.push("\"type\":\"terminated\"".to_string());
+            fields.push(format!("\"signal\":{}", quote_json(&signal_name(*signal))));
+        }
+    }
+    return format!("{{{}}}", fields.join(","));
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{format_record, TraceRecord};
+    use crate::json::Json;
+
+    #[test]
+    fn format_record_writes_one_json_object() {
+        let time = Duration::from_micros(1_500_000);
+        assert_eq!(
+            format_record(
+                time,
+                10,
+                &TraceRecord::Stop {
+                    tid: 11,
+                    reason: "signal",
+                    signal: Some(libc::SIGSEGV),
+                }
+            ),
+            "{\"time\":1.500000,\"pid\":10,\"tid\":11,\"type\":\"stop\",\"reason\":\"signal\",\"signal\":\"SIGSEGV\"}"
+        );
+        assert_eq!(
+            format_record(time, 10, &TraceRecord::Exit { code: 3 }),
+            "{\"time\":1.500000,\"pid\":10,\"type\":\"exit\",\"code\":3}"
+        );
+    }
+
+    #[test]
+    fn format_record_writes_forks_and_execs() {
+        let time = Duration::from_micros(1_500_000);
+        assert_eq!(
+            format_record(
+                time,
+                10,
+                &TraceRecord::Fork {
+                    tid: 11,
+                    child: 12,
+                    kind: "vfork",
+                }
+            ),
+            "{\"time\":1.500000,\"pid\":10,\"tid\":11,\"type\":\"vfork\",\"child\":12}"
+        );
+        assert_eq!(
+            format_record(
+                time,
+                10,
+                &TraceRecord::Exec {
+                    tid: 11,
+                    path: "/bin/echo".to_string(),
+                    args: vec!["echo".to_string(), "a \"b\"".to_string()],
+                }
+            ),
+            "{\"time\":1.500000,\"pid\":10,\"tid\":11,\"type\":\"exec\",\"path\":\"/bin/echo\",\"args\":[\"echo\",\"a \\\"b\\\"\"]}"
+        );
+    }
+
+    #[test]
+    fn to_json_matches_trace_file_fields() {
+        let records = [
+            TraceRecord::Signal {
+                tid: 11,
+                signal: libc::SIGUSR1,
+                stopped: true,
+                passed: false,
+            },
+            TraceRecord::Exec {
+                tid: 11,
+                path: "/bin/true".to_string(),
+                args: vec!["true".to_string()],
+            },
+        ];
+        for record in records {
+            let line = format_record(Duration::ZERO, 10, &record);
+            let mut expected = Json::parse(&line).unwrap();
+            if let Json::Object(fields) = &mut expected {
+                fields.retain(|(key, _)| key != "time");
+            }
+            assert_eq!(record.to_json(10), expected);
+        }
+    }
+}
@@ -1,14 +1,344 @@
 use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
     ffi::{CStr, CString},
-    mem,
-    process::exit,
+    io, mem,
+    path::{Path, PathBuf},
     ptr::{null, null_mut},
+    time::{Duration, Instant},
 };
 
-use crate::ipc::Pipe;
+use crate::{
+    auxv::AuxiliaryVector,
+    breakpoint::{Breakpoint, BreakpointSet},
+    calltrace::{
+        format_function_entry, format_function_return, format_library_call, CallTrace,
+        CallTraceKind, TracedCall, SHOWN_ARGUMENT_COUNT,
+    },
+    cfi::{find_unwind_row, unwind_registers, UnwindRow, DWARF_REGISTER_COUNT, DWARF_SP, DWARF_V0},
+    cleanup,
+    disasm::{
+        disassemble, format_instruction, preceding_instruction_address, restore_patched_bytes,
+        BREAKPOINT_INSTRUCTION, MAX_INSTRUCTION_LEN, SYSCALL_INSTRUCTION,
+    },
+    dwarf::{DwarfError, LineRow},
+    elf::{describe_address, load_bias, ElfError, ElfFile, FunctionSymbol},
+    event::PtraceEvent,
+    expr::{self, ExpressionContext, ExpressionError, Value},
+    hwdebug::{
+        read_slot_count, write_slots, DebugSlots, HardwareDebugError, HardwareSlot, SlotKind,
+        WatchAccess,
+    },
+    ipc::Pipe,
+    launch::{resource_name, LaunchOptions},
+    perf::{PerfCounterKind, PerfCounters, PerfError},
+    procfs,
+    pty::Pty,
+    reaper::{self, WaitStatus},
+    register::read_general_purpose_register,
+    replay::{
+        is_asynchronous_signal, is_replayed_syscall, syscall_outputs, ReplayEvent, ReplayLog,
+        ReplayMode, ReplayedSignal,
+    },
+    signal::{classify_trap, signal_name, ReceivedSignal, SignalDispositions, Trap, SI_TKILL},
+    solib::{find_rendezvous, read_link_map, read_rendezvous, RendezvousState},
+    source::{format_listing, SourceFinder, SourceSettings},
+    symbols::{self, ObjectIndex, SymbolIndex},
+    syscall::{
+        decode::{format_call, format_return},
+        describe_syscall, SyscallFilter, SyscallStop, SyscallSummary,
+    },
+    thread::{Thread, ThreadStatus},
+    trace_file::{TraceFile, TraceRecord},
+    unwind::{unwind_thread, Frame, ADDRESS_MASK},
+};
+
+const TRACE_OPTIONS: libc::c_int = libc::PTRACE_O_TRACECLONE
+    | libc::PTRACE_O_TRACEFORK
+    | libc::PTRACE_O_TRACEVFORK
+    | libc::PTRACE_O_TRACEVFORKDONE
+    | libc::PTRACE_O_TRACEEXEC
+    | libc::PTRACE_O_TRACEEXIT
+    | libc::PTRACE_O_TRACESECCOMP
+    | libc::PTRACE_O_TRACESYSGOOD;
+
+// The stop signal of syscall-stops, as distinguished by PTRACE_O_TRACESYSGOOD.
+const SYSCALL_STOP_SIGNAL: libc::c_int = libc::SIGTRAP | 0x80;
+
+// The regset that holds the system call number of a thread (see linux/elf.h).
+const NT_ARM_SYSTEM_CALL: libc::c_int = 0x404;
+
+// Upper bound on the number of received signals that are remembered, in case of long runs.
+const MAX_SIGNAL_HISTORY: usize = 10_000;
+
+// How much memory on each side of the pc is fetched when the tracee stops, which covers the
+// disassembly that is shown at stops.
+const STOP_MEMORY_RADIUS: u64 = 128;
+
+// System calls that may change the memory mappings of a process, other than execve().
+const MAPS_SYSCALLS: &[&str] = &[
+    "mmap",
+    "munmap",
+    "mremap",
+    "mprotect",
+    "pkey_mprotect",
+    "brk",
+    "shmat",
+    "shmdt",
+    "remap_file_pages",
+];
+
+// How far below the stack pointer functions that the debugger calls start their stack, in case
+// the interrupted code keeps anything there.
+const CALL_STACK_GAP: u64 = 256;
+
+// How many arguments of each kind the AAPCS64 passes in registers: x0-x7 and d0-d7.
+const MAX_CALL_ARGUMENTS: usize = 8;
+
+// Signals that put a process into group-stop when delivered.
+const JOB_CONTROL_STOP_SIGNALS: [libc::c_int; 4] =
+    [libc::SIGSTOP, libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU];
+
+#[derive(Debug, thiserror::Error)]
+pub enum TraceeError {
+    #[error("failed to read memory at {address:#x}: {message}")]
+    ReadMemory { address: u64, message: String },
+    #[error("failed to write memory at {address:#x}: {message}")]
+    WriteMemory { address: u64, message: String },
+    #[error(transparent)]
+    ReadElf(#[from] ElfError),
+    #[error("{path}: {source}")]
+    ReadSymbols { path: String, source: ElfError },
+    #[error("{path}: {source}")]
+    ReadLineTable { path: String, source: DwarfError },
+    #[error("{path}: {source}")]
+    ReadCallFrames { path: String, source: DwarfError },
+    #[error("{path}: not mapped")]
+    NotMapped { path: String },
+    #[error("failed to checkpoint: {0}")]
+    Checkpoint(&'static str),
+    #[error("failed to call function: {0}")]
+    Call(&'static str),
+    #[error("failed to return: {0}")]
+    Return(&'static str),
+    #[error("failed to restore patch: {0}")]
+    RestorePatch(&'static str),
+    #[error(transparent)]
+    HardwareDebug(#[from] HardwareDebugError),
+    #[error("failed to catch loads: {0}")]
+    CatchLoad(&'static str),
+    #[error("failed to break on resolution: {0}")]
+    BreakOnResolve(&'static str),
+    #[error("failed to set breakpoint: {0}")]
+    SetBreakpoint(&'static str),
+    #[error("no breakpoint number {0}")]
+    UnknownBreakpoint(usize),
+    #[error("failed to set watchpoint: {0}")]
+    SetWatchpoint(&'static str),
+    #[error("no watchpoint number {0}")]
+    UnknownWatchpoint(usize),
+    #[error("no hardware breakpoint in slot {0}")]
+    UnknownHardwareBreakpoint(usize),
+    #[error("failed to step thread ({tid}): {message}")]
+    Step { tid: libc::pid_t, message: String },
+    #[error(transparent)]
+    Expression(#[from] ExpressionError),
+}
+
+// An argument of a function that the debugger calls.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum CallArgument {
+    // Integers and pointers, which are passed in x0-x7.
+    Integer(u64),
+    // Floating-point numbers, which are passed in d0-d7.
+    Float(f64),
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum FollowForkMode {
+    // Keep debugging the parent and detach from the forked child.
+    Parent,
+    // Detach from the parent and debug the forked child instead.
+    Child,
+    // Keep debugging the parent and hand the forked child over as a new inferior.
+    Both,
+}
+
+impl FollowForkMode {
+    pub fn from_name(name: &str) -> Option<FollowForkMode> {
+        return match name {
+            "parent" => Some(FollowForkMode::Parent),
+            "child" => Some(FollowForkMode::Child),
+            "both" => Some(FollowForkMode::Both),
+            _ => None,
+        };
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum SchedulerLocking {
+    // Every thread runs whenever the tracee is resumed.
+    Off,
+    // Only the selected thread runs when single-stepping; every thread runs when continuing.
+    Step,
+    // Only the selected thread runs whenever the tracee is resumed.
+    On,
+}
+
+impl SchedulerLocking {
+    pub fn from_name(name: &str) -> Option<SchedulerLocking> {
+        return match name {
+            "off" => Some(SchedulerLocking::Off),
+            "step" => Some(SchedulerLocking::Step),
+            "on" => Some(SchedulerLocking::On),
+            _ => None,
+        };
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum SyscallTraceMode {
+    // Every system call stops the tracee at its entry and exit.
+    Ptrace,
+    // Only system calls that a seccomp filter of the tracee marks with SECCOMP_RET_TRACE stop the
+    // tracee, and only at their entry. It is much cheaper, but return values are not known.
+    Seccomp,
+}
+
+// A shared object that the dynamic linker has loaded into the tracee.
+#[derive(PartialEq, Clone, Debug)]
+pub struct SharedLibrary {
+    // The path that the object is mapped from.
+    pub path: String,
+    // The extent of the object's mappings.
+    pub start: u64,
+    pub end: u64,
+    pub has_symbols: bool,
+    pub has_debug_info: bool,
+}
+
+// What reloading the symbols of the shared libraries changed in the index.
+#[derive(PartialEq, Clone, Debug)]
+pub struct SymbolReload {
+    // The paths of the objects that were newly mapped, and indexed now.
+    pub added: Vec<String>,
+    // The paths of the objects that were unmapped, and dropped from the index.
+    pub removed: Vec<String>,
+}
 
-#[derive(PartialEq)]
-enum TraceeStatus {
+// A function that the tracee stops at the resolution of, once the dynamic linker writes its address
+// into the executable's GOT slot for it.
+#[derive(PartialEq, Clone, Debug)]
+pub struct ResolveBreak {
+    pub symbol: String,
+    pub got_address: u64,
+    // The hardware watchpoint slot that watches the GOT slot for writes.
+    slot: usize,
+}
+
+// The breakpoints that were taken out of a thread's way to step it over the instruction at its pc.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+struct LiftedBreakpoints {
+    // The ID of the software breakpoint that was disabled.
+    breakpoint: Option<usize>,
+    // Whether the thread's hardware breakpoint slots were written without the one at its pc.
+    hw_breakpoint: bool,
+}
+
+impl LiftedBreakpoints {
+    fn is_empty(&self) -> bool {
+        return self.breakpoint.is_none() && !self.hw_breakpoint;
+    }
+}
+
+// How a step of a thread over one instruction ended.
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum StepEnd {
+    // The instruction ran.
+    Stepped,
+    // The thread stopped at a ptrace event first, e.g. a clone, fork or exec of the system call that
+    // it was stepped over, which is left for `handle_wait_status`.
+    Event { is_exec: bool },
+}
+
+// A hardware watchpoint, which stops the tracee once a write changes the watched bytes, as long as
+// its condition holds of the new value.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Watchpoint {
+    pub id: usize,
+    pub address: u64,
+    pub len: u64,
+    // An expression that has to be true after the write for the tracee to stop, e.g.
+    // `$value == 0`. It reads the watched bytes before and after as `$old` and `$value`.
+    pub condition: Option<String>,
+    // The watched bytes as of the last write, as an integer.
+    pub value: u64,
+    // The hardware watchpoint slot that watches the bytes.
+    slot: usize,
+}
+
+// What a SIGTRAP was to the traps that pbreak sets for itself, e.g. to follow the dynamic linker.
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum InternalTrap {
+    // Not one of them.
+    Unrelated,
+    // One of them, after which the thread carries on.
+    Handled,
+    // What was waited for happened, which stops the tracee.
+    Caught,
+    // One of them, but the thread stopped at an event or ended while it was stepped past it, which
+    // is left for `handle_wait_status`.
+    Deferred,
+}
+
+// What the condition of a watchpoint reads: the registers and memory of the thread that wrote, the
+// functions of the tracee, and the watched bytes before and after the write.
+struct WatchConditionContext<'a> {
+    tracee: &'a Tracee,
+    tid: libc::pid_t,
+    regs: libc::user_regs_struct,
+    old: u64,
+    value: u64,
+}
+
+impl ExpressionContext for WatchConditionContext<'_> {
+    fn register(&self, name: &str) -> Option<u64> {
+        return read_general_purpose_register(&self.regs, name);
+    }
+
+    fn variable(&self, name: &str) -> Option<Value> {
+        return match name {
+            "old" => Some(Value::UInt(self.old)),
+            "value" => Some(Value::UInt(self.value)),
+            _ => None,
+        };
+    }
+
+    fn symbol(&self, name: &str) -> Option<u64> {
+        let functions = unsafe { self.tracee.read_functions() }.unwrap_or_default();
+        return functions
+            .into_iter()
+            .chain(unsafe { self.tracee.read_library_functions() })
+            .find(|function| function.name == name)
+            .map(|function| function.address);
+    }
+
+    fn read_memory(&self, address: u64, len: usize) -> Option<Vec<u8>> {
+        return unsafe { self.tracee.read_thread_memory(self.tid, address, len) }.ok();
+    }
+}
+
+// Code that was assembled into the tracee, e.g. with `patch asm`, along with the bytes that it
+// replaced.
+#[derive(PartialEq, Clone, Debug)]
+pub struct CodePatch {
+    pub address: u64,
+    pub original: Vec<u8>,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TraceeStatus {
     Running,
     Stopped,
     Exited,
@@ -18,39 +348,286 @@ enum TraceeStatus {
 pub struct Tracee {
     pid: libc::pid_t,
     status: TraceeStatus,
+    executable: PathBuf,
+    threads: Vec<Thread>,
+    selected_tid: libc::pid_t,
+    // Whether the tracee was attached with PTRACE_SEIZE rather than PTRACE_TRACEME. Only seized
+    // tracees report group-stops distinctly, and can be stopped with PTRACE_INTERRUPT.
+    seized: bool,
+    follow_fork_mode: FollowForkMode,
+    scheduler_locking: SchedulerLocking,
+    // Forked children whose initial stop was observed before the fork event of their parent.
+    early_fork_children: Vec<libc::pid_t>,
+    // Forked children that are being traced but have not been claimed as inferiors yet.
+    forked_tracees: Vec<Tracee>,
+    // While set, the selected system calls are printed as they happen, and every system call is
+    // stepped over instead of stopping the tracee.
+    syscall_trace_filter: Option<SyscallFilter>,
+    syscall_trace_mode: SyscallTraceMode,
+    // While set, traced system calls are counted rather than printed.
+    syscall_summary: Option<SyscallSummary>,
+    // While set, traced system calls are skipped and fail with this errno instead.
+    syscall_fault: Option<libc::c_int>,
+    // While set, every system call is recorded, without being printed or stopping the tracee.
+    auditing: bool,
+    // Whether the tracee was resumed until the next system call, which then stops it even while
+    // auditing.
+    stopping_at_syscall: bool,
+    signal_dispositions: SignalDispositions,
+    // Whether signals that threads stopped with are delivered when they are resumed, as long as
+    // their disposition is to pass them.
+    pass_pending_signals: bool,
+    attached_at: Instant,
+    // The signals received so far, oldest first.
+    signal_history: VecDeque<ReceivedSignal>,
+    // How the process ended, once it has.
+    exit_status: Option<WaitStatus>,
+    // While set, how much the counters advanced is printed whenever the tracee stops.
+    perf_counters: Option<PerfCounters>,
+    // While set, stops, traced system calls, and signals are recorded in the file.
+    trace_file: Option<TraceFile>,
+    // While set, records are also queued until taken, e.g. to be sent to RPC subscribers.
+    record_queue: Option<Vec<TraceRecord>>,
+    // Whether the process is in group-stop, e.g. from a shell's job control, while the tracee is
+    // otherwise running.
+    job_control_stopped: bool,
+    // How many instructions around the pc are printed whenever the tracee stops, if any.
+    stop_disassembly_count: usize,
+    // Whether stops are printed. Commands that stop the tracee many times over, e.g. `continue
+    // until`, report only the last stop themselves.
+    reports_stops: bool,
+    // While set, what the tracee does that may differ from run to run is recorded, or replayed
+    // from a recording. Either way, it is resumed until system calls so that they can be seen.
+    replay: Option<ReplayMode>,
+    // Finds the source files that the line table names, e.g. for disassembly.
+    sources: SourceFinder,
+    // How the program was launched, unless it was attached to.
+    launch_options: Option<LaunchOptions>,
+    // The pseudo-terminal that the program was launched on, if any.
+    pty: Option<Pty>,
+    // Where the debugger reaches the tracee's root directory, if not at its own, e.g. for a process
+    // in a container. The tracee's files are read from under it.
+    root: Option<PathBuf>,
+    // What the thread that stopped the tracee looked like at the stop, until it is resumed or
+    // written to.
+    stop_state: RefCell<Option<StopState>>,
+    // The functions and line tables of the files that the tracee has mapped, until it execs.
+    symbol_index: RefCell<SymbolIndex>,
+    // The memory mappings as of when they were last read, until the tracee may have changed them.
+    maps: RefCell<Option<Vec<procfs::MemoryMapping>>>,
+    // How many times the tracee has stopped, so that what is shown at each stop is shown once.
+    stop_count: u64,
+    // The code patched into the tracee, oldest first. Its words are registered for cleanup like
+    // those of breakpoints, but it is shown as the program now has it rather than hidden.
+    code_patches: Vec<CodePatch>,
+    // The hardware breakpoint and watchpoint slots, once the hardware has been asked how many it
+    // has. Every thread has the same slots set.
+    debug_slots: Option<DebugSlots>,
+    // The auxiliary vector of the program, once read. It is fixed at exec.
+    auxv: RefCell<Option<AuxiliaryVector>>,
+    // Patterns of library paths that stop the tracee once the dynamic linker loads a match.
+    load_catches: Vec<String>,
+    // The breakpoint at the dynamic linker's r_brk, with the bytes that it replaced, while loads
+    // are caught.
+    rendezvous_breakpoint: Option<(u64, Vec<u8>)>,
+    // The paths of the libraries in the link map as of when it was last consistent.
+    loaded_libraries: Vec<String>,
+    // The functions that stop the tracee once the dynamic linker resolves them.
+    resolve_breaks: Vec<ResolveBreak>,
+    // The software breakpoints set with `break`.
+    breakpoints: BreakpointSet,
+    // The thread that `step` took breakpoints out of the way of, and what it took, which is put
+    // back once the thread stops.
+    stepped_over: Option<(libc::pid_t, LiftedBreakpoints)>,
+    // The breakpoints that were taken out of the memory that a vforked child shares with the
+    // tracee, which are put back once the child no longer does.
+    vfork_disabled_breakpoints: Vec<usize>,
+    // The hardware watchpoints set with `watch`, and the ID of the last one set, which IDs carry on
+    // from.
+    watchpoints: Vec<Watchpoint>,
+    last_watchpoint_id: usize,
+    // The functions whose calls are traced with `ltrace` and `ftrace`.
+    call_traces: Vec<CallTrace>,
+    // The breakpoints at the return addresses of traced calls, with the bytes that they replaced,
+    // for as long as a thread is to return there.
+    return_breakpoints: Vec<(u64, Vec<u8>)>,
+    // Whether the call tracing breakpoints were taken out of the memory that a vforked child shares
+    // with the tracee, to be put back once the child no longer does.
+    vfork_lifted_call_traces: bool,
+}
+
+// The state of a stopped thread that the symbolizer, the disassembler, scripts and the frontends
+// all read at each stop. It is fetched in one pass, rather than by ptrace calls of their own.
+struct StopState {
+    tid: libc::pid_t,
+    regs: libc::user_regs_struct,
+    siginfo: Option<libc::siginfo_t>,
+    // The memory around the pc, starting at `memory_start`, which is cut short where unmapped.
+    memory_start: u64,
+    memory: Vec<u8>,
 }
 
 impl Tracee {
-    // Constructs a `Tracee` by attaching to an existing PID.
+    // Constructs a `Tracee` by seizing and interrupting every thread of an existing PID.
     pub unsafe fn from_pid(pid: libc::pid_t) -> Tracee {
-        if libc::ptrace(
-            libc::PTRACE_ATTACH,
-            pid,
-            null_mut::<*mut libc::c_void>(),
-            null_mut::<*mut libc::c_void>(),
-        ) < 0
-        {
-            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
-            panic!("failed to attach to pid ({}): {:?}", pid, errno_message);
-        }
-
         let mut tracee = Tracee {
-            pid: pid,
+            pid,
             status: TraceeStatus::Stopped,
+            executable: PathBuf::new(),
+            threads: vec![],
+            selected_tid: pid,
+            seized: true,
+            follow_fork_mode: FollowForkMode::Parent,
+            scheduler_locking: SchedulerLocking::Off,
+            early_fork_children: vec![],
+            forked_tracees: vec![],
+            syscall_trace_filter: None,
+            syscall_trace_mode: SyscallTraceMode::Ptrace,
+            syscall_summary: None,
+            syscall_fault: None,
+            auditing: false,
+            stopping_at_syscall: false,
+            signal_dispositions: SignalDispositions::new(),
+            pass_pending_signals: true,
+            attached_at: Instant::now(),
+            signal_history: VecDeque::new(),
+            exit_status: None,
+            perf_counters: None,
+            trace_file: None,
+            record_queue: None,
+            job_control_stopped: false,
+            stop_disassembly_count: 0,
+            reports_stops: true,
+            replay: None,
+            sources: SourceFinder::new(SourceSettings::default()),
+            launch_options: None,
+            pty: None,
+            root: None,
+            stop_state: RefCell::new(None),
+            symbol_index: RefCell::new(SymbolIndex::default()),
+            maps: RefCell::new(None),
+            stop_count: 0,
+            code_patches: vec![],
+            debug_slots: None,
+            auxv: RefCell::new(None),
+            load_catches: vec![],
+            rendezvous_breakpoint: None,
+            loaded_libraries: vec![],
+            resolve_breaks: vec![],
+            breakpoints: BreakpointSet::default(),
+            stepped_over: None,
+            vfork_disabled_breakpoints: vec![],
+            watchpoints: vec![],
+            last_watchpoint_id: 0,
+            call_traces: vec![],
+            return_breakpoints: vec![],
+            vfork_lifted_call_traces: false,
         };
+        tracee.seize_thread(pid);
+        cleanup::register_tracee(pid, true);
+        tracee.executable = procfs::read_exe_path(pid);
+        tracee.root = procfs::read_root(pid);
+
+        // Threads may be spawned while attaching, so keep attaching until none are left.
+        loop {
+            let new_tids = procfs::read_task_ids(pid)
+                .into_iter()
+                .filter(|tid| tracee.find_thread(*tid).is_none())
+                .collect::<Vec<libc::pid_t>>();
+            if new_tids.is_empty() {
+                break;
+            }
+
+            for tid in new_tids {
+                tracee.seize_thread(tid);
+            }
+        }
 
-        tracee.wait_on_signal();
+        tracee.wait_on_stopping_threads();
+        tracee.index_symbols();
+        println!(
+            "Attached to process ({}) with {} thread(s)",
+            pid,
+            tracee.threads.len()
+        );
 
         return tracee;
     }
 
     // Constructs a `Tracee` by executing a program.
     pub unsafe fn from_cmd(program: &str, args: &[String]) -> Tracee {
+        return Tracee::launch(&LaunchOptions::new(program, args));
+    }
+
+    // Constructs a `Tracee` by executing a program as the options say.
+    pub unsafe fn launch(options: &LaunchOptions) -> Tracee {
+        // Everything is prepared before forking, so that the child only has to make system calls.
+        // The strings are owned here, and outlive the pointers that exec is given.
+        let (program, args) = options.exec_args();
+        let program = CString::new(program.as_str())
+            .unwrap_or_else(|_| panic!("invalid program: {:?}", program));
+        let argv = args
+            .iter()
+            .map(|arg| CString::new(arg.as_str()))
+            .collect::<Result<Vec<CString>, _>>()
+            .unwrap_or_else(|err| panic!("invalid argument: {}", err));
+        let mut argv_ptrs = argv
+            .iter()
+            .map(|arg| arg.as_ptr())
+            .collect::<Vec<*const libc::c_char>>();
+        argv_ptrs.push(null());
+        let cwd = options
+            .cwd
+            .as_ref()
+            .map(|cwd| CString::new(cwd.as_str()).unwrap());
+        let redirections = options.redirections();
+        let environment = options.environment();
+        let pty = match options.pty {
+            true => Some(
+                Pty::open()
+                    .unwrap_or_else(|err| panic!("failed to open a pseudo-terminal: {}", err)),
+            ),
+            false => None,
+        };
+        let mut envp = environment
+            .iter()
+            .map(|var| var.as_ptr())
+            .collect::<Vec<*const libc::c_char>>();
+        envp.push(null());
+        // What the child reports when a step of setting itself up fails, by index, since it
+        // cannot build messages of its own.
+        let mut failures = vec![
+            "failed to ptrace newly forked process".to_string(),
+            "failed to disable ASLR".to_string(),
+            "failed to exec newly forked process".to_string(),
+        ];
+        let (trace_failure, aslr_failure, exec_failure) = (0, 1, 2);
+        let terminal_failure = failures.len();
+        failures.push(match &pty {
+            Some(pty) => format!("failed to open terminal {:?}", pty.slave_path()),
+            None => String::new(),
+        });
+        let cwd_failure = failures.len();
+        failures.push(match &cwd {
+            Some(cwd) => format!("failed to change directory to {:?}", cwd),
+            None => String::new(),
+        });
+        let redirection_failures = failures.len();
+        failures.extend(
+            redirections
+                .iter()
+                .map(|(path, _, fd)| format!("failed to redirect fd {} to {:?}", fd, path)),
+        );
+        let rlimit_failures = failures.len();
+        failures.extend(options.rlimits.iter().map(|limit| {
+            return format!("failed to set the {} limit", resource_name(limit.resource));
+        }));
         let mut pipe = Pipe::new();
 
         match libc::fork() {
             0 => {
-                // Child process
+                // Child process. The debugger's other threads may have held locks at fork, e.g.
+                // that of the allocator or of stdout, so only system calls are made from here on.
                 if libc::ptrace(
                     libc::PTRACE_TRACEME,
                     null_mut::<*mut libc::c_void>(),
@@ -58,278 +635,4487 @@ impl Tracee {
                     null_mut::<*mut libc::c_void>(),
                 ) < 0
                 {
-                    let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
-                    pipe.send(&format!(
-                        "failed to ptrace newly forked process: {:?}",
-                        errno_message,
-                    ));
-                    exit(-1);
+                    fail_launch(&pipe, trace_failure);
                 }
 
-                let program = CString::new(program).unwrap();
-                let mut args = args
-                    .iter()
-                    .map(|arg| {
-                        let arg = CString::new(arg.as_bytes()).unwrap();
-                        arg.as_ptr()
-                    })
-                    .collect::<Vec<*const libc::c_char>>();
-                args.push(null());
-
-                if libc::execvp(program.as_ptr(), args.as_ptr()) < 0 {
-                    let errno_message =
-                        CString::from_raw(libc::strerror(*libc::__errno_location()))
-                            .into_string()
-                            .unwrap();
-                    pipe.send(&format!(
-                        "failed to exec newly forked process: {:?}",
-                        errno_message
-                    ));
-                    exit(-1);
-                }
-
-                unreachable!("newly forked process should have successfully exec'ed");
+                // The program leads a session of its own, whose terminal is the pseudo-terminal.
+                // Redirections then replace some of its streams.
+                if let Some(pty) = &pty {
+                    libc::setsid();
+                    let pty_fd = libc::open(pty.slave_path().as_ptr(), libc::O_RDWR);
+                    if pty_fd < 0
+                        || libc::ioctl(pty_fd, libc::TIOCSCTTY, 0) < 0
+                        || libc::dup2(pty_fd, libc::STDIN_FILENO) < 0
+                        || libc::dup2(pty_fd, libc::STDOUT_FILENO) < 0
+                        || libc::dup2(pty_fd, libc::STDERR_FILENO) < 0
+                    {
+                        fail_launch(&pipe, terminal_failure);
+                    }
+                    if pty_fd > libc::STDERR_FILENO {
+                        libc::close(pty_fd);
+                    }
+                }
+
+                // The personality is kept across exec, so the program is laid out without
+                // randomization. Passing 0xffffffff only reads the current one.
+                if options.no_aslr {
+                    let persona = libc::personality(0xffffffff);
+                    if persona < 0
+                        || libc::personality((persona | libc::ADDR_NO_RANDOMIZE) as libc::c_ulong)
+                            < 0
+                    {
+                        fail_launch(&pipe, aslr_failure);
+                    }
+                }
+
+                // Paths are relative to the debugger's directory, so files are opened before
+                // changing it.
+                for (i, (path, flags, fd)) in redirections.iter().enumerate() {
+                    let file_fd = libc::open(path.as_ptr(), *flags | libc::O_CLOEXEC, 0o666);
+                    if file_fd < 0 || libc::dup2(file_fd, *fd) < 0 {
+                        fail_launch(&pipe, redirection_failures + i);
+                    }
+                }
+
+                if let Some(cwd) = &cwd {
+                    if libc::chdir(cwd.as_ptr()) < 0 {
+                        fail_launch(&pipe, cwd_failure);
+                    }
+                }
+
+                // Limits are set last, so that e.g. a low nofile limit does not fail the
+                // redirections.
+                for (i, limit) in options.rlimits.iter().enumerate() {
+                    let rlimit = libc::rlimit {
+                        rlim_cur: limit.soft,
+                        rlim_max: limit.hard,
+                    };
+                    if libc::setrlimit(limit.resource, &rlimit) < 0 {
+                        fail_launch(&pipe, rlimit_failures + i);
+                    }
+                }
+
+                libc::execvpe(program.as_ptr(), argv_ptrs.as_ptr(), envp.as_ptr());
+                fail_launch(&pipe, exec_failure);
             }
             pid => {
                 // Parent process
                 pipe.close_sender();
 
                 let mut tracee = Tracee {
-                    pid: pid,
-                    status: TraceeStatus::Stopped,
+                    pid,
+                    status: TraceeStatus::Running,
+                    executable: PathBuf::new(),
+                    threads: vec![Thread::new(pid, ThreadStatus::Running)],
+                    selected_tid: pid,
+                    seized: false,
+                    follow_fork_mode: FollowForkMode::Parent,
+                    scheduler_locking: SchedulerLocking::Off,
+                    early_fork_children: vec![],
+                    forked_tracees: vec![],
+                    syscall_trace_filter: None,
+                    syscall_trace_mode: SyscallTraceMode::Ptrace,
+                    syscall_summary: None,
+                    syscall_fault: None,
+                    auditing: false,
+                    stopping_at_syscall: false,
+                    signal_dispositions: SignalDispositions::new(),
+                    pass_pending_signals: true,
+                    attached_at: Instant::now(),
+                    signal_history: VecDeque::new(),
+                    exit_status: None,
+                    perf_counters: None,
+                    trace_file: None,
+                    record_queue: None,
+                    job_control_stopped: false,
+                    stop_disassembly_count: 0,
+                    reports_stops: true,
+                    replay: None,
+                    sources: SourceFinder::new(SourceSettings::default()),
+                    launch_options: None,
+                    pty: None,
+                    root: None,
+                    stop_state: RefCell::new(None),
+                    symbol_index: RefCell::new(SymbolIndex::default()),
+                    maps: RefCell::new(None),
+                    stop_count: 0,
+                    code_patches: vec![],
+                    debug_slots: None,
+                    auxv: RefCell::new(None),
+                    load_catches: vec![],
+                    rendezvous_breakpoint: None,
+                    loaded_libraries: vec![],
+                    resolve_breaks: vec![],
+                    breakpoints: BreakpointSet::default(),
+                    stepped_over: None,
+                    vfork_disabled_breakpoints: vec![],
+                    watchpoints: vec![],
+                    last_watchpoint_id: 0,
+                    call_traces: vec![],
+                    return_breakpoints: vec![],
+                    vfork_lifted_call_traces: false,
                 };
 
-                let err_str = pipe.receive();
-                if err_str.len() > 0 {
-                    panic!("failed to fork and trace: {}", err_str);
+                cleanup::register_tracee(pid, false);
+
+                // The child reports nothing once it has exec'd, which closes its end of the pipe.
+                let report = pipe.receive_raw();
+                if let Ok(report) = <[u8; 12]>::try_from(report.as_slice()) {
+                    let failure = u64::from_ne_bytes(report[..8].try_into().unwrap()) as usize;
+                    let errno = libc::c_int::from_ne_bytes(report[8..].try_into().unwrap());
+                    let errno_message = CStr::from_ptr(libc::strerror(errno));
+                    panic!(
+                        "failed to fork and trace: {}: {:?}",
+                        failures[failure], errno_message
+                    );
                 }
 
                 tracee.wait_on_signal();
+                if options.shell {
+                    tracee.resume_until_exec();
+                }
+                tracee.set_trace_options(pid);
+                tracee.executable = procfs::read_exe_path(pid);
+                tracee.root = procfs::read_root(pid);
+                tracee.launch_options = Some(options.clone());
+                tracee.index_symbols();
+                // The program has its end of the terminal open by now.
+                if let Some(pty) = &pty {
+                    pty.start_reading();
+                }
+                tracee.pty = pty;
+                if options.stop_at_entry {
+                    tracee.run_to_entry();
+                }
 
                 return tracee;
             }
         }
     }
 
-    pub unsafe fn wait_on_signal(&mut self) {
-        let mut wait_status = 0;
-        let wait_options = 0;
-        if libc::waitpid(self.pid, &mut wait_status, wait_options) < 0 {
-            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
-            panic!("failed to wait on pid ({}): {:?}", self.pid, errno_message);
-        }
+    // Constructs a `Tracee` for a forked child that was auto-attached and has already stopped.
+    unsafe fn from_forked_child(
+        pid: libc::pid_t,
+        seized: bool,
+        follow_fork_mode: FollowForkMode,
+    ) -> Tracee {
+        cleanup::register_tracee(pid, seized);
+        let mut tracee = Tracee {
+            pid,
+            status: TraceeStatus::Stopped,
+            executable: procfs::read_exe_path(pid),
+            threads: vec![Thread::new(pid, ThreadStatus::Stopped)],
+            selected_tid: pid,
+            seized,
+            follow_fork_mode,
+            scheduler_locking: SchedulerLocking::Off,
+            early_fork_children: vec![],
+            forked_tracees: vec![],
+            syscall_trace_filter: None,
+            syscall_trace_mode: SyscallTraceMode::Ptrace,
+            syscall_summary: None,
+            syscall_fault: None,
+            auditing: false,
+            stopping_at_syscall: false,
+            signal_dispositions: SignalDispositions::new(),
+            pass_pending_signals: true,
+            attached_at: Instant::now(),
+            signal_history: VecDeque::new(),
+            exit_status: None,
+            perf_counters: None,
+            trace_file: None,
+            record_queue: None,
+            job_control_stopped: false,
+            stop_disassembly_count: 0,
+            reports_stops: true,
+            replay: None,
+            sources: SourceFinder::new(SourceSettings::default()),
+            launch_options: None,
+            pty: None,
+            root: None,
+            stop_state: RefCell::new(None),
+            symbol_index: RefCell::new(SymbolIndex::default()),
+            maps: RefCell::new(None),
+            stop_count: 0,
+            code_patches: vec![],
+            debug_slots: None,
+            auxv: RefCell::new(None),
+            load_catches: vec![],
+            rendezvous_breakpoint: None,
+            loaded_libraries: vec![],
+            resolve_breaks: vec![],
+            breakpoints: BreakpointSet::default(),
+            stepped_over: None,
+            vfork_disabled_breakpoints: vec![],
+            watchpoints: vec![],
+            last_watchpoint_id: 0,
+            call_traces: vec![],
+            return_breakpoints: vec![],
+            vfork_lifted_call_traces: false,
+        };
+        // Children may have entered namespaces of their own by the time they are traced.
+        tracee.root = procfs::read_root(pid);
+        return tracee;
+    }
 
-        if libc::WIFSTOPPED(wait_status) {
-            self.status = TraceeStatus::Stopped;
-            let signal = libc::WSTOPSIG(wait_status);
-            println!(
-                "Process ({}) stopped with signal [{}: {:?}]",
-                self.pid,
-                signal,
-                CStr::from_ptr(libc::strsignal(signal)),
-            );
-            return;
-        }
+    pub fn pid(&self) -> libc::pid_t {
+        return self.pid;
+    }
 
-        if libc::WIFEXITED(wait_status) {
-            self.status = TraceeStatus::Exited;
-            let exit_code = libc::WEXITSTATUS(wait_status);
-            println!("Process ({}) exited with code [{}]", self.pid, exit_code);
-            return;
-        }
+    pub fn status(&self) -> TraceeStatus {
+        return self.status;
+    }
 
-        if libc::WIFSIGNALED(wait_status) {
-            self.status = TraceeStatus::Terminated;
-            let signal = libc::WTERMSIG(wait_status);
-            println!(
-                "Process ({}) terminated with signal [{}: {:?}]",
-                self.pid,
-                signal,
-                CStr::from_ptr(libc::strsignal(signal)),
-            );
-            return;
-        }
+    pub fn stop_count(&self) -> u64 {
+        return self.stop_count;
+    }
 
-        unreachable!("unexpected wait status [{}]", wait_status);
+    // Returns how the process ended, once it has exited or been terminated.
+    pub fn exit_status(&self) -> Option<WaitStatus> {
+        return self.exit_status;
     }
 
-    pub unsafe fn resume(&mut self) {
-        if libc::ptrace(
-            libc::PTRACE_CONT,
-            self.pid,
-            null_mut::<*mut libc::c_void>(),
-            null_mut::<*mut libc::c_void>(),
-        ) < 0
-        {
-            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
-            panic!("failed to continue: {:?}", errno_message);
-        }
-        self.status = TraceeStatus::Running;
+    // Hands over the forked children that were kept under trace by follow-fork-mode both.
+    pub fn take_forked_tracees(&mut self) -> Vec<Tracee> {
+        return mem::take(&mut self.forked_tracees);
     }
 
-    pub unsafe fn read_general_purpose_registers(&self) -> libc::user_regs_struct {
-        let mut data = mem::MaybeUninit::<libc::user_regs_struct>::uninit();
-        let mut iov = libc::iovec {
-            iov_base: data.as_mut_ptr() as *mut libc::c_void,
-            iov_len: mem::size_of::<libc::user_regs_struct>(),
-        };
-        if libc::ptrace(
-            libc::PTRACE_GETREGSET,
-            self.pid,
-            libc::NT_PRSTATUS,
-            &mut iov as *mut libc::iovec,
-        ) < 0
-        {
-            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
-            panic!(
-                "failed to read general purpose registers: {:?}",
-                errno_message
-            );
-        };
-        let regs = data.assume_init();
-        return regs;
+    // Returns the threads of the tracee, in the order they were discovered.
+    pub fn threads(&self) -> &[Thread] {
+        return &self.threads;
     }
 
-    pub unsafe fn write_general_purpose_registers(&self, regs: &mut libc::user_regs_struct) {
-        let mut iov = libc::iovec {
-            iov_base: regs as *mut libc::user_regs_struct as *mut libc::c_void,
-            iov_len: mem::size_of::<libc::user_regs_struct>(),
-        };
-        if libc::ptrace(
-            libc::PTRACE_SETREGSET,
-            self.pid,
-            libc::NT_PRSTATUS,
-            &mut iov as *mut libc::iovec as *mut libc::c_void,
-        ) < 0
-        {
-            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
-            panic!(
-                "failed to write general purpose registers: {:?}",
-                errno_message
-            );
-        };
+    // Returns the TID of the thread that register accesses are routed to.
+    pub fn selected_tid(&self) -> libc::pid_t {
+        return self.selected_tid;
     }
 
-    pub unsafe fn read_floating_point_registers(&self) -> libc::user_fpsimd_struct {
-        let mut data = mem::MaybeUninit::<libc::user_fpsimd_struct>::uninit();
-        let mut iov = libc::iovec {
-            iov_base: data.as_mut_ptr() as *mut libc::c_void,
-            iov_len: mem::size_of::<libc::user_fpsimd_struct>(),
-        };
-        if libc::ptrace(
-            libc::PTRACE_GETREGSET,
-            self.pid,
-            libc::NT_PRFPREG,
-            &mut iov as *mut libc::iovec as *mut libc::c_void,
-        ) < 0
-        {
-            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
-            panic!(
-                "failed to read floating point registers: {:?}",
-                errno_message
-            );
-        };
-        let regs = data.assume_init();
-        return regs;
+    // Routes subsequent register accesses to the given thread. Returns false if the TID is unknown.
+    pub fn select_thread(&mut self, tid: libc::pid_t) -> bool {
+        if self.find_thread(tid).is_none() {
+            return false;
+        }
+
+        self.selected_tid = tid;
+        return true;
     }
 
-    pub unsafe fn write_floating_point_registers(&self, regs: &mut libc::user_fpsimd_struct) {
-        let mut iov = libc::iovec {
-            iov_base: regs as *mut libc::user_fpsimd_struct as *mut libc::c_void,
-            iov_len: mem::size_of::<libc::user_fpsimd_struct>(),
-        };
-        if libc::ptrace(
-            libc::PTRACE_SETREGSET,
-            self.pid,
-            libc::NT_PRFPREG,
-            &mut iov as *mut libc::iovec as *mut libc::c_void,
-        ) < 0
-        {
-            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
-            panic!(
-                "failed to write floating point registers: {:?}",
-                errno_message
-            );
-        };
+    // Returns the path of the program image that the tracee is currently executing.
+    pub fn executable(&self) -> &PathBuf {
+        return &self.executable;
     }
-}
 
-impl Drop for Tracee {
-    fn drop(&mut self) {
-        if self.pid == 0 {
-            return;
-        }
+    pub fn set_follow_fork_mode(&mut self, mode: FollowForkMode) {
+        self.follow_fork_mode = mode;
+    }
 
-        unsafe {
-            let mut wait_status = 0;
-            let wait_options = 0;
-            if self.status == TraceeStatus::Running {
-                libc::kill(self.pid, libc::SIGSTOP);
-                libc::waitpid(self.pid, &mut wait_status, wait_options);
-            }
+    pub fn set_syscall_trace_filter(&mut self, filter: Option<SyscallFilter>) {
+        self.syscall_trace_filter = filter;
+    }
 
-            libc::ptrace(
-                libc::PTRACE_DETACH,
-                self.pid,
-                null_mut::<*mut libc::c_void>(),
-                null_mut::<*mut libc::c_void>(),
-            );
+    pub fn set_syscall_trace_mode(&mut self, mode: SyscallTraceMode) {
+        self.syscall_trace_mode = mode;
+    }
 
-            libc::kill(self.pid, libc::SIGCONT);
-            libc::kill(self.pid, libc::SIGKILL);
-            libc::waitpid(self.pid, &mut wait_status, wait_options);
-        }
+    pub fn set_syscall_summary(&mut self, summary: Option<SyscallSummary>) {
+        self.syscall_summary = summary;
     }
-}
 
-#[cfg(test)]
-mod test {
-    use std::{ffi::CString, io::BufRead, ptr::null};
+    pub fn take_syscall_summary(&mut self) -> Option<SyscallSummary> {
+        return self.syscall_summary.take();
+    }
 
-    use super::Tracee;
+    pub fn set_syscall_fault(&mut self, errno: Option<libc::c_int>) {
+        self.syscall_fault = errno;
+    }
 
-    #[test]
-    fn tracee_from_pid_succeeds_when_pid_exists() {
-        unsafe {
-            match libc::fork() {
-                0 => {
-                    // Child process
-                    let program = CString::new("sleep").unwrap();
-                    let arg = CString::new("1").unwrap();
-                    let mut args = vec![arg.as_ptr()];
-                    args.push(null());
-                    libc::execvp(program.as_ptr(), args.as_ptr());
-                }
-                pid => {
-                    // Parent process
-                    Tracee::from_pid(pid);
-                }
+    // Starts or stops recording every system call, e.g. to the trace file.
+    pub fn set_auditing(&mut self, enabled: bool) {
+        self.auditing = enabled;
+        if !enabled {
+            for thread in self.threads.iter_mut() {
+                thread.audited_call = None;
             }
         }
     }
 
-    #[test]
-    #[should_panic]
-    fn tracee_from_pid_panics_when_pid_does_not_exist() {
-        unsafe {
-            Tracee::from_pid(-1);
-        }
+    pub fn signal_history(&self) -> &VecDeque<ReceivedSignal> {
+        return &self.signal_history;
     }
 
-    #[test]
+    pub fn set_signal_dispositions(&mut self, dispositions: SignalDispositions) {
+        self.signal_dispositions = dispositions;
+    }
+
+    // Starts counting the given hardware events of every thread, or stops counting if none are
+    // given.
+    pub unsafe fn set_perf_counters(&mut self, kinds: &[PerfCounterKind]) -> Result<(), PerfError> {
+        self.perf_counters = None;
+        if !kinds.is_empty() {
+            self.perf_counters = Some(PerfCounters::open(&self.tids(), kinds)?);
+        }
+        return Ok(());
+    }
+
+    pub fn set_stop_disassembly_count(&mut self, count: usize) {
+        self.stop_disassembly_count = count;
+    }
+
+    pub fn reports_stops(&self) -> bool {
+        return self.reports_stops;
+    }
+
+    pub fn set_reports_stops(&mut self, reports_stops: bool) {
+        self.reports_stops = reports_stops;
+    }
+
+    pub fn launch_options(&self) -> Option<&LaunchOptions> {
+        return self.launch_options.as_ref();
+    }
+
+    pub fn pty(&self) -> Option<&Pty> {
+        return self.pty.as_ref();
+    }
+
+    // Resolves the path of a file as the tracee sees it, e.g. of a library in its mappings, to the
+    // path that the debugger reads it from.
+    pub fn resolve_path(&self, path: &Path) -> PathBuf {
+        return procfs::resolve_path(self.root.as_deref(), path);
+    }
+
+    pub fn set_source_settings(&mut self, settings: SourceSettings) {
+        self.sources = SourceFinder::new(settings);
+    }
+
+    // Reads the lines of a source file that the line table names, wherever the settings find it.
+    pub fn read_source_lines(&self, path: &str) -> Option<Vec<String>> {
+        return self.sources.read_lines(path, || {
+            let elf = ElfFile::read(&self.resolve_path(&self.executable)).ok()?;
+            return elf.build_id().ok().flatten();
+        });
+    }
+
+    // Prints the source lines around the one that an address was compiled from.
+    pub unsafe fn print_source_listing(&self, address: u64, count: usize) {
+        let line_rows = self.read_line_rows().unwrap_or_default();
+        let Some(row) = line_rows[..line_rows.partition_point(|row| row.address <= address)].last()
+        else {
+            println!("no source for {:#x}", address);
+            return;
+        };
+        let Some(lines) = self.read_source_lines(&row.path) else {
+            println!("{}:{}: source not found", row.path, row.line);
+            return;
+        };
+        println!("{}:", row.path);
+        for line in format_listing(&lines, row.line, count) {
+            println!("{}", line);
+        }
+    }
+
+    pub fn set_trace_file(&mut self, path: Option<&Path>) -> io::Result<()> {
+        self.trace_file = match path {
+            None => None,
+            Some(path) => Some(TraceFile::open(path)?),
+        };
+        return Ok(());
+    }
+
+    pub fn set_record_queue(&mut self, enabled: bool) {
+        self.record_queue = match enabled {
+            true => Some(self.record_queue.take().unwrap_or_default()),
+            false => None,
+        };
+    }
+
+    // Takes the records queued since the last time, oldest first.
+    pub fn take_records(&mut self) -> Vec<TraceRecord> {
+        return self
+            .record_queue
+            .as_mut()
+            .map(mem::take)
+            .unwrap_or_default();
+    }
+
+    fn record(&mut self, record: TraceRecord) {
+        if let Some(record_queue) = &mut self.record_queue {
+            record_queue.push(record.clone());
+        }
+        let Some(trace_file) = &mut self.trace_file else {
+            return;
+        };
+        if let Err(err) = trace_file.write(self.pid, &record) {
+            println!("failed to write trace file, which is now closed: {}", err);
+            self.trace_file = None;
+        }
+    }
+
+    pub fn set_pass_pending_signals(&mut self, enabled: bool) {
+        self.pass_pending_signals = enabled;
+    }
+
+    pub fn set_scheduler_locking(&mut self, scheduler_locking: SchedulerLocking) {
+        self.scheduler_locking = scheduler_locking;
+    }
+
+    fn tids(&self) -> Vec<libc::pid_t> {
+        return self.threads.iter().map(|thread| thread.tid).collect();
+    }
+
+    fn find_thread(&self, tid: libc::pid_t) -> Option<&Thread> {
+        return self.threads.iter().find(|thread| thread.tid == tid);
+    }
+
+    fn find_thread_mut(&mut self, tid: libc::pid_t) -> Option<&mut Thread> {
+        return self.threads.iter_mut().find(|thread| thread.tid == tid);
+    }
+
+    // Blocks until some thread of the tracee stops or the whole process exits. Once a thread
+    // stops, every other thread is stopped as well so that it can be inspected.
+    pub unsafe fn wait_on_signal(&mut self) {
+        loop {
+            let (tid, wait_status) = reaper::wait(|tid| self.owns_task(tid));
+            if self.handle_wait_status(tid, wait_status) {
+                return;
+            }
+        }
+    }
+
+    // Handles whatever the running tracee has done so far, without blocking. Returns true once
+    // the tracee has stopped or exited, like `wait_on_signal` would.
+    pub unsafe fn poll_signal(&mut self) -> bool {
+        while let Some((tid, wait_status)) = reaper::try_wait(|tid| self.owns_task(tid)) {
+            if self.handle_wait_status(tid, wait_status) {
+                return true;
+            }
+        }
+        return false;
+    }
+
+    // Handles a state change of one of the tracee's tasks. Returns true if the tracee stopped or
+    // exited, and false if it keeps running.
+    unsafe fn handle_wait_status(&mut self, tid: libc::pid_t, wait_status: WaitStatus) -> bool {
+        if let WaitStatus::Stopped(signal, event) = wait_status {
+            let ptrace_event = self.read_event(tid, event);
+            // The code of the old program is gone at exec, along with its breakpoints, which
+            // `handle_exec` forgets.
+            if self
+                .stepped_over
+                .as_ref()
+                .is_some_and(|(stepped_tid, _)| *stepped_tid == tid)
+                && !matches!(ptrace_event, Some(PtraceEvent::Exec { .. }))
+            {
+                let (_, lifted) = self.stepped_over.take().unwrap();
+                self.restore_breakpoints(tid, lifted);
+            }
+            match ptrace_event {
+                Some(PtraceEvent::Clone { new_tid }) => {
+                    self.add_cloned_thread(new_tid);
+                    self.record(TraceRecord::Fork {
+                        tid,
+                        child: new_tid,
+                        kind: "clone",
+                    });
+                    self.resume_thread(tid);
+                    return false;
+                }
+                Some(PtraceEvent::Fork { child_pid } | PtraceEvent::Vfork { child_pid }) => {
+                    let kind = match ptrace_event {
+                        Some(PtraceEvent::Vfork { .. }) => "vfork",
+                        _ => "fork",
+                    };
+                    self.record(TraceRecord::Fork {
+                        tid,
+                        child: child_pid,
+                        kind,
+                    });
+                    self.follow_fork(tid, child_pid, kind == "vfork");
+                    return false;
+                }
+                Some(PtraceEvent::VforkDone { .. }) => {
+                    // The child has exec'd or exited, so the parent's memory is its own again.
+                    for id in mem::take(&mut self.vfork_disabled_breakpoints) {
+                        if let Err(err) = self.enable_breakpoint(id) {
+                            println!("{}", err);
+                        }
+                    }
+                    if mem::take(&mut self.vfork_lifted_call_traces) {
+                        for (address, _) in self.call_trace_breakpoints() {
+                            if let Err(err) =
+                                self.write_thread_memory(tid, address, BREAKPOINT_INSTRUCTION)
+                            {
+                                println!("{}", err);
+                            }
+                        }
+                    }
+                    self.resume_thread(tid);
+                    return false;
+                }
+                Some(PtraceEvent::Exit { status }) => {
+                    self.end_traced_call(tid, "?");
+                    self.end_audited_call(tid, "?");
+                    self.report_thread_exit(tid, status);
+                    self.resume_thread(tid);
+                    return false;
+                }
+                Some(PtraceEvent::Exec { former_tid }) => {
+                    self.handle_exec(former_tid);
+                    self.resume_thread(self.pid);
+                    return false;
+                }
+                Some(PtraceEvent::Seccomp { .. }) if self.syscall_trace_filter.is_some() => {
+                    if self.syscall_trace_mode == SyscallTraceMode::Seccomp {
+                        self.trace_syscall_stop(tid);
+                    }
+                    self.resume_thread(tid);
+                    return false;
+                }
+                Some(PtraceEvent::Seccomp { .. } | PtraceEvent::Stop) | None => {}
+            }
+
+            // Seized tasks report their initial stop as PTRACE_EVENT_STOP, others as SIGSTOP.
+            let is_initial_stop = match self.seized {
+                true => ptrace_event == Some(PtraceEvent::Stop),
+                false => signal == libc::SIGSTOP,
+            };
+
+            if is_initial_stop
+                && self.find_thread(tid).is_none()
+                && procfs::read_tgid(tid) != Some(self.pid)
+            {
+                // The initial stop of a forked child, which arrived before the fork event.
+                self.early_fork_children.push(tid);
+                return false;
+            }
+
+            let is_new_thread = match self.find_thread(tid) {
+                None => true,
+                Some(thread) => thread.status == ThreadStatus::Stopping,
+            };
+            if is_initial_stop && is_new_thread {
+                // The initial stop of a freshly cloned thread, which starts without the debug
+                // registers of the thread that cloned it.
+                if self.find_thread(tid).is_none() {
+                    self.threads.push(Thread::new(tid, ThreadStatus::Stopped));
+                }
+                self.mirror_debug_slots(tid);
+                self.resume_thread(tid);
+                return false;
+            }
+
+            if ptrace_event == Some(PtraceEvent::Stop) {
+                if JOB_CONTROL_STOP_SIGNALS.contains(&signal) {
+                    // A group-stop, e.g. from a shell's job control. Let the thread stay
+                    // stopped, but keep listening so that SIGCONT is noticed.
+                    if tid == self.pid {
+                        self.job_control_stopped = true;
+                        println!(
+                            "[Process ({}) stopped by job control signal [{}: {:?}]]",
+                            self.pid,
+                            signal,
+                            CStr::from_ptr(libc::strsignal(signal)),
+                        );
+                    }
+                    self.listen_thread(tid);
+                    return false;
+                }
+
+                // The thread was woken up from group-stop, e.g. by SIGCONT.
+                if tid == self.pid {
+                    self.job_control_stopped = false;
+                }
+                self.resume_thread(tid);
+                return false;
+            }
+
+            if signal == SYSCALL_STOP_SIGNAL {
+                self.track_maps_syscall(tid);
+            }
+
+            // Audited system calls are recorded, whatever else happens at their stops.
+            if signal == SYSCALL_STOP_SIGNAL && self.auditing {
+                self.audit_syscall_stop(tid);
+            }
+
+            if signal == SYSCALL_STOP_SIGNAL && self.replay.is_some() {
+                // Replaying stops at system calls that differ from the recording.
+                if self.handle_replay_syscall_stop(tid) {
+                    if self.syscall_trace_filter.is_some() {
+                        self.trace_syscall_stop(tid);
+                    }
+                    self.restart_thread(tid, libc::PTRACE_SYSCALL);
+                    return false;
+                }
+            } else if signal == SYSCALL_STOP_SIGNAL
+                && self.syscall_trace_filter.is_some()
+                && self.syscall_trace_mode == SyscallTraceMode::Ptrace
+            {
+                self.trace_syscall_stop(tid);
+                self.restart_thread(tid, libc::PTRACE_SYSCALL);
+                return false;
+            } else if signal == SYSCALL_STOP_SIGNAL && self.auditing && !self.stopping_at_syscall {
+                self.restart_thread(tid, libc::PTRACE_SYSCALL);
+                return false;
+            }
+
+            // Traps that pbreak sets for itself, e.g. to follow the dynamic linker or to check the
+            // condition of a watchpoint, only stop the tracee once what they wait for happens.
+            if ptrace_event.is_none() && signal == libc::SIGTRAP {
+                match self.handle_internal_trap(tid) {
+                    InternalTrap::Handled => {
+                        self.resume_thread(tid);
+                        return false;
+                    }
+                    InternalTrap::Deferred => return false,
+                    InternalTrap::Unrelated | InternalTrap::Caught => {}
+                }
+            }
+
+            let is_signal_delivery = ptrace_event.is_none() && signal != SYSCALL_STOP_SIGNAL;
+            let replayed_signal = match is_signal_delivery {
+                true => self.handle_replay_signal(tid, signal),
+                false => ReplayedSignal::Delivered,
+            };
+            if replayed_signal == ReplayedSignal::Suppressed {
+                self.resume_thread(tid);
+                return false;
+            }
+
+            if is_signal_delivery && replayed_signal == ReplayedSignal::Delivered {
+                // A signal is about to be delivered to the thread.
+                let disposition = self.signal_dispositions.get(signal);
+                let is_passed = self.passes_signal(signal);
+                if is_passed {
+                    if let Some(thread) = self.find_thread_mut(tid) {
+                        thread.pending_signal = Some(signal);
+                    }
+                }
+
+                if self.signal_history.len() == MAX_SIGNAL_HISTORY {
+                    self.signal_history.pop_front();
+                }
+                self.signal_history.push_back(ReceivedSignal {
+                    elapsed: self.attached_at.elapsed(),
+                    tid,
+                    signal,
+                    stopped: disposition.stop,
+                    passed: is_passed,
+                });
+                self.record(TraceRecord::Signal {
+                    tid,
+                    signal,
+                    stopped: disposition.stop,
+                    passed: is_passed,
+                });
+
+                if !disposition.stop {
+                    if disposition.print {
+                        println!(
+                            "[Process ({}) received signal [{}: {}]{}]",
+                            self.pid,
+                            signal,
+                            signal_name(signal),
+                            self.thread_suffix(tid),
+                        );
+                    }
+                    self.resume_thread(tid);
+                    return false;
+                }
+            }
+
+            match self.find_thread_mut(tid) {
+                None => self.threads.push(Thread::new(tid, ThreadStatus::Stopped)),
+                Some(thread) => thread.status = ThreadStatus::Stopped,
+            }
+            self.status = TraceeStatus::Stopped;
+            self.stop_count += 1;
+            self.selected_tid = tid;
+            self.stop_all_threads();
+            self.prefetch_stop_state(tid);
+            let (reason, stop_signal) = match ptrace_event {
+                Some(PtraceEvent::Seccomp { .. }) => ("seccomp", None),
+                _ if signal == SYSCALL_STOP_SIGNAL => match self.syscall_stop() {
+                    Some(SyscallStop::Exit { .. }) => ("syscall-exit", None),
+                    _ => ("syscall-entry", None),
+                },
+                _ => match self.trap() {
+                    Some(Trap::Breakpoint { .. }) => ("breakpoint", Some(signal)),
+                    Some(Trap::SingleStep) => ("single-step", Some(signal)),
+                    Some(Trap::HardwareBreakpoint { address }) => {
+                        match self.is_hardware_breakpoint(address) {
+                            true => ("hardware-breakpoint", Some(signal)),
+                            false => ("watchpoint", Some(signal)),
+                        }
+                    }
+                    _ => ("signal", Some(signal)),
+                },
+            };
+            self.record(TraceRecord::Stop {
+                tid,
+                reason,
+                signal: stop_signal,
+            });
+            if !self.reports_stops {
+                return true;
+            }
+            match ptrace_event {
+                Some(PtraceEvent::Seccomp { .. }) => self.print_syscall_stop(tid),
+                _ if signal == SYSCALL_STOP_SIGNAL => self.print_syscall_stop(tid),
+                _ => self.print_stop(tid, signal),
+            }
+            self.print_perf_counter_deltas();
+            self.print_stop_disassembly();
+            return true;
+        }
+
+        if tid != self.pid {
+            self.threads.retain(|thread| thread.tid != tid);
+            return false;
+        }
+
+        self.threads.clear();
+        cleanup::unregister_tracee(self.pid);
+        self.exit_status = Some(wait_status);
+        match wait_status {
+            WaitStatus::Exited(exit_code) => {
+                self.status = TraceeStatus::Exited;
+                self.record(TraceRecord::Exit { code: exit_code });
+                println!("Process ({}) exited with code [{}]", self.pid, exit_code);
+            }
+            WaitStatus::Signaled(signal) => {
+                self.status = TraceeStatus::Terminated;
+                self.record(TraceRecord::Terminated { signal });
+                println!(
+                    "Process ({}) terminated with signal [{}: {:?}]",
+                    self.pid,
+                    signal,
+                    CStr::from_ptr(libc::strsignal(signal)),
+                );
+            }
+            WaitStatus::Stopped(..) => unreachable!("stops should have been handled"),
+        }
+        return true;
+    }
+
+    // Whether a signal that a thread stopped with is delivered once it resumes.
+    fn passes_signal(&self, signal: libc::c_int) -> bool {
+        // Without PTRACE_SEIZE, the group-stop that a stop signal causes looks just like another
+        // delivery of it, so passing it on would keep the tracee stopping.
+        return self.signal_dispositions.get(signal).pass
+            && (self.seized || !JOB_CONTROL_STOP_SIGNALS.contains(&signal));
+    }
+
+    // Whether a task belongs to this tracee: one of its threads, or a child it has just forked.
+    fn owns_task(&self, tid: libc::pid_t) -> bool {
+        return self.find_thread(tid).is_some()
+            || procfs::read_tgid(tid) == Some(self.pid)
+            || procfs::read_ppid(tid) == Some(self.pid);
+    }
+
+    unsafe fn print_syscall_stop(&mut self, tid: libc::pid_t) {
+        let pid = self.pid;
+        let thread_suffix = self.thread_suffix(tid);
+        let syscall_stop = self.syscall_stop();
+        let Some(thread) = self.find_thread_mut(tid) else {
+            return;
+        };
+
+        match syscall_stop {
+            Some(SyscallStop::Entry { arch, number, .. }) => {
+                thread.syscall = Some(number);
+                thread.traced_call = None;
+                thread.syscall_entered_at = None;
+                println!(
+                    "Process ({}) stopped at entry of syscall [{}: {}]{}",
+                    pid,
+                    number,
+                    describe_syscall(arch, number),
+                    thread_suffix,
+                );
+            }
+            Some(SyscallStop::Exit { arch, value, .. }) => {
+                thread.traced_call = None;
+                thread.syscall_entered_at = None;
+                let syscall = match thread.syscall.take() {
+                    None => "?".to_string(),
+                    Some(number) => format!("{}: {}", number, describe_syscall(arch, number)),
+                };
+                println!(
+                    "Process ({}) stopped at exit of syscall [{}] with return value [{}]{}",
+                    pid, syscall, value, thread_suffix,
+                );
+            }
+            Some(SyscallStop::Seccomp {
+                arch, number, data, ..
+            }) => {
+                println!(
+                    "Process ({}) stopped at seccomp-traced syscall [{}: {}] with data [{}]{}",
+                    pid,
+                    number,
+                    describe_syscall(arch, number),
+                    data,
+                    thread_suffix,
+                );
+            }
+            None => unreachable!("syscall-stop should have syscall info"),
+        }
+    }
+
+    // Prints a system call like strace does once it returns, if it is selected by the filter.
+    // System calls caught by seccomp are printed right away, since their exits are not traced.
+    unsafe fn trace_syscall_stop(&mut self, tid: libc::pid_t) {
+        match self.thread_syscall_stop(tid) {
+            Some(SyscallStop::Seccomp {
+                arch, number, args, ..
+            }) => {
+                let name = describe_syscall(arch, number);
+                self.inject_syscall_fault(tid, &name);
+                if let Some(summary) = &mut self.syscall_summary {
+                    if self
+                        .syscall_trace_filter
+                        .as_ref()
+                        .is_some_and(|filter| filter.matches(&name))
+                    {
+                        summary.record(&name, Duration::ZERO, false);
+                    }
+                } else if let Some(call) = self.format_traced_call(tid, arch, number, &args) {
+                    println!("{}{}", self.trace_prefix(tid), call);
+                    // While auditing, every system call is recorded already.
+                    if !self.auditing {
+                        self.record(TraceRecord::Syscall {
+                            tid,
+                            call,
+                            result: None,
+                        });
+                    }
+                }
+            }
+            Some(SyscallStop::Entry { arch, number, args }) => {
+                self.inject_syscall_fault(tid, &describe_syscall(arch, number));
+                let call = match self.syscall_summary {
+                    Some(_) => None,
+                    None => self.format_traced_call(tid, arch, number, &args),
+                };
+                if let Some(thread) = self.find_thread_mut(tid) {
+                    thread.syscall = Some(number);
+                    thread.traced_call = call;
+                    thread.syscall_entered_at = Some(Instant::now());
+                }
+            }
+            Some(SyscallStop::Exit {
+                arch,
+                value,
+                is_error,
+            }) => {
+                let Some(thread) = self.find_thread_mut(tid) else {
+                    return;
+                };
+                let (Some(number), Some(entered_at)) =
+                    (thread.syscall, thread.syscall_entered_at.take())
+                else {
+                    // The system call was entered before tracing began.
+                    return;
+                };
+
+                let name = describe_syscall(arch, number);
+                if let Some(summary) = &mut self.syscall_summary {
+                    if self
+                        .syscall_trace_filter
+                        .as_ref()
+                        .is_some_and(|filter| filter.matches(&name))
+                    {
+                        summary.record(&name, entered_at.elapsed(), is_error);
+                    }
+                }
+                self.end_traced_call(tid, &format_return(&name, value, is_error));
+            }
+            None => {}
+        }
+    }
+
+    // Skips a traced system call that the filter selects, if a fault is being injected.
+    unsafe fn inject_syscall_fault(&self, tid: libc::pid_t, name: &str) {
+        let Some(errno) = self.syscall_fault else {
+            return;
+        };
+        if self
+            .syscall_trace_filter
+            .as_ref()
+            .is_some_and(|filter| filter.matches(name))
+        {
+            self.skip_thread_syscall(tid, -(errno as i64));
+        }
+    }
+
+    // Decodes a system call, unless the filter does not select it. Decoding reads the tracee's
+    // memory, so it is skipped for unselected system calls.
+    unsafe fn format_traced_call(
+        &self,
+        tid: libc::pid_t,
+        arch: u32,
+        number: u64,
+        args: &[u64; 6],
+    ) -> Option<String> {
+        let name = describe_syscall(arch, number);
+        if !self.syscall_trace_filter.as_ref()?.matches(&name) {
+            return None;
+        }
+
+        return Some(format_call(
+            &name,
+            args,
+            |address, max_len| self.read_thread_c_string(tid, address, max_len).ok(),
+            |address, len| self.read_thread_memory(tid, address, len).ok(),
+        ));
+    }
+
+    // Tells which thread a traced system call was made by, unless it is the main thread.
+    fn trace_prefix(&self, tid: libc::pid_t) -> String {
+        return match tid == self.pid {
+            true => String::new(),
+            false => format!("[tid {}] ", tid),
+        };
+    }
+
+    // Prints the traced system call that a thread is in, if any, along with its return value.
+    fn end_traced_call(&mut self, tid: libc::pid_t, return_value: &str) {
+        let prefix = self.trace_prefix(tid);
+        let Some(thread) = self.find_thread_mut(tid) else {
+            return;
+        };
+
+        thread.syscall = None;
+        if let Some(call) = thread.traced_call.take() {
+            println!("{}{} = {}", prefix, call, return_value);
+            // While auditing, every system call is recorded already.
+            if !self.auditing {
+                self.record(TraceRecord::Syscall {
+                    tid,
+                    call,
+                    result: Some(return_value.to_string()),
+                });
+            }
+        }
+    }
+
+    // Records a system call that a thread stopped at while auditing. Calls are decoded at their
+    // entries, and recorded along with their return values at their exits.
+    unsafe fn audit_syscall_stop(&mut self, tid: libc::pid_t) {
+        match self.thread_syscall_stop(tid) {
+            Some(SyscallStop::Entry { arch, number, args }) => {
+                let name = describe_syscall(arch, number);
+                let call = format_call(
+                    &name,
+                    &args,
+                    |address, max_len| self.read_thread_c_string(tid, address, max_len).ok(),
+                    |address, len| self.read_thread_memory(tid, address, len).ok(),
+                );
+                if let Some(thread) = self.find_thread_mut(tid) {
+                    thread.audited_call = Some((name, call));
+                }
+            }
+            Some(SyscallStop::Exit {
+                value, is_error, ..
+            }) => {
+                let Some((name, _)) = self
+                    .find_thread(tid)
+                    .and_then(|thread| thread.audited_call.as_ref())
+                else {
+                    // The system call was entered before auditing began.
+                    return;
+                };
+                let return_value = format_return(name, value, is_error);
+                self.end_audited_call(tid, &return_value);
+            }
+            Some(SyscallStop::Seccomp { .. }) | None => {}
+        }
+    }
+
+    // Records the audited system call that a thread is in, if any, along with its return value.
+    fn end_audited_call(&mut self, tid: libc::pid_t, return_value: &str) {
+        let Some((_, call)) = self
+            .find_thread_mut(tid)
+            .and_then(|thread| thread.audited_call.take())
+        else {
+            return;
+        };
+        self.record(TraceRecord::Syscall {
+            tid,
+            call,
+            result: Some(return_value.to_string()),
+        });
+    }
+
+    unsafe fn print_stop(&self, tid: libc::pid_t, signal: libc::c_int) {
+        let trap = match signal {
+            libc::SIGTRAP => self.trap(),
+            _ => None,
+        };
+        let reason = match trap {
+            Some(Trap::Breakpoint { address }) => format!("at breakpoint trap [{:#x}]", address),
+            Some(Trap::SingleStep) => "after single-step".to_string(),
+            Some(Trap::Branch) => "after branch".to_string(),
+            Some(Trap::HardwareBreakpoint { address }) => {
+                match self.is_hardware_breakpoint(address) {
+                    true => format!("at hardware breakpoint [{:#x}]", address),
+                    false => format!("at watchpoint [{:#x}]", address),
+                }
+            }
+            Some(Trap::Sent) | Some(Trap::Other(_)) | None => String::new(),
+        };
+        if !reason.is_empty() {
+            println!(
+                "Process ({}) stopped {}{}",
+                self.pid,
+                reason,
+                self.thread_suffix(tid)
+            );
+            return;
+        }
+
+        println!(
+            "Process ({}) stopped with signal [{}: {:?}]{}",
+            self.pid,
+            signal,
+            CStr::from_ptr(libc::strsignal(signal)),
+            self.thread_suffix(tid),
+        );
+    }
+
+    // Prints how much each performance counter has counted since the last stop.
+    unsafe fn print_perf_counter_deltas(&mut self) {
+        let Some(perf_counters) = &mut self.perf_counters else {
+            return;
+        };
+
+        let deltas = perf_counters
+            .read_deltas()
+            .iter()
+            .map(|(kind, delta)| format!("{} {}", kind.name(), delta))
+            .collect::<Vec<String>>();
+        println!("[Since last stop: {}]", deltas.join(", "));
+    }
+
+    // Prints `count` instructions starting at `address`, as the program has them, i.e. with the
+    // original code in place of breakpoints.
+    pub unsafe fn print_disassembly(&self, address: u64, count: usize) {
+        // The code may end at an unmapped page before the longest possible instructions do.
+        let mut len = count * MAX_INSTRUCTION_LEN;
+        let mut code = self.read_memory(address, len);
+        while code.is_err() && len > MAX_INSTRUCTION_LEN {
+            len /= 2;
+            code = self.read_memory(address, len);
+        }
+        match code {
+            Err(err) => println!("{}", err),
+            Ok(code) => self.print_instructions(address, code, count),
+        }
+    }
+
+    // Prints every instruction of a function, which must be at its runtime address.
+    pub unsafe fn print_function_disassembly(&self, function: &FunctionSymbol) {
+        match self.read_memory(function.address, function.size as usize) {
+            Err(err) => println!("{}", err),
+            Ok(code) => {
+                let count = code.len();
+                self.print_instructions(function.address, code, count);
+            }
+        }
+    }
+
+    unsafe fn print_instructions(&self, address: u64, mut code: Vec<u8>, count: usize) {
+        let patched_addresses = self.hide_breakpoints(&mut code, address);
+        let instructions = match disassemble(&code, address, count) {
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+            Ok(instructions) => instructions,
+        };
+        if instructions.is_empty() {
+            println!("no valid instruction at {:#x}", address);
+        }
+
+        // Branch targets are named after the functions they lead into, and instructions are
+        // preceded by the source lines they were compiled from, where known.
+        let functions = self.read_functions().unwrap_or_default();
+        let line_rows = self.read_line_rows().unwrap_or_default();
+        let mut source_files = HashMap::new();
+        let mut last_row: Option<&LineRow> = None;
+        let pc = self.read_general_purpose_registers().pc;
+        for instruction in instructions {
+            let first_row = line_rows.partition_point(|row| row.address < instruction.address);
+            let row = line_rows[first_row..]
+                .iter()
+                .take_while(|row| row.address == instruction.address)
+                .last();
+            if let Some(row) = row {
+                let last_path = last_row.map(|last_row| last_row.path.as_str());
+                if last_row.map(|last_row| last_row.line) != Some(row.line)
+                    || last_path != Some(&row.path)
+                {
+                    let lines = source_files
+                        .entry(row.path.clone())
+                        .or_insert_with(|| self.read_source_lines(&row.path));
+                    print_source_line(lines.as_deref(), row, last_path != Some(&row.path));
+                }
+                last_row = Some(row);
+            }
+
+            let end = instruction.address + instruction.bytes.len() as u64;
+            let is_breakpoint = patched_addresses
+                .iter()
+                .any(|patched_address| (instruction.address..end).contains(patched_address));
+            println!(
+                "{}",
+                format_instruction(
+                    &instruction,
+                    instruction.address == pc,
+                    is_breakpoint,
+                    |target| describe_address(&functions, target),
+                )
+            );
+        }
+    }
+
+    // Prints the instructions around the pc of the thread that stopped the tracee, with the
+    // current one in the middle where the preceding ones are known.
+    pub unsafe fn print_stop_disassembly(&self) {
+        if self.stop_disassembly_count == 0 {
+            return;
+        }
+
+        let pc = self.read_general_purpose_registers().pc;
+        let mut address = preceding_instruction_address(pc, (self.stop_disassembly_count - 1) / 2);
+        // The pc may be at the very start of the mapped code.
+        if self.read_memory(address, 1).is_err() {
+            address = pc;
+        }
+        self.print_disassembly(address, self.stop_disassembly_count);
+    }
+
+    // Describes the thread that an event happened in, unless it is the main thread.
+    fn thread_suffix(&self, tid: libc::pid_t) -> String {
+        return match tid == self.pid {
+            true => String::new(),
+            false => format!(
+                " in thread ({} \"{}\")",
+                tid,
+                procfs::read_comm(tid).unwrap_or_default()
+            ),
+        };
+    }
+
+    unsafe fn read_event_message(&self, tid: libc::pid_t) -> libc::c_ulong {
+        let mut message: libc::c_ulong = 0;
+        if libc::ptrace(
+            libc::PTRACE_GETEVENTMSG,
+            tid,
+            null_mut::<*mut libc::c_void>(),
+            &mut message as *mut libc::c_ulong,
+        ) < 0
+        {
+            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+            panic!(
+                "failed to read event message of tid ({}): {:?}",
+                tid, errno_message
+            );
+        }
+
+        return message;
+    }
+
+    // Decodes the ptrace event that a thread stopped at, if any.
+    unsafe fn read_event(&self, tid: libc::pid_t, event: libc::c_int) -> Option<PtraceEvent> {
+        let message = match PtraceEvent::has_message(event) {
+            true => self.read_event_message(tid),
+            false => 0,
+        };
+        return PtraceEvent::decode(event, message);
+    }
+
+    // Records the thread created by a clone event.
+    unsafe fn add_cloned_thread(&mut self, new_tid: libc::pid_t) {
+        if self.find_thread(new_tid).is_none() {
+            self.threads
+                .push(Thread::new(new_tid, ThreadStatus::Stopping));
+        }
+    }
+
+    // Reports that a thread is about to exit. The thread is removed from the thread table once it
+    // has been reaped. Exits of the thread group leader are reported as process exits instead.
+    fn report_thread_exit(&self, tid: libc::pid_t, exit_status: libc::c_int) {
+        if tid == self.pid {
+            return;
+        }
+
+        println!(
+            "[Thread ({} \"{}\") exited with code {}]",
+            tid,
+            procfs::read_comm(tid).unwrap_or_default(),
+            libc::WEXITSTATUS(exit_status),
+        );
+    }
+
+    // Handles an exec event. By the time it is reported, the kernel has already destroyed every
+    // other thread and the exec'ing thread has taken over the PID of the thread group leader.
+    unsafe fn handle_exec(&mut self, former_tid: libc::pid_t) {
+        // The exec'ing thread is still in execve(), even if it took over the TID of the leader.
+        let mut thread = Thread::new(self.pid, ThreadStatus::Stopped);
+        if let Some(former_thread) = self.find_thread_mut(former_tid) {
+            thread.syscall = former_thread.syscall.take();
+            thread.traced_call = former_thread.traced_call.take();
+            thread.audited_call = former_thread.audited_call.take();
+        }
+        self.threads = vec![thread];
+        self.selected_tid = self.pid;
+        self.executable = procfs::read_exe_path(self.pid);
+        // The files of the new program are indexed once they are looked up.
+        self.symbol_index.replace(SymbolIndex::default());
+        self.forget_maps();
+        // The new program has none of the old one's patches, nor its dynamic linker.
+        cleanup::forget_patches(self.pid);
+        self.code_patches.clear();
+        self.load_catches.clear();
+        self.rendezvous_breakpoint = None;
+        self.loaded_libraries.clear();
+        self.resolve_breaks.clear();
+        let forgotten = self.breakpoints.make_pending();
+        self.stepped_over = None;
+        self.vfork_disabled_breakpoints.clear();
+        self.watchpoints.clear();
+        // The kernel clears the debug registers of a new program.
+        self.debug_slots = None;
+        self.auxv.replace(None);
+        self.record(TraceRecord::Exec {
+            tid: former_tid,
+            path: self.executable.display().to_string(),
+            args: procfs::read_cmdline(self.pid).unwrap_or_default(),
+        });
+        // The new program has none of the old one's code, nor its call tracing breakpoints.
+        let had_call_traces = !self.call_traces.is_empty();
+        self.call_traces.clear();
+        self.return_breakpoints.clear();
+        self.vfork_lifted_call_traces = false;
+        println!(
+            "Process ({}) is executing new program: {}",
+            self.pid,
+            self.executable.display()
+        );
+        for breakpoint in forgotten {
+            println!(
+                "Deleted breakpoint {}, which was set at {:#x} in the old program",
+                breakpoint.id(),
+                breakpoint.address()
+            );
+        }
+        // Breakpoints at locations are looked up in the new program, and in the libraries that it
+        // loads until they are all found.
+        self.resolve_pending_breakpoints();
+        if !self.breakpoints.pending().is_empty() {
+            if let Err(err) = self.insert_rendezvous_breakpoint() {
+                println!("{}", err);
+            }
+        }
+        if had_call_traces {
+            println!("Stopped tracing calls, which were traced in the old program");
+        }
+    }
+
+    // Handles a fork or vfork event that stopped the given thread according to the follow-fork
+    // mode. A vforked child shares the address space of its parent until it execs or exits, and
+    // the parent stays blocked in vfork() until then.
+    unsafe fn follow_fork(&mut self, tid: libc::pid_t, child_pid: libc::pid_t, is_vfork: bool) {
+        match self
+            .early_fork_children
+            .iter()
+            .position(|pid| *pid == child_pid)
+        {
+            Some(index) => {
+                self.early_fork_children.remove(index);
+            }
+            None => {
+                reaper::wait_on_task(child_pid);
+            }
+        }
+
+        match self.follow_fork_mode {
+            FollowForkMode::Parent => {
+                // The child would trap at the breakpoints in its copy of the parent's memory with
+                // no one to handle it. A vforked child shares the parent's memory instead, so they
+                // are taken out of it until the child execs or exits, as the parent is blocked
+                // until then anyway.
+                match is_vfork {
+                    true => {
+                        self.disable_breakpoints_for_vfork();
+                        self.lift_call_traces_for_vfork(tid);
+                    }
+                    false => self.remove_breakpoints_from(child_pid),
+                }
+                detach_thread(child_pid);
+                self.resume_thread(tid);
+            }
+            FollowForkMode::Both => {
+                // A vforked child shares the parent's memory, whose words only one tracee can own,
+                // so it runs without the breakpoints until it execs or exits, as in the parent
+                // mode.
+                let child = match is_vfork {
+                    true => {
+                        self.disable_breakpoints_for_vfork();
+                        self.lift_call_traces_for_vfork(tid);
+                        Tracee::from_forked_child(child_pid, self.seized, self.follow_fork_mode)
+                    }
+                    false => self.adopt_forked_child(child_pid),
+                };
+                self.forked_tracees.push(child);
+                self.resume_thread(tid);
+            }
+            FollowForkMode::Child => {
+                if let Some(thread) = self.find_thread_mut(tid) {
+                    thread.status = ThreadStatus::Stopped;
+                }
+                self.stop_all_threads();
+                // The breakpoints are the child's from now on, in its copy of the memory, or in the
+                // memory that it shares with the parent until it execs.
+                if !is_vfork {
+                    self.remove_breakpoints_from(tid);
+                }
+                for thread in &self.threads {
+                    detach_thread(thread.tid);
+                }
+                reaper::forget_tasks(&self.tids());
+                reaper::reap_when_exited(self.pid);
+                cleanup::register_tracee(child_pid, self.seized);
+                if !cleanup::copy_patches(self.pid, child_pid) {
+                    println!(
+                        "failed to register the patches of process ({}) for cleanup",
+                        child_pid
+                    );
+                }
+                cleanup::unregister_tracee(self.pid);
+
+                println!(
+                    "Detached from process ({}), following forked child ({})",
+                    self.pid, child_pid
+                );
+                self.pid = child_pid;
+                self.threads = vec![Thread::new(child_pid, ThreadStatus::Stopped)];
+                self.selected_tid = child_pid;
+                self.resume_thread(child_pid);
+            }
+        }
+    }
+
+    // Constructs a `Tracee` for a forked child with a copy of the tracee's memory, breakpoints and
+    // all, so it gets the breakpoints, the trap at the dynamic linker's rendezvous and the words to
+    // restore along with them. Calls are only traced in the tracee.
+    unsafe fn adopt_forked_child(&self, child_pid: libc::pid_t) -> Tracee {
+        self.remove_call_traces_from(child_pid);
+        let mut child = Tracee::from_forked_child(child_pid, self.seized, self.follow_fork_mode);
+        child.breakpoints = self.breakpoints.clone();
+        child.rendezvous_breakpoint = self.rendezvous_breakpoint.clone();
+        child.loaded_libraries = self.loaded_libraries.clone();
+        child.load_catches = self.load_catches.clone();
+        if !cleanup::copy_patches(self.pid, child_pid) {
+            println!(
+                "failed to register the patches of process ({}) for cleanup",
+                child_pid
+            );
+        }
+        return child;
+    }
+
+    // Writes the instructions that the breakpoints replaced back into a process that is about to
+    // be detached from with a copy of the tracee's memory, e.g. a forked child. The tracee keeps
+    // its own breakpoints.
+    unsafe fn remove_breakpoints_from(&self, tid: libc::pid_t) {
+        let originals = self
+            .breakpoints
+            .breakpoints()
+            .iter()
+            .filter_map(|breakpoint| Some((breakpoint.address(), breakpoint.original()?)))
+            .chain(
+                self.rendezvous_breakpoint
+                    .iter()
+                    .map(|(address, original)| (*address, original.as_slice())),
+            );
+        for (address, original) in originals {
+            if let Err(err) = self.write_thread_memory(tid, address, original) {
+                println!("{}", err);
+            }
+        }
+        self.remove_call_traces_from(tid);
+    }
+
+    // Writes the instructions that the call tracing breakpoints replaced into a process that has no
+    // one to handle them, e.g. a forked child.
+    unsafe fn remove_call_traces_from(&self, tid: libc::pid_t) {
+        for (address, original) in self.call_trace_breakpoints() {
+            if let Err(err) = self.write_thread_memory(tid, address, &original) {
+                println!("{}", err);
+            }
+        }
+    }
+
+    // Takes the call tracing breakpoints out of the memory that a vforked child shares with the
+    // tracee until the vfork done event, which puts them back.
+    unsafe fn lift_call_traces_for_vfork(&mut self, tid: libc::pid_t) {
+        self.remove_call_traces_from(tid);
+        self.vfork_lifted_call_traces = true;
+    }
+
+    // Disables the enabled breakpoints until the vfork done event, which enables them again.
+    unsafe fn disable_breakpoints_for_vfork(&mut self) {
+        let ids = self
+            .breakpoints
+            .breakpoints()
+            .iter()
+            .filter(|breakpoint| breakpoint.is_enabled())
+            .map(Breakpoint::id)
+            .collect::<Vec<usize>>();
+        for id in ids {
+            match self.disable_breakpoint(id) {
+                Err(err) => println!("{}", err),
+                Ok(()) => self.vfork_disabled_breakpoints.push(id),
+            }
+        }
+    }
+
+    // Stops every running thread and waits until each one has reported its stop.
+    unsafe fn stop_all_threads(&mut self) {
+        let running_tids = self
+            .threads
+            .iter()
+            .filter(|thread| thread.status == ThreadStatus::Running)
+            .map(|thread| thread.tid)
+            .collect::<Vec<libc::pid_t>>();
+        for tid in running_tids {
+            match self.seized {
+                true => interrupt_thread(tid),
+                false => {
+                    libc::syscall(libc::SYS_tgkill, self.pid, tid, libc::SIGSTOP);
+                }
+            }
+
+            if let Some(thread) = self.find_thread_mut(tid) {
+                thread.status = ThreadStatus::Stopping;
+            }
+        }
+
+        self.wait_on_stopping_threads();
+    }
+
+    unsafe fn wait_on_stopping_threads(&mut self) {
+        while let Some(tid) = self
+            .threads
+            .iter()
+            .find(|thread| thread.status == ThreadStatus::Stopping)
+            .map(|thread| thread.tid)
+        {
+            let WaitStatus::Stopped(signal, event) = reaper::wait_on_task(tid) else {
+                self.threads.retain(|thread| thread.tid != tid);
+                continue;
+            };
+
+            // Without PTRACE_SEIZE, threads are stopped with SIGSTOP, which must not be passed on.
+            let is_stopping_signal = !self.seized && signal == libc::SIGSTOP;
+            if event == 0 && signal != SYSCALL_STOP_SIGNAL && !is_stopping_signal {
+                // Some other signal arrived first. Keep it, rather than losing it to the stop.
+                if self.passes_signal(signal) {
+                    if let Some(thread) = self.find_thread_mut(tid) {
+                        thread.pending_signal = Some(signal);
+                    }
+                }
+                if !self.seized {
+                    // Deliver it now, since the SIGSTOP that stops the thread is still to come.
+                    self.restart_thread(tid, libc::PTRACE_CONT);
+                    if let Some(thread) = self.find_thread_mut(tid) {
+                        thread.status = ThreadStatus::Stopping;
+                    }
+                    continue;
+                }
+            }
+
+            match self.read_event(tid, event) {
+                Some(PtraceEvent::Clone { new_tid }) => {
+                    self.add_cloned_thread(new_tid);
+                    self.record(TraceRecord::Fork {
+                        tid,
+                        child: new_tid,
+                        kind: "clone",
+                    });
+                }
+                Some(PtraceEvent::Exit { status }) => self.report_thread_exit(tid, status),
+                _ => {}
+            }
+
+            if let Some(thread) = self.find_thread_mut(tid) {
+                thread.status = ThreadStatus::Stopped;
+            }
+        }
+    }
+
+    // Seizes a thread and interrupts it, without sending it any signal.
+    unsafe fn seize_thread(&mut self, tid: libc::pid_t) {
+        if libc::ptrace(
+            libc::PTRACE_SEIZE,
+            tid,
+            null_mut::<*mut libc::c_void>(),
+            TRACE_OPTIONS as *mut libc::c_void,
+        ) < 0
+        {
+            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+            panic!("failed to attach to tid ({}): {:?}", tid, errno_message);
+        }
+
+        interrupt_thread(tid);
+        self.threads.push(Thread::new(tid, ThreadStatus::Stopping));
+    }
+
+    // Lets a thread in group-stop remain stopped, while still reporting when it is woken up.
+    unsafe fn listen_thread(&mut self, tid: libc::pid_t) {
+        self.forget_stop_state();
+        if libc::ptrace(
+            libc::PTRACE_LISTEN,
+            tid,
+            null_mut::<*mut libc::c_void>(),
+            null_mut::<*mut libc::c_void>(),
+        ) < 0
+        {
+            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+            panic!("failed to listen on tid ({}): {:?}", tid, errno_message);
+        }
+
+        if let Some(thread) = self.find_thread_mut(tid) {
+            thread.status = ThreadStatus::Running;
+        }
+    }
+
+    // Reads the auxiliary vector of the program, which is read from /proc once per exec.
+    pub fn read_auxv(&self) -> Option<AuxiliaryVector> {
+        if self.auxv.borrow().is_none() {
+            self.auxv.replace(AuxiliaryVector::read(self.pid));
+        }
+        return self.auxv.borrow().clone();
+    }
+
+    // Reads the entry point of the program, i.e. AT_ENTRY.
+    fn read_entry(&self) -> Option<u64> {
+        return self.read_auxv()?.entry();
+    }
+
+    // Runs a program that was just exec'ed to its entry point, i.e. AT_ENTRY. Dynamically linked
+    // programs start in the dynamic linker, which loads their libraries before jumping there.
+    unsafe fn run_to_entry(&mut self) {
+        let Some(entry) = self.read_entry() else {
+            println!("failed to stop at the entry point: AT_ENTRY is missing");
+            return;
+        };
+        // Static executables start there.
+        if self.read_general_purpose_registers().pc == entry {
+            return;
+        }
+
+        let original = match self.patch_memory(entry, BREAKPOINT_INSTRUCTION) {
+            Err(err) => {
+                println!("failed to stop at the entry point: {}", err);
+                return;
+            }
+            Ok(original) => original,
+        };
+        self.resume();
+        self.wait_on_signal();
+        if self.status != TraceeStatus::Stopped {
+            return;
+        }
+        if let Err(err) = self.unpatch_memory(entry, &original) {
+            println!("{}", err);
+        }
+        // The pc stays at the trap instruction, which is now the original one again.
+        if self.read_general_purpose_registers().pc == entry {
+            println!("Stopped at the entry point ({:#x})", entry);
+        }
+    }
+
+    // Runs the shell that a program was launched through until it execs the program, which is then
+    // stopped like a program that was launched directly. Only exec is traced until then, so the
+    // shell's own children, e.g. of command substitutions, are left alone.
+    unsafe fn resume_until_exec(&mut self) {
+        self.forget_stop_state();
+        self.forget_maps();
+        if libc::ptrace(
+            libc::PTRACE_SETOPTIONS,
+            self.pid,
+            null_mut::<*mut libc::c_void>(),
+            libc::PTRACE_O_TRACEEXEC as *mut libc::c_void,
+        ) < 0
+        {
+            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+            panic!(
+                "failed to set trace options of shell ({}): {:?}",
+                self.pid, errno_message
+            );
+        }
+
+        let mut signal = 0;
+        loop {
+            if libc::ptrace(
+                libc::PTRACE_CONT,
+                self.pid,
+                null_mut::<*mut libc::c_void>(),
+                signal as *mut libc::c_void,
+            ) < 0
+            {
+                let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+                panic!(
+                    "failed to continue shell ({}): {:?}",
+                    self.pid, errno_message
+                );
+            }
+            match reaper::wait_on_task(self.pid) {
+                WaitStatus::Stopped(_, libc::PTRACE_EVENT_EXEC) => return,
+                // Signals are the shell's to handle.
+                WaitStatus::Stopped(stop_signal, _) => signal = stop_signal,
+                WaitStatus::Exited(code) => {
+                    self.status = TraceeStatus::Exited;
+                    panic!(
+                        "shell exited with code {} before exec'ing the program",
+                        code
+                    );
+                }
+                WaitStatus::Signaled(signal) => {
+                    self.status = TraceeStatus::Terminated;
+                    panic!(
+                        "shell was terminated by {} before exec'ing the program",
+                        signal_name(signal)
+                    );
+                }
+            }
+        }
+    }
+
+    unsafe fn set_trace_options(&self, tid: libc::pid_t) {
+        if libc::ptrace(
+            libc::PTRACE_SETOPTIONS,
+            tid,
+            null_mut::<*mut libc::c_void>(),
+            TRACE_OPTIONS as *mut libc::c_void,
+        ) < 0
+        {
+            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+            panic!(
+                "failed to set trace options of tid ({}): {:?}",
+                tid, errno_message
+            );
+        }
+    }
+
+    pub unsafe fn resume(&mut self) {
+        self.update_pending_signals(None);
+        self.resume_threads();
+    }
+
+    // Stops a tracee that was resumed without waiting on it, e.g. by `continue &`, and selects the
+    // given thread if any. Returns false if the thread does not belong to the tracee.
+    pub unsafe fn interrupt(&mut self, tid: Option<libc::pid_t>) -> bool {
+        if tid.is_some_and(|tid| self.find_thread(tid).is_none()) {
+            return false;
+        }
+        // Anything that already happened takes precedence, e.g. if the tracee has just exited.
+        if self.status != TraceeStatus::Running || self.poll_signal() {
+            return true;
+        }
+
+        // Threads in group-stop are interrupted like any other, after which they are stopped as
+        // usual, and resuming them ends the group-stop.
+        self.stop_all_threads();
+        self.status = TraceeStatus::Stopped;
+        self.stop_count += 1;
+        if let Some(tid) = tid.filter(|tid| self.find_thread(*tid).is_some()) {
+            self.selected_tid = tid;
+        }
+        if self.find_thread(self.selected_tid).is_none() {
+            self.selected_tid = self.pid;
+        }
+
+        let job_control = match self.job_control_stopped {
+            true => " while stopped by job control",
+            false => "",
+        };
+        self.job_control_stopped = false;
+        println!(
+            "Process ({}) interrupted{}{}",
+            self.pid,
+            job_control,
+            self.thread_suffix(self.selected_tid)
+        );
+        self.record(TraceRecord::Stop {
+            tid: self.selected_tid,
+            reason: "interrupt",
+            signal: None,
+        });
+        self.print_perf_counter_deltas();
+        self.print_stop_disassembly();
+        return true;
+    }
+
+    // Briefly stops the running threads of a running tracee to unwind their stacks, then resumes
+    // them. Nothing is printed, so that the tracee can be sampled many times a second.
+    pub unsafe fn sample_stacks(&mut self) -> Vec<(libc::pid_t, Vec<Frame>)> {
+        let running_tids = self
+            .threads
+            .iter()
+            .filter(|thread| thread.status == ThreadStatus::Running)
+            .map(|thread| thread.tid)
+            .collect::<Vec<libc::pid_t>>();
+        self.stop_all_threads();
+
+        let mut stacks = vec![];
+        for tid in running_tids {
+            if self
+                .find_thread(tid)
+                .is_some_and(|thread| thread.status == ThreadStatus::Stopped)
+            {
+                stacks.push((tid, unwind_thread(self, tid)));
+                self.resume_thread(tid);
+            }
+        }
+        return stacks;
+    }
+
+    // Resumes the tracee, delivering the given signal to the selected thread instead of the one
+    // that it stopped with.
+    pub unsafe fn resume_with_signal(&mut self, signal: libc::c_int) {
+        self.update_pending_signals(Some(signal));
+        self.resume_threads();
+    }
+
+    unsafe fn resume_threads(&mut self) {
+        if self.status == TraceeStatus::Exited || self.status == TraceeStatus::Terminated {
+            panic!(
+                "failed to continue: process ({}) is no longer alive",
+                self.pid
+            );
+        }
+
+        self.stopping_at_syscall = false;
+        let tids = self.tids_to_resume(false);
+        let set_aside = self.step_over_breakpoints(&tids);
+        for tid in tids.into_iter().filter(|tid| !set_aside.contains(tid)) {
+            self.resume_thread(tid);
+        }
+        self.status = TraceeStatus::Running;
+    }
+
+    // Steps the threads that are at a breakpoint over it, with the breakpoint out of their way
+    // meanwhile, since they would stop at it again right away otherwise. Every other thread is
+    // stopped, so none can run past it unseen. Returns the threads that stopped at an event or
+    // ended instead, which are not to be resumed.
+    unsafe fn step_over_breakpoints(&mut self, tids: &[libc::pid_t]) -> Vec<libc::pid_t> {
+        let mut set_aside = vec![];
+        for tid in tids {
+            let lifted = self.lift_breakpoints(*tid);
+            if lifted.is_empty() {
+                continue;
+            }
+            let step = self.step_thread(*tid);
+            self.forget_stop_state();
+            match step {
+                Ok(StepEnd::Stepped) => self.restore_breakpoints(*tid, lifted),
+                // The code of the old program is gone, along with its breakpoints, which
+                // `handle_exec` forgets.
+                Ok(StepEnd::Event { is_exec: true }) => set_aside.push(*tid),
+                Ok(StepEnd::Event { is_exec: false }) => {
+                    self.restore_breakpoints(*tid, lifted);
+                    set_aside.push(*tid);
+                }
+                // The thread has no debug registers to write back anymore.
+                Err(err) => {
+                    println!("{}", err);
+                    self.restore_breakpoints(
+                        *tid,
+                        LiftedBreakpoints {
+                            hw_breakpoint: false,
+                            ..lifted
+                        },
+                    );
+                    set_aside.push(*tid);
+                }
+            }
+        }
+        return set_aside;
+    }
+
+    // Takes the breakpoints at a thread's pc out of its way: an enabled software breakpoint has the
+    // original instruction put back, and a hardware breakpoint that the thread stopped at is
+    // cleared in the thread's own debug registers only.
+    unsafe fn lift_breakpoints(&mut self, tid: libc::pid_t) -> LiftedBreakpoints {
+        let mut lifted = LiftedBreakpoints::default();
+        let pc = self.read_thread_general_purpose_registers(tid).pc;
+        if let Some(id) = self
+            .breakpoints
+            .find(pc)
+            .filter(|breakpoint| breakpoint.is_enabled())
+            .map(Breakpoint::id)
+        {
+            match self.disable_breakpoint(id) {
+                Err(err) => println!("{}", err),
+                Ok(()) => lifted.breakpoint = Some(id),
+            }
+        }
+
+        let Some(slots) = self.debug_slots.clone() else {
+            return lifted;
+        };
+        let slots = slots.slots(SlotKind::Breakpoint);
+        let Some(Trap::HardwareBreakpoint { address }) = self
+            .read_thread_siginfo(tid)
+            .map(|info| classify_trap(&info))
+        else {
+            return lifted;
+        };
+        // The pc may have been moved away since, e.g. with `jump`.
+        if address != pc {
+            return lifted;
+        }
+        let Some(index) = slots
+            .iter()
+            .position(|slot| slot.is_some_and(|slot| slot.address == address))
+        else {
+            return lifted;
+        };
+        let mut stepping_slots = slots.to_vec();
+        stepping_slots[index] = None;
+        match write_slots(tid, SlotKind::Breakpoint, &stepping_slots) {
+            Err(err) => println!("{}", err),
+            Ok(()) => lifted.hw_breakpoint = true,
+        }
+        return lifted;
+    }
+
+    // Puts back what `lift_breakpoints` took out of a thread's way, once it has stepped.
+    unsafe fn restore_breakpoints(&mut self, tid: libc::pid_t, lifted: LiftedBreakpoints) {
+        if let Some(id) = lifted.breakpoint {
+            if let Err(err) = self.enable_breakpoint(id) {
+                println!("{}", err);
+            }
+        }
+        if !lifted.hw_breakpoint {
+            return;
+        }
+        let Some(slots) = self.debug_slots.clone() else {
+            return;
+        };
+        if let Err(err) = write_slots(tid, SlotKind::Breakpoint, slots.slots(SlotKind::Breakpoint))
+        {
+            println!("{}", err);
+        }
+    }
+
+    // Decides which signals are delivered when resuming. Signals that threads stopped with are
+    // discarded unless configured otherwise, and the given signal replaces the one of the
+    // selected thread.
+    fn update_pending_signals(&mut self, signal: Option<libc::c_int>) {
+        let selected_tid = self.selected_tid;
+        for thread in self.threads.iter_mut() {
+            if !self.pass_pending_signals {
+                thread.pending_signal = None;
+            }
+            if thread.tid == selected_tid && signal.is_some() {
+                thread.pending_signal = signal;
+            }
+        }
+    }
+
+    // Resumes the tracee until a thread enters or exits a system call.
+    pub unsafe fn resume_until_syscall(&mut self) {
+        self.update_pending_signals(None);
+        if self.status == TraceeStatus::Exited || self.status == TraceeStatus::Terminated {
+            panic!(
+                "failed to continue: process ({}) is no longer alive",
+                self.pid
+            );
+        }
+
+        self.stopping_at_syscall = true;
+        let tids = self.tids_to_resume(true);
+        let set_aside = self.step_over_breakpoints(&tids);
+        for tid in tids.into_iter().filter(|tid| !set_aside.contains(tid)) {
+            self.restart_thread(tid, libc::PTRACE_SYSCALL);
+        }
+        self.status = TraceeStatus::Running;
+    }
+
+    // Resumes only the selected thread for a single instruction, delivering the given signal to it
+    // if any.
+    pub unsafe fn step(&mut self, signal: Option<libc::c_int>) {
+        self.update_pending_signals(signal);
+        if self.status == TraceeStatus::Exited || self.status == TraceeStatus::Terminated {
+            panic!("failed to step: process ({}) is no longer alive", self.pid);
+        }
+
+        // The thread would stop at a breakpoint at its pc without running anything, so it is
+        // stepped over it like when resuming, but the breakpoint is only put back once the thread
+        // stops. Only this thread runs meanwhile.
+        let lifted = self.lift_breakpoints(self.selected_tid);
+        if !lifted.is_empty() {
+            self.stepped_over = Some((self.selected_tid, lifted));
+        }
+        self.restart_thread(self.selected_tid, libc::PTRACE_SINGLESTEP);
+        self.status = TraceeStatus::Running;
+    }
+
+    // Steps the selected thread over one instruction, and waits until it has. Returns its new pc, or
+    // None if the process ended instead. The stop is only reported if it was for something else than
+    // the step, e.g. a signal that arrived meanwhile.
+    pub unsafe fn step_instruction(&mut self) -> Option<u64> {
+        let reports_stops = self.reports_stops;
+        self.reports_stops = false;
+        self.step(None);
+        self.wait_on_signal();
+        self.reports_stops = reports_stops;
+        if self.status != TraceeStatus::Stopped {
+            return None;
+        }
+        if reports_stops && self.trap() != Some(Trap::SingleStep) {
+            if let Some(info) = self.read_siginfo() {
+                self.print_stop(self.selected_tid, info.si_signo);
+            }
+        }
+        return Some(self.read_general_purpose_registers().pc);
+    }
+
+    // Detaches from the stopped tracee, which carries on untraced. Code that was patched into it
+    // is restored first.
+    pub unsafe fn detach(mut self) {
+        for (address, original) in cleanup::patches(self.pid).iter().rev() {
+            if let Err(err) = self.write_memory(*address, &original.to_ne_bytes()) {
+                println!("{}", err);
+            }
+        }
+        for thread in &self.threads {
+            detach_thread(thread.tid);
+        }
+        reaper::forget_tasks(&self.tids());
+        reaper::reap_when_exited(self.pid);
+        cleanup::unregister_tracee(self.pid);
+        println!("Detached from process ({})", self.pid);
+
+        // There is nothing left for dropping to clean up.
+        self.pid = 0;
+    }
+
+    // Starts recording what the tracee does that may differ from run to run.
+    pub fn start_recording(&mut self) {
+        self.replay = Some(ReplayMode::Recording {
+            events: vec![],
+            entries: HashMap::new(),
+        });
+    }
+
+    // Stops recording, returning what was recorded, which ends where the tracee is now.
+    pub unsafe fn stop_recording(&mut self) -> Option<ReplayLog> {
+        let Some(ReplayMode::Recording { events, .. }) = self.replay.take() else {
+            return None;
+        };
+        let end_pc = match self.status {
+            TraceeStatus::Stopped => Some(self.read_general_purpose_registers().pc),
+            _ => None,
+        };
+        return Some(ReplayLog { events, end_pc });
+    }
+
+    // Starts replaying a recording, which must have started where the stopped tracee is now.
+    pub unsafe fn start_replaying(&mut self, log: ReplayLog) {
+        self.replay = Some(ReplayMode::Replaying {
+            log,
+            position: 0,
+            skipped_tid: None,
+            end_breakpoint: None,
+        });
+        self.advance_replay(self.selected_tid);
+    }
+
+    // Stops recording or replaying, without keeping anything.
+    pub unsafe fn stop_replay(&mut self) {
+        self.end_replay(self.selected_tid);
+    }
+
+    unsafe fn end_replay(&mut self, tid: libc::pid_t) {
+        if let Some(ReplayMode::Replaying {
+            log,
+            end_breakpoint: Some(original),
+            ..
+        }) = self.replay.take()
+        {
+            if let Err(err) = self.unpatch_thread_memory(tid, log.end_pc.unwrap_or(0), &original) {
+                println!("{}", err);
+            }
+        }
+    }
+
+    // Describes the recording or replay, if any, e.g. "replaying, at event 3 of 10".
+    pub fn replay_status(&self) -> Option<String> {
+        return match self.replay.as_ref()? {
+            ReplayMode::Recording { events, .. } => {
+                Some(format!("recording, {} events so far", events.len()))
+            }
+            ReplayMode::Replaying { log, position, .. } => Some(format!(
+                "replaying, at event {} of {}",
+                position,
+                log.events.len()
+            )),
+        };
+    }
+
+    // Records or replays a system call that a thread stopped at. Returns false if it differs from
+    // the recording, which then stops being replayed.
+    unsafe fn handle_replay_syscall_stop(&mut self, tid: libc::pid_t) -> bool {
+        let syscall_stop = self.thread_syscall_stop(tid);
+        return match self.replay {
+            Some(ReplayMode::Recording { .. }) => {
+                self.record_syscall_stop(tid, syscall_stop);
+                true
+            }
+            Some(ReplayMode::Replaying { .. }) => self.replay_syscall_stop(tid, syscall_stop),
+            None => true,
+        };
+    }
+
+    unsafe fn record_syscall_stop(&mut self, tid: libc::pid_t, syscall_stop: Option<SyscallStop>) {
+        let Some(ReplayMode::Recording { entries, .. }) = &mut self.replay else {
+            return;
+        };
+        match syscall_stop {
+            Some(SyscallStop::Entry { arch, number, args }) => {
+                if is_replayed_syscall(&describe_syscall(arch, number)) {
+                    entries.insert(tid, (number, args));
+                }
+            }
+            Some(SyscallStop::Exit { arch, value, .. }) => {
+                // System calls that were entered before recording began are left out.
+                let Some((number, args)) = entries.remove(&tid) else {
+                    return;
+                };
+                let read_memory = |address, len| self.read_thread_memory(tid, address, len).ok();
+                let writes =
+                    syscall_outputs(&describe_syscall(arch, number), &args, value, read_memory)
+                        .into_iter()
+                        .filter_map(|(address, len)| Some((address, read_memory(address, len)?)))
+                        .collect();
+                if let Some(ReplayMode::Recording { events, .. }) = &mut self.replay {
+                    events.push(ReplayEvent::Syscall {
+                        number,
+                        value,
+                        writes,
+                    });
+                }
+            }
+            Some(SyscallStop::Seccomp { .. }) | None => {}
+        }
+    }
+
+    // Skips a replayed system call at its entry, and makes it return what it did in the recording
+    // at its exit.
+    unsafe fn replay_syscall_stop(
+        &mut self,
+        tid: libc::pid_t,
+        syscall_stop: Option<SyscallStop>,
+    ) -> bool {
+        let Some(ReplayMode::Replaying {
+            log,
+            position,
+            skipped_tid,
+            ..
+        }) = &mut self.replay
+        else {
+            return true;
+        };
+        match syscall_stop {
+            Some(SyscallStop::Entry { arch, number, .. }) => {
+                let name = describe_syscall(arch, number);
+                if !is_replayed_syscall(&name) {
+                    return true;
+                }
+                let Some(ReplayEvent::Syscall {
+                    number: recorded_number,
+                    value,
+                    ..
+                }) = log.events.get(*position)
+                else {
+                    return true;
+                };
+                if *recorded_number != number {
+                    println!(
+                        "[Replay diverged at event {}: expected syscall [{}], got [{}]]",
+                        position,
+                        describe_syscall(arch, *recorded_number),
+                        name
+                    );
+                    self.end_replay(tid);
+                    return false;
+                }
+                *skipped_tid = Some(tid);
+                let value = *value;
+                self.skip_thread_syscall(tid, value);
+            }
+            Some(SyscallStop::Exit { .. }) => {
+                if *skipped_tid != Some(tid) {
+                    return true;
+                }
+                *skipped_tid = None;
+                let Some(ReplayEvent::Syscall { value, writes, .. }) =
+                    log.events.get(*position).cloned()
+                else {
+                    return true;
+                };
+                *position += 1;
+                for (address, bytes) in writes {
+                    if let Err(err) = self.write_thread_memory(tid, address, &bytes) {
+                        println!("failed to replay syscall: {}", err);
+                    }
+                }
+                let mut regs = self.read_thread_general_purpose_registers(tid);
+                regs.regs[0] = value as u64;
+                self.write_thread_general_purpose_registers(tid, &mut regs);
+                self.advance_replay(tid);
+            }
+            Some(SyscallStop::Seccomp { .. }) | None => {}
+        }
+        return true;
+    }
+
+    // Sends the thread the signals that arrived next in the recording, and once every event has
+    // been replayed, sets a breakpoint where the recording ended.
+    unsafe fn advance_replay(&mut self, tid: libc::pid_t) {
+        let Some(ReplayMode::Replaying {
+            log,
+            position,
+            end_breakpoint,
+            ..
+        }) = &mut self.replay
+        else {
+            return;
+        };
+        while let Some(ReplayEvent::Signal { signal }) = log.events.get(*position) {
+            libc::syscall(libc::SYS_tgkill, self.pid, tid, *signal);
+            *position += 1;
+        }
+        if *position < log.events.len() || end_breakpoint.is_some() {
+            return;
+        }
+        let Some(end_pc) = log.end_pc else {
+            return;
+        };
+
+        match self.patch_thread_memory(tid, end_pc, BREAKPOINT_INSTRUCTION) {
+            Err(err) => println!("failed to stop at the end of the recording: {}", err),
+            Ok(original) => {
+                if let Some(ReplayMode::Replaying { end_breakpoint, .. }) = &mut self.replay {
+                    *end_breakpoint = Some(original);
+                }
+            }
+        }
+    }
+
+    // Records an asynchronous signal that a thread stopped with. While replaying, such signals
+    // are discarded, except for those of the recording, which pbreak sends itself.
+    unsafe fn handle_replay_signal(
+        &mut self,
+        tid: libc::pid_t,
+        signal: libc::c_int,
+    ) -> ReplayedSignal {
+        if self.replay.is_none() {
+            return ReplayedSignal::Delivered;
+        }
+        let (code, sender) = match self.read_thread_siginfo(tid) {
+            None => (0, 0),
+            Some(info) => (info.si_code, info.si_pid()),
+        };
+        let is_passed = self.passes_signal(signal);
+        let pc = self.read_thread_general_purpose_registers(tid).pc;
+        match &mut self.replay {
+            Some(ReplayMode::Recording { events, .. })
+                if is_passed && is_asynchronous_signal(signal, code) =>
+            {
+                events.push(ReplayEvent::Signal { signal });
+            }
+            Some(ReplayMode::Replaying {
+                log,
+                end_breakpoint,
+                ..
+            }) => {
+                if signal == libc::SIGTRAP && end_breakpoint.is_some() && log.end_pc == Some(pc) {
+                    self.end_replay(tid);
+                    println!("[Replay reached the end of the recording]");
+                    return ReplayedSignal::End;
+                }
+                let is_replayed = code == SI_TKILL && sender == libc::getpid();
+                if is_asynchronous_signal(signal, code) && !is_replayed {
+                    return ReplayedSignal::Suppressed;
+                }
+            }
+            Some(ReplayMode::Recording { .. }) | None => {}
+        }
+        return ReplayedSignal::Delivered;
+    }
+
+    // Calls a function in the selected thread of the stopped tracee, with its arguments passed as
+    // the AAPCS64 has it, and returns what it returns in x0. The function returns to a breakpoint
+    // at the entry point of the program, which has run by then, and only the selected thread runs
+    // meanwhile. Its registers and pending signal are restored afterwards, even if the function
+    // stops before returning, e.g. at a breakpoint or a signal.
+    pub unsafe fn call_function(
+        &mut self,
+        address: u64,
+        args: &[CallArgument],
+    ) -> Result<u64, TraceeError> {
+        if self.status != TraceeStatus::Stopped {
+            return Err(TraceeError::Call("the process is not stopped"));
+        }
+        if self.syscall_stop().is_some() {
+            return Err(TraceeError::Call("the thread is in a system call"));
+        }
+        let (integers, floats): (Vec<CallArgument>, Vec<CallArgument>) = args
+            .iter()
+            .partition(|arg| matches!(arg, CallArgument::Integer(_)));
+        if integers.len() > MAX_CALL_ARGUMENTS || floats.len() > MAX_CALL_ARGUMENTS {
+            return Err(TraceeError::Call("too many arguments"));
+        }
+        let Some(entry) = self.read_entry() else {
+            return Err(TraceeError::Call("AT_ENTRY is missing"));
+        };
+
+        let tid = self.selected_tid;
+        let mut saved_regs = self.read_general_purpose_registers();
+        let mut saved_fp_regs = self.read_floating_point_registers();
+        // A breakpoint that is enabled at the entry point already is the trap to return to.
+        let original = match self.has_enabled_breakpoint(entry) {
+            true => None,
+            false => Some(self.patch_memory(entry, BREAKPOINT_INSTRUCTION)?),
+        };
+        let mut regs = saved_regs;
+        let mut fp_regs = saved_fp_regs;
+        for (i, arg) in integers.iter().enumerate() {
+            if let CallArgument::Integer(value) = arg {
+                regs.regs[i] = *value;
+            }
+        }
+        for (i, arg) in floats.iter().enumerate() {
+            if let CallArgument::Float(value) = arg {
+                fp_regs.vregs[i] = value.to_bits() as u128;
+            }
+        }
+        regs.regs[30] = entry;
+        regs.pc = address;
+        regs.sp = (saved_regs.sp - CALL_STACK_GAP) & !0xf;
+        self.write_general_purpose_registers(&mut regs);
+        self.write_floating_point_registers(&mut fp_regs);
+
+        // A signal that the thread stopped with is kept for when it resumes from where it was.
+        let pending_signal = self
+            .find_thread_mut(tid)
+            .and_then(|thread| thread.pending_signal.take());
+        let reports_stops = self.reports_stops;
+        self.reports_stops = false;
+        self.stopping_at_syscall = false;
+        self.restart_thread(tid, libc::PTRACE_CONT);
+        self.status = TraceeStatus::Running;
+        self.wait_on_signal();
+        self.reports_stops = reports_stops;
+        if self.status != TraceeStatus::Stopped {
+            return Err(TraceeError::Call(
+                "the process ended before the function returned",
+            ));
+        }
+
+        // The pc stays at the trap instruction once the function returns.
+        let returned_regs = self.read_thread_general_purpose_registers(tid);
+        let has_returned = self.selected_tid == tid && returned_regs.pc == entry;
+        self.selected_tid = tid;
+        if let Some(original) = &original {
+            self.unpatch_memory(entry, original)?;
+        }
+        self.write_general_purpose_registers(&mut saved_regs);
+        self.write_floating_point_registers(&mut saved_fp_regs);
+        if let Some(thread) = self.find_thread_mut(tid) {
+            thread.pending_signal = pending_signal;
+        }
+        return match has_returned {
+            true => Ok(returned_regs.regs[0]),
+            false => Err(TraceeError::Call(
+                "the function stopped before returning; the thread was restored",
+            )),
+        };
+    }
+
+    // Reads how to unwind the instruction at the address from the call frame information of the
+    // file mapped there. Returns None if the address is not in a file, or the file does not cover
+    // it.
+    pub unsafe fn read_unwind_row(&self, address: u64) -> Result<Option<UnwindRow>, TraceeError> {
+        let mappings = self.read_maps();
+        let Some(mapping) = mappings.iter().find(|mapping| {
+            return (mapping.start..mapping.end).contains(&address)
+                && mapping.path.starts_with('/');
+        }) else {
+            return Ok(None);
+        };
+        let path = mapping.path.clone();
+        let elf = ElfFile::read(&self.resolve_path(Path::new(&path)))?;
+        let Some(bias) = load_bias(&elf, &path, &mappings)? else {
+            return Err(TraceeError::NotMapped { path });
+        };
+        return find_unwind_row(&elf, address - bias)
+            .map_err(|source| TraceeError::ReadCallFrames { path, source });
+    }
+
+    // Pops the frame of the function that the selected thread is in, as if the function had
+    // returned: the caller's registers are restored as the call frame information has it, and the
+    // pc is set to the return address. Returns the new pc.
+    pub unsafe fn pop_frame(&mut self) -> Result<u64, TraceeError> {
+        if self.status != TraceeStatus::Stopped {
+            return Err(TraceeError::Return("the process is not stopped"));
+        }
+        if self.syscall_stop().is_some() {
+            return Err(TraceeError::Return("the thread is in a system call"));
+        }
+        let mut regs = self.read_general_purpose_registers();
+        let mut fp_regs = self.read_floating_point_registers();
+        let Some(row) = self.read_unwind_row(regs.pc)? else {
+            return Err(TraceeError::Return(
+                "no call frame information covers the pc",
+            ));
+        };
+
+        let mut dwarf_regs = [0u64; DWARF_REGISTER_COUNT];
+        dwarf_regs[..31].copy_from_slice(&regs.regs);
+        dwarf_regs[DWARF_SP] = regs.sp;
+        for (i, vreg) in fp_regs.vregs.iter().enumerate() {
+            dwarf_regs[DWARF_V0 + i] = *vreg as u64;
+        }
+        let caller = unwind_registers(&row, &dwarf_regs, |address| {
+            let bytes = self.read_memory(address, 8).ok()?;
+            return Some(u64::from_ne_bytes(bytes.try_into().unwrap()));
+        })
+        .ok_or(TraceeError::Return("failed to read the saved registers"))?;
+        let return_address = *caller
+            .get(row.return_address_register as usize)
+            .ok_or(TraceeError::Return("unsupported return address register"))?;
+
+        regs.regs.copy_from_slice(&caller[..31]);
+        regs.sp = caller[DWARF_SP];
+        regs.pc = return_address & ADDRESS_MASK;
+        // Only the low 64 bits of vector registers are saved across calls.
+        for (i, vreg) in fp_regs.vregs.iter_mut().enumerate() {
+            *vreg = (*vreg & !(u64::MAX as u128)) | caller[DWARF_V0 + i] as u128;
+        }
+        self.write_general_purpose_registers(&mut regs);
+        self.write_floating_point_registers(&mut fp_regs);
+        return Ok(regs.pc);
+    }
+
+    // Forks the stopped tracee by making the selected thread call clone() as fork() would. The
+    // child is a copy-on-write snapshot of the process, which stays stopped where the tracee is,
+    // and only has a copy of the selected thread. The thread must not be in a system call, which
+    // the one that it is made to call would take the place of.
+    pub unsafe fn checkpoint(&mut self) -> Result<Tracee, TraceeError> {
+        if self.status != TraceeStatus::Stopped {
+            return Err(TraceeError::Checkpoint("the process is not stopped"));
+        }
+        if self.syscall_stop().is_some() {
+            return Err(TraceeError::Checkpoint("the thread is in a system call"));
+        }
+
+        let tid = self.selected_tid;
+        let mut saved_regs = self.read_general_purpose_registers();
+        let original = self.patch_memory(saved_regs.pc, SYSCALL_INSTRUCTION)?;
+        let mut regs = saved_regs;
+        regs.regs[8] = libc::SYS_clone as u64;
+        regs.regs[0] = libc::SIGCHLD as u64;
+        regs.regs[1..5].fill(0);
+        self.write_general_purpose_registers(&mut regs);
+
+        // The thread stops at the fork event, and again once clone() has returned. Signals that
+        // arrive meanwhile are kept for when it is resumed.
+        let mut child_pid = None;
+        loop {
+            if libc::ptrace(
+                libc::PTRACE_SINGLESTEP,
+                tid,
+                null_mut::<*mut libc::c_void>(),
+                null_mut::<*mut libc::c_void>(),
+            ) < 0
+            {
+                let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+                panic!("failed to step tid ({}): {:?}", tid, errno_message);
+            }
+            match reaper::wait_on_task(tid) {
+                WaitStatus::Stopped(_, libc::PTRACE_EVENT_FORK) => {
+                    child_pid = Some(self.read_event_message(tid) as libc::pid_t);
+                }
+                WaitStatus::Stopped(libc::SIGTRAP, _) => break,
+                WaitStatus::Stopped(signal, _) => {
+                    if let Some(thread) = self.find_thread_mut(tid) {
+                        thread.pending_signal = Some(signal);
+                    }
+                }
+                status => panic!(
+                    "failed to checkpoint: tid ({}) ended with {:?}",
+                    tid, status
+                ),
+            }
+        }
+        self.unpatch_memory(saved_regs.pc, &original)?;
+        self.write_general_purpose_registers(&mut saved_regs);
+        let Some(child_pid) = child_pid else {
+            return Err(TraceeError::Checkpoint("clone() failed"));
+        };
+
+        // The child starts out stopped, as a copy of the thread right after the system call, and
+        // gets the breakpoints as a followed fork child would, one at its pc included.
+        reaper::wait_on_task(child_pid);
+        self.write_thread_memory(child_pid, saved_regs.pc, &original)?;
+        let mut child = self.adopt_forked_child(child_pid);
+        child.executable = self.executable.clone();
+        child.write_general_purpose_registers(&mut saved_regs);
+        return Ok(child);
+    }
+
+    // Lists the stopped threads that may run when resuming, according to the scheduler locking.
+    fn tids_to_resume(&self, stepping: bool) -> Vec<libc::pid_t> {
+        let selected_only = match self.scheduler_locking {
+            SchedulerLocking::Off => false,
+            SchedulerLocking::Step => stepping,
+            SchedulerLocking::On => true,
+        };
+
+        return self
+            .threads
+            .iter()
+            .filter(|thread| thread.status == ThreadStatus::Stopped)
+            .filter(|thread| !selected_only || thread.tid == self.selected_tid)
+            .map(|thread| thread.tid)
+            .collect::<Vec<libc::pid_t>>();
+    }
+
+    unsafe fn resume_thread(&mut self, tid: libc::pid_t) {
+        self.restart_thread(tid, self.resume_request());
+    }
+
+    fn resume_request(&self) -> libc::c_uint {
+        return match (&self.syscall_trace_filter, self.syscall_trace_mode) {
+            (Some(_), SyscallTraceMode::Ptrace) => libc::PTRACE_SYSCALL,
+            _ if self.replay.is_some() || self.auditing => libc::PTRACE_SYSCALL,
+            _ => libc::PTRACE_CONT,
+        };
+    }
+
+    // Reads the memory mappings of the tracee. They are only read afresh once the tracee may have
+    // changed them: when it was resumed without stopping at system calls, at system calls that
+    // change them, and at exec.
+    pub unsafe fn read_maps(&self) -> Vec<procfs::MemoryMapping> {
+        if let Some(maps) = self.maps.borrow().as_ref() {
+            return maps.clone();
+        }
+        let maps = procfs::read_maps(self.pid).unwrap_or_default();
+        // Threads that run without stopping at system calls may change the mappings unseen.
+        let is_observed = self.resume_request() == libc::PTRACE_SYSCALL
+            || self
+                .threads
+                .iter()
+                .all(|thread| thread.status != ThreadStatus::Running);
+        if is_observed {
+            self.maps.replace(Some(maps.clone()));
+        }
+        return maps;
+    }
+
+    // Drops the memory mappings that were read, e.g. once a library was loaded.
+    pub fn forget_maps(&self) {
+        self.maps.replace(None);
+    }
+
+    // Forgets the memory mappings at the entry and exit of a system call that may change them.
+    unsafe fn track_maps_syscall(&mut self, tid: libc::pid_t) {
+        let changing_maps = match self.thread_syscall_stop(tid) {
+            Some(SyscallStop::Entry { arch, number, .. }) => {
+                MAPS_SYSCALLS.contains(&describe_syscall(arch, number).as_str())
+            }
+            Some(SyscallStop::Seccomp { .. }) | Some(SyscallStop::Exit { .. }) | None => false,
+        };
+        let Some(thread) = self.find_thread_mut(tid) else {
+            return;
+        };
+        if changing_maps || thread.changing_maps {
+            thread.changing_maps = changing_maps;
+            self.forget_maps();
+        }
+    }
+
+    // Fetches the state of a thread that stopped the tracee, which reads of it are served from
+    // until the tracee resumes. The memory around the pc is read in a single call.
+    unsafe fn prefetch_stop_state(&self, tid: libc::pid_t) {
+        self.stop_state.replace(None);
+        let regs = self.read_thread_general_purpose_registers(tid);
+        let siginfo = self.read_thread_siginfo(tid);
+        let end = regs.pc.saturating_add(STOP_MEMORY_RADIUS);
+        // The pc may be at the very start of mapped memory.
+        let (memory_start, memory) = [regs.pc.saturating_sub(STOP_MEMORY_RADIUS), regs.pc]
+            .into_iter()
+            .find_map(|start| Some((start, self.read_memory_at_once(start, end)?)))
+            .unwrap_or((regs.pc, vec![]));
+        self.stop_state.replace(Some(StopState {
+            tid,
+            regs,
+            siginfo,
+            memory_start,
+            memory,
+        }));
+    }
+
+    // Reads memory from `start` up to `end` with one system call, rather than word by word, or up
+    // to where it stops being mapped. Returns None if `start` is not mapped.
+    unsafe fn read_memory_at_once(&self, start: u64, end: u64) -> Option<Vec<u8>> {
+        let mut memory = vec![0u8; (end - start) as usize];
+        let local = libc::iovec {
+            iov_base: memory.as_mut_ptr() as *mut libc::c_void,
+            iov_len: memory.len(),
+        };
+        let remote = libc::iovec {
+            iov_base: start as *mut libc::c_void,
+            iov_len: memory.len(),
+        };
+        let n_bytes = libc::process_vm_readv(self.pid, &local, 1, &remote, 1, 0);
+        if n_bytes <= 0 {
+            return None;
+        }
+        memory.truncate(n_bytes as usize);
+        return Some(memory);
+    }
+
+    // Drops what was fetched at the stop, once it may no longer be what the tracee has.
+    fn forget_stop_state(&self) {
+        self.stop_state.replace(None);
+    }
+
+    // Restarts a stopped thread with the given ptrace request, e.g. PTRACE_CONT. Any signal that
+    // the thread stopped with and should be passed on is delivered now.
+    unsafe fn restart_thread(&mut self, tid: libc::pid_t, request: libc::c_uint) {
+        self.forget_stop_state();
+        // Only system calls that the thread stops at are seen to change the mappings.
+        if request != libc::PTRACE_SYSCALL {
+            self.forget_maps();
+        }
+        let signal = self
+            .find_thread_mut(tid)
+            .and_then(|thread| thread.pending_signal.take())
+            .unwrap_or(0);
+        if libc::ptrace(
+            request,
+            tid,
+            null_mut::<*mut libc::c_void>(),
+            signal as libc::c_long as *mut libc::c_void,
+        ) < 0
+        {
+            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+            panic!("failed to continue: {:?}", errno_message);
+        }
+
+        if let Some(thread) = self.find_thread_mut(tid) {
+            thread.status = ThreadStatus::Running;
+            thread.single_stepping = request == libc::PTRACE_SINGLESTEP;
+        }
+    }
+
+    // Reads the siginfo of the signal that the selected thread stopped with. Returns None if the
+    // thread did not stop because of a signal, e.g. at a system call or ptrace event.
+    pub unsafe fn read_siginfo(&self) -> Option<libc::siginfo_t> {
+        return self.read_thread_siginfo(self.selected_tid);
+    }
+
+    unsafe fn read_thread_siginfo(&self, tid: libc::pid_t) -> Option<libc::siginfo_t> {
+        if let Some(state) = self
+            .stop_state
+            .borrow()
+            .as_ref()
+            .filter(|state| state.tid == tid)
+        {
+            return state.siginfo;
+        }
+        let mut info = mem::zeroed::<libc::siginfo_t>();
+        if libc::ptrace(
+            libc::PTRACE_GETSIGINFO,
+            tid,
+            null_mut::<*mut libc::c_void>(),
+            &mut info as *mut libc::siginfo_t,
+        ) < 0
+        {
+            return None;
+        }
+
+        // Syscall-stops and event stops report a made-up SIGTRAP, whose si_code is tagged with
+        // 0x80 or with the event in its second byte.
+        let is_made_up = info.si_signo == libc::SIGTRAP
+            && (info.si_code == SYSCALL_STOP_SIGNAL || info.si_code > 0xff);
+        return match is_made_up {
+            true => None,
+            false => Some(info),
+        };
+    }
+
+    // Classifies the SIGTRAP that the selected thread stopped with, if it stopped with one.
+    pub unsafe fn trap(&self) -> Option<Trap> {
+        let info = self.read_siginfo()?;
+        return match info.si_signo {
+            libc::SIGTRAP => Some(classify_trap(&info)),
+            _ => None,
+        };
+    }
+
+    // Whether a hardware debug trap at `address` came from a hardware breakpoint rather than a
+    // watchpoint, which reports the address of the data instead of an instruction.
+    fn is_hardware_breakpoint(&self, address: u64) -> bool {
+        let Some(slots) = &self.debug_slots else {
+            return false;
+        };
+        return slots
+            .slots(SlotKind::Breakpoint)
+            .iter()
+            .any(|slot| slot.is_some_and(|slot| slot.address == address));
+    }
+
+    // Sends a signal to the selected thread. It is delivered once the thread is resumed.
+    pub unsafe fn signal_thread(&self, signal: libc::c_int) {
+        if libc::syscall(libc::SYS_tgkill, self.pid, self.selected_tid, signal) < 0 {
+            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+            panic!("failed to send signal: {:?}", errno_message);
+        }
+    }
+
+    // Sends a signal to the process, which the kernel delivers to any thread that does not block
+    // it once the process is resumed.
+    pub unsafe fn signal_process(&self, signal: libc::c_int) {
+        if libc::kill(self.pid, signal) < 0 {
+            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+            panic!("failed to send signal: {:?}", errno_message);
+        }
+    }
+
+    // Kills the process and waits until it is gone.
+    pub unsafe fn kill(&mut self) {
+        self.signal_process(libc::SIGKILL);
+        while self.status != TraceeStatus::Exited && self.status != TraceeStatus::Terminated {
+            self.wait_on_signal();
+        }
+    }
+
+    // Describes the syscall-stop that the selected thread is in, if any.
+    pub unsafe fn syscall_stop(&self) -> Option<SyscallStop> {
+        return self.thread_syscall_stop(self.selected_tid);
+    }
+
+    unsafe fn thread_syscall_stop(&self, tid: libc::pid_t) -> Option<SyscallStop> {
+        let mut info = mem::zeroed::<libc::ptrace_syscall_info>();
+        if libc::ptrace(
+            libc::PTRACE_GET_SYSCALL_INFO,
+            tid,
+            mem::size_of::<libc::ptrace_syscall_info>(),
+            &mut info as *mut libc::ptrace_syscall_info,
+        ) < 0
+        {
+            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+            panic!("failed to read syscall info: {:?}", errno_message);
+        }
+
+        return match info.op {
+            libc::PTRACE_SYSCALL_INFO_ENTRY => Some(SyscallStop::Entry {
+                arch: info.arch,
+                number: info.u.entry.nr,
+                args: info.u.entry.args,
+            }),
+            libc::PTRACE_SYSCALL_INFO_EXIT => Some(SyscallStop::Exit {
+                arch: info.arch,
+                value: info.u.exit.sval,
+                is_error: info.u.exit.is_error != 0,
+            }),
+            libc::PTRACE_SYSCALL_INFO_SECCOMP => Some(SyscallStop::Seccomp {
+                arch: info.arch,
+                number: info.u.seccomp.nr,
+                args: info.u.seccomp.args,
+                data: info.u.seccomp.ret_data,
+            }),
+            _ => None,
+        };
+    }
+
+    // Overrides the value that the system call, which the selected thread is exiting from, returns.
+    // Returns false if the thread is not at a syscall-exit stop.
+    pub unsafe fn set_syscall_return_value(&self, value: i64) -> bool {
+        if self.status != TraceeStatus::Stopped
+            || !matches!(self.syscall_stop(), Some(SyscallStop::Exit { .. }))
+        {
+            return false;
+        }
+
+        let mut regs = self.read_general_purpose_registers();
+        regs.regs[0] = value as u64;
+        self.write_general_purpose_registers(&mut regs);
+        return true;
+    }
+
+    // Skips the system call that the selected thread is about to execute, making it fail with the
+    // given errno instead. Returns false if the thread is not at a syscall-entry or seccomp stop.
+    pub unsafe fn fail_syscall(&self, errno: libc::c_int) -> bool {
+        if self.status != TraceeStatus::Stopped
+            || !matches!(
+                self.syscall_stop(),
+                Some(SyscallStop::Entry { .. } | SyscallStop::Seccomp { .. })
+            )
+        {
+            return false;
+        }
+
+        self.skip_thread_syscall(self.selected_tid, -(errno as i64));
+        return true;
+    }
+
+    // Skips the system call that a thread is about to execute, making it return the given value,
+    // e.g. a negated errno.
+    unsafe fn skip_thread_syscall(&self, tid: libc::pid_t, value: i64) {
+        // The kernel skips system calls whose number is changed to -1.
+        let mut number: libc::c_int = -1;
+        let mut iov = libc::iovec {
+            iov_base: &mut number as *mut libc::c_int as *mut libc::c_void,
+            iov_len: mem::size_of::<libc::c_int>(),
+        };
+        if libc::ptrace(
+            libc::PTRACE_SETREGSET,
+            tid,
+            NT_ARM_SYSTEM_CALL,
+            &mut iov as *mut libc::iovec as *mut libc::c_void,
+        ) < 0
+        {
+            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+            panic!("failed to write syscall number: {:?}", errno_message);
+        }
+
+        // A skipped system call leaves x0 as it is, so it returns whatever is written there now,
+        // whether or not the thread stops at its exit.
+        let mut regs = self.read_thread_general_purpose_registers(tid);
+        regs.regs[0] = value as u64;
+        self.write_thread_general_purpose_registers(tid, &mut regs);
+    }
+
+    // Reads the functions of the main executable, at their runtime addresses.
+    pub unsafe fn read_functions(&self) -> Result<Vec<FunctionSymbol>, TraceeError> {
+        let path = self.executable.display().to_string();
+        return self.read_indexed_executable(|object, bias| {
+            let functions = object.functions.clone().map_err(|source| {
+                return TraceeError::ReadSymbols {
+                    path: path.clone(),
+                    source,
+                };
+            })?;
+            return Ok(relocate_functions(functions, bias));
+        });
+    }
+
+    // Reads the functions of the shared libraries that the tracee has loaded, at their runtime
+    // addresses. Libraries that cannot be read are left out.
+    pub unsafe fn read_library_functions(&self) -> Vec<FunctionSymbol> {
+        let path = self.executable.display().to_string();
+        let mappings = self.index_symbols();
+        let index = self.symbol_index.borrow();
+        let mut functions = vec![];
+        for object in index.objects() {
+            if object.path == path {
+                continue;
+            }
+            let (Ok(Some(bias)), Ok(object_functions)) =
+                (object.load_bias(&mappings), &object.functions)
+            else {
+                continue;
+            };
+            functions.extend(relocate_functions(object_functions.clone(), bias));
+        }
+        return functions;
+    }
+
+    // Finds the dynamic linker's rendezvous structure through the executable's DT_DEBUG. Returns
+    // None for static executables, and until the dynamic linker has started.
+    pub unsafe fn find_rendezvous(&self) -> Result<Option<u64>, TraceeError> {
+        let dynamic = self.read_indexed_executable(|object, bias| {
+            let layout = object.layout.clone()?;
+            return Ok(layout
+                .dynamic_address
+                .map(|address| address.wrapping_add(bias)));
+        })?;
+        let Some(dynamic) = dynamic else {
+            return Ok(None);
+        };
+        let read_memory = |address, len| self.read_memory(address, len).ok();
+        return Ok(find_rendezvous(read_memory, dynamic));
+    }
+
+    // Lists the shared objects that the dynamic linker has loaded, in load order, as its link map
+    // has them.
+    pub unsafe fn read_shared_libraries(&self) -> Result<Vec<SharedLibrary>, TraceeError> {
+        let Some(address) = self.find_rendezvous()? else {
+            return Ok(vec![]);
+        };
+        let read_memory = |address, len| self.read_memory(address, len).ok();
+        let Some(rendezvous) = read_rendezvous(read_memory, address) else {
+            return Ok(vec![]);
+        };
+        let read_string = |address| {
+            let bytes = self.read_c_string(address, libc::PATH_MAX as usize).ok()?;
+            return Some(String::from_utf8_lossy(&bytes).to_string());
+        };
+        let entries = read_link_map(read_memory, read_string, rendezvous.map);
+
+        let mappings = self.index_symbols();
+        let index = self.symbol_index.borrow();
+        let mut libraries = vec![];
+        for entry in entries {
+            // The executable's entry has no name. The objects are found by their dynamic sections,
+            // since the mappings name files by their real paths, and the vDSO is no file at all.
+            if entry.name.is_empty() {
+                continue;
+            }
+            let Some(mapping) = mappings
+                .iter()
+                .find(|mapping| (mapping.start..mapping.end).contains(&entry.dynamic))
+                .filter(|mapping| mapping.path.starts_with('/'))
+            else {
+                continue;
+            };
+            let object_mappings = mappings
+                .iter()
+                .filter(|object_mapping| object_mapping.path == mapping.path);
+            let start = object_mappings.clone().map(|mapping| mapping.start).min();
+            let end = object_mappings.map(|mapping| mapping.end).max();
+            let object = index.object(&mapping.path);
+            libraries.push(SharedLibrary {
+                path: mapping.path.clone(),
+                start: start.unwrap_or(mapping.start),
+                end: end.unwrap_or(mapping.end),
+                has_symbols: object.is_some_and(|object| {
+                    return object
+                        .functions
+                        .as_ref()
+                        .is_ok_and(|functions| !functions.is_empty());
+                }),
+                has_debug_info: object.is_some_and(|object| {
+                    return object.line_rows.as_ref().is_ok_and(|rows| !rows.is_empty());
+                }),
+            });
+        }
+        return Ok(libraries);
+    }
+
+    // Stops the tracee once the dynamic linker loads a library whose path contains `pattern`.
+    pub unsafe fn catch_load(&mut self, pattern: &str) -> Result<(), TraceeError> {
+        if !self.insert_rendezvous_breakpoint()? {
+            return Err(TraceeError::CatchLoad("no dynamic linker is loaded"));
+        }
+        self.load_catches.push(pattern.to_string());
+        return Ok(());
+    }
+
+    // Brings the symbol index up to date with the objects that the tracee has mapped: those that
+    // were mapped since are indexed, and those that were unmapped are dropped, while the rest are
+    // kept as they are. From then on, this happens whenever the dynamic linker changes the link
+    // map, e.g. at dlopen() and dlclose().
+    pub unsafe fn reload_shared_libraries(&mut self) -> Result<SymbolReload, TraceeError> {
+        self.insert_rendezvous_breakpoint()?;
+        return Ok(self.reload_symbols());
+    }
+
+    unsafe fn reload_symbols(&self) -> SymbolReload {
+        self.forget_maps();
+        let (mappings, added) = self.index_new_symbols();
+        let mut mapped_paths = symbols::mapped_code_files(&mappings);
+        mapped_paths.push(self.executable.display().to_string());
+        let removed = self
+            .symbol_index
+            .borrow_mut()
+            .remove_unmapped(&mapped_paths);
+        return SymbolReload { added, removed };
+    }
+
+    // Breaks at the function that the dynamic linker calls whenever it changes the link map, unless
+    // that is done already. Returns false if there is no dynamic linker.
+    unsafe fn insert_rendezvous_breakpoint(&mut self) -> Result<bool, TraceeError> {
+        if self.rendezvous_breakpoint.is_some() {
+            return Ok(true);
+        }
+        let read_memory = |address, len| self.read_memory(address, len).ok();
+        let brk = self
+            .find_rendezvous()?
+            .and_then(|address| read_rendezvous(read_memory, address))
+            .map(|rendezvous| rendezvous.brk);
+        // Until the dynamic linker has started, it is found by name instead.
+        let brk = brk.or_else(|| {
+            return self
+                .read_library_functions()
+                .iter()
+                .find(|function| function.name == "_dl_debug_state")
+                .map(|function| function.address);
+        });
+        let Some(brk) = brk else {
+            return Ok(false);
+        };
+        let original = self.patch_memory(brk, BREAKPOINT_INSTRUCTION)?;
+        self.rendezvous_breakpoint = Some((brk, original));
+        self.loaded_libraries = self
+            .read_shared_libraries()?
+            .into_iter()
+            .map(|library| library.path)
+            .collect();
+        return Ok(true);
+    }
+
+    pub fn load_catches(&self) -> &[String] {
+        return &self.load_catches;
+    }
+
+    // Stops the tracee once the dynamic linker resolves a function that the executable calls
+    // through its PLT, by watching the GOT slot that it writes the function's address into. Returns
+    // where the function was resolved to instead, if it already has been.
+    pub unsafe fn break_on_resolve(&mut self, symbol: &str) -> Result<Option<u64>, TraceeError> {
+        let elf = ElfFile::read(&self.resolve_path(&self.executable))?;
+        let Some(entry) = elf
+            .plt_entries()?
+            .into_iter()
+            .find(|entry| entry.name == symbol)
+        else {
+            return Err(TraceeError::BreakOnResolve(
+                "the program does not call it through its PLT",
+            ));
+        };
+        let got_address =
+            self.read_indexed_executable(|_, bias| Ok(entry.got_address.wrapping_add(bias)))?;
+        let target = u64::from_le_bytes(self.read_memory(got_address, 8)?.try_into().unwrap());
+        if self.is_resolved_target(target) {
+            return Ok(Some(target));
+        }
+
+        // GOT slots are aligned words.
+        let slot = HardwareSlot::watchpoint(got_address, 8, WatchAccess::Write).unwrap();
+        let slot = self.allocate_debug_slot(SlotKind::Watchpoint, slot)?;
+        self.resolve_breaks.push(ResolveBreak {
+            symbol: symbol.to_string(),
+            got_address,
+            slot,
+        });
+        return Ok(None);
+    }
+
+    pub fn resolve_breaks(&self) -> &[ResolveBreak] {
+        return &self.resolve_breaks;
+    }
+
+    // Whether a GOT slot points out of the executable, as it does once the dynamic linker resolved
+    // it, rather than at the lazy binding trampoline or at nothing before relocation.
+    unsafe fn is_resolved_target(&self, target: u64) -> bool {
+        let path = self.executable.display().to_string();
+        return self.read_maps().iter().any(|mapping| {
+            return (mapping.start..mapping.end).contains(&target) && mapping.path != path;
+        });
+    }
+
+    // Handles a SIGTRAP of a thread whose siblings may still be running, in case it is one of the
+    // traps that pbreak sets for itself.
+    unsafe fn handle_internal_trap(&mut self, tid: libc::pid_t) -> InternalTrap {
+        if let Some((brk, _)) = self.rendezvous_breakpoint {
+            if self.read_thread_general_purpose_registers(tid).pc == brk {
+                return self.handle_rendezvous_trap(tid);
+            }
+        }
+        if !self.call_traces.is_empty() || !self.return_breakpoints.is_empty() {
+            if let Some(Trap::Breakpoint { address }) = self
+                .read_thread_siginfo(tid)
+                .map(|info| classify_trap(&info))
+            {
+                return self.handle_call_trace_trap(tid, address);
+            }
+        }
+        if self.resolve_breaks.is_empty() && self.watchpoints.is_empty() {
+            return InternalTrap::Unrelated;
+        }
+        let Some(Trap::HardwareBreakpoint { address }) = self
+            .read_thread_siginfo(tid)
+            .map(|info| classify_trap(&info))
+        else {
+            return InternalTrap::Unrelated;
+        };
+        return match self.handle_resolve_trap(tid, address) {
+            InternalTrap::Unrelated => self.handle_watch_trap(tid, address),
+            trap => trap,
+        };
+    }
+
+    // Handles the dynamic linker calling r_brk, which it does before and after it changes the link
+    // map. Once the link map is consistent again, the symbols are reloaded and loads are caught.
+    unsafe fn handle_rendezvous_trap(&mut self, tid: libc::pid_t) -> InternalTrap {
+        // r_brk is an empty function, so returning from it is all that running it would do.
+        let mut regs = self.read_thread_general_purpose_registers(tid);
+        regs.pc = regs.regs[30];
+        self.write_thread_general_purpose_registers(tid, &mut regs);
+
+        // The link map is read through the thread that stopped, since the others may be running.
+        let selected_tid = mem::replace(&mut self.selected_tid, tid);
+        let read_memory = |address, len| self.read_memory(address, len).ok();
+        let state = self
+            .find_rendezvous()
+            .ok()
+            .flatten()
+            .and_then(|address| read_rendezvous(read_memory, address))
+            .map(|rendezvous| rendezvous.state);
+        if state != Some(RendezvousState::Consistent) {
+            self.selected_tid = selected_tid;
+            return InternalTrap::Handled;
+        }
+        self.reload_symbols();
+        self.resolve_pending_breakpoints();
+        let paths = match self.read_shared_libraries() {
+            Err(err) => {
+                println!("{}", err);
+                vec![]
+            }
+            Ok(libraries) => libraries.into_iter().map(|library| library.path).collect(),
+        };
+        let caught = paths
+            .iter()
+            .filter(|path| !self.loaded_libraries.contains(path))
+            .filter(|path| {
+                return self
+                    .load_catches
+                    .iter()
+                    .any(|pattern| path.contains(pattern.as_str()));
+            })
+            .cloned()
+            .collect::<Vec<String>>();
+        self.loaded_libraries = paths;
+        if caught.is_empty() {
+            self.selected_tid = selected_tid;
+            return InternalTrap::Handled;
+        }
+        for path in caught {
+            println!("Caught load of {}{}", path, self.thread_suffix(tid));
+        }
+        return InternalTrap::Caught;
+    }
+
+    // Handles a write to a watched GOT slot, which is about to happen. The thread is stepped over it
+    // to see what was written.
+    unsafe fn handle_resolve_trap(&mut self, tid: libc::pid_t, address: u64) -> InternalTrap {
+        let Some(i) = self.resolve_breaks.iter().position(|resolve_break| {
+            return (resolve_break.got_address..resolve_break.got_address + 8).contains(&address);
+        }) else {
+            return InternalTrap::Unrelated;
+        };
+        match self.step_over_watchpoint(tid, self.resolve_breaks[i].slot) {
+            Err(err) => {
+                println!("{}", err);
+                return InternalTrap::Deferred;
+            }
+            Ok(StepEnd::Event { .. }) => return InternalTrap::Deferred,
+            Ok(StepEnd::Stepped) => {}
+        }
+        let got_address = self.resolve_breaks[i].got_address;
+        let target = self
+            .read_thread_memory(tid, got_address, 8)
+            .ok()
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+            .filter(|target| self.is_resolved_target(*target));
+        let Some(target) = target else {
+            // e.g. the dynamic linker relocating the slot to the lazy binding trampoline.
+            return InternalTrap::Handled;
+        };
+
+        // The slot is freed in every thread once they have stopped.
+        let resolve_break = self.resolve_breaks.remove(i);
+        if let Some(thread) = self.find_thread_mut(tid) {
+            thread.status = ThreadStatus::Stopped;
+        }
+        self.stop_all_threads();
+        if let Err(err) = self.free_debug_slot(SlotKind::Watchpoint, resolve_break.slot) {
+            println!("{}", err);
+        }
+        let functions = self.read_library_functions();
+        match describe_address(&functions, target) {
+            None => println!(
+                "Resolved {} to {:#x}{}",
+                resolve_break.symbol,
+                target,
+                self.thread_suffix(tid)
+            ),
+            Some(function) => println!(
+                "Resolved {} to {:#x} in {}{}",
+                resolve_break.symbol,
+                target,
+                function,
+                self.thread_suffix(tid)
+            ),
+        }
+        return InternalTrap::Caught;
+    }
+
+    // Handles a write to the bytes of a watchpoint, which is about to happen. The thread is stepped
+    // over it, and the tracee only stops if the write changed them and the condition holds.
+    unsafe fn handle_watch_trap(&mut self, tid: libc::pid_t, address: u64) -> InternalTrap {
+        let Some(slots) = &self.debug_slots else {
+            return InternalTrap::Unrelated;
+        };
+        let Some(i) = self.watchpoints.iter().position(|watchpoint| {
+            return slots.slots(SlotKind::Watchpoint)[watchpoint.slot]
+                .is_some_and(|slot| slot.watches(address));
+        }) else {
+            return InternalTrap::Unrelated;
+        };
+        match self.step_over_watchpoint(tid, self.watchpoints[i].slot) {
+            Err(err) => {
+                println!("{}", err);
+                return InternalTrap::Deferred;
+            }
+            Ok(StepEnd::Event { .. }) => return InternalTrap::Deferred,
+            Ok(StepEnd::Stepped) => {}
+        }
+        let watchpoint = &self.watchpoints[i];
+        let value = match self.read_watched_value(tid, watchpoint.address, watchpoint.len) {
+            Err(err) => {
+                println!("{}", err);
+                return InternalTrap::Caught;
+            }
+            Ok(value) => value,
+        };
+        let old = watchpoint.value;
+        if value == old {
+            return InternalTrap::Handled;
+        }
+        self.watchpoints[i].value = value;
+
+        let watchpoint = &self.watchpoints[i];
+        if let Some(condition) = &watchpoint.condition {
+            let context = WatchConditionContext {
+                tracee: self,
+                tid,
+                regs: self.read_thread_general_purpose_registers(tid),
+                old,
+                value,
+            };
+            match expr::evaluate(condition, &context) {
+                Err(err) => println!("Watchpoint {}: {}", watchpoint.id, err),
+                Ok(result) if !result.is_true() => return InternalTrap::Handled,
+                Ok(_) => {}
+            }
+        }
+        println!(
+            "Watchpoint {} at {:#x}{}\nOld value = {}\nNew value = {}",
+            watchpoint.id,
+            watchpoint.address,
+            self.thread_suffix(tid),
+            Value::UInt(old),
+            Value::UInt(value)
+        );
+        return InternalTrap::Caught;
+    }
+
+    // Steps a thread over the write that triggered a hardware watchpoint, which has not happened
+    // yet, with the watchpoint out of its way. Only this thread is stopped, so only its debug
+    // registers are written.
+    unsafe fn step_over_watchpoint(
+        &mut self,
+        tid: libc::pid_t,
+        slot: usize,
+    ) -> Result<StepEnd, TraceeError> {
+        let Some(slots) = self.debug_slots.clone() else {
+            return self.step_thread(tid);
+        };
+        let slots = slots.slots(SlotKind::Watchpoint);
+        let mut stepping_slots = slots.to_vec();
+        stepping_slots[slot] = None;
+        write_slots(tid, SlotKind::Watchpoint, &stepping_slots)?;
+        let step = self.step_thread(tid)?;
+        // A new program starts out without watchpoints.
+        if step != (StepEnd::Event { is_exec: true }) {
+            write_slots(tid, SlotKind::Watchpoint, slots)?;
+        }
+        return Ok(step);
+    }
+
+    // Sets a hardware breakpoint at `address`, which stops the tracee without its code being
+    // patched, e.g. in read-only or self-checksumming code. Returns the index of its slot.
+    pub unsafe fn set_hw_breakpoint(&mut self, address: u64) -> Result<usize, TraceeError> {
+        let Some(slot) = HardwareSlot::breakpoint(address) else {
+            return Err(TraceeError::SetBreakpoint(
+                "the address is not that of an instruction",
+            ));
+        };
+        if self
+            .debug_slots()?
+            .slots(SlotKind::Breakpoint)
+            .iter()
+            .flatten()
+            .any(|slot| slot.address == address)
+        {
+            return Err(TraceeError::SetBreakpoint(
+                "there already is one at that address",
+            ));
+        }
+        return self.allocate_debug_slot(SlotKind::Breakpoint, slot);
+    }
+
+    pub unsafe fn remove_hw_breakpoint(&mut self, index: usize) -> Result<(), TraceeError> {
+        let is_set = self
+            .debug_slots
+            .as_ref()
+            .and_then(|slots| slots.slots(SlotKind::Breakpoint).get(index).copied())
+            .flatten()
+            .is_some();
+        if !is_set {
+            return Err(TraceeError::UnknownHardwareBreakpoint(index));
+        }
+        return self.free_debug_slot(SlotKind::Breakpoint, index);
+    }
+
+    // Watches `len` bytes at `address` for writes that change them. If there is a condition, the
+    // tracee only stops where it is true after the write. Returns the ID of the watchpoint.
+    pub unsafe fn insert_watchpoint(
+        &mut self,
+        address: u64,
+        len: u64,
+        condition: Option<&str>,
+    ) -> Result<usize, TraceeError> {
+        let Some(slot) = HardwareSlot::watchpoint(address, len, WatchAccess::Write) else {
+            return Err(TraceeError::SetWatchpoint(
+                "hardware watchpoints watch 1 to 8 bytes within an aligned 8-byte word",
+            ));
+        };
+        let value = self.read_watched_value(self.selected_tid, address, len)?;
+        // A malformed condition is caught before it is needed.
+        if let Some(condition) = condition {
+            let context = WatchConditionContext {
+                tracee: self,
+                tid: self.selected_tid,
+                regs: self.read_general_purpose_registers(),
+                old: value,
+                value,
+            };
+            expr::evaluate(condition, &context)?;
+        }
+        let slot = self.allocate_debug_slot(SlotKind::Watchpoint, slot)?;
+        self.last_watchpoint_id += 1;
+        self.watchpoints.push(Watchpoint {
+            id: self.last_watchpoint_id,
+            address,
+            len,
+            condition: condition.map(str::to_string),
+            value,
+            slot,
+        });
+        return Ok(self.last_watchpoint_id);
+    }
+
+    pub unsafe fn delete_watchpoint(&mut self, id: usize) -> Result<(), TraceeError> {
+        let Some(i) = self
+            .watchpoints
+            .iter()
+            .position(|watchpoint| watchpoint.id == id)
+        else {
+            return Err(TraceeError::UnknownWatchpoint(id));
+        };
+        self.free_debug_slot(SlotKind::Watchpoint, self.watchpoints[i].slot)?;
+        self.watchpoints.remove(i);
+        return Ok(());
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        return &self.watchpoints;
+    }
+
+    unsafe fn read_watched_value(
+        &self,
+        tid: libc::pid_t,
+        address: u64,
+        len: u64,
+    ) -> Result<u64, TraceeError> {
+        let mut bytes = self.read_thread_memory(tid, address, len as usize)?;
+        bytes.resize(8, 0);
+        return Ok(u64::from_le_bytes(bytes.try_into().unwrap()));
+    }
+
+    // Steps a stopped thread over one instruction, and waits until it has. Signals that arrive
+    // meanwhile are kept for when it is resumed. A ptrace event that the thread stops at first, or
+    // its end, is set aside for `handle_wait_status` to handle as usual.
+    unsafe fn step_thread(&mut self, tid: libc::pid_t) -> Result<StepEnd, TraceeError> {
+        loop {
+            if libc::ptrace(
+                libc::PTRACE_SINGLESTEP,
+                tid,
+                null_mut::<*mut libc::c_void>(),
+                null_mut::<*mut libc::c_void>(),
+            ) < 0
+            {
+                let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+                return Err(TraceeError::Step {
+                    tid,
+                    message: errno_message.to_string_lossy().to_string(),
+                });
+            }
+            match reaper::wait_on_task(tid) {
+                WaitStatus::Stopped(libc::SIGTRAP, 0) => return Ok(StepEnd::Stepped),
+                WaitStatus::Stopped(signal, 0) => {
+                    if let Some(thread) = self.find_thread_mut(tid) {
+                        thread.pending_signal = Some(signal);
+                    }
+                }
+                status @ WaitStatus::Stopped(_, event) => {
+                    reaper::defer_status(tid, status);
+                    return Ok(StepEnd::Event {
+                        is_exec: event == libc::PTRACE_EVENT_EXEC,
+                    });
+                }
+                status => {
+                    reaper::defer_status(tid, status);
+                    return Err(TraceeError::Step {
+                        tid,
+                        message: format!("it ended with {:?}", status),
+                    });
+                }
+            }
+        }
+    }
+
+    // Reads the line table of the main executable, at runtime addresses.
+    pub unsafe fn read_line_rows(&self) -> Result<Vec<LineRow>, TraceeError> {
+        let path = self.executable.display().to_string();
+        return self.read_indexed_executable(|object, bias| {
+            let rows = object.line_rows.clone().map_err(|source| {
+                return TraceeError::ReadLineTable {
+                    path: path.clone(),
+                    source,
+                };
+            })?;
+            return Ok(rows
+                .into_iter()
+                .map(|row| LineRow {
+                    address: row.address.wrapping_add(bias),
+                    ..row
+                })
+                .collect());
+        });
+    }
+
+    // Finds a function by name. Functions that the executable does not define are looked up in the
+    // shared libraries.
+    pub unsafe fn find_function(&self, name: &str) -> Result<FunctionSymbol, String> {
+        let functions = self.read_functions().map_err(|err| err.to_string())?;
+        return functions
+            .into_iter()
+            .find(|function| function.name == name)
+            .or_else(|| {
+                return self
+                    .read_library_functions()
+                    .into_iter()
+                    .find(|function| function.name == name);
+            })
+            .ok_or_else(|| format!("no function named \"{}\"", name));
+    }
+
+    // Resolves an address given in hexadecimal, e.g. "0x4005d0", as a source line, e.g.
+    // "main.c:12", or as the name of a function.
+    pub unsafe fn resolve_location(&self, location: &str) -> Result<u64, String> {
+        if let Some(hex) = location.strip_prefix("0x") {
+            return u64::from_str_radix(hex, 16)
+                .map_err(|_| format!("invalid address: \"{}\"", location));
+        }
+        if let Some((file, line)) = location
+            .rsplit_once(':')
+            .and_then(|(file, line_str)| Some((file, line_str.parse::<u64>().ok()?)))
+        {
+            return self.resolve_source_line(file, line);
+        }
+        return self
+            .find_function(location)
+            .map(|function| function.address);
+    }
+
+    // Finds the first instruction of a source line, or of the next line that has any code. The file
+    // may be named by any trailing part of its path, e.g. "main.c" for "/src/main.c".
+    unsafe fn resolve_source_line(&self, file: &str, line: u64) -> Result<u64, String> {
+        let rows = self.read_line_rows().map_err(|err| err.to_string())?;
+        return rows
+            .iter()
+            .filter(|row| row.path == file || row.path.ends_with(&format!("/{}", file)))
+            .filter(|row| row.line >= line)
+            .map(|row| (row.line, row.address))
+            .min()
+            .map(|(_, address)| address)
+            .ok_or_else(|| format!("no code at {}:{}", file, line));
+    }
+
+    // Reads from the index of the main executable, given the load bias that it is mapped at.
+    unsafe fn read_indexed_executable<T>(
+        &self,
+        read: impl FnOnce(&ObjectIndex, u64) -> Result<T, TraceeError>,
+    ) -> Result<T, TraceeError> {
+        let path = self.executable.display().to_string();
+        let mappings = self.index_symbols();
+        let index = self.symbol_index.borrow();
+        let Some(object) = index.object(&path) else {
+            return Err(TraceeError::NotMapped { path });
+        };
+        // The kernel says where it put the entry point, which holds even if the mappings do not
+        // name the file, e.g. once it is deleted.
+        if let Some(entry) = self.read_entry() {
+            return read(object, object.load_bias_from_entry(entry)?);
+        }
+        return match object.load_bias(&mappings)? {
+            None => Err(TraceeError::NotMapped { path }),
+            Some(bias) => read(object, bias),
+        };
+    }
+
+    // Indexes the executable and the shared libraries that the tracee has mapped since they were
+    // last indexed, in parallel. Returns the mappings that they were found in.
+    unsafe fn index_symbols(&self) -> Vec<procfs::MemoryMapping> {
+        return self.index_new_symbols().0;
+    }
+
+    // Indexes as `index_symbols` does, and also returns the paths of the files that were indexed
+    // now.
+    unsafe fn index_new_symbols(&self) -> (Vec<procfs::MemoryMapping>, Vec<String>) {
+        let mappings = self.read_maps();
+        let mut paths = symbols::mapped_code_files(&mappings);
+        let executable = self.executable.display().to_string();
+        if !paths.contains(&executable) {
+            paths.insert(0, executable);
+        }
+        // Mappings name the files as the tracee sees them, so only reading them is resolved.
+        let files = paths
+            .into_iter()
+            .map(|path| {
+                let read_path = self.resolve_path(Path::new(&path));
+                return (path, read_path);
+            })
+            .collect::<Vec<(String, PathBuf)>>();
+        let added = self.symbol_index.borrow_mut().add(files);
+        return (mappings, added);
+    }
+
+    // Traces the calls that a mapped object makes through its PLT stubs, e.g. into libc, with a
+    // breakpoint at each stub. Returns how many stubs are newly traced.
+    pub unsafe fn trace_library_calls(&mut self, path: &str) -> Result<usize, TraceeError> {
+        let read_symbols = |source| TraceeError::ReadSymbols {
+            path: path.to_string(),
+            source,
+        };
+        let elf = ElfFile::read(&self.resolve_path(Path::new(path))).map_err(read_symbols)?;
+        let Some(bias) = load_bias(&elf, path, &self.read_maps()).map_err(read_symbols)? else {
+            return Err(TraceeError::NotMapped {
+                path: path.to_string(),
+            });
+        };
+        let mut count = 0;
+        for entry in elf.plt_entries().map_err(read_symbols)? {
+            let address = entry.address.wrapping_add(bias);
+            // A stub that a breakpoint is set at stops the tracee instead.
+            if self
+                .call_traces
+                .iter()
+                .any(|trace| trace.address == address)
+                || self.has_enabled_breakpoint(address)
+            {
+                continue;
+            }
+            let original = self.insert_call_trace_breakpoint(self.selected_tid, address)?;
+            self.call_traces.push(CallTrace {
+                name: entry.name,
+                address,
+                kind: CallTraceKind::Library,
+                original,
+            });
+            count += 1;
+        }
+        return Ok(count);
+    }
+
+    // Traces the calls of a function, by name, with a breakpoint at its entry. Returns its address.
+    pub unsafe fn trace_function_calls(&mut self, name: &str) -> Result<u64, String> {
+        let address = self.find_function(name)?.address;
+        if self
+            .call_traces
+            .iter()
+            .any(|trace| trace.address == address)
+        {
+            return Err(format!("calls of {} are traced already", name));
+        }
+        if self.has_enabled_breakpoint(address) {
+            return Err(format!("{} has a breakpoint at its entry", name));
+        }
+        let original = self
+            .insert_call_trace_breakpoint(self.selected_tid, address)
+            .map_err(|err| err.to_string())?;
+        self.call_traces.push(CallTrace {
+            name: name.to_string(),
+            address,
+            kind: CallTraceKind::Function,
+            original,
+        });
+        return Ok(address);
+    }
+
+    // Stops tracing the calls of the functions that a command traces. Calls that are under way are
+    // still reported once they return. Returns how many functions were traced.
+    pub unsafe fn stop_tracing_calls(&mut self, kind: CallTraceKind) -> Result<usize, TraceeError> {
+        let (stopped, kept): (Vec<CallTrace>, Vec<CallTrace>) = mem::take(&mut self.call_traces)
+            .into_iter()
+            .partition(|trace| trace.kind == kind);
+        self.call_traces = kept;
+        for trace in &stopped {
+            if self.call_trace_original(trace.address).is_none() {
+                self.unpatch_memory(trace.address, &trace.original)?;
+            }
+        }
+        return Ok(stopped.len());
+    }
+
+    pub fn call_traces(&self) -> &[CallTrace] {
+        return &self.call_traces;
+    }
+
+    fn has_enabled_breakpoint(&self, address: u64) -> bool {
+        return self
+            .breakpoints
+            .find(address)
+            .is_some_and(|breakpoint| breakpoint.is_enabled());
+    }
+
+    // Refuses a breakpoint where calls are traced, since the breakpoint would replace the call
+    // tracing one.
+    fn check_no_call_trace_at(&self, address: u64) -> Result<(), TraceeError> {
+        return match self.call_trace_original(address) {
+            Some(_) => Err(TraceeError::SetBreakpoint("calls are traced there")),
+            None => Ok(()),
+        };
+    }
+
+    // The bytes that the call tracing breakpoint at `address` replaced, if there is one: at the
+    // entry of a traced function, or at the return address of a traced call.
+    fn call_trace_original(&self, address: u64) -> Option<&[u8]> {
+        return self
+            .call_traces
+            .iter()
+            .find(|trace| trace.address == address)
+            .map(|trace| trace.original.as_slice())
+            .or_else(|| {
+                return self
+                    .return_breakpoints
+                    .iter()
+                    .find(|(return_address, _)| *return_address == address)
+                    .map(|(_, original)| original.as_slice());
+            });
+    }
+
+    // The call tracing breakpoints, with the bytes that they replaced, once each.
+    fn call_trace_breakpoints(&self) -> Vec<(u64, Vec<u8>)> {
+        let mut breakpoints = self
+            .call_traces
+            .iter()
+            .map(|trace| (trace.address, trace.original.clone()))
+            .collect::<Vec<(u64, Vec<u8>)>>();
+        for (address, original) in &self.return_breakpoints {
+            if !breakpoints.iter().any(|(other, _)| other == address) {
+                breakpoints.push((*address, original.clone()));
+            }
+        }
+        return breakpoints;
+    }
+
+    // Puts a call tracing breakpoint at `address`, unless there is one already. Returns the bytes
+    // that it replaced.
+    unsafe fn insert_call_trace_breakpoint(
+        &self,
+        tid: libc::pid_t,
+        address: u64,
+    ) -> Result<Vec<u8>, TraceeError> {
+        return match self.call_trace_original(address) {
+            Some(original) => Ok(original.to_vec()),
+            None => self.patch_thread_memory(tid, address, BREAKPOINT_INSTRUCTION),
+        };
+    }
+
+    // Handles a thread reaching a traced function or the return address of a traced call. The
+    // thread is stepped over the breakpoint there and carries on, or stops after the step if it was
+    // single-stepping anyway.
+    unsafe fn handle_call_trace_trap(&mut self, tid: libc::pid_t, address: u64) -> InternalTrap {
+        let original = self.call_trace_original(address).map(<[u8]>::to_vec);
+        let regs = self.read_thread_general_purpose_registers(tid);
+        // A call may also return to a breakpoint, which stops the tracee once the call is reported.
+        self.end_traced_function_call(tid, address, &regs);
+        let Some(original) = original else {
+            return InternalTrap::Unrelated;
+        };
+        if let Some(trace) = self
+            .call_traces
+            .iter()
+            .find(|trace| trace.address == address)
+            .cloned()
+        {
+            self.begin_traced_function_call(tid, &trace, &regs);
+        }
+
+        // Other threads run meanwhile, and miss the breakpoint if they pass it before it is put
+        // back, as they would with ltrace.
+        if let Err(err) = self.write_thread_memory(tid, address, &original) {
+            println!("{}", err);
+            return InternalTrap::Caught;
+        }
+        let step = self.step_thread(tid);
+        self.forget_stop_state();
+        // The call tracing breakpoints are gone along with the old program, and `handle_exec`
+        // forgets them.
+        if matches!(step, Ok(StepEnd::Event { is_exec: true })) {
+            return InternalTrap::Deferred;
+        }
+        let result = match self.call_trace_original(address) {
+            Some(_) => self.write_thread_memory(tid, address, BREAKPOINT_INSTRUCTION),
+            None => self.unpatch_thread_memory(tid, address, &original),
+        };
+        if let Err(err) = result {
+            println!("{}", err);
+        }
+        match step {
+            Err(err) => {
+                println!("{}", err);
+                return InternalTrap::Deferred;
+            }
+            Ok(StepEnd::Event { .. }) => return InternalTrap::Deferred,
+            Ok(StepEnd::Stepped) => {}
+        }
+        return match self
+            .find_thread(tid)
+            .is_some_and(|thread| thread.single_stepping)
+        {
+            true => InternalTrap::Caught,
+            false => InternalTrap::Handled,
+        };
+    }
+
+    // Notes a thread's call of a traced function, and breaks at its return address to see it
+    // return.
+    unsafe fn begin_traced_function_call(
+        &mut self,
+        tid: libc::pid_t,
+        trace: &CallTrace,
+        regs: &libc::user_regs_struct,
+    ) {
+        let return_address = regs.regs[30];
+        if self.call_trace_original(return_address).is_none() {
+            // The call is reported when it returns to a breakpoint instead.
+            if !self.has_enabled_breakpoint(return_address) {
+                match self.patch_thread_memory(tid, return_address, BREAKPOINT_INSTRUCTION) {
+                    Err(err) => {
+                        println!("{}", err);
+                        return;
+                    }
+                    Ok(original) => self.return_breakpoints.push((return_address, original)),
+                }
+            }
+        }
+        let prefix = self.trace_prefix(tid);
+        let Some(thread) = self.find_thread_mut(tid) else {
+            return;
+        };
+        if trace.kind == CallTraceKind::Function {
+            let depth = function_call_depth(&thread.traced_function_calls);
+            println!("{}{}", prefix, format_function_entry(&trace.name, depth));
+        }
+        thread.traced_function_calls.push(TracedCall {
+            name: trace.name.clone(),
+            kind: trace.kind,
+            arguments: regs.regs[..SHOWN_ARGUMENT_COUNT].to_vec(),
+            return_address,
+            sp: regs.sp,
+            entered_at: Instant::now(),
+        });
+    }
+
+    // Reports the traced calls that a thread returned from to `address`, if any: more than one if
+    // the innermost were tail calls, which return to the same place. The calls that it made since
+    // never return, e.g. because of longjmp(), and are dropped. So are the breakpoints at return
+    // addresses that no thread is to return to anymore, other than the one at `address`, which the
+    // thread is stepped over first.
+    unsafe fn end_traced_function_call(
+        &mut self,
+        tid: libc::pid_t,
+        address: u64,
+        regs: &libc::user_regs_struct,
+    ) {
+        let prefix = self.trace_prefix(tid);
+        if let Some(thread) = self.find_thread_mut(tid) {
+            let calls = &mut thread.traced_function_calls;
+            let returns = |call: &TracedCall| call.return_address == address && call.sp == regs.sp;
+            if let Some(i) = calls.iter().position(returns) {
+                for j in (i..calls.len()).rev().filter(|j| returns(&calls[*j])) {
+                    let call = &calls[j];
+                    let line = match call.kind {
+                        CallTraceKind::Library => {
+                            format_library_call(&call.name, &call.arguments, regs.regs[0])
+                        }
+                        CallTraceKind::Function => format_function_return(
+                            &call.name,
+                            function_call_depth(&calls[..j]),
+                            regs.regs[0],
+                            call.entered_at.elapsed(),
+                        ),
+                    };
+                    println!("{}{}", prefix, line);
+                }
+                calls.truncate(i);
+            }
+        }
+
+        let retired = self
+            .return_breakpoints
+            .iter()
+            .filter(|(return_address, _)| {
+                return !self.threads.iter().any(|thread| {
+                    return thread
+                        .traced_function_calls
+                        .iter()
+                        .any(|call| call.return_address == *return_address);
+                });
+            })
+            .cloned()
+            .collect::<Vec<(u64, Vec<u8>)>>();
+        self.return_breakpoints.retain(|(return_address, _)| {
+            !retired.iter().any(|(other, _)| other == return_address)
+        });
+        for (return_address, original) in retired {
+            if return_address == address || self.call_trace_original(return_address).is_some() {
+                continue;
+            }
+            if let Err(err) = self.unpatch_thread_memory(tid, return_address, &original) {
+                println!("{}", err);
+            }
+        }
+    }
+
+    // Reads `len` bytes of the tracee's memory, starting at `address`.
+    pub unsafe fn read_memory(&self, address: u64, len: usize) -> Result<Vec<u8>, TraceeError> {
+        return self.read_thread_memory(self.selected_tid, address, len);
+    }
+
+    // Overwrites the tracee's memory at `address` with `bytes`, even where it is read-only, e.g. in
+    // code.
+    pub unsafe fn write_memory(&self, address: u64, bytes: &[u8]) -> Result<(), TraceeError> {
+        return self.write_thread_memory(self.selected_tid, address, bytes);
+    }
+
+    unsafe fn write_thread_memory(
+        &self,
+        tid: libc::pid_t,
+        address: u64,
+        bytes: &[u8],
+    ) -> Result<(), TraceeError> {
+        self.forget_stop_state();
+        let word_size = mem::size_of::<libc::c_long>() as u64;
+        let start = address - address % word_size;
+        let end = address + bytes.len() as u64;
+
+        // Words that are only partly overwritten keep the rest of their contents.
+        let mut words = self.read_thread_memory(
+            tid,
+            start,
+            end.next_multiple_of(word_size) as usize - start as usize,
+        )?;
+        let offset = (address - start) as usize;
+        words[offset..offset + bytes.len()].copy_from_slice(bytes);
+
+        for (i, word) in words.chunks(word_size as usize).enumerate() {
+            let word_address = start + i as u64 * word_size;
+            let word = libc::c_long::from_ne_bytes(word.try_into().unwrap());
+            if libc::ptrace(
+                libc::PTRACE_POKEDATA,
+                tid,
+                word_address as *mut libc::c_void,
+                word as *mut libc::c_void,
+            ) < 0
+            {
+                let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+                return Err(TraceeError::WriteMemory {
+                    address: word_address,
+                    message: errno_message.to_string_lossy().to_string(),
+                });
+            }
+        }
+        return Ok(());
+    }
+
+    // Writes `bytes` into the tracee for as long as it is traced, e.g. a breakpoint instruction.
+    // The words that it touches are registered for cleanup, so that they are restored even if
+    // pbreak dies. Returns the bytes that were overwritten, which `unpatch_memory` takes back.
+    pub unsafe fn patch_memory(&self, address: u64, bytes: &[u8]) -> Result<Vec<u8>, TraceeError> {
+        return self.patch_thread_memory(self.selected_tid, address, bytes);
+    }
+
+    unsafe fn patch_thread_memory(
+        &self,
+        tid: libc::pid_t,
+        address: u64,
+        bytes: &[u8],
+    ) -> Result<Vec<u8>, TraceeError> {
+        let word_size = mem::size_of::<libc::c_long>() as u64;
+        let start = address - address % word_size;
+        let end = (address + bytes.len() as u64).next_multiple_of(word_size);
+        let words = self.read_thread_memory(tid, start, (end - start) as usize)?;
+
+        // Registered first, so that the patch is never left behind.
+        for (i, word) in words.chunks(word_size as usize).enumerate() {
+            let word_address = start + i as u64 * word_size;
+            let word = u64::from_ne_bytes(word.try_into().unwrap());
+            if !cleanup::register_patch(self.pid, word_address, word) {
+                for registered_address in (start..word_address).step_by(word_size as usize) {
+                    cleanup::unregister_patch(self.pid, registered_address);
+                }
+                return Err(TraceeError::WriteMemory {
+                    address,
+                    message: "too many words are patched to restore them if pbreak dies"
+                        .to_string(),
+                });
+            }
+        }
+        if let Err(err) = self.write_thread_memory(tid, address, bytes) {
+            for word_address in (start..end).step_by(word_size as usize) {
+                cleanup::unregister_patch(self.pid, word_address);
+            }
+            return Err(err);
+        }
+
+        let offset = (address - start) as usize;
+        return Ok(words[offset..offset + bytes.len()].to_vec());
+    }
+
+    // Undoes a patch, given the bytes that it overwrote.
+    pub unsafe fn unpatch_memory(&self, address: u64, original: &[u8]) -> Result<(), TraceeError> {
+        return self.unpatch_thread_memory(self.selected_tid, address, original);
+    }
+
+    unsafe fn unpatch_thread_memory(
+        &self,
+        tid: libc::pid_t,
+        address: u64,
+        original: &[u8],
+    ) -> Result<(), TraceeError> {
+        self.write_thread_memory(tid, address, original)?;
+        let word_size = mem::size_of::<libc::c_long>() as u64;
+        let start = address - address % word_size;
+        let end = address + original.len() as u64;
+        for word_address in (start..end).step_by(word_size as usize) {
+            cleanup::unregister_patch(self.pid, word_address);
+        }
+        return Ok(());
+    }
+
+    // Writes code into the tracee, as `patch_memory` does, and keeps track of it so that it can be
+    // listed and restored.
+    pub unsafe fn patch_code(&mut self, address: u64, bytes: &[u8]) -> Result<(), TraceeError> {
+        let original = self.patch_memory(address, bytes)?;
+        self.code_patches.push(CodePatch {
+            address,
+            original,
+            bytes: bytes.to_vec(),
+        });
+        return Ok(());
+    }
+
+    pub fn code_patches(&self) -> &[CodePatch] {
+        return &self.code_patches;
+    }
+
+    // Puts back the code that the latest patch at `address` replaced. A patch that later patches
+    // overlap has to wait until they are restored, since they replaced some of its code.
+    pub unsafe fn restore_code_patch(&mut self, address: u64) -> Result<(), TraceeError> {
+        let Some(i) = self
+            .code_patches
+            .iter()
+            .rposition(|patch| patch.address == address)
+        else {
+            return Err(TraceeError::RestorePatch("no patch at that address"));
+        };
+        let patch = &self.code_patches[i];
+        let end = patch.address + patch.bytes.len() as u64;
+        if self.code_patches[i + 1..].iter().any(|later| {
+            later.address < end && patch.address < later.address + later.bytes.len() as u64
+        }) {
+            return Err(TraceeError::RestorePatch("a later patch overlaps it"));
+        }
+        self.unpatch_memory(patch.address, &patch.original)?;
+        self.code_patches.remove(i);
+        return Ok(());
+    }
+
+    // Sets a software breakpoint at `address`. Returns its ID.
+    pub unsafe fn insert_breakpoint(&mut self, address: u64) -> Result<usize, TraceeError> {
+        self.check_no_call_trace_at(address)?;
+        // The set is taken out while it patches the tracee.
+        let mut breakpoints = mem::take(&mut self.breakpoints);
+        let result = breakpoints.insert(self, address, None);
+        self.breakpoints = breakpoints;
+        return result;
+    }
+
+    // Sets a software breakpoint at a location, as `resolve_location` resolves it. Unless the
+    // location is an address, the breakpoint is looked up again in the new program at exec.
+    // Returns its ID and address.
+    pub unsafe fn insert_breakpoint_at(&mut self, location: &str) -> Result<(usize, u64), String> {
+        let address = self.resolve_location(location)?;
+        self.check_no_call_trace_at(address)
+            .map_err(|err| err.to_string())?;
+        let location = match location.starts_with("0x") {
+            true => None,
+            false => Some(location),
+        };
+        let mut breakpoints = mem::take(&mut self.breakpoints);
+        let result = breakpoints.insert(self, address, location);
+        self.breakpoints = breakpoints;
+        return result
+            .map(|id| (id, address))
+            .map_err(|err| err.to_string());
+    }
+
+    // Sets the pending breakpoints whose locations the program now has code for, e.g. once it has
+    // exec'd or loaded a library.
+    unsafe fn resolve_pending_breakpoints(&mut self) {
+        for pending in self.breakpoints.pending().to_vec() {
+            let Ok(address) = self.resolve_location(&pending.location) else {
+                continue;
+            };
+            let mut breakpoints = mem::take(&mut self.breakpoints);
+            let result = breakpoints.resolve_pending(self, pending.id, address);
+            self.breakpoints = breakpoints;
+            match result {
+                Err(err) => println!("{}", err),
+                Ok(()) => println!(
+                    "Breakpoint {} at {:#x}: {}",
+                    pending.id, address, pending.location
+                ),
+            }
+        }
+    }
+
+    pub unsafe fn enable_breakpoint(&mut self, id: usize) -> Result<(), TraceeError> {
+        if let Some(breakpoint) = self
+            .breakpoints
+            .breakpoints()
+            .iter()
+            .find(|breakpoint| breakpoint.id() == id)
+        {
+            self.check_no_call_trace_at(breakpoint.address())?;
+        }
+        let mut breakpoints = mem::take(&mut self.breakpoints);
+        let result = breakpoints.enable(self, id);
+        self.breakpoints = breakpoints;
+        return result;
+    }
+
+    // Puts back the instruction that a breakpoint replaced, but keeps it for when it is enabled
+    // again.
+    pub unsafe fn disable_breakpoint(&mut self, id: usize) -> Result<(), TraceeError> {
+        let mut breakpoints = mem::take(&mut self.breakpoints);
+        let result = breakpoints.disable(self, id);
+        self.breakpoints = breakpoints;
+        return result;
+    }
+
+    pub unsafe fn delete_breakpoint(&mut self, id: usize) -> Result<(), TraceeError> {
+        let mut breakpoints = mem::take(&mut self.breakpoints);
+        let result = breakpoints.delete(self, id);
+        self.breakpoints = breakpoints;
+        return result;
+    }
+
+    pub fn breakpoints(&self) -> &BreakpointSet {
+        return &self.breakpoints;
+    }
+
+    // Undoes the breakpoints in `code`, which was read from `address`, so that it shows the program
+    // as it would run without them, code patches included. Returns the addresses of the bytes that
+    // differ from what was read.
+    pub fn hide_breakpoints(&self, code: &mut [u8], address: u64) -> Vec<u64> {
+        let read = code.to_vec();
+        restore_patched_bytes(code, address, &cleanup::patches(self.pid));
+        for patch in &self.code_patches {
+            for (i, byte) in patch.bytes.iter().enumerate() {
+                let Some(offset) = (patch.address + i as u64).checked_sub(address) else {
+                    continue;
+                };
+                if let Some(code_byte) = code.get_mut(offset as usize) {
+                    *code_byte = *byte;
+                }
+            }
+        }
+        return (0..code.len())
+            .filter(|i| code[*i] != read[*i])
+            .map(|i| address + i as u64)
+            .collect();
+    }
+
+    // The hardware breakpoint and watchpoint slots, and which of them are in use. The hardware is
+    // asked how many there are the first time.
+    pub unsafe fn debug_slots(&mut self) -> Result<&DebugSlots, TraceeError> {
+        if self.debug_slots.is_none() {
+            let breakpoint_count = read_slot_count(self.selected_tid, SlotKind::Breakpoint)?;
+            let watchpoint_count = read_slot_count(self.selected_tid, SlotKind::Watchpoint)?;
+            self.debug_slots = Some(DebugSlots::new(breakpoint_count, watchpoint_count));
+        }
+        return Ok(self.debug_slots.as_ref().unwrap());
+    }
+
+    // Takes a free hardware slot and sets it in every thread. Returns the index of the slot.
+    pub unsafe fn allocate_debug_slot(
+        &mut self,
+        kind: SlotKind,
+        slot: HardwareSlot,
+    ) -> Result<usize, TraceeError> {
+        self.debug_slots()?;
+        let index = self.debug_slots.as_mut().unwrap().allocate(kind, slot)?;
+        if let Err(err) = self.write_debug_slots(kind) {
+            // Threads that were written already are put back as they were.
+            self.debug_slots.as_mut().unwrap().free(kind, index);
+            let _ = self.write_debug_slots(kind);
+            return Err(err.into());
+        }
+        return Ok(index);
+    }
+
+    // Clears a hardware slot in every thread, so that it can be taken again.
+    pub unsafe fn free_debug_slot(
+        &mut self,
+        kind: SlotKind,
+        index: usize,
+    ) -> Result<(), TraceeError> {
+        let Some(slots) = self.debug_slots.as_mut() else {
+            return Ok(());
+        };
+        if slots.free(kind, index).is_some() {
+            self.write_debug_slots(kind)?;
+        }
+        return Ok(());
+    }
+
+    unsafe fn write_debug_slots(&self, kind: SlotKind) -> Result<(), HardwareDebugError> {
+        let Some(slots) = &self.debug_slots else {
+            return Ok(());
+        };
+        for tid in self.tids() {
+            write_slots(tid, kind, slots.slots(kind))?;
+        }
+        return Ok(());
+    }
+
+    // Sets the slots that are in use in a thread that does not have them yet, e.g. a new one.
+    unsafe fn mirror_debug_slots(&self, tid: libc::pid_t) {
+        let Some(slots) = self.debug_slots.as_ref().filter(|slots| slots.is_in_use()) else {
+            return;
+        };
+        for kind in [SlotKind::Breakpoint, SlotKind::Watchpoint] {
+            if let Err(err) = write_slots(tid, kind, slots.slots(kind)) {
+                println!("{}", err);
+            }
+        }
+    }
+
+    // Reads a NUL-terminated string out of the tracee's memory, without the NUL. At most
+    // `max_len` bytes are read.
+    pub unsafe fn read_c_string(
+        &self,
+        address: u64,
+        max_len: usize,
+    ) -> Result<Vec<u8>, TraceeError> {
+        return self.read_thread_c_string(self.selected_tid, address, max_len);
+    }
+
+    // Memory is accessed through a specific thread, which must be stopped.
+    unsafe fn read_thread_memory(
+        &self,
+        tid: libc::pid_t,
+        address: u64,
+        len: usize,
+    ) -> Result<Vec<u8>, TraceeError> {
+        // Threads share their memory, so what was fetched at the stop serves every one of them.
+        if let Some(state) = self.stop_state.borrow().as_ref() {
+            let offset = address.wrapping_sub(state.memory_start) as usize;
+            if address >= state.memory_start && offset + len <= state.memory.len() {
+                return Ok(state.memory[offset..offset + len].to_vec());
+            }
+        }
+
+        let word_size = mem::size_of::<libc::c_long>() as u64;
+        let start = address - address % word_size;
+        let end = address + len as u64;
+
+        let mut bytes = Vec::with_capacity(len + 2 * word_size as usize);
+        let mut word_address = start;
+        while word_address < end {
+            // PTRACE_PEEKDATA returns the word itself, so errors can only be told apart by errno.
+            *libc::__errno_location() = 0;
+            let word = libc::ptrace(
+                libc::PTRACE_PEEKDATA,
+                tid,
+                word_address as *mut libc::c_void,
+                null_mut::<*mut libc::c_void>(),
+            );
+            if *libc::__errno_location() != 0 {
+                let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+                return Err(TraceeError::ReadMemory {
+                    address: word_address,
+                    message: errno_message.to_string_lossy().to_string(),
+                });
+            }
+
+            bytes.extend_from_slice(&word.to_ne_bytes());
+            word_address += word_size;
+        }
+
+        let offset = (address - start) as usize;
+        return Ok(bytes[offset..offset + len].to_vec());
+    }
+
+    unsafe fn read_thread_c_string(
+        &self,
+        tid: libc::pid_t,
+        address: u64,
+        max_len: usize,
+    ) -> Result<Vec<u8>, TraceeError> {
+        let word_size = mem::size_of::<libc::c_long>() as u64;
+        let mut string = vec![];
+        let mut address = address;
+        while string.len() < max_len {
+            // Read up to the end of the word, so that the string may end just before unmapped memory.
+            let len = (word_size - address % word_size) as usize;
+            for byte in self.read_thread_memory(tid, address, len)? {
+                if byte == 0 || string.len() == max_len {
+                    return Ok(string);
+                }
+                string.push(byte);
+            }
+            address += len as u64;
+        }
+        return Ok(string);
+    }
+
+    pub unsafe fn read_general_purpose_registers(&self) -> libc::user_regs_struct {
+        return self.read_thread_general_purpose_registers(self.selected_tid);
+    }
+
+    // Reads the general purpose registers of a specific thread, regardless of the selection.
+    pub unsafe fn read_thread_general_purpose_registers(
+        &self,
+        tid: libc::pid_t,
+    ) -> libc::user_regs_struct {
+        if let Some(state) = self
+            .stop_state
+            .borrow()
+            .as_ref()
+            .filter(|state| state.tid == tid)
+        {
+            return state.regs;
+        }
+        let mut data = mem::MaybeUninit::<libc::user_regs_struct>::uninit();
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr() as *mut libc::c_void,
+            iov_len: mem::size_of::<libc::user_regs_struct>(),
+        };
+        if libc::ptrace(
+            libc::PTRACE_GETREGSET,
+            tid,
+            libc::NT_PRSTATUS,
+            &mut iov as *mut libc::iovec,
+        ) < 0
+        {
+            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+            panic!(
+                "failed to read general purpose registers: {:?}",
+                errno_message
+            );
+        };
+        let regs = data.assume_init();
+        return regs;
+    }
+
+    pub unsafe fn write_general_purpose_registers(&self, regs: &mut libc::user_regs_struct) {
+        self.write_thread_general_purpose_registers(self.selected_tid, regs);
+    }
+
+    // Writes the general purpose registers of a specific thread, regardless of the selection.
+    pub unsafe fn write_thread_general_purpose_registers(
+        &self,
+        tid: libc::pid_t,
+        regs: &mut libc::user_regs_struct,
+    ) {
+        self.forget_stop_state();
+        let mut iov = libc::iovec {
+            iov_base: regs as *mut libc::user_regs_struct as *mut libc::c_void,
+            iov_len: mem::size_of::<libc::user_regs_struct>(),
+        };
+        if libc::ptrace(
+            libc::PTRACE_SETREGSET,
+            tid,
+            libc::NT_PRSTATUS,
+            &mut iov as *mut libc::iovec as *mut libc::c_void,
+        ) < 0
+        {
+            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+            panic!(
+                "failed to write general purpose registers: {:?}",
+                errno_message
+            );
+        };
+    }
+
+    pub unsafe fn read_floating_point_registers(&self) -> libc::user_fpsimd_struct {
+        return self.read_thread_floating_point_registers(self.selected_tid);
+    }
+
+    pub unsafe fn read_thread_floating_point_registers(
+        &self,
+        tid: libc::pid_t,
+    ) -> libc::user_fpsimd_struct {
+        let mut data = mem::MaybeUninit::<libc::user_fpsimd_struct>::uninit();
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr() as *mut libc::c_void,
+            iov_len: mem::size_of::<libc::user_fpsimd_struct>(),
+        };
+        if libc::ptrace(
+            libc::PTRACE_GETREGSET,
+            tid,
+            libc::NT_PRFPREG,
+            &mut iov as *mut libc::iovec as *mut libc::c_void,
+        ) < 0
+        {
+            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+            panic!(
+                "failed to read floating point registers: {:?}",
+                errno_message
+            );
+        };
+        let regs = data.assume_init();
+        return regs;
+    }
+
+    pub unsafe fn write_floating_point_registers(&self, regs: &mut libc::user_fpsimd_struct) {
+        let mut iov = libc::iovec {
+            iov_base: regs as *mut libc::user_fpsimd_struct as *mut libc::c_void,
+            iov_len: mem::size_of::<libc::user_fpsimd_struct>(),
+        };
+        if libc::ptrace(
+            libc::PTRACE_SETREGSET,
+            self.selected_tid,
+            libc::NT_PRFPREG,
+            &mut iov as *mut libc::iovec as *mut libc::c_void,
+        ) < 0
+        {
+            let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+            panic!(
+                "failed to write floating point registers: {:?}",
+                errno_message
+            );
+        };
+    }
+}
+
+// How many of the traced calls that a thread is in are calls of functions that `ftrace` traces,
+// which their entries and returns are indented by.
+fn function_call_depth(calls: &[TracedCall]) -> usize {
+    return calls
+        .iter()
+        .filter(|call| call.kind == CallTraceKind::Function)
+        .count();
+}
+
+// Stops a seized thread. The stop is reported as a PTRACE_EVENT_STOP.
+unsafe fn interrupt_thread(tid: libc::pid_t) {
+    if libc::ptrace(
+        libc::PTRACE_INTERRUPT,
+        tid,
+        null_mut::<*mut libc::c_void>(),
+        null_mut::<*mut libc::c_void>(),
+    ) < 0
+    {
+        let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+        panic!("failed to interrupt tid ({}): {:?}", tid, errno_message);
+    }
+}
+
+// Sends which step of setting up a newly forked child failed, and errno, to the parent, and exits.
+// The parent builds the message.
+unsafe fn fail_launch(pipe: &Pipe, failure: usize) -> ! {
+    let errno = *libc::__errno_location();
+    let mut report = [0; 12];
+    report[..8].copy_from_slice(&(failure as u64).to_ne_bytes());
+    report[8..].copy_from_slice(&errno.to_ne_bytes());
+    pipe.send_raw(&report);
+    libc::_exit(-1);
+}
+
+unsafe fn detach_thread(tid: libc::pid_t) {
+    if libc::ptrace(
+        libc::PTRACE_DETACH,
+        tid,
+        null_mut::<*mut libc::c_void>(),
+        null_mut::<*mut libc::c_void>(),
+    ) < 0
+    {
+        let errno_message = CStr::from_ptr(libc::strerror(*libc::__errno_location()));
+        panic!("failed to detach from tid ({}): {:?}", tid, errno_message);
+    }
+}
+
+// Prints a source line ahead of the instructions that were compiled from it, preceded by the path
+// of its file if it is not the same as the previous line's. The lines of the file may be missing.
+fn print_source_line(lines: Option<&[String]>, row: &LineRow, is_new_file: bool) {
+    let text = lines.and_then(|lines| lines.get((row.line as usize).wrapping_sub(1)));
+    match text {
+        None => println!("{}:{}", row.path, row.line),
+        Some(text) => {
+            if is_new_file {
+                println!("{}:", row.path);
+            }
+            println!("{:>6}\t{}", row.line, text);
+        }
+    }
+}
+
+impl Drop for Tracee {
+    fn drop(&mut self) {
+        // Once cleaned up, the process is no longer traced and must be left running.
+        if self.pid == 0 || cleanup::has_run() {
+            return;
+        }
+        cleanup::unregister_tracee(self.pid);
+
+        unsafe {
+            let mut wait_status = 0;
+            for thread in &self.threads {
+                if thread.status == ThreadStatus::Running {
+                    libc::syscall(libc::SYS_tgkill, self.pid, thread.tid, libc::SIGSTOP);
+                    libc::waitpid(thread.tid, &mut wait_status, libc::__WALL);
+                }
+
+                libc::ptrace(
+                    libc::PTRACE_DETACH,
+                    thread.tid,
+                    null_mut::<*mut libc::c_void>(),
+                    null_mut::<*mut libc::c_void>(),
+                );
+            }
+
+            libc::kill(self.pid, libc::SIGCONT);
+            libc::kill(self.pid, libc::SIGKILL);
+            libc::waitpid(self.pid, &mut wait_status, libc::__WALL);
+            reaper::forget_tasks(&self.tids());
+        }
+    }
+}
+
+// Moves functions from the addresses that their file gives to where it is loaded.
+fn relocate_functions(functions: Vec<FunctionSymbol>, bias: u64) -> Vec<FunctionSymbol> {
+    return functions
+        .into_iter()
+        .map(|function| FunctionSymbol {
+            address: function.address.wrapping_add(bias),
+            ..function
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod test {
+    use std::{ffi::CString, io::BufRead, ptr::null};
+
+    use super::{
+        CallArgument, FollowForkMode, SchedulerLocking, Tracee, TraceeError, TraceeStatus,
+    };
+    use crate::{
+        asm::assemble,
+        cleanup,
+        disasm::BREAKPOINT_INSTRUCTION,
+        hwdebug::{HardwareDebugError, HardwareSlot, SlotKind},
+        launch::LaunchOptions,
+        procfs,
+        reaper::WaitStatus,
+        signal::Trap,
+        syscall::SyscallStop,
+    };
+
+    #[test]
+    fn tracee_from_pid_succeeds_when_pid_exists() {
+        unsafe {
+            match libc::fork() {
+                0 => {
+                    // Child process
+                    let program = CString::new("sleep").unwrap();
+                    let arg = CString::new("1").unwrap();
+                    let args = [program.as_ptr(), arg.as_ptr(), null()];
+                    libc::execvp(program.as_ptr(), args.as_ptr());
+                }
+                pid => {
+                    // Parent process
+                    Tracee::from_pid(pid);
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn tracee_from_pid_panics_when_pid_does_not_exist() {
+        unsafe {
+            Tracee::from_pid(-1);
+        }
+    }
+
+    #[test]
     fn tracee_from_cmd_succeeds_when_command_is_valid() {
         unsafe {
-            let tracee = Tracee::from_cmd("sleep", &vec!["1".to_string()]);
+            let tracee = Tracee::from_cmd("sleep", &["1".to_string()]);
             let status = procfs_read_status(tracee.pid);
             assert_eq!('t', status);
         }
     }
 
+    #[test]
+    fn tracee_from_cmd_passes_program_and_args_as_argv() {
+        unsafe {
+            let args = ["1".to_string(), "a b".to_string(), "".to_string()];
+            let tracee = Tracee::from_cmd("sleep", &args);
+            assert_eq!(
+                procfs::read_cmdline(tracee.pid).unwrap(),
+                vec!["sleep", "1", "a b", ""]
+            );
+        }
+    }
+
+    #[test]
+    fn tracee_from_cmd_runs_program_with_args() {
+        unsafe {
+            let args = ["-c", "exit $#", "sh", "a", "b c"].map(str::to_string);
+            let mut tracee = Tracee::from_cmd("sh", &args);
+            tracee.resume();
+            tracee.wait_on_signal();
+            assert_eq!(tracee.exit_status(), Some(WaitStatus::Exited(2)));
+        }
+    }
+
     #[test]
     #[should_panic]
     fn tracee_from_cmd_panics_when_command_is_not_valid() {
         unsafe {
-            Tracee::from_cmd("nonexistent_program", &vec![]);
+            Tracee::from_cmd("nonexistent_program", &[]);
         }
     }
 
@@ -341,8 +5127,7 @@ mod test {
                     // Child process
                     let program = CString::new("sleep").unwrap();
                     let arg = CString::new("1").unwrap();
-                    let mut args = vec![arg.as_ptr()];
-                    args.push(null());
+                    let args = [program.as_ptr(), arg.as_ptr(), null()];
                     libc::execvp(program.as_ptr(), args.as_ptr());
                 }
                 pid => {
@@ -359,7 +5144,7 @@ mod test {
     #[test]
     fn tracee_resume_succeeds_when_tracee_is_from_cmd() {
         unsafe {
-            let mut tracee = Tracee::from_cmd("sleep", &vec!["1".to_string()]);
+            let mut tracee = Tracee::from_cmd("sleep", &["1".to_string()]);
             tracee.resume();
             let status = procfs_read_status(tracee.pid);
             assert_eq!('R', status);
@@ -370,7 +5155,7 @@ mod test {
     #[should_panic]
     fn tracee_resume_panics_when_tracee_has_existed() {
         unsafe {
-            let mut tracee = Tracee::from_cmd("echo", &vec![]);
+            let mut tracee = Tracee::from_cmd("echo", &[]);
             tracee.resume();
             tracee.wait_on_signal();
             tracee.resume();
@@ -380,7 +5165,7 @@ mod test {
     #[test]
     fn tracee_read_general_purpose_registers_works() {
         unsafe {
-            let tracee = Tracee::from_cmd("echo", &vec![]);
+            let tracee = Tracee::from_cmd("echo", &[]);
             tracee.read_general_purpose_registers();
         }
     }
@@ -388,11 +5173,793 @@ mod test {
     #[test]
     fn tracee_read_floating_point_registers_works() {
         unsafe {
-            let tracee = Tracee::from_cmd("echo", &vec![]);
+            let tracee = Tracee::from_cmd("echo", &[]);
             tracee.read_floating_point_registers();
         }
     }
 
+    #[test]
+    fn tracee_from_cmd_selects_main_thread() {
+        unsafe {
+            let tracee = Tracee::from_cmd("sleep", &["1".to_string()]);
+            assert_eq!(tracee.selected_tid(), tracee.pid);
+            assert_eq!(tracee.threads().len(), 1);
+        }
+    }
+
+    #[test]
+    fn tracee_select_thread_rejects_unknown_tid() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("sleep", &["1".to_string()]);
+            assert!(!tracee.select_thread(-1));
+            assert_eq!(tracee.selected_tid(), tracee.pid);
+        }
+    }
+
+    #[test]
+    fn follow_fork_mode_from_name_parses_known_modes() {
+        assert_eq!(
+            FollowForkMode::from_name("parent"),
+            Some(FollowForkMode::Parent)
+        );
+        assert_eq!(
+            FollowForkMode::from_name("child"),
+            Some(FollowForkMode::Child)
+        );
+        assert_eq!(
+            FollowForkMode::from_name("both"),
+            Some(FollowForkMode::Both)
+        );
+        assert_eq!(FollowForkMode::from_name("sibling"), None);
+    }
+
+    #[test]
+    fn scheduler_locking_from_name_parses_known_modes() {
+        assert_eq!(
+            SchedulerLocking::from_name("off"),
+            Some(SchedulerLocking::Off)
+        );
+        assert_eq!(
+            SchedulerLocking::from_name("step"),
+            Some(SchedulerLocking::Step)
+        );
+        assert_eq!(
+            SchedulerLocking::from_name("on"),
+            Some(SchedulerLocking::On)
+        );
+        assert_eq!(SchedulerLocking::from_name("replay"), None);
+    }
+
+    #[test]
+    fn tracee_stop_state_matches_ptrace_reads() {
+        unsafe {
+            let tracee = Tracee::from_cmd("echo", &[]);
+            assert!(tracee.stop_state.borrow().is_some());
+            let pc = tracee.read_general_purpose_registers().pc;
+            let prefetched = tracee.read_memory(pc, 16).unwrap();
+            tracee.forget_stop_state();
+            assert_eq!(tracee.read_memory(pc, 16).unwrap(), prefetched);
+            assert_eq!(tracee.read_general_purpose_registers().pc, pc);
+        }
+    }
+
+    #[test]
+    fn tracee_read_maps_is_cached_until_resumed() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            let maps = tracee.read_maps();
+            assert_eq!(Some(maps), procfs::read_maps(tracee.pid()));
+            assert!(tracee.maps.borrow().is_some());
+            tracee.resume();
+            assert!(tracee.maps.borrow().is_none());
+        }
+    }
+
+    #[test]
+    fn tracee_call_function_returns_result_and_restores_registers() {
+        unsafe {
+            let mut options = LaunchOptions::new("sleep", &["1".to_string()]);
+            options.stop_at_entry = true;
+            let mut tracee = Tracee::launch(&options);
+            let functions = tracee.read_library_functions();
+            let find = |name: &str| {
+                return functions
+                    .iter()
+                    .find(|function| function.name == name)
+                    .unwrap()
+                    .address;
+            };
+
+            let regs = tracee.read_general_purpose_registers();
+            let pid = tracee.call_function(find("getpid"), &[]).unwrap();
+            assert_eq!(pid, tracee.pid() as u64);
+            let abs = tracee
+                .call_function(find("labs"), &[CallArgument::Integer(-7i64 as u64)])
+                .unwrap();
+            assert_eq!(abs, 7);
+            let restored = tracee.read_general_purpose_registers();
+            assert_eq!(
+                (restored.regs, restored.sp, restored.pc),
+                (regs.regs, regs.sp, regs.pc)
+            );
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn tracee_call_function_returns_to_breakpoint_at_entry() {
+        unsafe {
+            let mut options = LaunchOptions::new("sleep", &["1".to_string()]);
+            options.stop_at_entry = true;
+            let mut tracee = Tracee::launch(&options);
+            let getpid = tracee
+                .read_library_functions()
+                .into_iter()
+                .find(|function| function.name == "getpid")
+                .unwrap();
+            let entry = tracee.read_entry().unwrap();
+            let id = tracee.insert_breakpoint(entry).unwrap();
+            let patches = cleanup::patches(tracee.pid());
+
+            let pid = tracee.call_function(getpid.address, &[]).unwrap();
+            assert_eq!(pid, tracee.pid() as u64);
+            // The breakpoint is left in place, along with the patch that restores its word.
+            assert!(tracee.breakpoints().get(id).unwrap().is_enabled());
+            assert_eq!(
+                tracee
+                    .read_memory(entry, BREAKPOINT_INSTRUCTION.len())
+                    .unwrap(),
+                BREAKPOINT_INSTRUCTION
+            );
+            assert_eq!(cleanup::patches(tracee.pid()), patches);
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn tracee_pop_frame_returns_to_caller() {
+        unsafe {
+            let mut options = LaunchOptions::new("sleep", &["1".to_string()]);
+            options.stop_at_entry = true;
+            let mut tracee = Tracee::launch(&options);
+            let getpid = tracee
+                .read_library_functions()
+                .into_iter()
+                .find(|function| function.name == "getpid")
+                .unwrap();
+
+            // As if the entry point had just called getpid().
+            let caller_regs = tracee.read_general_purpose_registers();
+            let mut regs = caller_regs;
+            regs.pc = getpid.address;
+            regs.regs[30] = caller_regs.pc;
+            tracee.write_general_purpose_registers(&mut regs);
+            assert_eq!(tracee.pop_frame().unwrap(), caller_regs.pc);
+            let popped_regs = tracee.read_general_purpose_registers();
+            assert_eq!(
+                (popped_regs.pc, popped_regs.sp),
+                (caller_regs.pc, caller_regs.sp)
+            );
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn tracee_watchpoint_stops_only_where_its_condition_holds() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            tracee.set_reports_stops(false);
+            let regs = tracee.read_general_purpose_registers();
+            // Stores 5 and then 7 below the stack pointer, with `stur x1, [sp, #-8]`.
+            let code = assemble(
+                "mov x1, #5; .inst 0xf81f83e1; mov x1, #7; .inst 0xf81f83e1; brk #0",
+                regs.pc,
+            )
+            .unwrap();
+            tracee.patch_code(regs.pc, &code).unwrap();
+            let address = regs.sp - 8;
+            assert!(matches!(
+                tracee.insert_watchpoint(address, 8, Some("$value ==")),
+                Err(TraceeError::Expression(_))
+            ));
+            let id = match tracee.insert_watchpoint(address, 8, Some("$value == 7")) {
+                Err(TraceeError::HardwareDebug(HardwareDebugError::Unsupported { .. })) => {
+                    tracee.kill();
+                    return;
+                }
+                result => result.unwrap(),
+            };
+
+            tracee.resume();
+            tracee.wait_on_signal();
+            assert_eq!(tracee.status(), TraceeStatus::Stopped);
+            assert_eq!(tracee.read_general_purpose_registers().pc, regs.pc + 16);
+            assert_eq!(tracee.watchpoints()[0].value, 7);
+            tracee.delete_watchpoint(id).unwrap();
+            assert!(tracee.watchpoints().is_empty());
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn tracee_patch_code_is_listed_and_restored() {
+        unsafe {
+            let mut options = LaunchOptions::new("sleep", &["1".to_string()]);
+            options.stop_at_entry = true;
+            let mut tracee = Tracee::launch(&options);
+            let getpid = tracee
+                .read_library_functions()
+                .into_iter()
+                .find(|function| function.name == "getpid")
+                .unwrap();
+
+            let code = assemble("mov w0, #7; ret", getpid.address).unwrap();
+            tracee.patch_code(getpid.address, &code).unwrap();
+            assert_eq!(tracee.code_patches().len(), 1);
+            assert_eq!(tracee.code_patches()[0].bytes, code);
+            let mut shown = tracee.read_memory(getpid.address, code.len()).unwrap();
+            tracee.hide_breakpoints(&mut shown, getpid.address);
+            assert_eq!(shown, code);
+            assert_eq!(tracee.call_function(getpid.address, &[]).unwrap(), 7);
+
+            tracee.restore_code_patch(getpid.address).unwrap();
+            assert!(tracee.code_patches().is_empty());
+            assert_eq!(
+                tracee.call_function(getpid.address, &[]).unwrap(),
+                tracee.pid() as u64
+            );
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn tracee_debug_slots_run_out_and_are_freed() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            let total = tracee
+                .debug_slots()
+                .unwrap()
+                .slots(SlotKind::Breakpoint)
+                .len();
+            let pc = tracee.read_general_purpose_registers().pc;
+            // Disabled, so that the slots are taken without any breakpoint being hit.
+            let slot = HardwareSlot {
+                address: pc,
+                ctrl: 0,
+            };
+            for index in 0..total {
+                let allocated = tracee.allocate_debug_slot(SlotKind::Breakpoint, slot);
+                assert_eq!(allocated.unwrap(), index);
+            }
+            match tracee.allocate_debug_slot(SlotKind::Breakpoint, slot) {
+                Err(TraceeError::HardwareDebug(HardwareDebugError::NoFreeSlot { .. }))
+                | Err(TraceeError::HardwareDebug(HardwareDebugError::Unsupported { .. })) => {}
+                result => panic!("unexpected result: {:?}", result),
+            }
+            if total > 0 {
+                tracee.free_debug_slot(SlotKind::Breakpoint, 0).unwrap();
+                let slots = tracee.debug_slots().unwrap();
+                assert_eq!(slots.free_count(SlotKind::Breakpoint), 1);
+                assert_eq!(
+                    tracee
+                        .allocate_debug_slot(SlotKind::Breakpoint, slot)
+                        .unwrap(),
+                    0
+                );
+            }
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn tracee_read_shared_libraries_lists_libc() {
+        unsafe {
+            let mut options = LaunchOptions::new("sleep", &["1".to_string()]);
+            options.stop_at_entry = true;
+            let mut tracee = Tracee::launch(&options);
+            let libraries = tracee.read_shared_libraries().unwrap();
+            let libc = libraries
+                .iter()
+                .find(|library| library.path.contains("libc.so"))
+                .unwrap();
+            assert!(libc.start < libc.end);
+            assert!(libc.has_symbols);
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn tracee_catch_load_stops_once_libc_is_loaded() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("sleep", &["1".to_string()]);
+            tracee.catch_load("libc.so").unwrap();
+            assert!(tracee.read_shared_libraries().unwrap().is_empty());
+            match tracee.break_on_resolve("no_such_function") {
+                Err(TraceeError::BreakOnResolve(_)) => {}
+                result => panic!("unexpected result: {:?}", result),
+            }
+            tracee.resume();
+            tracee.wait_on_signal();
+            assert_eq!(tracee.status(), TraceeStatus::Stopped);
+            let libraries = tracee.read_shared_libraries().unwrap();
+            assert!(libraries
+                .iter()
+                .any(|library| library.path.contains("libc.so")));
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn tracee_reload_shared_libraries_indexes_new_objects_once() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("sleep", &["1".to_string()]);
+            tracee.reload_shared_libraries().unwrap();
+            let reload = tracee.reload_shared_libraries().unwrap();
+            assert!(reload.added.is_empty() && reload.removed.is_empty());
+
+            // libc is indexed as it is loaded, before anything looks it up.
+            tracee.catch_load("libc.so").unwrap();
+            tracee.resume();
+            tracee.wait_on_signal();
+            assert!(tracee
+                .symbol_index
+                .borrow()
+                .objects()
+                .iter()
+                .any(|object| object.path.contains("libc.so")));
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn tracee_stops_at_breakpoint() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            tracee.set_reports_stops(false);
+            let entry = tracee.read_entry().unwrap();
+            let id = tracee.insert_breakpoint(entry).unwrap();
+            assert!(matches!(
+                tracee.insert_breakpoint(entry),
+                Err(TraceeError::SetBreakpoint(_))
+            ));
+            tracee.resume();
+            tracee.wait_on_signal();
+            assert_eq!(tracee.status(), TraceeStatus::Stopped);
+            assert_eq!(tracee.read_general_purpose_registers().pc, entry);
+            // Disabled breakpoints are kept, but no longer stop the tracee.
+            tracee.disable_breakpoint(id).unwrap();
+            assert_eq!(tracee.breakpoints().breakpoints().len(), 1);
+            assert!(matches!(
+                tracee.delete_breakpoint(id + 1),
+                Err(TraceeError::UnknownBreakpoint(_))
+            ));
+            tracee.resume();
+            tracee.wait_on_signal();
+            assert_eq!(tracee.status(), TraceeStatus::Exited);
+        }
+    }
+
+    #[test]
+    fn tracee_restores_word_with_two_breakpoints() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            tracee.set_reports_stops(false);
+            let pc = tracee.read_general_purpose_registers().pc;
+            let original = tracee.read_memory(pc, 8).unwrap();
+            let first = tracee.insert_breakpoint(pc).unwrap();
+            let second = tracee.insert_breakpoint(pc + 4).unwrap();
+            // The word that both patch is restored to what it held before either, even once one
+            // of them is gone.
+            tracee.delete_breakpoint(first).unwrap();
+            let mut code = tracee.read_memory(pc, 8).unwrap();
+            tracee.hide_breakpoints(&mut code, pc);
+            assert_eq!(code, original);
+            tracee.delete_breakpoint(second).unwrap();
+            assert_eq!(tracee.read_memory(pc, 8).unwrap(), original);
+            assert!(cleanup::patches(tracee.pid()).is_empty());
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn tracee_stops_at_hw_breakpoint_once_per_resume() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            tracee.set_reports_stops(false);
+            let entry = tracee.read_entry().unwrap();
+            assert!(matches!(
+                tracee.set_hw_breakpoint(entry + 1),
+                Err(TraceeError::SetBreakpoint(_))
+            ));
+            let index = match tracee.set_hw_breakpoint(entry) {
+                Err(TraceeError::HardwareDebug(HardwareDebugError::Unsupported { .. })) => {
+                    tracee.kill();
+                    return;
+                }
+                result => result.unwrap(),
+            };
+            tracee.resume();
+            tracee.wait_on_signal();
+            assert_eq!(tracee.status(), TraceeStatus::Stopped);
+            assert_eq!(tracee.read_general_purpose_registers().pc, entry);
+            // Resuming steps over the breakpoint rather than stopping at it again.
+            tracee.resume();
+            tracee.wait_on_signal();
+            assert_eq!(tracee.status(), TraceeStatus::Exited);
+            assert!(matches!(
+                tracee.remove_hw_breakpoint(index + 1),
+                Err(TraceeError::UnknownHardwareBreakpoint(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn tracee_steps_from_hw_breakpoint() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            tracee.set_reports_stops(false);
+            let entry = tracee.read_entry().unwrap();
+            match tracee.set_hw_breakpoint(entry) {
+                Err(TraceeError::HardwareDebug(HardwareDebugError::Unsupported { .. })) => {
+                    tracee.kill();
+                    return;
+                }
+                result => result.unwrap(),
+            };
+            tracee.resume();
+            tracee.wait_on_signal();
+            assert_eq!(tracee.read_general_purpose_registers().pc, entry);
+            // Stepping runs the instruction at the breakpoint instead of stopping at it again.
+            assert_ne!(tracee.step_instruction(), Some(entry));
+            assert_eq!(tracee.trap(), Some(Trap::SingleStep));
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn tracee_step_instruction_returns_the_next_pc() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            let pc = tracee.read_general_purpose_registers().pc;
+            let code = assemble("mov x1, #5; mov x2, #7", pc).unwrap();
+            tracee.patch_code(pc, &code).unwrap();
+            assert_eq!(tracee.step_instruction(), Some(pc + 4));
+            assert_eq!(tracee.read_general_purpose_registers().regs[1], 5);
+            assert_eq!(tracee.step_instruction(), Some(pc + 8));
+            assert_eq!(tracee.read_general_purpose_registers().regs[2], 7);
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn tracee_resumes_and_steps_over_breakpoints() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            tracee.set_reports_stops(false);
+            let pc = tracee.read_general_purpose_registers().pc;
+            // Counts x2 down from 3, with `sub x2, x2, #1`.
+            let code = assemble(
+                &format!(
+                    "mov x2, #3; .inst 0xd1000442; cbnz x2, {:#x}; brk #0",
+                    pc + 4
+                ),
+                pc,
+            )
+            .unwrap();
+            tracee.patch_code(pc, &code).unwrap();
+            let id = tracee.insert_breakpoint(pc + 4).unwrap();
+            for count in [3, 2] {
+                tracee.resume();
+                tracee.wait_on_signal();
+                let regs = tracee.read_general_purpose_registers();
+                assert_eq!((regs.pc, regs.regs[2]), (pc + 4, count));
+            }
+            // Stepping from the breakpoint runs the instruction that it replaced.
+            assert_eq!(tracee.step_instruction(), Some(pc + 8));
+            assert_eq!(tracee.read_general_purpose_registers().regs[2], 1);
+            assert!(tracee.breakpoints().get(id).unwrap().is_enabled());
+            tracee.resume();
+            tracee.wait_on_signal();
+            let regs = tracee.read_general_purpose_registers();
+            assert_eq!((regs.pc, regs.regs[2]), (pc + 4, 1));
+            tracee.resume();
+            tracee.wait_on_signal();
+            assert_eq!(tracee.read_general_purpose_registers().pc, pc + 12);
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn tracee_step_from_breakpoint_runs_the_replaced_instruction() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            tracee.set_reports_stops(false);
+            let pc = tracee.read_general_purpose_registers().pc;
+            let code = assemble("mov x2, #3; .inst 0xd1000442; brk #0", pc).unwrap();
+            tracee.patch_code(pc, &code).unwrap();
+            let id = tracee.insert_breakpoint(pc).unwrap();
+            tracee.step(None);
+            tracee.wait_on_signal();
+            let regs = tracee.read_general_purpose_registers();
+            assert_eq!((regs.pc, regs.regs[2]), (pc + 4, 3));
+            // The breakpoint is back in place once the step stops.
+            assert!(tracee.breakpoints().get(id).unwrap().is_enabled());
+            assert_eq!(
+                tracee
+                    .read_memory(pc, BREAKPOINT_INSTRUCTION.len())
+                    .unwrap(),
+                BREAKPOINT_INSTRUCTION
+            );
+            tracee.step(None);
+            tracee.wait_on_signal();
+            let regs = tracee.read_general_purpose_registers();
+            assert_eq!((regs.pc, regs.regs[2]), (pc + 8, 2));
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn tracee_forked_child_stops_at_inherited_breakpoint() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            tracee.set_reports_stops(false);
+            tracee.set_follow_fork_mode(FollowForkMode::Both);
+            let pc = tracee.read_general_purpose_registers().pc;
+            // Forks with clone(SIGCHLD, 0, 0, 0, 0), after which both processes run `mov x5, #1`.
+            let code = assemble(
+                "mov x0, #17; mov x1, #0; mov x2, #0; mov x3, #0; mov x4, #0; mov x8, #220; \
+                 svc #0; mov x5, #1; brk #0",
+                pc,
+            )
+            .unwrap();
+            tracee.patch_code(pc, &code).unwrap();
+            tracee.insert_breakpoint(pc + 28).unwrap();
+            tracee.resume();
+            tracee.wait_on_signal();
+            assert_eq!(tracee.read_general_purpose_registers().pc, pc + 28);
+            let mut children = tracee.take_forked_tracees();
+            assert_eq!(children.len(), 1);
+            let child = &mut children[0];
+            child.set_reports_stops(false);
+            child.resume();
+            child.wait_on_signal();
+            assert_eq!(child.status(), TraceeStatus::Stopped);
+            assert_eq!(child.read_general_purpose_registers().pc, pc + 28);
+            // The child steps over its copy of the breakpoint like the parent would.
+            assert_eq!(child.step_instruction(), Some(pc + 32));
+            assert_eq!(child.read_general_purpose_registers().regs[5], 1);
+            assert_eq!(
+                child
+                    .read_memory(pc + 28, BREAKPOINT_INSTRUCTION.len())
+                    .unwrap(),
+                BREAKPOINT_INSTRUCTION
+            );
+            child.kill();
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn tracee_checkpoint_keeps_breakpoint_at_pc() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            tracee.set_reports_stops(false);
+            let pc = tracee.read_general_purpose_registers().pc;
+            let code = assemble("mov x2, #3; brk #0", pc).unwrap();
+            tracee.patch_code(pc, &code).unwrap();
+            tracee.insert_breakpoint(pc).unwrap();
+            let mut child = tracee.checkpoint().unwrap();
+            child.set_reports_stops(false);
+            assert_eq!(child.read_general_purpose_registers().pc, pc);
+            assert_eq!(child.breakpoints().breakpoints().len(), 1);
+            assert!(!cleanup::patches(child.pid()).is_empty());
+            // Both keep the breakpoint, which the child steps over like the tracee would.
+            for process in [&tracee, &child] {
+                assert_eq!(
+                    process
+                        .read_memory(pc, BREAKPOINT_INSTRUCTION.len())
+                        .unwrap(),
+                    BREAKPOINT_INSTRUCTION
+                );
+            }
+            assert_eq!(child.step_instruction(), Some(pc + 4));
+            assert_eq!(child.read_general_purpose_registers().regs[2], 3);
+            child.kill();
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn tracee_step_counts_stops() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            tracee.set_reports_stops(false);
+            let stop_count = tracee.stop_count();
+            for _ in 0..3 {
+                tracee.step(None);
+                tracee.wait_on_signal();
+            }
+            assert_eq!(tracee.status(), TraceeStatus::Stopped);
+            assert_eq!(tracee.stop_count(), stop_count + 3);
+        }
+    }
+
+    #[test]
+    fn tracee_read_memory_reads_unaligned_ranges() {
+        unsafe {
+            let tracee = Tracee::from_cmd("echo", &[]);
+            let pc = tracee.read_general_purpose_registers().pc;
+            let aligned = tracee.read_memory(pc, 16).unwrap();
+            let unaligned = tracee.read_memory(pc + 3, 7).unwrap();
+            assert_eq!(&aligned[3..10], unaligned.as_slice());
+        }
+    }
+
+    #[test]
+    fn tracee_read_memory_fails_on_unmapped_address() {
+        unsafe {
+            let tracee = Tracee::from_cmd("echo", &[]);
+            assert!(tracee.read_memory(0, 8).is_err());
+        }
+    }
+
+    #[test]
+    fn tracee_resume_until_syscall_stops_at_entry_and_exit() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            tracee.resume_until_syscall();
+            tracee.wait_on_signal();
+            assert!(matches!(
+                tracee.syscall_stop(),
+                Some(SyscallStop::Entry { .. })
+            ));
+
+            tracee.resume_until_syscall();
+            tracee.wait_on_signal();
+            assert!(matches!(
+                tracee.syscall_stop(),
+                Some(SyscallStop::Exit { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn tracee_set_syscall_return_value_only_works_at_syscall_exit() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            tracee.resume_until_syscall();
+            tracee.wait_on_signal();
+            assert!(!tracee.set_syscall_return_value(-libc::EPERM as i64));
+
+            tracee.resume_until_syscall();
+            tracee.wait_on_signal();
+            assert!(tracee.set_syscall_return_value(-libc::EPERM as i64));
+            assert_eq!(
+                tracee.read_general_purpose_registers().regs[0] as i64,
+                -libc::EPERM as i64
+            );
+        }
+    }
+
+    #[test]
+    fn tracee_fail_syscall_skips_syscall_with_errno() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            tracee.resume_until_syscall();
+            tracee.wait_on_signal();
+            assert!(tracee.fail_syscall(libc::ENOSPC));
+
+            tracee.resume_until_syscall();
+            tracee.wait_on_signal();
+            assert!(!tracee.fail_syscall(libc::ENOSPC));
+            assert!(matches!(
+                tracee.syscall_stop(),
+                Some(SyscallStop::Exit {
+                    value,
+                    is_error: true,
+                    ..
+                }) if value == -libc::ENOSPC as i64
+            ));
+        }
+    }
+
+    #[test]
+    fn tracee_resume_with_signal_delivers_signal() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            tracee.resume_with_signal(libc::SIGTERM);
+            tracee.wait_on_signal();
+            assert_eq!(tracee.status(), TraceeStatus::Terminated);
+        }
+    }
+
+    #[test]
+    fn tracee_interrupt_stops_running_process() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("sleep", &["10".to_string()]);
+            tracee.resume();
+            assert!(!tracee.poll_signal());
+
+            assert!(!tracee.interrupt(Some(-1)));
+            assert!(tracee.interrupt(None));
+            assert_eq!(tracee.status(), TraceeStatus::Stopped);
+            tracee.kill();
+        }
+    }
+
+    #[test]
+    fn tracee_kill_terminates_process() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            tracee.kill();
+            assert_eq!(tracee.status(), TraceeStatus::Terminated);
+        }
+    }
+
+    #[test]
+    fn tracee_read_siginfo_ignores_syscall_stops() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            tracee.resume_until_syscall();
+            tracee.wait_on_signal();
+            assert!(tracee.read_siginfo().is_none());
+        }
+    }
+
+    #[test]
+    fn tracee_records_received_signals() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &[]);
+            tracee.signal_thread(libc::SIGUSR1);
+            tracee.resume();
+            tracee.wait_on_signal();
+
+            let history = tracee.signal_history();
+            assert_eq!(history.len(), 1);
+            assert_eq!(history[0].signal, libc::SIGUSR1);
+            assert!(history[0].stopped);
+            assert!(history[0].passed);
+        }
+    }
+
+    #[test]
+    fn tracee_traces_library_calls_without_stopping() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &["hi".to_string()]);
+            tracee.set_reports_stops(false);
+            let path = tracee.executable().display().to_string();
+            assert!(tracee.trace_library_calls(&path).unwrap() > 0);
+            // Tracing the same stubs again adds none, and breakpoints are refused at them.
+            assert_eq!(tracee.trace_library_calls(&path).unwrap(), 0);
+            let address = tracee.call_traces()[0].address;
+            assert!(matches!(
+                tracee.insert_breakpoint(address),
+                Err(TraceeError::SetBreakpoint(_))
+            ));
+            tracee.resume();
+            tracee.wait_on_signal();
+            assert_eq!(tracee.status(), TraceeStatus::Exited);
+        }
+    }
+
+    #[test]
+    fn tracee_traces_function_calls_without_stopping() {
+        unsafe {
+            let mut tracee = Tracee::from_cmd("echo", &["hi".to_string()]);
+            tracee.set_reports_stops(false);
+            assert!(tracee.trace_function_calls("no_such_function").is_err());
+            tracee.catch_load("libc.so").unwrap();
+            tracee.resume();
+            tracee.wait_on_signal();
+            let address = tracee.trace_function_calls("write").unwrap();
+            assert!(tracee.trace_function_calls("write").is_err());
+            assert_eq!(tracee.call_traces()[0].address, address);
+            tracee.resume();
+            tracee.wait_on_signal();
+            assert_eq!(tracee.status(), TraceeStatus::Exited);
+        }
+    }
+
     fn procfs_read_status(pid: libc::pid_t) -> char {
         let procfs_path = format!("/proc/{}/stat", pid);
         let file = std::fs::File::open(procfs_path).unwrap();
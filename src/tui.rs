@@ -0,0 +1,454 @@
+// A full-screen terminal UI, drawn with plain ANSI escape sequences. Panes for the source,
+// disassembly, registers and threads fill the top of the screen, and are redrawn whenever the
+// session is ready for a command. Below them, the command line scrolls on its own, so commands
+// print as they do without the TUI. The panes are laid out again whenever the terminal is resized.
+use std::{
+    io::{self, stdout, Write},
+    mem,
+    ptr::null_mut,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        mpsc::Sender,
+    },
+    thread,
+};
+
+use crate::{
+    disasm::{disassemble, format_instruction, preceding_instruction_address, MAX_INSTRUCTION_LEN},
+    elf::describe_address,
+    procfs,
+    source::format_listing,
+    tracee::{Tracee, TraceeStatus},
+};
+
+// Leaves the alternate screen, after making the whole screen scroll again.
+const RESTORE_SEQUENCE: &[u8] = b"\x1b[r\x1b[?1049l";
+
+// The shares of the screen that the panes take, out of 100. The rest of the rows are left to the
+// command line, and the rest of the columns to the registers and threads.
+const PANE_ROWS_SHARE: usize = 65;
+const CODE_COLUMNS_SHARE: usize = 65;
+
+// The shares of the pane rows that the source and registers panes take, out of 100, above the
+// disassembly and threads panes.
+const SOURCE_ROWS_SHARE: usize = 50;
+const REGISTERS_ROWS_SHARE: usize = 70;
+
+// Below this, the panes would be too small to show anything.
+const MIN_ROWS: usize = 16;
+const MIN_COLUMNS: usize = 60;
+
+const TAB_WIDTH: usize = 4;
+
+// The write end of the pipe that SIGWINCH is passed on through, once resizes are watched.
+static RESIZE_FD: AtomicI32 = AtomicI32::new(-1);
+
+// A region of the screen, in rows and columns from the top left corner, which is (0, 0).
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Rect {
+    pub row: usize,
+    pub column: usize,
+    pub rows: usize,
+    pub columns: usize,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Layout {
+    pub source: Rect,
+    pub disassembly: Rect,
+    pub registers: Rect,
+    pub threads: Rect,
+    // The command line, whose first row is its title, and the rest scroll.
+    pub command: Rect,
+}
+
+impl Layout {
+    // Lays out the panes of a screen of the given size.
+    pub fn new(rows: usize, columns: usize) -> Layout {
+        let pane_rows = rows * PANE_ROWS_SHARE / 100;
+        let code_columns = columns * CODE_COLUMNS_SHARE / 100;
+        // A column is left between the left and right panes.
+        let side_column = code_columns + 1;
+        let side_columns = columns.saturating_sub(side_column);
+        let source_rows = pane_rows * SOURCE_ROWS_SHARE / 100;
+        let registers_rows = pane_rows * REGISTERS_ROWS_SHARE / 100;
+        return Layout {
+            source: Rect {
+                row: 0,
+                column: 0,
+                rows: source_rows,
+                columns: code_columns,
+            },
+            disassembly: Rect {
+                row: source_rows,
+                column: 0,
+                rows: pane_rows - source_rows,
+                columns: code_columns,
+            },
+            registers: Rect {
+                row: 0,
+                column: side_column,
+                rows: registers_rows,
+                columns: side_columns,
+            },
+            threads: Rect {
+                row: registers_rows,
+                column: side_column,
+                rows: pane_rows - registers_rows,
+                columns: side_columns,
+            },
+            command: Rect {
+                row: pane_rows,
+                column: 0,
+                rows: rows - pane_rows,
+                columns,
+            },
+        };
+    }
+}
+
+pub struct Tui {
+    size: (usize, usize),
+    layout: Layout,
+}
+
+// The terminal was resized, as SIGWINCH says.
+pub struct Resize;
+
+impl Tui {
+    // Takes over the terminal, which is restored once the TUI is dropped or pbreak exits.
+    pub unsafe fn enter() -> io::Result<Tui> {
+        let size = terminal_size()?;
+        let mut tui = Tui {
+            size,
+            layout: Layout::new(size.0, size.1),
+        };
+        libc::atexit(restore_terminal);
+        print!("\x1b[?1049h");
+        tui.reset_screen();
+        return Ok(tui);
+    }
+
+    // Clears the screen, and makes only the command line scroll, with the cursor at its bottom.
+    fn reset_screen(&mut self) {
+        let (rows, _) = self.size;
+        let command = self.layout.command;
+        print!("\x1b[2J\x1b[{};{}r\x1b[{};1H", command.row + 2, rows, rows);
+        print!("{}", draw_pane(command, "Command", &[]));
+        print!("\x1b[{};1H", rows);
+        stdout().flush().unwrap();
+    }
+
+    // Lays the panes out again if the terminal has changed size since. The screen is cleared then,
+    // and so is the command line. Returns whether it was.
+    pub unsafe fn resize(&mut self) -> bool {
+        let Ok(size) = terminal_size() else {
+            return false;
+        };
+        if size == self.size {
+            return false;
+        }
+        self.size = size;
+        self.layout = Layout::new(size.0, size.1);
+        self.reset_screen();
+        return true;
+    }
+
+    // Redraws the panes for the tracee, e.g. once it has stopped, leaving the cursor where it is.
+    pub unsafe fn draw(&mut self, tracee: &Tracee) {
+        self.resize();
+
+        let layout = self.layout;
+        let (source, disassembly, registers) = match tracee.status() {
+            TraceeStatus::Stopped => {
+                let regs = tracee.read_general_purpose_registers();
+                (
+                    source_lines(tracee, regs.pc, layout.source.rows.saturating_sub(1)),
+                    disassembly_lines(tracee, regs.pc, layout.disassembly.rows.saturating_sub(1)),
+                    register_lines(&regs),
+                )
+            }
+            status => {
+                let message = vec![format!("Process ({}) is {:?}", tracee.pid(), status)];
+                (message, vec![], vec![])
+            }
+        };
+
+        // The cursor is saved and restored around the panes, so that typing carries on below.
+        let mut screen = "\x1b7".to_string();
+        screen += &draw_pane(layout.source, "Source", &source);
+        screen += &draw_pane(layout.disassembly, "Disassembly", &disassembly);
+        screen += &draw_pane(layout.registers, "Registers", &registers);
+        screen += &draw_pane(layout.threads, "Threads", &thread_lines(tracee));
+        screen += "\x1b8";
+        print!("{}", screen);
+        stdout().flush().unwrap();
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+extern "C" fn restore_terminal() {
+    unsafe {
+        libc::write(
+            libc::STDOUT_FILENO,
+            RESTORE_SEQUENCE.as_ptr() as *const libc::c_void,
+            RESTORE_SEQUENCE.len(),
+        );
+    }
+}
+
+// Sends a `Resize` whenever the terminal is resized, from a thread of its own, since the handler of
+// SIGWINCH may only make system calls. It writes into a pipe that the thread reads.
+pub unsafe fn watch_resizes<T: From<Resize> + Send + 'static>(sender: Sender<T>) -> io::Result<()> {
+    let mut fds = [0; 2];
+    if libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let [read_fd, write_fd] = fds;
+    // Resizes that come faster than they are handled are dropped rather than block the handler.
+    if libc::fcntl(write_fd, libc::F_SETFL, libc::O_NONBLOCK) < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    RESIZE_FD.store(write_fd, Ordering::SeqCst);
+
+    let mut action = mem::zeroed::<libc::sigaction>();
+    action.sa_sigaction = handle_resize as *const () as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESTART;
+    if libc::sigaction(libc::SIGWINCH, &action, null_mut()) < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    thread::spawn(move || {
+        let mut byte = 0u8;
+        while unsafe { libc::read(read_fd, &mut byte as *mut u8 as *mut libc::c_void, 1) } == 1 {
+            if sender.send(Resize.into()).is_err() {
+                return;
+            }
+        }
+    });
+    return Ok(());
+}
+
+extern "C" fn handle_resize(_: libc::c_int) {
+    let byte = 0u8;
+    unsafe {
+        libc::write(
+            RESIZE_FD.load(Ordering::SeqCst),
+            &byte as *const u8 as *const libc::c_void,
+            1,
+        );
+    }
+}
+
+// Reads the size of the terminal, as rows and columns. Fails unless stdout is a terminal that is
+// big enough for the panes.
+unsafe fn terminal_size() -> io::Result<(usize, usize)> {
+    let mut size = mem::zeroed::<libc::winsize>();
+    if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (rows, columns) = (size.ws_row as usize, size.ws_col as usize);
+    if rows < MIN_ROWS || columns < MIN_COLUMNS {
+        return Err(io::Error::other(format!(
+            "the terminal is too small ({}x{}, needs at least {}x{})",
+            columns, rows, MIN_COLUMNS, MIN_ROWS
+        )));
+    }
+    return Ok((rows, columns));
+}
+
+// Draws a pane: its title on its first row in reverse video, then as many of the lines as fit,
+// cut to its width. The rest of the pane is blanked.
+pub fn draw_pane(rect: Rect, title: &str, lines: &[String]) -> String {
+    if rect.rows == 0 {
+        return String::new();
+    }
+    let mut screen = format!(
+        "\x1b[{};{}H\x1b[7m{}\x1b[0m",
+        rect.row + 1,
+        rect.column + 1,
+        fit(&format!(" {}", title), rect.columns)
+    );
+    for i in 0..rect.rows - 1 {
+        let line = lines.get(i).map_or("", String::as_str);
+        screen += &format!(
+            "\x1b[{};{}H{}",
+            rect.row + 2 + i,
+            rect.column + 1,
+            fit(line, rect.columns)
+        );
+    }
+    return screen;
+}
+
+// Expands the tabs of a line, then cuts or pads it to exactly the given width.
+fn fit(line: &str, columns: usize) -> String {
+    let mut fitted = String::new();
+    let mut width = 0;
+    for c in line.chars() {
+        let expanded = match c {
+            '\t' => " ".repeat(TAB_WIDTH - width % TAB_WIDTH),
+            c if c.is_control() => continue,
+            c => c.to_string(),
+        };
+        for c in expanded.chars() {
+            if width == columns {
+                return fitted;
+            }
+            fitted.push(c);
+            width += 1;
+        }
+    }
+    fitted += &" ".repeat(columns - width);
+    return fitted;
+}
+
+// Lists the source lines around the one that the pc was compiled from, marking it with an arrow.
+unsafe fn source_lines(tracee: &Tracee, pc: u64, rows: usize) -> Vec<String> {
+    let line_rows = tracee.read_line_rows().unwrap_or_default();
+    let Some(row) = line_rows[..line_rows.partition_point(|row| row.address <= pc)].last() else {
+        return vec![format!("no source for {:#x}", pc)];
+    };
+    let Some(source) = tracee.read_source_lines(&row.path) else {
+        return vec![format!("{}:{}", row.path, row.line)];
+    };
+
+    // The first row names the file.
+    let mut lines = vec![format!("{}:", row.path)];
+    lines.extend(format_listing(&source, row.line, rows.saturating_sub(1)));
+    return lines;
+}
+
+// Lists the instructions around the pc, as the program has them, i.e. without breakpoints.
+unsafe fn disassembly_lines(tracee: &Tracee, pc: u64, rows: usize) -> Vec<String> {
+    let mut address = preceding_instruction_address(pc, rows.saturating_sub(1) / 2);
+    // The pc may be at the very start of the mapped code.
+    if tracee.read_memory(address, 1).is_err() {
+        address = pc;
+    }
+    let mut len = rows * MAX_INSTRUCTION_LEN;
+    let mut code = tracee.read_memory(address, len);
+    while code.is_err() && len > MAX_INSTRUCTION_LEN {
+        len /= 2;
+        code = tracee.read_memory(address, len);
+    }
+    let mut code = match code {
+        Err(err) => return vec![err.to_string()],
+        Ok(code) => code,
+    };
+
+    let patched_addresses = tracee.hide_breakpoints(&mut code, address);
+    let functions = tracee.read_functions().unwrap_or_default();
+    return match disassemble(&code, address, rows) {
+        Err(err) => vec![err.to_string()],
+        Ok(instructions) => instructions
+            .iter()
+            .map(|instruction| {
+                let end = instruction.address + instruction.bytes.len() as u64;
+                let is_breakpoint = patched_addresses
+                    .iter()
+                    .any(|patched_address| (instruction.address..end).contains(patched_address));
+                return format_instruction(
+                    instruction,
+                    instruction.address == pc,
+                    is_breakpoint,
+                    |target| describe_address(&functions, target),
+                );
+            })
+            .collect(),
+    };
+}
+
+// Lists the general purpose registers, the pc, sp and pstate first.
+fn register_lines(regs: &libc::user_regs_struct) -> Vec<String> {
+    let mut lines = vec![
+        format!("pc     {:#018x}", regs.pc),
+        format!("sp     {:#018x}", regs.sp),
+        format!("pstate {:#018x}", regs.pstate),
+    ];
+    for (i, reg) in regs.regs.iter().enumerate() {
+        lines.push(format!("{:<6} {:#018x}", format!("x{}", i), reg));
+    }
+    return lines;
+}
+
+// Lists the threads, marking the selected one with an asterisk.
+fn thread_lines(tracee: &Tracee) -> Vec<String> {
+    return tracee
+        .threads()
+        .iter()
+        .map(|thread| {
+            let marker = match thread.tid == tracee.selected_tid() {
+                true => "*",
+                false => " ",
+            };
+            return format!(
+                "{} {} \"{}\" {:?}",
+                marker,
+                thread.tid,
+                procfs::read_comm(thread.tid).unwrap_or_default(),
+                thread.status
+            );
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod test {
+    use super::{draw_pane, fit, Layout, Rect};
+
+    #[test]
+    fn layout_fills_the_screen() {
+        let layout = Layout::new(40, 100);
+        assert_eq!(
+            layout.source,
+            Rect {
+                row: 0,
+                column: 0,
+                rows: 13,
+                columns: 65
+            }
+        );
+        assert_eq!(layout.disassembly.row, 13);
+        assert_eq!(layout.disassembly.rows, 13);
+        assert_eq!(layout.registers.column, 66);
+        assert_eq!(layout.registers.columns, 34);
+        assert_eq!(layout.threads.row + layout.threads.rows, 26);
+        assert_eq!(
+            layout.command,
+            Rect {
+                row: 26,
+                column: 0,
+                rows: 14,
+                columns: 100
+            }
+        );
+    }
+
+    #[test]
+    fn fit_expands_tabs_and_cuts_or_pads() {
+        assert_eq!(fit("ab", 4), "ab  ");
+        assert_eq!(fit("abcdef", 4), "abcd");
+        assert_eq!(fit("a\tb", 6), "a   b ");
+        assert_eq!(fit("\x1b[0m", 2), "[0");
+    }
+
+    #[test]
+    fn draw_pane_draws_title_and_lines() {
+        let rect = Rect {
+            row: 1,
+            column: 2,
+            rows: 3,
+            columns: 4,
+        };
+        assert_eq!(
+            draw_pane(rect, "Regs", &["x0".to_string()]),
+            "\x1b[2;3H\x1b[7m Reg\x1b[0m\x1b[3;3Hx0  \x1b[4;3H    "
+        );
+    }
+}
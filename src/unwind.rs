@@ -0,0 +1,226 @@
+use crate::target::Target;
+
+// Upper bound on the number of frames walked, in case the frame chain loops.
+const MAX_FRAMES: usize = 256;
+
+// Pointer authentication codes live in the upper bits of return addresses.
+pub const ADDRESS_MASK: u64 = 0x0000_ffff_ffff_ffff;
+
+// The signal trampoline (__kernel_rt_sigreturn in the vDSO) that signal handlers return into,
+// `mov x8, #__NR_rt_sigreturn; svc #0`, read as a single little-endian word.
+const SIGRETURN_TRAMPOLINE: u64 = 0xd400_0001_d280_1168;
+
+// The layout of the signal frame that the kernel pushes before calling a handler (see
+// arch/arm64/kernel/signal.c). The rt_sigframe lies just below a frame record that holds the
+// interrupted x29 and x30, which is where the handler's frame chain leads.
+const RT_SIGFRAME_SIZE: u64 = 4688;
+// Large register state, e.g. SVE, spills out of the rt_sigframe and makes it bigger.
+const MAX_SIGFRAME_EXTRA_SIZE: u64 = 64 * 1024;
+// Offsets into the rt_sigframe: siginfo, then ucontext up to uc_mcontext, then fault_address.
+const SIGCONTEXT_REGS_OFFSET: u64 = 128 + 176 + 8;
+const SIGCONTEXT_PC_OFFSET: u64 = SIGCONTEXT_REGS_OFFSET + 32 * 8;
+
+#[derive(PartialEq, Debug)]
+pub struct Frame {
+    pub pc: u64,
+    pub fp: u64,
+    // Whether the frame is the signal trampoline, meaning that the next frame is the one that
+    // was interrupted by a signal.
+    pub is_signal_trampoline: bool,
+}
+
+// Unwinds the stack of the selected thread by walking the AArch64 frame record chain.
+//
+// Each frame record is a pair of the caller's frame pointer (x29) and the return address (x30),
+// stored at the address held by the frame pointer. Frames that do not maintain a frame record
+// (e.g. a function that is still in its prologue) are skipped. Signal handlers are unwound into the
+// code that the signal interrupted, using the context that the kernel saved on the stack.
+pub unsafe fn unwind(target: &dyn Target) -> Vec<Frame> {
+    return unwind_thread(target, target.selected_tid());
+}
+
+// Unwinds the stack of a specific thread, regardless of the selection.
+pub unsafe fn unwind_thread(target: &dyn Target, tid: libc::pid_t) -> Vec<Frame> {
+    let regs = target.read_thread_general_purpose_registers(tid);
+    return unwind_frames(regs.pc, regs.regs[29], |address| {
+        let bytes = target.read_memory(address, 8).ok()?;
+        return Some(u64::from_ne_bytes(bytes.try_into().unwrap()));
+    });
+}
+
+fn unwind_frames(pc: u64, fp: u64, read_word: impl Fn(u64) -> Option<u64>) -> Vec<Frame> {
+    let mut frames = vec![Frame {
+        pc,
+        fp,
+        is_signal_trampoline: false,
+    }];
+
+    let mut fp = fp;
+    while frames.len() < MAX_FRAMES && fp != 0 && fp.is_multiple_of(16) {
+        let (Some(caller_fp), Some(return_address)) = (read_word(fp), read_word(fp + 8)) else {
+            break;
+        };
+
+        let return_address = return_address & ADDRESS_MASK;
+        if return_address == 0 {
+            break;
+        }
+
+        let is_signal_trampoline = read_word(return_address) == Some(SIGRETURN_TRAMPOLINE);
+        frames.push(Frame {
+            pc: return_address,
+            fp: caller_fp,
+            is_signal_trampoline,
+        });
+
+        if is_signal_trampoline && frames.len() < MAX_FRAMES {
+            let Some((interrupted_pc, interrupted_fp)) = read_signal_context(caller_fp, &read_word)
+            else {
+                break;
+            };
+
+            frames.push(Frame {
+                pc: interrupted_pc & ADDRESS_MASK,
+                fp: interrupted_fp,
+                is_signal_trampoline: false,
+            });
+            // Handlers may run on an alternate signal stack, so the interrupted frames need not
+            // live at higher addresses.
+            fp = interrupted_fp;
+            continue;
+        }
+
+        // The stack grows downwards, so callers' frame records must live at higher addresses.
+        if caller_fp <= fp {
+            break;
+        }
+        fp = caller_fp;
+    }
+
+    return frames;
+}
+
+// Recovers the pc and frame pointer that a signal interrupted, given the frame record that the
+// kernel pushed above the signal frame.
+fn read_signal_context(
+    frame_record: u64,
+    read_word: impl Fn(u64) -> Option<u64>,
+) -> Option<(u64, u64)> {
+    let (interrupted_fp, interrupted_lr) = (read_word(frame_record)?, read_word(frame_record + 8)?);
+
+    // The frame record duplicates x29 and x30 of the saved context, which tells where the signal
+    // frame starts when it is bigger than usual.
+    let mut sigframe = frame_record.checked_sub(RT_SIGFRAME_SIZE)?;
+    while frame_record - sigframe <= RT_SIGFRAME_SIZE + MAX_SIGFRAME_EXTRA_SIZE {
+        if read_word(sigframe + SIGCONTEXT_REGS_OFFSET + 29 * 8) == Some(interrupted_fp)
+            && read_word(sigframe + SIGCONTEXT_REGS_OFFSET + 30 * 8) == Some(interrupted_lr)
+        {
+            let interrupted_pc = read_word(sigframe + SIGCONTEXT_PC_OFFSET)?;
+            return Some((interrupted_pc, interrupted_fp));
+        }
+        sigframe = sigframe.checked_sub(16)?;
+    }
+    return None;
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{
+        unwind_frames, Frame, RT_SIGFRAME_SIZE, SIGCONTEXT_PC_OFFSET, SIGCONTEXT_REGS_OFFSET,
+        SIGRETURN_TRAMPOLINE,
+    };
+
+    #[test]
+    fn unwind_frames_walks_frame_records() {
+        let memory = HashMap::from([
+            (0x1000, 0x1100),
+            (0x1008, 0x400200),
+            (0x1100, 0x0),
+            (0x1108, 0x400300),
+        ]);
+        let frames = unwind_frames(0x400100, 0x1000, |address| memory.get(&address).copied());
+        assert_eq!(
+            frames,
+            vec![
+                Frame {
+                    pc: 0x400100,
+                    fp: 0x1000,
+                    is_signal_trampoline: false,
+                },
+                Frame {
+                    pc: 0x400200,
+                    fp: 0x1100,
+                    is_signal_trampoline: false,
+                },
+                Frame {
+                    pc: 0x400300,
+                    fp: 0x0,
+                    is_signal_trampoline: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unwind_frames_strips_pointer_authentication_codes() {
+        let memory = HashMap::from([(0x1000, 0x0), (0x1008, 0x002f_0000_0040_0200)]);
+        let frames = unwind_frames(0x400100, 0x1000, |address| memory.get(&address).copied());
+        assert_eq!(frames[1].pc, 0x400200);
+    }
+
+    #[test]
+    fn unwind_frames_stops_on_unreadable_memory() {
+        let frames = unwind_frames(0x400100, 0x1000, |_| None);
+        assert_eq!(
+            frames,
+            vec![Frame {
+                pc: 0x400100,
+                fp: 0x1000,
+                is_signal_trampoline: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn unwind_frames_stops_when_frame_chain_does_not_ascend() {
+        let memory = HashMap::from([(0x1000, 0x1000), (0x1008, 0x400200)]);
+        let frames = unwind_frames(0x400100, 0x1000, |address| memory.get(&address).copied());
+        assert_eq!(frames.len(), 2);
+    }
+
+    #[test]
+    fn unwind_frames_continues_into_code_interrupted_by_signal() {
+        let frame_record = 0x10_0000;
+        let sigframe = frame_record - RT_SIGFRAME_SIZE;
+        let memory = HashMap::from([
+            // The signal handler's frame record returns into the trampoline.
+            (0x8000, frame_record),
+            (0x8008, 0x7000),
+            (0x7000, SIGRETURN_TRAMPOLINE),
+            // The kernel's frame record and saved context.
+            (frame_record, 0x20_0000),
+            (frame_record + 8, 0x400300),
+            (sigframe + SIGCONTEXT_REGS_OFFSET + 29 * 8, 0x20_0000),
+            (sigframe + SIGCONTEXT_REGS_OFFSET + 30 * 8, 0x400300),
+            (sigframe + SIGCONTEXT_PC_OFFSET, 0x400200),
+            // The interrupted function's frame record.
+            (0x20_0000, 0x0),
+            (0x20_0008, 0x400400),
+        ]);
+        let frames = unwind_frames(0x400100, 0x8000, |address| memory.get(&address).copied());
+        assert_eq!(
+            frames
+                .iter()
+                .map(|frame| (frame.pc, frame.is_signal_trampoline))
+                .collect::<Vec<(u64, bool)>>(),
+            vec![
+                (0x400100, false),
+                (0x7000, true),
+                (0x400200, false),
+                (0x400400, false),
+            ]
+        );
+    }
+}
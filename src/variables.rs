@@ -0,0 +1,80 @@
+// Convenience variables, e.g. `$count`, which the session keeps across commands for counting and
+// tracking state. They hold the values of expressions, and are read by expressions along with the
+// registers of the selected thread, e.g. `$count + 1` or `$x0 - $sp`.
+use std::collections::BTreeMap;
+
+use crate::expr::Value;
+
+#[derive(Default)]
+pub struct ConvenienceVariables {
+    values: BTreeMap<String, Value>,
+}
+
+impl ConvenienceVariables {
+    pub fn get(&self, name: &str) -> Option<Value> {
+        return self.values.get(name).copied();
+    }
+
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.values.insert(name.to_string(), value);
+    }
+
+    // Lists the variables that are set, by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Value)> {
+        return self
+            .values
+            .iter()
+            .map(|(name, value)| (name.as_str(), *value));
+    }
+}
+
+// Parses an assignment to a convenience variable, e.g. "$count = $count + 1", into the name of
+// the variable and the expression. Returns None if the line is not one.
+pub fn parse_assignment(line: &str) -> Option<(&str, &str)> {
+    let (name, expression) = line.trim().strip_prefix('$')?.split_once('=')?;
+    let name = name.trim();
+    if !is_variable_name(name) {
+        return None;
+    }
+    return Some((name, expression.trim()));
+}
+
+// Names are made of letters, digits and underscores, and do not start with a digit.
+fn is_variable_name(name: &str) -> bool {
+    return name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_assignment, ConvenienceVariables};
+    use crate::expr::Value;
+
+    #[test]
+    fn convenience_variables_are_listed_by_name() {
+        let mut variables = ConvenienceVariables::default();
+        variables.set("count", Value::Int(41));
+        variables.set("base", Value::UInt(0x1000));
+        variables.set("count", Value::Int(42));
+        assert_eq!(variables.get("count"), Some(Value::Int(42)));
+        assert_eq!(variables.get("hits"), None);
+        assert_eq!(
+            variables.iter().collect::<Vec<(&str, Value)>>(),
+            vec![("base", Value::UInt(0x1000)), ("count", Value::Int(42))]
+        );
+    }
+
+    #[test]
+    fn parse_assignment_splits_name_and_expression() {
+        assert_eq!(
+            parse_assignment("$count = $count + 1"),
+            Some(("count", "$count + 1"))
+        );
+        assert_eq!(parse_assignment("$hits=0"), Some(("hits", "0")));
+        assert_eq!(parse_assignment("$1x = 0"), None);
+        assert_eq!(parse_assignment("count = 0"), None);
+    }
+}
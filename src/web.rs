@@ -0,0 +1,473 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Sender},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    cleanup,
+    json::Json,
+    rpc::{handle_line, RpcClient, RpcRequest},
+};
+
+// The frontend, which is a single page that calls the JSON-RPC methods of the session.
+const INDEX_HTML: &str = include_str!("web/index.html");
+
+// How long a request may wait on the session, e.g. while the prompt runs a long command.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Requests bigger than this are refused, since nothing the frontend sends comes close.
+const MAX_BODY_LEN: usize = 1024 * 1024;
+
+// The JSON-RPC methods that browsers may call, which only look at the process. Anyone who can reach
+// the frontend can see the process, but not run commands or change it.
+const READ_ONLY_METHODS: &[&str] = &[
+    "status",
+    "backtrace",
+    "readMemory",
+    "readRegisters",
+    "breakpoints",
+];
+
+// The header that the frontend sends the token in.
+const TOKEN_HEADER: &str = "x-pbreak-token";
+
+#[derive(PartialEq, Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    // The names are lowercase.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        return self
+            .headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str());
+    }
+
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        return self
+            .query
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .find(|(param, _)| *param == name)
+            .map(|(_, value)| value);
+    }
+}
+
+// Serves a small web frontend over HTTP, for looking at a process from a browser. The frontend
+// posts JSON-RPC requests to /rpc, which are handled by the session like those of the control
+// socket. Notifications are not pushed to browsers, which poll instead.
+//
+// Requests have to carry a token, which is made up at startup and only shown to the user, and
+// have to be addressed to the server itself, so that other pages that the browser has open cannot
+// reach it, e.g. by rebinding a name of theirs to 127.0.0.1.
+pub struct WebServer {
+    address: SocketAddr,
+    token: String,
+    closed: Arc<AtomicBool>,
+}
+
+impl WebServer {
+    // Listens at the address, e.g. ":8080" for port 8080 of 127.0.0.1, or "0.0.0.0:8080" for every
+    // interface, sending every request that arrives to `requests`.
+    pub fn listen<T>(address: &str, requests: Sender<T>) -> io::Result<WebServer>
+    where
+        T: From<RpcRequest> + Send + 'static,
+    {
+        let address = match address.starts_with(':') {
+            true => format!("127.0.0.1{}", address),
+            false => address.to_string(),
+        };
+        let token = new_token()?;
+        let listener = TcpListener::bind(&address)?;
+        let address = listener.local_addr()?;
+        let closed = Arc::new(AtomicBool::new(false));
+        let is_closed = closed.clone();
+        let server_token = token.clone();
+        thread::spawn(move || {
+            unsafe { cleanup::block_fatal_signals() };
+            for stream_result in listener.incoming() {
+                if is_closed.load(Ordering::SeqCst) {
+                    return;
+                }
+                let Ok(stream) = stream_result else {
+                    continue;
+                };
+                let requests = requests.clone();
+                let token = server_token.clone();
+                thread::spawn(move || {
+                    unsafe { cleanup::block_fatal_signals() };
+                    let _ = serve_connection(stream, &requests, address, &token);
+                });
+            }
+        });
+
+        return Ok(WebServer {
+            address,
+            token,
+            closed,
+        });
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        return self.address;
+    }
+
+    // The address to open the frontend at, token included.
+    pub fn url(&self) -> String {
+        return format!("http://{}/?token={}", self.address, self.token);
+    }
+}
+
+impl Drop for WebServer {
+    fn drop(&mut self) {
+        // The listening thread is blocked accepting, so it is woken up by a last connection.
+        self.closed.store(true, Ordering::SeqCst);
+        let mut address = self.address;
+        if address.ip().is_unspecified() {
+            address.set_ip([127, 0, 0, 1].into());
+        }
+        let _ = TcpStream::connect(address);
+    }
+}
+
+// Passes what is written to it on to a channel, which is how responses to the requests of a
+// browser are waited on.
+struct ResponseWriter(Sender<Vec<u8>>);
+
+impl Write for ResponseWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0
+            .send(bytes.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        return Ok(bytes.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+// Answers a single request of a browser, then closes the connection.
+fn serve_connection<T: From<RpcRequest>>(
+    mut stream: TcpStream,
+    requests: &Sender<T>,
+    address: SocketAddr,
+    token: &str,
+) -> io::Result<()> {
+    let request = match read_http_request(&mut BufReader::new(&stream)) {
+        Err(err) => {
+            let body = format!("{}\n", err);
+            return stream.write_all(&format_http_response(400, "text/plain", body.as_bytes()));
+        }
+        Ok(request) => request,
+    };
+    if !is_addressed_to(&request, address) {
+        return stream.write_all(&format_http_response(
+            403,
+            "text/plain",
+            b"the request is not addressed to this server\n",
+        ));
+    }
+    if !has_token(&request, token) {
+        return stream.write_all(&format_http_response(
+            403,
+            "text/plain",
+            b"missing or wrong token\n",
+        ));
+    }
+
+    let response = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/") => format_http_response(200, "text/html", INDEX_HTML.as_bytes()),
+        ("POST", "/rpc") => {
+            let (sender, receiver) = mpsc::channel();
+            let client = RpcClient::new(ResponseWriter(sender));
+            let line = String::from_utf8_lossy(&request.body).replace('\n', " ");
+            if let Some(method) = read_rpc_method(&line).filter(|method| !is_read_only(method)) {
+                let body = format!("\"{}\" is not available over the web\n", method);
+                return stream.write_all(&format_http_response(403, "text/plain", body.as_bytes()));
+            }
+            if !handle_line(&client, &line, requests) {
+                format_http_response(503, "text/plain", b"the session has ended\n")
+            } else {
+                // The client is dropped along with the request once it is answered, so
+                // notifications end up here rather than with the response.
+                drop(client);
+                match receiver.recv_timeout(RESPONSE_TIMEOUT) {
+                    Ok(response) => format_http_response(200, "application/json", &response),
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        format_http_response(204, "application/json", b"")
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        format_http_response(504, "text/plain", b"the session is busy\n")
+                    }
+                }
+            }
+        }
+        (_, "/" | "/rpc") => format_http_response(405, "text/plain", b"method not allowed\n"),
+        _ => format_http_response(404, "text/plain", b"not found\n"),
+    };
+    return stream.write_all(&response);
+}
+
+// Makes up a token of 128 random bits, in hex.
+fn new_token() -> io::Result<String> {
+    let mut bytes = [0; 16];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    return Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect());
+}
+
+// Tells whether a request names the server as its host, and comes from a page of the server if it
+// comes from a page at all. A server that listens on every interface cannot tell which of the
+// names of the machine it was reached by, so only the port is checked then, and the token is what
+// keeps other pages out.
+pub fn is_addressed_to(request: &HttpRequest, address: SocketAddr) -> bool {
+    let Some(host) = request.header("host") else {
+        return false;
+    };
+    let is_server = host == address.to_string()
+        || (address.ip().is_loopback() && host == format!("localhost:{}", address.port()))
+        || (address.ip().is_unspecified()
+            && host
+                .rsplit_once(':')
+                .is_some_and(|(_, port)| port == address.port().to_string()));
+    return is_server
+        && request
+            .header("origin")
+            .is_none_or(|origin| origin == format!("http://{}", host));
+}
+
+// The page is opened with the token in its query, and passes it on in a header.
+pub fn has_token(request: &HttpRequest, token: &str) -> bool {
+    return request.header(TOKEN_HEADER) == Some(token)
+        || request.query_param("token") == Some(token);
+}
+
+// The method of a JSON-RPC request, if it is well-formed enough to have one. Malformed requests
+// are left to `handle_line` to answer.
+fn read_rpc_method(line: &str) -> Option<String> {
+    return Json::parse(line)
+        .ok()?
+        .get("method")?
+        .as_str()
+        .map(str::to_string);
+}
+
+pub fn is_read_only(method: &str) -> bool {
+    return READ_ONLY_METHODS.contains(&method);
+}
+
+// Reads a request of HTTP/1.1, up to the end of its body.
+pub fn read_http_request(reader: &mut impl BufRead) -> io::Result<HttpRequest> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut words = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (words.next(), words.next()) else {
+        return Err(invalid("malformed request line"));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut headers = vec![];
+    let mut content_len = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Err(invalid("unexpected end of headers"));
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(invalid("malformed header"));
+        };
+        if name.eq_ignore_ascii_case("content-length") {
+            content_len = value
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| invalid("invalid content-length"))?;
+        }
+        headers.push((name.to_ascii_lowercase(), value.trim().to_string()));
+    }
+    if content_len > MAX_BODY_LEN {
+        return Err(invalid("request body too large"));
+    }
+
+    let mut body = vec![0; content_len];
+    reader.read_exact(&mut body)?;
+    return Ok(HttpRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        headers,
+        body,
+    });
+}
+
+pub fn format_http_response(status: u16, content_type: &str, body: &[u8]) -> Vec<u8> {
+    let reason = match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "",
+    };
+    let mut response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        content_type,
+        body.len()
+    )
+    .into_bytes();
+    response.extend_from_slice(body);
+    return response;
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Cursor, net::SocketAddr};
+
+    use super::{
+        format_http_response, has_token, is_addressed_to, is_read_only, read_http_request,
+        HttpRequest,
+    };
+
+    fn request_with_headers(headers: &[(&str, &str)]) -> HttpRequest {
+        return HttpRequest {
+            method: "POST".to_string(),
+            path: "/rpc".to_string(),
+            query: String::new(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: vec![],
+        };
+    }
+
+    #[test]
+    fn read_http_request_reads_method_path_and_body() {
+        let request =
+            "POST /rpc?x=1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\n{}\n\nrest";
+        assert_eq!(
+            read_http_request(&mut Cursor::new(request)).unwrap(),
+            HttpRequest {
+                method: "POST".to_string(),
+                path: "/rpc".to_string(),
+                query: "x=1".to_string(),
+                headers: vec![
+                    ("host".to_string(), "localhost".to_string()),
+                    ("content-length".to_string(), "4".to_string()),
+                ],
+                body: b"{}\n\n".to_vec(),
+            }
+        );
+
+        let request = "GET / HTTP/1.1\r\n\r\n";
+        assert_eq!(
+            read_http_request(&mut Cursor::new(request)).unwrap().body,
+            b""
+        );
+    }
+
+    #[test]
+    fn read_http_request_rejects_malformed_requests() {
+        assert!(read_http_request(&mut Cursor::new("\r\n")).is_err());
+        assert!(read_http_request(&mut Cursor::new("GET / HTTP/1.1\r\nHost")).is_err());
+        assert!(read_http_request(&mut Cursor::new(
+            "POST /rpc HTTP/1.1\r\nContent-Length: x\r\n\r\n"
+        ))
+        .is_err());
+        assert!(read_http_request(&mut Cursor::new(
+            "POST /rpc HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}"
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn is_addressed_to_checks_host_and_origin() {
+        let address: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        assert!(is_addressed_to(
+            &request_with_headers(&[("host", "127.0.0.1:8080")]),
+            address
+        ));
+        assert!(is_addressed_to(
+            &request_with_headers(&[
+                ("host", "localhost:8080"),
+                ("origin", "http://localhost:8080")
+            ]),
+            address
+        ));
+        assert!(!is_addressed_to(&request_with_headers(&[]), address));
+        // A name of another site that resolves to 127.0.0.1.
+        assert!(!is_addressed_to(
+            &request_with_headers(&[("host", "attacker.example:8080")]),
+            address
+        ));
+        assert!(!is_addressed_to(
+            &request_with_headers(&[
+                ("host", "127.0.0.1:8080"),
+                ("origin", "http://attacker.example")
+            ]),
+            address
+        ));
+
+        let address: SocketAddr = "0.0.0.0:8080".parse().unwrap();
+        assert!(is_addressed_to(
+            &request_with_headers(&[("host", "debugbox:8080")]),
+            address
+        ));
+        assert!(!is_addressed_to(
+            &request_with_headers(&[("host", "debugbox:9090")]),
+            address
+        ));
+    }
+
+    #[test]
+    fn has_token_reads_header_or_query() {
+        let mut request = request_with_headers(&[("x-pbreak-token", "abc")]);
+        assert!(has_token(&request, "abc"));
+        assert!(!has_token(&request, "abd"));
+        request.headers.clear();
+        assert!(!has_token(&request, "abc"));
+        request.query = "token=abc".to_string();
+        assert!(has_token(&request, "abc"));
+    }
+
+    #[test]
+    fn is_read_only_refuses_commands() {
+        assert!(is_read_only("backtrace"));
+        assert!(!is_read_only("command"));
+        assert!(!is_read_only("writeMemory"));
+        assert!(!is_read_only("continue"));
+    }
+
+    #[test]
+    fn format_http_response_writes_headers_and_body() {
+        assert_eq!(
+            String::from_utf8(format_http_response(404, "text/plain", b"not found\n")).unwrap(),
+            "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: 10\r\nCache-Control: no-store\r\nConnection: close\r\n\r\nnot found\n"
+        );
+    }
+}